@@ -1,13 +1,71 @@
-#[cfg(windows)]
+use std::{env,
+          path::Path,
+          process::Command};
+
 fn main() {
+    build_info();
+    windows_main();
+}
+
+/// Records what `build_info` reports about the build in environment variables for the compiler.
+fn build_info() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=HAB_GIT_SHA");
+    let git_sha = env::var("HAB_GIT_SHA").ok().or_else(|| {
+                                                  rerun_if_head_changes();
+                                                  git(&["rev-parse", "HEAD"])
+                                              });
+    let mut features: Vec<String> =
+        env::vars().filter(|(k, _)| k.starts_with("CARGO_FEATURE_"))
+                   .map(|(k, _)| k["CARGO_FEATURE_".len()..].to_lowercase().replace('_', "-"))
+                   .collect();
+    features.sort();
+    println!("cargo:rustc-env=HAB_CORE_GIT_SHA={}",
+             git_sha.unwrap_or_default());
+    println!("cargo:rustc-env=HAB_CORE_BUILD_TARGET={}",
+             env::var("TARGET").unwrap());
+    println!("cargo:rustc-env=HAB_CORE_BUILD_PROFILE={}",
+             env::var("PROFILE").unwrap());
+    println!("cargo:rustc-env=HAB_CORE_FEATURES={}", features.join(","));
+}
+
+/// Asks Cargo to run this script again when another commit is checked out, which changes
+/// `HEAD` or, on a branch, the branch's ref.
+fn rerun_if_head_changes() {
+    let mut paths = vec!["HEAD".to_string(), "packed-refs".to_string()];
+    if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+        paths.push(head_ref);
+    }
+    for path in paths {
+        // Cargo reruns the script on every build for a path which does not exist
+        if let Some(path) = git(&["rev-parse", "--git-path", &path]) {
+            if Path::new(&path).exists() {
+                println!("cargo:rerun-if-changed={}", path);
+            }
+        }
+    }
+}
+
+/// Runs `git` with `args`, returning its trimmed output if it succeeds.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(windows)]
+fn windows_main() {
     extern crate base64;
     extern crate gcc;
 
-    use std::{env,
-              fs::File,
-              io::prelude::*,
-              path::Path};
+    use std::{fs::File,
+              io::prelude::*};
 
+    println!("cargo:rerun-if-changed=src/os/users/admincheck.c");
+    println!("cargo:rerun-if-env-changed=HAB_CRYPTO_KEY");
     gcc::compile_library("libadmincheck.a", &["./src/os/users/admincheck.c"]);
     let mut file =
         File::create(Path::new(&env::var("OUT_DIR").unwrap()).join("hab-crypt")).unwrap();
@@ -20,4 +78,4 @@ fn main() {
 }
 
 #[cfg(not(windows))]
-fn main() {}
+fn windows_main() {}
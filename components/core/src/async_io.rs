@@ -0,0 +1,149 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Futures for core's heavy IO entry points, for use from a Tokio runtime such as the
+//! Supervisor's. Each runs its blocking work through `tokio_threadpool::blocking`, so the pool
+//! can hand its other tasks to a fresh thread rather than stalling them behind the filesystem.
+//!
+//! The futures may also be driven outside of a thread pool, for instance with `Future::wait`,
+//! in which case the work is simply done inline.
+//!
+//! A future does its work with the active `PackageTarget` of the thread which created it, even
+//! when it is polled on another thread, so an override in place when it is created still applies.
+//!
+//! This module is only built with the `async` feature.
+
+use std::path::PathBuf;
+
+use futures::{future,
+              Async,
+              Future};
+use tokio_threadpool;
+
+use crate::{error::{Error,
+                    Result},
+            package::{self,
+                      PackageArchive,
+                      PackageIdent,
+                      PackageInstall,
+                      PackageTarget}};
+
+/// Returns a future which runs the blocking function `f`, marking the current thread pool
+/// worker as blocked while it does. `f` runs with the active target of the thread calling
+/// `blocking`.
+pub fn blocking<T, F>(f: F) -> impl Future<Item = T, Error = Error>
+    where F: FnOnce() -> Result<T>
+{
+    let target = PackageTarget::active_target();
+    let mut f = Some(f);
+    let mut run = move || {
+        let f = f.take().expect("Polled after completion");
+        PackageTarget::with_active_target(target, f)
+    };
+    future::poll_fn(move || {
+        match tokio_threadpool::blocking(&mut run) {
+            Ok(Async::Ready(result)) => result.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // Not running on a thread pool, so there is nothing to hand work off to
+            Err(_) => run().map(Async::Ready),
+        }
+    })
+}
+
+/// As `PackageInstall::load`.
+pub fn load_package(ident: PackageIdent,
+                    fs_root_path: Option<PathBuf>)
+                    -> impl Future<Item = PackageInstall, Error = Error> {
+    blocking(move || PackageInstall::load(&ident, fs_root_path.as_ref().map(PathBuf::as_path)))
+}
+
+/// As `package::all_packages`.
+pub fn all_packages(path: PathBuf) -> impl Future<Item = Vec<PackageIdent>, Error = Error> {
+    blocking(move || package::all_packages(&path))
+}
+
+/// As `PackageArchive::verify`, resolving to the archive along with the signer's key name and
+/// the archive's checksum.
+pub fn verify_archive(archive: PackageArchive,
+                      cache_key_path: PathBuf)
+                      -> impl Future<Item = (PackageArchive, (String, String)), Error = Error> {
+    blocking(move || {
+        let verified = archive.verify(&cache_key_path)?;
+        Ok((archive, verified))
+    })
+}
+
+/// As `PackageArchive::unpack`, resolving to the archive once it has been unpacked.
+pub fn unpack_archive(archive: PackageArchive,
+                      fs_root_path: Option<PathBuf>)
+                      -> impl Future<Item = PackageArchive, Error = Error> {
+    blocking(move || {
+        archive.unpack(fs_root_path.as_ref().map(PathBuf::as_path))?;
+        Ok(archive)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{fs,
+                package::test_support::testing_package_install};
+    use std::str::FromStr;
+    use tempfile::Builder;
+    use tokio_threadpool::ThreadPool;
+
+    #[test]
+    fn futures_resolve_outside_a_thread_pool() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let installed = testing_package_install("core/redis/5.0.3/20190319155852", fs_root.path());
+        let ident = PackageIdent::from_str("core/redis").unwrap();
+
+        assert_eq!(installed,
+                   load_package(ident, Some(fs_root.path().to_path_buf())).wait()
+                                                                          .unwrap());
+        match load_package(PackageIdent::from_str("core/nginx").unwrap(),
+                           Some(fs_root.path().to_path_buf())).wait()
+        {
+            Err(Error::PackageNotFound(_)) => {}
+            other => panic!("Expected PackageNotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn futures_resolve_on_a_thread_pool() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let installed = testing_package_install("core/redis/5.0.3/20190319155852", fs_root.path());
+        let pool = ThreadPool::new();
+
+        let listed = pool.spawn_handle(all_packages(fs::pkg_root_path(Some(fs_root.path()))))
+                         .wait()
+                         .unwrap();
+
+        assert_eq!(vec![installed.ident().clone()], listed);
+    }
+
+    #[test]
+    fn futures_run_with_the_active_target_they_were_created_with() {
+        let target =
+            *PackageTarget::supported_targets().find(|&&t| t != PackageTarget::system_target())
+                                               .unwrap();
+        let pool = ThreadPool::new();
+
+        let active = PackageTarget::with_active_target(target, || {
+            blocking(|| Ok(PackageTarget::active_target()))
+        });
+
+        assert_eq!(target, pool.spawn_handle(active).wait().unwrap());
+    }
+}
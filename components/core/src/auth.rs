@@ -0,0 +1,385 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Storage for a user's Builder auth token.
+//!
+//! Tokens are kept in the OS keyring where there is one: the Secret Service (through
+//! `secret-tool`) on Linux, the login Keychain (through `security`) on macOS, and on Windows a
+//! file under the auth cache encrypted with DPAPI, which only the same user can decrypt. When the
+//! keyring can't be used, such as on a headless host with no Secret Service running, tokens fall
+//! back to a plain file under the auth cache which only the user can read.
+//!
+//! On macOS the token is briefly visible in the arguments of the `security` command as it is
+//! stored.
+
+use std::{fs as stdfs,
+          io,
+          path::{Path,
+                 PathBuf}};
+#[cfg(not(windows))]
+use std::{io::Write,
+          process::{Command,
+                    Stdio}};
+
+use crate::{error::{Error,
+                    Result},
+            fs};
+
+/// The service tokens are filed under in the keyring.
+pub const KEYRING_SERVICE: &str = "habitat";
+/// The account a token is filed under when none is given.
+pub const DEFAULT_ACCOUNT: &str = "default";
+
+/// Where a token was stored.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Storage {
+    Keyring,
+    /// The plain file fallback.
+    File,
+}
+
+/// A user's auth token, in the keyring or the auth cache.
+///
+/// A store may hold one token for each account, such as one per Builder instance.
+#[derive(Clone, Debug)]
+pub struct AuthTokenStore {
+    account:     String,
+    cache_path:  PathBuf,
+    use_keyring: bool,
+}
+
+impl AuthTokenStore {
+    /// A store for the default account, optionally taking a custom filesystem root for the
+    /// auth cache.
+    pub fn new(fs_root_path: Option<&Path>) -> Self {
+        AuthTokenStore { account:     DEFAULT_ACCOUNT.to_string(),
+                         cache_path:  fs::cache_auth_path(fs_root_path),
+                         use_keyring: true, }
+    }
+
+    /// Files the token under `account` rather than the default.
+    pub fn account<S: Into<String>>(mut self, account: S) -> Self {
+        self.account = account.into();
+        self
+    }
+
+    /// Whether to try the keyring before the plain file. Tests and hosts where the keyring is
+    /// known to be missing can turn it off.
+    pub fn use_keyring(mut self, use_keyring: bool) -> Self {
+        self.use_keyring = use_keyring;
+        self
+    }
+
+    /// The plain file the token falls back to.
+    pub fn file_path(&self) -> PathBuf { self.cache_path.join(&self.account) }
+
+    /// Returns the stored token, if there is one, looking in the keyring before the file.
+    ///
+    /// # Failures
+    ///
+    /// * The token file exists but cannot be read
+    pub fn load(&self) -> Result<Option<String>> {
+        if self.use_keyring {
+            match keyring::get(&self.cache_path, &self.account) {
+                Ok(Some(token)) => return Ok(Some(token)),
+                Ok(None) => {}
+                Err(e) => debug!("Not reading auth token from keyring: {}", e),
+            }
+        }
+        match stdfs::read_to_string(self.file_path()) {
+            Ok(token) => Ok(Some(token.trim().to_string()).filter(|t| !t.is_empty())),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::IO(e)),
+        }
+    }
+
+    /// Stores `token` in the keyring, or in the file if the keyring can't be used, returning
+    /// where it went. Storing in the keyring removes any token left in the file.
+    ///
+    /// # Failures
+    ///
+    /// * The keyring can't be used and the file cannot be written
+    pub fn store(&self, token: &str) -> Result<Storage> {
+        if self.use_keyring {
+            match keyring::set(&self.cache_path, &self.account, token) {
+                Ok(()) => {
+                    self.remove_file()?;
+                    return Ok(Storage::Keyring);
+                }
+                Err(e) => {
+                    warn!("Storing auth token in {} instead of the keyring: {}",
+                          self.file_path().display(),
+                          e)
+                }
+            }
+        }
+        stdfs::create_dir_all(&self.cache_path)?;
+        // The temporary file the token is written through is only readable by its owner
+        fs::atomic_write(&self.file_path(), token)?;
+        Ok(Storage::File)
+    }
+
+    /// Removes the token from both the keyring and the file.
+    ///
+    /// # Failures
+    ///
+    /// * The token is in the keyring but cannot be removed from it
+    /// * The file exists but cannot be removed
+    pub fn remove(&self) -> Result<()> {
+        if self.use_keyring {
+            if let Err(e) = keyring::delete(&self.cache_path, &self.account) {
+                // Failing to remove a token is only a problem if there is a token
+                if let Ok(Some(_)) = keyring::get(&self.cache_path, &self.account) {
+                    return Err(e);
+                }
+                debug!("Not removing auth token from keyring: {}", e);
+            }
+        }
+        self.remove_file()
+    }
+
+    fn remove_file(&self) -> Result<()> {
+        match stdfs::remove_file(self.file_path()) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::IO(e)),
+        }
+    }
+}
+
+/// The exit code and output of a keyring tool.
+#[cfg(not(windows))]
+struct Output {
+    code:   Option<i32>,
+    stdout: String,
+    stderr: String,
+}
+
+#[cfg(not(windows))]
+impl Output {
+    fn success(&self) -> bool { self.code == Some(0) }
+
+    fn into_error(self, tool: &str) -> Error {
+        Error::KeyringUnavailable(format!("{} exited with {:?}: {}", tool, self.code, self.stderr))
+    }
+}
+
+/// Runs a keyring tool, feeding `input` to its standard input.
+#[cfg(not(windows))]
+fn run(args: &[&str], input: Option<&str>) -> Result<Output> {
+    let unavailable = |e: io::Error| Error::KeyringUnavailable(format!("{}: {}", args[0], e));
+    let mut child = Command::new(args[0]).args(&args[1..])
+                                         .stdin(Stdio::piped())
+                                         .stdout(Stdio::piped())
+                                         .stderr(Stdio::piped())
+                                         .spawn()
+                                         .map_err(unavailable)?;
+    if let Some(input) = input {
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        stdin.write_all(input.as_bytes()).map_err(unavailable)?;
+    }
+    let output = child.wait_with_output().map_err(unavailable)?;
+    Ok(Output { code:   output.status.code(),
+                stdout: String::from_utf8_lossy(&output.stdout).trim().to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(), })
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod keyring {
+    use super::{run,
+                KEYRING_SERVICE};
+    use crate::error::Result;
+    use std::path::Path;
+
+    pub fn get(_cache_path: &Path, account: &str) -> Result<Option<String>> {
+        let output = run(&["secret-tool",
+                           "lookup",
+                           "service",
+                           KEYRING_SERVICE,
+                           "account",
+                           account],
+                         None)?;
+        if output.success() {
+            Ok(Some(output.stdout).filter(|t| !t.is_empty()))
+        } else if output.stderr.is_empty() {
+            // Nothing is stored under the account
+            Ok(None)
+        } else {
+            Err(output.into_error("secret-tool"))
+        }
+    }
+
+    pub fn set(_cache_path: &Path, account: &str, token: &str) -> Result<()> {
+        let label = format!("Habitat auth token ({})", account);
+        let output = run(&["secret-tool",
+                           "store",
+                           "--label",
+                           &label,
+                           "service",
+                           KEYRING_SERVICE,
+                           "account",
+                           account],
+                         Some(token))?;
+        if output.success() {
+            Ok(())
+        } else {
+            Err(output.into_error("secret-tool"))
+        }
+    }
+
+    pub fn delete(_cache_path: &Path, account: &str) -> Result<()> {
+        let output = run(&["secret-tool",
+                           "clear",
+                           "service",
+                           KEYRING_SERVICE,
+                           "account",
+                           account],
+                         None)?;
+        if output.success() {
+            Ok(())
+        } else {
+            Err(output.into_error("secret-tool"))
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod keyring {
+    use super::{run,
+                KEYRING_SERVICE};
+    use crate::error::Result;
+    use std::path::Path;
+
+    /// What `security` exits with when there is no such item.
+    const ERR_SEC_ITEM_NOT_FOUND: i32 = 44;
+
+    pub fn get(_cache_path: &Path, account: &str) -> Result<Option<String>> {
+        let output = run(&["security",
+                           "find-generic-password",
+                           "-s",
+                           KEYRING_SERVICE,
+                           "-a",
+                           account,
+                           "-w"],
+                         None)?;
+        if output.success() {
+            Ok(Some(output.stdout).filter(|t| !t.is_empty()))
+        } else if output.code == Some(ERR_SEC_ITEM_NOT_FOUND) {
+            Ok(None)
+        } else {
+            Err(output.into_error("security"))
+        }
+    }
+
+    pub fn set(_cache_path: &Path, account: &str, token: &str) -> Result<()> {
+        let output = run(&["security",
+                           "add-generic-password",
+                           "-U",
+                           "-s",
+                           KEYRING_SERVICE,
+                           "-a",
+                           account,
+                           "-w",
+                           token],
+                         None)?;
+        if output.success() {
+            Ok(())
+        } else {
+            Err(output.into_error("security"))
+        }
+    }
+
+    pub fn delete(_cache_path: &Path, account: &str) -> Result<()> {
+        let output = run(&["security",
+                           "delete-generic-password",
+                           "-s",
+                           KEYRING_SERVICE,
+                           "-a",
+                           account],
+                         None)?;
+        if output.success() || output.code == Some(ERR_SEC_ITEM_NOT_FOUND) {
+            Ok(())
+        } else {
+            Err(output.into_error("security"))
+        }
+    }
+}
+
+#[cfg(windows)]
+mod keyring {
+    use crate::{crypto::dpapi,
+                error::{Error,
+                        Result}};
+    use std::{fs,
+              io,
+              path::{Path,
+                     PathBuf}};
+
+    fn path(cache_path: &Path, account: &str) -> PathBuf {
+        cache_path.join(format!("{}.dpapi", account))
+    }
+
+    pub fn get(cache_path: &Path, account: &str) -> Result<Option<String>> {
+        match fs::read_to_string(path(cache_path, account)) {
+            Ok(secret) => dpapi::decrypt(secret.trim().to_string()).map(Some),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::IO(e)),
+        }
+    }
+
+    pub fn set(cache_path: &Path, account: &str, token: &str) -> Result<()> {
+        let secret = dpapi::encrypt(token.to_string())?;
+        fs::create_dir_all(cache_path)?;
+        crate::fs::atomic_write(&path(cache_path, account), secret)?;
+        Ok(())
+    }
+
+    pub fn delete(cache_path: &Path, account: &str) -> Result<()> {
+        match fs::remove_file(path(cache_path, account)) {
+            Ok(()) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::IO(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn file_storage_round_trip() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let store = AuthTokenStore::new(Some(fs_root.path())).account("bldr.example.com")
+                                                             .use_keyring(false);
+
+        assert_eq!(None, store.load().unwrap());
+        assert_eq!(Storage::File, store.store("_Qk9YLTEK").unwrap());
+        assert_eq!(Some("_Qk9YLTEK".to_string()), store.load().unwrap());
+        assert!(store.file_path().ends_with("bldr.example.com"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = stdfs::metadata(store.file_path()).unwrap()
+                                                         .permissions()
+                                                         .mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+
+        store.remove().unwrap();
+        assert_eq!(None, store.load().unwrap());
+        store.remove().unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! What a program built on this crate was built from, for `--version` output and bug reports.
+//!
+//! The build script records the git commit, Rust target, profile, and enabled features of
+//! this crate when it is compiled. A program adds its own name and version with
+//! `BuildInfo::new`, so that every program reports the same fields in the same shape:
+//!
+//! ```
+//! use habitat_core::build_info::BuildInfo;
+//!
+//! let info = BuildInfo::new("hab", "0.79.1");
+//! println!("{}", info);
+//! println!("{}", serde_json::to_string(&info).unwrap());
+//! ```
+
+use std::fmt;
+
+use serde_derive::Serialize;
+
+use crate::package::PackageTarget;
+
+/// The version of this crate.
+pub const CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Set by the build script. `option_env!` keeps the crate building without it, such as when
+// compiled by tools which skip build scripts.
+const GIT_SHA: Option<&str> = option_env!("HAB_CORE_GIT_SHA");
+const BUILD_TARGET: Option<&str> = option_env!("HAB_CORE_BUILD_TARGET");
+const BUILD_PROFILE: Option<&str> = option_env!("HAB_CORE_BUILD_PROFILE");
+const FEATURES: Option<&str> = option_env!("HAB_CORE_FEATURES");
+
+/// Identifies a build of a program.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct BuildInfo {
+    pub name:           String,
+    pub version:        String,
+    pub core_version:   String,
+    /// The commit this crate was built from, if it was built from a git checkout.
+    pub git_sha:        Option<String>,
+    /// The Rust target triple, such as `x86_64-unknown-linux-gnu`.
+    pub target:         String,
+    /// The package target the program treats as active, such as `x86_64-linux`.
+    pub package_target: String,
+    /// `debug` or `release`.
+    pub profile:        String,
+    /// The features this crate was built with.
+    pub features:       Vec<String>,
+}
+
+impl BuildInfo {
+    pub fn new<N, V>(name: N, version: V) -> Self
+        where N: Into<String>,
+              V: Into<String>
+    {
+        let features = FEATURES.unwrap_or("")
+                               .split(',')
+                               .filter(|f| !f.is_empty())
+                               .map(str::to_string)
+                               .collect();
+        BuildInfo { name: name.into(),
+                    version: version.into(),
+                    core_version: CORE_VERSION.to_string(),
+                    git_sha: GIT_SHA.filter(|s| !s.is_empty()).map(str::to_string),
+                    target: BUILD_TARGET.unwrap_or("unknown").to_string(),
+                    package_target: PackageTarget::system_target().to_string(),
+                    profile: BUILD_PROFILE.unwrap_or("unknown").to_string(),
+                    features }
+    }
+
+    /// The build of this crate itself.
+    pub fn core() -> Self { Self::new("habitat_core", CORE_VERSION) }
+
+    /// The first eight characters of the commit, as shown in `--version` output.
+    pub fn short_sha(&self) -> Option<&str> { self.git_sha.as_ref().map(|s| &s[..s.len().min(8)]) }
+}
+
+/// The one line form for `--version`, such as `hab 0.79.1 (3a2b5c7d, x86_64-linux)`.
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} (", self.name, self.version)?;
+        if let Some(sha) = self.short_sha() {
+            write!(f, "{}, ", sha)?;
+        }
+        write!(f, "{})", self.package_target)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn describes_the_build() {
+        let info = BuildInfo::new("hab", "0.79.1");
+
+        assert_eq!(CORE_VERSION, info.core_version);
+        assert!(!info.target.is_empty());
+        assert!(info.to_string().starts_with("hab 0.79.1 ("));
+        assert!(info.to_string()
+                    .ends_with(&format!("{})", PackageTarget::system_target())));
+
+        let info = BuildInfo { git_sha: Some("3a2b5c7d9e0f".to_string()),
+                               ..info };
+        assert_eq!(Some("3a2b5c7d"), info.short_sha());
+        assert!(info.to_string().contains("(3a2b5c7d, "));
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The values CLIs accept for arguments naming core types, so that shell completions and
+//! argument validation can be generated from the types themselves instead of from lists kept in
+//! each CLI, which go stale as variants are added here.
+//!
+//! ```
+//! use habitat_core::cli_support;
+//!
+//! let topologies = cli_support::topologies();
+//! assert_eq!(vec!["standalone", "leader"], topologies.values);
+//! assert!(topologies.accepts("Leader"));
+//! assert!(!topologies.accepts("follower"));
+//! ```
+
+use std::str::FromStr;
+
+use serde_derive::Serialize;
+
+#[cfg(unix)]
+use crate::os::process::Signal;
+use crate::{package::PackageTarget,
+            service::{update::{UpdateCondition,
+                               UpdateStrategy},
+                      BindingMode,
+                      Topology},
+            ChannelIdent};
+
+/// Which type a `ValueSet` lists the values of.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Target,
+    Channel,
+    Topology,
+    UpdateStrategy,
+    UpdateCondition,
+    BindingMode,
+    #[cfg(unix)]
+    Signal,
+}
+
+/// The values an argument accepts.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ValueSet {
+    /// What the values are, such as `topology`.
+    pub name:    &'static str,
+    /// The values to offer as completions, in the order to offer them.
+    pub values:  Vec<String>,
+    /// The value used when the argument is not given, if there is one.
+    pub default: Option<String>,
+    /// Whether values besides `values` are accepted. Channels, for example, may be created with
+    /// any name, so only the well-known ones are listed.
+    pub open:    bool,
+    #[serde(skip)]
+    kind:        Kind,
+}
+
+impl ValueSet {
+    fn new<T: ToString>(name: &'static str, kind: Kind, values: &[T], default: T) -> Self {
+        ValueSet { name,
+                   values: values.iter().map(ToString::to_string).collect(),
+                   default: Some(default.to_string()),
+                   open: false,
+                   kind }
+    }
+
+    /// Whether `value` is valid for the argument, as the type's own parsing judges it. This can
+    /// be more lenient than `values` suggests, as with the case-insensitive topologies.
+    pub fn accepts(&self, value: &str) -> bool {
+        match self.kind {
+            Kind::Target => PackageTarget::from_str(value).is_ok(),
+            Kind::Channel => !value.trim().is_empty(),
+            Kind::Topology => Topology::from_str(value).is_ok(),
+            Kind::UpdateStrategy => UpdateStrategy::from_str(value).is_ok(),
+            Kind::UpdateCondition => UpdateCondition::from_str(value).is_ok(),
+            Kind::BindingMode => BindingMode::from_str(value).is_ok(),
+            #[cfg(unix)]
+            Kind::Signal => Signal::from_str(value).is_ok(),
+        }
+    }
+}
+
+/// The package targets this build of core supports, defaulting to the one it is running as.
+pub fn targets() -> ValueSet {
+    let targets: Vec<_> = PackageTarget::supported_targets().collect();
+    ValueSet::new("target",
+                  Kind::Target,
+                  &targets,
+                  &PackageTarget::active_target())
+}
+
+/// The well-known Builder channels. Any other channel name is accepted too.
+pub fn channels() -> ValueSet {
+    ValueSet { open: true,
+               ..ValueSet::new("channel",
+                               Kind::Channel,
+                               &[ChannelIdent::stable(), ChannelIdent::unstable()],
+                               ChannelIdent::default()) }
+}
+
+pub fn topologies() -> ValueSet {
+    ValueSet::new("topology",
+                  Kind::Topology,
+                  Topology::variants(),
+                  Topology::default())
+}
+
+pub fn update_strategies() -> ValueSet {
+    ValueSet::new("update strategy",
+                  Kind::UpdateStrategy,
+                  UpdateStrategy::variants(),
+                  UpdateStrategy::default())
+}
+
+pub fn update_conditions() -> ValueSet {
+    ValueSet::new("update condition",
+                  Kind::UpdateCondition,
+                  UpdateCondition::variants(),
+                  UpdateCondition::default())
+}
+
+pub fn binding_modes() -> ValueSet {
+    ValueSet::new("binding mode",
+                  Kind::BindingMode,
+                  BindingMode::variants(),
+                  BindingMode::default())
+}
+
+/// The signals a service can be sent. There is no default, so a CLI has to ask for one.
+#[cfg(unix)]
+pub fn signals() -> ValueSet {
+    ValueSet { default: None,
+               ..ValueSet::new("signal", Kind::Signal, Signal::variants(), Signal::HUP) }
+}
+
+/// Every set of values listed here.
+pub fn all() -> Vec<ValueSet> {
+    #[cfg_attr(not(unix), allow(unused_mut))]
+    let mut all = vec![targets(),
+                       channels(),
+                       topologies(),
+                       update_strategies(),
+                       update_conditions(),
+                       binding_modes()];
+    #[cfg(unix)]
+    all.push(signals());
+    all
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_listed_value_is_accepted() {
+        for set in all() {
+            assert!(!set.values.is_empty(), "{} lists no values", set.name);
+            for value in set.values.iter().chain(set.default.iter()) {
+                assert!(set.accepts(value), "{} does not accept {}", set.name, value);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_values_are_rejected_unless_open() {
+        for set in all() {
+            assert_eq!(set.open, set.accepts("no-such-value"), "{}", set.name);
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn signals_parse_with_or_without_prefix() {
+        let signals = signals();
+        assert!(signals.values.contains(&"HUP".to_string()));
+        assert!(signals.accepts("SIGHUP"));
+        assert!(signals.accepts("usr1"));
+        assert!(!signals.accepts("SIG"));
+    }
+}
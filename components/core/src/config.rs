@@ -12,6 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod flatten;
+pub mod layered;
+
 use std::{error::Error as StdError,
           fs::File,
           io::Read,
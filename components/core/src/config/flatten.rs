@@ -0,0 +1,320 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Conversions of a TOML configuration tree into other forms services can consume directly:
+//! JSON, and environment variables for services configured in the twelve-factor style.
+//!
+//! # Environment variables
+//!
+//! Each value other than a table becomes one variable, named `HAB_CFG_` followed by the keys on
+//! the path to the value, separated by `__`:
+//!
+//! ```toml
+//! port = 6379
+//! [tls]
+//! cert_file = "/hab/svc/redis/files/cert.pem"
+//! ```
+//!
+//! becomes `HAB_CFG_PORT=6379` and `HAB_CFG_TLS__CERT_FILE=/hab/svc/redis/files/cert.pem`.
+//!
+//! Keys are escaped so that any key can be recovered from its variable name:
+//!
+//! * Lower case ASCII letters are written in upper case, and digits as they are
+//! * `_` is written as it is, unless it starts or ends the key or follows another `_`
+//! * Every other byte of the key, upper case letters included, is written as `x` followed by its
+//!   value as two lower case hex digits, so that `max-conns` becomes `MAXx2dCONNS`
+//!
+//! An empty key cannot be written, and neither can an empty table, which has no values.
+//!
+//! Values are written as they would be in TOML, except that a string is written bare unless it
+//! would read back as some other TOML value, such as `"42"` or `" true"`. Arrays are written
+//! whole, as inline TOML. Reading a variable back, its value is taken as TOML if it parses as a
+//! single TOML value, and as a bare string otherwise.
+
+use std::collections::BTreeMap;
+
+use serde_json::{self,
+                 Number};
+use toml::{self,
+           value::Table,
+           Value};
+
+use crate::error::{Error,
+                   Result};
+
+/// The prefix of every variable holding a configuration value.
+pub const ENV_PREFIX: &str = "HAB_CFG_";
+
+/// The separator between the keys in a variable name.
+pub const ENV_SEPARATOR: &str = "__";
+
+/// Returns the variables holding each value in `table`, by name.
+///
+/// # Failures
+///
+/// * A key in `table` is empty
+///
+/// # Examples
+///
+/// ```
+/// use habitat_core::config::flatten;
+///
+/// let table = toml::from_str("port = 6379\n[tls]\nenabled = true\n").unwrap();
+/// let vars = flatten::to_env(&table).unwrap();
+///
+/// assert_eq!("6379", vars["HAB_CFG_PORT"]);
+/// assert_eq!("true", vars["HAB_CFG_TLS__ENABLED"]);
+/// ```
+pub fn to_env(table: &Table) -> Result<BTreeMap<String, String>> {
+    let mut vars = BTreeMap::new();
+    flatten_into(table, ENV_PREFIX, &mut vars)?;
+    Ok(vars)
+}
+
+fn flatten_into(table: &Table, prefix: &str, vars: &mut BTreeMap<String, String>) -> Result<()> {
+    for (key, value) in table {
+        let name = format!("{}{}", prefix, encode_key(key)?);
+        match *value {
+            Value::Table(ref table) => {
+                flatten_into(table, &format!("{}{}", name, ENV_SEPARATOR), vars)?
+            }
+            ref value => {
+                vars.insert(name, encode_value(value));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rebuilds a configuration tree from the variables named with `ENV_PREFIX` among `vars`, such
+/// as those returned by `to_env` or `std::env::vars()`. Other variables are ignored.
+///
+/// # Failures
+///
+/// * A variable's name does not follow the escaping rules
+/// * One variable holds a value where another needs a table, such as `HAB_CFG_TLS` alongside
+///   `HAB_CFG_TLS__ENABLED`
+pub fn from_env<I, K, V>(vars: I) -> Result<Table>
+    where I: IntoIterator<Item = (K, V)>,
+          K: AsRef<str>,
+          V: AsRef<str>
+{
+    let mut table = Table::new();
+    for (name, raw) in vars {
+        let name = name.as_ref();
+        if !name.starts_with(ENV_PREFIX) {
+            continue;
+        }
+        let keys = name[ENV_PREFIX.len()..].split(ENV_SEPARATOR)
+                                           .map(|k| decode_key(k).ok_or_else(|| invalid(name)))
+                                           .collect::<Result<Vec<_>>>()?;
+        let (last, parents) = keys.split_last().ok_or_else(|| invalid(name))?;
+        let mut current = &mut table;
+        for key in parents {
+            current = match *current.entry(key.clone())
+                                    .or_insert_with(|| Value::Table(Table::new()))
+            {
+                Value::Table(ref mut table) => table,
+                _ => return Err(conflict(name)),
+            };
+        }
+        if current.contains_key(last) {
+            return Err(conflict(name));
+        }
+        current.insert(last.clone(), decode_value(raw.as_ref()));
+    }
+    Ok(table)
+}
+
+fn invalid(name: &str) -> Error {
+    Error::ConfigEnvInvalid(name.to_string(), "not a valid escaped key path".to_string())
+}
+
+fn conflict(name: &str) -> Error {
+    Error::ConfigEnvInvalid(name.to_string(),
+                            "conflicts with the value of another variable".to_string())
+}
+
+/// Converts a TOML value to JSON. Datetimes become strings in RFC 3339 format, and floats
+/// which JSON cannot hold, such as NaN, become null.
+pub fn to_json(value: &Value) -> serde_json::Value {
+    match *value {
+        Value::String(ref s) => serde_json::Value::String(s.clone()),
+        Value::Integer(i) => serde_json::Value::Number(i.into()),
+        Value::Float(f) => {
+            Number::from_f64(f).map_or(serde_json::Value::Null, serde_json::Value::Number)
+        }
+        Value::Boolean(b) => serde_json::Value::Bool(b),
+        Value::Datetime(ref d) => serde_json::Value::String(d.to_string()),
+        Value::Array(ref values) => serde_json::Value::Array(values.iter().map(to_json).collect()),
+        Value::Table(ref table) => {
+            serde_json::Value::Object(table.iter().map(|(k, v)| (k.clone(), to_json(v))).collect())
+        }
+    }
+}
+
+/// Escapes `key` for use in a variable name.
+fn encode_key(key: &str) -> Result<String> {
+    if key.is_empty() {
+        return Err(Error::ConfigEnvInvalid(String::new(), "empty key".to_string()));
+    }
+    let bytes = key.as_bytes();
+    let mut encoded = String::with_capacity(key.len());
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'a'..=b'z' => encoded.push(b.to_ascii_uppercase() as char),
+            b'0'..=b'9' => encoded.push(b as char),
+            b'_' if i > 0 && i < bytes.len() - 1 && bytes[i - 1] != b'_' => encoded.push('_'),
+            _ => encoded.push_str(&format!("x{:02x}", b)),
+        }
+    }
+    Ok(encoded)
+}
+
+/// Recovers a key escaped by `encode_key`, if `encoded` is one.
+fn decode_key(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut chars = encoded.bytes();
+    while let Some(c) = chars.next() {
+        match c {
+            b'A'..=b'Z' => bytes.push(c.to_ascii_lowercase()),
+            b'0'..=b'9' | b'_' => bytes.push(c),
+            b'x' => {
+                let hex = [chars.next()?, chars.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                if hex.bytes().any(|h| h.is_ascii_uppercase()) {
+                    return None;
+                }
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            _ => return None,
+        }
+    }
+    let key = String::from_utf8(bytes).ok()?;
+    if key.is_empty() || encode_key(&key).ok()? != encoded {
+        return None;
+    }
+    Some(key)
+}
+
+fn encode_value(value: &Value) -> String {
+    match *value {
+        Value::String(ref s) if parse_value(s).is_none() => s.clone(),
+        ref value => value.to_string(),
+    }
+}
+
+fn decode_value(raw: &str) -> Value {
+    parse_value(raw).unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+/// Parses `raw` as a single TOML value, if it is one.
+fn parse_value(raw: &str) -> Option<Value> {
+    let mut table = toml::from_str::<Table>(&format!("v = {}", raw)).ok()?;
+    if table.len() == 1 {
+        table.remove("v")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn table(raw: &str) -> Table { toml::from_str(raw).unwrap() }
+
+    #[test]
+    fn keys_round_trip() {
+        for &(key, encoded) in &[("port", "PORT"),
+                                 ("max_conns", "MAX_CONNS"),
+                                 ("max-conns", "MAXx2dCONNS"),
+                                 ("_private", "x5fPRIVATE"),
+                                 ("a__b", "A_x5fB"),
+                                 ("trailing_", "TRAILINGx5f"),
+                                 ("Mixed", "x4dIXED"),
+                                 ("tls.v1", "TLSx2eV1"),
+                                 ("x", "X"),
+                                 ("café", "CAFxc3xa9")]
+        {
+            assert_eq!(encoded, encode_key(key).unwrap());
+            assert_eq!(Some(key.to_string()), decode_key(encoded));
+        }
+        assert!(encode_key("").is_err());
+        for encoded in &["", "max-conns", "x2", "x2D", "A__B", "_PORT", "Px6fRT"] {
+            assert_eq!(None, decode_key(encoded), "{}", encoded);
+        }
+    }
+
+    #[test]
+    fn tables_round_trip() {
+        let config = table(
+                           r#"
+port = 6379
+ratio = 0.5
+debug = false
+name = "redis"
+number = "42"
+padded = " true"
+save = ["900 1", "300 10"]
+started = 1979-05-27T07:32:00Z
+[tls]
+cert_file = "/hab/svc/redis/files/cert.pem"
+[tls.client-auth]
+required = true
+"#,
+        );
+
+        let vars = to_env(&config).unwrap();
+
+        assert_eq!("6379", vars["HAB_CFG_PORT"]);
+        assert_eq!("redis", vars["HAB_CFG_NAME"]);
+        assert_eq!("\"42\"", vars["HAB_CFG_NUMBER"]);
+        assert_eq!("\" true\"", vars["HAB_CFG_PADDED"]);
+        assert_eq!("/hab/svc/redis/files/cert.pem",
+                   vars["HAB_CFG_TLS__CERT_FILE"]);
+        assert_eq!("true", vars["HAB_CFG_TLS__CLIENTx2dAUTH__REQUIRED"]);
+        assert_eq!(config, from_env(&vars).unwrap());
+    }
+
+    #[test]
+    fn from_env_ignores_other_vars_and_rejects_conflicts() {
+        let vars = vec![("PATH", "/bin"), ("HAB_CFG_PORT", "80")];
+        assert_eq!(table("port = 80"), from_env(vars).unwrap());
+
+        let vars = vec![("HAB_CFG_TLS", "off"), ("HAB_CFG_TLS__ENABLED", "true")];
+        match from_env(vars) {
+            Err(Error::ConfigEnvInvalid(ref name, _)) => assert_eq!("HAB_CFG_TLS__ENABLED", name),
+            other => panic!("Expected ConfigEnvInvalid, got {:?}", other),
+        }
+        match from_env(vec![("HAB_CFG_max", "1")]) {
+            Err(Error::ConfigEnvInvalid(..)) => {}
+            other => panic!("Expected ConfigEnvInvalid, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn to_json_converts_every_type() {
+        let config = table("port = 80\nratio = nan\nsave = [\"a\"]\nstarted = \
+                            1979-05-27T07:32:00Z\n[tls]\nenabled = true\n");
+
+        assert_eq!(json!({ "port": 80,
+                           "ratio": null,
+                           "save": ["a"],
+                           "started": "1979-05-27T07:32:00Z",
+                           "tls": { "enabled": true } }),
+                   to_json(&Value::Table(config)));
+    }
+}
@@ -0,0 +1,287 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A service's configuration, layered from each of the places it can come from, along with which
+//! layer supplied each value.
+//!
+//! Layers are applied lowest precedence first, each overriding the values of those before it:
+//!
+//! 1. `default.toml`, shipped in the package
+//! 2. `user.toml`, in `/hab/user/<name>/config`
+//! 3. A TOML document in the environment variable `HAB_<NAME>`, where `<NAME>` is the service name
+//!    in upper case with each `-` replaced by `_`
+//!
+//! Tables are merged key by key; any other value, arrays included, replaces the value below it
+//! as a whole. Any layer may be absent.
+
+use std::{collections::BTreeMap,
+          env::VarError,
+          fmt,
+          fs,
+          io,
+          path::{Path,
+                 PathBuf}};
+
+use serde_derive::Serialize;
+use toml::{self,
+           value::Table,
+           Value};
+
+use crate::{env,
+            error::{Error,
+                    Result},
+            fs::{user_config_path,
+                 USER_CONFIG_FILE},
+            package::{install::DEFAULT_CFG_FILE,
+                      PackageInstall}};
+
+/// A layer of a service's configuration, in order of precedence from lowest to highest.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum ConfigLayer {
+    Default,
+    User,
+    Environment,
+}
+
+impl fmt::Display for ConfigLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            ConfigLayer::Default => "default",
+            ConfigLayer::User => "user",
+            ConfigLayer::Environment => "environment",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Returns the environment variable a service's configuration layer is read from.
+///
+/// # Examples
+///
+/// ```
+/// use habitat_core::config::layered::env_var_name;
+///
+/// assert_eq!("HAB_NGINX_PROXY", env_var_name("nginx-proxy"));
+/// ```
+pub fn env_var_name(service_name: &str) -> String {
+    format!("HAB_{}",
+            service_name.to_ascii_uppercase().replace("-", "_"))
+}
+
+/// The places a service's configuration layers are read from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConfigSources {
+    default_toml: PathBuf,
+    user_toml:    PathBuf,
+    env_var:      String,
+}
+
+impl ConfigSources {
+    /// The well-known places for the service `service_name` running the package `pkg`.
+    pub fn new(pkg: &PackageInstall, service_name: &str) -> Self {
+        ConfigSources { default_toml: pkg.installed_path().join(DEFAULT_CFG_FILE),
+                        user_toml:    user_config_path(service_name).join(USER_CONFIG_FILE),
+                        env_var:      env_var_name(service_name), }
+    }
+
+    /// Reads `default.toml` from `path` instead.
+    pub fn default_toml<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.default_toml = path.into();
+        self
+    }
+
+    /// Reads `user.toml` from `path` instead.
+    pub fn user_toml<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.user_toml = path.into();
+        self
+    }
+
+    /// Reads the environment layer from the variable `name` instead.
+    pub fn env_var<T: Into<String>>(mut self, name: T) -> Self {
+        self.env_var = name.into();
+        self
+    }
+
+    /// Reads and merges every layer which is present.
+    ///
+    /// # Failures
+    ///
+    /// * A layer's file exists but cannot be read
+    /// * A layer is not valid TOML
+    pub fn load(&self) -> Result<LayeredConfig> {
+        let mut config = LayeredConfig::new();
+        if let Some(table) = read_toml_file(&self.default_toml)? {
+            config = config.layer(ConfigLayer::Default, table);
+        }
+        if let Some(table) = read_toml_file(&self.user_toml)? {
+            config = config.layer(ConfigLayer::User, table);
+        }
+        match env::var(&self.env_var) {
+            Ok(raw) => {
+                let table = toml::from_str(&raw).map_err(Error::ConfigFileSyntax)?;
+                config = config.layer(ConfigLayer::Environment, table);
+            }
+            Err(VarError::NotPresent) => {}
+            Err(VarError::NotUnicode(_)) => {
+                return Err(Error::ConfigEnvVarNotUnicode(self.env_var.clone()));
+            }
+        }
+        Ok(config)
+    }
+}
+
+fn read_toml_file(path: &Path) -> Result<Option<Table>> {
+    match fs::read_to_string(path) {
+        Ok(raw) => Ok(Some(toml::from_str(&raw).map_err(Error::ConfigFileSyntax)?)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::ConfigFileIO(path.to_path_buf(), e)),
+    }
+}
+
+/// A service's merged configuration, and which layer supplied each of its values.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct LayeredConfig {
+    value:      Table,
+    provenance: BTreeMap<String, ConfigLayer>,
+}
+
+impl LayeredConfig {
+    pub fn new() -> Self { Self::default() }
+
+    /// Merges `table` over the configuration so far, recording that `layer` supplied each value
+    /// in it.
+    pub fn layer(mut self, layer: ConfigLayer, table: Table) -> Self {
+        merge(&mut self.value, table, layer, "", &mut self.provenance);
+        self
+    }
+
+    /// The merged configuration, as a TOML table.
+    pub fn value(&self) -> &Table { &self.value }
+
+    pub fn into_value(self) -> Value { Value::Table(self.value) }
+
+    /// Which layer supplied each value, keyed by its dotted path, such as `server.port`. Only
+    /// values other than tables are listed; a value in an array is attributed to the array.
+    pub fn provenance(&self) -> &BTreeMap<String, ConfigLayer> { &self.provenance }
+
+    /// The layer which supplied the value at the dotted path `key`, if there is one.
+    pub fn layer_of(&self, key: &str) -> Option<ConfigLayer> { self.provenance.get(key).cloned() }
+}
+
+fn merge(base: &mut Table,
+         overlay: Table,
+         layer: ConfigLayer,
+         prefix: &str,
+         provenance: &mut BTreeMap<String, ConfigLayer>) {
+    for (key, value) in overlay {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(ref mut existing)), Value::Table(table)) => {
+                merge(existing, table, layer, &path, provenance);
+            }
+            (_, value) => {
+                // Whatever this value replaces, nothing below it survives
+                let nested = format!("{}.", path);
+                provenance.retain(|k, _| k != &path && !k.starts_with(&nested));
+                record(&value, layer, &path, provenance);
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn record(value: &Value,
+          layer: ConfigLayer,
+          path: &str,
+          provenance: &mut BTreeMap<String, ConfigLayer>) {
+    match *value {
+        Value::Table(ref table) => {
+            for (key, value) in table {
+                record(value, layer, &format!("{}.{}", path, key), provenance);
+            }
+        }
+        _ => {
+            provenance.insert(path.to_string(), layer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std;
+    use tempfile::Builder;
+
+    #[test]
+    fn layers_merge_in_order_of_precedence() {
+        let dir = Builder::new().prefix("layered").tempdir().unwrap();
+        let default_toml = dir.path().join(DEFAULT_CFG_FILE);
+        let user_toml = dir.path().join(USER_CONFIG_FILE);
+        fs::write(&default_toml,
+                  "port = 6379\nsave = [\"900 1\"]\n[tls]\nenabled = false\nport = 6380\n").unwrap();
+        fs::write(&user_toml, "save = []\n[tls]\nenabled = true\n").unwrap();
+        let var = "HAB_LAYERED_CONFIG_TEST_MERGE";
+        std::env::set_var(var, "tls = \"off\"\nport = 7000\n");
+
+        let sources = ConfigSources { default_toml,
+                                      user_toml,
+                                      env_var: var.to_string() };
+        let config = sources.load().unwrap();
+        std::env::remove_var(var);
+
+        let expected: Table = toml::from_str("port = 7000\nsave = []\ntls = \"off\"\n").unwrap();
+        assert_eq!(&expected, config.value());
+        assert_eq!(Some(ConfigLayer::Environment), config.layer_of("port"));
+        assert_eq!(Some(ConfigLayer::User), config.layer_of("save"));
+        assert_eq!(Some(ConfigLayer::Environment), config.layer_of("tls"));
+        assert_eq!(None, config.layer_of("tls.enabled"));
+        assert_eq!(3, config.provenance().len());
+    }
+
+    #[test]
+    fn absent_layers_are_skipped() {
+        let dir = Builder::new().prefix("layered").tempdir().unwrap();
+        let default_toml = dir.path().join(DEFAULT_CFG_FILE);
+        fs::write(&default_toml, "[server]\nport = 80\n").unwrap();
+
+        let sources = ConfigSources { default_toml,
+                                      user_toml: dir.path().join(USER_CONFIG_FILE),
+                                      env_var: "HAB_LAYERED_CONFIG_TEST_ABSENT".to_string() };
+        let config = sources.load().unwrap();
+
+        assert_eq!(Some(ConfigLayer::Default), config.layer_of("server.port"));
+        assert_eq!(None, config.layer_of("server"));
+        assert_eq!(Some(&Value::Integer(80)),
+                   config.value()["server"].get("port"));
+    }
+
+    #[test]
+    fn invalid_layers_fail() {
+        let dir = Builder::new().prefix("layered").tempdir().unwrap();
+        let user_toml = dir.path().join(USER_CONFIG_FILE);
+        fs::write(&user_toml, "port = \n").unwrap();
+
+        let sources = ConfigSources { default_toml: dir.path().join(DEFAULT_CFG_FILE),
+                                      user_toml,
+                                      env_var: "HAB_LAYERED_CONFIG_TEST_INVALID".to_string() };
+        match sources.load() {
+            Err(Error::ConfigFileSyntax(_)) => {}
+            other => panic!("Expected ConfigFileSyntax, got {:?}", other),
+        }
+    }
+}
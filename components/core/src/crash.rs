@@ -0,0 +1,243 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Crash reports for programs built on this crate.
+//!
+//! When a program panics, the default hook prints the message to standard error, where it is
+//! usually lost by the time anyone asks about it. `CrashReporter::install` adds a panic hook
+//! which first writes a `CrashReport` as JSON to a directory of its own, by default
+//! `hab/cache/crash`, keeping only the most recent reports. The report holds what support needs
+//! to act on it: the program and its version, the platform, where and why it panicked, a
+//! backtrace, and the log lines recorded just before, when the program keeps them.
+//!
+//! ```no_run
+//! use habitat_core::crash::CrashReporter;
+//!
+//! CrashReporter::new("hab-sup", "0.79.1").keep(5).install();
+//! ```
+
+use std::{fs,
+          io,
+          panic::{self,
+                  PanicInfo},
+          path::{Path,
+                 PathBuf},
+          process,
+          thread,
+          time::{SystemTime,
+                 UNIX_EPOCH}};
+
+use backtrace::Backtrace;
+use serde_derive::Serialize;
+use serde_json;
+
+use crate::{build_info::CORE_VERSION,
+            fs::{atomic_write,
+                 cache_crash_path},
+            package::PackageTarget};
+
+/// How many reports are kept when no other number is given.
+pub const DEFAULT_KEEP: usize = 10;
+
+const REPORT_PREFIX: &str = "crash-";
+const REPORT_EXTENSION: &str = "json";
+
+/// Returns the log lines to include in a report, oldest first.
+pub type LogSource = dyn Fn() -> Vec<String> + Send + Sync;
+
+/// Everything recorded about a panic.
+#[derive(Clone, Debug, Serialize)]
+pub struct CrashReport {
+    pub program:     String,
+    pub version:     String,
+    /// The version of this crate the program was built with.
+    pub core:        String,
+    pub target:      String,
+    pub pid:         u32,
+    /// The name of the thread which panicked, if it has one.
+    pub thread:      Option<String>,
+    pub message:     String,
+    /// The source file, line, and column of the panic.
+    pub location:    Option<String>,
+    /// When the panic happened, in seconds since the Unix epoch.
+    pub timestamp:   u64,
+    pub backtrace:   String,
+    pub recent_logs: Vec<String>,
+}
+
+/// Writes crash reports for a program, and installs the panic hook that does so.
+pub struct CrashReporter {
+    program:    String,
+    version:    String,
+    dir:        PathBuf,
+    keep:       usize,
+    log_source: Option<Box<LogSource>>,
+}
+
+impl CrashReporter {
+    pub fn new<P, V>(program: P, version: V) -> Self
+        where P: Into<String>,
+              V: Into<String>
+    {
+        CrashReporter { program:    program.into(),
+                        version:    version.into(),
+                        dir:        cache_crash_path(None::<&Path>),
+                        keep:       DEFAULT_KEEP,
+                        log_source: None, }
+    }
+
+    /// Writes reports to `dir` rather than the crash cache.
+    pub fn dir<T: Into<PathBuf>>(mut self, dir: T) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    /// Keeps only the `keep` most recent reports, removing older ones as new ones are written.
+    pub fn keep(mut self, keep: usize) -> Self {
+        self.keep = keep.max(1);
+        self
+    }
+
+    /// Includes the lines `source` returns in each report as its recent logs.
+    pub fn log_source<F>(mut self, source: F) -> Self
+        where F: Fn() -> Vec<String> + Send + Sync + 'static
+    {
+        self.log_source = Some(Box::new(source));
+        self
+    }
+
+    /// Installs a panic hook which writes a report before handing the panic on to whatever hook
+    /// was installed before, which by default prints it.
+    pub fn install(self) {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+                            match self.write(&self.report(info)) {
+                                Ok(path) => {
+                                    eprintln!("A crash report was written to {}", path.display())
+                                }
+                                Err(e) => eprintln!("Failed to write a crash report: {}", e),
+                            }
+                            previous(info);
+                        }));
+    }
+
+    /// Builds the report for a panic.
+    pub fn report(&self, info: &PanicInfo<'_>) -> CrashReport {
+        let payload = info.payload();
+        let message = payload.downcast_ref::<&str>()
+                             .map(|s| (*s).to_string())
+                             .or_else(|| payload.downcast_ref::<String>().cloned())
+                             .unwrap_or_else(|| "Box<Any>".to_string());
+        let location = info.location()
+                           .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()));
+        self.report_for(message, location)
+    }
+
+    fn report_for(&self, message: String, location: Option<String>) -> CrashReport {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+                                         .map(|d| d.as_secs())
+                                         .unwrap_or(0);
+        CrashReport { program: self.program.clone(),
+                      version: self.version.clone(),
+                      core: CORE_VERSION.to_string(),
+                      target: PackageTarget::system_target().to_string(),
+                      pid: process::id(),
+                      thread: thread::current().name().map(str::to_string),
+                      message,
+                      location,
+                      timestamp,
+                      backtrace: format!("{:?}", Backtrace::new()),
+                      recent_logs: self.log_source.as_ref().map(|f| f()).unwrap_or_default() }
+    }
+
+    /// Writes a report to the report directory, removing the oldest reports beyond those to
+    /// keep, and returns where it was written.
+    ///
+    /// # Failures
+    ///
+    /// * The report directory cannot be created or written to
+    pub fn write(&self, report: &CrashReport) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+        // Zero padding keeps the names of reports in the order they were written.
+        let path =
+            self.dir.join(format!("{}{:020}-{}.{}",
+                                  REPORT_PREFIX, report.timestamp, report.pid, REPORT_EXTENSION));
+        let json =
+            serde_json::to_vec_pretty(report).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        atomic_write(&path, json)?;
+        self.rotate()?;
+        Ok(path)
+    }
+
+    /// Returns the reports in the report directory, oldest first.
+    ///
+    /// # Failures
+    ///
+    /// * The report directory cannot be read
+    pub fn reports(&self) -> io::Result<Vec<PathBuf>> {
+        let mut reports = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let is_report = path.file_name()
+                                .and_then(|n| n.to_str())
+                                .map_or(false, |n| n.starts_with(REPORT_PREFIX))
+                            && path.extension().map_or(false, |e| e == REPORT_EXTENSION);
+            if is_report {
+                reports.push(path);
+            }
+        }
+        reports.sort();
+        Ok(reports)
+    }
+
+    fn rotate(&self) -> io::Result<()> {
+        let reports = self.reports()?;
+        if reports.len() > self.keep {
+            for old in &reports[..reports.len() - self.keep] {
+                fs::remove_file(old)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn writes_reports_and_keeps_the_latest() {
+        let dir = Builder::new().prefix("crash").tempdir().unwrap();
+        let reporter =
+            CrashReporter::new("hab-sup", "0.79.1").dir(dir.path())
+                                                   .keep(2)
+                                                   .log_source(|| vec!["starting up".to_string()]);
+
+        let mut written = Vec::new();
+        for timestamp in 1..=3 {
+            let mut report = reporter.report_for("boom".to_string(), None);
+            report.timestamp = timestamp;
+            written.push(reporter.write(&report).unwrap());
+        }
+
+        assert_eq!(written[1..].to_vec(), reporter.reports().unwrap());
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&written[2]).unwrap()).unwrap();
+        assert_eq!("hab-sup", json["program"]);
+        assert_eq!("boom", json["message"]);
+        assert_eq!(PackageTarget::system_target().to_string(), json["target"]);
+        assert_eq!("starting up", json["recent_logs"][0]);
+    }
+}
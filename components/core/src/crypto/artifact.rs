@@ -27,8 +27,9 @@ use super::{hash,
             SigKeyPair,
             HART_FORMAT_VERSION,
             SIG_HASH_TYPE};
-use crate::error::{Error,
-                   Result};
+use crate::{error::{Error,
+                    Result},
+            package::metadata::ReadLimits};
 
 /// Generate and sign a package
 pub fn sign<P1: ?Sized, P2: ?Sized>(src: &P1, dst: &P2, pair: &SigKeyPair) -> Result<()>
@@ -62,19 +63,19 @@ pub fn get_archive_reader<P: AsRef<Path>>(src: &P) -> Result<BufReader<File>> {
     let mut empty_line = String::new();
 
     let mut reader = BufReader::new(f);
-    if reader.read_line(&mut your_format_version)? == 0 {
+    if read_header_line(&mut reader, &mut your_format_version)? == 0 {
         return Err(Error::CryptoError("Can't read format version".to_string()));
     }
-    if reader.read_line(&mut your_key_name)? == 0 {
+    if read_header_line(&mut reader, &mut your_key_name)? == 0 {
         return Err(Error::CryptoError("Can't read keyname".to_string()));
     }
-    if reader.read_line(&mut your_hash_type)? == 0 {
+    if read_header_line(&mut reader, &mut your_hash_type)? == 0 {
         return Err(Error::CryptoError("Can't read hash type".to_string()));
     }
-    if reader.read_line(&mut your_signature_raw)? == 0 {
+    if read_header_line(&mut reader, &mut your_signature_raw)? == 0 {
         return Err(Error::CryptoError("Can't read signature".to_string()));
     }
-    if reader.read_line(&mut empty_line)? == 0 {
+    if read_header_line(&mut reader, &mut empty_line)? == 0 {
         return Err(Error::CryptoError("Can't end of header".to_string()));
     }
     Ok(reader)
@@ -114,19 +115,19 @@ pub fn get_artifact_header<P: ?Sized>(src: &P) -> Result<ArtifactHeader>
     let mut empty_line = String::new();
 
     let mut reader = BufReader::new(f);
-    if reader.read_line(&mut your_format_version)? == 0 {
+    if read_header_line(&mut reader, &mut your_format_version)? == 0 {
         return Err(Error::CryptoError("Can't read format version".to_string()));
     }
-    if reader.read_line(&mut your_key_name)? == 0 {
+    if read_header_line(&mut reader, &mut your_key_name)? == 0 {
         return Err(Error::CryptoError("Can't read keyname".to_string()));
     }
-    if reader.read_line(&mut your_hash_type)? == 0 {
+    if read_header_line(&mut reader, &mut your_hash_type)? == 0 {
         return Err(Error::CryptoError("Can't read hash type".to_string()));
     }
-    if reader.read_line(&mut your_signature_raw)? == 0 {
+    if read_header_line(&mut reader, &mut your_signature_raw)? == 0 {
         return Err(Error::CryptoError("Can't read signature".to_string()));
     }
-    if reader.read_line(&mut empty_line)? == 0 {
+    if read_header_line(&mut reader, &mut empty_line)? == 0 {
         return Err(Error::CryptoError("Can't end of header".to_string()));
     }
     let your_format_version = your_format_version.trim().to_string();
@@ -146,11 +147,17 @@ pub fn verify<P1: ?Sized, P2: ?Sized>(src: &P1, cache_key_path: &P2) -> Result<(
           P2: AsRef<Path>
 {
     let f = File::open(src)?;
-    let mut reader = BufReader::new(f);
+    verify_reader(BufReader::new(f), cache_key_path)
+}
 
+/// As `verify`, but reading the signed content from `reader`, such as a signed metafile.
+pub fn verify_reader<R, P>(mut reader: R, cache_key_path: &P) -> Result<(String, String)>
+    where R: BufRead,
+          P: ?Sized + AsRef<Path>
+{
     let _ = {
         let mut buffer = String::new();
-        match reader.read_line(&mut buffer) {
+        match read_header_line(&mut reader, &mut buffer) {
             Ok(0) => {
                 return Err(Error::CryptoError("Corrupt payload, can't read format \
                                                version"
@@ -162,13 +169,13 @@ pub fn verify<P1: ?Sized, P2: ?Sized>(src: &P1, cache_key_path: &P2) -> Result<(
                     return Err(Error::CryptoError(msg));
                 }
             }
-            Err(e) => return Err(Error::from(e)),
+            Err(e) => return Err(e),
         };
         buffer.trim().to_string()
     };
     let pair = {
         let mut buffer = String::new();
-        if reader.read_line(&mut buffer)? == 0 {
+        if read_header_line(&mut reader, &mut buffer)? == 0 {
             return Err(Error::CryptoError("Corrupt payload, can't read origin \
                                            key name"
                                                     .to_string()));
@@ -177,7 +184,7 @@ pub fn verify<P1: ?Sized, P2: ?Sized>(src: &P1, cache_key_path: &P2) -> Result<(
     };
     {
         let mut buffer = String::new();
-        match reader.read_line(&mut buffer) {
+        match read_header_line(&mut reader, &mut buffer) {
             Ok(0) => {
                 return Err(Error::CryptoError(
                     "Corrupt payload, can't read hash type".to_string(),
@@ -189,12 +196,12 @@ pub fn verify<P1: ?Sized, P2: ?Sized>(src: &P1, cache_key_path: &P2) -> Result<(
                     return Err(Error::CryptoError(msg));
                 }
             }
-            Err(e) => return Err(Error::from(e)),
+            Err(e) => return Err(e),
         };
     };
     let signature = {
         let mut buffer = String::new();
-        match reader.read_line(&mut buffer) {
+        match read_header_line(&mut reader, &mut buffer) {
             Ok(0) => {
                 return Err(Error::CryptoError(
                     "Corrupt payload, can't read signature".to_string(),
@@ -207,12 +214,12 @@ pub fn verify<P1: ?Sized, P2: ?Sized>(src: &P1, cache_key_path: &P2) -> Result<(
                                                                             e))
                                              })?
             }
-            Err(e) => return Err(Error::from(e)),
+            Err(e) => return Err(e),
         }
     };
     {
         let mut buffer = String::new();
-        if reader.read_line(&mut buffer)? == 0 {
+        if read_header_line(&mut reader, &mut buffer)? == 0 {
             return Err(Error::CryptoError("Corrupt payload, can't find end of \
                                            header"
                                                   .to_string()));
@@ -235,13 +242,27 @@ pub fn verify<P1: ?Sized, P2: ?Sized>(src: &P1, cache_key_path: &P2) -> Result<(
     }
 }
 
+/// Reads a line of an artifact's header into `buffer`, failing rather than reading any further
+/// if the line is longer than `ReadLimits` allows.
+fn read_header_line<R: BufRead>(reader: &mut R, buffer: &mut String) -> Result<usize> {
+    read_line_limited(reader, buffer, ReadLimits::current().header_line_bytes)
+}
+
+fn read_line_limited<R: BufRead>(reader: &mut R, buffer: &mut String, max: u64) -> Result<usize> {
+    let read = reader.take(max.saturating_add(1)).read_line(buffer)?;
+    if read as u64 > max {
+        return Err(Error::ArtifactHeaderTooLarge(max));
+    }
+    Ok(read)
+}
+
 pub fn artifact_signer<P: AsRef<Path>>(src: &P) -> Result<String> {
     let f = File::open(src)?;
     let mut reader = BufReader::new(f);
 
     let _ = {
         let mut buffer = String::new();
-        match reader.read_line(&mut buffer) {
+        match read_header_line(&mut reader, &mut buffer) {
             Ok(0) => {
                 return Err(Error::CryptoError("Corrupt payload, can't read format \
                                                version"
@@ -253,13 +274,13 @@ pub fn artifact_signer<P: AsRef<Path>>(src: &P) -> Result<String> {
                     return Err(Error::CryptoError(msg));
                 }
             }
-            Err(e) => return Err(Error::from(e)),
+            Err(e) => return Err(e),
         };
         buffer.trim().to_string()
     };
     let name_with_rev = {
         let mut buffer = String::new();
-        if reader.read_line(&mut buffer)? == 0 {
+        if read_header_line(&mut reader, &mut buffer)? == 0 {
             return Err(Error::CryptoError("Corrupt payload, can't read origin \
                                            key name"
                                                     .to_string()));
@@ -522,4 +543,30 @@ mod test {
         assert_eq!(SIG_HASH_TYPE, hart_header.hash_type);
         assert!(!hart_header.signature_raw.is_empty());
     }
+
+    #[test]
+    fn header_lines_beyond_the_limit_are_refused() {
+        let mut reader = BufReader::new(&b"short\nmuch longer than that\n"[..]);
+        let mut buffer = String::new();
+
+        assert_eq!(6, read_line_limited(&mut reader, &mut buffer, 8).unwrap());
+        match read_line_limited(&mut reader, &mut buffer, 8) {
+            Err(Error::ArtifactHeaderTooLarge(8)) => (),
+            other => panic!("expected the header line to be too long, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn get_artifact_header_refuses_an_endless_line() {
+        let dir = Builder::new().prefix("artifact").tempdir().unwrap();
+        let dst = dir.path().join("corrupt.hart");
+        fs::write(&dst,
+                  vec![b'x'; ReadLimits::default().header_line_bytes as usize * 4]).unwrap();
+
+        match get_artifact_header(&dst) {
+            Err(Error::ArtifactHeaderTooLarge(_)) => (),
+            Err(e) => panic!("expected the header line to be too long, got {:?}", e),
+            Ok(_) => panic!("expected the header line to be too long"),
+        }
+    }
 }
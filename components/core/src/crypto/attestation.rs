@@ -0,0 +1,341 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Signed provenance for build outputs.
+//!
+//! A `Statement` follows the in-toto statement layout with a SLSA-style provenance predicate,
+//! recording which builder produced an artifact from which source revision and plan. An
+//! `Attestation` is a statement signed with an origin key, serialized much like the header of a
+//! `.hart`:
+//!
+//! ```text
+//! ATTEST-1
+//! <signing key name with revision>
+//! <base64 signature>
+//!
+//! <statement as JSON>
+//! ```
+
+use std::{collections::BTreeMap,
+          fmt,
+          path::Path,
+          str::FromStr};
+
+use base64;
+use serde_derive::{Deserialize,
+                   Serialize};
+use serde_json;
+use sodiumoxide::crypto::sign;
+
+use super::{hash,
+            SigKeyPair};
+use crate::error::{Error,
+                   Result};
+
+/// The format version of a serialized `Attestation`.
+pub const ATTESTATION_FORMAT_VERSION: &str = "ATTEST-1";
+pub const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v0.1";
+pub const PROVENANCE_PREDICATE_TYPE: &str = "https://slsa.dev/provenance/v0.1";
+/// The key of a subject's digest, which is computed as `.hart` checksums are.
+pub const DIGEST_ALGORITHM: &str = "blake2b";
+
+/// An in-toto statement about one or more artifacts.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Statement {
+    #[serde(rename = "_type")]
+    pub statement_type: String,
+    pub subject:        Vec<Subject>,
+    #[serde(rename = "predicateType")]
+    pub predicate_type: String,
+    pub predicate:      Provenance,
+}
+
+/// An artifact a statement is about.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Subject {
+    /// The artifact's file name.
+    pub name:   String,
+    /// The artifact's digests, keyed by algorithm.
+    pub digest: BTreeMap<String, String>,
+}
+
+/// How an artifact was produced.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Provenance {
+    /// Identifies the builder, such as the URL of a Builder instance or a worker's hostname.
+    #[serde(rename = "builderId")]
+    pub builder_id:      String,
+    /// Where the source came from, such as a repository URL.
+    #[serde(rename = "sourceUri")]
+    pub source_uri:      String,
+    /// The revision of the source which was built, such as a commit SHA.
+    #[serde(rename = "sourceRevision")]
+    pub source_revision: String,
+    /// The BLAKE2b hash of the plan which was built, as hex.
+    #[serde(rename = "planHash")]
+    pub plan_hash:       String,
+}
+
+impl Statement {
+    /// Creates a provenance statement about the artifact at `path`.
+    ///
+    /// # Failures
+    ///
+    /// * The artifact cannot be read
+    pub fn for_artifact<P>(path: P, provenance: Provenance) -> Result<Self>
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let name = file_name(path)?;
+        let mut digest = BTreeMap::new();
+        digest.insert(DIGEST_ALGORITHM.to_string(), hash::hash_file(path)?);
+        Ok(Statement { statement_type: STATEMENT_TYPE.to_string(),
+                       subject:        vec![Subject { name, digest }],
+                       predicate_type: PROVENANCE_PREDICATE_TYPE.to_string(),
+                       predicate:      provenance, })
+    }
+
+    /// Checks that the artifact at `path` is a subject of the statement, by both name and
+    /// digest.
+    ///
+    /// # Failures
+    ///
+    /// * The artifact cannot be read
+    /// * No subject matches the artifact
+    pub fn covers<P>(&self, path: P) -> Result<()>
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let name = file_name(path)?;
+        let computed = hash::hash_file(path)?;
+        let covers = |subject: &Subject| {
+            subject.name == name && subject.digest.get(DIGEST_ALGORITHM) == Some(&computed)
+        };
+        if self.subject.iter().any(covers) {
+            Ok(())
+        } else {
+            Err(Error::CryptoError(format!("Attestation does not cover {}", path.display())))
+        }
+    }
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    match path.file_name() {
+        Some(name) => Ok(name.to_string_lossy().into_owned()),
+        None => Err(Error::CryptoError(format!("{} is not a file", path.display()))),
+    }
+}
+
+/// A statement signed with an origin key.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Attestation {
+    signer:    String,
+    signature: Vec<u8>,
+    payload:   Vec<u8>,
+}
+
+impl Attestation {
+    /// Signs `statement` with `signer`'s secret key.
+    ///
+    /// # Failures
+    ///
+    /// * `signer` has no secret key
+    pub fn create(statement: &Statement, signer: &SigKeyPair) -> Result<Self> {
+        let mut attestation = Attestation { signer:    signer.name_with_rev(),
+                                            signature: Vec::new(),
+                                            payload:   serde_json::to_vec(statement)?, };
+        attestation.signature = sign::sign(&attestation.signed_bytes(), signer.secret()?);
+        Ok(attestation)
+    }
+
+    /// The name with revision of the key the attestation was signed with.
+    pub fn signer(&self) -> &str { &self.signer }
+
+    /// Verifies the attestation with the signing key from `cache_key_path` and returns its
+    /// statement.
+    ///
+    /// # Failures
+    ///
+    /// * The signing key is not in the cache
+    /// * The attestation fails verification
+    pub fn verify<P>(&self, cache_key_path: P) -> Result<Statement>
+        where P: AsRef<Path>
+    {
+        let signer = SigKeyPair::get_pair_for(&self.signer, cache_key_path.as_ref())?;
+        self.verify_with(&signer)
+    }
+
+    /// Verifies the attestation with `signer` and returns its statement.
+    ///
+    /// # Failures
+    ///
+    /// * `signer` is not the key the attestation names, or has no public key
+    /// * The signature does not match the attestation's content
+    /// * The statement cannot be parsed
+    pub fn verify_with(&self, signer: &SigKeyPair) -> Result<Statement> {
+        if signer.name_with_rev() != self.signer {
+            return Err(Error::CryptoError(format!("Attestation was signed by \
+                                                   {}, not {}",
+                                                  self.signer,
+                                                  signer.name_with_rev())));
+        }
+        match sign::verify(&self.signature, signer.public()?) {
+            Ok(ref signed) if *signed == self.signed_bytes() => {}
+            _ => return Err(Error::CryptoError("Attestation failed verification".to_string())),
+        }
+        Ok(serde_json::from_slice(&self.payload)?)
+    }
+
+    /// Verifies the attestation as `verify` does and checks that it covers the artifact at
+    /// `path`, returning the verified statement.
+    ///
+    /// # Failures
+    ///
+    /// * The attestation fails verification
+    /// * The attestation is not about the artifact
+    pub fn verify_artifact<P1, P2>(&self, path: P1, cache_key_path: P2) -> Result<Statement>
+        where P1: AsRef<Path>,
+              P2: AsRef<Path>
+    {
+        let statement = self.verify(cache_key_path)?;
+        statement.covers(path)?;
+        Ok(statement)
+    }
+
+    /// Everything the signature covers: the serialized attestation without its signature.
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!("{}\n{}\n\n", ATTESTATION_FORMAT_VERSION, self.signer).into_bytes();
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+}
+
+impl fmt::Display for Attestation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,
+               "{}\n{}\n{}\n\n{}",
+               ATTESTATION_FORMAT_VERSION,
+               self.signer,
+               base64::encode(&self.signature),
+               String::from_utf8_lossy(&self.payload))
+    }
+}
+
+impl FromStr for Attestation {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let invalid = |what: &str| Error::CryptoError(format!("Corrupt attestation, {}", what));
+        let mut lines = value.splitn(5, '\n');
+        let mut next = |what: &str| {
+            lines.next()
+                 .ok_or_else(|| invalid(&format!("can't read {}", what)))
+        };
+
+        let version = next("format version")?;
+        if version != ATTESTATION_FORMAT_VERSION {
+            return Err(Error::CryptoError(format!("Unsupported attestation \
+                                                   format version: {}",
+                                                  version)));
+        }
+        let signer = next("signing key name")?.to_string();
+        let signature =
+            base64::decode(next("signature")?).map_err(|e| {
+                                                  invalid(&format!("can't decode signature: {}", e))
+                                              })?;
+        if !next("end of header")?.is_empty() {
+            return Err(invalid("can't find end of header"));
+        }
+        let payload = next("statement")?.trim_end().as_bytes().to_vec();
+        Ok(Attestation { signer,
+                         signature,
+                         payload })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use tempfile::Builder;
+
+    fn provenance() -> Provenance {
+        Provenance { builder_id:      "https://bldr.habitat.sh".to_string(),
+                     source_uri:      "https://github.com/habitat-sh/core-plans".to_string(),
+                     source_revision: "4b1c5c6bd1e8f1e1f5d2c9d6b4d2d36bb1a3f1b2".to_string(),
+                     plan_hash:       hash::hash_string("pkg_name=redis"), }
+    }
+
+    #[test]
+    fn attestation_round_trips_and_verifies() {
+        let dir = Builder::new().prefix("attestation").tempdir().unwrap();
+        let hart = dir.path()
+                      .join("core-redis-4.0.14-20190319155852-x86_64-linux.hart");
+        fs::write(&hart, "not really a hart").unwrap();
+        let signer = SigKeyPair::generate_pair_for_origin("core").unwrap();
+        signer.to_pair_files(dir.path()).unwrap();
+
+        let statement = Statement::for_artifact(&hart, provenance()).unwrap();
+        let attestation = Attestation::create(&statement, &signer).unwrap();
+        let parsed = Attestation::from_str(&attestation.to_string()).unwrap();
+
+        assert_eq!(attestation, parsed);
+        assert_eq!(signer.name_with_rev(), parsed.signer());
+        assert_eq!(statement,
+                   parsed.verify_artifact(&hart, dir.path()).unwrap());
+        assert_eq!(STATEMENT_TYPE, statement.statement_type);
+        assert_eq!("core-redis-4.0.14-20190319155852-x86_64-linux.hart",
+                   statement.subject[0].name);
+    }
+
+    #[test]
+    fn attestation_rejects_tampering() {
+        let signer = SigKeyPair::generate_pair_for_origin("core").unwrap();
+        let mut statement = Statement { statement_type: STATEMENT_TYPE.to_string(),
+                                        subject:        Vec::new(),
+                                        predicate_type: PROVENANCE_PREDICATE_TYPE.to_string(),
+                                        predicate:      provenance(), };
+        let attestation = Attestation::create(&statement, &signer).unwrap();
+
+        statement.predicate.builder_id = "https://evil.example.com".to_string();
+        let forged = Attestation { payload: serde_json::to_vec(&statement).unwrap(),
+                                   ..attestation.clone() };
+        match forged.verify_with(&signer) {
+            Err(Error::CryptoError(_)) => {}
+            other => panic!("Expected CryptoError, got {:?}", other),
+        }
+
+        let other = SigKeyPair::generate_pair_for_origin("acme").unwrap();
+        assert!(attestation.verify_with(&other).is_err());
+    }
+
+    #[test]
+    fn statement_must_cover_artifact() {
+        let dir = Builder::new().prefix("attestation").tempdir().unwrap();
+        let hart = dir.path()
+                      .join("core-redis-4.0.14-20190319155852-x86_64-linux.hart");
+        fs::write(&hart, "not really a hart").unwrap();
+        let mut statement = Statement::for_artifact(&hart, provenance()).unwrap();
+
+        statement.covers(&hart).unwrap();
+        let renamed = dir.path()
+                         .join("core-redis-5.0.3-20190401000000-x86_64-linux.hart");
+        fs::copy(&hart, &renamed).unwrap();
+        assert!(statement.covers(&renamed).is_err());
+
+        statement.subject[0].digest
+                            .insert(DIGEST_ALGORITHM.to_string(), "deadbeef".to_string());
+        assert!(statement.covers(&hart).is_err());
+    }
+}
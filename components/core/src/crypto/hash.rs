@@ -60,7 +60,7 @@ pub fn hash_bytes(data: &[u8]) -> String {
     hex::encode(out)
 }
 
-pub fn hash_reader(reader: &mut BufReader<File>) -> Result<String> {
+pub fn hash_reader<R: Read>(reader: &mut R) -> Result<String> {
     let mut out = [0u8; libsodium_sys::crypto_generichash_BYTES];
     let mut st = vec![0u8; unsafe { libsodium_sys::crypto_generichash_statebytes() }];
     let pst = st.as_mut_ptr() as *mut libsodium_sys::crypto_generichash_state;
@@ -0,0 +1,353 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A key cache shared by the users of one host, which keeps each user's secret keys to
+//! themselves.
+//!
+//! Public keys are shared, but on a build host with several users nobody should be able to sign
+//! with another user's origin key. The cache is laid out as
+//!
+//! ```text
+//! /hab/cache/keys/                  public keys, readable by everyone
+//! /hab/cache/keys/secret/           0711: users can reach their own directory, but not list
+//! /hab/cache/keys/secret/<user>/    0700, owned by the user: that user's secret keys
+//! ```
+//!
+//! Secret keys are looked for in the user's directory first, then in the shared directory, where
+//! caches laid out before per-user directories keep them. A user's directory which anyone else
+//! can read is refused, just as `ssh` refuses a private key which others can read.
+
+use std::{fs,
+          path::{Path,
+                 PathBuf}};
+
+use super::{parse_key_str,
+            sig_key_pair::SigKeyPair,
+            sym_key::SymKey,
+            PairType};
+use crate::{crypto::{default_cache_key_path,
+                     PUBLIC_SIG_KEY_VERSION,
+                     SECRET_SIG_KEY_VERSION,
+                     SECRET_SYM_KEY_VERSION},
+            error::{Error,
+                    Result},
+            os::users};
+
+/// The directory under the cache root holding each user's secret keys.
+pub const SECRET_KEYS_DIR: &str = "secret";
+
+#[cfg(not(windows))]
+const SHARED_DIR_PERMISSIONS: u32 = 0o755;
+#[cfg(not(windows))]
+const SECRET_KEYS_DIR_PERMISSIONS: u32 = 0o711;
+#[cfg(not(windows))]
+const USER_DIR_PERMISSIONS: u32 = 0o700;
+
+/// One user's view of a shared key cache.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeyCache {
+    root: PathBuf,
+    user: String,
+}
+
+impl KeyCache {
+    /// The view of `user` of the cache rooted at `root`.
+    pub fn new<P, S>(root: P, user: S) -> Self
+        where P: Into<PathBuf>,
+              S: Into<String>
+    {
+        KeyCache { root: root.into(),
+                   user: user.into(), }
+    }
+
+    /// The current user's view of the default key cache, optionally taking a custom filesystem
+    /// root.
+    ///
+    /// # Failures
+    ///
+    /// * The current user's name cannot be determined
+    pub fn for_current_user(fs_root_path: Option<&Path>) -> Result<Self> {
+        let user = users::get_current_username().ok_or_else(|| {
+                                                    Error::CryptoError("Cannot determine the \
+                                                                        current user for the key \
+                                                                        cache"
+                                                                              .to_string())
+                                                })?;
+        Ok(Self::new(default_cache_key_path(fs_root_path), user))
+    }
+
+    /// Where public keys are kept.
+    pub fn public_path(&self) -> &Path { &self.root }
+
+    /// Where the user's secret keys are kept.
+    pub fn secret_path(&self) -> PathBuf { self.root.join(SECRET_KEYS_DIR).join(&self.user) }
+
+    /// The directories to look for keys of `pair_type` in, in the order to look.
+    pub fn search_paths(&self, pair_type: PairType) -> Vec<PathBuf> {
+        match pair_type {
+            PairType::Public => vec![self.root.clone(), self.secret_path()],
+            PairType::Secret => vec![self.secret_path(), self.root.clone()],
+        }
+    }
+
+    /// Creates the cache's directories, with the permissions described in the module
+    /// documentation. When set up for a user other than the current one, which needs root, the
+    /// user's directory is handed over to them.
+    ///
+    /// # Failures
+    ///
+    /// * A directory cannot be created, or its permissions or owner cannot be set
+    pub fn setup(&self) -> Result<()> {
+        let secret_root = self.root.join(SECRET_KEYS_DIR);
+        let secret_path = self.secret_path();
+        fs::create_dir_all(&secret_path)?;
+        #[cfg(not(windows))]
+        {
+            use crate::util::posix_perm::set_permissions;
+
+            set_permissions(&self.root, SHARED_DIR_PERMISSIONS)?;
+            set_permissions(&secret_root, SECRET_KEYS_DIR_PERMISSIONS)?;
+            set_permissions(&secret_path, USER_DIR_PERMISSIONS)?;
+            if users::get_current_username().as_ref() != Some(&self.user) {
+                give_to_user(&secret_path, &self.user)?;
+            }
+        }
+        #[cfg(windows)]
+        {
+            let _ = secret_root;
+            crate::util::win_perm::harden_path(&secret_path)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that nobody but the user can reach their secret keys. A user without a directory
+    /// passes.
+    ///
+    /// # Failures
+    ///
+    /// * The user's directory is owned by someone else, or others can read or write it
+    #[cfg(not(windows))]
+    pub fn check_permissions(&self) -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let path = self.secret_path();
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => return Ok(()),
+        };
+        if Some(metadata.uid()) != users::get_uid_by_name(&self.user) {
+            return Err(Error::PermissionFailed(format!("Secret key directory {} \
+                                                        is not owned by {}",
+                                                       path.display(),
+                                                       self.user)));
+        }
+        if metadata.mode() & 0o077 != 0 {
+            return Err(Error::PermissionFailed(format!("Secret key directory {} \
+                                                        can be reached by users \
+                                                        other than {}; its \
+                                                        permissions should be \
+                                                        {:o}",
+                                                       path.display(),
+                                                       self.user,
+                                                       USER_DIR_PERMISSIONS)));
+        }
+        Ok(())
+    }
+
+    /// Checks that nobody but the user can reach their secret keys. On Windows the directory's
+    /// ACL is set by `setup`, so there is nothing to check.
+    #[cfg(windows)]
+    pub fn check_permissions(&self) -> Result<()> { Ok(()) }
+
+    /// Calls `lookup` with each directory to look for keys of `pair_type` in, returning the
+    /// first key it finds, or the last error if it finds none.
+    ///
+    /// ```
+    /// use habitat_core::crypto::{keys::{cache::KeyCache,
+    ///                                   PairType},
+    ///                            SigKeyPair};
+    /// # let dir = tempfile::tempdir().unwrap();
+    /// # let user = habitat_core::os::users::get_current_username().unwrap();
+    /// # let cache = KeyCache::new(dir.path(), user);
+    /// # cache.setup().unwrap();
+    /// # cache.write_sig_pair(&SigKeyPair::generate_pair_for_origin("acme").unwrap()).unwrap();
+    ///
+    /// let pair = cache.find(PairType::Secret, |path| {
+    ///                     SigKeyPair::get_latest_pair_for("acme", path, Some(&PairType::Secret))
+    ///                 })
+    ///                 .unwrap();
+    /// assert!(pair.secret().is_ok());
+    /// ```
+    ///
+    /// # Failures
+    ///
+    /// * Looking for secret keys, and the user's directory fails `check_permissions`
+    /// * `lookup` fails for every directory
+    pub fn find<T, F>(&self, pair_type: PairType, mut lookup: F) -> Result<T>
+        where F: FnMut(&Path) -> Result<T>
+    {
+        if pair_type == PairType::Secret {
+            self.check_permissions()?;
+        }
+        let mut last_err = None;
+        for path in self.search_paths(pair_type) {
+            if !path.is_dir() {
+                continue;
+            }
+            match lookup(&path) {
+                Ok(key) => {
+                    if pair_type == PairType::Secret && path == self.root {
+                        warn!("Using a secret key from the shared key cache {}; move it to {} so \
+                               other users cannot read it",
+                              self.root.display(),
+                              self.secret_path().display());
+                    }
+                    return Ok(key);
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+                        Error::CryptoError(format!("No key cache found at {}", self.root.display()))
+                    }))
+    }
+
+    /// Writes a signing or ring key, given as a string, to the cache: public keys to the shared
+    /// directory, and secret keys to the user's. Returns the key's name with revision and whether
+    /// it is public or secret.
+    ///
+    /// # Failures
+    ///
+    /// * The key is a box key, or cannot be parsed
+    /// * The key cannot be written
+    pub fn import(&self, content: &str) -> Result<(String, PairType)> {
+        let (pair_type, name_with_rev, _) = parse_key_str(content)?;
+        let path = match pair_type {
+            PairType::Public => self.root.clone(),
+            PairType::Secret => self.secret_path(),
+        };
+        let version = content.lines().next().unwrap_or("");
+        if version == PUBLIC_SIG_KEY_VERSION || version == SECRET_SIG_KEY_VERSION {
+            SigKeyPair::write_file_from_str(content, &path)?;
+        } else if version == SECRET_SYM_KEY_VERSION {
+            SymKey::write_file_from_str(content, &path)?;
+        } else {
+            return Err(Error::CryptoError(format!("Cannot import {} keys into a \
+                                                   shared key cache",
+                                                  version)));
+        }
+        Ok((name_with_rev, pair_type))
+    }
+
+    /// Writes both halves of a signing key pair to the cache.
+    ///
+    /// # Failures
+    ///
+    /// * The pair is missing a half, or a key cannot be written
+    pub fn write_sig_pair(&self, pair: &SigKeyPair) -> Result<()> {
+        self.import(&pair.to_public_string()?)?;
+        self.import(&pair.to_secret_string()?)?;
+        Ok(())
+    }
+}
+
+/// Hands `path` over to `user`, leaving its group as it is.
+#[cfg(not(windows))]
+fn give_to_user(path: &Path, user: &str) -> Result<()> {
+    use std::{ffi::CString,
+              os::unix::ffi::OsStrExt};
+
+    let uid = users::get_uid_by_name(user).ok_or_else(|| {
+                                              Error::PermissionFailed(format!("Cannot give {} to \
+                                                                               {}, no such user",
+                                                                              path.display(),
+                                                                              user))
+                                          })?;
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| {
+                     Error::PermissionFailed(format!("Invalid path {}: {}", path.display(), e))
+                 })?;
+    if unsafe { libc::chown(c_path.as_ptr(), uid, !0) } != 0 {
+        return Err(Error::PermissionFailed(format!("Cannot give {} to {}: {}",
+                                                   path.display(),
+                                                   user,
+                                                   std::io::Error::last_os_error())));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::Builder;
+
+    fn cache(root: &Path) -> KeyCache {
+        KeyCache::new(root, users::get_current_username().unwrap())
+    }
+
+    #[test]
+    fn secret_keys_are_kept_per_user() {
+        let root = Builder::new().prefix("key-cache").tempdir().unwrap();
+        let cache = cache(root.path());
+        cache.setup().unwrap();
+        let pair = SigKeyPair::generate_pair_for_origin("acme").unwrap();
+
+        cache.write_sig_pair(&pair).unwrap();
+
+        assert!(SigKeyPair::get_public_key_path(&pair.name_with_rev(), cache.public_path()).is_ok());
+        assert!(SigKeyPair::get_secret_key_path(&pair.name_with_rev(), cache.public_path()).is_err());
+        let found = cache.find(PairType::Secret, |path| {
+                             SigKeyPair::get_secret_key_path(&pair.name_with_rev(), path)
+                         })
+                         .unwrap();
+        assert!(found.starts_with(cache.secret_path()));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+            assert_eq!(0o711, mode(&root.path().join(SECRET_KEYS_DIR)));
+            assert_eq!(0o700, mode(&cache.secret_path()));
+        }
+    }
+
+    #[test]
+    fn secret_keys_in_shared_directory_are_still_found() {
+        let root = Builder::new().prefix("key-cache").tempdir().unwrap();
+        let cache = cache(root.path());
+        let pair = SigKeyPair::generate_pair_for_origin("acme").unwrap();
+        pair.to_pair_files(cache.public_path()).unwrap();
+
+        let found = cache.find(PairType::Secret, |path| {
+                             SigKeyPair::get_latest_pair_for("acme", path, Some(&PairType::Secret))
+                         })
+                         .unwrap();
+        assert_eq!(pair.name_with_rev(), found.name_with_rev());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn readable_user_directory_is_refused() {
+        use crate::util::posix_perm::set_permissions;
+
+        let root = Builder::new().prefix("key-cache").tempdir().unwrap();
+        let cache = cache(root.path());
+        cache.setup().unwrap();
+        set_permissions(cache.secret_path(), 0o755).unwrap();
+
+        assert!(cache.check_permissions().is_err());
+        assert!(cache.find(PairType::Secret, |path| Ok(path.to_path_buf()))
+                     .is_err());
+        assert!(cache.find(PairType::Public, |path| Ok(path.to_path_buf()))
+                     .is_ok());
+    }
+}
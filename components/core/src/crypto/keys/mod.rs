@@ -16,7 +16,8 @@ use std::{collections::HashSet,
           fmt,
           fs::{self,
                File},
-          io::{prelude::*,
+          io::{self,
+               prelude::*,
                BufReader,
                BufWriter},
           path::{Path,
@@ -28,8 +29,11 @@ use base64;
 use regex::Regex;
 use time;
 
-use crate::error::{Error,
-                   Result};
+use crate::{error::{Error,
+                    Result},
+            fs::{access_error,
+                 Access,
+                 PermissionDenied}};
 
 use super::{PUBLIC_BOX_KEY_VERSION,
             PUBLIC_KEY_SUFFIX,
@@ -48,6 +52,7 @@ lazy_static::lazy_static! {
 }
 
 pub mod box_key_pair;
+pub mod cache;
 pub mod sig_key_pair;
 pub mod sym_key;
 
@@ -243,8 +248,14 @@ fn get_key_revisions<P>(keyname: &str,
 {
     // accumulator for files that match
     let mut candidates = HashSet::new();
+    let needed_by = format!("looking for revisions of the {} key", keyname);
     let dir_entries = match fs::read_dir(cache_key_path.as_ref()) {
         Ok(dir_entries) => dir_entries,
+        Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            return Err(Error::PermissionDenied(PermissionDenied::new(cache_key_path.as_ref(),
+                                                                     needed_by,
+                                                                     Access::Key)));
+        }
         Err(e) => {
             return Err(Error::CryptoError(format!("Error reading key directory \
                                                    {}: {}",
@@ -276,7 +287,8 @@ fn get_key_revisions<P>(keyname: &str,
             }
         };
 
-        let file = File::open(dir_entry.path())?;
+        let path = dir_entry.path();
+        let file = File::open(&path).map_err(|e| access_error(e, &path, Access::Key, &needed_by))?;
         let mut reader = BufReader::new(file);
         let mut buf = String::new();
 
@@ -460,9 +472,10 @@ pub fn parse_key_str(content: &str) -> Result<(PairType, String, String)> {
 }
 
 fn read_key_bytes(keyfile: &Path) -> Result<Vec<u8>> {
-    let mut f = File::open(keyfile)?;
+    let key_error = |e| access_error(e, keyfile, Access::Key, "reading a key");
+    let mut f = File::open(keyfile).map_err(key_error)?;
     let mut s = String::new();
-    if f.read_to_string(&mut s)? == 0 {
+    if f.read_to_string(&mut s).map_err(key_error)? == 0 {
         return Err(Error::CryptoError("Can't read key bytes".to_string()));
     }
     read_key_bytes_from_str(&s)
@@ -534,11 +547,13 @@ fn write_keypair_files(public_keyfile: Option<&Path>,
 
 #[cfg(not(windows))]
 fn set_permissions<T: AsRef<Path>>(path: T) -> Result<()> {
-    use crate::util::posix_perm;
+    use crate::{fs::PermissionPolicy,
+                util::posix_perm};
 
     use super::KEY_PERMISSIONS;
 
-    posix_perm::set_permissions(path.as_ref(), KEY_PERMISSIONS)
+    posix_perm::set_permissions(path.as_ref(),
+                                PermissionPolicy::current().mode(KEY_PERMISSIONS))
 }
 
 #[cfg(windows)]
@@ -0,0 +1,289 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Leader election among a group of peers.
+//!
+//! Every member starts an election by nominating itself, then shares its `Election` with its
+//! peers by whatever transport it likes. A member receiving an election merges it into its own
+//! with `Election::merge` and adds its vote with `Election::vote`. Merging keeps the better
+//! candidate of the two, which is the one with the higher suitability, or the higher member ID
+//! when suitabilities are equal, and pools the votes. Once every alive member has voted for the
+//! same candidate, and enough members are alive to form a quorum, the election is finished and
+//! that candidate is the leader.
+//!
+//! Elections are numbered by term. A member which loses its leader starts a new election in the
+//! next term, and an election from a later term always replaces one from an earlier term.
+//!
+//! Nothing here sends or receives anything, so the same rules apply whether elections travel
+//! over gossip, a shared file, or a test harness calling `merge` directly.
+
+use std::{collections::{BTreeSet,
+                        HashSet},
+          fmt};
+
+use serde_derive::{Deserialize,
+                   Serialize};
+
+/// The progress of an `Election`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ElectionStatus {
+    /// Votes are still being collected.
+    Running,
+    /// Too few members are alive to elect a leader.
+    NoQuorum,
+    /// Every alive member has voted for the leader.
+    Finished,
+}
+
+impl fmt::Display for ElectionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match *self {
+            ElectionStatus::Running => "running",
+            ElectionStatus::NoQuorum => "no-quorum",
+            ElectionStatus::Finished => "finished",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+/// One member's view of an election.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Election {
+    pub term:        u64,
+    /// The member ID of the best candidate seen so far.
+    pub candidate:   String,
+    /// How well suited the candidate is to lead. Higher is better.
+    pub suitability: u64,
+    /// The member IDs of the members which have voted.
+    pub votes:       BTreeSet<String>,
+    pub status:      ElectionStatus,
+}
+
+impl Election {
+    /// Starts an election in `term` with `member_id` nominating and voting for itself.
+    pub fn new<S>(member_id: S, term: u64, suitability: u64) -> Self
+        where S: Into<String>
+    {
+        let candidate = member_id.into();
+        let mut votes = BTreeSet::new();
+        votes.insert(candidate.clone());
+        Election { term,
+                   candidate,
+                   suitability,
+                   votes,
+                   status: ElectionStatus::Running }
+    }
+
+    /// Starts the election for the term after this one, such as when the leader has departed.
+    pub fn next_term<S>(&self, member_id: S, suitability: u64) -> Self
+        where S: Into<String>
+    {
+        Election::new(member_id, self.term + 1, suitability)
+    }
+
+    /// Merges another member's view of the election into this one, returning whether this one
+    /// changed and so should be shared again.
+    ///
+    /// An election from a later term replaces this one and one from an earlier term is ignored.
+    /// Within a term, a finished election is final: it replaces a running one and nothing
+    /// replaces it. Otherwise the better candidate is kept and the votes are pooled.
+    pub fn merge(&mut self, other: &Election) -> bool {
+        if other.term < self.term
+           || (other.term == self.term && self.status == ElectionStatus::Finished)
+        {
+            return false;
+        }
+        if other.term > self.term || other.status == ElectionStatus::Finished {
+            let changed = self != other;
+            *self = other.clone();
+            return changed;
+        }
+        let mut changed = false;
+        if (other.suitability, &other.candidate) > (self.suitability, &self.candidate) {
+            self.candidate = other.candidate.clone();
+            self.suitability = other.suitability;
+            changed = true;
+        }
+        for vote in &other.votes {
+            changed |= self.votes.insert(vote.clone());
+        }
+        changed
+    }
+
+    /// Adds `member_id`'s vote for the current candidate, returning whether it had not already
+    /// voted.
+    pub fn vote<S>(&mut self, member_id: S) -> bool
+        where S: Into<String>
+    {
+        self.status != ElectionStatus::Finished && self.votes.insert(member_id.into())
+    }
+
+    /// Updates the status from the votes cast so far, given the member IDs of the members which
+    /// are alive and the total number of members, alive or not. A finished election stays
+    /// finished.
+    pub fn tally(&mut self, alive: &HashSet<String>, total: usize) -> ElectionStatus {
+        if self.status != ElectionStatus::Finished {
+            self.status = if !has_quorum(alive.len(), total) {
+                ElectionStatus::NoQuorum
+            } else if alive.iter().all(|m| self.votes.contains(m)) {
+                ElectionStatus::Finished
+            } else {
+                ElectionStatus::Running
+            };
+        }
+        self.status
+    }
+
+    /// The member ID of the leader, once the election has finished.
+    pub fn leader(&self) -> Option<&str> {
+        match self.status {
+            ElectionStatus::Finished => Some(&self.candidate),
+            _ => None,
+        }
+    }
+
+    /// Whether `member_id` has been elected leader.
+    pub fn is_leader(&self, member_id: &str) -> bool { self.leader() == Some(member_id) }
+}
+
+/// Whether `alive` members out of `total` are a majority, and so are enough to elect a leader
+/// without risking another majority electing a different one.
+pub fn has_quorum(alive: usize, total: usize) -> bool { alive > total / 2 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn alive(ids: &[&str]) -> HashSet<String> { ids.iter().map(|s| s.to_string()).collect() }
+
+    /// Repeatedly shares every member's election with every other member until nothing
+    /// changes, as gossip eventually would.
+    fn converge(elections: &mut [(String, Election)], alive: &HashSet<String>, total: usize) {
+        loop {
+            let mut changed = false;
+            for i in 0..elections.len() {
+                for j in 0..elections.len() {
+                    let other = elections[j].1.clone();
+                    let (ref id, ref mut election) = elections[i];
+                    changed |= election.merge(&other);
+                    changed |= election.vote(id.as_str());
+                    let before = election.status;
+                    changed |= election.tally(alive, total) != before;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    fn start(members: &[(&str, u64)]) -> Vec<(String, Election)> {
+        members.iter()
+               .map(|&(id, suitability)| (id.to_string(), Election::new(id, 1, suitability)))
+               .collect()
+    }
+
+    #[test]
+    fn most_suitable_member_wins() {
+        let mut elections = start(&[("a", 1), ("b", 5), ("c", 3)]);
+
+        converge(&mut elections, &alive(&["a", "b", "c"]), 3);
+
+        for (id, election) in &elections {
+            assert_eq!(ElectionStatus::Finished, election.status, "{}", id);
+            assert_eq!(Some("b"), election.leader());
+        }
+        assert!(elections[1].1.is_leader("b"));
+        assert!(!elections[0].1.is_leader("a"));
+    }
+
+    #[test]
+    fn ties_go_to_highest_member_id() {
+        let mut elections = start(&[("b", 1), ("c", 1), ("a", 1)]);
+
+        converge(&mut elections, &alive(&["a", "b", "c"]), 3);
+
+        assert!(elections.iter().all(|(_, e)| e.leader() == Some("c")));
+    }
+
+    #[test]
+    fn every_member_order_agrees() {
+        let members = [("a", 2), ("b", 7), ("c", 7), ("d", 0)];
+        let everyone = alive(&["a", "b", "c", "d"]);
+        // Every rotation of who starts first must elect the same leader
+        for shift in 0..members.len() {
+            let mut order = members.to_vec();
+            order.rotate_left(shift);
+            let mut elections = start(&order);
+
+            converge(&mut elections, &everyone, members.len());
+
+            assert!(elections.iter().all(|(_, e)| e.leader() == Some("c")),
+                    "rotation {}: {:?}",
+                    shift,
+                    elections);
+        }
+    }
+
+    #[test]
+    fn no_quorum_without_majority() {
+        let mut elections = start(&[("a", 1), ("b", 2)]);
+
+        converge(&mut elections, &alive(&["a", "b"]), 4);
+
+        assert!(elections.iter()
+                         .all(|(_, e)| e.status == ElectionStatus::NoQuorum));
+        assert!(elections.iter().all(|(_, e)| e.leader().is_none()));
+    }
+
+    #[test]
+    fn later_term_replaces_earlier() {
+        let mut finished = Election::new("a", 1, 1);
+        finished.tally(&alive(&["a"]), 1);
+        assert_eq!(Some("a"), finished.leader());
+
+        let next = finished.next_term("b", 0);
+        assert!(finished.merge(&next));
+        assert_eq!(2, finished.term);
+        assert_eq!(ElectionStatus::Running, finished.status);
+
+        let stale = Election::new("z", 1, 100);
+        assert!(!finished.merge(&stale));
+        assert_eq!("b", finished.candidate);
+    }
+
+    #[test]
+    fn finished_election_is_final_within_term() {
+        let mut finished = Election::new("a", 1, 1);
+        finished.tally(&alive(&["a"]), 1);
+        let better = Election::new("b", 1, 10);
+
+        assert!(!finished.merge(&better));
+        assert!(!finished.vote("b"));
+
+        let mut running = better.clone();
+        assert!(running.merge(&finished));
+        assert_eq!(Some("a"), running.leader());
+    }
+
+    #[test]
+    fn has_quorum_needs_majority() {
+        assert!(has_quorum(1, 1));
+        assert!(has_quorum(2, 3));
+        assert!(!has_quorum(1, 3));
+        assert!(!has_quorum(2, 4));
+        assert!(has_quorum(3, 4));
+    }
+}
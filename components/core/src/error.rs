@@ -21,27 +21,70 @@ use std::{env,
           path::PathBuf,
           result,
           str,
-          string};
+          string,
+          time::Duration};
 
+#[cfg(feature = "fs")]
 use libarchive;
 use regex;
 use toml;
 
 use crate::package::{self,
                      Identifiable};
+#[cfg(feature = "fs")]
+use crate::{fs,
+            hooks,
+            lock,
+            os};
 
 pub type Result<T> = result::Result<T, Error>;
 
 /// Core error types
 #[derive(Debug)]
 pub enum Error {
+    /// Occurs when an admission policy refuses a package.
+    AdmissionDenied(String, String),
     /// Occurs when a `habitat_core::package::PackageArchive` is being read.
+    #[cfg(feature = "fs")]
     ArchiveError(libarchive::error::ArchiveError),
+    /// Occurs when a line of an artifact's header is longer than the configured limit, in bytes.
+    ArtifactHeaderTooLarge(u64),
+    /// Occurs when a fetched artifact is not for the package or target asked for. The fields are
+    /// what was asked for and what the artifact holds.
+    ArtifactMismatch(String, String),
+    /// Occurs when a fetched artifact was not signed by a key of its package's origin. The fields
+    /// are the package, the expected origin and the key which signed it.
+    ArtifactSignerMismatch(String, String, String),
     BadBindingMode(String),
+    /// Occurs when an update strategy string cannot be parsed.
+    BadUpdateStrategy(String),
+    /// Occurs when an update condition string cannot be parsed.
+    BadUpdateCondition(String),
+    /// Occurs when an update condition is used with an update strategy it cannot apply to.
+    IncompatibleUpdateCondition(String, String),
+    /// Occurs when a topology string cannot be parsed.
+    BadTopology(String),
+    /// Occurs when a signal name cannot be parsed.
+    BadSignal(String),
+    /// Occurs when an integrity verification level cannot be parsed.
+    BadVerifyLevel(String),
+    /// Occurs when a service group has too few members for its topology.
+    InsufficientMembers(String, usize, usize),
+    /// Occurs when a service configuration is applied with an incarnation no higher than
+    /// the\nlatest one applied.
+    StaleConfigIncarnation(u64, u64),
     /// An invalid path to a keyfile was given.
     BadKeyPath(String),
+    /// Occurs when an operation stops because its `CancellationToken` was cancelled.
+    Cancelled,
     /// An operation expected a composite package
     CompositePackageExpected(String),
+    /// Occurs when configuration cannot be written to or read from environment variables, with the
+    /// variable's name and the reason.
+    ConfigEnvInvalid(String, String),
+    /// Occurs when the environment variable holding a service's configuration is not valid
+    /// Unicode.
+    ConfigEnvVarNotUnicode(String),
     /// Error reading raw contents of configuration file.
     ConfigFileIO(PathBuf, io::Error),
     /// Parsing error while reading a configuration file.
@@ -89,22 +132,58 @@ pub enum Error {
     CryptProtectDataFailed(String),
     /// Occurs when a call to CryptUnprotectData fails
     CryptUnprotectDataFailed(String),
+    /// Occurs when an event is emitted after the event stream's writer has stopped.
+    EventStreamClosed,
     /// Occurs when a file that should exist does not or could not be read.
     FileNotFound(String),
+    /// Occurs when a firewall command fails.
+    FirewallCommandFailed(String, String),
     /// Occurs when a fully-qualified package identifier is required,
     /// but a non-qualified identifier (e.g. "foo/bar" or
     /// "foo/bar/1.0.0") was given instead.
     FullyQualifiedPackageIdentRequired(String),
+    /// Occurs when a filesystem has too little free space for an operation, such as unpacking a
+    /// package: the path, the bytes required, and the bytes available.
+    InsufficientDiskSpace(PathBuf, u64, u64),
+    /// Occurs when an installed package fails an integrity check, with what was found wrong.
+    IntegrityCheckFailed(String, String),
     /// Occurs when an application environment string cannot be successfully parsed.
     InvalidApplicationEnvironment(String),
     /// Occurs when a service binding cannot be successfully parsed.
     InvalidBinding(String),
+    /// Occurs when a gossip datagram cannot be decoded.
+    InvalidGossipMessage(String),
+    /// Occurs when a log sink specification cannot be parsed.
+    InvalidLogSink(String),
+    /// Occurs when a restart policy specification cannot be parsed.
+    InvalidRestartPolicy(String),
+    /// Occurs when a staleness policy specification cannot be parsed.
+    InvalidStalenessPolicy(String),
+    /// Occurs when a read limits specification cannot be parsed.
+    InvalidReadLimits(String),
+    /// Occurs when a service lifecycle transition is not valid from the current state.
+    InvalidStateTransition(String, String),
     /// Occurs when a package identifier string cannot be successfully parsed.
     InvalidPackageIdent(String),
+    /// Occurs when loosely typed input cannot be made into a package identifier.
+    MalformedPackageIdent(package::ident::IdentDiagnostic),
     /// Occurs when a package target string cannot be successfully parsed.
     InvalidPackageTarget(String),
     /// Occurs when a package type is not recognized.
     InvalidPackageType(String),
+    /// Occurs when a rumor store file is not in the expected format, or a record cannot be written
+    /// to it.
+    InvalidRumorStore(PathBuf, String),
+    /// Occurs when a file uploaded to a service group is misnamed, too large, or corrupt.
+    InvalidServiceFile(String, String),
+    /// Occurs when a package's metadata cannot be turned into a scheduler's service definition.
+    InvalidServiceMetadata(String, String),
+    /// Occurs when a suitability hook's output is not a valid suitability.
+    InvalidSuitability(String),
+    /// Occurs when a seccomp profile cannot be parsed or compiled.
+    InvalidSeccompProfile(String),
+    /// Occurs when a service spec is not valid.
+    InvalidServiceSpec(String),
     /// Occurs when a service group string cannot be successfully parsed.
     InvalidServiceGroup(String),
     /// Occurs when an origin is in an invalid format
@@ -113,45 +192,103 @@ pub enum Error {
     InvalidPathString(ffi::OsString),
     /// Occurs when making lower level IO calls.
     IO(io::Error),
+    /// Occurs when a value cannot be serialized to or deserialized from JSON.
+    Json(serde_json::Error),
     /// Errors when joining paths :)
     JoinPathsError(env::JoinPathsError),
     // When LogonUserW does not have the correct logon type
+    /// Occurs when a line of a key-value metafile has no `=`.
+    KeyValueMalformed(String),
+    /// Occurs when the OS keyring cannot be reached, or refuses a request.
+    KeyringUnavailable(String),
     LogonTypeNotGranted,
     /// Occurs when a call to LogonUserW fails
     LogonUserFailed(io::Error),
+    /// Occurs when an artifact fetched from a mirror was not signed with the key configured for
+    /// it. The fields are the package, the expected key and the key which signed it.
+    MirrorSignerMismatch(String, String, String),
     /// Occurs when a BIND, BIND_OPTIONAL, or BIND_MAP MetaFile is
     /// read and contains a bad entry.
     MetaFileBadBind,
     /// Occurs when a package metadata file cannot be opened, read, or parsed.
     MetaFileMalformed(package::metadata::MetaFile),
+    /// Occurs when a package metadata file is larger than the configured limits allow.
+    MetaFileTooLarge(package::metadata::MetaFile, String),
     /// Occurs when a particular package metadata file is not found.
     MetaFileNotFound(package::metadata::MetaFile),
     /// When an IO error while accessing a MetaFile.
     MetaFileIO(io::Error),
+    /// Occurs when connecting or publishing to a NATS server fails.
+    NatsError(String),
     /// Occurs when we can't find an outbound IP address
     NoOutboundAddr,
     /// Occurs when a call to OpenDesktopW fails
     OpenDesktopFailed(String),
+    /// Occurs when a blocking operation, named by the first field, does not finish in time.
+    OperationTimedOut(String, Duration),
+    /// Occurs when a package's hook, named by its path in the package, fails.
+    #[cfg(feature = "fs")]
+    PackageHookFailed(package::PackageIdent,
+                      &'static str,
+                      Box<hooks::package::HookResult>),
+    /// Occurs when a package is held at a release other than the one it would be moved to.
+    PackageHeld(String, String),
+    /// Occurs when a package cannot be removed because processes, given by their identifiers,
+    /// are running from it.
+    #[cfg(feature = "fs")]
+    PackageInUse(package::PackageIdent, Vec<os::process::Pid>),
     /// Occurs when a suitable installed package cannot be found.
     PackageNotFound(package::PackageIdent),
     /// Occurs where trying to unpack a package
     PackageUnpackFailed(String),
     /// When an error occurs parsing an integer.
     ParseIntError(num::ParseIntError),
+    /// Occurs when the operating system refuses access to a path, with a hint at how it should be
+    /// owned and permitted.
+    #[cfg(feature = "fs")]
+    PermissionDenied(fs::PermissionDenied),
     /// Occurs upon errors related to file or directory permissions.
     PermissionFailed(String),
     /// Error parsing the contents of a plan file were incomplete or malformed.
     PlanMalformed,
     // When CreateProcessAsUserW does not have the correct privileges
     PrivilegeNotHeld,
+    /// Occurs when a port a service needs is already in use, with a free port to use instead if
+    /// one was found.
+    PortUnavailable(u16, Option<u16>),
+    /// Occurs when a health probe finds its target unhealthy or unreachable.
+    ProbeFailed(String),
     /// When an error occurs parsing or compiling a regular expression.
     RegexParse(regex::Error),
+    /// Occurs when the references between `RUNTIME_ENVIRONMENT` values form a cycle.
+    RuntimeEnvironmentCycle(String),
+    /// Occurs when a service file is applied with an incarnation no higher than the one already
+    /// applied, which is the second.
+    StaleFileIncarnation(String, u64, u64),
     /// When an error occurs converting a `String` from a UTF-8 byte vector.
     StringFromUtf8Error(string::FromUtf8Error),
+    /// Occurs when a Supervisor lock is already held by a running process.
+    #[cfg(feature = "fs")]
+    SupervisorLocked(PathBuf, lock::LockHolder),
+    /// Occurs when a Supervisor lock exists but records no process that can be checked, so it
+    /// cannot be known to be stale.
+    #[cfg(feature = "fs")]
+    SupervisorLockUnreadable(PathBuf),
+    /// Occurs when a template is not valid Handlebars.
+    TemplateError(handlebars::TemplateError),
+    /// Occurs when a template file cannot be read or is not valid Handlebars.
+    TemplateFileError(handlebars::TemplateFileError),
+    /// Occurs when a template cannot be rendered.
+    TemplateRenderError(handlebars::RenderError),
     /// When the system target (platform and architecture) do not match the package target.
     TargetMatchError(String),
+    /// Occurs when TLS settings cannot be loaded or a TLS connection cannot be established.
+    TlsError(String),
     /// Occurs when a `uname` libc call returns an error.
     UnameFailed(String),
+    /// Occurs when a package archive contains entries which break the sanitize policy.
+    #[cfg(feature = "fs")]
+    UnsafeArchive(PathBuf, Vec<package::archive::sanitize::Violation>),
     /// Occurs when a `waitpid` libc call returns an error.
     WaitpidFailed(String),
     /// Occurs when a `kill` libc call returns an error.
@@ -164,6 +301,12 @@ pub enum Error {
     WaitForSingleObjectFailed(String),
     /// Occurs when a `TerminateProcess` win32 call returns an error.
     TerminateProcessFailed(String),
+    /// Occurs when a call to the Windows Event Log API fails.
+    EventLogFailed(&'static str, io::Error),
+    /// Occurs when a Windows registry win32 call returns an error.
+    RegistryFailed(&'static str, io::Error),
+    /// Occurs when a Windows Service Control Manager win32 call returns an error.
+    ServiceControlManagerFailed(&'static str, io::Error),
     /// When an error occurs attempting to interpret a sequence of u8 as a string.
     Utf8Error(str::Utf8Error),
     /// When a `PackageTaget` for a package does not match the active `PackageTarget` for this
@@ -174,15 +317,57 @@ pub enum Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let msg = match *self {
+            Error::AdmissionDenied(ref ident, ref reason) => {
+                format!("Package {} was refused by policy: {}", ident, reason)
+            }
+            #[cfg(feature = "fs")]
             Error::ArchiveError(ref err) => format!("{}", err),
+            Error::ArtifactHeaderTooLarge(max) => {
+                format!("Corrupt payload, a header line is longer than {} bytes",
+                        max)
+            }
+            Error::ArtifactMismatch(ref expected, ref found) => {
+                format!("Expected an artifact for {}, but the one fetched is for {}",
+                        expected, found)
+            }
+            Error::ArtifactSignerMismatch(ref ident, ref origin, ref signer) => {
+                format!("The artifact for {} was signed by {}, but must be signed by a key of                          the {} origin",
+                        ident, signer, origin)
+            }
             Error::BadBindingMode(ref value) => format!("Unknown binding mode '{}'", value),
+            Error::BadUpdateStrategy(ref value) => format!("Unknown update strategy '{}'", value),
+            Error::BadUpdateCondition(ref value) => format!("Unknown update condition '{}'", value),
+            Error::IncompatibleUpdateCondition(ref condition, ref strategy) => {
+                format!("Update condition '{}' cannot be used with update strategy '{}'",
+                        condition, strategy)
+            }
+            Error::BadTopology(ref value) => format!("Unknown topology '{}'", value),
+            Error::BadSignal(ref value) => format!("Unknown signal '{}'", value),
+            Error::BadVerifyLevel(ref value) => format!("Unknown verification level '{}'", value),
+            Error::InsufficientMembers(ref topology, required, count) => {
+                format!("The {} topology requires at least {} members, but only {} are present",
+                        topology, required, count)
+            }
+            Error::StaleConfigIncarnation(incarnation, latest) => {
+                format!("Configuration incarnation {} is not newer than the latest applied, {}",
+                        incarnation, latest)
+            }
             Error::BadKeyPath(ref e) => {
                 format!("Invalid keypath: {}. Specify an absolute path to a file on disk.",
                         e)
             }
+            Error::Cancelled => "The operation was cancelled".to_string(),
             Error::CompositePackageExpected(ref ident) => {
                 format!("The package is not a composite: {}", ident)
             }
+            Error::ConfigEnvInvalid(ref var, ref reason) => {
+                format!("Invalid configuration environment variable {}: {}",
+                        var, reason)
+            }
+            Error::ConfigEnvVarNotUnicode(ref var) => {
+                format!("Configuration in environment variable {} is not valid Unicode",
+                        var)
+            }
             Error::ConfigFileIO(ref f, ref e) => {
                 format!("Error reading configuration file, {}, {}", f.display(), e)
             }
@@ -260,11 +445,25 @@ impl fmt::Display for Error {
             Error::CryptoError(ref e) => format!("Crypto error: {}", e),
             Error::CryptProtectDataFailed(ref e) => e.to_string(),
             Error::CryptUnprotectDataFailed(ref e) => e.to_string(),
+            Error::EventStreamClosed => "Event stream is closed".to_string(),
             Error::FileNotFound(ref e) => format!("File not found at: {}", e),
+            Error::FirewallCommandFailed(ref cmd, ref e) => {
+                format!("Firewall command `{}` failed: {}", cmd, e)
+            }
             Error::FullyQualifiedPackageIdentRequired(ref ident) => {
                 format!("Fully-qualified package identifier was expected, but found: {:?}",
                         ident)
             }
+            Error::InsufficientDiskSpace(ref path, required, available) => {
+                format!("Not enough free space at {}: {} bytes are required, but only {} are \
+                         available",
+                        path.display(),
+                        required,
+                        available)
+            }
+            Error::IntegrityCheckFailed(ref ident, ref detail) => {
+                format!("Integrity check of {} failed: {}", ident, detail)
+            }
             Error::InvalidApplicationEnvironment(ref e) => {
                 format!("Invalid application environment: {}. A valid application environment \
                          string is in the form application.environment (example: twitter.prod)",
@@ -275,17 +474,60 @@ impl fmt::Display for Error {
                          <NAME> is a service name, and <SERVICE_GROUP> is a valid service group",
                         binding)
             }
+            Error::InvalidGossipMessage(ref e) => format!("Invalid gossip message: {}", e),
+            Error::InvalidLogSink(ref e) => {
+                format!("Invalid log sink: {}. A valid log sink is one of syslog, \
+                         syslog+udp://<HOST>:<PORT>, syslog+unix://<PATH>, or eventlog[:<SOURCE>]",
+                        e)
+            }
+            Error::InvalidRestartPolicy(ref e) => {
+                format!("Invalid restart policy: {}. A valid restart policy is a comma-separated \
+                         list of initial=<DURATION>, multiplier=<NUMBER>, max=<DURATION>, \
+                         jitter=<FRACTION>, and budget=<COUNT|none> (example: \
+                         initial=1s,max=5m,budget=10)",
+                        e)
+            }
+            Error::InvalidStalenessPolicy(ref e) => {
+                format!("Invalid staleness policy: {}. A valid staleness policy is a \
+                         comma-separated list of <ORIGIN>=<AGE> and *=<AGE>, where an age is a \
+                         number of days followed by d, a number of weeks followed by w, or none \
+                         (example: core=30d,*=13w)",
+                        e)
+            }
+            Error::InvalidReadLimits(ref e) => {
+                format!("Invalid read limits: {}. Valid read limits are a comma-separated list of \
+                         metafile_bytes=<BYTES>, metafile_lines=<COUNT>, and \
+                         header_line_bytes=<BYTES>",
+                        e)
+            }
+            Error::InvalidStateTransition(ref state, ref transition) => {
+                format!("Cannot apply {} to a service which is {}",
+                        transition, state)
+            }
             Error::InvalidPackageIdent(ref e) => {
                 format!("Invalid package identifier: {:?}. A valid identifier is in the form \
                          origin/name (example: acme/redis)",
                         e)
             }
+            Error::MalformedPackageIdent(ref diagnostic) => diagnostic.to_string(),
             Error::InvalidPackageTarget(ref e) => {
                 format!("Invalid package target: {}. A valid target is in the form \
                          architecture-platform (example: x86_64-linux)",
                         e)
             }
             Error::InvalidPackageType(ref e) => format!("Invalid package type: {}.", e),
+            Error::InvalidRumorStore(ref path, ref e) => {
+                format!("Invalid rumor store {}: {}", path.display(), e)
+            }
+            Error::InvalidServiceFile(ref name, ref reason) => {
+                format!("Invalid service file {}: {}", name, reason)
+            }
+            Error::InvalidServiceMetadata(ref ident, ref reason) => {
+                format!("Cannot describe {} to a scheduler: {}", ident, reason)
+            }
+            Error::InvalidSuitability(ref e) => format!("Invalid suitability: {}", e),
+            Error::InvalidSeccompProfile(ref e) => format!("Invalid seccomp profile: {}", e),
+            Error::InvalidServiceSpec(ref e) => format!("Invalid service spec: {}", e),
             Error::InvalidServiceGroup(ref e) => {
                 format!("Invalid service group: {}. A valid service group string is in the form \
                          service.group (example: redis.production)",
@@ -301,25 +543,58 @@ impl fmt::Display for Error {
                 format!("Could not generate String from path: {:?}", s)
             }
             Error::IO(ref err) => format!("{}", err),
+            Error::Json(ref e) => format!("{}", e),
             Error::JoinPathsError(ref err) => format!("{}", err),
+            Error::KeyValueMalformed(ref line) => format!("Expected KEY=VALUE, found '{}'", line),
+            Error::KeyringUnavailable(ref e) => format!("Keyring unavailable: {}", e),
             Error::LogonTypeNotGranted => {
                 "hab_svc_user user must possess the 'SE_SERVICE_LOGON_NAME' account right to be \
                  spawned as a service by the Supervisor"
                                                         .to_string()
             }
             Error::LogonUserFailed(ref e) => format!("Failure calling LogonUserW: {:?}", e),
+            Error::MirrorSignerMismatch(ref ident, ref expected, ref signer) => {
+                format!("The artifact for {} fetched from its mirror was signed by {}, but the \
+                         mirror's artifacts must be signed by {}",
+                        ident, signer, expected)
+            }
             Error::MetaFileBadBind => {
                 "Bad value parsed from BIND, BIND_OPTIONAL, or BIND_MAP".to_string()
             }
             Error::MetaFileMalformed(ref e) => {
                 format!("MetaFile: {:?}, didn't contain a valid UTF-8 string", e)
             }
+            Error::MetaFileTooLarge(ref file, ref limit) => {
+                format!("MetaFile: {}, is {}", file, limit)
+            }
             Error::MetaFileNotFound(ref e) => format!("Couldn't read MetaFile: {}, not found", e),
             Error::MetaFileIO(ref e) => format!("IO error while accessing MetaFile: {:?}", e),
+            Error::NatsError(ref e) => format!("NATS error: {}", e),
             Error::NoOutboundAddr => {
                 "Failed to discover this hosts outbound IP address".to_string()
             }
             Error::OpenDesktopFailed(ref e) => e.to_string(),
+            Error::OperationTimedOut(ref operation, ref timeout) => {
+                format!("Timed out after {:?} waiting to {}", timeout, operation)
+            }
+            #[cfg(feature = "fs")]
+            Error::PackageHookFailed(ref ident, hook, ref result) => {
+                format!("The {} hook of {} failed with exit code {}: {}",
+                        hook,
+                        ident,
+                        result.exit_code
+                              .map_or_else(|| "none".to_string(), |c| c.to_string()),
+                        result.stderr.trim())
+            }
+            Error::PackageHeld(ref held, ref candidate) => {
+                format!("Package is held at {}, and cannot be moved to {}",
+                        held, candidate)
+            }
+            #[cfg(feature = "fs")]
+            Error::PackageInUse(ref ident, ref pids) => {
+                format!("Cannot remove {}, as processes {:?} are running from it",
+                        ident, pids)
+            }
             Error::PackageNotFound(ref pkg) => {
                 if pkg.fully_qualified() {
                     format!("Cannot find package: {}", pkg)
@@ -330,15 +605,53 @@ impl fmt::Display for Error {
             Error::PackageUnpackFailed(ref e) => format!("Package could not be unpacked. {}", e),
             Error::ParseIntError(ref e) => format!("{}", e),
             Error::PlanMalformed => "Failed to read or parse contents of Plan file".to_string(),
+            #[cfg(feature = "fs")]
+            Error::PermissionDenied(ref denied) => denied.to_string(),
             Error::PermissionFailed(ref e) => e.to_string(),
             Error::PrivilegeNotHeld => "Current user must possess the 'SE_INCREASE_QUOTA_NAME' \
                                         and 'SE_ASSIGNPRIMARYTOKEN_NAME' privilege to spawn a new \
                                         process as a different user"
                                                                     .to_string(),
+            Error::PortUnavailable(port, Some(free)) => {
+                format!("Port {} is already in use; port {} is free", port, free)
+            }
+            Error::PortUnavailable(port, None) => format!("Port {} is already in use", port),
+            Error::ProbeFailed(ref e) => format!("Health probe failed: {}", e),
             Error::RegexParse(ref e) => format!("{}", e),
+            Error::RuntimeEnvironmentCycle(ref cycle) => {
+                format!("Cyclic reference found while expanding RUNTIME_ENVIRONMENT: {}",
+                        cycle)
+            }
+            Error::StaleFileIncarnation(ref name, incarnation, current) => {
+                format!("Cannot apply {} at incarnation {}, as incarnation {} is already applied",
+                        name, incarnation, current)
+            }
             Error::StringFromUtf8Error(ref e) => format!("{}", e),
+            #[cfg(feature = "fs")]
+            Error::SupervisorLocked(ref path, ref holder) => {
+                format!("Another Supervisor (PID {}) holds the lock at {}",
+                        holder.pid,
+                        path.display())
+            }
+            #[cfg(feature = "fs")]
+            Error::SupervisorLockUnreadable(ref path) => {
+                format!("The Supervisor lock at {} cannot be read; if no Supervisor is running, \
+                         remove it",
+                        path.display())
+            }
+            Error::TemplateError(ref e) => format!("Invalid template: {}", e),
+            Error::TemplateFileError(ref e) => format!("Unable to load template file: {}", e),
+            Error::TemplateRenderError(ref e) => format!("Unable to render template: {}", e),
             Error::TargetMatchError(ref e) => e.to_string(),
+            Error::TlsError(ref e) => format!("TLS error: {}", e),
             Error::UnameFailed(ref e) => e.to_string(),
+            #[cfg(feature = "fs")]
+            Error::UnsafeArchive(ref path, ref violations) => {
+                let violations: Vec<String> = violations.iter().map(ToString::to_string).collect();
+                format!("Refusing to unpack {}, which contains unsafe entries: {}",
+                        path.display(),
+                        violations.join("; "))
+            }
             Error::WaitpidFailed(ref e) => e.to_string(),
             Error::SignalFailed(ref r, ref e) => {
                 format!("Failed to send a signal to the child process: {}, {}", r, e)
@@ -347,6 +660,9 @@ impl fmt::Display for Error {
             Error::CreateToolhelp32SnapshotFailed(ref e) => e.to_string(),
             Error::WaitForSingleObjectFailed(ref e) => e.to_string(),
             Error::TerminateProcessFailed(ref e) => e.to_string(),
+            Error::EventLogFailed(call, ref e) => format!("{} failed: {}", call, e),
+            Error::RegistryFailed(call, ref e) => format!("{} failed: {}", call, e),
+            Error::ServiceControlManagerFailed(call, ref e) => format!("{} failed: {}", call, e),
             Error::Utf8Error(ref e) => format!("{}", e),
             Error::WrongActivePackageTarget(ref active, ref wrong) => {
                 format!("Package target '{}' is not supported as this system has a different \
@@ -361,10 +677,32 @@ impl fmt::Display for Error {
 impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
+            Error::AdmissionDenied(..) => "Package was refused by policy",
+            #[cfg(feature = "fs")]
             Error::ArchiveError(ref err) => err.description(),
+            Error::ArtifactHeaderTooLarge(_) => "Artifact header line is too long",
+            Error::ArtifactMismatch(..) => "Fetched artifact is not for the package asked for",
+            Error::ArtifactSignerMismatch(..) => {
+                "Fetched artifact is not signed by its origin's key"
+            }
             Error::BadBindingMode(_) => "Unknown binding mode",
+            Error::BadUpdateStrategy(_) => "Unknown update strategy",
+            Error::BadUpdateCondition(_) => "Unknown update condition",
+            Error::IncompatibleUpdateCondition(..) => "Incompatible update condition and strategy",
+            Error::BadTopology(_) => "Unknown topology",
+            Error::BadSignal(_) => "Unknown signal",
+            Error::BadVerifyLevel(_) => "Unknown verification level",
+            Error::InsufficientMembers(..) => "Too few members for the service topology",
+            Error::StaleConfigIncarnation(..) => {
+                "Configuration incarnation is not newer than the latest applied"
+            }
             Error::BadKeyPath(_) => "An absolute path to a file on disk is required",
+            Error::Cancelled => "The operation was cancelled",
             Error::CompositePackageExpected(_) => "A composite package was expected",
+            Error::ConfigEnvInvalid(..) => "Invalid configuration environment variable",
+            Error::ConfigEnvVarNotUnicode(_) => {
+                "Configuration in environment variable is not valid Unicode"
+            }
             Error::ConfigFileIO(..) => "Unable to read the raw contents of a configuration file",
             Error::ConfigFileSyntax(_) => "Error parsing contents of configuration file",
             Error::ConfigInvalidArraySocketAddr(_) => {
@@ -428,10 +766,14 @@ impl error::Error for Error {
             Error::CryptoError(_) => "Crypto error",
             Error::CryptProtectDataFailed(_) => "CryptProtectData failed",
             Error::CryptUnprotectDataFailed(_) => "CryptUnprotectData failed",
+            Error::EventStreamClosed => "Event stream is closed",
             Error::FileNotFound(_) => "File not found",
+            Error::FirewallCommandFailed(..) => "A firewall command failed",
             Error::FullyQualifiedPackageIdentRequired(_) => {
                 "A fully-qualified package identifier was expected"
             }
+            Error::InsufficientDiskSpace(..) => "Not enough free disk space",
+            Error::IntegrityCheckFailed(..) => "Installed package failed an integrity check",
             Error::InvalidApplicationEnvironment(_) => {
                 "Application environment strings must be in application.environment format \
                  (example: twitter.prod)"
@@ -440,13 +782,28 @@ impl error::Error for Error {
                 "Service Bind strings must be in name:service_group format (example \
                  cache:redis.cache@organization)."
             }
+            Error::InvalidGossipMessage(_) => "Invalid gossip message",
+            Error::InvalidLogSink(_) => "Log sink specification is invalid",
+            Error::InvalidRestartPolicy(_) => "Restart policy specification is invalid",
+            Error::InvalidStalenessPolicy(_) => "Invalid staleness policy",
+            Error::InvalidReadLimits(_) => "Read limits specification is invalid",
+            Error::InvalidStateTransition(..) => "Invalid service state transition",
             Error::InvalidPackageIdent(_) => {
                 "Package identifiers must be in origin/name format (example: acme/redis)"
             }
+            Error::MalformedPackageIdent(_) => "Invalid package identifier",
             Error::InvalidPackageTarget(_) => {
                 "Package targets must be in architecture-platform format (example: x86_64-linux)"
             }
             Error::InvalidPackageType(_) => "Unsupported package type supplied.",
+            Error::InvalidRumorStore(..) => "Invalid rumor store",
+            Error::InvalidServiceFile(..) => "Invalid service file",
+            Error::InvalidServiceMetadata(..) => "Invalid service metadata for a scheduler",
+            Error::InvalidSuitability(_) => {
+                "A suitability must be a whole number between 0 and 18446744073709551615"
+            }
+            Error::InvalidSeccompProfile(_) => "Invalid seccomp profile",
+            Error::InvalidServiceSpec(_) => "Invalid service spec",
             Error::InvalidServiceGroup(_) => {
                 "Service group strings must be in service.group[@organization] format (example: \
                  redis.production or foo.default@bazcorp)"
@@ -457,35 +814,72 @@ impl error::Error for Error {
             }
             Error::InvalidPathString(_) => "Failed to convert an OsString Path to a String",
             Error::IO(ref err) => err.description(),
+            Error::Json(_) => "Failed to serialize or deserialize JSON",
             Error::JoinPathsError(ref err) => err.description(),
+            Error::KeyValueMalformed(_) => "Malformed key-value line",
+            Error::KeyringUnavailable(_) => "The OS keyring could not be used",
             Error::LogonTypeNotGranted => {
                 "Logon type not granted to hab_svc_user to be spawned by the Supervisor"
             }
             Error::LogonUserFailed(_) => "LogonUserW failed",
+            Error::MirrorSignerMismatch(..) => {
+                "Mirrored artifact is not signed by the mirror's key"
+            }
             Error::MetaFileBadBind => {
                 "Bad value parsed from BIND, BIND_OPTIONAL, or BIND_MAP MetaFile"
             }
             Error::MetaFileMalformed(_) => "MetaFile didn't contain a valid UTF-8 string",
+            Error::MetaFileTooLarge(..) => "Metafile is larger than allowed",
             Error::MetaFileNotFound(_) => "Failed to read an archive's metafile",
             Error::MetaFileIO(_) => "MetaFile could not be read or written to",
+            Error::NatsError(_) => "Failed to communicate with a NATS server",
             Error::NoOutboundAddr => "Failed to discover the outbound IP address",
             Error::OpenDesktopFailed(_) => "OpenDesktopW failed",
+            Error::OperationTimedOut(..) => "A blocking operation timed out",
+            #[cfg(feature = "fs")]
+            Error::PackageHookFailed(..) => "A package hook failed",
+            Error::PackageHeld(..) => "Package is held at another release",
+            #[cfg(feature = "fs")]
+            Error::PackageInUse(..) => "Package is in use by running processes",
             Error::PackageNotFound(_) => "Cannot find a package",
             Error::PackageUnpackFailed(_) => "Package could not be unpacked",
             Error::ParseIntError(_) => "Failed to parse an integer from a string!",
+            #[cfg(feature = "fs")]
+            Error::PermissionDenied(_) => "Permission denied",
             Error::PermissionFailed(_) => "File system permissions error",
             Error::PlanMalformed => "Failed to read or parse contents of Plan file",
             Error::PrivilegeNotHeld => "Privilege not held to spawn process as different user",
+            Error::PortUnavailable(..) => "A port is already in use",
+            Error::ProbeFailed(_) => "A health probe failed",
             Error::RegexParse(_) => "Failed to parse a regular expression",
+            Error::RuntimeEnvironmentCycle(_) => {
+                "Cyclic reference found while expanding RUNTIME_ENVIRONMENT"
+            }
+            Error::StaleFileIncarnation(..) => {
+                "Service file incarnation is not newer than the current one"
+            }
             Error::StringFromUtf8Error(_) => "Failed to convert a string from a Vec<u8> as UTF-8",
+            #[cfg(feature = "fs")]
+            Error::SupervisorLocked(..) => "Another Supervisor is already running",
+            #[cfg(feature = "fs")]
+            Error::SupervisorLockUnreadable(_) => "The Supervisor lock cannot be read",
+            Error::TemplateError(_) => "Invalid template",
+            Error::TemplateFileError(_) => "Unable to load template file",
+            Error::TemplateRenderError(_) => "Unable to render template",
             Error::TargetMatchError(_) => "System target does not match package target",
+            Error::TlsError(_) => "Failed to establish a TLS connection",
             Error::UnameFailed(_) => "uname failed",
             Error::SignalFailed(..) => "Failed to send a signal to the child process",
             Error::CreateToolhelp32SnapshotFailed(_) => "CreateToolhelp32Snapshot failed",
+            #[cfg(feature = "fs")]
+            Error::UnsafeArchive(..) => "Package archive contains unsafe entries",
             Error::WaitpidFailed(_) => "waitpid failed",
             Error::GetExitCodeProcessFailed(_) => "GetExitCodeProcess failed",
             Error::WaitForSingleObjectFailed(_) => "WaitForSingleObjectFailed failed",
             Error::TerminateProcessFailed(_) => "Failed to call TerminateProcess",
+            Error::EventLogFailed(..) => "Windows Event Log call failed",
+            Error::RegistryFailed(..) => "Windows registry call failed",
+            Error::ServiceControlManagerFailed(..) => "Windows Service Control Manager call failed",
             Error::Utf8Error(_) => "Failed to interpret a sequence of bytes as a string",
             Error::WrongActivePackageTarget(..) => {
                 "Package target is not supported as this system has a different active package \
@@ -507,10 +901,27 @@ impl From<str::Utf8Error> for Error {
     fn from(err: str::Utf8Error) -> Self { Error::Utf8Error(err) }
 }
 
+impl From<handlebars::TemplateError> for Error {
+    fn from(err: handlebars::TemplateError) -> Self { Error::TemplateError(err) }
+}
+
+impl From<handlebars::TemplateFileError> for Error {
+    fn from(err: handlebars::TemplateFileError) -> Self { Error::TemplateFileError(err) }
+}
+
+impl From<handlebars::RenderError> for Error {
+    fn from(err: handlebars::RenderError) -> Self { Error::TemplateRenderError(err) }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self { Error::IO(err) }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self { Error::Json(err) }
+}
+
+#[cfg(feature = "fs")]
 impl From<libarchive::error::ArchiveError> for Error {
     fn from(err: libarchive::error::ArchiveError) -> Self { Error::ArchiveError(err) }
 }
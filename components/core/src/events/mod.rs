@@ -0,0 +1,178 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Structured events describing what happens to packages and services.
+//!
+//! Events are written as JSON lines: one JSON object per line, each carrying the
+//! `schema_version` it was written with, a `timestamp`, and a `type` naming the event, alongside
+//! the event's own fields. Consumers should ignore fields they do not recognize; the schema
+//! version is only incremented when existing fields change meaning or are removed.
+
+#[cfg(feature = "nats")]
+pub mod nats;
+mod stream;
+
+pub use self::stream::{Backpressure,
+                       Destination,
+                       EventStream,
+                       StreamOptions};
+
+use serde_derive::{Deserialize,
+                   Serialize};
+
+use crate::{error::Result,
+            package::{PackageIdent,
+                      PackageTarget},
+            service::{state::ServiceState,
+                      ServiceGroup}};
+
+/// The version of the event schema written by this crate.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The result of a service's health check.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    Warning,
+    Critical,
+    Unknown,
+}
+
+impl HealthStatus {
+    /// The status a health check hook reports by exiting with `code`: 0 is ok, 1 a warning and
+    /// 2 critical. Any other code is unknown.
+    pub fn from_exit_code(code: i32) -> Self {
+        match code {
+            0 => HealthStatus::Ok,
+            1 => HealthStatus::Warning,
+            2 => HealthStatus::Critical,
+            _ => HealthStatus::Unknown,
+        }
+    }
+}
+
+/// Something which happened to a package or service.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    PackageInstalled {
+        #[serde(with = "ident_string")]
+        ident:  PackageIdent,
+        target: PackageTarget,
+    },
+    ServiceStateChanged {
+        service_group: ServiceGroup,
+        #[serde(with = "ident_string")]
+        ident:         PackageIdent,
+        from:          ServiceState,
+        to:            ServiceState,
+    },
+    HealthCheck {
+        service_group: ServiceGroup,
+        status:        HealthStatus,
+        /// Output from the health check hook, if any.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        output:        Option<String>,
+    },
+}
+
+/// An event as written to a stream.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Envelope {
+    pub schema_version: u32,
+    /// When the event was emitted, in RFC 3339 format.
+    pub timestamp:      String,
+    #[serde(flatten)]
+    pub event:          Event,
+}
+
+impl Envelope {
+    /// Wraps `event` with the current schema version and time.
+    pub fn new(event: Event) -> Self {
+        Envelope { schema_version: SCHEMA_VERSION,
+                   timestamp: time::now_utc().rfc3339().to_string(),
+                   event }
+    }
+
+    /// Serializes the envelope as a single line of JSON, including the trailing newline.
+    pub fn to_line(&self) -> Result<Vec<u8>> {
+        let mut line = serde_json::to_vec(self)?;
+        line.push(b'\n');
+        Ok(line)
+    }
+}
+
+/// Package identifiers are written in their `origin/name/version/release` string form, which
+/// is what consumers match against and display.
+pub(crate) mod ident_string {
+    use crate::package::PackageIdent;
+    use serde::{de::Error,
+                Deserialize,
+                Deserializer,
+                Serializer};
+
+    pub fn serialize<S>(ident: &PackageIdent, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_str(ident)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<PackageIdent, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn envelope_serializes_flat() {
+        let event =
+            Event::PackageInstalled { ident:  PackageIdent::from_str("core/redis/4.0.14/\
+                                                                      20190319155852").unwrap(),
+                                      target: PackageTarget::from_str("x86_64-linux").unwrap(), };
+        let envelope = Envelope { schema_version: SCHEMA_VERSION,
+                                  timestamp:      "2019-03-01T12:00:00Z".to_string(),
+                                  event:          event.clone(), };
+
+        let line = String::from_utf8(envelope.to_line().unwrap()).unwrap();
+
+        assert_eq!("{\"schema_version\":1,\"timestamp\":\"2019-03-01T12:00:00Z\",\"type\":\"\
+                    package_installed\",\"ident\":\"core/redis/4.0.14/20190319155852\",\"target\"\
+                    :\"x86_64-linux\"}\n",
+                   line);
+        let parsed: Envelope = serde_json::from_str(&line).unwrap();
+        assert_eq!(event, parsed.event);
+    }
+
+    #[test]
+    fn health_check_omits_missing_output() {
+        let event = Event::HealthCheck { service_group:
+                                             ServiceGroup::from_str("redis.default").unwrap(),
+                                         status:        HealthStatus::Critical,
+                                         output:        None, };
+
+        let value = serde_json::to_value(Envelope::new(event)).unwrap();
+
+        assert_eq!("health_check", value["type"]);
+        assert_eq!("critical", value["status"]);
+        assert!(value.get("output").is_none());
+    }
+}
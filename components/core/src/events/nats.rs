@@ -0,0 +1,432 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A minimal publisher for the NATS messaging system.
+//!
+//! Only what is needed to publish events is implemented: connecting with optional TLS and token
+//! authentication, publishing, and answering the server's keep-alive pings. Events can be
+//! published directly with `NatsPublisher::publish_event`, or through an `EventStream` using the
+//! writer returned by `NatsPublisher::into_writer` to get buffering and backpressure handling.
+
+use std::{io::{self,
+               BufRead,
+               BufReader,
+               Read,
+               Write},
+          net::TcpStream,
+          time::Duration};
+
+use native_tls::TlsStream;
+use serde_derive::{Deserialize,
+                   Serialize};
+use url::Url;
+
+use super::{Envelope,
+            Event};
+use crate::error::{Error,
+                   Result};
+
+pub use crate::tls::TlsOptions;
+
+/// The port NATS servers listen on by default.
+pub const DEFAULT_NATS_PORT: u16 = 4222;
+
+/// Options for `NatsPublisher::connect`.
+#[derive(Clone, Debug)]
+pub struct NatsOptions {
+    /// The server to connect to, as `nats://<HOST>:<PORT>` or `tls://<HOST>:<PORT>`. A `tls`
+    /// scheme requires TLS even if the server does not.
+    pub url:     String,
+    /// The token for servers which require token authentication.
+    pub token:   Option<String>,
+    /// TLS settings, used whenever the connection is upgraded to TLS.
+    pub tls:     TlsOptions,
+    /// The client name reported to the server, shown in its monitoring endpoints.
+    pub name:    String,
+    pub timeout: Duration,
+}
+
+impl Default for NatsOptions {
+    fn default() -> Self {
+        NatsOptions { url:     format!("nats://127.0.0.1:{}", DEFAULT_NATS_PORT),
+                      token:   None,
+                      tls:     TlsOptions::default(),
+                      name:    "habitat".to_string(),
+                      timeout: Duration::from_secs(10), }
+    }
+}
+
+/// The parts of the server's `INFO` message the publisher acts on.
+#[derive(Debug, Default, Deserialize)]
+struct ServerInfo {
+    #[serde(default)]
+    tls_required: bool,
+    #[serde(default)]
+    max_payload:  Option<usize>,
+}
+
+/// The client's `CONNECT` message.
+#[derive(Debug, Serialize)]
+struct Connect<'a> {
+    verbose:      bool,
+    pedantic:     bool,
+    tls_required: bool,
+    name:         &'a str,
+    lang:         &'a str,
+    version:      &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    auth_token:   Option<&'a str>,
+}
+
+enum Stream {
+    Plain(TcpStream),
+    Tls(TlsStream<TcpStream>),
+}
+
+impl Stream {
+    fn tcp(&self) -> &TcpStream {
+        match *self {
+            Stream::Plain(ref s) => s,
+            Stream::Tls(ref s) => s.get_ref(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Plain(ref mut s) => s.read(buf),
+            Stream::Tls(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Plain(ref mut s) => s.write(buf),
+            Stream::Tls(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Stream::Plain(ref mut s) => s.flush(),
+            Stream::Tls(ref mut s) => s.flush(),
+        }
+    }
+}
+
+/// A connection to a NATS server for publishing messages.
+pub struct NatsPublisher {
+    conn:        BufReader<Stream>,
+    /// A server line which was only partially read when the last poll timed out.
+    partial:     String,
+    max_payload: Option<usize>,
+}
+
+impl NatsPublisher {
+    /// Connects and authenticates to the server named in `opts`.
+    ///
+    /// # Failures
+    ///
+    /// * The server cannot be reached, or does not respond within the timeout
+    /// * The TLS handshake fails or the TLS settings cannot be loaded
+    /// * The server rejects the connection, such as for a bad token
+    pub fn connect(opts: &NatsOptions) -> Result<Self> {
+        let url = match Url::parse(&opts.url) {
+            Ok(url) => url,
+            Err(e) => {
+                return Err(Error::NatsError(format!("invalid server URL {}: {}", opts.url, e)));
+            }
+        };
+        let host = url.host_str()
+                      .ok_or_else(|| Error::NatsError(format!("no host in {}", opts.url)))?
+                      .to_string();
+        let port = url.port().unwrap_or(DEFAULT_NATS_PORT);
+        let want_tls = url.scheme() == "tls";
+
+        let tcp = TcpStream::connect((host.as_str(), port))?;
+        tcp.set_read_timeout(Some(opts.timeout))?;
+        tcp.set_write_timeout(Some(opts.timeout))?;
+        let mut conn = BufReader::new(Stream::Plain(tcp));
+
+        // The server greets every connection with an INFO message, in plain text
+        let line = read_line(&mut conn)?;
+        let info: ServerInfo = if line.starts_with("INFO ") {
+            serde_json::from_str(&line[5..])?
+        } else {
+            let msg = format!("expected INFO from server, got {:?}", line);
+            return Err(Error::NatsError(msg));
+        };
+
+        let tls_required = want_tls || info.tls_required;
+        if tls_required {
+            let tcp = match conn.into_inner() {
+                Stream::Plain(tcp) => tcp,
+                Stream::Tls(_) => unreachable!("connection is not yet upgraded"),
+            };
+            let stream = match opts.tls.connector()?.connect(&host, tcp) {
+                Ok(stream) => stream,
+                Err(e) => return Err(Error::NatsError(format!("TLS handshake failed: {}", e))),
+            };
+            conn = BufReader::new(Stream::Tls(stream));
+        }
+
+        let connect = Connect { verbose: false,
+                                pedantic: false,
+                                tls_required,
+                                name: &opts.name,
+                                lang: "rust",
+                                version: env!("CARGO_PKG_VERSION"),
+                                auth_token: opts.token.as_ref().map(String::as_str) };
+        let mut handshake = b"CONNECT ".to_vec();
+        serde_json::to_writer(&mut handshake, &connect)?;
+        handshake.extend_from_slice(b"\r\nPING\r\n");
+        conn.get_mut().write_all(&handshake)?;
+
+        // The server answers a PING sent straight after CONNECT with PONG once the connection is
+        // accepted, or -ERR if it is not
+        loop {
+            let line = read_line(&mut conn)?;
+            if line == "PONG" {
+                break;
+            } else if line.starts_with("-ERR") {
+                return Err(Error::NatsError(line[4..].trim().to_string()));
+            } else if line == "PING" {
+                conn.get_mut().write_all(b"PONG\r\n")?;
+            }
+        }
+        Ok(NatsPublisher { conn,
+                           partial: String::new(),
+                           max_payload: info.max_payload })
+    }
+
+    /// Publishes `payload` to `subject`.
+    ///
+    /// # Failures
+    ///
+    /// * The payload is larger than the server allows
+    /// * The connection fails or the server reports an error
+    pub fn publish(&mut self, subject: &str, payload: &[u8]) -> Result<()> {
+        if subject.is_empty() || subject.chars().any(char::is_whitespace) {
+            return Err(Error::NatsError(format!("invalid subject {:?}", subject)));
+        }
+        if let Some(max) = self.max_payload {
+            if payload.len() > max {
+                return Err(Error::NatsError(format!("payload of {} bytes exceeds \
+                                                     the server's maximum of {}",
+                                                    payload.len(),
+                                                    max)));
+            }
+        }
+        let mut msg = format!("PUB {} {}\r\n", subject, payload.len()).into_bytes();
+        msg.extend_from_slice(payload);
+        msg.extend_from_slice(b"\r\n");
+        self.conn.get_mut().write_all(&msg)?;
+        self.poll()
+    }
+
+    /// Publishes `event` to `subject` as a JSON object.
+    ///
+    /// # Failures
+    ///
+    /// * The event cannot be serialized
+    /// * The event cannot be published
+    pub fn publish_event(&mut self, subject: &str, event: Event) -> Result<()> {
+        let line = Envelope::new(event).to_line()?;
+        self.publish(subject, &line[..line.len() - 1])
+    }
+
+    /// Converts the publisher into a writer which publishes each line written to it to
+    /// `subject`, for use with `EventStream::from_writer`.
+    pub fn into_writer<S: Into<String>>(self, subject: S) -> NatsWriter {
+        NatsWriter { publisher: self,
+                     subject:   subject.into(),
+                     buf:       Vec::new(), }
+    }
+
+    /// Handles anything the server has sent since the last poll without waiting for more:
+    /// answering pings, which the server sends to detect dead clients, and surfacing errors.
+    fn poll(&mut self) -> Result<()> {
+        let timeout = self.conn.get_ref().tcp().read_timeout()?;
+        self.conn
+            .get_ref()
+            .tcp()
+            .set_read_timeout(Some(Duration::from_millis(1)))?;
+        let result = self.drain();
+        self.conn.get_ref().tcp().set_read_timeout(timeout)?;
+        result
+    }
+
+    fn drain(&mut self) -> Result<()> {
+        loop {
+            match self.conn.read_line(&mut self.partial) {
+                Ok(0) => {
+                    return Err(Error::NatsError("connection closed by server".to_string()));
+                }
+                Ok(_) => {
+                    let line = self.partial.trim_end().to_string();
+                    self.partial.clear();
+                    if line == "PING" {
+                        self.conn.get_mut().write_all(b"PONG\r\n")?;
+                    } else if line.starts_with("-ERR") {
+                        return Err(Error::NatsError(line[4..].trim().to_string()));
+                    }
+                }
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                       || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Ok(());
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Publishes each line written to it as a message. Produced by `NatsPublisher::into_writer`.
+pub struct NatsWriter {
+    publisher: NatsPublisher,
+    subject:   String,
+    buf:       Vec<u8>,
+}
+
+impl Write for NatsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            self.publisher
+                .publish(&self.subject, &line[..end])
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.publisher.conn.get_mut().flush() }
+}
+
+fn read_line<R: BufRead>(conn: &mut R) -> Result<String> {
+    let mut line = String::new();
+    if conn.read_line(&mut line)? == 0 {
+        return Err(Error::NatsError("connection closed by server".to_string()));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{events::HealthStatus,
+                service::ServiceGroup};
+    use std::{net::TcpListener,
+              str::FromStr,
+              thread};
+
+    /// Accepts one client, expecting `token`, and returns every line the client sent.
+    fn fake_server(token: Option<&'static str>) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("nats://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            writer.write_all(b"INFO {\"server_id\":\"test\",\"max_payload\":1048576}\r\n")
+                  .unwrap();
+            let mut lines = Vec::new();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    return lines;
+                }
+                let line = line.trim_end().to_string();
+                if line.starts_with("CONNECT ") {
+                    let connect: serde_json::Value = serde_json::from_str(&line[8..]).unwrap();
+                    if connect["auth_token"].as_str() != token {
+                        writer.write_all(b"-ERR 'Authorization Violation'\r\n")
+                              .unwrap();
+                        return lines;
+                    }
+                } else if line == "PING" {
+                    writer.write_all(b"PONG\r\n").unwrap();
+                }
+                lines.push(line);
+            }
+        });
+        (url, handle)
+    }
+
+    fn health_check() -> Event {
+        Event::HealthCheck { service_group: ServiceGroup::from_str("redis.default").unwrap(),
+                             status:        HealthStatus::Ok,
+                             output:        None, }
+    }
+
+    #[test]
+    fn publishes_events() {
+        let (url, server) = fake_server(Some("s3cr3t"));
+        let opts = NatsOptions { url,
+                                 token: Some("s3cr3t".to_string()),
+                                 ..Default::default() };
+
+        let mut publisher = NatsPublisher::connect(&opts).unwrap();
+        publisher.publish("habitat.test", b"hello").unwrap();
+        publisher.publish_event("habitat.event", health_check())
+                 .unwrap();
+        drop(publisher);
+
+        let lines = server.join().unwrap();
+        assert!(lines[0].starts_with("CONNECT "));
+        assert_eq!("PING", lines[1]);
+        assert_eq!("PUB habitat.test 5", lines[2]);
+        assert_eq!("hello", lines[3]);
+        assert!(lines[4].starts_with("PUB habitat.event "));
+        let envelope: Envelope = serde_json::from_str(&lines[5]).unwrap();
+        assert_eq!(health_check(), envelope.event);
+    }
+
+    #[test]
+    fn writer_publishes_each_line() {
+        let (url, server) = fake_server(None);
+        let opts = NatsOptions { url,
+                                 ..Default::default() };
+
+        let mut writer = NatsPublisher::connect(&opts).unwrap()
+                                                      .into_writer("habitat.lines");
+        writer.write_all(b"one\ntw").unwrap();
+        writer.write_all(b"o\n").unwrap();
+        drop(writer);
+
+        let lines = server.join().unwrap();
+        assert_eq!(vec!["PUB habitat.lines 3", "one", "PUB habitat.lines 3", "two"],
+                   lines[2..].to_vec());
+    }
+
+    #[test]
+    fn connect_rejected_with_bad_token() {
+        let (url, server) = fake_server(Some("s3cr3t"));
+        let opts = NatsOptions { url,
+                                 token: Some("wrong".to_string()),
+                                 ..Default::default() };
+
+        match NatsPublisher::connect(&opts) {
+            Err(Error::NatsError(msg)) => assert_eq!("'Authorization Violation'", msg),
+            Err(e) => panic!("Expected NatsError, got {:?}", e),
+            Ok(_) => panic!("Expected connect to fail"),
+        }
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,297 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::{fs::OpenOptions,
+          io::{BufWriter,
+               Write},
+          net::TcpStream,
+          path::PathBuf,
+          sync::{atomic::{AtomicUsize,
+                          Ordering},
+                 mpsc::{self,
+                        Receiver,
+                        SyncSender,
+                        TrySendError},
+                 Arc},
+          thread::{self,
+                   JoinHandle}};
+
+use super::{Envelope,
+            Event};
+use crate::error::{Error,
+                   Result};
+
+/// Where an `EventStream` writes its events.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Destination {
+    /// A file, which is appended to and created if it does not exist.
+    File(PathBuf),
+    /// A `<HOST>:<PORT>` address to connect to over TCP.
+    Tcp(String),
+    /// The path of a Unix stream socket to connect to.
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// What `EventStream::emit` does when the stream's buffer is full because the destination is
+/// not keeping up.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Backpressure {
+    /// Wait for room in the buffer, slowing the emitter to the pace of the destination.
+    Block,
+    /// Discard the event, counting it in `EventStream::dropped`. Emitters are never slowed, at
+    /// the cost of gaps in the stream.
+    Drop,
+}
+
+/// Options for an `EventStream`.
+#[derive(Clone, Copy, Debug)]
+pub struct StreamOptions {
+    /// The number of events buffered between emitters and the destination.
+    pub capacity:     usize,
+    pub backpressure: Backpressure,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        StreamOptions { capacity:     1024,
+                        backpressure: Backpressure::Drop, }
+    }
+}
+
+/// Writes events as JSON lines to a destination from a background thread.
+///
+/// Events are serialized by the emitting thread and buffered until the background thread
+/// writes them, so a slow destination only affects emitters according to the stream's
+/// `Backpressure`. If writing to the destination fails the background thread logs the error and
+/// stops, and subsequent emits fail with `Error::EventStreamClosed`. Dropping the stream waits
+/// for buffered events to be written.
+#[derive(Debug)]
+pub struct EventStream {
+    tx:           Option<SyncSender<Vec<u8>>>,
+    backpressure: Backpressure,
+    dropped:      Arc<AtomicUsize>,
+    writer:       Option<JoinHandle<()>>,
+}
+
+impl EventStream {
+    /// Opens `destination` and starts writing events to it.
+    ///
+    /// # Failures
+    ///
+    /// * The file cannot be opened or the socket cannot be connected
+    pub fn open(destination: &Destination, opts: StreamOptions) -> Result<Self> {
+        let stream = match *destination {
+            Destination::File(ref path) => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                Self::from_writer(file, opts)
+            }
+            Destination::Tcp(ref addr) => {
+                Self::from_writer(TcpStream::connect(addr.as_str())?, opts)
+            }
+            #[cfg(unix)]
+            Destination::Unix(ref path) => Self::from_writer(UnixStream::connect(path)?, opts),
+        };
+        Ok(stream)
+    }
+
+    /// Starts writing events to `writer`.
+    pub fn from_writer<W>(writer: W, opts: StreamOptions) -> Self
+        where W: Write + Send + 'static
+    {
+        let (tx, rx) = mpsc::sync_channel(opts.capacity);
+        let writer = thread::Builder::new().name("event-stream".to_string())
+                                           .spawn(move || write_events(writer, &rx))
+                                           .expect("Unable to start event stream thread");
+        EventStream { tx:           Some(tx),
+                      backpressure: opts.backpressure,
+                      dropped:      Arc::new(AtomicUsize::new(0)),
+                      writer:       Some(writer), }
+    }
+
+    /// Emits `event`, stamped with the current schema version and time.
+    ///
+    /// # Failures
+    ///
+    /// * The event cannot be serialized
+    /// * The stream has stopped writing because its destination failed
+    pub fn emit(&self, event: Event) -> Result<()> {
+        let line = Envelope::new(event).to_line()?;
+        let tx = self.tx
+                     .as_ref()
+                     .expect("Event stream sender is only taken on drop");
+        match self.backpressure {
+            Backpressure::Block => tx.send(line).map_err(|_| Error::EventStreamClosed),
+            Backpressure::Drop => {
+                match tx.try_send(line) {
+                    Ok(()) => Ok(()),
+                    Err(TrySendError::Full(_)) => {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        Ok(())
+                    }
+                    Err(TrySendError::Disconnected(_)) => Err(Error::EventStreamClosed),
+                }
+            }
+        }
+    }
+
+    /// The number of events discarded because the buffer was full.
+    pub fn dropped(&self) -> usize { self.dropped.load(Ordering::Relaxed) }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        // Closing the channel lets the writer finish the buffered events and exit
+        self.tx.take();
+        if let Some(writer) = self.writer.take() {
+            if writer.join().is_err() {
+                error!("Event stream writer panicked");
+            }
+        }
+    }
+}
+
+fn write_events<W: Write>(writer: W, rx: &Receiver<Vec<u8>>) {
+    let mut writer = BufWriter::new(writer);
+    while let Ok(line) = rx.recv() {
+        let mut result = writer.write_all(&line);
+        // Write everything which is already waiting before flushing
+        while result.is_ok() {
+            match rx.try_recv() {
+                Ok(line) => result = writer.write_all(&line),
+                Err(_) => break,
+            }
+        }
+        if let Err(e) = result.and_then(|_| writer.flush()) {
+            error!("Unable to write to event stream, no further events will be written: {}",
+                   e);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{events::{HealthStatus,
+                         SCHEMA_VERSION},
+                service::ServiceGroup};
+    use std::{fs,
+              io,
+              str::FromStr,
+              sync::{Condvar,
+                     Mutex}};
+    use tempfile::Builder;
+
+    fn health_check() -> Event {
+        Event::HealthCheck { service_group: ServiceGroup::from_str("redis.default").unwrap(),
+                             status:        HealthStatus::Ok,
+                             output:        None, }
+    }
+
+    /// A writer which blocks until released.
+    #[derive(Clone)]
+    struct Gate(Arc<(Mutex<bool>, Condvar)>);
+
+    impl Gate {
+        fn new() -> Self { Gate(Arc::new((Mutex::new(false), Condvar::new()))) }
+
+        fn open(&self) {
+            *(self.0).0.lock().unwrap() = true;
+            (self.0).1.notify_all();
+        }
+    }
+
+    impl Write for Gate {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut open = (self.0).0.lock().unwrap();
+            while !*open {
+                open = (self.0).1.wait(open).unwrap();
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn writes_json_lines_to_file() {
+        let dir = Builder::new().prefix("events").tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+
+        let stream =
+            EventStream::open(&Destination::File(path.clone()), StreamOptions::default()).unwrap();
+        stream.emit(health_check()).unwrap();
+        stream.emit(health_check()).unwrap();
+        drop(stream);
+
+        let content = fs::read_to_string(&path).unwrap();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(2, lines.len());
+        for line in lines {
+            let envelope: Envelope = serde_json::from_str(line).unwrap();
+            assert_eq!(SCHEMA_VERSION, envelope.schema_version);
+            assert_eq!(health_check(), envelope.event);
+        }
+    }
+
+    #[test]
+    fn drops_events_when_full() {
+        let gate = Gate::new();
+        let opts = StreamOptions { capacity:     1,
+                                   backpressure: Backpressure::Drop, };
+        let stream = EventStream::from_writer(gate.clone(), opts);
+
+        // Whichever event the writer picks up blocks it; at most one more fits in the buffer
+        for _ in 0..10 {
+            stream.emit(health_check()).unwrap();
+        }
+        assert!(stream.dropped() >= 8);
+
+        gate.open();
+    }
+
+    #[test]
+    fn emit_fails_once_writer_stops() {
+        let stream = EventStream::from_writer(Broken,
+                                              StreamOptions { capacity:     1,
+                                                              backpressure: Backpressure::Block, });
+        stream.emit(health_check()).unwrap();
+
+        // The writer fails on the first event, after which the channel is closed
+        let mut result = Ok(());
+        for _ in 0..10 {
+            result = stream.emit(health_check());
+            if result.is_err() {
+                break;
+            }
+        }
+        match result {
+            Err(Error::EventStreamClosed) => {}
+            other => panic!("Expected EventStreamClosed, got {:?}", other),
+        }
+    }
+}
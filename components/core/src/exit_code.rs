@@ -0,0 +1,334 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Stable process exit codes for the failures core reports, so that automation wrapping `hab`
+//! or the Supervisor can branch on why a command failed without parsing its output.
+//!
+//! | Code | Name                | Meaning                                                       |
+//! |------|---------------------|---------------------------------------------------------------|
+//! | 0    | `Success`           | The command succeeded                                         |
+//! | 1    | `Failure`           | A failure which falls in none of the categories below         |
+//! | 2    | `InvalidInput`      | An argument, identifier or other input could not be parsed    |
+//! | 3    | `Config`            | A configuration file, template or plan is missing or invalid  |
+//! | 4    | `NotFound`          | A package, metafile or other file does not exist              |
+//! | 5    | `PermissionDenied`  | The caller lacks a permission, privilege or admission         |
+//! | 6    | `Conflict`          | Something else holds a lock, port, hold or newer state        |
+//! | 7    | `InvalidPackage`    | A package or archive is malformed or for another target       |
+//! | 8    | `Crypto`            | A key, signature or keyring operation failed                  |
+//! | 9    | `ResourceExhausted` | The system is out of a resource, such as disk space           |
+//! | 10   | `Unavailable`       | A network peer or service could not be reached or used        |
+//! | 11   | `TimedOut`          | An operation did not finish in the time allowed               |
+//! | 12   | `System`            | An operating system call or IO operation failed               |
+//! | 13   | `Cancelled`         | The operation was cancelled before it finished                |
+//!
+//! These values are part of core's interface: a code, once assigned, keeps its meaning across
+//! releases, and new categories are only ever given new codes. Codes 126 and above are left
+//! alone, as shells use them to report commands which could not be run or were signaled.
+//!
+//! ```no_run
+//! use habitat_core::{exit_code::ExitCode,
+//!                    package::{PackageIdent,
+//!                              PackageInstall}};
+//! use std::str::FromStr;
+//!
+//! let ident = PackageIdent::from_str("core/redis").unwrap();
+//! if let Err(e) = PackageInstall::load(&ident, None) {
+//!     eprintln!("{}", e);
+//!     ExitCode::from(&e).exit();
+//! }
+//! ```
+
+use std::{fmt,
+          io,
+          process};
+
+use crate::error::Error;
+
+/// The category of a failure, as the exit code a process reports for it.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ExitCode {
+    Success           = 0,
+    Failure           = 1,
+    InvalidInput      = 2,
+    Config            = 3,
+    NotFound          = 4,
+    PermissionDenied  = 5,
+    Conflict          = 6,
+    InvalidPackage    = 7,
+    Crypto            = 8,
+    ResourceExhausted = 9,
+    Unavailable       = 10,
+    TimedOut          = 11,
+    System            = 12,
+    Cancelled         = 13,
+}
+
+impl ExitCode {
+    /// Every exit code, in order of value.
+    pub const ALL: [ExitCode; 14] = [ExitCode::Success,
+                                     ExitCode::Failure,
+                                     ExitCode::InvalidInput,
+                                     ExitCode::Config,
+                                     ExitCode::NotFound,
+                                     ExitCode::PermissionDenied,
+                                     ExitCode::Conflict,
+                                     ExitCode::InvalidPackage,
+                                     ExitCode::Crypto,
+                                     ExitCode::ResourceExhausted,
+                                     ExitCode::Unavailable,
+                                     ExitCode::TimedOut,
+                                     ExitCode::System,
+                                     ExitCode::Cancelled];
+
+    /// The value to pass to `std::process::exit`.
+    pub fn code(self) -> i32 { self as i32 }
+
+    /// Returns the exit code with the given value, if there is one.
+    pub fn from_code(code: i32) -> Option<ExitCode> {
+        Self::ALL.iter().cloned().find(|c| c.code() == code)
+    }
+
+    /// A one line description of the failures which exit with this code.
+    pub fn description(self) -> &'static str {
+        match self {
+            ExitCode::Success => "The command succeeded",
+            ExitCode::Failure => "The command failed",
+            ExitCode::InvalidInput => "Invalid input",
+            ExitCode::Config => "Invalid or missing configuration",
+            ExitCode::NotFound => "Not found",
+            ExitCode::PermissionDenied => "Permission denied",
+            ExitCode::Conflict => "Conflicts with a lock, hold or current state",
+            ExitCode::InvalidPackage => "Invalid package",
+            ExitCode::Crypto => "Cryptographic operation failed",
+            ExitCode::ResourceExhausted => "Resource exhausted",
+            ExitCode::Unavailable => "Service or network peer unavailable",
+            ExitCode::TimedOut => "Timed out",
+            ExitCode::System => "Operating system or IO failure",
+            ExitCode::Cancelled => "Cancelled",
+        }
+    }
+
+    /// Exits the current process with this code.
+    pub fn exit(self) -> ! { process::exit(self.code()) }
+}
+
+impl fmt::Display for ExitCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.code(), self.description())
+    }
+}
+
+impl From<&io::Error> for ExitCode {
+    fn from(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound => ExitCode::NotFound,
+            io::ErrorKind::PermissionDenied => ExitCode::PermissionDenied,
+            io::ErrorKind::AddrInUse | io::ErrorKind::AlreadyExists => ExitCode::Conflict,
+            io::ErrorKind::TimedOut => ExitCode::TimedOut,
+            io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::AddrNotAvailable => ExitCode::Unavailable,
+            io::ErrorKind::InvalidInput => ExitCode::InvalidInput,
+            _ => ExitCode::System,
+        }
+    }
+}
+
+// Every variant is listed, rather than falling through to `Failure`, so that a new variant has to
+// be given a category when it is added.
+impl From<&Error> for ExitCode {
+    fn from(err: &Error) -> Self {
+        match *err {
+            Error::BadBindingMode(_)
+            | Error::BadUpdateStrategy(_)
+            | Error::BadUpdateCondition(_)
+            | Error::IncompatibleUpdateCondition(..)
+            | Error::BadTopology(_)
+            | Error::BadSignal(_)
+            | Error::BadVerifyLevel(_)
+            | Error::BadKeyPath(_)
+            | Error::CompositePackageExpected(_)
+            | Error::FullyQualifiedPackageIdentRequired(_)
+            | Error::InvalidApplicationEnvironment(_)
+            | Error::InvalidBinding(_)
+            | Error::InvalidLogSink(_)
+            | Error::InvalidReadLimits(_)
+            | Error::InvalidRestartPolicy(_)
+            | Error::InvalidStalenessPolicy(_)
+            | Error::InvalidPackageIdent(_)
+            | Error::MalformedPackageIdent(_)
+            | Error::InvalidPackageTarget(_)
+            | Error::InvalidPackageType(_)
+            | Error::InvalidServiceFile(..)
+            | Error::InvalidServiceMetadata(..)
+            | Error::InvalidServiceGroup(_)
+            | Error::InvalidOrigin(_)
+            | Error::InvalidPathString(_)
+            | Error::JoinPathsError(_)
+            | Error::ParseIntError(_)
+            | Error::RegexParse(_)
+            | Error::StringFromUtf8Error(_)
+            | Error::Utf8Error(_) => ExitCode::InvalidInput,
+
+            Error::ConfigEnvInvalid(..)
+            | Error::ConfigEnvVarNotUnicode(_)
+            | Error::ConfigFileIO(..)
+            | Error::ConfigFileSyntax(_)
+            | Error::ConfigInvalidArraySocketAddr(_)
+            | Error::ConfigInvalidArrayTableString(_)
+            | Error::ConfigInvalidArrayTarget(_)
+            | Error::ConfigInvalidArrayU16(_)
+            | Error::ConfigInvalidArrayU32(_)
+            | Error::ConfigInvalidArrayU64(_)
+            | Error::ConfigInvalidBool(_)
+            | Error::ConfigInvalidIdent(_)
+            | Error::ConfigInvalidIpAddr(_)
+            | Error::ConfigInvalidSocketAddr(_)
+            | Error::ConfigInvalidString(_)
+            | Error::ConfigInvalidTableString(_)
+            | Error::ConfigInvalidTarget(_)
+            | Error::ConfigInvalidU16(_)
+            | Error::ConfigInvalidU32(_)
+            | Error::ConfigInvalidU64(_)
+            | Error::ConfigInvalidUsize(_)
+            | Error::InvalidSeccompProfile(_)
+            | Error::InvalidServiceSpec(_)
+            | Error::PlanMalformed
+            | Error::RuntimeEnvironmentCycle(_)
+            | Error::TemplateError(_)
+            | Error::TemplateFileError(_)
+            | Error::TemplateRenderError(_) => ExitCode::Config,
+
+            Error::FileNotFound(_) | Error::MetaFileNotFound(_) | Error::PackageNotFound(_) => {
+                ExitCode::NotFound
+            }
+
+            Error::AdmissionDenied(..)
+            | Error::LogonTypeNotGranted
+            | Error::PermissionDenied(_)
+            | Error::PermissionFailed(_)
+            | Error::PrivilegeNotHeld => ExitCode::PermissionDenied,
+
+            Error::InvalidStateTransition(..)
+            | Error::PackageHeld(..)
+            | Error::PackageInUse(..)
+            | Error::PortUnavailable(..)
+            | Error::StaleConfigIncarnation(..)
+            | Error::StaleFileIncarnation(..)
+            | Error::SupervisorLocked(..)
+            | Error::SupervisorLockUnreadable(_) => ExitCode::Conflict,
+
+            Error::ArchiveError(_)
+            | Error::MetaFileBadBind
+            | Error::MetaFileMalformed(_)
+            | Error::MetaFileTooLarge(..)
+            | Error::KeyValueMalformed(_)
+            | Error::PackageUnpackFailed(_)
+            | Error::TargetMatchError(_)
+            | Error::UnsafeArchive(..)
+            | Error::ArtifactMismatch(..)
+            | Error::IntegrityCheckFailed(..)
+            | Error::WrongActivePackageTarget(..) => ExitCode::InvalidPackage,
+
+            Error::ArtifactHeaderTooLarge(_)
+            | Error::ArtifactSignerMismatch(..)
+            | Error::CryptoError(_)
+            | Error::MirrorSignerMismatch(..)
+            | Error::CryptProtectDataFailed(_)
+            | Error::CryptUnprotectDataFailed(_)
+            | Error::KeyringUnavailable(_) => ExitCode::Crypto,
+
+            Error::InsufficientDiskSpace(..) => ExitCode::ResourceExhausted,
+
+            Error::OperationTimedOut(..) => ExitCode::TimedOut,
+
+            Error::Cancelled => ExitCode::Cancelled,
+
+            Error::InsufficientMembers(..)
+            | Error::InvalidGossipMessage(_)
+            | Error::NatsError(_)
+            | Error::NoOutboundAddr
+            | Error::ProbeFailed(_)
+            | Error::TlsError(_) => ExitCode::Unavailable,
+
+            Error::IO(ref e) | Error::MetaFileIO(ref e) => ExitCode::from(e),
+
+            Error::CreateProcessAsUserFailed(_)
+            | Error::CreateToolhelp32SnapshotFailed(_)
+            | Error::EventLogFailed(..)
+            | Error::FirewallCommandFailed(..)
+            | Error::GetExitCodeProcessFailed(_)
+            | Error::LogonUserFailed(_)
+            | Error::OpenDesktopFailed(_)
+            | Error::RegistryFailed(..)
+            | Error::ServiceControlManagerFailed(..)
+            | Error::SignalFailed(..)
+            | Error::TerminateProcessFailed(_)
+            | Error::UnameFailed(_)
+            | Error::WaitForSingleObjectFailed(_)
+            | Error::WaitpidFailed(_) => ExitCode::System,
+
+            Error::EventStreamClosed
+            | Error::InvalidRumorStore(..)
+            | Error::InvalidSuitability(_)
+            | Error::Json(_)
+            | Error::PackageHookFailed(..) => ExitCode::Failure,
+        }
+    }
+}
+
+impl From<Error> for ExitCode {
+    fn from(err: Error) -> Self { ExitCode::from(&err) }
+}
+
+impl From<ExitCode> for i32 {
+    fn from(code: ExitCode) -> Self { code.code() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::PackageIdent;
+    use std::str::FromStr;
+
+    #[test]
+    fn codes_are_stable() {
+        for (value, code) in ExitCode::ALL.iter().enumerate() {
+            assert_eq!(value as i32, code.code());
+            assert_eq!(Some(*code), ExitCode::from_code(value as i32));
+        }
+        assert_eq!(None, ExitCode::from_code(14));
+        assert_eq!(None, ExitCode::from_code(-1));
+        assert_eq!("4 (Not found)", ExitCode::NotFound.to_string());
+    }
+
+    #[test]
+    fn errors_map_to_codes() {
+        let ident = PackageIdent::from_str("core/redis").unwrap();
+        assert_eq!(ExitCode::NotFound,
+                   ExitCode::from(Error::PackageNotFound(ident)));
+        assert_eq!(ExitCode::InvalidInput,
+                   ExitCode::from(&Error::InvalidPackageIdent("core".to_string())));
+        assert_eq!(ExitCode::Conflict,
+                   ExitCode::from(&Error::PortUnavailable(9631, None)));
+        assert_eq!(4,
+                   i32::from(ExitCode::from(&Error::IO(io::Error::from(io::ErrorKind::NotFound)))));
+        assert_eq!(ExitCode::PermissionDenied,
+                   ExitCode::from(&Error::MetaFileIO(io::Error::from(io::ErrorKind::PermissionDenied))));
+        assert_eq!(ExitCode::System,
+                   ExitCode::from(&Error::IO(io::Error::from(io::ErrorKind::BrokenPipe))));
+    }
+}
@@ -0,0 +1,443 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A C ABI for package identifier parsing, version comparison and reading the header of a `.hart`
+//! file, for tools not written in Rust which would otherwise shell out to `hab` for them.
+//!
+//! Built with the `ffi` feature; a shared library comes from
+//! `cargo rustc --lib --release --features ffi -- --crate-type cdylib`. The declarations for C
+//! are:
+//!
+//! ```c
+//! #define HAB_OK 0
+//! #define HAB_ERR_NULL_ARGUMENT 1
+//! #define HAB_ERR_INVALID_STRING 2
+//! #define HAB_ERR_FAILED 3
+//! #define HAB_ERR_PANIC 4
+//!
+//! typedef struct {
+//!     char *origin;
+//!     char *name;
+//!     char *version; /* NULL when not given */
+//!     char *release; /* NULL when not given */
+//! } hab_ident;
+//!
+//! typedef struct {
+//!     char *format_version;
+//!     char *key_name;
+//!     char *hash_type;
+//!     char *ident;
+//!     char *target;
+//! } hab_archive_header;
+//!
+//! int hab_ident_parse(const char *input, hab_ident *out);
+//! void hab_ident_free(hab_ident *ident);
+//! int hab_version_cmp(const char *a, const char *b, int *out);
+//! int hab_archive_header(const char *path, hab_archive_header *out);
+//! void hab_archive_header_free(hab_archive_header *header);
+//! const char *hab_last_error(void);
+//! ```
+//!
+//! Every function returning an `int` returns `HAB_OK` or one of the `HAB_ERR_*` codes, and only
+//! writes through its `out` pointer on success. After a failure, `hab_last_error` describes what
+//! went wrong; the string it returns belongs to the library and is good until the next call on
+//! the same thread. Strings handed out in `hab_ident` and `hab_archive_header` belong to the
+//! caller, who gives them back with the matching `_free` function. All strings are UTF-8.
+//!
+//! These names, codes and struct layouts are kept as they are; anything new comes as new
+//! functions.
+
+use crate::{crypto::artifact,
+            error::Error,
+            package::{ident,
+                      PackageArchive,
+                      PackageIdent}};
+use std::{cell::RefCell,
+          cmp::Ordering,
+          ffi::{CStr,
+                CString},
+          fmt,
+          os::raw::{c_char,
+                    c_int},
+          panic::{self,
+                  AssertUnwindSafe},
+          ptr,
+          str::FromStr};
+
+/// The call succeeded.
+pub const HAB_OK: c_int = 0;
+/// A pointer argument was `NULL`.
+pub const HAB_ERR_NULL_ARGUMENT: c_int = 1;
+/// A string argument was not UTF-8, or a string to hand back held a NUL byte.
+pub const HAB_ERR_INVALID_STRING: c_int = 2;
+/// The arguments were readable but the operation failed, such as on a malformed identifier.
+pub const HAB_ERR_FAILED: c_int = 3;
+/// The library panicked; this is a bug in Habitat.
+pub const HAB_ERR_PANIC: c_int = 4;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+/// A package identifier as handed to C; `version` and `release` are `NULL` when absent.
+#[repr(C)]
+#[derive(Debug)]
+pub struct HabIdent {
+    pub origin:  *mut c_char,
+    pub name:    *mut c_char,
+    pub version: *mut c_char,
+    pub release: *mut c_char,
+}
+
+/// The signing header of a `.hart` file, with the identifier and target of the package in it.
+#[repr(C)]
+#[derive(Debug)]
+pub struct HabArchiveHeader {
+    pub format_version: *mut c_char,
+    pub key_name:       *mut c_char,
+    pub hash_type:      *mut c_char,
+    pub ident:          *mut c_char,
+    pub target:         *mut c_char,
+}
+
+/// Parses `input` as a fully or partially qualified package identifier, such as
+/// `core/redis/4.0.14`, into `out`.
+///
+/// # Safety
+///
+/// `input` must be `NULL` or a NUL-terminated string, and `out` must be `NULL` or point to a
+/// `HabIdent` the caller may write to. On success `out` must later go to `hab_ident_free`.
+#[no_mangle]
+pub unsafe extern "C" fn hab_ident_parse(input: *const c_char, out: *mut HabIdent) -> c_int {
+    call(|| {
+        let input = str_arg(input, "input")?;
+        let out = out_arg(out, "out")?;
+        let ident = PackageIdent::from_str(input)?;
+        let version = optional_string(ident.version.as_ref())?;
+        let release = optional_string(ident.release.as_ref())?;
+        let origin = CString::new(ident.origin).map_err(|_| Failure::Nul("origin"))?;
+        let name = CString::new(ident.name).map_err(|_| Failure::Nul("name"))?;
+        *out = HabIdent { origin:  origin.into_raw(),
+                          name:    name.into_raw(),
+                          version: into_raw(version),
+                          release: into_raw(release), };
+        Ok(())
+    })
+}
+
+/// Frees the strings of an identifier filled in by `hab_ident_parse` and sets them to `NULL`,
+/// so that freeing it again does nothing.
+///
+/// # Safety
+///
+/// `ident` must be `NULL` or point to a `HabIdent` whose strings are each `NULL` or were
+/// handed out by `hab_ident_parse`.
+#[no_mangle]
+pub unsafe extern "C" fn hab_ident_free(ident: *mut HabIdent) {
+    if let Some(ident) = ident.as_mut() {
+        free_string(&mut ident.origin);
+        free_string(&mut ident.name);
+        free_string(&mut ident.version);
+        free_string(&mut ident.release);
+    }
+}
+
+/// Compares two package versions the way Habitat orders them, writing `-1`, `0` or `1` to `out`
+/// as `a` is older than, the same as or newer than `b`.
+///
+/// # Safety
+///
+/// `a` and `b` must be `NULL` or NUL-terminated strings, and `out` must be `NULL` or point to an
+/// `int` the caller may write to.
+#[no_mangle]
+pub unsafe extern "C" fn hab_version_cmp(a: *const c_char,
+                                         b: *const c_char,
+                                         out: *mut c_int)
+                                         -> c_int {
+    call(|| {
+        let a = str_arg(a, "a")?;
+        let b = str_arg(b, "b")?;
+        let out = out_arg(out, "out")?;
+        *out = match ident::version_sort(a, b)? {
+            Ordering::Less => -1,
+            Ordering::Equal => 0,
+            Ordering::Greater => 1,
+        };
+        Ok(())
+    })
+}
+
+/// Reads the header of the `.hart` file at `path`, and the identifier and target of the package
+/// inside it, into `out`. The signature is not checked.
+///
+/// # Safety
+///
+/// `path` must be `NULL` or a NUL-terminated string, and `out` must be `NULL` or point to a
+/// `HabArchiveHeader` the caller may write to. On success `out` must later go to
+/// `hab_archive_header_free`.
+#[no_mangle]
+pub unsafe extern "C" fn hab_archive_header(path: *const c_char,
+                                            out: *mut HabArchiveHeader)
+                                            -> c_int {
+    call(|| {
+        let path = str_arg(path, "path")?;
+        let out = out_arg(out, "out")?;
+        let header = artifact::get_artifact_header(path)?;
+        let mut archive = PackageArchive::new(path);
+        let ident = archive.ident()?.to_string();
+        let target = archive.target()?.to_string();
+        let format_version =
+            CString::new(header.format_version).map_err(|_| Failure::Nul("format version"))?;
+        let key_name = CString::new(header.key_name).map_err(|_| Failure::Nul("key name"))?;
+        let hash_type = CString::new(header.hash_type).map_err(|_| Failure::Nul("hash type"))?;
+        let ident = CString::new(ident).map_err(|_| Failure::Nul("ident"))?;
+        let target = CString::new(target).map_err(|_| Failure::Nul("target"))?;
+        *out = HabArchiveHeader { format_version: format_version.into_raw(),
+                                  key_name:       key_name.into_raw(),
+                                  hash_type:      hash_type.into_raw(),
+                                  ident:          ident.into_raw(),
+                                  target:         target.into_raw(), };
+        Ok(())
+    })
+}
+
+/// Frees the strings of a header filled in by `hab_archive_header` and sets them to `NULL`, so
+/// that freeing it again does nothing.
+///
+/// # Safety
+///
+/// `header` must be `NULL` or point to a `HabArchiveHeader` whose strings are each `NULL` or
+/// were handed out by `hab_archive_header`.
+#[no_mangle]
+pub unsafe extern "C" fn hab_archive_header_free(header: *mut HabArchiveHeader) {
+    if let Some(header) = header.as_mut() {
+        free_string(&mut header.format_version);
+        free_string(&mut header.key_name);
+        free_string(&mut header.hash_type);
+        free_string(&mut header.ident);
+        free_string(&mut header.target);
+    }
+}
+
+/// Describes why the last call on this thread failed, or returns `NULL` if it succeeded.
+#[no_mangle]
+pub extern "C" fn hab_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+                  last.borrow()
+                      .as_ref()
+                      .map_or(ptr::null(), |message| message.as_ptr())
+              })
+}
+
+/// Why a call failed, before it is turned into a code and a message for `hab_last_error`.
+#[derive(Debug)]
+enum Failure {
+    Null(&'static str),
+    Utf8(&'static str),
+    Nul(&'static str),
+    Failed(String),
+}
+
+impl Failure {
+    fn code(&self) -> c_int {
+        match *self {
+            Failure::Null(_) => HAB_ERR_NULL_ARGUMENT,
+            Failure::Utf8(_) | Failure::Nul(_) => HAB_ERR_INVALID_STRING,
+            Failure::Failed(_) => HAB_ERR_FAILED,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Failure::Null(arg) => write!(f, "{} is NULL", arg),
+            Failure::Utf8(arg) => write!(f, "{} is not valid UTF-8", arg),
+            Failure::Nul(what) => write!(f, "The {} contains a NUL byte", what),
+            Failure::Failed(ref message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(err: Error) -> Self { Failure::Failed(err.to_string()) }
+}
+
+/// Runs the body of an exported function, recording its failure for `hab_last_error` and keeping
+/// any panic from unwinding into the caller.
+fn call<F>(f: F) -> c_int
+    where F: FnOnce() -> std::result::Result<(), Failure>
+{
+    let (code, message) = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => (HAB_OK, None),
+        Ok(Err(failure)) => (failure.code(), Some(failure.to_string())),
+        Err(_) => (HAB_ERR_PANIC, Some("habitat_core panicked".to_string())),
+    };
+    LAST_ERROR.with(|last| {
+                  *last.borrow_mut() =
+                      message.map(|m| CString::new(m.replace('\0', "")).expect("NULs removed"));
+              });
+    code
+}
+
+unsafe fn str_arg<'a>(ptr: *const c_char,
+                      name: &'static str)
+                      -> std::result::Result<&'a str, Failure> {
+    if ptr.is_null() {
+        return Err(Failure::Null(name));
+    }
+    CStr::from_ptr(ptr).to_str()
+                       .map_err(|_| Failure::Utf8(name))
+}
+
+unsafe fn out_arg<'a, T>(ptr: *mut T,
+                         name: &'static str)
+                         -> std::result::Result<&'a mut T, Failure> {
+    ptr.as_mut().ok_or(Failure::Null(name))
+}
+
+fn optional_string(value: Option<&String>) -> std::result::Result<Option<CString>, Failure> {
+    match value {
+        Some(value) => {
+            CString::new(value.as_str()).map(Some)
+                                        .map_err(|_| Failure::Nul("identifier"))
+        }
+        None => Ok(None),
+    }
+}
+
+fn into_raw(value: Option<CString>) -> *mut c_char {
+    value.map_or(ptr::null_mut(), CString::into_raw)
+}
+
+unsafe fn free_string(value: &mut *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(*value));
+        *value = ptr::null_mut();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::test_support::{fixture_path,
+                                       fixtures};
+
+    fn c(s: &str) -> CString { CString::new(s).unwrap() }
+
+    fn string(ptr: *const c_char) -> Option<String> {
+        if ptr.is_null() {
+            None
+        } else {
+            Some(unsafe { CStr::from_ptr(ptr) }.to_str().unwrap().to_string())
+        }
+    }
+
+    fn empty_ident() -> HabIdent {
+        HabIdent { origin:  ptr::null_mut(),
+                   name:    ptr::null_mut(),
+                   version: ptr::null_mut(),
+                   release: ptr::null_mut(), }
+    }
+
+    #[test]
+    fn parses_idents() {
+        let mut ident = empty_ident();
+        let input = c("core/redis/4.0.14");
+        assert_eq!(HAB_OK, unsafe {
+            hab_ident_parse(input.as_ptr(), &mut ident)
+        });
+        assert_eq!(None, string(hab_last_error()));
+        assert_eq!(Some("core".to_string()), string(ident.origin));
+        assert_eq!(Some("redis".to_string()), string(ident.name));
+        assert_eq!(Some("4.0.14".to_string()), string(ident.version));
+        assert_eq!(None, string(ident.release));
+
+        unsafe {
+            hab_ident_free(&mut ident);
+            hab_ident_free(&mut ident);
+        }
+        assert!(ident.origin.is_null());
+        assert!(ident.version.is_null());
+    }
+
+    #[test]
+    fn reports_why_an_ident_is_rejected() {
+        let mut ident = empty_ident();
+        let input = c("core");
+        assert_eq!(HAB_ERR_FAILED, unsafe {
+            hab_ident_parse(input.as_ptr(), &mut ident)
+        });
+        assert!(string(hab_last_error()).unwrap().contains("core"));
+        assert!(ident.origin.is_null());
+
+        assert_eq!(HAB_ERR_NULL_ARGUMENT, unsafe {
+            hab_ident_parse(ptr::null(), &mut ident)
+        });
+        assert_eq!(Some("input is NULL".to_string()), string(hab_last_error()));
+        assert_eq!(HAB_ERR_NULL_ARGUMENT, unsafe {
+            hab_ident_parse(input.as_ptr(), ptr::null_mut())
+        });
+
+        let invalid = CString::new(vec![b'c', 0xff]).unwrap();
+        assert_eq!(HAB_ERR_INVALID_STRING, unsafe {
+            hab_ident_parse(invalid.as_ptr(), &mut ident)
+        });
+    }
+
+    #[test]
+    fn compares_versions() {
+        let cmp = |a: &str, b: &str| {
+            let (a, b) = (c(a), c(b));
+            let mut out = 42;
+            let code = unsafe { hab_version_cmp(a.as_ptr(), b.as_ptr(), &mut out) };
+            (code, out)
+        };
+        assert_eq!((HAB_OK, -1), cmp("1.0.0-alpha6", "1.0.0"));
+        assert_eq!((HAB_OK, 0), cmp("1.2", "1.2"));
+        assert_eq!((HAB_OK, 1), cmp("1.10.0", "1.9.3"));
+        assert_eq!((HAB_ERR_FAILED, 42), cmp("one", "1.0.0"));
+        assert!(string(hab_last_error()).is_some());
+    }
+
+    #[test]
+    fn reads_archive_headers() {
+        let path = fixture_path(&fixtures(),
+                                "happyhumans-possums-8.1.4-20160427165340-x86_64-linux.hart");
+        let path = c(path.to_str().unwrap());
+        let mut header = HabArchiveHeader { format_version: ptr::null_mut(),
+                                            key_name:       ptr::null_mut(),
+                                            hash_type:      ptr::null_mut(),
+                                            ident:          ptr::null_mut(),
+                                            target:         ptr::null_mut(), };
+        assert_eq!(HAB_OK, unsafe {
+            hab_archive_header(path.as_ptr(), &mut header)
+        });
+        assert_eq!(Some("HART-1".to_string()), string(header.format_version));
+        assert_eq!(Some("happyhumans-20160424223347".to_string()),
+                   string(header.key_name));
+        assert_eq!(Some("BLAKE2b".to_string()), string(header.hash_type));
+        assert_eq!(Some("happyhumans/possums/8.1.4/20160427165340".to_string()),
+                   string(header.ident));
+        assert_eq!(Some("x86_64-linux".to_string()), string(header.target));
+
+        unsafe { hab_archive_header_free(&mut header) };
+        assert!(header.ident.is_null());
+
+        let missing = c("/no/such/package.hart");
+        assert_eq!(HAB_ERR_FAILED, unsafe {
+            hab_archive_header(missing.as_ptr(), &mut header)
+        });
+    }
+}
@@ -12,16 +12,32 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod copy;
+mod file_system;
+pub mod watch;
+
+pub use self::{copy::{copy_file,
+                      copy_tree,
+                      CopyMethod},
+               file_system::{real_file_system,
+                             FileKind,
+                             FileMetadata,
+                             FileSystem,
+                             MemoryFileSystem,
+                             RealFileSystem}};
+
 use crate::{env as henv,
             error::{Error,
                     Result},
-            os::users::{self,
-                        assert_pkg_user_and_group},
+            os::{filesystem,
+                 users::{self,
+                         assert_pkg_user_and_group}},
             package::{Identifiable,
                       PackageIdent,
                       PackageInstall}};
 use dirs;
 use std::{env,
+          fmt,
           fs,
           io::{self,
                Write},
@@ -34,6 +50,10 @@ use tempfile;
 pub const ROOT_PATH: &str = "hab";
 /// The default path for any analytics related files
 pub const CACHE_ANALYTICS_PATH: &str = "hab/cache/analytics";
+/// The default path where Builder auth tokens are stored when no keyring is available
+pub const CACHE_AUTH_PATH: &str = "hab/cache/auth";
+/// The default path where crash reports are written
+pub const CACHE_CRASH_PATH: &str = "hab/cache/crash";
 /// The default download root path for package artifacts, used on package installation
 pub const CACHE_ARTIFACT_PATH: &str = "hab/cache/artifacts";
 /// The default path where cryptographic keys are stored
@@ -51,6 +71,9 @@ pub const LAUNCHER_ROOT_PATH: &str = "hab/launcher";
 pub const PKG_PATH: &str = "hab/pkgs";
 #[cfg(target_os = "windows")]
 pub const PKG_PATH: &str = "hab\\pkgs";
+/// The root path for state kept about installed packages, such as whether their post-install
+/// hooks have run, which must not live in the packages themselves
+pub const PKG_STATE_PATH: &str = "hab/state/pkgs";
 /// The environment variable pointing to the filesystem root. This exists for internal
 /// Habitat team usage and is not intended to be used by Habitat consumers.
 /// Using this variable could lead to broken Supervisor services and it should
@@ -82,6 +105,9 @@ lazy_static::lazy_static! {
 
     static ref EUID: u32 = users::get_effective_uid();
 
+    static ref PERMISSION_POLICY: PermissionPolicy =
+        <PermissionPolicy as henv::Config>::configured_value();
+
     static ref MY_CACHE_ANALYTICS_PATH: PathBuf = {
         if am_i_root() {
             PathBuf::from(CACHE_ANALYTICS_PATH)
@@ -93,6 +119,28 @@ lazy_static::lazy_static! {
         }
     };
 
+    static ref MY_CACHE_AUTH_PATH: PathBuf = {
+        if am_i_root() {
+            PathBuf::from(CACHE_AUTH_PATH)
+        } else {
+            match dirs::home_dir() {
+                Some(home) => home.join(format!(".{}", CACHE_AUTH_PATH)),
+                None => PathBuf::from(CACHE_AUTH_PATH),
+            }
+        }
+    };
+
+    static ref MY_CACHE_CRASH_PATH: PathBuf = {
+        if am_i_root() {
+            PathBuf::from(CACHE_CRASH_PATH)
+        } else {
+            match dirs::home_dir() {
+                Some(home) => home.join(format!(".{}", CACHE_CRASH_PATH)),
+                None => PathBuf::from(CACHE_CRASH_PATH),
+            }
+        }
+    };
+
     static ref MY_CACHE_ARTIFACT_PATH: PathBuf = {
         if am_i_root() {
             PathBuf::from(CACHE_ARTIFACT_PATH)
@@ -148,6 +196,26 @@ pub fn cache_analytics_path<T>(fs_root_path: Option<T>) -> PathBuf
     }
 }
 
+/// Returns the path to the auth token cache, optionally taking a custom filesystem root.
+pub fn cache_auth_path<T>(fs_root_path: Option<T>) -> PathBuf
+    where T: AsRef<Path>
+{
+    match fs_root_path {
+        Some(fs_root_path) => fs_root_path.as_ref().join(&*MY_CACHE_AUTH_PATH),
+        None => Path::new(&*FS_ROOT_PATH).join(&*MY_CACHE_AUTH_PATH),
+    }
+}
+
+/// Returns the path to the crash report directory, optionally taking a custom filesystem root.
+pub fn cache_crash_path<T>(fs_root_path: Option<T>) -> PathBuf
+    where T: AsRef<Path>
+{
+    match fs_root_path {
+        Some(fs_root_path) => fs_root_path.as_ref().join(&*MY_CACHE_CRASH_PATH),
+        None => Path::new(&*FS_ROOT_PATH).join(&*MY_CACHE_CRASH_PATH),
+    }
+}
+
 /// Returns the path to the artifacts cache, optionally taking a custom filesystem root.
 pub fn cache_artifact_path<T>(fs_root_path: Option<T>) -> PathBuf
     where T: AsRef<Path>
@@ -209,6 +277,21 @@ pub fn pkg_install_path<T>(ident: &PackageIdent, fs_root: Option<T>) -> PathBuf
     pkg_path
 }
 
+/// Returns the path of the state kept about the installed package `ident`.
+pub fn pkg_state_path<T>(ident: &PackageIdent, fs_root: Option<T>) -> PathBuf
+    where T: AsRef<Path>
+{
+    assert!(ident.fully_qualified(),
+            "Cannot determine state path without fully qualified ident");
+    let mut state_path = fs_root.map_or(PathBuf::from("/"), |p| p.as_ref().into());
+    state_path.push(PKG_STATE_PATH);
+    state_path.push(&ident.origin);
+    state_path.push(&ident.name);
+    state_path.push(ident.version.as_ref().unwrap());
+    state_path.push(ident.release.as_ref().unwrap());
+    state_path
+}
+
 /// Given a linux style absolute path (prepended with '/') and a fs_root,
 /// this will "re-root" the path just under the fs_root. Otherwise returns
 /// the given path unchanged. Non-Windows platforms will always return the
@@ -234,6 +317,11 @@ pub fn launcher_root_path<T>(fs_root_path: Option<T>) -> PathBuf
 /// Returns the root path for a given service's configuration, files, and data.
 pub fn svc_path<T: AsRef<Path>>(service_name: T) -> PathBuf { SVC_ROOT.join(service_name) }
 
+/// Returns the root path for a given service under the filesystem root `fs_root_path`.
+pub fn svc_path_in<T: AsRef<Path>>(fs_root_path: &Path, service_name: T) -> PathBuf {
+    fs_root_path.join("hab").join("svc").join(service_name)
+}
+
 /// Returns the path to the configuration directory for a given service.
 pub fn svc_config_path<T: AsRef<Path>>(service_name: T) -> PathBuf {
     svc_path(service_name).join("config")
@@ -289,6 +377,205 @@ pub fn user_config_path<T: AsRef<Path>>(service_name: T) -> PathBuf {
     user_path(service_name).join("config")
 }
 
+/// Limits on the permissions of the files and directories this crate creates, applied like a
+/// umask: any mode bit in the mask is cleared from whatever a helper would otherwise create,
+/// so a policy can only tighten permissions. The default mask is empty.
+///
+/// The policy is set for the whole process through the `HAB_UMASK` environment variable, in
+/// octal, such as `027` to keep everything created away from other users and out of the group's
+/// reach for writing, as CIS benchmarks ask. It covers `atomic_write` and `AtomicWriter`, service
+/// directories, and key files.
+///
+/// On Windows permissions are set through ACLs, and the policy has no effect.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PermissionPolicy {
+    umask: u32,
+}
+
+impl henv::Config for PermissionPolicy {
+    const ENVVAR: &'static str = "HAB_UMASK";
+}
+
+impl FromStr for PermissionPolicy {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let digits = value.trim();
+        let digits = if digits.starts_with("0o") {
+            &digits[2..]
+        } else {
+            digits
+        };
+        match u32::from_str_radix(digits, 8) {
+            Ok(umask) if umask <= 0o777 => Ok(PermissionPolicy { umask }),
+            _ => {
+                Err(Error::PermissionFailed(format!("Invalid umask '{}'; \
+                                                     expected octal digits \
+                                                     such as 027",
+                                                    value)))
+            }
+        }
+    }
+}
+
+impl PermissionPolicy {
+    pub fn new(umask: u32) -> Self { PermissionPolicy { umask: umask & 0o777, } }
+
+    /// The policy for this process, from `HAB_UMASK`.
+    pub fn current() -> Self { *PERMISSION_POLICY }
+
+    pub fn umask(self) -> u32 { self.umask }
+
+    /// The mode to create something with, given the mode a helper asks for.
+    pub fn mode(self, requested: u32) -> u32 { requested & !self.umask }
+
+    /// Clears the policy's bits from the mode of `path`, leaving it alone if there is nothing to
+    /// clear.
+    ///
+    /// # Failures
+    ///
+    /// * The mode of `path` cannot be read or set
+    #[cfg(not(windows))]
+    pub fn restrict<P: AsRef<Path>>(self, path: P) -> Result<()> {
+        use crate::util::posix_perm;
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = path.as_ref();
+        let mode = fs::metadata(path)?.permissions().mode() & 0o7777;
+        if mode & self.umask == 0 {
+            return Ok(());
+        }
+        posix_perm::set_permissions(path, self.mode(mode))
+    }
+
+    #[cfg(windows)]
+    pub fn restrict<P: AsRef<Path>>(self, _path: P) -> Result<()> { Ok(()) }
+
+    /// Creates `path` and any missing parents, restricting each directory it creates.
+    ///
+    /// # Failures
+    ///
+    /// * A directory cannot be created, or its mode cannot be set
+    pub fn create_dir_all<P: AsRef<Path>>(self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let missing: Vec<&Path> = path.ancestors().take_while(|p| !p.exists()).collect();
+        fs::create_dir_all(path)?;
+        for dir in missing.into_iter().rev() {
+            self.restrict(dir)?;
+        }
+        Ok(())
+    }
+}
+
+/// What kind of path the operating system refused access to, which decides how it is expected to
+/// be owned and permitted.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Access {
+    /// An installed package, or a directory of the package root leading to one.
+    Package,
+    /// A key file, or the key cache holding it.
+    Key,
+}
+
+/// Why the operating system refused access to a path, carried by `Error::PermissionDenied`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PermissionDenied {
+    pub path:      PathBuf,
+    /// What was being done with the path, such as "reading the DEPS metafile".
+    pub needed_by: String,
+    /// How the path is expected to be owned and permitted, and how it actually is where that can
+    /// be found out.
+    pub hint:      String,
+}
+
+impl PermissionDenied {
+    pub fn new<P, S>(path: P, needed_by: S, access: Access) -> Self
+        where P: Into<PathBuf>,
+              S: Into<String>
+    {
+        let path = path.into();
+        let hint = PermissionPolicy::current().hint(&path, access);
+        PermissionDenied { path,
+                           needed_by: needed_by.into(),
+                           hint }
+    }
+}
+
+impl fmt::Display for PermissionDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,
+               "Permission denied to {} while {}. {}",
+               self.path.display(),
+               self.needed_by,
+               self.hint)
+    }
+}
+
+impl PermissionPolicy {
+    /// Describes how a path of the given kind is expected to be owned and permitted under this
+    /// policy, and how `path` is, for a permission-denied error about it.
+    #[cfg(not(windows))]
+    pub fn hint(self, path: &Path, access: Access) -> String {
+        use std::os::unix::fs::MetadataExt;
+
+        let (what, owner, dir_mode, file_mode) = match access {
+            Access::Package => {
+                ("Installed packages", "root or the user which installed them", 0o755, 0o644)
+            }
+            Access::Key => {
+                ("Keys",
+                 "the user which created or imported them",
+                 0o755,
+                 crate::crypto::KEY_PERMISSIONS)
+            }
+        };
+        let umask = if self.umask == 0 {
+            String::new()
+        } else {
+            format!(", after HAB_UMASK {:03o}", self.umask)
+        };
+        let actual = match fs::metadata(path) {
+            Ok(m) => {
+                format!("It is mode {:04o} and owned by uid {}",
+                        m.mode() & 0o7777,
+                        m.uid())
+            }
+            Err(_) => "One of its parent directories cannot be searched".to_string(),
+        };
+        format!("{} are expected to be owned by {}, with mode {:04o} for directories and {:04o} \
+                 for files{}. {}, and this process runs as uid {}.",
+                what,
+                owner,
+                self.mode(dir_mode),
+                self.mode(file_mode),
+                umask,
+                actual,
+                *EUID)
+    }
+
+    #[cfg(windows)]
+    pub fn hint(self, path: &Path, access: Access) -> String {
+        let what = match access {
+            Access::Package => "installed packages",
+            Access::Key => "keys",
+        };
+        format!("Check that the ACLs on {} and its parent directories let the user running this \
+                 process read {}.",
+                path.display(),
+                what)
+    }
+}
+
+/// Makes an `Error` of a failure to access `path`: `Error::PermissionDenied`, with a hint for
+/// `access`, when the operating system refused it, and `Error::IO` otherwise.
+pub fn access_error(err: io::Error, path: &Path, access: Access, needed_by: &str) -> Error {
+    if err.kind() == io::ErrorKind::PermissionDenied {
+        Error::PermissionDenied(PermissionDenied::new(path, needed_by, access))
+    } else {
+        Error::IO(err)
+    }
+}
+
 /// Represents the service directory for a given package.
 pub struct SvcDir<'a> {
     service_name: &'a str,
@@ -421,7 +708,7 @@ impl<'a> SvcDir<'a> {
 
     fn create_dir_all<P: AsRef<Path>>(path: P) -> Result<()> {
         debug!("Creating dir with subdirs: {:?}", &path.as_ref());
-        if let Err(e) = fs::create_dir_all(&path) {
+        if let Err(e) = PermissionPolicy::current().create_dir_all(&path) {
             Err(Error::PermissionFailed(format!("Can't create {:?}, {}",
                                                 &path.as_ref(),
                                                 e)))
@@ -437,7 +724,9 @@ impl<'a> SvcDir<'a> {
         if users::can_run_services_as_svc_user() {
             posix_perm::set_owner(path.as_ref(), &self.svc_user, &self.svc_group)?;
         }
-        posix_perm::set_permissions(path.as_ref(), SVC_DIR_PERMISSIONS).map_err(From::from)
+        posix_perm::set_permissions(path.as_ref(),
+                                    PermissionPolicy::current().mode(SVC_DIR_PERMISSIONS))
+                   .map_err(From::from)
     }
 
     #[cfg(windows)]
@@ -591,7 +880,7 @@ pub fn resolve_cmd_in_pkg(program: &str, ident_str: &str) -> PathBuf {
 // Windows relies on path extensions to resolve commands like `docker` to `docker.exe`
 // Path extensions are found in the PATHEXT environment variable.
 // We should only search with PATHEXT if the file does not already have an extension.
-fn find_command_with_pathext(candidate: &PathBuf) -> Option<PathBuf> {
+pub(crate) fn find_command_with_pathext(candidate: &PathBuf) -> Option<PathBuf> {
     if candidate.extension().is_none() {
         if let Some(pathexts) = henv::var_os("PATHEXT") {
             for pathext in env::split_paths(&pathexts) {
@@ -608,6 +897,22 @@ fn find_command_with_pathext(candidate: &PathBuf) -> Option<PathBuf> {
     None
 }
 
+/// Returns whether `path` is a file a shell would run as a command: on Unix, a file with at least
+/// one of its execute bits set, so that a non-executable file earlier in a `PATH` does not shadow
+/// the real command; elsewhere, any file.
+#[cfg(not(windows))]
+pub(crate) fn is_executable_file(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    match fs::metadata(path) {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+#[cfg(windows)]
+pub(crate) fn is_executable_file(path: &Path) -> bool { path.is_file() }
+
 /// Returns whether or not the current process is running with a root
 /// effective user id or not.
 ///
@@ -713,10 +1018,13 @@ impl AtomicWriter {
         Ok(r)
     }
 
-    /// finish completes the atomic write by calling sync on the
-    /// temporary file to ensure all data is flushed to disk and then
-    /// renaming the file into place.
+    /// finish completes the atomic write by applying the
+    /// permission policy to the temporary file, calling sync on it
+    /// to ensure all data is flushed to disk, and then renaming the
+    /// file into place.
     fn finish(self) -> io::Result<()> {
+        PermissionPolicy::current().restrict(self.tempfile.path())
+                                   .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
         self.tempfile.as_file().sync_all()?;
         debug!("Renaming {} to {}",
                self.tempfile.path().to_string_lossy(),
@@ -762,6 +1070,28 @@ pub fn atomic_write(dest_path: &Path, data: impl AsRef<[u8]>) -> io::Result<()>
     w.with_writer(|f| f.write_all(data.as_ref()))
 }
 
+/// Checks that the filesystem which holds `path`, or would hold it once created, has at least
+/// `required` bytes free, so that an operation which needs the space can fail before it starts
+/// rather than part way through.
+///
+/// # Failures
+///
+/// * There is less than `required` bytes free
+/// * The free space cannot be determined
+pub fn check_free_space<P: AsRef<Path>>(path: P, required: u64) -> Result<()> {
+    let path = path.as_ref();
+    let existing = path.ancestors()
+                       .find(|p| p.exists())
+                       .unwrap_or_else(|| Path::new("."));
+    let available = filesystem::free_space(existing)?;
+    if available < required {
+        return Err(Error::InsufficientDiskSpace(path.to_path_buf(),
+                                                required,
+                                                available));
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -999,6 +1329,124 @@ mod test_find_command {
     }
 }
 
+#[cfg(test)]
+mod test_check_free_space {
+    use super::check_free_space;
+    use crate::error::Error;
+    use std::u64;
+    use tempfile::Builder;
+
+    #[test]
+    fn fails_early_without_room() {
+        let root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let path = root.path().join("hab").join("pkgs");
+
+        check_free_space(&path, 0).unwrap();
+        match check_free_space(&path, u64::MAX) {
+            Err(Error::InsufficientDiskSpace(p, required, available)) => {
+                assert_eq!(path, p);
+                assert_eq!(u64::MAX, required);
+                assert!(available < required);
+            }
+            other => panic!("expected InsufficientDiskSpace, got {:?}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_permission_policy {
+    use super::PermissionPolicy;
+    use tempfile::Builder;
+
+    #[test]
+    fn parse_umask() {
+        assert_eq!(PermissionPolicy::new(0o027), "027".parse().unwrap());
+        assert_eq!(PermissionPolicy::new(0o077), "0o077".parse().unwrap());
+        assert!("1000".parse::<PermissionPolicy>().is_err());
+        assert!("rwx".parse::<PermissionPolicy>().is_err());
+        assert_eq!(0o750, PermissionPolicy::new(0o027).mode(0o770));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn created_directories_are_restricted() {
+        use std::{fs,
+                  os::unix::fs::PermissionsExt,
+                  path::Path};
+
+        let root = Builder::new().prefix("policy").tempdir().unwrap();
+        let path = root.path().join("a").join("b");
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let root_mode = mode(root.path());
+
+        PermissionPolicy::new(0o077).create_dir_all(&path).unwrap();
+
+        assert_eq!(0, mode(&path) & 0o077);
+        assert_eq!(0, mode(&root.path().join("a")) & 0o077);
+        assert_eq!(root_mode, mode(root.path()));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn hints_say_how_a_path_is_and_should_be_permitted() {
+        use super::Access;
+        use std::{fs,
+                  os::unix::fs::PermissionsExt};
+
+        let root = Builder::new().prefix("policy").tempdir().unwrap();
+        let path = root.path().join("DEPS");
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        let hint = PermissionPolicy::new(0o027).hint(&path, Access::Package);
+        assert!(hint.contains("mode 0750 for directories and 0640 for files, after HAB_UMASK 027"),
+                "{}",
+                hint);
+        assert!(hint.contains("It is mode 0600"), "{}", hint);
+
+        let hint = PermissionPolicy::new(0).hint(&root.path().join("missing"), Access::Key);
+        assert!(hint.contains("0755 for directories and 0400 for files."),
+                "{}",
+                hint);
+        assert!(hint.contains("parent directories cannot be searched"),
+                "{}",
+                hint);
+    }
+
+    #[test]
+    fn access_errors_explain_refusals() {
+        use super::{access_error,
+                    Access};
+        use crate::error::Error;
+        use std::{io,
+                  path::Path};
+
+        let path = Path::new("/hab/pkgs/core/redis");
+        match access_error(io::ErrorKind::PermissionDenied.into(),
+                           path,
+                           Access::Package,
+                           "looking for installed packages")
+        {
+            Error::PermissionDenied(denied) => {
+                assert_eq!(path, denied.path);
+                assert_eq!("looking for installed packages", denied.needed_by);
+                assert!(denied.to_string()
+                              .starts_with("Permission denied to /hab/pkgs/core/redis while \
+                                            looking for installed packages. "));
+            }
+            other => panic!("expected a permission denied error, got {:?}", other),
+        }
+        match access_error(io::ErrorKind::NotFound.into(),
+                           path,
+                           Access::Package,
+                           "listing")
+        {
+            Error::IO(_) => (),
+            other => panic!("expected an IO error, got {:?}", other),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test_atomic_writer {
     use super::{atomic_write,
@@ -0,0 +1,331 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Copying files and directory trees without wasting time or disk.
+//!
+//! Studio provisioning and export staging copy whole package trees, which are often large and
+//! sometimes sparse. A plain copy reads and writes every byte, and writes the holes of a sparse
+//! file out as real zeros. Here each file is instead cloned with a reflink where the filesystem
+//! supports it, such as on Btrfs or XFS, so that the copy shares storage with the original until
+//! either is modified. Failing that, only the regions of the file holding data are copied, in
+//! the kernel with `copy_file_range` where possible, and the holes between them are left as
+//! holes. Everywhere else, and whenever the faster methods are unavailable, files are copied
+//! through a buffer, skipping blocks of zeros so that the copy is sparse regardless.
+
+use std::{cmp,
+          fs::{self,
+               File,
+               OpenOptions},
+          io::{self,
+               Read,
+               Seek,
+               SeekFrom,
+               Write},
+          path::Path};
+
+/// The size of the blocks copied at a time when copying through a buffer, and of the runs of
+/// zeros which are left as holes.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// How the contents of a file were copied.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CopyMethod {
+    /// The copy shares its storage with the original.
+    Reflink,
+    /// The data was copied within the kernel, with `copy_file_range`.
+    CopyFileRange,
+    /// The data was read and written through a buffer.
+    Buffered,
+}
+
+/// Copies the file at `src` to `dest`, replacing any file already there, and gives the copy the
+/// permissions of the original. Holes in the original are preserved.
+///
+/// # Failures
+///
+/// * `src` cannot be read
+/// * `dest` cannot be written, or its permissions cannot be set
+pub fn copy_file<S, D>(src: S, dest: D) -> io::Result<CopyMethod>
+    where S: AsRef<Path>,
+          D: AsRef<Path>
+{
+    let src_file = File::open(src.as_ref())?;
+    let metadata = src_file.metadata()?;
+    let dest_file = OpenOptions::new().write(true)
+                                      .create(true)
+                                      .truncate(true)
+                                      .open(dest.as_ref())?;
+    let method = copy_contents(&src_file, &dest_file, metadata.len())?;
+    if method != CopyMethod::Reflink {
+        // Trailing holes are never written, so the length has to be set explicitly.
+        dest_file.set_len(metadata.len())?;
+    }
+    fs::set_permissions(dest.as_ref(), metadata.permissions())?;
+    debug!("Copied {} to {} ({:?})",
+           src.as_ref().display(),
+           dest.as_ref().display(),
+           method);
+    Ok(method)
+}
+
+/// Recursively copies `src` to `dest` with `copy_file`, creating `dest` and any missing parents.
+/// Symlinks are copied as symlinks, pointing at the same targets, and directories are given the
+/// permissions of the originals once their contents have been copied.
+///
+/// `dest` must not be inside `src`.
+///
+/// # Failures
+///
+/// * Anything under `src` cannot be read
+/// * Anything under `dest` cannot be created
+pub fn copy_tree<S, D>(src: S, dest: D) -> io::Result<()>
+    where S: AsRef<Path>,
+          D: AsRef<Path>
+{
+    let src = src.as_ref();
+    let dest = dest.as_ref();
+    let metadata = fs::symlink_metadata(src)?;
+    if metadata.file_type().is_symlink() {
+        copy_symlink(src, dest)
+    } else if metadata.is_dir() {
+        fs::create_dir_all(dest)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(entry.path(), dest.join(entry.file_name()))?;
+        }
+        fs::set_permissions(dest, metadata.permissions())
+    } else {
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        copy_file(src, dest).map(|_| ())
+    }
+}
+
+fn copy_symlink(src: &Path, dest: &Path) -> io::Result<()> {
+    let target = fs::read_link(src)?;
+    if fs::symlink_metadata(dest).is_ok() {
+        fs::remove_file(dest)?;
+    }
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(&target, dest)
+    }
+    #[cfg(windows)]
+    {
+        if fs::metadata(src).map(|m| m.is_dir()).unwrap_or(false) {
+            std::os::windows::fs::symlink_dir(&target, dest)
+        } else {
+            std::os::windows::fs::symlink_file(&target, dest)
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn copy_contents(src: &File, dest: &File, len: u64) -> io::Result<CopyMethod> {
+    match linux::reflink(src, dest) {
+        Ok(()) => return Ok(CopyMethod::Reflink),
+        Err(e) => trace!("Reflink unavailable, copying data instead: {}", e),
+    }
+    let extents = linux::data_extents(src, len)?.unwrap_or_else(|| vec![(0, len)]);
+    let mut method = CopyMethod::CopyFileRange;
+    for (start, end) in extents {
+        let mut offset = start;
+        if method == CopyMethod::CopyFileRange {
+            if let Err(e) = linux::copy_range(src, dest, &mut offset, end) {
+                trace!("copy_file_range unavailable, copying through a buffer: {}",
+                       e);
+                method = CopyMethod::Buffered;
+            }
+        }
+        copy_buffered(src, dest, offset, end)?;
+    }
+    Ok(method)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_contents(src: &File, dest: &File, len: u64) -> io::Result<CopyMethod> {
+    copy_buffered(src, dest, 0, len)?;
+    Ok(CopyMethod::Buffered)
+}
+
+/// Copies the bytes of `src` from `start` up to `end` to the same place in `dest`, seeking over
+/// blocks of zeros rather than writing them.
+fn copy_buffered(mut src: &File, mut dest: &File, start: u64, end: u64) -> io::Result<()> {
+    if start >= end {
+        return Ok(());
+    }
+    src.seek(SeekFrom::Start(start))?;
+    dest.seek(SeekFrom::Start(start))?;
+    let mut buf = vec![0; BUFFER_SIZE];
+    let mut offset = start;
+    while offset < end {
+        let want = cmp::min(BUFFER_SIZE as u64, end - offset) as usize;
+        let n = src.read(&mut buf[..want])?;
+        if n == 0 {
+            break;
+        }
+        if buf[..n].iter().all(|&b| b == 0) {
+            dest.seek(SeekFrom::Current(n as i64))?;
+        } else {
+            dest.write_all(&buf[..n])?;
+        }
+        offset += n as u64;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use libc;
+    use std::{fs::File,
+              io,
+              os::unix::io::AsRawFd};
+
+    // Not every version of libc defines these.
+    const FICLONE: libc::c_ulong = 0x4004_9409;
+    const SEEK_DATA: libc::c_int = 3;
+    const SEEK_HOLE: libc::c_int = 4;
+
+    /// Makes `dest` share the storage of `src`.
+    pub fn reflink(src: &File, dest: &File) -> io::Result<()> {
+        let rc = unsafe { libc::ioctl(dest.as_raw_fd(), FICLONE as _, src.as_raw_fd()) };
+        if rc == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the start and end of each region of `src` which holds data, or `None` if the
+    /// filesystem cannot say where its holes are.
+    pub fn data_extents(src: &File, len: u64) -> io::Result<Option<Vec<(u64, u64)>>> {
+        let fd = src.as_raw_fd();
+        let mut extents = Vec::new();
+        let mut offset = 0;
+        while offset < len {
+            let start = unsafe { libc::lseek(fd, offset as libc::off_t, SEEK_DATA) };
+            if start == -1 {
+                let e = io::Error::last_os_error();
+                return match e.raw_os_error() {
+                    // There is no data after `offset`.
+                    Some(libc::ENXIO) => Ok(Some(extents)),
+                    Some(libc::EINVAL) => Ok(None),
+                    _ => Err(e),
+                };
+            }
+            let end = unsafe { libc::lseek(fd, start, SEEK_HOLE) };
+            if end == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let end = (end as u64).min(len);
+            extents.push((start as u64, end));
+            offset = end;
+        }
+        Ok(Some(extents))
+    }
+
+    /// Copies the bytes of `src` from `offset` up to `end` to the same place in `dest` within the
+    /// kernel. `offset` is advanced past whatever was copied, even on failure.
+    pub fn copy_range(src: &File, dest: &File, offset: &mut u64, end: u64) -> io::Result<()> {
+        while *offset < end {
+            let mut off_in = *offset as i64;
+            let mut off_out = *offset as i64;
+            let n = unsafe {
+                libc::syscall(libc::SYS_copy_file_range,
+                              src.as_raw_fd(),
+                              &mut off_in as *mut i64,
+                              dest.as_raw_fd(),
+                              &mut off_out as *mut i64,
+                              (end - *offset) as libc::size_t,
+                              0 as libc::c_uint)
+            };
+            if n == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if n == 0 {
+                // `src` is shorter than expected; there is nothing more to copy.
+                *offset = end;
+                break;
+            }
+            *offset += n as u64;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn copy_tree_copies_files_dirs_and_symlinks() {
+        let root = Builder::new().prefix("copy-tree").tempdir().unwrap();
+        let src = root.path().join("src");
+        fs::create_dir_all(src.join("bin")).unwrap();
+        fs::write(src.join("bin").join("app"), "#!/bin/sh\necho hi\n").unwrap();
+        fs::write(src.join("README"), "hello").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("bin/app", src.join("app")).unwrap();
+
+        let dest = root.path().join("staging").join("dest");
+        copy_tree(&src, &dest).unwrap();
+
+        assert_eq!("#!/bin/sh\necho hi\n",
+                   fs::read_to_string(dest.join("bin").join("app")).unwrap());
+        assert_eq!("hello", fs::read_to_string(dest.join("README")).unwrap());
+        #[cfg(unix)]
+        assert_eq!(Path::new("bin/app"),
+                   fs::read_link(dest.join("app")).unwrap());
+
+        // Copying again replaces what is there.
+        fs::write(src.join("README"), "bye").unwrap();
+        copy_tree(&src, &dest).unwrap();
+        assert_eq!("bye", fs::read_to_string(dest.join("README")).unwrap());
+    }
+
+    #[test]
+    fn copy_file_preserves_holes_and_permissions() {
+        let root = Builder::new().prefix("copy-file").tempdir().unwrap();
+        let src = root.path().join("sparse");
+        let len = 16 * 1024 * 1024;
+        {
+            let mut file = File::create(&src).unwrap();
+            file.set_len(len).unwrap();
+            file.seek(SeekFrom::Start(len / 2)).unwrap();
+            file.write_all(b"data").unwrap();
+        }
+        let mut permissions = fs::metadata(&src).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&src, permissions).unwrap();
+
+        let dest = root.path().join("copy");
+        copy_file(&src, &dest).unwrap();
+
+        let metadata = fs::metadata(&dest).unwrap();
+        assert_eq!(len, metadata.len());
+        assert!(metadata.permissions().readonly());
+        let mut data = vec![0; 4];
+        let mut file = File::open(&dest).unwrap();
+        file.seek(SeekFrom::Start(len / 2)).unwrap();
+        file.read_exact(&mut data).unwrap();
+        assert_eq!(b"data", &data[..]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert!(metadata.blocks() * 512 < len / 4);
+        }
+    }
+}
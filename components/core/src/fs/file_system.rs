@@ -0,0 +1,320 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The filesystem that package operations read installed packages from.
+//!
+//! `PackageInstall`, the metafile reader and package resolution go through a `FileSystem`
+//! rather than `std::fs`. Ordinarily that is `RealFileSystem`, which is `std::fs`, but a
+//! `MemoryFileSystem` can stand in for it so that resolution can be tested without laying out
+//! packages on disk, and other backends, such as one reading from a bundle, can be added without
+//! touching the code which uses them.
+//!
+//! Resolving packages and reading their metafiles and default configuration go through here.
+//! Finding their commands, measuring their size, installing them and running them still use the
+//! real filesystem.
+
+use std::{collections::BTreeMap,
+          fmt,
+          fs,
+          io::{self,
+               Cursor,
+               Read},
+          path::{Path,
+                 PathBuf},
+          sync::{Arc,
+                 RwLock}};
+
+/// Whether a path is a file or a directory.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FileKind {
+    File,
+    Dir,
+}
+
+/// What a `FileSystem` knows about a file or directory.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FileMetadata {
+    pub kind: FileKind,
+    /// The length of a file in bytes. For a directory, this is whatever the filesystem reports.
+    pub len:  u64,
+}
+
+impl FileMetadata {
+    pub fn is_dir(&self) -> bool { self.kind == FileKind::Dir }
+
+    pub fn is_file(&self) -> bool { self.kind == FileKind::File }
+}
+
+/// A filesystem which packages can be read from.
+///
+/// Errors are the `io::Error`s `std::fs` would give in the same situation, so that callers can
+/// go on telling a missing file from an unreadable one by its `ErrorKind`.
+pub trait FileSystem: fmt::Debug + Send + Sync {
+    /// Opens the file at `path` for reading.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>>;
+
+    /// Writes `data` to the file at `path`, replacing anything it held and creating its parent
+    /// directories if need be.
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    /// The paths of the entries of the directory at `path`, in no particular order.
+    fn list(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Describes the file or directory at `path`, following any symlinks.
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata>;
+
+    /// Whether `path` is itself a symlink. Filesystems without symlinks say it is not, once they
+    /// have checked that it exists.
+    fn is_symlink(&self, path: &Path) -> io::Result<bool> { self.metadata(path).map(|_| false) }
+
+    /// The absolute path `path` leads to once every symlink on the way is followed. Filesystems
+    /// without symlinks give back `path` as it is.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> { Ok(path.to_path_buf()) }
+
+    /// Where the symlink at `path` points, as it was written. Filesystems without symlinks say
+    /// that `path` is not one.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput,
+                           format!("{} is not a symlink",
+                                   path.display())))
+    }
+
+    /// Reads the whole file at `path`.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.open(path)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Reads the whole file at `path`, which must be UTF-8.
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        let mut data = String::new();
+        self.open(path)?.read_to_string(&mut data)?;
+        Ok(data)
+    }
+
+    /// Whether a file or directory exists at `path`.
+    fn exists(&self, path: &Path) -> bool { self.metadata(path).is_ok() }
+}
+
+/// The filesystem of the machine, through `std::fs`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RealFileSystem;
+
+impl FileSystem for RealFileSystem {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> { Ok(Box::new(fs::File::open(path)?)) }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, data)
+    }
+
+    fn list(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?.map(|entry| entry.map(|e| e.path()))
+                           .collect()
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        let metadata = fs::metadata(path)?;
+        let kind = if metadata.is_dir() {
+            FileKind::Dir
+        } else {
+            FileKind::File
+        };
+        Ok(FileMetadata { kind,
+                          len: metadata.len() })
+    }
+
+    fn is_symlink(&self, path: &Path) -> io::Result<bool> {
+        Ok(fs::symlink_metadata(path)?.file_type().is_symlink())
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> { fs::canonicalize(path) }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> { fs::read_link(path) }
+}
+
+/// The filesystem used when none is given: the real one.
+pub fn real_file_system() -> Arc<dyn FileSystem> { Arc::new(RealFileSystem) }
+
+#[derive(Clone, Debug)]
+enum Entry {
+    File(Vec<u8>),
+    Dir,
+}
+
+/// A filesystem held in memory, for tests. It starts out holding only the root directory.
+///
+/// Paths are taken as they are given: `a/../b` and `b` are different paths, and there are no
+/// symlinks or permissions.
+#[derive(Debug)]
+pub struct MemoryFileSystem {
+    entries: RwLock<BTreeMap<PathBuf, Entry>>,
+}
+
+impl Default for MemoryFileSystem {
+    fn default() -> Self {
+        let mut entries = BTreeMap::new();
+        entries.insert(PathBuf::from("/"), Entry::Dir);
+        MemoryFileSystem { entries: RwLock::new(entries), }
+    }
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self { Self::default() }
+
+    /// Creates the directory at `path` and any of its parents which do not exist yet.
+    ///
+    /// # Failures
+    ///
+    /// * A file exists at `path` or at one of its parents
+    pub fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries
+                              .write()
+                              .expect("MemoryFileSystem lock poisoned");
+        for dir in path.ancestors() {
+            if dir.as_os_str().is_empty() {
+                continue;
+            }
+            match entries.get(dir) {
+                Some(Entry::Dir) => break,
+                Some(Entry::File(_)) => {
+                    return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                                              format!("{} is a file", dir.display())));
+                }
+                None => {}
+            }
+        }
+        for dir in path.ancestors().filter(|d| !d.as_os_str().is_empty()) {
+            entries.entry(dir.to_path_buf()).or_insert(Entry::Dir);
+        }
+        Ok(())
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound,
+                       format!("{} does not exist", path.display()))
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read>> {
+        let entries = self.entries.read().expect("MemoryFileSystem lock poisoned");
+        match entries.get(path) {
+            Some(Entry::File(data)) => Ok(Box::new(Cursor::new(data.clone()))),
+            Some(Entry::Dir) => {
+                Err(io::Error::new(io::ErrorKind::Other,
+                                   format!("{} is a directory", path.display())))
+            }
+            None => Err(Self::not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)?;
+        }
+        let mut entries = self.entries
+                              .write()
+                              .expect("MemoryFileSystem lock poisoned");
+        if let Some(Entry::Dir) = entries.get(path) {
+            return Err(io::Error::new(io::ErrorKind::Other,
+                                      format!("{} is a directory", path.display())));
+        }
+        entries.insert(path.to_path_buf(), Entry::File(data.to_vec()));
+        Ok(())
+    }
+
+    fn list(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let entries = self.entries.read().expect("MemoryFileSystem lock poisoned");
+        match entries.get(path) {
+            Some(Entry::Dir) => {
+                Ok(entries.keys()
+                          .filter(|p| p.parent() == Some(path))
+                          .cloned()
+                          .collect())
+            }
+            Some(Entry::File(_)) => {
+                Err(io::Error::new(io::ErrorKind::Other,
+                                   format!("{} is not a directory",
+                                           path.display())))
+            }
+            None => Err(Self::not_found(path)),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        let entries = self.entries.read().expect("MemoryFileSystem lock poisoned");
+        match entries.get(path) {
+            Some(Entry::File(data)) => {
+                Ok(FileMetadata { kind: FileKind::File,
+                                  len:  data.len() as u64, })
+            }
+            Some(Entry::Dir) => {
+                Ok(FileMetadata { kind: FileKind::Dir,
+                                  len:  0, })
+            }
+            None => Err(Self::not_found(path)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::Builder;
+
+    fn exercise(filesystem: &dyn FileSystem, root: &Path) {
+        let file = root.join("a/b/file");
+
+        assert_eq!(io::ErrorKind::NotFound,
+                   filesystem.read(&file).unwrap_err().kind());
+        filesystem.write(&file, b"hello").unwrap();
+        filesystem.write(&root.join("a/other"), b"").unwrap();
+
+        assert_eq!(b"hello".to_vec(), filesystem.read(&file).unwrap());
+        assert_eq!("hello", filesystem.read_to_string(&file).unwrap());
+        assert!(filesystem.metadata(&root.join("a/b")).unwrap().is_dir());
+        assert_eq!(FileMetadata { kind: FileKind::File,
+                                  len:  5, },
+                   filesystem.metadata(&file).unwrap());
+        let mut listed = filesystem.list(&root.join("a")).unwrap();
+        listed.sort();
+        assert_eq!(vec![root.join("a/b"), root.join("a/other")], listed);
+        assert!(filesystem.list(&file).is_err());
+        assert!(!filesystem.exists(&root.join("a/missing")));
+    }
+
+    #[test]
+    fn real_file_system() {
+        let root = Builder::new().prefix("file-system").tempdir().unwrap();
+        exercise(&RealFileSystem, root.path());
+    }
+
+    #[test]
+    fn memory_file_system_behaves_as_the_real_one() {
+        exercise(&MemoryFileSystem::new(), Path::new("/root"));
+    }
+
+    #[test]
+    fn memory_file_system_cannot_put_a_directory_under_a_file() {
+        let filesystem = MemoryFileSystem::new();
+        filesystem.write(Path::new("/file"), b"").unwrap();
+
+        assert!(filesystem.write(Path::new("/file/nested"), b"").is_err());
+        assert!(filesystem.create_dir_all(Path::new("/file/dir")).is_err());
+    }
+}
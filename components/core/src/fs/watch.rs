@@ -0,0 +1,615 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Watching individual files, such as `user.toml` or keys, for changes.
+//!
+//! Editors and tools like `hab config apply` rarely modify a file in place. More often they write
+//! a temporary file and rename it over the original, or remove the file and write it again, and
+//! a single save can produce a burst of notifications. A `DebouncedWatcher` therefore watches the
+//! directory containing each file rather than the file itself, so it keeps working after the
+//! file is replaced, and waits until a file has been quiet for the debounce period before
+//! comparing it with its state at the last event. A burst of activity which leaves a file changed
+//! produces a single `Change`; one which leaves it as it was produces none.
+//!
+//! A directory can be watched too, and then counts as changed whenever any entry directly
+//! within it is created, removed, or changed. The directory itself must exist when the watcher
+//! starts.
+//!
+//! On Linux, notifications come from inotify. Elsewhere, on NFS mounts, where inotify does not
+//! see changes made by other clients, or when polling is forced, each file's metadata is checked
+//! every poll interval instead.
+
+#[cfg(target_os = "linux")]
+use std::{collections::HashMap,
+          ffi::CString,
+          io,
+          mem,
+          os::unix::ffi::OsStrExt};
+use std::{fs,
+          path::{Path,
+                 PathBuf},
+          sync::{atomic::{AtomicBool,
+                          Ordering},
+                 mpsc::{self,
+                        Receiver,
+                        Sender},
+                 Arc},
+          thread::{self,
+                   JoinHandle},
+          time::{Duration,
+                 Instant,
+                 SystemTime}};
+
+use crate::error::Result;
+
+/// How often the watcher thread checks whether it should stop.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What happened to a watched file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChangeKind {
+    /// The file did not exist and now does.
+    Created,
+    /// The file existed and was changed or replaced.
+    Modified,
+    /// The file existed and now does not.
+    Removed,
+}
+
+/// A change to a watched file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Change {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+}
+
+/// Options for a `DebouncedWatcher`.
+#[derive(Clone, Copy, Debug)]
+pub struct WatchOptions {
+    /// How long a file must go without further activity before its change is reported.
+    pub debounce:      Duration,
+    /// How often files are checked when polling.
+    pub poll_interval: Duration,
+    /// Poll even where file notifications are available.
+    pub force_polling: bool,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions { debounce:      Duration::from_millis(500),
+                       poll_interval: Duration::from_secs(2),
+                       force_polling: false, }
+    }
+}
+
+/// Watches a set of files from a background thread, sending a `Change` for each settled change.
+///
+/// The watched files need not exist yet, but their directories must. The thread stops when the
+/// watcher is dropped.
+#[derive(Debug)]
+pub struct DebouncedWatcher {
+    rx:      Receiver<Change>,
+    polling: bool,
+    stop:    Arc<AtomicBool>,
+    thread:  Option<JoinHandle<()>>,
+}
+
+impl DebouncedWatcher {
+    /// Starts watching `paths`.
+    ///
+    /// # Failures
+    ///
+    /// * File notifications are available but a directory cannot be watched, such as because it
+    ///   does not exist
+    pub fn new<I, P>(paths: I, opts: WatchOptions) -> Result<Self>
+        where I: IntoIterator<Item = P>,
+              P: Into<PathBuf>
+    {
+        let files: Vec<Watched> = paths.into_iter().map(|p| Watched::new(p.into())).collect();
+        let source = Source::new(&files, opts)?;
+        let polling = source.is_polling();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (tx, rx) = mpsc::channel();
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::Builder::new().name("fs-watch".to_string())
+                                  .spawn(move || watch(files, source, opts, &stop, &tx))?
+        };
+        Ok(DebouncedWatcher { rx,
+                              polling,
+                              stop,
+                              thread: Some(thread) })
+    }
+
+    /// The changes to the watched files, in the order they settled.
+    pub fn events(&self) -> &Receiver<Change> { &self.rx }
+
+    /// Whether files are being polled rather than watched with file notifications.
+    pub fn is_polling(&self) -> bool { self.polling }
+}
+
+impl Drop for DebouncedWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("File watcher thread panicked");
+            }
+        }
+    }
+}
+
+/// The parts of a file's metadata which change when it is written or replaced.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Snapshot {
+    len:      u64,
+    modified: Option<SystemTime>,
+    /// The file's inode, which changes when it is replaced by a rename, or zero where there are
+    /// no inodes.
+    inode:    u64,
+}
+
+impl Snapshot {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let snapshot = Snapshot { len:      metadata.len(),
+                                  modified: metadata.modified().ok(),
+                                  inode:    inode(&metadata), };
+        if metadata.is_dir() {
+            Some(snapshot.with_entries(path))
+        } else {
+            Some(snapshot)
+        }
+    }
+
+    /// Folds the snapshots of a directory's entries into its own, since changing an entry in
+    /// place changes nothing about the directory.
+    fn with_entries(mut self, dir: &Path) -> Self {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return self,
+        };
+        for metadata in entries.filter_map(|e| e.and_then(|e| e.metadata()).ok()) {
+            self.len = self.len.wrapping_add(metadata.len()).wrapping_add(1);
+            self.inode ^= inode(&metadata);
+            if let Ok(modified) = metadata.modified() {
+                self.modified = self.modified.max(Some(modified));
+            }
+        }
+        self
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> u64 { std::os::unix::fs::MetadataExt::ino(metadata) }
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> u64 { 0 }
+
+#[derive(Debug)]
+struct Watched {
+    path:         PathBuf,
+    /// The file's state as of the last reported change.
+    reported:     Option<Snapshot>,
+    /// The file's state when it was last polled.
+    polled:       Option<Snapshot>,
+    /// When activity was last seen on the file, if it has not yet settled.
+    active_since: Option<Instant>,
+    /// Whether a file notification was received for the file since its last reported change.
+    notified:     bool,
+    /// Whether the path is a directory, whose entries are watched.
+    is_dir:       bool,
+}
+
+impl Watched {
+    fn new(path: PathBuf) -> Self {
+        let snapshot = Snapshot::of(&path);
+        let is_dir = path.is_dir();
+        Watched { path,
+                  reported: snapshot,
+                  polled: snapshot,
+                  active_since: None,
+                  notified: false,
+                  is_dir }
+    }
+
+    fn dir(&self) -> &Path {
+        match self.path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        }
+    }
+
+    /// Returns the change since the last reported one, if there is one.
+    fn settle(&mut self) -> Option<ChangeKind> {
+        let current = Snapshot::of(&self.path);
+        let kind = match (self.reported, current) {
+            (None, Some(_)) => Some(ChangeKind::Created),
+            (Some(_), None) => Some(ChangeKind::Removed),
+            // Metadata may not change if a file is rewritten quickly with content of the same
+            // length, so trust a notification over the metadata
+            (Some(before), Some(after)) if before != after || self.notified => {
+                Some(ChangeKind::Modified)
+            }
+            _ => None,
+        };
+        self.reported = current;
+        self.polled = current;
+        self.active_since = None;
+        self.notified = false;
+        kind
+    }
+}
+
+fn watch(mut files: Vec<Watched>,
+         mut source: Source,
+         opts: WatchOptions,
+         stop: &AtomicBool,
+         tx: &Sender<Change>) {
+    while !stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        let next_settle = files.iter()
+                               .filter_map(|f| f.active_since)
+                               .map(|t| until(t + opts.debounce, now))
+                               .min();
+        let timeout = next_settle.unwrap_or(STOP_CHECK_INTERVAL)
+                                 .min(STOP_CHECK_INTERVAL);
+
+        source.wait(&mut files, timeout);
+
+        let now = Instant::now();
+        for file in files.iter_mut() {
+            let settled = file.active_since
+                              .map_or(false, |t| now.duration_since(t) >= opts.debounce);
+            if !settled {
+                continue;
+            }
+            if let Some(kind) = file.settle() {
+                let change = Change { path: file.path.clone(),
+                                      kind };
+                if tx.send(change).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// The time from `now` until `deadline`, or zero if it has passed.
+fn until(deadline: Instant, now: Instant) -> Duration {
+    if deadline > now {
+        deadline - now
+    } else {
+        Duration::from_secs(0)
+    }
+}
+
+/// Where the watcher learns of activity on its files.
+enum Source {
+    Poll {
+        interval:  Duration,
+        last_poll: Instant,
+    },
+    #[cfg(target_os = "linux")]
+    Inotify(Inotify),
+}
+
+impl Source {
+    #[cfg(target_os = "linux")]
+    fn new(files: &[Watched], opts: WatchOptions) -> Result<Self> {
+        let on_nfs = files.iter().find(|f| is_nfs(f.dir()));
+        if let Some(file) = on_nfs {
+            debug!("Polling for changes to files, since {} is on NFS",
+                   file.dir().display());
+        }
+        if opts.force_polling || on_nfs.is_some() {
+            Ok(Source::poll(opts))
+        } else {
+            Ok(Source::Inotify(Inotify::new(files)?))
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn new(_files: &[Watched], opts: WatchOptions) -> Result<Self> { Ok(Source::poll(opts)) }
+
+    fn poll(opts: WatchOptions) -> Self {
+        Source::Poll { interval:  opts.poll_interval,
+                       last_poll: Instant::now(), }
+    }
+
+    fn is_polling(&self) -> bool {
+        match *self {
+            Source::Poll { .. } => true,
+            #[cfg(target_os = "linux")]
+            Source::Inotify(_) => false,
+        }
+    }
+
+    /// Waits up to `timeout` for activity, marking the files on which there was any.
+    fn wait(&mut self, files: &mut [Watched], timeout: Duration) {
+        match *self {
+            Source::Poll { interval,
+                           ref mut last_poll, } => {
+                let next_poll = until(*last_poll + interval, Instant::now());
+                thread::sleep(timeout.min(next_poll));
+                if last_poll.elapsed() < interval {
+                    return;
+                }
+                *last_poll = Instant::now();
+                for file in files.iter_mut() {
+                    let current = Snapshot::of(&file.path);
+                    if current != file.polled {
+                        file.polled = current;
+                        file.active_since = Some(*last_poll);
+                    }
+                }
+            }
+            #[cfg(target_os = "linux")]
+            Source::Inotify(ref mut inotify) => {
+                if let Err(e) = inotify.wait(files, timeout) {
+                    warn!("Unable to read file notifications: {}", e);
+                    thread::sleep(timeout);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_nfs(dir: &Path) -> bool {
+    const NFS_SUPER_MAGIC: i64 = 0x6969;
+
+    let path = match CString::new(dir.as_os_str().as_bytes()) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    unsafe {
+        let mut buf: libc::statfs = mem::zeroed();
+        libc::statfs(path.as_ptr(), &mut buf) == 0 && buf.f_type as i64 == NFS_SUPER_MAGIC
+    }
+}
+
+#[cfg(target_os = "linux")]
+struct Inotify {
+    fd:   libc::c_int,
+    /// The directory each watch descriptor refers to.
+    dirs: HashMap<libc::c_int, PathBuf>,
+}
+
+#[cfg(target_os = "linux")]
+impl Inotify {
+    const MASK: u32 = libc::IN_ATTRIB
+                      | libc::IN_CLOSE_WRITE
+                      | libc::IN_CREATE
+                      | libc::IN_DELETE
+                      | libc::IN_MODIFY
+                      | libc::IN_MOVED_FROM
+                      | libc::IN_MOVED_TO;
+
+    fn new(files: &[Watched]) -> Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        // Owning the descriptor from here on closes it if a watch cannot be added
+        let mut inotify = Inotify { fd,
+                                    dirs: HashMap::new() };
+        for file in files {
+            inotify.add_watch(file.dir())?;
+            if file.is_dir {
+                inotify.add_watch(&file.path)?;
+            }
+        }
+        Ok(inotify)
+    }
+
+    fn add_watch(&mut self, dir: &Path) -> io::Result<()> {
+        if self.dirs.values().any(|d| d == dir) {
+            return Ok(());
+        }
+        let path = CString::new(dir.as_os_str().as_bytes())?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), Self::MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.dirs.insert(wd, dir.to_path_buf());
+        Ok(())
+    }
+
+    fn wait(&mut self, files: &mut [Watched], timeout: Duration) -> io::Result<()> {
+        let mut pollfd = libc::pollfd { fd:      self.fd,
+                                        events:  libc::POLLIN,
+                                        revents: 0, };
+        let timeout_ms = timeout.as_millis().min(libc::c_int::max_value() as u128) as libc::c_int;
+        if unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } < 0 {
+            let err = io::Error::last_os_error();
+            return if err.kind() == io::ErrorKind::Interrupted {
+                Ok(())
+            } else {
+                Err(err)
+            };
+        }
+
+        let mut buf = [0u8; 4096];
+        let now = Instant::now();
+        loop {
+            let len =
+                unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if len < 0 {
+                let err = io::Error::last_os_error();
+                return if err.kind() == io::ErrorKind::WouldBlock {
+                    Ok(())
+                } else {
+                    Err(err)
+                };
+            }
+            let len = len as usize;
+            let mut offset = 0;
+            while offset + mem::size_of::<libc::inotify_event>() <= len {
+                let event = unsafe {
+                    std::ptr::read_unaligned(buf[offset..].as_ptr() as *const libc::inotify_event)
+                };
+                let name_start = offset + mem::size_of::<libc::inotify_event>();
+                let name_end = (name_start + event.len as usize).min(len);
+                let name = buf[name_start..name_end].split(|&b| b == 0)
+                                                    .next()
+                                                    .unwrap_or(&[]);
+                offset = name_end;
+
+                if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                    // Events were lost, so anything may have changed
+                    for file in files.iter_mut() {
+                        file.active_since = Some(now);
+                        file.notified = true;
+                    }
+                    continue;
+                }
+                let dir = match self.dirs.get(&event.wd) {
+                    Some(dir) => dir,
+                    None => continue,
+                };
+                let path = dir.join(std::ffi::OsStr::from_bytes(name));
+                for file in files.iter_mut()
+                                 .filter(|f| f.path == path || (f.is_dir && f.path == *dir))
+                {
+                    file.active_since = Some(now);
+                    file.notified = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use tempfile::Builder;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn options(force_polling: bool) -> WatchOptions {
+        WatchOptions { debounce: Duration::from_millis(100),
+                       poll_interval: Duration::from_millis(50),
+                       force_polling }
+    }
+
+    fn expect(watcher: &DebouncedWatcher, path: &Path, kind: ChangeKind) {
+        let change = watcher.events().recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(Change { path: path.to_path_buf(),
+                            kind },
+                   change);
+    }
+
+    fn expect_nothing(watcher: &DebouncedWatcher) {
+        if let Ok(change) = watcher.events().recv_timeout(Duration::from_millis(400)) {
+            panic!("Unexpected {:?}", change);
+        }
+    }
+
+    fn lifecycle(force_polling: bool) {
+        let dir = Builder::new().prefix("watch").tempdir().unwrap();
+        let path = dir.path().join("user.toml");
+        let watcher = DebouncedWatcher::new(vec![path.clone()], options(force_polling)).unwrap();
+        assert_eq!(force_polling, watcher.is_polling());
+
+        fs::write(&path, "port = 1").unwrap();
+        expect(&watcher, &path, ChangeKind::Created);
+
+        // Replaced the way editors save, by renaming a new file over the old one
+        let tmp = dir.path().join(".user.toml.swp");
+        fs::write(&tmp, "port = 22").unwrap();
+        fs::rename(&tmp, &path).unwrap();
+        expect(&watcher, &path, ChangeKind::Modified);
+
+        fs::remove_file(&path).unwrap();
+        expect(&watcher, &path, ChangeKind::Removed);
+
+        // Files which are not watched are ignored
+        fs::write(dir.path().join("other.toml"), "").unwrap();
+        expect_nothing(&watcher);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn notifications() { lifecycle(false) }
+
+    #[test]
+    fn polling() { lifecycle(true) }
+
+    fn directory(force_polling: bool) {
+        let dir = Builder::new().prefix("watch").tempdir().unwrap();
+        let specs = dir.path().join("specs");
+        fs::create_dir(&specs).unwrap();
+        let watcher = DebouncedWatcher::new(vec![specs.clone()], options(force_polling)).unwrap();
+
+        fs::write(specs.join("redis.spec"), "ident = \"core/redis\"").unwrap();
+        expect(&watcher, &specs, ChangeKind::Modified);
+
+        let mut file = fs::OpenOptions::new().append(true)
+                                             .open(specs.join("redis.spec"))
+                                             .unwrap();
+        writeln!(file, "group = \"prod\"").unwrap();
+        drop(file);
+        expect(&watcher, &specs, ChangeKind::Modified);
+
+        fs::remove_file(specs.join("redis.spec")).unwrap();
+        expect(&watcher, &specs, ChangeKind::Modified);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn directory_notifications() { directory(false) }
+
+    #[test]
+    fn directory_polling() { directory(true) }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn bursts_are_debounced() {
+        let dir = Builder::new().prefix("watch").tempdir().unwrap();
+        let path = dir.path().join("user.toml");
+        fs::write(&path, "").unwrap();
+        let opts = WatchOptions { debounce: Duration::from_millis(300),
+                                  ..options(false) };
+        let watcher = DebouncedWatcher::new(vec![path.clone()], opts).unwrap();
+
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        for i in 0..5 {
+            writeln!(file, "line = {}", i).unwrap();
+        }
+        drop(file);
+
+        expect(&watcher, &path, ChangeKind::Modified);
+        expect_nothing(&watcher);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn missing_directory_fails() {
+        let dir = Builder::new().prefix("watch").tempdir().unwrap();
+        let path = dir.path().join("missing").join("user.toml");
+
+        assert!(DebouncedWatcher::new(vec![path], options(false)).is_err());
+    }
+}
@@ -0,0 +1,24 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Types shared with the Supervisor's HTTP gateway.
+//!
+//! The gateway's JSON responses are described in `types`, so that monitoring clients can
+//! deserialize them into the same types the Supervisor serializes, rather than picking fields
+//! out of untyped JSON.
+
+pub mod types;
+
+/// The port the gateway listens on unless told otherwise.
+pub const DEFAULT_PORT: u16 = 9631;
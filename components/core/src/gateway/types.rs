@@ -0,0 +1,222 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The bodies of the gateway's `/services` and `/census` responses.
+//!
+//! Every response carries the `api_version` it was written with. Within a version, fields are
+//! only ever added, and added fields are optional, so a client can read responses from a newer
+//! Supervisor by ignoring what it does not recognize. The version is only incremented when an
+//! existing field changes meaning or is removed; a client should refuse a response whose version
+//! is newer than `API_VERSION`.
+
+use std::collections::BTreeMap;
+
+use serde_derive::{Deserialize,
+                   Serialize};
+
+use crate::{election::ElectionStatus,
+            events::{ident_string,
+                     HealthStatus},
+            package::PackageIdent,
+            service::{state::ServiceState,
+                      update::UpdateStrategy,
+                      ServiceBind,
+                      ServiceGroup,
+                      Topology}};
+
+/// The version of the gateway's response types defined by this crate.
+pub const API_VERSION: u32 = 1;
+
+/// The body of `/services`: every service the Supervisor is running.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ServicesResponse {
+    pub api_version: u32,
+    pub services:    Vec<ServiceStatus>,
+}
+
+impl ServicesResponse {
+    pub fn new(services: Vec<ServiceStatus>) -> Self {
+        ServicesResponse { api_version: API_VERSION,
+                           services }
+    }
+
+    /// Whether the response can be understood by this crate.
+    pub fn is_supported(&self) -> bool { self.api_version <= API_VERSION }
+}
+
+/// One service the Supervisor is running, also the body of `/services/{service}/{group}`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ServiceStatus {
+    pub service_group:   ServiceGroup,
+    /// The release currently running.
+    #[serde(with = "ident_string")]
+    pub pkg:             PackageIdent,
+    pub state:           ServiceState,
+    pub health:          HealthStatus,
+    pub topology:        Topology,
+    pub update_strategy: UpdateStrategy,
+    #[serde(default)]
+    pub binds:           Vec<ServiceBind>,
+}
+
+/// The body of `/census`: the Supervisor's view of every service group in the ring.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CensusResponse {
+    pub api_version:   u32,
+    /// Whether anything has changed since the census was last read.
+    pub changed:       bool,
+    /// The service groups, keyed by their `service.group` name.
+    pub census_groups: BTreeMap<String, CensusGroup>,
+}
+
+impl CensusResponse {
+    pub fn new(changed: bool, census_groups: BTreeMap<String, CensusGroup>) -> Self {
+        CensusResponse { api_version: API_VERSION,
+                         changed,
+                         census_groups }
+    }
+
+    /// Whether the response can be understood by this crate.
+    pub fn is_supported(&self) -> bool { self.api_version <= API_VERSION }
+}
+
+/// One service group and its members.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CensusGroup {
+    pub service_group: ServiceGroup,
+    /// The state of the group's leader election, for groups with a leader topology.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub election:      Option<ElectionStatus>,
+    /// The members of the group, keyed by member ID.
+    pub members:       BTreeMap<String, CensusMember>,
+}
+
+/// How a member of the ring appears to the Supervisor answering the request.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberHealth {
+    Alive,
+    /// The member has stopped responding, but may yet return.
+    Suspect,
+    /// The member has been unresponsive long enough to be considered dead.
+    Confirmed,
+    /// The member has been removed from the ring.
+    Departed,
+}
+
+/// One member of a service group.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CensusMember {
+    pub member_id: String,
+    #[serde(with = "ident_string")]
+    pub pkg:       PackageIdent,
+    pub health:    MemberHealth,
+    pub leader:    bool,
+    pub follower:  bool,
+    pub sys:       SysInfo,
+    /// The configuration the member has exported to its peers.
+    #[serde(default)]
+    pub cfg:       serde_json::Map<String, serde_json::Value>,
+}
+
+/// Where a member can be reached.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SysInfo {
+    pub ip:                String,
+    pub hostname:          String,
+    pub gossip_ip:         String,
+    pub gossip_port:       u16,
+    pub http_gateway_ip:   String,
+    pub http_gateway_port: u16,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn services_round_trip() {
+        let service =
+            ServiceStatus { service_group:   ServiceGroup::from_str("redis.default").unwrap(),
+                            pkg:             PackageIdent::from_str("core/redis/4.0.14/\
+                                                                     20190319155852").unwrap(),
+                            state:           ServiceState::Up { pid:      1234,
+                                                                failures: 0, },
+                            health:          HealthStatus::Ok,
+                            topology:        Topology::Standalone,
+                            update_strategy: UpdateStrategy::None,
+                            binds:           Vec::new(), };
+        let response = ServicesResponse::new(vec![service]);
+
+        let value = serde_json::to_value(&response).unwrap();
+        assert_eq!(API_VERSION, value["api_version"]);
+        assert_eq!("core/redis/4.0.14/20190319155852",
+                   value["services"][0]["pkg"]);
+        assert_eq!("up", value["services"][0]["state"]["state"]);
+
+        let parsed: ServicesResponse = serde_json::from_value(value).unwrap();
+        assert_eq!(response, parsed);
+        assert!(parsed.is_supported());
+    }
+
+    #[test]
+    fn census_ignores_unknown_fields_and_defaults_optional_ones() {
+        let body = r#"{
+            "api_version": 1,
+            "changed": true,
+            "added_in_a_later_release": "ignored",
+            "census_groups": {
+                "redis.default": {
+                    "service_group": "redis.default",
+                    "members": {
+                        "6d1ab0b7": {
+                            "member_id": "6d1ab0b7",
+                            "pkg": "core/redis/4.0.14/20190319155852",
+                            "health": "suspect",
+                            "leader": false,
+                            "follower": false,
+                            "sys": {
+                                "ip": "10.0.0.4",
+                                "hostname": "redis-0",
+                                "gossip_ip": "0.0.0.0",
+                                "gossip_port": 9638,
+                                "http_gateway_ip": "0.0.0.0",
+                                "http_gateway_port": 9631
+                            }
+                        }
+                    }
+                }
+            }
+        }"#;
+
+        let census: CensusResponse = serde_json::from_str(body).unwrap();
+
+        let group = &census.census_groups["redis.default"];
+        assert_eq!(None, group.election);
+        let member = &group.members["6d1ab0b7"];
+        assert_eq!(MemberHealth::Suspect, member.health);
+        assert_eq!(9631, member.sys.http_gateway_port);
+        assert!(member.cfg.is_empty());
+    }
+
+    #[test]
+    fn newer_versions_are_unsupported() {
+        let census = CensusResponse { api_version:   API_VERSION + 1,
+                                      changed:       false,
+                                      census_groups: BTreeMap::new(), };
+
+        assert!(!census.is_supported());
+    }
+}
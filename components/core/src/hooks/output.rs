@@ -0,0 +1,143 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Parsing of hook output.
+//!
+//! A hook reports its result through its exit code, and whatever it prints is kept for the log.
+//! A hook which has more to say may end its output with a trailer: a last line holding a JSON
+//! object, such as
+//!
+//! ```text
+//! checked 3 replicas, 1 lagging
+//! {"health": "warning", "message": "replica-2 is 40s behind", "metrics": {"lag_seconds": 40}}
+//! ```
+//!
+//! The trailer can carry health details, a hint that the service should be reconfigured, and
+//! custom metrics. Only an object made up of the fields `Trailer` knows about counts as a
+//! trailer, so a hook which happens to print some other JSON last is read as plain output, and
+//! hooks without a trailer keep their exit-code-only meaning.
+
+use std::{collections::BTreeMap,
+          str};
+
+use serde_derive::{Deserialize,
+                   Serialize};
+use serde_json;
+
+use crate::{events::HealthStatus,
+            os::system};
+
+/// The structured result a hook may give on the last line of its output.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Trailer {
+    /// The health the hook reports, which takes precedence over its exit code.
+    #[serde(default)]
+    pub health:      Option<HealthStatus>,
+    /// Details to show alongside the result, such as the reason a health check failed.
+    #[serde(default)]
+    pub message:     Option<String>,
+    /// Asks for the service's configuration to be rendered again and the service reloaded.
+    #[serde(default)]
+    pub reconfigure: bool,
+    /// Custom metrics, by name.
+    #[serde(default)]
+    pub metrics:     BTreeMap<String, f64>,
+}
+
+/// A hook's output, split into its text and its trailer, if it gave one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HookOutput {
+    /// The output with any trailer removed.
+    pub text:    String,
+    pub trailer: Option<Trailer>,
+}
+
+impl HookOutput {
+    pub fn parse(output: &str) -> Self {
+        let body = output.trim_end();
+        let (text, last) = match body.rfind('\n') {
+            Some(i) => (&body[..=i], &body[i + 1..]),
+            None => ("", body),
+        };
+        let last = last.trim();
+        if last.starts_with('{') {
+            if let Ok(trailer) = serde_json::from_str::<Trailer>(last) {
+                return HookOutput { text:    text.to_string(),
+                                    trailer: Some(trailer), };
+            }
+        }
+        HookOutput { text:    output.to_string(),
+                     trailer: None, }
+    }
+
+    /// Parses raw output, decoding it according to the active locale.
+    pub fn from_bytes(output: &[u8]) -> Self { Self::parse(&system::decode_output(output)) }
+
+    /// The health the hook reports: the trailer's if it gave one, and otherwise that of its exit
+    /// code, where a hook which was killed by a signal has no exit code.
+    pub fn health(&self, exit_code: Option<i32>) -> HealthStatus {
+        self.trailer
+            .as_ref()
+            .and_then(|t| t.health)
+            .unwrap_or_else(|| {
+                exit_code.map_or(HealthStatus::Unknown, HealthStatus::from_exit_code)
+            })
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.trailer
+            .as_ref()
+            .and_then(|t| t.message.as_ref().map(String::as_str))
+    }
+
+    pub fn wants_reconfigure(&self) -> bool {
+        self.trailer.as_ref().map_or(false, |t| t.reconfigure)
+    }
+
+    pub fn metrics(&self) -> Option<&BTreeMap<String, f64>> {
+        self.trailer.as_ref().map(|t| &t.metrics)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn trailer_is_split_from_text() {
+        let output = HookOutput::parse("checked 3 replicas, 1 lagging\n{\"health\": \"warning\", \
+                                        \"message\": \"replica-2 is 40s behind\", \"metrics\": \
+                                        {\"lag_seconds\": 40}}\n");
+
+        assert_eq!("checked 3 replicas, 1 lagging\n", output.text);
+        assert_eq!(HealthStatus::Warning, output.health(Some(0)));
+        assert_eq!(Some("replica-2 is 40s behind"), output.message());
+        assert_eq!(Some(&40.0), output.metrics().unwrap().get("lag_seconds"));
+        assert!(!output.wants_reconfigure());
+        assert!(HookOutput::parse("{\"reconfigure\": true}").wants_reconfigure());
+    }
+
+    #[test]
+    fn output_without_trailer_falls_back_to_exit_code() {
+        let text = "starting\n{\"status\": \"green\"}\n";
+        let output = HookOutput::parse(text);
+
+        assert_eq!(text, output.text);
+        assert_eq!(None, output.trailer);
+        assert_eq!(HealthStatus::Ok, output.health(Some(0)));
+        assert_eq!(HealthStatus::Critical, output.health(Some(2)));
+        assert_eq!(HealthStatus::Unknown, output.health(None));
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hooks which belong to a package rather than to a service, and run when it is installed or
+//! uninstalled.
+//!
+//! A package which needs to do work on the machine it lands on, such as an interpreter building
+//! its caches, ships a `hooks/post-install` script. `PackageArchive::unpack_and_run_hooks` runs it
+//! once the package is unpacked under `/`, from the package's directory and with the environment
+//! `environment_for_command` gives it; a plain `unpack` leaves it to the caller. Its exit code is
+//! then recorded in a `POST_INSTALL_HOOK_STATUS` file under the package's state path, outside the
+//! package itself, so that a hook which succeeded is not run again; one which failed is retried the
+//! next time.
+//!
+//! A `hooks/pre-uninstall` script is run in the same way, each time the package is about to be
+//! removed, to undo anything its post-install hook set up outside the package.
+
+use std::{ffi::OsString,
+          fs,
+          io,
+          path::{Path,
+                 PathBuf},
+          process::{Command,
+                    Stdio}};
+
+use crate::{error::{Error,
+                    Result},
+            fs::pkg_state_path,
+            os::system,
+            package::{install::EnvironmentOptions,
+                      PackageInstall}};
+
+/// The post-install hook's path, relative to the package's installed path.
+pub const POST_INSTALL_HOOK: &str = "hooks/post-install";
+/// The pre-uninstall hook's path, relative to the package's installed path.
+pub const PRE_UNINSTALL_HOOK: &str = "hooks/pre-uninstall";
+/// The file recording the post-install hook's exit code, relative to the package's state path,
+/// `fs::pkg_state_path`.
+pub const POST_INSTALL_STATUS: &str = "POST_INSTALL_HOOK_STATUS";
+
+/// What happened when a package hook was asked to run.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HookRun {
+    /// The package has no such hook.
+    NoHook,
+    /// The hook already ran successfully, so was not run again.
+    AlreadyRan,
+    /// The hook ran successfully, printing `HookResult`'s output.
+    Ran(HookResult),
+    /// The hook failed, and the caller chose to carry on regardless.
+    Failed(HookResult),
+}
+
+/// The captured result of running a hook.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HookResult {
+    /// The hook's exit code, or `None` if it was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub stdout:    String,
+    pub stderr:    String,
+}
+
+impl HookResult {
+    pub fn success(&self) -> bool { self.exit_code == Some(0) }
+}
+
+/// Runs `pkg_install`'s post-install hook, unless it has none or the hook has already run
+/// successfully.
+///
+/// # Failures
+///
+/// * The package's runtime environment cannot be read
+/// * The hook cannot be started, or its status cannot be recorded
+/// * The hook fails, in which case the error carries its result
+pub fn run_post_install_hook(pkg_install: &PackageInstall) -> Result<HookRun> {
+    let hook = pkg_install.installed_path().join(POST_INSTALL_HOOK);
+    if !hook.is_file() {
+        return Ok(HookRun::NoHook);
+    }
+    let status_file = post_install_status_file(pkg_install);
+    if post_install_succeeded(&status_file)? {
+        debug!("Post-install hook of {} already ran", pkg_install.ident());
+        return Ok(HookRun::AlreadyRan);
+    }
+
+    let result = run_hook(pkg_install, &hook)?;
+    let status = result.exit_code
+                       .map_or_else(|| "signal".to_string(), |c| c.to_string());
+    if let Some(state_path) = status_file.parent() {
+        fs::create_dir_all(state_path)?;
+    }
+    fs::write(&status_file, status)?;
+    if result.success() {
+        Ok(HookRun::Ran(result))
+    } else {
+        Err(Error::PackageHookFailed(pkg_install.ident().clone(),
+                                     POST_INSTALL_HOOK,
+                                     Box::new(result)))
+    }
+}
+
+/// Runs `pkg_install`'s pre-uninstall hook, unless it has none.
+///
+/// # Failures
+///
+/// * The package's runtime environment cannot be read
+/// * The hook cannot be started
+/// * The hook fails, in which case the error carries its result
+pub fn run_pre_uninstall_hook(pkg_install: &PackageInstall) -> Result<HookRun> {
+    let hook = pkg_install.installed_path().join(PRE_UNINSTALL_HOOK);
+    if !hook.is_file() {
+        return Ok(HookRun::NoHook);
+    }
+    let result = run_hook(pkg_install, &hook)?;
+    if result.success() {
+        Ok(HookRun::Ran(result))
+    } else {
+        Err(Error::PackageHookFailed(pkg_install.ident().clone(),
+                                     PRE_UNINSTALL_HOOK,
+                                     Box::new(result)))
+    }
+}
+
+/// Forgets whether `pkg_install`'s post-install hook has run, so that it runs again if the
+/// package is reinstalled. This is done when the package is uninstalled.
+///
+/// # Failures
+///
+/// * The recorded status exists but cannot be removed
+pub fn clear_post_install_status(pkg_install: &PackageInstall) -> Result<()> {
+    match fs::remove_file(post_install_status_file(pkg_install)) {
+        Ok(()) => Ok(()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::IO(e)),
+    }
+}
+
+fn post_install_status_file(pkg_install: &PackageInstall) -> PathBuf {
+    pkg_state_path(pkg_install.ident(), Some(pkg_install.fs_root_path())).join(POST_INSTALL_STATUS)
+}
+
+/// Whether the status file at `path` records a successful run.
+fn post_install_succeeded(path: &Path) -> Result<bool> {
+    match fs::read_to_string(path) {
+        Ok(status) => Ok(status.trim() == "0"),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(Error::IO(e)),
+    }
+}
+
+/// Runs the hook at `hook` from the package's directory, with its runtime environment.
+fn run_hook(pkg_install: &PackageInstall, hook: &Path) -> Result<HookResult> {
+    let env = pkg_install.environment_for_command(EnvironmentOptions::default())?;
+    let (program, args) = hook_command(hook);
+    debug!("Running {} of {}", hook.display(), pkg_install.ident());
+    let output = Command::new(program).args(&args)
+                                      .envs(env)
+                                      .current_dir(pkg_install.installed_path())
+                                      .stdin(Stdio::null())
+                                      .output()?;
+    let result = HookResult { exit_code: output.status.code(),
+                              stdout:    system::decode_output(&output.stdout),
+                              stderr:    system::decode_output(&output.stderr), };
+    for line in result.stdout.lines().chain(result.stderr.lines()) {
+        info!("{} {}: {}",
+              pkg_install.ident(),
+              hook.file_name().unwrap_or_default().to_string_lossy(),
+              line);
+    }
+    Ok(result)
+}
+
+/// The program and arguments which run the hook at `hook`: the hook itself on Unix, where it
+/// names its interpreter, and PowerShell on Windows.
+#[cfg(not(windows))]
+fn hook_command(hook: &Path) -> (PathBuf, Vec<OsString>) { (hook.to_path_buf(), Vec::new()) }
+
+#[cfg(windows)]
+fn hook_command(hook: &Path) -> (PathBuf, Vec<OsString>) {
+    (PathBuf::from("pwsh.exe"),
+     vec!["-NonInteractive".into(),
+          "-NoProfile".into(),
+          "-ExecutionPolicy".into(),
+          "Bypass".into(),
+          "-File".into(),
+          hook.into()])
+}
+
+#[cfg(all(test, not(windows)))]
+pub(crate) mod test {
+    use super::*;
+    use crate::package::test_support::testing_package_install;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::Builder;
+
+    pub(crate) fn write_hook(pkg_install: &PackageInstall, name: &str, script: &str) {
+        let hook = pkg_install.installed_path().join(name);
+        fs::create_dir_all(hook.parent().unwrap()).unwrap();
+        fs::write(&hook, script).unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn post_install_hook_runs_once() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("core/ruby", fs_root.path());
+        assert_eq!(HookRun::NoHook,
+                   run_post_install_hook(&pkg_install).unwrap());
+
+        fs::write(pkg_install.installed_path().join("RUNTIME_ENVIRONMENT"),
+                  "GEM_HOME=/hab/cache/gems\n").unwrap();
+        write_hook(&pkg_install,
+                   POST_INSTALL_HOOK,
+                   "#!/bin/sh\necho \"building caches in $GEM_HOME\"\necho \"from $(pwd)\"\n");
+
+        match run_post_install_hook(&pkg_install).unwrap() {
+            HookRun::Ran(result) => {
+                assert!(result.success());
+                assert_eq!(format!("building caches in /hab/cache/gems\nfrom {}\n",
+                                   pkg_install.installed_path().display()),
+                           result.stdout);
+            }
+            other => panic!("Expected the hook to run, got {:?}", other),
+        }
+        assert_eq!(HookRun::AlreadyRan,
+                   run_post_install_hook(&pkg_install).unwrap());
+        assert!(!pkg_install.installed_path()
+                            .join(POST_INSTALL_STATUS)
+                            .exists());
+        assert_eq!("0",
+                   fs::read_to_string(post_install_status_file(&pkg_install)).unwrap());
+
+        clear_post_install_status(&pkg_install).unwrap();
+        match run_post_install_hook(&pkg_install).unwrap() {
+            HookRun::Ran(ref result) => assert!(result.success()),
+            other => panic!("Expected the hook to run again, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn failed_post_install_hook_is_retried() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("core/ruby", fs_root.path());
+        write_hook(&pkg_install,
+                   POST_INSTALL_HOOK,
+                   "#!/bin/sh\necho 'no compiler' >&2\nexit 3\n");
+
+        match run_post_install_hook(&pkg_install) {
+            Err(Error::PackageHookFailed(_, POST_INSTALL_HOOK, ref result)) => {
+                assert_eq!(Some(3), result.exit_code);
+                assert_eq!("no compiler\n", result.stderr);
+            }
+            other => panic!("Expected PackageHookFailed, got {:?}", other),
+        }
+
+        write_hook(&pkg_install, POST_INSTALL_HOOK, "#!/bin/sh\nexit 0\n");
+        match run_post_install_hook(&pkg_install).unwrap() {
+            HookRun::Ran(ref result) => assert!(result.success()),
+            other => panic!("Expected the hook to run, got {:?}", other),
+        }
+    }
+}
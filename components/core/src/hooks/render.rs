@@ -0,0 +1,153 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rendering a service's hooks outside the Supervisor.
+//!
+//! A package's hooks are templates: the Supervisor renders each into `/hab/svc/<name>/hooks`
+//! before it runs it, and only the rendered copy can be run. Anything else which runs a service,
+//! such as a systemd unit or launchd job, renders its hooks here first and runs them from there.
+//!
+//! Outside the Supervisor there is no census, so a hook is rendered with the `pkg` and `cfg`
+//! values of the service alone; `sys`, `svc` and `bind` render as empty.
+
+use std::path::{Path,
+                PathBuf};
+
+use serde_json::{self,
+                 json,
+                 Value as Json};
+
+use crate::{config::layered::ConfigSources,
+            error::Result,
+            fs::{svc_config_path,
+                 svc_data_path,
+                 svc_files_path,
+                 svc_path,
+                 svc_pid_file,
+                 svc_static_path,
+                 svc_var_path},
+            package::{install::{lossy_environment,
+                                EnvironmentOptions},
+                      PackageInstall},
+            templating::TemplateRenderer};
+
+/// Returns the context `service_name`'s hooks are rendered with when it runs `pkg_install`.
+///
+/// # Failures
+///
+/// * A metafile exists but cannot be properly parsed
+/// * A layer of the service's configuration cannot be read
+pub fn hook_context(pkg_install: &PackageInstall, service_name: &str) -> Result<Json> {
+    let ident = pkg_install.ident();
+    let cfg = ConfigSources::new(pkg_install, service_name).load()?
+                                                           .into_value();
+    let env =
+        lossy_environment(pkg_install.environment_for_command(EnvironmentOptions::default())?);
+    Ok(json!({
+        "pkg": {
+            "ident": ident.to_string(),
+            "origin": ident.origin,
+            "name": ident.name,
+            "version": ident.version,
+            "release": ident.release,
+            "deps": pkg_install.tdeps()?,
+            "env": env,
+            "exposes": pkg_install.exposes()?,
+            "exports": pkg_install.exports()?,
+            "path": pkg_install.installed_path(),
+            "svc_path": svc_path(service_name),
+            "svc_config_path": svc_config_path(service_name),
+            "svc_data_path": svc_data_path(service_name),
+            "svc_files_path": svc_files_path(service_name),
+            "svc_static_path": svc_static_path(service_name),
+            "svc_var_path": svc_var_path(service_name),
+            "svc_pid_file": svc_pid_file(service_name),
+            "svc_user": pkg_install.svc_user()?.map(|u| u.trim().to_string()),
+            "svc_group": pkg_install.svc_group()?.map(|g| g.trim().to_string()),
+        },
+        "cfg": serde_json::to_value(cfg)?,
+    }))
+}
+
+/// Renders `pkg_install`'s `hooks/<hook>` template for the service `service_name` into
+/// `hooks_dir`, normally `fs::svc_hooks_path(service_name)`, and returns the path of the
+/// rendered hook. The rendered hook is only rewritten when it changes, and can be run by its
+/// owner and group.
+///
+/// # Failures
+///
+/// * The package has no such hook, or it is not a valid template
+/// * The hook's context cannot be built
+/// * The rendered hook cannot be written
+pub fn render_hook(pkg_install: &PackageInstall,
+                   service_name: &str,
+                   hook: &str,
+                   hooks_dir: &Path)
+                   -> Result<PathBuf> {
+    let mut renderer = TemplateRenderer::new();
+    renderer.register_template_file(hook, pkg_install.installed_path().join("hooks").join(hook))?;
+    let ctx = hook_context(pkg_install, service_name)?;
+    std::fs::create_dir_all(hooks_dir)?;
+    let dest = hooks_dir.join(hook);
+    renderer.render_to_file(hook, &ctx, &dest)?;
+    set_executable(&dest)?;
+    Ok(dest)
+}
+
+#[cfg(not(windows))]
+fn set_executable(path: &Path) -> Result<()> {
+    use crate::{fs::PermissionPolicy,
+                util::posix_perm};
+
+    posix_perm::set_permissions(path, PermissionPolicy::current().mode(0o770))
+}
+
+#[cfg(windows)]
+fn set_executable(_path: &Path) -> Result<()> { Ok(()) }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::test_support::testing_package_install;
+    use std::fs;
+    use tempfile::Builder;
+
+    #[test]
+    fn renders_run_hook_with_pkg_and_cfg() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        let hooks = pkg_install.installed_path().join("hooks");
+        fs::create_dir_all(&hooks).unwrap();
+        fs::write(hooks.join("run"),
+                  "#!/bin/sh\nexec {{pkg.path}}/bin/pathy --port {{cfg.port}}\n").unwrap();
+        fs::write(pkg_install.installed_path().join("default.toml"),
+                  "port = 8080\n").unwrap();
+        let hooks_dir = fs_root.path().join("svc").join("pathy").join("hooks");
+
+        let run = render_hook(&pkg_install, "pathy-never-configured", "run", &hooks_dir).unwrap();
+
+        assert_eq!(hooks_dir.join("run"), run);
+        assert_eq!(format!("#!/bin/sh\nexec {}/bin/pathy --port 8080\n",
+                           pkg_install.installed_path().display()),
+                   fs::read_to_string(&run).unwrap());
+    }
+
+    #[test]
+    fn missing_hook_is_an_error() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+
+        assert!(render_hook(&pkg_install, "pathy", "run", fs_root.path()).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The value a suitability hook reports.
+//!
+//! A service's suitability hook tells leader elections how well suited the member is to lead:
+//! it prints a whole number between 0 and 2^64 - 1 on the last line of its output and exits 0,
+//! and the member with the highest suitability wins. A member whose hook is missing, fails, or
+//! prints anything else has the default suitability of 0, so it only leads when no better
+//! member is available.
+
+use std::{fmt,
+          str::FromStr,
+          u64};
+
+use serde_derive::{Deserialize,
+                   Serialize};
+
+use crate::error::{Error,
+                   Result};
+
+/// How well suited a member is to lead, as used by `election::Election`. Higher is better.
+#[derive(Clone,
+         Copy,
+         Debug,
+         Default,
+         Deserialize,
+         Eq,
+         Ord,
+         PartialEq,
+         PartialOrd,
+         Serialize)]
+#[serde(transparent)]
+pub struct Suitability(pub u64);
+
+impl Suitability {
+    /// Reads the value from the last non-empty line of a suitability hook's output.
+    ///
+    /// # Failures
+    ///
+    /// * The output is empty
+    /// * The last line is not a whole number between 0 and 2^64 - 1
+    pub fn from_hook_output(output: &str) -> Result<Self> {
+        output.lines()
+              .rev()
+              .map(str::trim)
+              .find(|line| !line.is_empty())
+              .ok_or_else(|| Error::InvalidSuitability("no output".to_string()))?
+              .parse()
+    }
+
+    /// The suitability reported by a hook which exited with `exit_code` after printing `output`.
+    /// A hook which failed, or whose output cannot be read, reports the default.
+    pub fn from_hook_result(exit_code: Option<i32>, output: &str) -> Self {
+        if exit_code != Some(0) {
+            warn!("Suitability hook failed with exit code {:?}, using the default suitability",
+                  exit_code);
+            return Self::default();
+        }
+        Self::from_hook_output(output).unwrap_or_else(|e| {
+                                          warn!("{}, using the default suitability", e);
+                                          Self::default()
+                                      })
+    }
+}
+
+impl FromStr for Suitability {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(Error::InvalidSuitability(format!("'{}' is not a whole \
+                                                          number",
+                                                         value)));
+        }
+        value.parse().map(Suitability).map_err(|_| {
+                                          Error::InvalidSuitability(format!("'{}' is greater \
+                                                                             than {}",
+                                                                            value,
+                                                                            u64::MAX))
+                                      })
+    }
+}
+
+impl fmt::Display for Suitability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", self.0) }
+}
+
+impl From<u64> for Suitability {
+    fn from(value: u64) -> Self { Suitability(value) }
+}
+
+impl From<Suitability> for u64 {
+    fn from(suitability: Suitability) -> Self { suitability.0 }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_bounds() {
+        assert_eq!(Suitability(0), "0".parse().unwrap());
+        assert_eq!(Suitability(u64::MAX),
+                   "18446744073709551615".parse().unwrap());
+        assert!("18446744073709551616".parse::<Suitability>().is_err());
+        assert!("-1".parse::<Suitability>().is_err());
+        assert!("+1".parse::<Suitability>().is_err());
+        assert!("1.5".parse::<Suitability>().is_err());
+    }
+
+    #[test]
+    fn hook_output() {
+        assert_eq!(Suitability(42),
+                   Suitability::from_hook_output("checking replication lag\n42\n\n").unwrap());
+        assert!(Suitability::from_hook_output("\n").is_err());
+        assert_eq!(Suitability(42),
+                   Suitability::from_hook_result(Some(0), "42"));
+        assert_eq!(Suitability::default(),
+                   Suitability::from_hook_result(Some(1), "42"));
+        assert_eq!(Suitability::default(),
+                   Suitability::from_hook_result(Some(0), "very"));
+        assert_eq!("42", serde_json::to_string(&Suitability(42)).unwrap());
+    }
+}
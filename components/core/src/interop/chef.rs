@@ -0,0 +1,237 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Package and service facts for Chef Infra and InSpec.
+//!
+//! `NodeFacts` serializes as flat lists of records, the shape InSpec's `json` resource and
+//! custom resources built on `FilterTable` expect:
+//!
+//! ```json
+//! {
+//!   "packages": [
+//!     { "ident": "core/redis/4.0.14/20190319155852", "origin": "core", "name": "redis",
+//!       "version": "4.0.14", "release": "20190319155852" }
+//!   ],
+//!   "services": [
+//!     { "service_group": "redis.default", "service": "redis", "group": "default",
+//!       "package": "core/redis/4.0.14/20190319155852", "state": "up", "running": true,
+//!       "pid": 4242 }
+//!   ]
+//! }
+//! ```
+//!
+//! `NodeFacts::to_ohai` gives the same facts as an attribute tree for an Ohai plugin to merge
+//! into the node's automatic attributes under `habitat`, keyed by package and service group so
+//! recipes can look them up with `node['habitat']['packages']['core/redis']`.
+
+use std::{collections::BTreeMap,
+          path::Path};
+
+use serde_derive::Serialize;
+
+use crate::{error::Result,
+            fs::pkg_root_path,
+            package::{list,
+                      PackageIdent},
+            service::{state::ServiceState,
+                      ServiceGroup}};
+
+/// An installed package release.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct PackageFact {
+    pub ident:   String,
+    pub origin:  String,
+    pub name:    String,
+    pub version: String,
+    pub release: String,
+}
+
+impl PackageFact {
+    /// Returns `None` for an ident which is not fully qualified, and so is not an installed
+    /// release.
+    pub fn new(ident: &PackageIdent) -> Option<Self> {
+        match (&ident.version, &ident.release) {
+            (Some(version), Some(release)) => {
+                Some(PackageFact { ident:   ident.to_string(),
+                                   origin:  ident.origin.clone(),
+                                   name:    ident.name.clone(),
+                                   version: version.clone(),
+                                   release: release.clone(), })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A supervised service and the state it is in.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ServiceFact {
+    pub service_group: String,
+    pub service:       String,
+    pub group:         String,
+    /// The package the service is running from.
+    pub package:       String,
+    /// As shown by `ServiceState`'s `Display`, such as `up` or `failed`.
+    pub state:         String,
+    /// Whether the service's process may be running.
+    pub running:       bool,
+    pub pid:           Option<u32>,
+}
+
+impl ServiceFact {
+    pub fn new(service_group: &ServiceGroup, package: &PackageIdent, state: ServiceState) -> Self {
+        let pid = match state {
+            ServiceState::Up { pid, .. } => Some(pid),
+            _ => None,
+        };
+        ServiceFact { service_group: service_group.to_string(),
+                      service: service_group.service().to_string(),
+                      group: service_group.group().to_string(),
+                      package: package.to_string(),
+                      state: state.to_string(),
+                      running: state.is_running(),
+                      pid }
+    }
+}
+
+/// The packages installed on a node and the services it is supervising.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct NodeFacts {
+    /// Sorted by origin, name, version and release.
+    pub packages: Vec<PackageFact>,
+    /// Sorted by service group.
+    pub services: Vec<ServiceFact>,
+}
+
+impl NodeFacts {
+    /// Gathers the packages installed under `fs_root_path` along with the given `services`,
+    /// whose states only the Supervisor knows.
+    ///
+    /// # Failures
+    ///
+    /// * The package root cannot be read
+    pub fn collect(fs_root_path: Option<&Path>, services: Vec<ServiceFact>) -> Result<Self> {
+        let mut idents = list::all_packages(&pkg_root_path(fs_root_path))?;
+        idents.sort_by(PackageIdent::by_parts_cmp);
+        Ok(Self::new(idents.iter()
+                           .filter_map(PackageFact::new)
+                           .collect(),
+                     services))
+    }
+
+    pub fn new(packages: Vec<PackageFact>, mut services: Vec<ServiceFact>) -> Self {
+        services.sort_by(|a, b| a.service_group.cmp(&b.service_group));
+        NodeFacts { packages, services }
+    }
+
+    /// The facts as Ohai attributes, to be found under `node['habitat']`.
+    pub fn to_ohai(&self) -> OhaiAttributes {
+        let mut packages = BTreeMap::<String, OhaiPackage>::new();
+        for package in &self.packages {
+            let entry = packages.entry(format!("{}/{}", package.origin, package.name))
+                                .or_default();
+            // Packages are sorted, so the last release seen is the newest.
+            entry.version = package.version.clone();
+            entry.release = package.release.clone();
+            entry.ident = package.ident.clone();
+            entry.installed
+                 .push(format!("{}/{}", package.version, package.release));
+        }
+        let services = self.services
+                           .iter()
+                           .map(|s| (s.service_group.clone(), s.clone()))
+                           .collect();
+        OhaiAttributes { habitat: OhaiHabitat { packages, services }, }
+    }
+}
+
+/// The top level of the Ohai attribute tree.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct OhaiAttributes {
+    pub habitat: OhaiHabitat,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct OhaiHabitat {
+    /// Keyed by `origin/name`.
+    pub packages: BTreeMap<String, OhaiPackage>,
+    /// Keyed by service group.
+    pub services: BTreeMap<String, ServiceFact>,
+}
+
+/// The releases of one package which are installed, described by the newest of them.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct OhaiPackage {
+    pub ident:     String,
+    pub version:   String,
+    pub release:   String,
+    /// Every installed release, as `version/release`, oldest first.
+    pub installed: Vec<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::test_support::testing_package_install;
+    use serde_json::{self,
+                     json};
+    use std::str::FromStr;
+    use tempfile::Builder;
+
+    #[test]
+    fn facts_in_inspec_and_ohai_shapes() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        testing_package_install("core/redis/4.0.14/20190319155852", fs_root.path());
+        testing_package_install("core/redis/3.2.4/20170514150022", fs_root.path());
+        testing_package_install("acme/app/0.1.0/20190101000000", fs_root.path());
+        let redis = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+        let app = PackageIdent::from_str("acme/app/0.1.0/20190101000000").unwrap();
+        let services = vec![ServiceFact::new(&ServiceGroup::from_str("redis.default").unwrap(),
+                                             &redis,
+                                             ServiceState::Up { pid:      4242,
+                                                                failures: 0, }),
+                            ServiceFact::new(&ServiceGroup::from_str("app.prod").unwrap(),
+                                             &app,
+                                             ServiceState::Failed { failures: 3 }),];
+
+        let facts = NodeFacts::collect(Some(fs_root.path()), services).unwrap();
+
+        let inspec = serde_json::to_value(&facts).unwrap();
+        assert_eq!(json!(["acme/app/0.1.0/20190101000000",
+                          "core/redis/3.2.4/20170514150022",
+                          "core/redis/4.0.14/20190319155852"]),
+                   json!(inspec["packages"].as_array()
+                                           .unwrap()
+                                           .iter()
+                                           .map(|p| p["ident"].clone())
+                                           .collect::<Vec<_>>()));
+        assert_eq!(json!({ "service_group": "app.prod",
+                           "service": "app",
+                           "group": "prod",
+                           "package": "acme/app/0.1.0/20190101000000",
+                           "state": "failed",
+                           "running": false,
+                           "pid": null }),
+                   inspec["services"][0]);
+
+        let ohai = serde_json::to_value(facts.to_ohai()).unwrap();
+        assert_eq!(json!({ "ident": "core/redis/4.0.14/20190319155852",
+                           "version": "4.0.14",
+                           "release": "20190319155852",
+                           "installed": ["3.2.4/20170514150022", "4.0.14/20190319155852"] }),
+                   ohai["habitat"]["packages"]["core/redis"]);
+        assert_eq!(json!(4242),
+                   ohai["habitat"]["services"]["redis.default"]["pid"]);
+    }
+}
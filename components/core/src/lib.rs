@@ -16,18 +16,81 @@
 #[macro_use]
 extern crate log;
 
+// Without the `fs` feature, which is on by default, only what needs neither a filesystem nor
+// native libraries is built: package idents, targets, version ordering and metafile parsing, with
+// the errors, environment configuration and Builder URLs they use. This is the subset which builds
+// for `wasm32-unknown-unknown` with `default-features = false`, so that browser tooling can parse
+// and order packages as Habitat does.
+#[cfg(all(target_arch = "wasm32", feature = "fs"))]
+compile_error!("habitat_core only builds for wasm32 without the `fs` feature; disable the \
+                default features");
+
 pub use self::error::{Error,
                       Result};
 
+#[cfg(feature = "async")]
+pub mod async_io;
+#[cfg(feature = "fs")]
+pub mod auth;
+#[cfg(feature = "fs")]
 pub mod binlink;
+#[cfg(feature = "fs")]
+pub mod build_info;
+#[cfg(feature = "fs")]
+pub mod cli_support;
+#[cfg(feature = "fs")]
 pub mod config;
+#[cfg(feature = "fs")]
+pub mod crash;
+#[cfg(feature = "fs")]
 pub mod crypto;
+#[cfg(feature = "fs")]
+pub mod election;
 pub mod env;
 pub mod error;
+#[cfg(feature = "fs")]
+pub mod events;
+#[cfg(feature = "fs")]
+pub mod exit_code;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fs")]
 pub mod fs;
+#[cfg(feature = "fs")]
+pub mod gateway;
+#[cfg(feature = "fs")]
+pub mod hooks;
+#[cfg(feature = "fs")]
+pub mod interop;
+#[cfg(feature = "fs")]
+pub mod lock;
+#[cfg(feature = "fs")]
+pub mod logger;
+#[cfg(feature = "fs")]
+pub mod machine;
+#[cfg(feature = "fs")]
+pub mod metrics;
+#[cfg(feature = "fs")]
+pub mod objectstore;
+#[cfg(feature = "fs")]
 pub mod os;
 pub mod package;
+#[cfg(feature = "fs")]
+pub mod probe;
+#[cfg(feature = "fs")]
+pub mod rumor;
+#[cfg(feature = "fs")]
 pub mod service;
+#[cfg(feature = "fs")]
+pub mod shutdown;
+#[cfg(feature = "fs")]
+pub mod support;
+#[cfg(feature = "fs")]
+pub mod swim;
+#[cfg(feature = "fs")]
+pub mod templating;
+#[cfg(feature = "tls")]
+pub mod tls;
 pub mod url;
 pub mod util;
 
@@ -36,6 +99,7 @@ use std::fmt;
 use serde_derive::{Deserialize,
                    Serialize};
 
+#[cfg(feature = "fs")]
 pub use crate::os::{filesystem,
                     users};
 
@@ -0,0 +1,348 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The lock which keeps more than one Supervisor from running against the same filesystem root.
+//!
+//! Two Supervisors sharing a root fight over the same service directories, rumor stores, and
+//! pid files, corrupting them without either noticing. A Supervisor therefore takes a
+//! `SupLock` before touching any of it, and holds it until it exits.
+//!
+//! The lock is a file, `hab/sup/default/LOCK` under the root, recording the PID and start time
+//! of the process holding it. It is created with its content in place in a single step, so no
+//! one ever sees a half-written lock. A Supervisor which dies without releasing it leaves the
+//! file behind; such a lock is stale once no running process matches it, and the next
+//! Supervisor to start clears it. Comparing start times as well as PIDs keeps a lock from
+//! looking held when its PID has since been given to an unrelated process.
+//!
+//! Older Supervisors wrote only their PID to the lock, and such a lock is still honoured. A lock
+//! which cannot be read as either is never cleared, since there is no telling whether it is
+//! stale; it has to be removed by hand.
+
+use std::{fs,
+          io::{self,
+               Write},
+          path::{Path,
+                 PathBuf},
+          time::{SystemTime,
+                 UNIX_EPOCH}};
+
+use serde_derive::{Deserialize,
+                   Serialize};
+use serde_json;
+use tempfile;
+
+use crate::{error::{Error,
+                    Result},
+            fs::FS_ROOT_PATH,
+            os::process::{self,
+                          Pid}};
+
+/// Where the lock is kept, relative to the filesystem root.
+pub const LOCK_PATH: &str = "hab/sup/default/LOCK";
+
+/// How many times acquisition clears a stale lock before giving up, so that processes racing
+/// for the lock cannot go on clearing each other's.
+const ACQUIRE_ATTEMPTS: usize = 3;
+
+/// Returns the path to the lock, optionally taking a custom filesystem root.
+pub fn lock_path(fs_root_path: Option<&Path>) -> PathBuf {
+    fs_root_path.unwrap_or(&*FS_ROOT_PATH).join(LOCK_PATH)
+}
+
+/// The process which took a lock.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LockHolder {
+    pub pid:         Pid,
+    /// When the process started, as `os::process::start_time` reports it, if known.
+    pub start_time:  Option<u64>,
+    /// When the lock was taken, in seconds since the Unix epoch.
+    pub acquired_at: u64,
+}
+
+impl LockHolder {
+    fn current() -> Self {
+        let pid = process::current_pid();
+        let acquired_at = SystemTime::now().duration_since(UNIX_EPOCH)
+                                           .map(|d| d.as_secs())
+                                           .unwrap_or(0);
+        LockHolder { pid,
+                     start_time: process::start_time(pid),
+                     acquired_at }
+    }
+
+    /// Whether the process which took the lock is still running. Where start times are not
+    /// known, any running process with the same PID counts.
+    pub fn is_running(&self) -> bool {
+        if !process::is_alive(self.pid) {
+            return false;
+        }
+        match (self.start_time, process::start_time(self.pid)) {
+            (Some(recorded), Some(actual)) => recorded == actual,
+            _ => true,
+        }
+    }
+}
+
+/// A held Supervisor lock, released when dropped.
+#[derive(Debug)]
+pub struct SupLock {
+    path:   PathBuf,
+    holder: LockHolder,
+}
+
+impl SupLock {
+    /// Takes the lock for the filesystem root `fs_root_path`, clearing it first if it is stale.
+    ///
+    /// # Failures
+    ///
+    /// * A running process holds the lock, which fails with `Error::SupervisorLocked`
+    /// * The lock cannot be parsed, which fails with `Error::SupervisorLockUnreadable`
+    /// * The lock cannot be read or written
+    pub fn acquire(fs_root_path: Option<&Path>) -> Result<Self> {
+        let path = lock_path(fs_root_path);
+        let dir = path.parent().expect("lock path has a parent");
+        fs::create_dir_all(dir)?;
+        let holder = LockHolder::current();
+
+        let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+        tmp.write_all(serde_json::to_string(&holder)?.as_bytes())?;
+        tmp.as_file().sync_all()?;
+
+        for _ in 0..ACQUIRE_ATTEMPTS {
+            // Unlike a rename, a hard link fails if the lock already exists.
+            match fs::hard_link(tmp.path(), &path) {
+                Ok(()) => {
+                    debug!("Acquired Supervisor lock {}", path.display());
+                    return Ok(SupLock { path, holder });
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+            match read_holder(&path) {
+                Ok(Some(existing)) => {
+                    if existing.is_running() {
+                        return Err(Error::SupervisorLocked(path, existing));
+                    }
+                    warn!("Clearing stale Supervisor lock {} left by PID {}",
+                          path.display(),
+                          existing.pid);
+                    clear_stale(&path, &existing)?;
+                }
+                Ok(None) => return Err(Error::SupervisorLockUnreadable(path)),
+                // Released since the link was attempted
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        match read_holder(&path) {
+            Ok(Some(existing)) => Err(Error::SupervisorLocked(path, existing)),
+            _ => {
+                Err(Error::IO(io::Error::new(io::ErrorKind::WouldBlock,
+                                             format!("Could not acquire \
+                                                      Supervisor lock {}",
+                                                     path.display()))))
+            }
+        }
+    }
+
+    /// Returns the process holding the lock for the filesystem root `fs_root_path`, or `None`
+    /// if it is not held or the lock is stale.
+    ///
+    /// # Failures
+    ///
+    /// * The lock exists but cannot be read or parsed
+    pub fn holder_of(fs_root_path: Option<&Path>) -> Result<Option<LockHolder>> {
+        let path = lock_path(fs_root_path);
+        match read_holder(&path) {
+            Ok(Some(holder)) => Ok(Some(holder).filter(LockHolder::is_running)),
+            Ok(None) => Err(Error::SupervisorLockUnreadable(path)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    pub fn holder(&self) -> &LockHolder { &self.holder }
+
+    /// Releases the lock, as dropping it does, but reports any failure to remove it.
+    ///
+    /// # Failures
+    ///
+    /// * The lock cannot be removed
+    pub fn release(mut self) -> Result<()> {
+        let result = self.remove();
+        // Nothing is left for `drop` to do.
+        self.path = PathBuf::new();
+        result
+    }
+
+    fn remove(&self) -> Result<()> {
+        // The lock is only removed if it is still this one, in case another process wrongly
+        // took it over as stale.
+        match read_holder(&self.path) {
+            Ok(Some(ref holder)) if *holder == self.holder => {
+                fs::remove_file(&self.path)?;
+                debug!("Released Supervisor lock {}", self.path.display());
+                Ok(())
+            }
+            Ok(_) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for SupLock {
+    fn drop(&mut self) {
+        if self.path.as_os_str().is_empty() {
+            return;
+        }
+        if let Err(e) = self.remove() {
+            warn!("Failed to release Supervisor lock {}: {}",
+                  self.path.display(),
+                  e);
+        }
+    }
+}
+
+/// Reads the holder recorded in a lock, which is `None` if the lock cannot be parsed. A lock
+/// holding only a PID, as older Supervisors wrote, has a holder whose start time is not known.
+fn read_holder(path: &Path) -> io::Result<Option<LockHolder>> {
+    let content = fs::read_to_string(path)?;
+    if let Ok(holder) = serde_json::from_str(&content) {
+        return Ok(Some(holder));
+    }
+    // Signalling PID 0 or a negative PID reaches a whole group of processes, so those are
+    // not taken as the PID of a holder.
+    let pid = content.trim().parse::<Pid>().ok().filter(|pid| *pid > 0);
+    Ok(pid.map(|pid| {
+              LockHolder { pid,
+                           start_time: None,
+                           acquired_at: 0 }
+          }))
+}
+
+/// Removes the stale lock at `path`, which held `stale` when it was read. The lock is moved
+/// aside before it is removed, and put back if it turns out to have been replaced with a live
+/// one in the meantime.
+fn clear_stale(path: &Path, stale: &LockHolder) -> Result<()> {
+    let moved = path.with_file_name(format!("LOCK.stale.{}", process::current_pid()));
+    match fs::rename(path, &moved) {
+        Ok(()) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+    let replaced = match read_holder(&moved) {
+        Ok(holder) => holder.as_ref() != Some(stale),
+        Err(_) => false,
+    };
+    if replaced {
+        // Failing to put it back means yet another process holds the lock, which acquisition
+        // will find.
+        let _ = fs::hard_link(&moved, path);
+    }
+    fs::remove_file(&moved)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::process::Command;
+    use tempfile::Builder;
+
+    #[test]
+    fn only_one_holder_at_a_time() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+
+        let lock = SupLock::acquire(Some(fs_root.path())).unwrap();
+        assert_eq!(Some(lock.holder().clone()),
+                   SupLock::holder_of(Some(fs_root.path())).unwrap());
+        match SupLock::acquire(Some(fs_root.path())) {
+            Err(Error::SupervisorLocked(_, holder)) => {
+                assert_eq!(process::current_pid(), holder.pid)
+            }
+            other => panic!("expected SupervisorLocked, got {:?}", other),
+        }
+
+        lock.release().unwrap();
+        assert!(!lock_path(Some(fs_root.path())).exists());
+        assert_eq!(None, SupLock::holder_of(Some(fs_root.path())).unwrap());
+        drop(SupLock::acquire(Some(fs_root.path())).unwrap());
+        assert!(!lock_path(Some(fs_root.path())).exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn stale_locks_are_cleared() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let path = lock_path(Some(fs_root.path()));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut child = Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        let dead = LockHolder { pid:         child.id() as Pid,
+                                start_time:  None,
+                                acquired_at: 0, };
+        fs::write(&path, serde_json::to_string(&dead).unwrap()).unwrap();
+        assert_eq!(None, SupLock::holder_of(Some(fs_root.path())).unwrap());
+
+        let lock = SupLock::acquire(Some(fs_root.path())).unwrap();
+
+        assert_eq!(process::current_pid(), lock.holder().pid);
+        assert_eq!(vec![path.clone()],
+                   fs::read_dir(path.parent().unwrap()).unwrap()
+                                                       .map(|e| e.unwrap().path())
+                                                       .collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn locks_holding_only_a_pid_are_honoured() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let path = lock_path(Some(fs_root.path()));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, format!("{}\n", process::current_pid())).unwrap();
+
+        match SupLock::acquire(Some(fs_root.path())) {
+            Err(Error::SupervisorLocked(_, holder)) => {
+                assert_eq!(process::current_pid(), holder.pid);
+                assert_eq!(None, holder.start_time);
+            }
+            other => panic!("expected SupervisorLocked, got {:?}", other),
+        }
+
+        let mut child = Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        fs::write(&path, child.id().to_string()).unwrap();
+        assert_eq!(None, SupLock::holder_of(Some(fs_root.path())).unwrap());
+        let lock = SupLock::acquire(Some(fs_root.path())).unwrap();
+        assert_eq!(process::current_pid(), lock.holder().pid);
+    }
+
+    #[test]
+    fn unreadable_locks_are_left_alone() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let path = lock_path(Some(fs_root.path()));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "not a lock").unwrap();
+
+        match SupLock::acquire(Some(fs_root.path())) {
+            Err(Error::SupervisorLockUnreadable(p)) => assert_eq!(path, p),
+            other => panic!("expected SupervisorLockUnreadable, got {:?}", other),
+        }
+        assert!(SupLock::holder_of(Some(fs_root.path())).is_err());
+        assert_eq!("not a lock", fs::read_to_string(&path).unwrap());
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A `Sink` which writes records to the Windows Event Log.
+
+use std::{io,
+          path::Path,
+          ptr};
+
+use winapi::{shared::minwindef::DWORD,
+             um::{winbase::{DeregisterEventSource,
+                            RegisterEventSourceW,
+                            ReportEventW},
+                  winnt::{EVENTLOG_ERROR_TYPE,
+                          EVENTLOG_INFORMATION_TYPE,
+                          EVENTLOG_WARNING_TYPE,
+                          HANDLE}}};
+
+use super::{Record,
+            Severity,
+            Sink};
+use crate::{error::{Error,
+                    Result},
+            os::registry::{wide,
+                           Hive,
+                           RegKey,
+                           RegValue}};
+
+/// The registry key under which the sources of the `Application` log are registered.
+const APPLICATION_LOG_KEY: &str = "SYSTEM\\CurrentControlSet\\Services\\EventLog\\Application";
+
+/// A message file present on every supported Windows release whose messages each consist of a
+/// single insertion string, so that a record's text is shown verbatim in the Event Viewer.
+pub const DEFAULT_MESSAGE_FILE: &str =
+    "%SystemRoot%\\Microsoft.NET\\Framework64\\v4.0.30319\\EventLogMessages.dll";
+
+/// The event identifier records are reported with.
+const EVENT_ID: DWORD = 1000;
+
+/// Registers `source` as an event source of the `Application` log, using `message_file` to
+/// render its events. Registration requires administrative rights and only needs to happen once,
+/// typically when a service is installed; registering an existing source updates it.
+///
+/// # Failures
+///
+/// * The caller is not permitted to write to `HKEY_LOCAL_MACHINE`
+pub fn register_source(source: &str, message_file: &Path) -> Result<()> {
+    let key = RegKey::create(Hive::LocalMachine,
+                             &format!("{}\\{}", APPLICATION_LOG_KEY, source))?;
+    let types = EVENTLOG_ERROR_TYPE | EVENTLOG_WARNING_TYPE | EVENTLOG_INFORMATION_TYPE;
+    key.set("EventMessageFile",
+            &RegValue::ExpandString(message_file.to_string_lossy().into_owned()))?;
+    key.set_dword("TypesSupported", u32::from(types))
+}
+
+/// Reports records to the Windows Event Log under a registered event source.
+#[derive(Debug)]
+pub struct EventLogSink {
+    handle: HANDLE,
+}
+
+// The handle returned by `RegisterEventSourceW` may be used from any thread.
+unsafe impl Send for EventLogSink {}
+unsafe impl Sync for EventLogSink {}
+
+impl EventLogSink {
+    /// Opens the Event Log for reporting under `source`, which should already have been
+    /// registered with `register_source`. Events reported under an unregistered source are still
+    /// logged, but the Event Viewer cannot render their messages cleanly.
+    ///
+    /// # Failures
+    ///
+    /// * The Event Log service cannot be contacted
+    pub fn open(source: &str) -> Result<Self> {
+        let source = wide(source)?;
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), source.as_ptr()) };
+        if handle.is_null() {
+            return Err(Error::EventLogFailed("RegisterEventSourceW", io::Error::last_os_error()));
+        }
+        Ok(EventLogSink { handle })
+    }
+}
+
+impl Sink for EventLogSink {
+    fn send(&self, record: &Record) -> Result<()> {
+        let event_type = match record.severity {
+            Severity::Emergency | Severity::Alert | Severity::Critical | Severity::Error => {
+                EVENTLOG_ERROR_TYPE
+            }
+            Severity::Warning => EVENTLOG_WARNING_TYPE,
+            Severity::Notice | Severity::Informational | Severity::Debug => {
+                EVENTLOG_INFORMATION_TYPE
+            }
+        };
+        let mut text = format!("{}: {}", record.app_name, record.message);
+        for (key, value) in &record.fields {
+            text.push_str(&format!("\r\n{}={}", key, value));
+        }
+        // A NUL would end the message early, so it is shown rather than dropped with the rest
+        let text = wide(&text.replace('\0', "\\0"))?;
+        let mut strings = [text.as_ptr()];
+        let ok = unsafe {
+            ReportEventW(self.handle,
+                         event_type,
+                         0,
+                         EVENT_ID,
+                         ptr::null_mut(),
+                         strings.len() as u16,
+                         0,
+                         strings.as_mut_ptr(),
+                         ptr::null_mut())
+        };
+        if ok == 0 {
+            return Err(Error::EventLogFailed("ReportEventW", io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EventLogSink {
+    fn drop(&mut self) {
+        unsafe {
+            DeregisterEventSource(self.handle);
+        }
+    }
+}
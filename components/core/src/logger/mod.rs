@@ -0,0 +1,228 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Writers for capturing service output.
+//!
+//! The Launcher and Supervisor capture the standard output and standard error streams of the
+//! services they run. The writers here let that output be kept on disk without an external
+//! tool such as `logrotate` to stop the files growing without bound, or be forwarded as
+//! structured records to the host's log collection through a `Sink`. The most recent lines can
+//! also be kept in memory, in a `ring::RingBuffer`, for diagnostics.
+
+#[cfg(windows)]
+pub mod eventlog;
+pub mod ring;
+mod rotate;
+pub mod syslog;
+
+pub use self::rotate::{RotatingWriter,
+                       RotationPolicy};
+
+use std::{collections::BTreeMap,
+          path::PathBuf,
+          str::FromStr};
+
+use crate::{env,
+            error::{Error,
+                    Result}};
+
+/// The Windows Event Log source used when none is given.
+pub const DEFAULT_EVENT_SOURCE: &str = "Habitat";
+
+/// The severity of a `Record`, using the levels defined by RFC 5424.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    Emergency     = 0,
+    Alert         = 1,
+    Critical      = 2,
+    Error         = 3,
+    Warning       = 4,
+    Notice        = 5,
+    Informational = 6,
+    Debug         = 7,
+}
+
+impl From<log::Level> for Severity {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Error => Severity::Error,
+            log::Level::Warn => Severity::Warning,
+            log::Level::Info => Severity::Informational,
+            log::Level::Debug | log::Level::Trace => Severity::Debug,
+        }
+    }
+}
+
+/// A single structured log record.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Record {
+    pub severity: Severity,
+    /// The program or service the record came from, such as `redis`.
+    pub app_name: String,
+    pub proc_id:  Option<u32>,
+    /// Identifies the type of record, such as `HEALTH_CHECK`.
+    pub msg_id:   Option<String>,
+    /// Additional key/value data about the record, such as the service group.
+    pub fields:   BTreeMap<String, String>,
+    pub message:  String,
+}
+
+impl Record {
+    pub fn new<A, M>(severity: Severity, app_name: A, message: M) -> Self
+        where A: Into<String>,
+              M: Into<String>
+    {
+        Record { severity,
+                 app_name: app_name.into(),
+                 proc_id: None,
+                 msg_id: None,
+                 fields: BTreeMap::new(),
+                 message: message.into() }
+    }
+}
+
+/// A destination for `Record`s outside of the process, such as syslog.
+pub trait Sink: Send + Sync {
+    /// Delivers a record to the sink.
+    ///
+    /// # Failures
+    ///
+    /// * The record cannot be delivered
+    fn send(&self, record: &Record) -> Result<()>;
+}
+
+/// Which `Sink`, if any, records are forwarded to, as read from `HAB_LOG_SINK`.
+///
+/// The accepted values are:
+///
+/// * `syslog`: the local syslog daemon, through `/dev/log` on Unix or UDP port 514 on localhost on
+///   Windows
+/// * `syslog+udp://<HOST>:<PORT>`: a syslog daemon listening on a UDP port
+/// * `syslog+unix://<PATH>`: a syslog daemon listening on a Unix datagram socket
+/// * `eventlog` or `eventlog:<SOURCE>`: the Windows Event Log, under the given event source or
+///   `DEFAULT_EVENT_SOURCE`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SinkConfig {
+    Disabled,
+    Syslog(syslog::Transport),
+    EventLog(String),
+}
+
+impl SinkConfig {
+    /// Opens the configured sink, returning `None` if forwarding is disabled.
+    ///
+    /// # Failures
+    ///
+    /// * The sink cannot be connected to or registered with
+    /// * The Windows Event Log is configured on a platform other than Windows
+    pub fn open(&self) -> Result<Option<Box<dyn Sink>>> {
+        match *self {
+            SinkConfig::Disabled => Ok(None),
+            SinkConfig::Syslog(ref transport) => {
+                Ok(Some(Box::new(syslog::SyslogSink::connect(transport)?)))
+            }
+            #[cfg(windows)]
+            SinkConfig::EventLog(ref source) => {
+                Ok(Some(Box::new(eventlog::EventLogSink::open(source)?)))
+            }
+            #[cfg(not(windows))]
+            SinkConfig::EventLog(_) => {
+                let msg = "the Windows Event Log is only available on Windows";
+                Err(Error::InvalidLogSink(msg.to_string()))
+            }
+        }
+    }
+}
+
+impl env::Config for SinkConfig {
+    const ENVVAR: &'static str = "HAB_LOG_SINK";
+}
+
+impl Default for SinkConfig {
+    fn default() -> Self { SinkConfig::Disabled }
+}
+
+impl FromStr for SinkConfig {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let value = value.trim();
+        if value == "syslog" {
+            return Ok(SinkConfig::Syslog(syslog::Transport::default()));
+        }
+        if let Some(addr) = strip_prefix(value, "syslog+udp://") {
+            if addr.is_empty() {
+                return Err(Error::InvalidLogSink(value.to_string()));
+            }
+            return Ok(SinkConfig::Syslog(syslog::Transport::Udp(addr.to_string())));
+        }
+        if let Some(path) = strip_prefix(value, "syslog+unix://") {
+            if path.is_empty() {
+                return Err(Error::InvalidLogSink(value.to_string()));
+            }
+            return Ok(SinkConfig::Syslog(syslog::Transport::Unix(PathBuf::from(path))));
+        }
+        if value == "eventlog" {
+            return Ok(SinkConfig::EventLog(DEFAULT_EVENT_SOURCE.to_string()));
+        }
+        if let Some(source) = strip_prefix(value, "eventlog:") {
+            if source.is_empty() {
+                return Err(Error::InvalidLogSink(value.to_string()));
+            }
+            return Ok(SinkConfig::EventLog(source.to_string()));
+        }
+        Err(Error::InvalidLogSink(value.to_string()))
+    }
+}
+
+fn strip_prefix<'a>(value: &'a str, prefix: &str) -> Option<&'a str> {
+    if value.starts_with(prefix) {
+        Some(&value[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sink_config_from_str() {
+        assert_eq!(SinkConfig::Syslog(syslog::Transport::default()),
+                   "syslog".parse().unwrap());
+        assert_eq!(SinkConfig::Syslog(syslog::Transport::Udp("logs.example.com:514".to_string())),
+                   "syslog+udp://logs.example.com:514".parse().unwrap());
+        assert_eq!(SinkConfig::Syslog(syslog::Transport::Unix(PathBuf::from("/run/log"))),
+                   "syslog+unix:///run/log".parse().unwrap());
+        assert_eq!(SinkConfig::EventLog(DEFAULT_EVENT_SOURCE.to_string()),
+                   "eventlog".parse().unwrap());
+        assert_eq!(SinkConfig::EventLog("Redis".to_string()),
+                   "eventlog:Redis".parse().unwrap());
+    }
+
+    #[test]
+    fn sink_config_from_str_invalid() {
+        for value in &["journald",
+                       "syslog+udp://",
+                       "syslog+tcp://host:514",
+                       "eventlog:"]
+        {
+            match value.parse::<SinkConfig>() {
+                Err(Error::InvalidLogSink(_)) => {}
+                other => panic!("Expected InvalidLogSink for {}, got {:?}", value, other),
+            }
+        }
+    }
+}
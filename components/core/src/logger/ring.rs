@@ -0,0 +1,208 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Keeping the most recent log lines in memory.
+//!
+//! Persistent debug logging is usually off, so when something goes wrong the lines that would
+//! explain it were never written anywhere. A `RingBuffer` keeps the last few hundred lines at
+//! debug level in memory regardless, for crash reports and diagnostics bundles to include.
+//! `RingLogger` feeds it from the `log` macros, passing each record on to the program's own
+//! logger as well, and the buffer is also a `Sink` for service output `Record`s.
+//!
+//! ```no_run
+//! use habitat_core::{crash::CrashReporter,
+//!                    logger::ring::{RingBuffer,
+//!                                   RingLogger}};
+//! use log::LevelFilter;
+//! use std::sync::Arc;
+//!
+//! let ring = Arc::new(RingBuffer::new(500));
+//! RingLogger::new(Arc::clone(&ring), LevelFilter::Debug).install()
+//!                                                       .unwrap();
+//!
+//! CrashReporter::new("hab-sup", "0.79.1").log_source(move || ring.dump())
+//!                                        .install();
+//! ```
+
+use std::{collections::VecDeque,
+          sync::{Arc,
+                 Mutex}};
+
+use log::{LevelFilter,
+          Log,
+          Metadata,
+          SetLoggerError};
+
+use super::{Record,
+            Sink};
+use crate::error::Result;
+
+/// How many lines a `RingBuffer` holds when no other number is given.
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+/// A bounded buffer of log lines, which drops the oldest line to make room for each new one
+/// once full.
+#[derive(Debug)]
+pub struct RingBuffer {
+    capacity: usize,
+    lines:    Mutex<VecDeque<String>>,
+}
+
+impl Default for RingBuffer {
+    fn default() -> Self { Self::new(DEFAULT_CAPACITY) }
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        RingBuffer { capacity,
+                     lines: Mutex::new(VecDeque::with_capacity(capacity)) }
+    }
+
+    pub fn capacity(&self) -> usize { self.capacity }
+
+    pub fn push<T: Into<String>>(&self, line: T) {
+        // Nothing is done while holding the lock that could panic, so a poisoned lock is still
+        // usable; logging should never be the thing that brings a program down.
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line.into());
+    }
+
+    /// Returns the lines in the buffer, oldest first.
+    pub fn dump(&self) -> Vec<String> {
+        let lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        lines.iter().cloned().collect()
+    }
+
+    pub fn clear(&self) { self.lines.lock().unwrap_or_else(|e| e.into_inner()).clear() }
+}
+
+impl Sink for RingBuffer {
+    fn send(&self, record: &Record) -> Result<()> {
+        self.push(format!("{} {:?} {}: {}",
+                          time::now_utc().rfc3339(),
+                          record.severity,
+                          record.app_name,
+                          record.message));
+        Ok(())
+    }
+}
+
+/// A `log::Log` which records to a `RingBuffer` everything at or above its level, and passes
+/// records on to another logger, which filters them by its own level.
+pub struct RingLogger {
+    ring:  Arc<RingBuffer>,
+    level: LevelFilter,
+    inner: Option<Box<dyn Log>>,
+}
+
+impl RingLogger {
+    pub fn new(ring: Arc<RingBuffer>, level: LevelFilter) -> Self {
+        RingLogger { ring,
+                     level,
+                     inner: None }
+    }
+
+    /// Passes records on to `inner`, such as the logger built by `env_logger`.
+    pub fn inner(mut self, inner: Box<dyn Log>) -> Self {
+        self.inner = Some(inner);
+        self
+    }
+
+    /// Makes this the global logger. The global maximum level is raised to this logger's level,
+    /// so the inner logger sees records it has not enabled and must filter them itself, as
+    /// `Log` implementations do in `enabled`.
+    ///
+    /// # Failures
+    ///
+    /// * A global logger has already been set
+    pub fn install(self) -> std::result::Result<(), SetLoggerError> {
+        let level = self.level.max(log::max_level());
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+}
+
+impl Log for RingLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+        || self.inner
+               .as_ref()
+               .map_or(false, |inner| inner.enabled(metadata))
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if record.level() <= self.level {
+            self.ring.push(format!("{} {:<5} {}: {}",
+                                   time::now_utc().rfc3339(),
+                                   record.level(),
+                                   record.target(),
+                                   record.args()));
+        }
+        if let Some(ref inner) = self.inner {
+            if inner.enabled(record.metadata()) {
+                inner.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(ref inner) = self.inner {
+            inner.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::logger::Severity;
+    use log::Level;
+
+    #[test]
+    fn keeps_only_the_latest_lines() {
+        let ring = RingBuffer::new(3);
+        for i in 1..=5 {
+            ring.push(format!("line {}", i));
+        }
+
+        assert_eq!(vec!["line 3", "line 4", "line 5"], ring.dump());
+        ring.clear();
+        assert!(ring.dump().is_empty());
+    }
+
+    #[test]
+    fn logger_records_at_its_level() {
+        let ring = Arc::new(RingBuffer::new(10));
+        let logger = RingLogger::new(Arc::clone(&ring), LevelFilter::Debug);
+
+        for &(level, message) in &[(Level::Debug, "debug"), (Level::Trace, "trace")] {
+            logger.log(&log::Record::builder().level(level)
+                                              .target("habitat_core::fs")
+                                              .args(format_args!("{}", message))
+                                              .build());
+        }
+        ring.send(&Record::new(Severity::Error, "redis", "out of memory"))
+            .unwrap();
+
+        let lines = ring.dump();
+        assert_eq!(2, lines.len());
+        assert!(lines[0].ends_with("DEBUG habitat_core::fs: debug"));
+        assert!(lines[1].ends_with("Error redis: out of memory"));
+    }
+}
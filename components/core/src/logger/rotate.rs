@@ -0,0 +1,298 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{ffi::OsString,
+          fs::{self,
+               File,
+               OpenOptions},
+          io::{self,
+               Write},
+          path::{Path,
+                 PathBuf},
+          sync::{Arc,
+                 Mutex},
+          time::{Duration,
+                 Instant}};
+
+use flate2::{write::GzEncoder,
+             Compression};
+
+use crate::error::Result;
+
+/// When a `RotatingWriter` rotates its file, and what it keeps afterwards.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RotationPolicy {
+    /// Rotate once the file reaches this many bytes.
+    pub max_size: Option<u64>,
+    /// Rotate once the file has been written to for this long.
+    pub max_age:  Option<Duration>,
+    /// The number of rotated files to keep. Older files are deleted.
+    pub retain:   usize,
+    /// Gzip rotated files, adding a `.gz` extension.
+    pub compress: bool,
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        RotationPolicy { max_size: Some(10 * 1024 * 1024),
+                         max_age:  None,
+                         retain:   5,
+                         compress: true, }
+    }
+}
+
+/// A file writer which rotates the file according to a `RotationPolicy`.
+///
+/// Rotated files are named after the file with a numeric suffix, `.1` being the most recent,
+/// such as `out.log.1.gz`, `out.log.2.gz`, and so on. Clones share the underlying file, so
+/// a writer may be handed to several threads; each call to `write` is appended whole and never
+/// interleaved with, or split by a rotation from, another writer's data.
+#[derive(Clone, Debug)]
+pub struct RotatingWriter {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    path:      PathBuf,
+    policy:    RotationPolicy,
+    file:      File,
+    size:      u64,
+    opened_at: Instant,
+}
+
+impl RotatingWriter {
+    /// Opens `path` for appending, creating it if it does not exist.
+    ///
+    /// # Failures
+    ///
+    /// * The file cannot be opened or its metadata cannot be read
+    pub fn open<P: Into<PathBuf>>(path: P, policy: RotationPolicy) -> Result<Self> {
+        let path = path.into();
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        let inner = Inner { path,
+                            policy,
+                            file,
+                            size,
+                            opened_at: Instant::now() };
+        Ok(RotatingWriter { inner: Arc::new(Mutex::new(inner)), })
+    }
+
+    /// Rotates the file immediately, regardless of the policy.
+    pub fn rotate(&self) -> io::Result<()> { self.lock().rotate() }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        // A writer which panicked mid-write leaves nothing inconsistent which a later write
+        // could trip over, so a poisoned lock is still usable.
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Write for RotatingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self.lock();
+        if inner.should_rotate(buf.len() as u64) {
+            inner.rotate()?;
+        }
+        inner.file.write_all(buf)?;
+        inner.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.lock().file.flush() }
+}
+
+impl Inner {
+    fn should_rotate(&self, incoming: u64) -> bool {
+        // An empty file is never rotated, so a single write larger than `max_size` still
+        // lands somewhere.
+        if self.size == 0 {
+            return false;
+        }
+        if let Some(max_size) = self.policy.max_size {
+            if self.size + incoming > max_size {
+                return true;
+            }
+        }
+        if let Some(max_age) = self.policy.max_age {
+            if self.opened_at.elapsed() >= max_age {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.policy.retain == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let oldest = self.rotated_path(self.policy.retain);
+            if oldest.exists() {
+                fs::remove_file(&oldest)?;
+            }
+            for n in (1..self.policy.retain).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(n + 1))?;
+                }
+            }
+            let newest = self.suffixed(1, false);
+            fs::rename(&self.path, &newest)?;
+            if self.policy.compress {
+                compress(&newest, &self.suffixed(1, true))?;
+            }
+        }
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        self.opened_at = Instant::now();
+        Ok(())
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf { self.suffixed(n, self.policy.compress) }
+
+    fn suffixed(&self, n: usize, gz: bool) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{}", n));
+        if gz {
+            name.push(".gz");
+        }
+        PathBuf::from(name)
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Gzips `src` to `dst`, removing `src` once `dst` is complete.
+fn compress(src: &Path, dst: &Path) -> io::Result<()> {
+    let mut input = File::open(src)?;
+    let mut encoder = GzEncoder::new(File::create(dst)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    fs::remove_file(src)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::{io::Read,
+              thread};
+    use tempfile::Builder;
+
+    fn read_gz(path: &Path) -> String {
+        let mut s = String::new();
+        GzDecoder::new(File::open(path).unwrap()).read_to_string(&mut s)
+                                                 .unwrap();
+        s
+    }
+
+    #[test]
+    fn rotates_by_size_and_retains() {
+        let dir = Builder::new().prefix("logs").tempdir().unwrap();
+        let path = dir.path().join("out.log");
+        let policy = RotationPolicy { max_size: Some(6),
+                                      retain: 2,
+                                      compress: false,
+                                      ..Default::default() };
+        let mut writer = RotatingWriter::open(&path, policy).unwrap();
+
+        for line in &["one\n", "two\n", "three\n", "four\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+
+        assert_eq!("four\n", fs::read_to_string(&path).unwrap());
+        assert_eq!("three\n",
+                   fs::read_to_string(dir.path().join("out.log.1")).unwrap());
+        assert_eq!("two\n",
+                   fs::read_to_string(dir.path().join("out.log.2")).unwrap());
+        assert!(!dir.path().join("out.log.3").exists());
+    }
+
+    #[test]
+    fn rotates_by_age() {
+        let dir = Builder::new().prefix("logs").tempdir().unwrap();
+        let path = dir.path().join("out.log");
+        let policy = RotationPolicy { max_size: None,
+                                      max_age: Some(Duration::from_millis(10)),
+                                      compress: false,
+                                      ..Default::default() };
+        let mut writer = RotatingWriter::open(&path, policy).unwrap();
+
+        writer.write_all(b"before\n").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        writer.write_all(b"after\n").unwrap();
+
+        assert_eq!("after\n", fs::read_to_string(&path).unwrap());
+        assert_eq!("before\n",
+                   fs::read_to_string(dir.path().join("out.log.1")).unwrap());
+    }
+
+    #[test]
+    fn compresses_rotated_files() {
+        let dir = Builder::new().prefix("logs").tempdir().unwrap();
+        let path = dir.path().join("out.log");
+        let mut writer = RotatingWriter::open(&path, RotationPolicy::default()).unwrap();
+
+        writer.write_all(b"first\n").unwrap();
+        writer.rotate().unwrap();
+        writer.write_all(b"second\n").unwrap();
+        writer.rotate().unwrap();
+
+        assert_eq!("", fs::read_to_string(&path).unwrap());
+        assert_eq!("second\n", read_gz(&dir.path().join("out.log.1.gz")));
+        assert_eq!("first\n", read_gz(&dir.path().join("out.log.2.gz")));
+        assert!(!dir.path().join("out.log.1").exists());
+    }
+
+    #[test]
+    fn concurrent_writes_are_not_interleaved() {
+        let dir = Builder::new().prefix("logs").tempdir().unwrap();
+        let path = dir.path().join("out.log");
+        let policy = RotationPolicy { max_size: Some(1024),
+                                      retain: 100,
+                                      compress: false,
+                                      ..Default::default() };
+        let writer = RotatingWriter::open(&path, policy).unwrap();
+
+        let handles: Vec<_> = (0..4).map(|t| {
+                                        let mut writer = writer.clone();
+                                        thread::spawn(move || {
+                                            for _ in 0..100 {
+                                                let line = format!("thread-{}-line\n", t);
+                                                writer.write_all(line.as_bytes()).unwrap();
+                                            }
+                                        })
+                                    })
+                                    .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut lines = 0;
+        for entry in fs::read_dir(dir.path()).unwrap() {
+            let content = fs::read_to_string(entry.unwrap().path()).unwrap();
+            for line in content.lines() {
+                assert!(line.starts_with("thread-") && line.ends_with("-line"),
+                        "unexpected line {:?}",
+                        line);
+                lines += 1;
+            }
+        }
+        assert_eq!(400, lines);
+    }
+}
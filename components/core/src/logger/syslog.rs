@@ -0,0 +1,267 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A `Sink` which forwards records to syslog in the RFC 5424 format.
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::{fmt::Write,
+          net::UdpSocket,
+          path::PathBuf};
+
+use super::{Record,
+            Sink};
+#[cfg(not(unix))]
+use crate::error::Error;
+use crate::{error::Result,
+            os::net};
+
+/// The structured data ID under which a record's fields are sent.
+///
+/// 32473 is the private enterprise number reserved for documentation by RFC 5612; collectors
+/// which care about the ID can be given another with `SyslogSink::sd_id`.
+pub const DEFAULT_SD_ID: &str = "habitat@32473";
+
+/// How to reach the syslog daemon.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Transport {
+    /// A `<HOST>:<PORT>` address to send UDP datagrams to.
+    Udp(String),
+    /// The path of a Unix datagram socket, such as `/dev/log`.
+    Unix(PathBuf),
+}
+
+impl Default for Transport {
+    #[cfg(unix)]
+    fn default() -> Self { Transport::Unix(PathBuf::from("/dev/log")) }
+
+    #[cfg(not(unix))]
+    fn default() -> Self { Transport::Udp("127.0.0.1:514".to_string()) }
+}
+
+/// The syslog facility records are sent with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Facility {
+    User,
+    Daemon,
+    /// One of the locally defined facilities, `local0` through `local7`.
+    Local(u8),
+}
+
+impl Facility {
+    fn code(self) -> u8 {
+        match self {
+            Facility::User => 1,
+            Facility::Daemon => 3,
+            Facility::Local(n) => 16 + n.min(7),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Socket {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+}
+
+/// Sends records to a syslog daemon, one record per datagram.
+#[derive(Debug)]
+pub struct SyslogSink {
+    socket:   Socket,
+    facility: Facility,
+    hostname: String,
+    sd_id:    String,
+}
+
+impl SyslogSink {
+    /// Connects to the syslog daemon over `transport`, sending records with the `daemon`
+    /// facility.
+    ///
+    /// # Failures
+    ///
+    /// * The socket cannot be created or connected
+    /// * A Unix socket transport is used on a platform without Unix sockets
+    pub fn connect(transport: &Transport) -> Result<Self> {
+        let socket = match *transport {
+            Transport::Udp(ref addr) => {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(addr.as_str())?;
+                Socket::Udp(socket)
+            }
+            #[cfg(unix)]
+            Transport::Unix(ref path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Socket::Unix(socket)
+            }
+            #[cfg(not(unix))]
+            Transport::Unix(ref path) => {
+                return Err(Error::InvalidLogSink(format!("Unix sockets are not \
+                                                          available on this \
+                                                          platform: {}",
+                                                         path.display())));
+            }
+        };
+        Ok(SyslogSink { socket,
+                        facility: Facility::Daemon,
+                        hostname: net::hostname().unwrap_or_default(),
+                        sd_id: DEFAULT_SD_ID.to_string() })
+    }
+
+    pub fn facility(mut self, facility: Facility) -> Self {
+        self.facility = facility;
+        self
+    }
+
+    pub fn sd_id<S: Into<String>>(mut self, sd_id: S) -> Self {
+        self.sd_id = sd_id.into();
+        self
+    }
+}
+
+impl Sink for SyslogSink {
+    fn send(&self, record: &Record) -> Result<()> {
+        let msg = format(record,
+                         self.facility,
+                         &self.hostname,
+                         &self.sd_id,
+                         &time::now_utc().rfc3339().to_string());
+        match self.socket {
+            Socket::Udp(ref socket) => socket.send(msg.as_bytes())?,
+            #[cfg(unix)]
+            Socket::Unix(ref socket) => socket.send(msg.as_bytes())?,
+        };
+        Ok(())
+    }
+}
+
+/// Formats a record as an RFC 5424 syslog message.
+fn format(record: &Record,
+          facility: Facility,
+          hostname: &str,
+          sd_id: &str,
+          timestamp: &str)
+          -> String {
+    let pri = u16::from(facility.code()) * 8 + record.severity as u16;
+    let proc_id = record.proc_id.map(|p| p.to_string());
+    // Writing to a `String` cannot fail
+    let mut msg = String::new();
+    write!(msg,
+           "<{}>1 {} {} {} {} {} ",
+           pri,
+           timestamp,
+           header_field(hostname, 255),
+           header_field(&record.app_name, 48),
+           header_field(proc_id.as_ref().map_or("", String::as_str), 128),
+           header_field(record.msg_id.as_ref().map_or("", String::as_str), 32)).unwrap();
+    if record.fields.is_empty() {
+        msg.push('-');
+    } else {
+        write!(msg, "[{}", sd_id).unwrap();
+        for (key, value) in &record.fields {
+            write!(msg, " {}=\"{}\"", sd_name(key), sd_value(value)).unwrap();
+        }
+        msg.push(']');
+    }
+    if !record.message.is_empty() {
+        write!(msg, " {}", record.message).unwrap();
+    }
+    msg
+}
+
+/// Header fields may only contain printable ASCII, and an empty field is written as `-`.
+fn header_field(value: &str, max_len: usize) -> String {
+    let field: String = value.chars()
+                             .filter(|c| c.is_ascii_graphic())
+                             .take(max_len)
+                             .collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+/// Parameter names are at most 32 printable ASCII characters, excluding `=`, ` `, `]`, and `"`.
+fn sd_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_ascii_graphic() && !"= ]\"".contains(*c))
+        .take(32)
+        .collect()
+}
+
+/// Parameter values must escape `"`, `\`, and `]`.
+fn sd_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '"' || c == '\\' || c == ']' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::logger::Severity;
+
+    const TIMESTAMP: &str = "2019-03-01T12:00:00Z";
+
+    #[test]
+    fn format_minimal_record() {
+        let record = Record::new(Severity::Informational, "redis", "ready");
+
+        assert_eq!("<30>1 2019-03-01T12:00:00Z host redis - - - ready",
+                   format(&record, Facility::Daemon, "host", DEFAULT_SD_ID, TIMESTAMP));
+    }
+
+    #[test]
+    fn format_structured_record() {
+        let mut record = Record::new(Severity::Error, "my redis", "failed");
+        record.proc_id = Some(42);
+        record.msg_id = Some("HEALTH_CHECK".to_string());
+        record.fields
+              .insert("group".to_string(), "redis.default".to_string());
+        record.fields
+              .insert("reason".to_string(), "said \"no\" [x]".to_string());
+
+        assert_eq!("<131>1 2019-03-01T12:00:00Z - myredis 42 HEALTH_CHECK [hab@1 \
+                    group=\"redis.default\" reason=\"said \\\"no\\\" [x\\]\"] failed",
+                   format(&record, Facility::Local(0), "", "hab@1", TIMESTAMP));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sends_to_unix_socket() {
+        let dir = tempfile::Builder::new().prefix("syslog").tempdir().unwrap();
+        let path = dir.path().join("log.sock");
+        let server = UnixDatagram::bind(&path).unwrap();
+        let sink = SyslogSink::connect(&Transport::Unix(path)).unwrap()
+                                                              .facility(Facility::User);
+
+        sink.send(&Record::new(Severity::Warning, "redis", "slow"))
+            .unwrap();
+
+        let mut buf = [0; 1024];
+        let len = server.recv(&mut buf).unwrap();
+        let msg = String::from_utf8_lossy(&buf[..len]);
+        assert!(msg.starts_with("<12>1 "), "unexpected message {:?}", msg);
+        assert!(msg.ends_with(" redis - - - slow"),
+                "unexpected message {:?}",
+                msg);
+    }
+}
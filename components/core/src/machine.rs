@@ -0,0 +1,414 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A JSON request and response interface to core's package operations, read from one stream and
+//! answered on another, for scripts which would rather talk to a `hab core-api` process than
+//! link Rust or scrape `hab` CLI output.
+//!
+//! Each line of input is one request, and each request is answered with exactly one line of
+//! output, in order:
+//!
+//! ```text
+//! {"id": 1, "method": "parse_ident", "params": {"ident": "core/redis/4.0.14"}}
+//! {"api_version":1,"id":1,"result":{"origin":"core","name":"redis","version":"4.0.14",...}}
+//! {"id": 2, "method": "resolve_installed", "params": {"ident": "core/nope"}}
+//! {"api_version":1,"id":2,"error":{"kind":"failed","code":4,"message":"..."}}
+//! ```
+//!
+//! The `id`, any JSON value, is handed back as it was given so that callers can match responses
+//! to requests; `params` may be left out when a method needs none. The methods are:
+//!
+//! | Method              | Params                                | Result                       |
+//! |---------------------|---------------------------------------|------------------------------|
+//! | `parse_ident`       | `ident`                               | `origin`, `name`, and so on  |
+//! | `resolve_installed` | `ident`, `fs_root`?, `target`?        | `ident`, `target`, `path`    |
+//! | `verify_artifact`   | `path`, `fs_root`?, `cache_key_path`? | `signer`, `checksum`         |
+//! | `list_packages`     | `fs_root`?, `origin`?, `target`?      | `packages`                   |
+//!
+//! Params marked `?` may be left out; `fs_root` defaults to `/`, `target` to the active target and
+//! `cache_key_path` to the key cache under `fs_root`.
+//!
+//! An error's `kind` is `invalid_request` for a line which is not a request, `unknown_method`,
+//! `invalid_params`, or `failed` when the operation itself failed. Its `code` is the
+//! `exit_code::ExitCode` of the failure, so callers can branch on why it failed as they would on
+//! the exit code of `hab`.
+//!
+//! Every response carries the `api_version` it was written with. Within a version, methods,
+//! params and result fields are only ever added, and added params are optional; the version is
+//! only incremented when something existing changes meaning or is removed.
+
+use crate::{crypto::artifact,
+            error::Error,
+            exit_code::ExitCode,
+            fs,
+            package::{list,
+                      PackageIdent,
+                      PackageInstall,
+                      PackageTarget}};
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize,
+                   Serialize};
+use serde_json::{self,
+                 Value};
+use std::{fmt,
+          io::{self,
+               BufRead,
+               Write},
+          path::PathBuf,
+          str::FromStr};
+
+/// The version of the interface implemented by this crate.
+pub const API_VERSION: u32 = 1;
+
+/// One line of input.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Request {
+    #[serde(default)]
+    pub id:     Value,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// One line of output: either a `result` or an `error`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Response {
+    pub api_version: u32,
+    pub id:          Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result:      Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error:       Option<ResponseError>,
+}
+
+impl Response {
+    fn new(id: Value, outcome: Result<Value, ResponseError>) -> Self {
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Response { api_version: API_VERSION,
+                   id,
+                   result,
+                   error }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ResponseError {
+    pub kind:    ErrorKind,
+    /// The `exit_code::ExitCode` value of the failure.
+    pub code:    i32,
+    pub message: String,
+}
+
+impl ResponseError {
+    fn new(kind: ErrorKind, code: ExitCode, message: String) -> Self {
+        ResponseError { kind,
+                        code: code.code(),
+                        message }
+    }
+}
+
+impl From<Error> for ResponseError {
+    fn from(err: Error) -> Self {
+        Self::new(ErrorKind::Failed, ExitCode::from(&err), err.to_string())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    InvalidRequest,
+    UnknownMethod,
+    InvalidParams,
+    Failed,
+}
+
+/// Answers each line of `input` on `output` until `input` ends. Blank lines are skipped.
+///
+/// # Failures
+///
+/// * `input` cannot be read or `output` cannot be written
+pub fn serve<R, W>(input: R, mut output: W) -> io::Result<()>
+    where R: BufRead,
+          W: Write
+{
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        serde_json::to_writer(&mut output, &handle(&line))?;
+        output.write_all(b"\n")?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// Answers one line of input.
+pub fn handle(line: &str) -> Response {
+    match serde_json::from_str::<Request>(line) {
+        Ok(request) => dispatch(request),
+        Err(err) => {
+            let error = ResponseError::new(ErrorKind::InvalidRequest,
+                                           ExitCode::InvalidInput,
+                                           format!("Not a request: {}", err));
+            Response::new(Value::Null, Err(error))
+        }
+    }
+}
+
+/// Runs the method a request names.
+pub fn dispatch(request: Request) -> Response {
+    let outcome = match request.method.as_str() {
+        "parse_ident" => params(request.params).and_then(parse_ident),
+        "resolve_installed" => params(request.params).and_then(resolve_installed),
+        "verify_artifact" => params(request.params).and_then(verify_artifact),
+        "list_packages" => params(request.params).and_then(list_packages),
+        method => {
+            Err(ResponseError::new(ErrorKind::UnknownMethod,
+                                   ExitCode::InvalidInput,
+                                   format!("No such method: {}", method)))
+        }
+    };
+    Response::new(request.id, outcome)
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, ResponseError> {
+    let params = if params.is_null() {
+        Value::Object(serde_json::Map::new())
+    } else {
+        params
+    };
+    serde_json::from_value(params).map_err(invalid_params)
+}
+
+fn to_value<T: serde::Serialize>(value: T) -> Result<Value, ResponseError> {
+    serde_json::to_value(value).map_err(|e| Error::from(e).into())
+}
+
+fn ident(ident: &str) -> Result<PackageIdent, ResponseError> {
+    PackageIdent::from_str(ident).map_err(invalid_params)
+}
+
+fn invalid_params<E: fmt::Display>(err: E) -> ResponseError {
+    ResponseError::new(ErrorKind::InvalidParams,
+                       ExitCode::InvalidInput,
+                       err.to_string())
+}
+
+fn target(target: Option<&String>) -> Result<Option<PackageTarget>, ResponseError> {
+    match target {
+        Some(target) => {
+            PackageTarget::from_str(target).map(Some)
+                                           .map_err(invalid_params)
+        }
+        None => Ok(None),
+    }
+}
+
+#[derive(Deserialize)]
+struct IdentParams {
+    ident: String,
+}
+
+fn parse_ident(params: IdentParams) -> Result<Value, ResponseError> {
+    to_value(ident(&params.ident)?)
+}
+
+#[derive(Deserialize)]
+struct ResolveParams {
+    ident:   String,
+    fs_root: Option<PathBuf>,
+    target:  Option<String>,
+}
+
+#[derive(Serialize)]
+struct Resolved {
+    ident:  String,
+    target: String,
+    path:   PathBuf,
+}
+
+fn resolve_installed(params: ResolveParams) -> Result<Value, ResponseError> {
+    let ident = ident(&params.ident)?;
+    let fs_root = params.fs_root.as_ref().map(PathBuf::as_path);
+    let pkg_install = match target(params.target.as_ref())? {
+        Some(target) => PackageInstall::load_for_target(&ident, target, fs_root)?,
+        None => PackageInstall::load(&ident, fs_root)?,
+    };
+    to_value(Resolved { ident:  pkg_install.ident().to_string(),
+                        target: pkg_install.target()?.to_string(),
+                        path:   pkg_install.installed_path().to_path_buf(), })
+}
+
+#[derive(Deserialize)]
+struct VerifyParams {
+    path:           PathBuf,
+    fs_root:        Option<PathBuf>,
+    cache_key_path: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct Verified {
+    /// The name and revision of the key the artifact is signed with.
+    signer:   String,
+    checksum: String,
+}
+
+fn verify_artifact(params: VerifyParams) -> Result<Value, ResponseError> {
+    let VerifyParams { path,
+                       fs_root,
+                       cache_key_path, } = params;
+    let cache_key_path = cache_key_path.unwrap_or_else(|| fs::cache_key_path(fs_root.as_ref()));
+    let (signer, checksum) = artifact::verify(&path, &cache_key_path)?;
+    to_value(Verified { signer, checksum })
+}
+
+#[derive(Deserialize)]
+struct ListParams {
+    fs_root: Option<PathBuf>,
+    origin:  Option<String>,
+    target:  Option<String>,
+}
+
+#[derive(Serialize)]
+struct Listed {
+    packages: Vec<String>,
+}
+
+fn list_packages(params: ListParams) -> Result<Value, ResponseError> {
+    let pkg_root = fs::pkg_root_path(params.fs_root.as_ref());
+    let target = target(params.target.as_ref())?.unwrap_or_else(PackageTarget::active_target);
+    let packages = if pkg_root.is_dir() {
+        list::all_packages_for_target(&pkg_root, target)?
+    } else {
+        Vec::new()
+    };
+    let packages = packages.into_iter()
+                           .filter(|p| params.origin.as_ref().map_or(true, |o| &p.origin == o))
+                           .map(|p| p.to_string())
+                           .collect();
+    to_value(Listed { packages })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{crypto::SigKeyPair,
+                package::test_support::{fixture_path,
+                                        fixtures,
+                                        testing_package_install}};
+    use serde_json::json;
+    use tempfile::Builder;
+
+    fn call(request: Value) -> Response { handle(&request.to_string()) }
+
+    #[test]
+    fn parses_idents() {
+        let response = call(json!({"id": "a", "method": "parse_ident",
+                                   "params": {"ident": "core/redis/4.0.14"}}));
+        assert_eq!(json!("a"), response.id);
+        assert_eq!(None, response.error);
+        assert_eq!(Some(json!({"origin": "core", "name": "redis", "version": "4.0.14",
+                               "release": null})),
+                   response.result);
+
+        let response = call(json!({"id": 2, "method": "parse_ident",
+                                   "params": {"ident": "core"}}));
+        let error = response.error.unwrap();
+        assert_eq!(ErrorKind::InvalidParams, error.kind);
+        assert_eq!(ExitCode::InvalidInput.code(), error.code);
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_request() {
+        let response = handle("core/redis");
+        assert_eq!(Value::Null, response.id);
+        assert_eq!(ErrorKind::InvalidRequest, response.error.unwrap().kind);
+
+        let response = call(json!({"id": 3, "method": "frobnicate"}));
+        assert_eq!(json!(3), response.id);
+        assert_eq!(ErrorKind::UnknownMethod, response.error.unwrap().kind);
+
+        let response = call(json!({"id": 4, "method": "parse_ident"}));
+        assert_eq!(ErrorKind::InvalidParams, response.error.unwrap().kind);
+    }
+
+    #[test]
+    fn resolves_and_lists_installed_packages() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let redis = testing_package_install("core/redis/4.0.14/20190115013919", fs_root.path());
+        testing_package_install("acme/redis/5.0.0/20190201000000", fs_root.path());
+
+        let response = call(json!({"method": "resolve_installed",
+                                   "params": {"ident": "core/redis",
+                                              "fs_root": fs_root.path()}}));
+        let result = response.result.unwrap();
+        assert_eq!(json!("core/redis/4.0.14/20190115013919"), result["ident"]);
+        assert_eq!(json!(PackageTarget::active_target().to_string()),
+                   result["target"]);
+        assert_eq!(json!(redis.installed_path()), result["path"]);
+
+        let response = call(json!({"method": "resolve_installed",
+                                   "params": {"ident": "core/nope",
+                                              "fs_root": fs_root.path()}}));
+        let error = response.error.unwrap();
+        assert_eq!(ErrorKind::Failed, error.kind);
+        assert_eq!(ExitCode::NotFound.code(), error.code);
+
+        let response = call(json!({"method": "list_packages",
+                                   "params": {"fs_root": fs_root.path(), "origin": "core"}}));
+        assert_eq!(Some(json!({"packages": ["core/redis/4.0.14/20190115013919"]})),
+                   response.result);
+    }
+
+    #[test]
+    fn verifies_artifacts() {
+        let cache = Builder::new().prefix("key_cache").tempdir().unwrap();
+        let pair = SigKeyPair::generate_pair_for_origin("core").unwrap();
+        pair.to_pair_files(cache.path()).unwrap();
+        let hart = cache.path().join("signed.hart");
+        artifact::sign(&fixture_path(&fixtures(), "signme.dat"), &hart, &pair).unwrap();
+
+        let response = call(json!({"method": "verify_artifact",
+                                   "params": {"path": hart, "cache_key_path": cache.path()}}));
+        assert_eq!(json!(pair.name_with_rev()),
+                   response.result.unwrap()["signer"]);
+
+        let response = call(json!({"method": "verify_artifact",
+                                   "params": {"path": cache.path().join("missing.hart"),
+                                              "cache_key_path": cache.path()}}));
+        assert_eq!(ExitCode::NotFound.code(), response.error.unwrap().code);
+    }
+
+    #[test]
+    fn serves_a_response_line_per_request_line() {
+        let input = "{\"id\": 1, \"method\": \"parse_ident\", \"params\": {\"ident\": \
+                     \"a/b\"}}\n\nnonsense\n";
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output).unwrap();
+
+        let lines: Vec<Response> =
+            String::from_utf8(output).unwrap()
+                                     .lines()
+                                     .map(|l| serde_json::from_str(l).unwrap())
+                                     .collect();
+        assert_eq!(2, lines.len());
+        assert_eq!(json!(1), lines[0].id);
+        assert!(lines[0].result.is_some());
+        assert!(lines[1].error.is_some());
+    }
+}
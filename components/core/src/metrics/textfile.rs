@@ -0,0 +1,226 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Metrics about installed packages in the format of the Prometheus node exporter's textfile
+//! collector, which reads every `*.prom` file in a directory it is given.
+//!
+//! Each origin with installed packages gets a sample of each of these gauges:
+//!
+//! * `hab_installed_packages`: how many releases of its packages are installed
+//! * `hab_installed_package_bytes`: the disk space those releases take up
+//! * `hab_installed_package_oldest_release_age_seconds` and
+//!   `hab_installed_package_newest_release_age_seconds`: how long ago, from collection time, the
+//!   oldest and newest of those releases were built, judged by their release timestamps
+//!
+//! along with `hab_installed_packages_collected_timestamp_seconds`, the time of collection, so
+//! that a file which has stopped being refreshed can be noticed.
+
+use std::{collections::BTreeMap,
+          fmt,
+          path::Path};
+
+use time;
+
+use crate::{error::Result,
+            fs::{atomic_write,
+                 pkg_install_path,
+                 pkg_root_path},
+            package::{list,
+                      report::installed_size}};
+
+/// What is installed from one origin.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OriginStats {
+    pub packages:       u64,
+    /// The disk space taken up by the origin's installed packages, in bytes.
+    pub bytes:          u64,
+    /// The oldest release timestamp, as seconds since the Unix epoch.
+    pub oldest_release: Option<i64>,
+    /// The newest release timestamp, as seconds since the Unix epoch.
+    pub newest_release: Option<i64>,
+}
+
+impl OriginStats {
+    fn add(&mut self, bytes: u64, release: Option<i64>) {
+        self.packages += 1;
+        self.bytes += bytes;
+        if let Some(release) = release {
+            self.oldest_release = Some(self.oldest_release.map_or(release, |t| t.min(release)));
+            self.newest_release = Some(self.newest_release.map_or(release, |t| t.max(release)));
+        }
+    }
+}
+
+/// The installed packages under a filesystem root, summed by origin.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InstalledMetrics {
+    origins:      BTreeMap<String, OriginStats>,
+    /// When the metrics were collected, as seconds since the Unix epoch.
+    collected_at: i64,
+}
+
+impl InstalledMetrics {
+    /// Collects metrics about the packages installed under `fs_root_path`, as of now.
+    ///
+    /// # Failures
+    ///
+    /// * The package root or an installed package cannot be read
+    pub fn collect(fs_root_path: Option<&Path>) -> Result<Self> {
+        Self::collect_at(fs_root_path, time::now_utc().to_timespec().sec)
+    }
+
+    /// Collects metrics about the packages installed under `fs_root_path`, with ages measured
+    /// from `now`, in seconds since the Unix epoch.
+    ///
+    /// # Failures
+    ///
+    /// * The package root or an installed package cannot be read
+    pub fn collect_at(fs_root_path: Option<&Path>, now: i64) -> Result<Self> {
+        let mut origins = BTreeMap::<String, OriginStats>::new();
+        for ident in list::all_packages(&pkg_root_path(fs_root_path))? {
+            let bytes = installed_size(&pkg_install_path(&ident, fs_root_path))?;
+            origins.entry(ident.origin.clone())
+                   .or_default()
+                   .add(bytes, ident.release_time());
+        }
+        Ok(InstalledMetrics { origins,
+                              collected_at: now })
+    }
+
+    pub fn origins(&self) -> &BTreeMap<String, OriginStats> { &self.origins }
+
+    /// Writes the metrics to `path`, atomically, so the collector never reads a partial file.
+    /// The collector only reads files whose names end in `.prom`.
+    ///
+    /// # Failures
+    ///
+    /// * The file cannot be written
+    pub fn write(&self, path: &Path) -> Result<()> {
+        atomic_write(path, self.to_string())?;
+        Ok(())
+    }
+
+    fn age(&self, release: Option<i64>) -> Option<i64> {
+        release.map(|release| self.collected_at - release)
+    }
+}
+
+/// Renders the metrics in the Prometheus text exposition format.
+impl fmt::Display for InstalledMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        gauge(f,
+              "hab_installed_packages",
+              "Installed package releases, by origin.",
+              self.origins
+                  .iter()
+                  .map(|(o, s)| (o, Some(s.packages as i64))))?;
+        gauge(f,
+              "hab_installed_package_bytes",
+              "Disk space taken up by installed packages, by origin.",
+              self.origins.iter().map(|(o, s)| (o, Some(s.bytes as i64))))?;
+        gauge(f,
+              "hab_installed_package_oldest_release_age_seconds",
+              "Age of the oldest installed release, by origin.",
+              self.origins
+                  .iter()
+                  .map(|(o, s)| (o, self.age(s.oldest_release))))?;
+        gauge(f,
+              "hab_installed_package_newest_release_age_seconds",
+              "Age of the newest installed release, by origin.",
+              self.origins
+                  .iter()
+                  .map(|(o, s)| (o, self.age(s.newest_release))))?;
+        writeln!(f,
+                 "# HELP hab_installed_packages_collected_timestamp_seconds When these metrics \
+                  were collected.")?;
+        writeln!(f,
+                 "# TYPE hab_installed_packages_collected_timestamp_seconds gauge")?;
+        writeln!(f,
+                 "hab_installed_packages_collected_timestamp_seconds {}",
+                 self.collected_at)
+    }
+}
+
+/// Writes one gauge with a sample per origin, leaving out origins without a value.
+fn gauge<'a, I>(f: &mut fmt::Formatter<'_>, name: &str, help: &str, samples: I) -> fmt::Result
+    where I: Iterator<Item = (&'a String, Option<i64>)>
+{
+    writeln!(f, "# HELP {} {}", name, help)?;
+    writeln!(f, "# TYPE {} gauge", name)?;
+    for (origin, value) in samples {
+        if let Some(value) = value {
+            writeln!(f, "{}{{origin=\"{}\"}} {}", name, escape(origin), value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Escapes a label value: backslashes, double quotes and line feeds are written with a
+/// backslash.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::test_support::testing_package_install;
+    use std::fs;
+    use tempfile::Builder;
+
+    #[test]
+    fn collect_and_render() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let old = testing_package_install("core/redis/4.0.14/20190319155852", fs_root.path());
+        let new = testing_package_install("core/redis/5.0.4/20190419155852", fs_root.path());
+        testing_package_install("acme/app/1.0.0/20190419155852", fs_root.path());
+        fs::write(old.installed_path().join("README"), vec![0; 1000]).unwrap();
+        let now = new.ident().release_time().unwrap() + 60;
+
+        let metrics = InstalledMetrics::collect_at(Some(fs_root.path()), now).unwrap();
+
+        let core = &metrics.origins()["core"];
+        assert_eq!(2, core.packages);
+        assert!(core.bytes > 1000);
+        assert_eq!(old.ident().release_time(), core.oldest_release);
+        let rendered = metrics.to_string();
+        assert!(rendered.contains("\nhab_installed_packages{origin=\"acme\"} 1\n"));
+        assert!(rendered.contains("\nhab_installed_packages{origin=\"core\"} 2\n"));
+        assert!(rendered.contains("\nhab_installed_package_oldest_release_age_seconds{origin=\"\
+                                   core\"} 2678460\n"));
+        assert!(rendered.contains("\nhab_installed_package_newest_release_age_seconds{origin=\"\
+                                   core\"} 60\n"));
+        assert!(rendered.ends_with(&format!("hab_installed_packages_collected_timestamp_seconds \
+                                             {}\n",
+                                            now)));
+
+        let path = fs_root.path().join("hab.prom");
+        metrics.write(&path).unwrap();
+        assert_eq!(rendered, fs::read_to_string(&path).unwrap());
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!("a\\\\b\\\"c\\n", escape("a\\b\"c\n"));
+    }
+}
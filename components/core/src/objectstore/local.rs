@@ -0,0 +1,244 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{ArtifactIndex,
+            ArtifactIndexEntry,
+            ArtifactKey,
+            ObjectMeta,
+            ObjectStore};
+use crate::{crypto::hash,
+            error::{Error,
+                    Result},
+            fs::AtomicWriter};
+use std::{fs::{self,
+               File},
+          io::{self,
+               Read,
+               Write},
+          path::{Path,
+                 PathBuf}};
+
+/// An `ObjectStore` which keeps artifacts as files in a single directory.
+///
+/// Artifacts are stored under their object names, which is the same layout as the artifact cache,
+/// so a store can be pointed directly at an existing cache or at a shared mount.
+#[derive(Clone, Debug)]
+pub struct LocalDirStore {
+    root: PathBuf,
+}
+
+impl LocalDirStore {
+    /// Creates a store rooted at `root`. The directory is created when the first artifact is
+    /// stored.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self { LocalDirStore { root: root.into() } }
+
+    pub fn root(&self) -> &Path { &self.root }
+
+    /// Returns the path of the file holding the artifact for `key`, whether or not it exists.
+    pub fn path_for(&self, key: &ArtifactKey) -> PathBuf { self.root.join(key.object_name()) }
+
+    /// Returns an index of the `.hart` files in the store, sorted by name.
+    ///
+    /// Computing the index reads every artifact to checksum it, so callers serving the index to
+    /// other nodes should cache the result rather than computing it per request. A store whose
+    /// directory does not yet exist has an empty index.
+    pub fn index(&self) -> Result<ArtifactIndex> {
+        let entries = match fs::read_dir(&self.root) {
+            Ok(entries) => entries,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(ArtifactIndex::default());
+            }
+            Err(e) => return Err(Error::IO(e)),
+        };
+        let mut artifacts = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            let md = entry.metadata()?;
+            if !md.is_file() || path.extension().map_or(true, |ext| ext != "hart") {
+                continue;
+            }
+            artifacts.push(ArtifactIndexEntry { name:     entry.file_name()
+                                                               .to_string_lossy()
+                                                               .into_owned(),
+                                                size:     md.len(),
+                                                checksum: hash::hash_file(&path)?, });
+        }
+        artifacts.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(ArtifactIndex { artifacts })
+    }
+}
+
+impl ObjectStore for LocalDirStore {
+    fn head(&self, key: &ArtifactKey) -> Result<Option<ObjectMeta>> {
+        match fs::metadata(self.path_for(key)) {
+            Ok(ref md) if md.is_file() => Ok(Some(ObjectMeta { size: md.len() })),
+            Ok(_) => Ok(None),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::IO(e)),
+        }
+    }
+
+    fn get(&self, key: &ArtifactKey, dst: &mut dyn Write) -> Result<u64> {
+        let mut file = match File::open(self.path_for(key)) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::PackageNotFound(key.ident().clone()));
+            }
+            Err(e) => return Err(Error::IO(e)),
+        };
+        Ok(io::copy(&mut file, dst)?)
+    }
+
+    fn put(&self, key: &ArtifactKey, src: &mut dyn Read) -> Result<u64> {
+        fs::create_dir_all(&self.root)?;
+        let writer = AtomicWriter::new(&self.path_for(key))?;
+        writer.with_writer(|f| io::copy(src, f)).map_err(Error::IO)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::{PackageIdent,
+                         PackageTarget};
+    use std::str::FromStr;
+    use tempfile::Builder;
+
+    fn key() -> ArtifactKey {
+        ArtifactKey::new(PackageIdent::from_str("acme/pathy/1.0.0/20190101000000").unwrap(),
+                         PackageTarget::from_str("x86_64-linux").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn put_then_get_round_trips() {
+        let root = Builder::new().prefix("objectstore").tempdir().unwrap();
+        let store = LocalDirStore::new(root.path().join("artifacts"));
+        let data = b"not really a hart";
+
+        assert_eq!(None, store.head(&key()).unwrap());
+        assert_eq!(data.len() as u64,
+                   store.put(&key(), &mut &data[..]).unwrap());
+        assert_eq!(Some(ObjectMeta { size: data.len() as u64, }),
+                   store.head(&key()).unwrap());
+        assert!(root.path()
+                    .join("artifacts")
+                    .join("acme-pathy-1.0.0-20190101000000-x86_64-linux.hart")
+                    .is_file());
+
+        let mut out = Vec::new();
+        store.get(&key(), &mut out).unwrap();
+        assert_eq!(&data[..], &out[..]);
+    }
+
+    #[test]
+    fn index_lists_only_artifacts() {
+        let root = Builder::new().prefix("objectstore").tempdir().unwrap();
+        let store = LocalDirStore::new(root.path());
+        store.put(&key(), &mut &b"not really a hart"[..]).unwrap();
+        File::create(root.path().join("notes.txt")).unwrap();
+
+        let index = store.index().unwrap();
+
+        assert_eq!(1, index.artifacts.len());
+        let entry = index.get(&key()).expect("artifact should be in index");
+        assert_eq!(key().object_name(), entry.name);
+        assert_eq!(17, entry.size);
+    }
+
+    #[test]
+    fn latest_finds_the_newest_artifact_satisfying_an_ident() {
+        let root = Builder::new().prefix("objectstore").tempdir().unwrap();
+        let store = LocalDirStore::new(root.path());
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        for ident in &["acme/pathy/1.0.0/20190101000000",
+                       "acme/pathy/1.2.0/20190201000000",
+                       "acme/pathy/1.10.0/20190102000000",
+                       "acme/pathy-tools/2.0.0/20190301000000",
+                       "acme/pathy/1.0.0/20190301000000"]
+        {
+            let key = ArtifactKey::new(PackageIdent::from_str(ident).unwrap(), target).unwrap();
+            store.put(&key, &mut &b"not really a hart"[..]).unwrap();
+        }
+        let index = store.index().unwrap();
+        let latest = |ident: &str| {
+            index.latest(&PackageIdent::from_str(ident).unwrap(), target)
+                 .map(|(key, _)| key.ident().to_string())
+        };
+
+        assert_eq!(Some("acme/pathy/1.10.0/20190102000000".to_string()),
+                   latest("acme/pathy"));
+        assert_eq!(Some("acme/pathy/1.0.0/20190301000000".to_string()),
+                   latest("acme/pathy/1.0.0"));
+        assert_eq!(Some("acme/pathy/1.0.0/20190101000000".to_string()),
+                   latest("acme/pathy/1.0.0/20190101000000"));
+        assert_eq!(Some("acme/pathy-tools/2.0.0/20190301000000".to_string()),
+                   latest("acme/pathy-tools"));
+        assert_eq!(None, latest("acme/pathy/3.0.0"));
+        assert_eq!(None,
+                   index.latest(&PackageIdent::from_str("acme/pathy").unwrap(),
+                                PackageTarget::from_str("x86_64-windows").unwrap()));
+    }
+
+    #[test]
+    fn latest_ignores_names_with_unusable_versions() {
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let names = ["acme-pathy-..-20190101000000-x86_64-linux.hart",
+                     "acme-pathy-1.0/..-20190101000000-x86_64-linux.hart",
+                     "acme-pathy-1.0\\..-20190101000000-x86_64-linux.hart",
+                     "acme-pathy--20190101000000-x86_64-linux.hart"];
+        let artifacts = names.iter()
+                             .map(|name| {
+                                 ArtifactIndexEntry { name:     name.to_string(),
+                                                      size:     0,
+                                                      checksum: String::new(), }
+                             })
+                             .collect();
+        let index = ArtifactIndex { artifacts };
+
+        assert!(index.latest(&PackageIdent::from_str("acme/pathy").unwrap(), target)
+                     .is_none());
+    }
+
+    #[test]
+    fn index_of_missing_directory_is_empty() {
+        let root = Builder::new().prefix("objectstore").tempdir().unwrap();
+        let store = LocalDirStore::new(root.path().join("nope"));
+
+        assert!(store.index().unwrap().artifacts.is_empty());
+    }
+
+    #[test]
+    fn get_missing_artifact_returns_package_not_found() {
+        let root = Builder::new().prefix("objectstore").tempdir().unwrap();
+        let store = LocalDirStore::new(root.path());
+
+        match store.get(&key(), &mut Vec::new()) {
+            Err(Error::PackageNotFound(ident)) => assert_eq!(key().ident(), &ident),
+            other => panic!("Expected PackageNotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn artifact_key_requires_fully_qualified_ident() {
+        let ident = PackageIdent::from_str("acme/pathy").unwrap();
+        match ArtifactKey::new(ident, PackageTarget::from_str("x86_64-linux").unwrap()) {
+            Err(Error::FullyQualifiedPackageIdentRequired(_)) => (),
+            other => {
+                panic!("Expected FullyQualifiedPackageIdentRequired, got {:?}",
+                       other)
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Storage backends for package artifacts.
+//!
+//! An `ObjectStore` holds `.hart` artifacts keyed by a fully qualified package identifier and
+//! the target it was built for. Core ships with a store backed by a local directory; stores for
+//! remote services (such as S3, GCS, or Azure Blob Storage) implement the same trait and live
+//! behind their own feature flags or in external crates, so that consumers only pay for the
+//! client libraries they use.
+
+mod local;
+
+pub use self::local::LocalDirStore;
+
+use crate::{error::Result,
+            package::{Identifiable,
+                      PackageIdent,
+                      PackageTarget}};
+use serde_derive::{Deserialize,
+                   Serialize};
+use std::{fmt,
+          io::{Read,
+               Write}};
+
+/// The key under which an artifact is stored: a fully qualified package identifier and the
+/// target the artifact was built for.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ArtifactKey {
+    ident:  PackageIdent,
+    target: PackageTarget,
+}
+
+impl ArtifactKey {
+    /// Creates a new key for the given identifier and target.
+    ///
+    /// # Failures
+    ///
+    /// * The package identifier is not fully qualified
+    pub fn new(ident: PackageIdent, target: PackageTarget) -> Result<Self> {
+        // Validates that the identifier is fully qualified
+        ident.archive_name_with_target(target)?;
+        Ok(ArtifactKey { ident, target })
+    }
+
+    pub fn ident(&self) -> &PackageIdent { &self.ident }
+
+    pub fn target(&self) -> PackageTarget { self.target }
+
+    /// Returns the name of the object for this key, which is the artifact's file name, such as
+    /// `core-redis-3.0.7-20160614001713-x86_64-linux.hart`.
+    ///
+    /// Object names are flat and unique, and so can be used directly as object keys by stores
+    /// with no notion of directories.
+    pub fn object_name(&self) -> String {
+        self.ident
+            .archive_name_with_target(self.target)
+            .expect("ArtifactKey ident is fully qualified")
+    }
+}
+
+impl fmt::Display for ArtifactKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.ident, self.target)
+    }
+}
+
+/// Metadata about a stored artifact.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ObjectMeta {
+    /// The size of the artifact in bytes.
+    pub size: u64,
+}
+
+/// The name under which a store's `ArtifactIndex` is advertised, relative to the location of the
+/// artifacts themselves.
+pub const ARTIFACT_INDEX_NAME: &str = "index.json";
+
+/// A listing of the artifacts held by a store, suitable for advertising a store's contents to
+/// other nodes.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ArtifactIndex {
+    pub artifacts: Vec<ArtifactIndexEntry>,
+}
+
+impl ArtifactIndex {
+    /// Returns the entry for `key`, if present.
+    pub fn get(&self, key: &ArtifactKey) -> Option<&ArtifactIndexEntry> {
+        let name = key.object_name();
+        self.artifacts.iter().find(|entry| entry.name == name)
+    }
+
+    /// Returns the key and entry of the newest artifact which satisfies `ident` and was built for
+    /// `target`, if there is one. A fully qualified `ident` finds only its own artifact.
+    ///
+    /// The package is recovered from each entry's object name, which cannot tell apart an origin
+    /// and name that differ only in where a dash falls, such as `acme-tools/pathy` and
+    /// `acme/tools-pathy`, nor a version containing a dash from a name which ends in one.
+    /// Versions containing a dash are therefore only found when `ident` gives the version.
+    pub fn latest(&self,
+                  ident: &PackageIdent,
+                  target: PackageTarget)
+                  -> Option<(ArtifactKey, &ArtifactIndexEntry)> {
+        if ident.fully_qualified() {
+            let key = ArtifactKey::new(ident.clone(), target).ok()?;
+            return self.get(&key).map(|entry| (key, entry));
+        }
+        self.artifacts
+            .iter()
+            .filter_map(|entry| {
+                let found = ident_in_object_name(&entry.name, ident, target)?;
+                ArtifactKey::new(found, target).ok().map(|key| (key, entry))
+            })
+            .max_by(|(a, _), (b, _)| a.ident.cmp(&b.ident))
+    }
+}
+
+/// The fully qualified package satisfying `ident` whose artifact for `target` has the object name
+/// `name`, if there is one.
+fn ident_in_object_name(name: &str,
+                        ident: &PackageIdent,
+                        target: PackageTarget)
+                        -> Option<PackageIdent> {
+    let prefix = match ident.version {
+        Some(ref version) => format!("{}-{}-{}-", ident.origin, ident.name, version),
+        None => format!("{}-{}-", ident.origin, ident.name),
+    };
+    let suffix = format!("-{}.hart", target);
+    if name.len() <= prefix.len() + suffix.len()
+       || !name.starts_with(&prefix)
+       || !name.ends_with(&suffix)
+    {
+        return None;
+    }
+    let rest = &name[prefix.len()..name.len() - suffix.len()];
+    let (version, release) = match ident.version {
+        Some(ref version) => (version.as_str(), rest),
+        None => {
+            let mut parts = rest.rsplitn(2, '-');
+            let release = parts.next()?;
+            let version = parts.next()?;
+            if !is_object_name_version(version) {
+                return None;
+            }
+            (version, release)
+        }
+    };
+    if release.len() != 14 || !release.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(PackageIdent::new(ident.origin.as_str(),
+                           ident.name.as_str(),
+                           Some(version),
+                           Some(release)))
+}
+
+/// Whether `version`, recovered from an object name, could be a package version. Since an index
+/// may come from a peer, this refuses a dash, which would be ambiguous, as well as path
+/// separators and `.` or `..`, so that the version can safely become part of a path.
+fn is_object_name_version(version: &str) -> bool {
+    version.bytes()
+           .all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'_' || b == b'+')
+    && version.bytes().any(|b| b.is_ascii_alphanumeric())
+}
+
+/// A single artifact in an `ArtifactIndex`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ArtifactIndexEntry {
+    /// The object name of the artifact (see `ArtifactKey::object_name`).
+    pub name:     String,
+    /// The size of the artifact in bytes.
+    pub size:     u64,
+    /// The BLAKE2b checksum of the artifact, as a hex string.
+    pub checksum: String,
+}
+
+/// A store of package artifacts.
+///
+/// Implementations must be safe to share between threads so a single store can serve concurrent
+/// installs.
+pub trait ObjectStore: Send + Sync {
+    /// Returns the metadata for the artifact stored under `key`, or `None` if there is no such
+    /// artifact.
+    fn head(&self, key: &ArtifactKey) -> Result<Option<ObjectMeta>>;
+
+    /// Copies the artifact stored under `key` into `dst`, returning the number of bytes written.
+    ///
+    /// # Failures
+    ///
+    /// * There is no artifact stored under `key`, in which case `Error::PackageNotFound` is
+    ///   returned
+    fn get(&self, key: &ArtifactKey, dst: &mut dyn Write) -> Result<u64>;
+
+    /// Stores the contents of `src` as the artifact under `key`, replacing any existing artifact,
+    /// and returns the number of bytes stored.
+    ///
+    /// Implementations must not expose a partially written artifact to readers.
+    fn put(&self, key: &ArtifactKey, src: &mut dyn Read) -> Result<u64>;
+
+    /// Returns whether an artifact is stored under `key`.
+    fn contains(&self, key: &ArtifactKey) -> Result<bool> { Ok(self.head(key)?.is_some()) }
+}
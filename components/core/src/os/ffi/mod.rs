@@ -20,3 +20,34 @@ pub use self::windows::OsStrExt3 as OsStrExt;
 
 #[cfg(not(windows))]
 pub use std::os::unix::ffi::OsStrExt;
+
+use std::{borrow::Cow,
+          ffi::{OsStr,
+                OsString}};
+
+/// Converts bytes read from a file, such as a package metafile, to an `OsString`. On Unix the
+/// bytes are kept as they are; on Windows they are taken to be UTF-8, and any invalid sequences
+/// are replaced.
+#[cfg(not(windows))]
+pub fn os_string_from_bytes(bytes: Vec<u8>) -> OsString {
+    std::os::unix::ffi::OsStringExt::from_vec(bytes)
+}
+
+#[cfg(windows)]
+pub fn os_string_from_bytes(bytes: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Returns the bytes of `s`, for processing which only looks for ASCII characters and passes
+/// everything else through. On Windows, `s` is converted to UTF-8, and any unpaired surrogates
+/// are replaced.
+#[cfg(not(windows))]
+pub fn os_str_bytes(s: &OsStr) -> Cow<'_, [u8]> { Cow::Borrowed(s.as_bytes()) }
+
+#[cfg(windows)]
+pub fn os_str_bytes(s: &OsStr) -> Cow<'_, [u8]> {
+    match s.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+    }
+}
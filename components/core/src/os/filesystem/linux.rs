@@ -13,3 +13,23 @@
 // limitations under the License.
 
 pub use std::os::unix::fs::symlink;
+
+use libc;
+use std::{ffi::CString,
+          io,
+          mem,
+          os::unix::ffi::OsStrExt,
+          path::Path};
+
+/// Returns the number of bytes available to unprivileged users on the filesystem holding `path`.
+#[allow(clippy::identity_conversion)]
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    unsafe {
+        let mut stat: libc::statvfs = mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(u64::from(stat.f_bavail) * stat.f_frsize as u64)
+    }
+}
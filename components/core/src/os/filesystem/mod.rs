@@ -17,10 +17,12 @@
 mod windows;
 
 #[cfg(windows)]
-pub use self::windows::symlink;
+pub use self::windows::{free_space,
+                        symlink};
 
 #[cfg(not(windows))]
 mod linux;
 
 #[cfg(not(windows))]
-pub use self::linux::symlink;
+pub use self::linux::{free_space,
+                      symlink};
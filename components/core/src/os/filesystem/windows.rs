@@ -13,8 +13,29 @@
 // limitations under the License.
 
 use std::{io,
-          path::Path};
+          os::windows::ffi::OsStrExt,
+          path::Path,
+          ptr};
+use winapi::{shared::ntdef::ULARGE_INTEGER,
+             um::fileapi::GetDiskFreeSpaceExW};
 
 pub fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> io::Result<()> {
     unimplemented!();
 }
+
+/// Returns the number of bytes available to the current user on the volume holding `path`.
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    unsafe {
+        let mut available: ULARGE_INTEGER = std::mem::zeroed();
+        if GetDiskFreeSpaceExW(wide.as_ptr(),
+                               &mut available,
+                               ptr::null_mut(),
+                               ptr::null_mut())
+           == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(*available.QuadPart())
+    }
+}
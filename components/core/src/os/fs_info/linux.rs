@@ -0,0 +1,189 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{FsInfo,
+            MountOptions};
+use libc;
+use std::{ffi::CString,
+          fs,
+          io,
+          mem,
+          os::unix::ffi::OsStrExt,
+          path::{Path,
+                 PathBuf},
+          str};
+
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+/// Describes the filesystem holding `path`, which must exist.
+///
+/// The mount is looked up in `/proc/self/mountinfo`. Where that cannot be read, as on macOS,
+/// only the options `statvfs` reports are known, and the filesystem type is `unknown`.
+///
+/// # Failures
+///
+/// * `path` does not exist or cannot be inspected
+#[allow(clippy::identity_conversion)]
+pub fn fs_info(path: &Path) -> io::Result<FsInfo> {
+    let path = path.canonicalize()?;
+    let stat = statvfs(&path)?;
+    let mut info =
+        FsInfo { mount_point:  path.clone(),
+                 fs_type:      "unknown".to_string(),
+                 source:       String::new(),
+                 options:      MountOptions { read_only: stat.f_flag & libc::ST_RDONLY != 0,
+                                              nosuid: stat.f_flag & libc::ST_NOSUID != 0,
+                                              ..MountOptions::default() },
+                 free_bytes:   u64::from(stat.f_bavail) * u64::from(stat.f_frsize),
+                 free_inodes:  None,
+                 total_inodes: None, };
+    if stat.f_files > 0 {
+        info.free_inodes = Some(u64::from(stat.f_favail));
+        info.total_inodes = Some(u64::from(stat.f_files));
+    }
+    if let Ok(mountinfo) = fs::read_to_string(MOUNTINFO) {
+        if let Some(mount) = find_mount(&mountinfo, &path) {
+            info.mount_point = mount.mount_point;
+            info.fs_type = mount.fs_type;
+            info.source = mount.source;
+            info.options = mount.options;
+        }
+    }
+    Ok(info)
+}
+
+fn statvfs(path: &Path) -> io::Result<libc::statvfs> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    unsafe {
+        let mut stat: libc::statvfs = mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(stat)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Mount {
+    mount_point: PathBuf,
+    fs_type:     String,
+    source:      String,
+    options:     MountOptions,
+}
+
+/// Finds the mount holding `path` in the content of a mountinfo file: the one with the longest
+/// mount point containing `path`, and of those the last mounted, which hides the others.
+fn find_mount(mountinfo: &str, path: &Path) -> Option<Mount> {
+    let mut found: Option<Mount> = None;
+    for mount in mountinfo.lines().filter_map(parse_mountinfo_line) {
+        if !path.starts_with(&mount.mount_point) {
+            continue;
+        }
+        let deeper = match found {
+            Some(ref f) => {
+                mount.mount_point.components().count() >= f.mount_point.components().count()
+            }
+            None => true,
+        };
+        if deeper {
+            found = Some(mount);
+        }
+    }
+    found
+}
+
+/// Parses a line such as
+///
+/// ```text
+/// 36 35 98:0 / /hab rw,nosuid,noexec shared:1 - ext4 /dev/sdb1 rw,errors=continue
+/// ```
+///
+/// where the fields before the `-` are the mount ID, parent ID, device numbers, root, mount
+/// point, per-mount options, and optional tags, and those after are the filesystem type, source,
+/// and filesystem options.
+fn parse_mountinfo_line(line: &str) -> Option<Mount> {
+    let mut halves = line.splitn(2, " - ");
+    let mount_fields: Vec<&str> = halves.next()?.split(' ').collect();
+    let fs_fields: Vec<&str> = halves.next()?.split(' ').collect();
+    if mount_fields.len() < 6 || fs_fields.len() < 3 {
+        return None;
+    }
+    let mut options = MountOptions::default();
+    for option in mount_fields[5].split(',').chain(fs_fields[2].split(',')) {
+        match option {
+            "ro" => options.read_only = true,
+            "noexec" => options.noexec = true,
+            "nosuid" => options.nosuid = true,
+            "nodev" => options.nodev = true,
+            _ => {}
+        }
+    }
+    Some(Mount { mount_point: PathBuf::from(unescape(mount_fields[4])),
+                 fs_type: unescape(fs_fields[0]),
+                 source: unescape(fs_fields[1]),
+                 options })
+}
+
+/// Undoes the octal escaping of spaces, tabs, newlines, and backslashes in mountinfo fields.
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() {
+            let digits = str::from_utf8(&bytes[i + 1..i + 4]).ok();
+            if let Some(byte) = digits.and_then(|d| u8::from_str_radix(d, 8).ok()) {
+                out.push(byte);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw,errors=remount-ro
+23 22 0:21 / /proc rw,nosuid,nodev,noexec,relatime shared:2 - proc proc rw
+41 22 8:17 / /hab rw,nosuid,noexec,relatime shared:20 - xfs /dev/sdb1 rw,attr2
+42 41 0:45 / /hab/svc/my\\040app rw shared:21 - tmpfs tmpfs ro
+";
+
+    #[test]
+    fn finds_the_deepest_mount() {
+        let mount = find_mount(MOUNTINFO, Path::new("/hab/pkgs/core/redis")).unwrap();
+
+        assert_eq!(Path::new("/hab"), mount.mount_point);
+        assert_eq!("xfs", mount.fs_type);
+        assert_eq!("/dev/sdb1", mount.source);
+        assert_eq!(MountOptions { read_only: false,
+                                  noexec:    true,
+                                  nosuid:    true,
+                                  nodev:     false, },
+                   mount.options);
+
+        let mount = find_mount(MOUNTINFO, Path::new("/hab/svc/my app/data")).unwrap();
+        assert_eq!(Path::new("/hab/svc/my app"), mount.mount_point);
+        assert!(mount.options.read_only);
+
+        let mount = find_mount(MOUNTINFO, Path::new("/habitat")).unwrap();
+        assert_eq!(Path::new("/"), mount.mount_point);
+    }
+}
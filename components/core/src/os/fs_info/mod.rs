@@ -0,0 +1,159 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The filesystem and mount a path lives on.
+//!
+//! Packages and services under `/hab` need a filesystem they can execute from, write to, and
+//! create files on. When `/hab` sits on a `noexec` or read-only mount, or one out of inodes,
+//! installs may appear to succeed and services then fail at start with errors that say nothing
+//! about the mount. `fs_info` describes the mount, and `FsInfo::problems` lists what about it
+//! would get in the way, so installers can warn up front.
+
+#[cfg(windows)]
+mod windows;
+#[cfg(windows)]
+pub use self::windows::fs_info;
+
+#[cfg(not(windows))]
+mod linux;
+#[cfg(not(windows))]
+pub use self::linux::fs_info;
+
+use serde_derive::Serialize;
+use std::{fmt,
+          path::PathBuf};
+
+/// Below this many free inodes, a filesystem is reported as running out.
+pub const LOW_INODES: u64 = 1024;
+
+/// The mount options which matter for running packages.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct MountOptions {
+    pub read_only: bool,
+    /// Programs cannot be executed from the mount.
+    pub noexec:    bool,
+    /// Setuid and setgid bits are ignored on the mount.
+    pub nosuid:    bool,
+    /// Device files cannot be used on the mount.
+    pub nodev:     bool,
+}
+
+/// What is known about the filesystem holding a path.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct FsInfo {
+    /// Where the filesystem is mounted, or on Windows the root of the volume.
+    pub mount_point:  PathBuf,
+    /// The type of filesystem, such as `ext4`, `nfs`, `overlay`, or `NTFS`.
+    pub fs_type:      String,
+    /// The device or remote share mounted, or on Windows the volume label.
+    pub source:       String,
+    pub options:      MountOptions,
+    /// Bytes available to unprivileged users.
+    pub free_bytes:   u64,
+    /// Inodes available to unprivileged users, or `None` where the filesystem allocates them as
+    /// it needs, as Btrfs and NTFS do.
+    pub free_inodes:  Option<u64>,
+    pub total_inodes: Option<u64>,
+}
+
+impl FsInfo {
+    /// Everything about the filesystem which would stop packages installed on it from running.
+    pub fn problems(&self) -> Vec<MountProblem> {
+        let mut problems = Vec::new();
+        if self.options.read_only {
+            problems.push(MountProblem::ReadOnly);
+        }
+        if self.options.noexec {
+            problems.push(MountProblem::NoExec);
+        }
+        if self.options.nosuid {
+            problems.push(MountProblem::NoSuid);
+        }
+        match self.free_inodes {
+            Some(free) if free < LOW_INODES => problems.push(MountProblem::LowInodes(free)),
+            _ => {}
+        }
+        problems
+    }
+}
+
+/// Something about a mount which gets in the way of installing or running packages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum MountProblem {
+    ReadOnly,
+    NoExec,
+    NoSuid,
+    /// Only this many inodes are free.
+    LowInodes(u64),
+}
+
+impl fmt::Display for MountProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            MountProblem::ReadOnly => write!(f, "the filesystem is mounted read-only"),
+            MountProblem::NoExec => {
+                write!(f,
+                       "the filesystem is mounted noexec, so package binaries cannot be run")
+            }
+            MountProblem::NoSuid => {
+                write!(f,
+                       "the filesystem is mounted nosuid, so setuid binaries lose their privileges")
+            }
+            MountProblem::LowInodes(free) => {
+                write!(f, "the filesystem has only {} free inodes", free)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn describes_the_filesystem_holding_a_path() {
+        let root = Builder::new().prefix("fs-root").tempdir().unwrap();
+
+        let info = fs_info(root.path()).unwrap();
+
+        assert!(root.path()
+                    .canonicalize()
+                    .unwrap()
+                    .starts_with(&info.mount_point));
+        assert!(!info.fs_type.is_empty());
+        assert!(!info.options.read_only);
+    }
+
+    #[test]
+    fn problems_with_a_mount() {
+        let mut info = FsInfo { mount_point:  PathBuf::from("/hab"),
+                                fs_type:      "ext4".to_string(),
+                                source:       "/dev/sdb1".to_string(),
+                                options:      MountOptions::default(),
+                                free_bytes:   1 << 30,
+                                free_inodes:  Some(1 << 20),
+                                total_inodes: Some(1 << 20), };
+        assert!(info.problems().is_empty());
+
+        info.options.noexec = true;
+        info.options.read_only = true;
+        info.free_inodes = Some(12);
+
+        assert_eq!(vec![MountProblem::ReadOnly,
+                        MountProblem::NoExec,
+                        MountProblem::LowInodes(12)],
+                   info.problems());
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{FsInfo,
+            MountOptions};
+use crate::os::filesystem;
+use std::{ffi::OsString,
+          io,
+          os::windows::ffi::{OsStrExt,
+                             OsStringExt},
+          path::{Path,
+                 PathBuf},
+          ptr};
+use winapi::{shared::minwindef::{DWORD,
+                                 MAX_PATH},
+             um::{fileapi::{GetVolumeInformationW,
+                            GetVolumePathNameW},
+                  winnt::FILE_READ_ONLY_VOLUME}};
+
+/// Describes the volume holding `path`, which must exist. Windows has no equivalent of
+/// `noexec` or `nosuid`, and NTFS allocates file records as it needs them, so only whether the
+/// volume is read-only and its free space are known.
+///
+/// # Failures
+///
+/// * `path` does not exist or its volume cannot be inspected
+pub fn fs_info(path: &Path) -> io::Result<FsInfo> {
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut root = vec![0u16; MAX_PATH + 1];
+    let mut label = vec![0u16; MAX_PATH + 1];
+    let mut fs_name = vec![0u16; MAX_PATH + 1];
+    let mut flags: DWORD = 0;
+    unsafe {
+        if GetVolumePathNameW(wide.as_ptr(), root.as_mut_ptr(), root.len() as DWORD) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if GetVolumeInformationW(root.as_ptr(),
+                                 label.as_mut_ptr(),
+                                 label.len() as DWORD,
+                                 ptr::null_mut(),
+                                 ptr::null_mut(),
+                                 &mut flags,
+                                 fs_name.as_mut_ptr(),
+                                 fs_name.len() as DWORD)
+           == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    let mount_point = PathBuf::from(from_wide(&root));
+    let free_bytes = filesystem::free_space(&mount_point)?;
+    Ok(FsInfo { mount_point,
+                fs_type: from_wide(&fs_name).to_string_lossy().into_owned(),
+                source: from_wide(&label).to_string_lossy().into_owned(),
+                options: MountOptions { read_only: flags & FILE_READ_ONLY_VOLUME != 0,
+                                        ..MountOptions::default() },
+                free_bytes,
+                free_inodes: None,
+                total_inodes: None })
+}
+
+fn from_wide(buf: &[u16]) -> OsString {
+    let len = buf.iter()
+                 .position(|&c| c == 0)
+                 .unwrap_or_else(|| buf.len());
+    OsString::from_wide(&buf[..len])
+}
@@ -14,8 +14,14 @@
 
 pub mod ffi;
 pub mod filesystem;
+pub mod fs_info;
 pub mod net;
 pub mod process;
+#[cfg(windows)]
+pub mod registry;
+pub mod service;
 pub mod signals;
 pub mod system;
+#[cfg(windows)]
+pub mod system_path;
 pub mod users;
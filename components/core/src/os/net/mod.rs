@@ -20,5 +20,8 @@ mod imp;
 #[cfg(not(windows))]
 #[path = "unix.rs"]
 mod imp;
+mod ports;
 
-pub use self::imp::*;
+pub use self::{imp::*,
+               ports::{PortPicker,
+                       Reservation}};
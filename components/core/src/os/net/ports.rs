@@ -0,0 +1,176 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reserving ports for services.
+//!
+//! Checking that a port is free and then binding it later leaves a window in which something
+//! else can take it. A `Reservation` closes that window by binding the port straight away and
+//! holding it until the service is ready to listen, at which point the bound listener is handed
+//! over, or the port is released just before the service binds it itself.
+
+use std::{net::{IpAddr,
+                Ipv4Addr,
+                SocketAddr,
+                TcpListener},
+          ops::RangeInclusive};
+
+use crate::error::{Error,
+                   Result};
+
+/// Where to look for a free port when the one asked for is taken, when no range is given.
+const DEFAULT_SEARCH_RANGE: RangeInclusive<u16> = 1024..=65535;
+
+/// Reserves and checks ports on one address.
+#[derive(Clone, Debug)]
+pub struct PortPicker {
+    ip:    IpAddr,
+    range: RangeInclusive<u16>,
+}
+
+impl Default for PortPicker {
+    fn default() -> Self {
+        PortPicker { ip:    IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                     range: DEFAULT_SEARCH_RANGE, }
+    }
+}
+
+impl PortPicker {
+    /// A picker for ports on every IPv4 address.
+    pub fn new() -> Self { Self::default() }
+
+    /// Picks ports on `ip` instead.
+    pub fn ip(mut self, ip: IpAddr) -> Self {
+        self.ip = ip;
+        self
+    }
+
+    /// Only suggests ports in `range` in place of ports which are taken.
+    pub fn range(mut self, range: RangeInclusive<u16>) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// Reserves a port chosen by the operating system.
+    ///
+    /// # Failures
+    ///
+    /// * No port can be bound on the picker's address
+    pub fn reserve_ephemeral(&self) -> Result<Reservation> { Ok(self.bind(0)?) }
+
+    /// Reserves `port`.
+    ///
+    /// # Failures
+    ///
+    /// * The port is already in use, in which case the error suggests a free port
+    pub fn reserve(&self, port: u16) -> Result<Reservation> {
+        self.bind(port)
+            .map_err(|_| Error::PortUnavailable(port, self.suggest(port)))
+    }
+
+    /// Reserves every one of `ports`, or none of them if any is already in use. Use this to
+    /// check a service's exposed ports before it starts.
+    ///
+    /// # Failures
+    ///
+    /// * One of the ports is already in use, in which case the error names the first such port and
+    ///   suggests a free one
+    pub fn reserve_all(&self, ports: &[u16]) -> Result<Vec<Reservation>> {
+        ports.iter().map(|&port| self.reserve(port)).collect()
+    }
+
+    /// Whether `port` is free at this moment. Prefer `reserve`, since the port may be taken as
+    /// soon as this returns.
+    pub fn is_available(&self, port: u16) -> bool { self.bind(port).is_ok() }
+
+    /// Returns the first free port in the picker's range after `port`, wrapping around to the
+    /// start of the range, if there is one.
+    pub fn suggest(&self, port: u16) -> Option<u16> {
+        let (start, end) = (*self.range.start(), *self.range.end());
+        let after = port.max(start.saturating_sub(1));
+        (after.saturating_add(1)..=end).chain(start..=after.min(end))
+                                       .filter(|&candidate| candidate != port)
+                                       .find(|&candidate| self.is_available(candidate))
+    }
+
+    fn bind(&self, port: u16) -> std::io::Result<Reservation> {
+        TcpListener::bind(SocketAddr::new(self.ip, port)).map(|listener| Reservation { listener })
+    }
+}
+
+/// A port bound on behalf of a service, which nothing else can take until it is released.
+#[derive(Debug)]
+pub struct Reservation {
+    listener: TcpListener,
+}
+
+impl Reservation {
+    /// The reserved port.
+    pub fn port(&self) -> u16 {
+        self.listener
+            .local_addr()
+            .map(|addr| addr.port())
+            .expect("a bound listener has a local address")
+    }
+
+    /// Hands over the bound listener, for services which can accept an inherited socket.
+    pub fn into_listener(self) -> TcpListener { self.listener }
+
+    /// Releases the port so the service can bind it, returning the port.
+    pub fn release(self) -> u16 { self.port() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn loopback() -> PortPicker { PortPicker::new().ip(IpAddr::V4(Ipv4Addr::LOCALHOST)) }
+
+    #[test]
+    fn reservations_hold_their_port() {
+        let picker = loopback();
+        let reservation = picker.reserve_ephemeral().unwrap();
+        let port = reservation.port();
+
+        assert!(!picker.is_available(port));
+        match picker.reserve(port) {
+            Err(Error::PortUnavailable(taken, Some(free))) => {
+                assert_eq!(port, taken);
+                assert_ne!(port, free);
+            }
+            other => panic!("Expected PortUnavailable, got {:?}", other),
+        }
+
+        assert_eq!(port, reservation.release());
+        assert!(picker.is_available(port));
+    }
+
+    #[test]
+    fn reserve_all_reserves_none_when_one_is_taken() {
+        let picker = loopback();
+        let taken = picker.reserve_ephemeral().unwrap();
+        let free = picker.reserve_ephemeral().unwrap().release();
+
+        assert!(picker.reserve_all(&[free, taken.port()]).is_err());
+        assert!(picker.is_available(free));
+    }
+
+    #[test]
+    fn suggestions_stay_in_range() {
+        let held = loopback().reserve_ephemeral().unwrap();
+        let port = held.port();
+        let picker = loopback().range(port..=port);
+
+        assert_eq!(None, picker.suggest(port));
+    }
+}
@@ -15,6 +15,9 @@
 #[cfg(windows)]
 pub mod windows_child;
 
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub mod seccomp;
+
 #[allow(unused_variables)]
 #[cfg(windows)]
 mod windows;
@@ -25,8 +28,10 @@ mod unix;
 #[cfg(windows)]
 pub use self::windows::{become_command,
                         current_pid,
+                        executables,
                         handle_from_pid,
                         is_alive,
+                        start_time,
                         Pid};
 
 #[cfg(unix)]
@@ -34,7 +39,9 @@ pub(crate) use self::unix::SignalCode;
 #[cfg(unix)]
 pub use self::unix::{become_command,
                      current_pid,
+                     executables,
                      is_alive,
                      signal,
+                     start_time,
                      Pid,
                      Signal};
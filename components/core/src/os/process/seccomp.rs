@@ -0,0 +1,836 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Syscall confinement for service processes with seccomp filters.
+//!
+//! Confinement is opt-in: a Supervisor which wants it calls `confine` on the `Command` for a
+//! service, and the filter is applied in the child just before it execs the service, so it holds
+//! for the service and everything it runs. A package may declare its own profile in its
+//! `SECCOMP_PROFILE` metafile; packages which don't get `Profile::baseline`, which allows
+//! everything except the syscalls a service has no business making, such as `mount`, `reboot`
+//! or `kexec_load`.
+//!
+//! A profile is written one rule to a line, each an action followed by the names of the syscalls
+//! it applies to. A `default` line gives the action for syscalls no rule names, which is `allow`
+//! if there is none. The first rule naming a syscall wins, and `#` starts a comment:
+//!
+//! ```text
+//! # Only allow what redis needs
+//! default enosys
+//! allow read write openat close fstat mmap munmap brk epoll_wait accept4
+//! kill ptrace
+//! ```
+//!
+//! The actions are `allow`, `errno`, which fails the syscall with `EPERM`, `enosys`, which fails
+//! it with `ENOSYS` as if the kernel lacked it, `kill`, which kills the process, and `log`, which
+//! allows the syscall but logs it to the audit log and needs Linux 4.14 or later. A profile which
+//! only allows what it names should use `default enosys`, since the C library falls back from
+//! syscalls newer than the profile, such as `clone3`, only when they fail with `ENOSYS`. Only
+//! x86_64 syscalls are known; 32-bit and x32 syscalls are always refused.
+
+use std::{fmt,
+          io,
+          os::unix::process::CommandExt,
+          process::Command,
+          str::FromStr};
+
+use libc::{self,
+           c_long,
+           c_ulong};
+
+use crate::{error::{Error,
+                    Result},
+            package::PackageInstall};
+
+/// `offsetof(struct seccomp_data, nr)`
+const SECCOMP_DATA_NR: u32 = 0;
+/// `offsetof(struct seccomp_data, arch)`
+const SECCOMP_DATA_ARCH: u32 = 4;
+const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+/// Syscalls numbered from here up are made through the x32 ABI.
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+/// The most instructions the kernel accepts in one filter.
+const BPF_MAXINSNS: usize = 4096;
+
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+/// The syscalls `Profile::baseline` refuses: those which reconfigure the host, load code into the
+/// kernel, or reach into other processes. This includes the newer mount API, which would
+/// otherwise get around refusing `mount`.
+pub const BASELINE_DENIED: &[&str] = &["acct",
+                                       "add_key",
+                                       "bpf",
+                                       "clock_adjtime",
+                                       "clock_settime",
+                                       "delete_module",
+                                       "finit_module",
+                                       "fsconfig",
+                                       "fsmount",
+                                       "fsopen",
+                                       "fspick",
+                                       "get_mempolicy",
+                                       "init_module",
+                                       "ioperm",
+                                       "iopl",
+                                       "kcmp",
+                                       "kexec_file_load",
+                                       "kexec_load",
+                                       "keyctl",
+                                       "lookup_dcookie",
+                                       "mbind",
+                                       "mount",
+                                       "mount_setattr",
+                                       "move_mount",
+                                       "move_pages",
+                                       "name_to_handle_at",
+                                       "nfsservctl",
+                                       "open_by_handle_at",
+                                       "open_tree",
+                                       "perf_event_open",
+                                       "pidfd_getfd",
+                                       "pivot_root",
+                                       "process_madvise",
+                                       "process_vm_readv",
+                                       "process_vm_writev",
+                                       "ptrace",
+                                       "quotactl",
+                                       "quotactl_fd",
+                                       "reboot",
+                                       "request_key",
+                                       "set_mempolicy",
+                                       "setns",
+                                       "settimeofday",
+                                       "swapoff",
+                                       "swapon",
+                                       "_sysctl",
+                                       "sysfs",
+                                       "umount2",
+                                       "unshare",
+                                       "uselib",
+                                       "userfaultfd",
+                                       "ustat"];
+
+/// What happens when a process makes a syscall.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    Allow,
+    /// The syscall fails with `EPERM`.
+    Errno,
+    /// The syscall fails with `ENOSYS`, as if the kernel did not have it, so that the C library
+    /// falls back to an older syscall where it can, such as from `clone3` to `clone`.
+    Enosys,
+    /// The process is killed.
+    Kill,
+    /// The syscall is allowed, and logged to the audit log.
+    Log,
+}
+
+impl Action {
+    fn ret(self) -> u32 {
+        match self {
+            Action::Allow => SECCOMP_RET_ALLOW,
+            Action::Errno => SECCOMP_RET_ERRNO | libc::EPERM as u32,
+            Action::Enosys => SECCOMP_RET_ERRNO | libc::ENOSYS as u32,
+            Action::Kill => SECCOMP_RET_KILL_PROCESS,
+            Action::Log => SECCOMP_RET_LOG,
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "allow" => Ok(Action::Allow),
+            "errno" => Ok(Action::Errno),
+            "enosys" => Ok(Action::Enosys),
+            "kill" => Ok(Action::Kill),
+            "log" => Ok(Action::Log),
+            _ => Err(Error::InvalidSeccompProfile(format!("unknown action '{}'", value))),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match *self {
+            Action::Allow => "allow",
+            Action::Errno => "errno",
+            Action::Enosys => "enosys",
+            Action::Kill => "kill",
+            Action::Log => "log",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+/// Which syscalls a service may make.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Profile {
+    default: Action,
+    rules:   Vec<(String, Action)>,
+}
+
+impl Default for Profile {
+    fn default() -> Self { Profile::new(Action::Allow) }
+}
+
+impl Profile {
+    /// A profile with no rules, which applies `default` to every syscall.
+    pub fn new(default: Action) -> Self {
+        Profile { default,
+                  rules: Vec::new() }
+    }
+
+    /// The profile for packages which don't declare their own.
+    pub fn baseline() -> Self {
+        BASELINE_DENIED.iter()
+                       .fold(Self::new(Action::Allow), |profile, syscall| {
+                           profile.rule(*syscall, Action::Errno)
+                       })
+    }
+
+    /// The profile declared by `package`, or the baseline if it declares none.
+    ///
+    /// # Failures
+    ///
+    /// * The package's `SECCOMP_PROFILE` metafile cannot be read or parsed
+    pub fn for_package(package: &PackageInstall) -> Result<Self> {
+        package.seccomp_profile()
+               .map(|profile| profile.unwrap_or_else(Self::baseline))
+    }
+
+    /// Applies `action` to `syscall`, unless an earlier rule already covers it.
+    pub fn rule<S: Into<String>>(mut self, syscall: S, action: Action) -> Self {
+        self.rules.push((syscall.into(), action));
+        self
+    }
+
+    /// Compiles the profile into a filter ready to apply.
+    ///
+    /// # Failures
+    ///
+    /// * A rule names a syscall which isn't known
+    /// * There are more rules than fit in one filter
+    pub fn compile(&self) -> Result<Filter> {
+        let mut program = vec![load(SECCOMP_DATA_ARCH),
+                               jump(BPF_JEQ_K, AUDIT_ARCH_X86_64, 1, 0),
+                               ret(SECCOMP_RET_KILL_PROCESS),
+                               load(SECCOMP_DATA_NR),
+                               jump(BPF_JGE_K, X32_SYSCALL_BIT, 0, 1),
+                               ret(Action::Errno.ret()),];
+        for (syscall, action) in &self.rules {
+            program.push(jump(BPF_JEQ_K, syscall_number(syscall)?, 0, 1));
+            program.push(ret(action.ret()));
+        }
+        program.push(ret(self.default.ret()));
+        if program.len() > BPF_MAXINSNS {
+            return Err(Error::InvalidSeccompProfile(format!("{} rules are more \
+                                                             than a filter can \
+                                                             hold",
+                                                            self.rules.len())));
+        }
+        Ok(Filter { program })
+    }
+}
+
+impl FromStr for Profile {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let mut profile = Profile::default();
+        let mut default = None;
+        for line in value.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let first = match words.next() {
+                Some(first) => first,
+                None => continue,
+            };
+            if first == "default" {
+                let action = match (words.next(), words.next()) {
+                    (Some(action), None) => action.parse()?,
+                    _ => {
+                        return Err(Error::InvalidSeccompProfile(format!("'{}' should name \
+                                                                         one action",
+                                                                        line.trim())));
+                    }
+                };
+                if default.replace(action).is_some() {
+                    return Err(Error::InvalidSeccompProfile("more than one default \
+                                                             action"
+                                                                    .to_string()));
+                }
+                continue;
+            }
+            let action = first.parse()?;
+            for syscall in words {
+                syscall_number(syscall)?;
+                profile = profile.rule(syscall, action);
+            }
+        }
+        profile.default = default.unwrap_or(Action::Allow);
+        Ok(profile)
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "default {}", self.default)?;
+        for (syscall, action) in &self.rules {
+            writeln!(f, "{} {}", action, syscall)?;
+        }
+        Ok(())
+    }
+}
+
+/// One BPF instruction, as `struct sock_filter`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct SockFilter {
+    code: u16,
+    jt:   u8,
+    jf:   u8,
+    k:    u32,
+}
+
+/// A BPF program, as `struct sock_fprog`.
+#[repr(C)]
+struct SockFprog {
+    len:    u16,
+    filter: *const SockFilter,
+}
+
+fn load(offset: u32) -> SockFilter {
+    SockFilter { code: BPF_LD_W_ABS,
+                 jt:   0,
+                 jf:   0,
+                 k:    offset, }
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter { SockFilter { code, jt, jf, k } }
+
+fn ret(k: u32) -> SockFilter {
+    SockFilter { code: BPF_RET_K,
+                 jt: 0,
+                 jf: 0,
+                 k }
+}
+
+/// A compiled `Profile`.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    program: Vec<SockFilter>,
+}
+
+impl Filter {
+    /// Confines the calling thread, and any process it goes on to exec, to the filter. Once
+    /// applied the filter cannot be removed, and the process can no longer gain privileges
+    /// through setuid binaries.
+    ///
+    /// This makes no allocations, so it is safe to call between `fork` and `exec`.
+    pub fn apply(&self) -> io::Result<()> {
+        let prog = SockFprog { len:    self.program.len() as u16,
+                               filter: self.program.as_ptr(), };
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::prctl(libc::PR_SET_SECCOMP,
+                           libc::SECCOMP_MODE_FILTER,
+                           &prog as *const SockFprog as c_ulong,
+                           0,
+                           0)
+               != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Arranges for `command` to run confined by `profile`.
+///
+/// # Failures
+///
+/// * The profile cannot be compiled
+pub fn confine(command: &mut Command, profile: &Profile) -> Result<()> {
+    let filter = profile.compile()?;
+    unsafe {
+        command.pre_exec(move || filter.apply());
+    }
+    Ok(())
+}
+
+fn syscall_number(name: &str) -> Result<u32> {
+    SYSCALLS.iter()
+            .find(|(syscall, _)| *syscall == name)
+            .map(|(_, number)| *number as u32)
+            .ok_or_else(|| Error::InvalidSeccompProfile(format!("unknown syscall '{}'", name)))
+}
+
+/// The x86_64 syscalls, by name. Those newer than the `libc` crate knows are given by number.
+const SYSCALLS: &[(&str, c_long)] = &[("read", libc::SYS_read),
+                                      ("write", libc::SYS_write),
+                                      ("open", libc::SYS_open),
+                                      ("close", libc::SYS_close),
+                                      ("stat", libc::SYS_stat),
+                                      ("fstat", libc::SYS_fstat),
+                                      ("lstat", libc::SYS_lstat),
+                                      ("poll", libc::SYS_poll),
+                                      ("lseek", libc::SYS_lseek),
+                                      ("mmap", libc::SYS_mmap),
+                                      ("mprotect", libc::SYS_mprotect),
+                                      ("munmap", libc::SYS_munmap),
+                                      ("brk", libc::SYS_brk),
+                                      ("rt_sigaction", libc::SYS_rt_sigaction),
+                                      ("rt_sigprocmask", libc::SYS_rt_sigprocmask),
+                                      ("rt_sigreturn", libc::SYS_rt_sigreturn),
+                                      ("ioctl", libc::SYS_ioctl),
+                                      ("pread64", libc::SYS_pread64),
+                                      ("pwrite64", libc::SYS_pwrite64),
+                                      ("readv", libc::SYS_readv),
+                                      ("writev", libc::SYS_writev),
+                                      ("access", libc::SYS_access),
+                                      ("pipe", libc::SYS_pipe),
+                                      ("select", libc::SYS_select),
+                                      ("sched_yield", libc::SYS_sched_yield),
+                                      ("mremap", libc::SYS_mremap),
+                                      ("msync", libc::SYS_msync),
+                                      ("mincore", libc::SYS_mincore),
+                                      ("madvise", libc::SYS_madvise),
+                                      ("shmget", libc::SYS_shmget),
+                                      ("shmat", libc::SYS_shmat),
+                                      ("shmctl", libc::SYS_shmctl),
+                                      ("dup", libc::SYS_dup),
+                                      ("dup2", libc::SYS_dup2),
+                                      ("pause", libc::SYS_pause),
+                                      ("nanosleep", libc::SYS_nanosleep),
+                                      ("getitimer", libc::SYS_getitimer),
+                                      ("alarm", libc::SYS_alarm),
+                                      ("setitimer", libc::SYS_setitimer),
+                                      ("getpid", libc::SYS_getpid),
+                                      ("sendfile", libc::SYS_sendfile),
+                                      ("socket", libc::SYS_socket),
+                                      ("connect", libc::SYS_connect),
+                                      ("accept", libc::SYS_accept),
+                                      ("sendto", libc::SYS_sendto),
+                                      ("recvfrom", libc::SYS_recvfrom),
+                                      ("sendmsg", libc::SYS_sendmsg),
+                                      ("recvmsg", libc::SYS_recvmsg),
+                                      ("shutdown", libc::SYS_shutdown),
+                                      ("bind", libc::SYS_bind),
+                                      ("listen", libc::SYS_listen),
+                                      ("getsockname", libc::SYS_getsockname),
+                                      ("getpeername", libc::SYS_getpeername),
+                                      ("socketpair", libc::SYS_socketpair),
+                                      ("setsockopt", libc::SYS_setsockopt),
+                                      ("getsockopt", libc::SYS_getsockopt),
+                                      ("clone", libc::SYS_clone),
+                                      ("fork", libc::SYS_fork),
+                                      ("vfork", libc::SYS_vfork),
+                                      ("execve", libc::SYS_execve),
+                                      ("exit", libc::SYS_exit),
+                                      ("wait4", libc::SYS_wait4),
+                                      ("kill", libc::SYS_kill),
+                                      ("uname", libc::SYS_uname),
+                                      ("semget", libc::SYS_semget),
+                                      ("semop", libc::SYS_semop),
+                                      ("semctl", libc::SYS_semctl),
+                                      ("shmdt", libc::SYS_shmdt),
+                                      ("msgget", libc::SYS_msgget),
+                                      ("msgsnd", libc::SYS_msgsnd),
+                                      ("msgrcv", libc::SYS_msgrcv),
+                                      ("msgctl", libc::SYS_msgctl),
+                                      ("fcntl", libc::SYS_fcntl),
+                                      ("flock", libc::SYS_flock),
+                                      ("fsync", libc::SYS_fsync),
+                                      ("fdatasync", libc::SYS_fdatasync),
+                                      ("truncate", libc::SYS_truncate),
+                                      ("ftruncate", libc::SYS_ftruncate),
+                                      ("getdents", libc::SYS_getdents),
+                                      ("getcwd", libc::SYS_getcwd),
+                                      ("chdir", libc::SYS_chdir),
+                                      ("fchdir", libc::SYS_fchdir),
+                                      ("rename", libc::SYS_rename),
+                                      ("mkdir", libc::SYS_mkdir),
+                                      ("rmdir", libc::SYS_rmdir),
+                                      ("creat", libc::SYS_creat),
+                                      ("link", libc::SYS_link),
+                                      ("unlink", libc::SYS_unlink),
+                                      ("symlink", libc::SYS_symlink),
+                                      ("readlink", libc::SYS_readlink),
+                                      ("chmod", libc::SYS_chmod),
+                                      ("fchmod", libc::SYS_fchmod),
+                                      ("chown", libc::SYS_chown),
+                                      ("fchown", libc::SYS_fchown),
+                                      ("lchown", libc::SYS_lchown),
+                                      ("umask", libc::SYS_umask),
+                                      ("gettimeofday", libc::SYS_gettimeofday),
+                                      ("getrlimit", libc::SYS_getrlimit),
+                                      ("getrusage", libc::SYS_getrusage),
+                                      ("sysinfo", libc::SYS_sysinfo),
+                                      ("times", libc::SYS_times),
+                                      ("ptrace", libc::SYS_ptrace),
+                                      ("getuid", libc::SYS_getuid),
+                                      ("syslog", libc::SYS_syslog),
+                                      ("getgid", libc::SYS_getgid),
+                                      ("setuid", libc::SYS_setuid),
+                                      ("setgid", libc::SYS_setgid),
+                                      ("geteuid", libc::SYS_geteuid),
+                                      ("getegid", libc::SYS_getegid),
+                                      ("setpgid", libc::SYS_setpgid),
+                                      ("getppid", libc::SYS_getppid),
+                                      ("getpgrp", libc::SYS_getpgrp),
+                                      ("setsid", libc::SYS_setsid),
+                                      ("setreuid", libc::SYS_setreuid),
+                                      ("setregid", libc::SYS_setregid),
+                                      ("getgroups", libc::SYS_getgroups),
+                                      ("setgroups", libc::SYS_setgroups),
+                                      ("setresuid", libc::SYS_setresuid),
+                                      ("getresuid", libc::SYS_getresuid),
+                                      ("setresgid", libc::SYS_setresgid),
+                                      ("getresgid", libc::SYS_getresgid),
+                                      ("getpgid", libc::SYS_getpgid),
+                                      ("setfsuid", libc::SYS_setfsuid),
+                                      ("setfsgid", libc::SYS_setfsgid),
+                                      ("getsid", libc::SYS_getsid),
+                                      ("capget", libc::SYS_capget),
+                                      ("capset", libc::SYS_capset),
+                                      ("rt_sigpending", libc::SYS_rt_sigpending),
+                                      ("rt_sigtimedwait", libc::SYS_rt_sigtimedwait),
+                                      ("rt_sigqueueinfo", libc::SYS_rt_sigqueueinfo),
+                                      ("rt_sigsuspend", libc::SYS_rt_sigsuspend),
+                                      ("sigaltstack", libc::SYS_sigaltstack),
+                                      ("utime", libc::SYS_utime),
+                                      ("mknod", libc::SYS_mknod),
+                                      ("uselib", libc::SYS_uselib),
+                                      ("personality", libc::SYS_personality),
+                                      ("ustat", libc::SYS_ustat),
+                                      ("statfs", libc::SYS_statfs),
+                                      ("fstatfs", libc::SYS_fstatfs),
+                                      ("sysfs", libc::SYS_sysfs),
+                                      ("getpriority", libc::SYS_getpriority),
+                                      ("setpriority", libc::SYS_setpriority),
+                                      ("sched_setparam", libc::SYS_sched_setparam),
+                                      ("sched_getparam", libc::SYS_sched_getparam),
+                                      ("sched_setscheduler", libc::SYS_sched_setscheduler),
+                                      ("sched_getscheduler", libc::SYS_sched_getscheduler),
+                                      ("sched_get_priority_max", libc::SYS_sched_get_priority_max),
+                                      ("sched_get_priority_min", libc::SYS_sched_get_priority_min),
+                                      ("sched_rr_get_interval", libc::SYS_sched_rr_get_interval),
+                                      ("mlock", libc::SYS_mlock),
+                                      ("munlock", libc::SYS_munlock),
+                                      ("mlockall", libc::SYS_mlockall),
+                                      ("munlockall", libc::SYS_munlockall),
+                                      ("vhangup", libc::SYS_vhangup),
+                                      ("modify_ldt", libc::SYS_modify_ldt),
+                                      ("pivot_root", libc::SYS_pivot_root),
+                                      ("_sysctl", libc::SYS__sysctl),
+                                      ("prctl", libc::SYS_prctl),
+                                      ("arch_prctl", libc::SYS_arch_prctl),
+                                      ("adjtimex", libc::SYS_adjtimex),
+                                      ("setrlimit", libc::SYS_setrlimit),
+                                      ("chroot", libc::SYS_chroot),
+                                      ("sync", libc::SYS_sync),
+                                      ("acct", libc::SYS_acct),
+                                      ("settimeofday", libc::SYS_settimeofday),
+                                      ("mount", libc::SYS_mount),
+                                      ("umount2", libc::SYS_umount2),
+                                      ("swapon", libc::SYS_swapon),
+                                      ("swapoff", libc::SYS_swapoff),
+                                      ("reboot", libc::SYS_reboot),
+                                      ("sethostname", libc::SYS_sethostname),
+                                      ("setdomainname", libc::SYS_setdomainname),
+                                      ("iopl", libc::SYS_iopl),
+                                      ("ioperm", libc::SYS_ioperm),
+                                      ("init_module", libc::SYS_init_module),
+                                      ("delete_module", libc::SYS_delete_module),
+                                      ("quotactl", libc::SYS_quotactl),
+                                      ("nfsservctl", libc::SYS_nfsservctl),
+                                      ("getpmsg", libc::SYS_getpmsg),
+                                      ("putpmsg", libc::SYS_putpmsg),
+                                      ("afs_syscall", libc::SYS_afs_syscall),
+                                      ("tuxcall", libc::SYS_tuxcall),
+                                      ("security", libc::SYS_security),
+                                      ("gettid", libc::SYS_gettid),
+                                      ("readahead", libc::SYS_readahead),
+                                      ("setxattr", libc::SYS_setxattr),
+                                      ("lsetxattr", libc::SYS_lsetxattr),
+                                      ("fsetxattr", libc::SYS_fsetxattr),
+                                      ("getxattr", libc::SYS_getxattr),
+                                      ("lgetxattr", libc::SYS_lgetxattr),
+                                      ("fgetxattr", libc::SYS_fgetxattr),
+                                      ("listxattr", libc::SYS_listxattr),
+                                      ("llistxattr", libc::SYS_llistxattr),
+                                      ("flistxattr", libc::SYS_flistxattr),
+                                      ("removexattr", libc::SYS_removexattr),
+                                      ("lremovexattr", libc::SYS_lremovexattr),
+                                      ("fremovexattr", libc::SYS_fremovexattr),
+                                      ("tkill", libc::SYS_tkill),
+                                      ("time", libc::SYS_time),
+                                      ("futex", libc::SYS_futex),
+                                      ("sched_setaffinity", libc::SYS_sched_setaffinity),
+                                      ("sched_getaffinity", libc::SYS_sched_getaffinity),
+                                      ("set_thread_area", libc::SYS_set_thread_area),
+                                      ("io_setup", libc::SYS_io_setup),
+                                      ("io_destroy", libc::SYS_io_destroy),
+                                      ("io_getevents", libc::SYS_io_getevents),
+                                      ("io_submit", libc::SYS_io_submit),
+                                      ("io_cancel", libc::SYS_io_cancel),
+                                      ("get_thread_area", libc::SYS_get_thread_area),
+                                      ("lookup_dcookie", libc::SYS_lookup_dcookie),
+                                      ("epoll_create", libc::SYS_epoll_create),
+                                      ("epoll_ctl_old", libc::SYS_epoll_ctl_old),
+                                      ("epoll_wait_old", libc::SYS_epoll_wait_old),
+                                      ("remap_file_pages", libc::SYS_remap_file_pages),
+                                      ("getdents64", libc::SYS_getdents64),
+                                      ("set_tid_address", libc::SYS_set_tid_address),
+                                      ("restart_syscall", libc::SYS_restart_syscall),
+                                      ("semtimedop", libc::SYS_semtimedop),
+                                      ("fadvise64", libc::SYS_fadvise64),
+                                      ("timer_create", libc::SYS_timer_create),
+                                      ("timer_settime", libc::SYS_timer_settime),
+                                      ("timer_gettime", libc::SYS_timer_gettime),
+                                      ("timer_getoverrun", libc::SYS_timer_getoverrun),
+                                      ("timer_delete", libc::SYS_timer_delete),
+                                      ("clock_settime", libc::SYS_clock_settime),
+                                      ("clock_gettime", libc::SYS_clock_gettime),
+                                      ("clock_getres", libc::SYS_clock_getres),
+                                      ("clock_nanosleep", libc::SYS_clock_nanosleep),
+                                      ("exit_group", libc::SYS_exit_group),
+                                      ("epoll_wait", libc::SYS_epoll_wait),
+                                      ("epoll_ctl", libc::SYS_epoll_ctl),
+                                      ("tgkill", libc::SYS_tgkill),
+                                      ("utimes", libc::SYS_utimes),
+                                      ("vserver", libc::SYS_vserver),
+                                      ("mbind", libc::SYS_mbind),
+                                      ("set_mempolicy", libc::SYS_set_mempolicy),
+                                      ("get_mempolicy", libc::SYS_get_mempolicy),
+                                      ("mq_open", libc::SYS_mq_open),
+                                      ("mq_unlink", libc::SYS_mq_unlink),
+                                      ("mq_timedsend", libc::SYS_mq_timedsend),
+                                      ("mq_timedreceive", libc::SYS_mq_timedreceive),
+                                      ("mq_notify", libc::SYS_mq_notify),
+                                      ("mq_getsetattr", libc::SYS_mq_getsetattr),
+                                      ("kexec_load", libc::SYS_kexec_load),
+                                      ("waitid", libc::SYS_waitid),
+                                      ("add_key", libc::SYS_add_key),
+                                      ("request_key", libc::SYS_request_key),
+                                      ("keyctl", libc::SYS_keyctl),
+                                      ("ioprio_set", libc::SYS_ioprio_set),
+                                      ("ioprio_get", libc::SYS_ioprio_get),
+                                      ("inotify_init", libc::SYS_inotify_init),
+                                      ("inotify_add_watch", libc::SYS_inotify_add_watch),
+                                      ("inotify_rm_watch", libc::SYS_inotify_rm_watch),
+                                      ("migrate_pages", libc::SYS_migrate_pages),
+                                      ("openat", libc::SYS_openat),
+                                      ("mkdirat", libc::SYS_mkdirat),
+                                      ("mknodat", libc::SYS_mknodat),
+                                      ("fchownat", libc::SYS_fchownat),
+                                      ("futimesat", libc::SYS_futimesat),
+                                      ("newfstatat", libc::SYS_newfstatat),
+                                      ("unlinkat", libc::SYS_unlinkat),
+                                      ("renameat", libc::SYS_renameat),
+                                      ("linkat", libc::SYS_linkat),
+                                      ("symlinkat", libc::SYS_symlinkat),
+                                      ("readlinkat", libc::SYS_readlinkat),
+                                      ("fchmodat", libc::SYS_fchmodat),
+                                      ("faccessat", libc::SYS_faccessat),
+                                      ("pselect6", libc::SYS_pselect6),
+                                      ("ppoll", libc::SYS_ppoll),
+                                      ("unshare", libc::SYS_unshare),
+                                      ("set_robust_list", libc::SYS_set_robust_list),
+                                      ("get_robust_list", libc::SYS_get_robust_list),
+                                      ("splice", libc::SYS_splice),
+                                      ("tee", libc::SYS_tee),
+                                      ("sync_file_range", libc::SYS_sync_file_range),
+                                      ("vmsplice", libc::SYS_vmsplice),
+                                      ("move_pages", libc::SYS_move_pages),
+                                      ("utimensat", libc::SYS_utimensat),
+                                      ("epoll_pwait", libc::SYS_epoll_pwait),
+                                      ("signalfd", libc::SYS_signalfd),
+                                      ("timerfd_create", libc::SYS_timerfd_create),
+                                      ("eventfd", libc::SYS_eventfd),
+                                      ("fallocate", libc::SYS_fallocate),
+                                      ("timerfd_settime", libc::SYS_timerfd_settime),
+                                      ("timerfd_gettime", libc::SYS_timerfd_gettime),
+                                      ("accept4", libc::SYS_accept4),
+                                      ("signalfd4", libc::SYS_signalfd4),
+                                      ("eventfd2", libc::SYS_eventfd2),
+                                      ("epoll_create1", libc::SYS_epoll_create1),
+                                      ("dup3", libc::SYS_dup3),
+                                      ("pipe2", libc::SYS_pipe2),
+                                      ("inotify_init1", libc::SYS_inotify_init1),
+                                      ("preadv", libc::SYS_preadv),
+                                      ("pwritev", libc::SYS_pwritev),
+                                      ("rt_tgsigqueueinfo", libc::SYS_rt_tgsigqueueinfo),
+                                      ("perf_event_open", libc::SYS_perf_event_open),
+                                      ("recvmmsg", libc::SYS_recvmmsg),
+                                      ("fanotify_init", libc::SYS_fanotify_init),
+                                      ("fanotify_mark", libc::SYS_fanotify_mark),
+                                      ("prlimit64", libc::SYS_prlimit64),
+                                      ("name_to_handle_at", libc::SYS_name_to_handle_at),
+                                      ("open_by_handle_at", libc::SYS_open_by_handle_at),
+                                      ("clock_adjtime", libc::SYS_clock_adjtime),
+                                      ("syncfs", libc::SYS_syncfs),
+                                      ("sendmmsg", libc::SYS_sendmmsg),
+                                      ("setns", libc::SYS_setns),
+                                      ("getcpu", libc::SYS_getcpu),
+                                      ("process_vm_readv", libc::SYS_process_vm_readv),
+                                      ("process_vm_writev", libc::SYS_process_vm_writev),
+                                      ("kcmp", libc::SYS_kcmp),
+                                      ("finit_module", libc::SYS_finit_module),
+                                      ("sched_setattr", libc::SYS_sched_setattr),
+                                      ("sched_getattr", libc::SYS_sched_getattr),
+                                      ("renameat2", libc::SYS_renameat2),
+                                      ("seccomp", libc::SYS_seccomp),
+                                      ("getrandom", libc::SYS_getrandom),
+                                      ("memfd_create", libc::SYS_memfd_create),
+                                      ("kexec_file_load", libc::SYS_kexec_file_load),
+                                      ("bpf", libc::SYS_bpf),
+                                      ("execveat", libc::SYS_execveat),
+                                      ("userfaultfd", libc::SYS_userfaultfd),
+                                      ("membarrier", libc::SYS_membarrier),
+                                      ("mlock2", libc::SYS_mlock2),
+                                      ("copy_file_range", libc::SYS_copy_file_range),
+                                      ("preadv2", libc::SYS_preadv2),
+                                      ("pwritev2", libc::SYS_pwritev2),
+                                      ("pkey_mprotect", 329),
+                                      ("pkey_alloc", 330),
+                                      ("pkey_free", 331),
+                                      ("statx", 332),
+                                      ("io_pgetevents", 333),
+                                      ("rseq", 334),
+                                      ("pidfd_send_signal", 424),
+                                      ("io_uring_setup", 425),
+                                      ("io_uring_enter", 426),
+                                      ("io_uring_register", 427),
+                                      ("open_tree", 428),
+                                      ("move_mount", 429),
+                                      ("fsopen", 430),
+                                      ("fsconfig", 431),
+                                      ("fsmount", 432),
+                                      ("fspick", 433),
+                                      ("pidfd_open", 434),
+                                      ("clone3", 435),
+                                      ("close_range", 436),
+                                      ("openat2", 437),
+                                      ("pidfd_getfd", 438),
+                                      ("faccessat2", 439),
+                                      ("process_madvise", 440),
+                                      ("epoll_pwait2", 441),
+                                      ("mount_setattr", 442),
+                                      ("quotactl_fd", 443),
+                                      ("landlock_create_ruleset", 444),
+                                      ("landlock_add_rule", 445),
+                                      ("landlock_restrict_self", 446),
+                                      ("memfd_secret", 447),
+                                      ("process_mrelease", 448),
+                                      ("futex_waitv", 449),
+                                      ("set_mempolicy_home_node", 450),
+                                      ("cachestat", 451),
+                                      ("fchmodat2", 452),
+                                      ("map_shadow_stack", 453),
+                                      ("futex_wake", 454),
+                                      ("futex_wait", 455),
+                                      ("futex_requeue", 456),
+                                      ("statmount", 457),
+                                      ("listmount", 458),
+                                      ("lsm_get_self_attr", 459),
+                                      ("lsm_set_self_attr", 460),
+                                      ("lsm_list_modules", 461),
+                                      ("mseal", 462)];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_profile() {
+        let profile: Profile = "# Only allow what's needed\ndefault errno\nallow read write \
+                                close\n\nkill ptrace # never\n"
+                                                               .parse()
+                                                               .unwrap();
+
+        assert_eq!(Profile::new(Action::Errno).rule("read", Action::Allow)
+                                              .rule("write", Action::Allow)
+                                              .rule("close", Action::Allow)
+                                              .rule("ptrace", Action::Kill),
+                   profile);
+        assert_eq!(profile, profile.to_string().parse().unwrap());
+        assert!("allow frobnicate".parse::<Profile>().is_err());
+        assert!("deny read".parse::<Profile>().is_err());
+        assert!("default allow\ndefault errno".parse::<Profile>().is_err());
+    }
+
+    #[test]
+    fn compile_profile() {
+        let filter = Profile::new(Action::Errno).rule("read", Action::Allow)
+                                                .compile()
+                                                .unwrap();
+        let program = &filter.program;
+
+        assert_eq!(9, program.len());
+        assert_eq!(libc::SYS_read as u32, program[6].k);
+        assert_eq!(SECCOMP_RET_ALLOW, program[7].k);
+        assert_eq!(SECCOMP_RET_ERRNO | libc::EPERM as u32, program[8].k);
+        assert!(Profile::baseline().compile().is_ok());
+    }
+
+    #[test]
+    fn enosys_profile() {
+        let profile: Profile = "default enosys\nallow read\n".parse().unwrap();
+        let program = profile.compile().unwrap().program;
+
+        assert_eq!(Profile::new(Action::Enosys).rule("read", Action::Allow),
+                   profile);
+        assert_eq!(profile, profile.to_string().parse().unwrap());
+        assert_eq!(SECCOMP_RET_ERRNO | libc::ENOSYS as u32, program[8].k);
+    }
+
+    #[test]
+    fn syscalls_newer_than_libc_are_known() {
+        assert_eq!(329, syscall_number("pkey_mprotect").unwrap());
+        assert_eq!(332, syscall_number("statx").unwrap());
+        assert_eq!(334, syscall_number("rseq").unwrap());
+        assert_eq!(435, syscall_number("clone3").unwrap());
+        assert_eq!(437, syscall_number("openat2").unwrap());
+    }
+
+    #[test]
+    fn confined_command_cannot_make_refused_syscalls() {
+        let dir = tempfile::Builder::new().prefix("seccomp")
+                                          .tempdir()
+                                          .unwrap();
+        let profile = Profile::default().rule("mkdir", Action::Errno)
+                                        .rule("mkdirat", Action::Errno);
+        let mut command = Command::new("mkdir");
+        command.arg(dir.path().join("refused"));
+        confine(&mut command, &profile).unwrap();
+
+        assert!(!command.status().unwrap().success());
+        assert!(!dir.path().join("refused").exists());
+    }
+}
@@ -13,10 +13,13 @@
 // limitations under the License.
 
 use std::{ffi::OsString,
+          fmt,
+          fs,
           io,
           os::unix::process::CommandExt,
           path::PathBuf,
-          process::Command};
+          process::Command,
+          str::FromStr};
 
 use libc::{self,
            pid_t};
@@ -45,6 +48,48 @@ pub enum Signal {
     CHLD,
 }
 
+impl Signal {
+    /// Every signal which can be sent, for listing the values a CLI accepts.
+    pub fn variants() -> &'static [Self] {
+        &[Signal::INT,
+          Signal::ILL,
+          Signal::ABRT,
+          Signal::FPE,
+          Signal::KILL,
+          Signal::SEGV,
+          Signal::TERM,
+          Signal::HUP,
+          Signal::QUIT,
+          Signal::ALRM,
+          Signal::USR1,
+          Signal::USR2,
+          Signal::CHLD]
+    }
+}
+
+/// Shows the signal by its name without the `SIG` prefix, such as `HUP`.
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{:?}", self) }
+}
+
+/// Parses a signal name, in any case and with or without the `SIG` prefix.
+impl FromStr for Signal {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let name = value.to_uppercase();
+        let name = if name.starts_with("SIG") {
+            &name[3..]
+        } else {
+            &name[..]
+        };
+        Signal::variants().iter()
+                          .find(|signal| signal.to_string() == name)
+                          .cloned()
+                          .ok_or_else(|| Error::BadSignal(value.to_string()))
+    }
+}
+
 pub fn become_command(command: PathBuf, args: &[OsString]) -> Result<()> {
     become_exec_command(command, args)
 }
@@ -66,6 +111,43 @@ pub fn is_alive(pid: Pid) -> bool {
     }
 }
 
+/// Returns when the process with the given identifier started, or `None` if it is not running or
+/// the platform does not say. The value is only meaningful compared with another from this
+/// function: together with the process identifier, it tells a process apart from a later one
+/// which has been given the same identifier.
+#[cfg(target_os = "linux")]
+pub fn start_time(pid: Pid) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name comes second, in parentheses, and may itself contain spaces or
+    // parentheses. The start time is the 22nd field, and the 20th after the command name.
+    let fields = &stat[stat.rfind(')')? + 1..];
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn start_time(_pid: Pid) -> Option<u64> { None }
+
+/// Returns the path of the executable of each running process which can be inspected, which
+/// without privileges means those of the caller's own user. Only Linux is supported; elsewhere
+/// the list is empty.
+#[cfg(target_os = "linux")]
+pub fn executables() -> Vec<(Pid, PathBuf)> {
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries.filter_map(|entry| {
+               let entry = entry.ok()?;
+               let pid = entry.file_name().to_str()?.parse().ok()?;
+               let exe = fs::read_link(entry.path().join("exe")).ok()?;
+               Some((pid, exe))
+           })
+           .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn executables() -> Vec<(Pid, PathBuf)> { Vec::new() }
+
 pub fn signal(pid: Pid, signal: Signal) -> Result<()> {
     unsafe {
         match libc::kill(pid as pid_t, signal.into()) {
@@ -108,3 +190,33 @@ fn become_exec_command(command: PathBuf, args: &[OsString]) -> Result<()> {
     // failed to exec to our target program
     Err(error_if_failed.into())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn signal_variants_lists_every_variant() {
+        // The match has no wildcard arm, so a new variant cannot compile until it is given a
+        // position here, and the test fails until `variants` lists it.
+        let position = |value: &Signal| {
+            match *value {
+                Signal::INT => 0,
+                Signal::ILL => 1,
+                Signal::ABRT => 2,
+                Signal::FPE => 3,
+                Signal::KILL => 4,
+                Signal::SEGV => 5,
+                Signal::TERM => 6,
+                Signal::HUP => 7,
+                Signal::QUIT => 8,
+                Signal::ALRM => 9,
+                Signal::USR1 => 10,
+                Signal::USR2 => 11,
+                Signal::CHLD => 12,
+            }
+        };
+        let positions: Vec<_> = Signal::variants().iter().map(position).collect();
+        assert_eq!(vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12], positions);
+    }
+}
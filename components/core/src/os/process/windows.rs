@@ -16,15 +16,20 @@ use crate::error::{Error,
                    Result};
 use std::{ffi::OsString,
           io,
+          mem,
+          os::windows::ffi::OsStringExt,
           path::PathBuf,
           process::{self,
                     Command},
           ptr};
 use winapi::{shared::minwindef::{DWORD,
                                  FALSE,
+                                 FILETIME,
                                  LPDWORD},
              um::{handleapi,
                   processthreadsapi,
+                  psapi,
+                  winbase,
                   winnt::{HANDLE,
                           PROCESS_QUERY_LIMITED_INFORMATION,
                           PROCESS_TERMINATE}}};
@@ -71,6 +76,65 @@ pub fn is_alive(pid: Pid) -> bool {
     }
 }
 
+/// Returns when the process with the given identifier was created, as a `FILETIME`, or `None` if
+/// it is not running or cannot be opened. Together with the process identifier, it tells a
+/// process apart from a later one which has been given the same identifier.
+pub fn start_time(pid: Pid) -> Option<u64> {
+    let handle = handle_from_pid(pid)?;
+    unsafe {
+        let mut creation: FILETIME = mem::zeroed();
+        let mut exit: FILETIME = mem::zeroed();
+        let mut kernel: FILETIME = mem::zeroed();
+        let mut user: FILETIME = mem::zeroed();
+        let ret = processthreadsapi::GetProcessTimes(handle,
+                                                     &mut creation,
+                                                     &mut exit,
+                                                     &mut kernel,
+                                                     &mut user);
+        let _ = handleapi::CloseHandle(handle);
+        if ret == 0 {
+            return None;
+        }
+        Some(u64::from(creation.dwHighDateTime) << 32 | u64::from(creation.dwLowDateTime))
+    }
+}
+
+/// Returns the path of the executable of each running process which can be inspected.
+pub fn executables() -> Vec<(Pid, PathBuf)> {
+    let mut pids: Vec<DWORD> = vec![0; 4096];
+    loop {
+        let mut needed: DWORD = 0;
+        let size = (pids.len() * mem::size_of::<DWORD>()) as DWORD;
+        if unsafe { psapi::EnumProcesses(pids.as_mut_ptr(), size, &mut needed) } == 0 {
+            return Vec::new();
+        }
+        // A full buffer may mean there were more processes than fit, so try again with more room
+        if needed < size {
+            pids.truncate(needed as usize / mem::size_of::<DWORD>());
+            break;
+        }
+        let len = pids.len() * 2;
+        pids.resize(len, 0);
+    }
+    pids.into_iter()
+        .filter_map(|pid| executable(pid).map(|exe| (pid, exe)))
+        .collect()
+}
+
+fn executable(pid: Pid) -> Option<PathBuf> {
+    let handle = handle_from_pid(pid)?;
+    let mut buffer = vec![0u16; 32_768];
+    let mut len = buffer.len() as DWORD;
+    unsafe {
+        let ret = winbase::QueryFullProcessImageNameW(handle, 0, buffer.as_mut_ptr(), &mut len);
+        let _ = handleapi::CloseHandle(handle);
+        if ret == 0 {
+            return None;
+        }
+    }
+    Some(PathBuf::from(OsString::from_wide(&buffer[..len as usize])))
+}
+
 /// Executes a command as a child process and exits with the child's exit code.
 ///
 /// Note that if successful, this function will not return.
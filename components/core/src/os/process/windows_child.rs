@@ -17,7 +17,8 @@
 // order to be able to start habitat Supervisor services as different users.
 
 use std::{cmp,
-          collections::HashMap,
+          collections::{BTreeMap,
+                        HashMap},
           env,
           ffi::{OsStr,
                 OsString},
@@ -28,10 +29,12 @@ use std::{cmp,
                Read},
           mem,
           ops::Deref,
-          os::windows::ffi::OsStrExt,
+          os::windows::ffi::{OsStrExt,
+                             OsStringExt},
           path::Path,
           ptr,
-          slice::from_raw_parts_mut,
+          slice::{from_raw_parts,
+                  from_raw_parts_mut},
           sync::Mutex};
 
 use rand::{self,
@@ -105,8 +108,9 @@ use winapi::{shared::{minwindef::{BOOL,
                           READ_CONTROL,
                           WRITE_DAC}}};
 
-use crate::error::{Error,
-                   Result};
+use crate::{env::ScrubPolicy,
+            error::{Error,
+                    Result}};
 use habitat_win_users::sid::{self,
                              Sid};
 
@@ -153,11 +157,6 @@ const HANDLE_FLAG_INHERIT: DWORD = 0x00000001;
 
 const LOGON32_LOGON_SERVICE: DWORD = 5;
 
-enum ParsePart {
-    Key,
-    Value,
-}
-
 struct ServiceCredential {
     pub user:     String,
     pub domain:   String,
@@ -201,6 +200,139 @@ impl ServiceCredential {
     }
 }
 
+/// An environment for a new process, in the form `CreateProcessW` and `CreateProcessAsUserW`
+/// take it with `CREATE_UNICODE_ENVIRONMENT`.
+///
+/// Variable names are compared without regard to case, as Windows compares them: setting `Path`
+/// replaces any `PATH` already present, keeping the name as most recently given. Names and
+/// values are kept as UTF-16, so nothing is lost or mangled in conversion.
+#[derive(Clone, Debug, Default)]
+pub struct EnvironmentBlock {
+    /// Each variable's name and value, keyed by the upper-cased name.
+    vars: BTreeMap<Vec<u16>, (OsString, OsString)>,
+}
+
+impl EnvironmentBlock {
+    pub fn new() -> Self { Self::default() }
+
+    /// The environment of the current process.
+    pub fn from_system() -> Self {
+        let mut block = Self::new();
+        block.extend(env::vars_os());
+        block
+    }
+
+    /// The default environment for the user `token` belongs to, as a service started as that
+    /// user would have it.
+    pub fn from_user_token(token: HANDLE) -> io::Result<Self> {
+        unsafe {
+            let mut raw: LPVOID = ptr::null_mut();
+            cvt(userenv::CreateEnvironmentBlock(&mut raw, token, FALSE))?;
+            // The block ends at the first empty entry, so at the first pair of NULs
+            let start = raw as *const u16;
+            let mut len = 0;
+            while *start.add(len) != 0 || *start.add(len + 1) != 0 {
+                len += 1;
+            }
+            let block = Self::from_wide(from_raw_parts(start, len + 1));
+            cvt(userenv::DestroyEnvironmentBlock(raw))?;
+            Ok(block)
+        }
+    }
+
+    /// Reads an environment block made up of `NAME=VALUE` entries, each ending with a NUL. An
+    /// empty entry or the end of `block` ends the block.
+    pub fn from_wide(block: &[u16]) -> Self {
+        let mut env = Self::new();
+        for entry in block.split(|&unit| unit == 0)
+                          .take_while(|entry| !entry.is_empty())
+        {
+            // Names may start with `=`, such as the `=C:` variables which record the current
+            // directory of each drive, so the separator is the first `=` after that
+            match entry.iter().skip(1).position(|&unit| unit == '=' as u16) {
+                Some(i) => {
+                    env.set(OsString::from_wide(&entry[..=i]),
+                            OsString::from_wide(&entry[i + 2..]))
+                }
+                None => env.set(OsString::from_wide(entry), OsString::new()),
+            }
+        }
+        env
+    }
+
+    pub fn get<K: AsRef<OsStr>>(&self, name: K) -> Option<&OsStr> {
+        self.vars
+            .get(&sort_key(name.as_ref()))
+            .map(|(_, value)| value.as_os_str())
+    }
+
+    /// Sets `name` to `value`, replacing any variable whose name differs only in case.
+    pub fn set<K, V>(&mut self, name: K, value: V)
+        where K: AsRef<OsStr>,
+              V: AsRef<OsStr>
+    {
+        let name = name.as_ref();
+        self.vars.insert(sort_key(name),
+                         (name.to_os_string(), value.as_ref().to_os_string()));
+    }
+
+    /// Sets each of `vars` in turn, such as the environment from `environment_for_command`.
+    pub fn extend<I, K, V>(&mut self, vars: I)
+        where I: IntoIterator<Item = (K, V)>,
+              K: AsRef<OsStr>,
+              V: AsRef<OsStr>
+    {
+        for (name, value) in vars {
+            self.set(name, value);
+        }
+    }
+
+    /// Encodes the block as UTF-16, sorted by name without regard to case as Windows requires,
+    /// and ending with an empty entry.
+    ///
+    /// # Failures
+    ///
+    /// * A name or value contains a NUL, or a name contains `=` other than as its first character
+    pub fn to_wide(&self) -> io::Result<Vec<u16>> {
+        let mut block = Vec::new();
+        for (name, value) in self.vars.values() {
+            let name = ensure_no_nuls(name)?;
+            if name.is_empty() || name.encode_wide().skip(1).any(|unit| unit == '=' as u16) {
+                return Err(io::Error::new(ErrorKind::InvalidInput,
+                                          format!("invalid variable name {:?}", name)));
+            }
+            block.extend(name.encode_wide());
+            block.push('=' as u16);
+            block.extend(ensure_no_nuls(value)?.encode_wide());
+            block.push(0);
+        }
+        if block.is_empty() {
+            // An empty block still needs its terminating empty entry to end with a NUL
+            block.push(0);
+        }
+        block.push(0);
+        Ok(block)
+    }
+}
+
+/// Upper-cases a variable name one UTF-16 unit at a time, which is how Windows orders and
+/// compares names.
+fn sort_key(name: &OsStr) -> Vec<u16> { name.encode_wide().map(upcase).collect() }
+
+/// Units with no single-unit upper-case form, including unpaired surrogates, are kept as they
+/// are.
+fn upcase(unit: u16) -> u16 {
+    let c = match std::char::from_u32(u32::from(unit)) {
+        Some(c) => c,
+        None => return unit,
+    };
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(u), None) if (u as u32) <= 0xFFFF => u as u32 as u16,
+        _ => unit,
+    }
+}
+
 pub struct Child {
     pub handle: Handle,
     pub stdout: Option<ChildStdout>,
@@ -208,31 +340,59 @@ pub struct Child {
 }
 
 impl Child {
+    /// Spawns `program` with the whole environment of the current process, plus `env`.
     pub fn spawn<U, P>(program: &str,
                        args: Vec<&str>,
-                       env: &HashMap<String, String>,
+                       env: &HashMap<OsString, OsString>,
                        svc_user: U,
                        svc_encrypted_password: Option<P>)
                        -> Result<Child>
         where U: ToString,
               P: ToString
     {
-        let mut os_env: HashMap<OsString, OsString> =
-            env::vars_os().map(|(key, val)| (mk_key(key.to_str().unwrap()), val))
-                          .collect();
-        for (k, v) in env {
-            os_env.insert(mk_key(k.as_str()), OsString::from(v));
-        }
+        Self::spawn_in(EnvironmentBlock::from_system(),
+                       program,
+                       args,
+                       env,
+                       svc_user,
+                       svc_encrypted_password)
+    }
+
+    /// As `spawn`, but passing on only what `scrub` leaves of the current process's
+    /// environment, so that the service does not inherit the Supervisor's secrets.
+    pub fn spawn_scrubbed<U, P>(program: &str,
+                                args: Vec<&str>,
+                                env: &HashMap<OsString, OsString>,
+                                svc_user: U,
+                                svc_encrypted_password: Option<P>,
+                                scrub: &ScrubPolicy)
+                                -> Result<Child>
+        where U: ToString,
+              P: ToString
+    {
+        let mut os_env = EnvironmentBlock::new();
+        os_env.extend(crate::env::scrub_for_child(scrub));
+        Self::spawn_in(os_env, program, args, env, svc_user, svc_encrypted_password)
+    }
+
+    fn spawn_in<U, P>(mut os_env: EnvironmentBlock,
+                      program: &str,
+                      args: Vec<&str>,
+                      env: &HashMap<OsString, OsString>,
+                      svc_user: U,
+                      svc_encrypted_password: Option<P>)
+                      -> Result<Child>
+        where U: ToString,
+              P: ToString
+    {
+        os_env.extend(env);
 
         let program_path = {
             let mut res = None;
-            for (key, v) in &os_env {
-                if &OsString::from("PATH") != key {
-                    continue;
-                }
+            if let Some(v) = os_env.get("PATH") {
                 // Split the value and test each path to see if the
                 // program exists.
-                for path in env::split_paths(&v) {
+                for path in env::split_paths(v) {
                     let path = path.join(program)
                                    .with_extension(env::consts::EXE_EXTENSION);
                     if fs::metadata(&path).is_ok() {
@@ -240,7 +400,6 @@ impl Child {
                         break;
                     }
                 }
-                break;
             }
             res
         };
@@ -280,7 +439,7 @@ impl Child {
 
         let cred = ServiceCredential::new(svc_user, svc_encrypted_password)?;
         if cred.is_current_user() {
-            create_process(cmd_str.as_mut_ptr(), flags, os_env, &mut si, &mut pi)?;
+            create_process(cmd_str.as_mut_ptr(), flags, &os_env, &mut si, &mut pi)?;
         } else {
             create_process_as_user(cred, cmd_str.as_mut_ptr(), flags, env, &mut si, &mut pi)?;
         }
@@ -849,11 +1008,12 @@ impl<'a> Read for &'a RawHandle {
 
 fn create_process(command: LPWSTR,
                   flags: DWORD,
-                  env: HashMap<OsString, OsString>,
+                  env: &EnvironmentBlock,
                   si: LPSTARTUPINFOW,
                   pi: LPPROCESS_INFORMATION)
                   -> io::Result<i32> {
-    let (envp, _data) = make_envp(&env)?;
+    let mut block = env.to_wide()?;
+    let envp = block.as_mut_ptr() as LPVOID;
 
     unsafe {
         cvt(processthreadsapi::CreateProcessW(ptr::null(),
@@ -872,7 +1032,7 @@ fn create_process(command: LPWSTR,
 fn create_process_as_user(credential: ServiceCredential,
                           command: LPWSTR,
                           flags: DWORD,
-                          env: &HashMap<String, String>,
+                          env: &HashMap<OsString, OsString>,
                           si: LPSTARTUPINFOW,
                           pi: LPPROCESS_INFORMATION)
                           -> Result<i32> {
@@ -941,7 +1101,9 @@ fn create_process_as_user(credential: ServiceCredential,
                                | sid::WRITE_DAC
                                | sid::WRITE_OWNER)?;
 
-        let mut os_env = create_user_environment(token, &mut env.clone())?;
+        let mut user_env = EnvironmentBlock::from_user_token(token)?;
+        user_env.extend(env);
+        let mut os_env = user_env.to_wide()?;
         match cvt(CreateProcessAsUserW(token,
                                        ptr::null(),
                                        command,
@@ -963,70 +1125,6 @@ fn create_process_as_user(credential: ServiceCredential,
     }
 }
 
-fn create_user_environment(token: HANDLE,
-                           env: &mut HashMap<String, String>)
-                           -> io::Result<Vec<u16>> {
-    unsafe {
-        let mut new_env: Vec<u16> = Vec::new();
-        let mut block: LPVOID = ptr::null_mut();
-        cvt(userenv::CreateEnvironmentBlock(&mut block, token, FALSE))?;
-        let mut tail: u32 = MAXDWORD;
-        let mut offset = 0;
-        let mut part = ParsePart::Key;
-        let mut cur_key: Vec<u16> = Vec::new();
-        let mut cur_val: Vec<u16> = Vec::new();
-
-        // we keep shifting the last u16 char into tail's u32 space
-        // when it is 0 we know we are done because a propper environment
-        // block ends in \0\0
-        while tail != 0 {
-            tail = tail << 16;
-            let next_char = *(block.offset(offset) as *mut u16);
-            tail = tail | (next_char as u32);
-            offset = offset + 2;
-
-            match part {
-                ParsePart::Key => {
-                    new_env.push(next_char);
-                    if next_char == ('=' as u16) {
-                        part = ParsePart::Value;
-                    } else {
-                        cur_key.push(next_char);
-                    }
-                }
-                ParsePart::Value => {
-                    if next_char == 0 {
-                        part = ParsePart::Key;
-                        match env.remove(&String::from_utf16_lossy(&cur_key).to_uppercase()) {
-                            Some(val) => {
-                                new_env.extend(OsStr::new(&val).encode_wide());
-                            }
-                            None => new_env.append(&mut cur_val),
-                        }
-                        new_env.push(next_char);
-                        cur_key = Vec::new();
-                        cur_val = Vec::new();
-                    } else {
-                        cur_val.push(next_char);
-                    }
-                }
-            }
-        }
-        cvt(userenv::DestroyEnvironmentBlock(block))?;
-
-        let len = new_env.len();
-        new_env.truncate(len - 1);
-        for (k, v) in env {
-            new_env.extend(OsStr::new(k).encode_wide());
-            new_env.push('=' as u16);
-            new_env.extend(OsStr::new(v).encode_wide());
-            new_env.push(0);
-        }
-        new_env.push(0);
-        Ok(new_env)
-    }
-}
-
 fn cvt(i: i32) -> io::Result<i32> {
     if i == 0 {
         Err(io::Error::last_os_error())
@@ -1095,24 +1193,6 @@ fn make_command_line(prog: &OsStr, args: &[&str]) -> io::Result<Vec<u16>> {
     }
 }
 
-fn make_envp(env: &HashMap<OsString, OsString>) -> io::Result<(LPVOID, Vec<u16>)> {
-    // On Windows we pass an "environment block" which is not a char**, but
-    // rather a concatenation of null-terminated k=v\0 sequences, with a final
-    // \0 to terminate.
-    let mut blk = Vec::new();
-
-    for pair in env {
-        blk.extend(ensure_no_nuls(pair.0)?.encode_wide());
-        blk.push('=' as u16);
-        blk.extend(ensure_no_nuls(pair.1)?.encode_wide());
-        blk.push(0);
-    }
-    blk.push(0);
-    Ok((blk.as_mut_ptr() as LPVOID, blk))
-}
-
-fn mk_key(s: &str) -> OsString { OsString::from(s.to_ascii_uppercase()) }
-
 fn null_stdio_handle() -> Result<Handle> {
     let size = mem::size_of::<SECURITY_ATTRIBUTES>();
     let mut sa = SECURITY_ATTRIBUTES { nLength:              size as DWORD,
@@ -1212,3 +1292,52 @@ fn zeroed_process_information() -> PROCESS_INFORMATION {
                           dwProcessId: 0,
                           dwThreadId:  0, }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Encodes a raw environment block, keeping its NUL separators and adding no terminator.
+    fn utf16(s: &str) -> Vec<u16> { OsStr::new(s).encode_wide().collect() }
+
+    #[test]
+    fn environment_block_is_sorted_and_case_insensitive() {
+        let mut block = EnvironmentBlock::new();
+        block.set("Path", "C:\\Windows");
+        block.set("ALLUSERSPROFILE", "C:\\ProgramData");
+        block.set("PATH", "C:\\hab\\bin");
+        block.set("GREETING", "grüße");
+
+        assert_eq!(Some(OsStr::new("C:\\hab\\bin")), block.get("path"));
+        let mut expected =
+            utf16("ALLUSERSPROFILE=C:\\ProgramData\0GREETING=grüße\0PATH=C:\\hab\\bin\0");
+        expected.push(0);
+        assert_eq!(expected, block.to_wide().unwrap());
+    }
+
+    #[test]
+    fn environment_block_round_trips() {
+        let mut raw = utf16("=C:=C:\\hab\0Path=C:\\Windows\0LONE=");
+        // An unpaired surrogate, which cannot be represented as UTF-8
+        raw.extend(&[0xD800, 0, 0]);
+
+        let block = EnvironmentBlock::from_wide(&raw);
+
+        assert_eq!(Some(OsStr::new("C:\\hab")), block.get("=C:"));
+        assert_eq!(vec![0xD800],
+                   block.get("LONE").unwrap().encode_wide().collect::<Vec<_>>());
+        let mut expected = utf16("=C:=C:\\hab\0LONE=");
+        expected.extend(&[0xD800, 0]);
+        expected.extend(utf16("Path=C:\\Windows\0\0"));
+        assert_eq!(expected, block.to_wide().unwrap());
+    }
+
+    #[test]
+    fn environment_block_rejects_bad_names() {
+        let mut block = EnvironmentBlock::new();
+        block.set("A=B", "c");
+
+        assert!(block.to_wide().is_err());
+        assert_eq!(vec![0, 0], EnvironmentBlock::new().to_wide().unwrap());
+    }
+}
@@ -0,0 +1,378 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading and writing values in the Windows registry.
+//!
+//! Only the value types Habitat has a use for are supported: strings, strings holding
+//! `%VARIABLE%` references which readers expand, and 32-bit numbers. Keys are opened under one
+//! of two hives, the current user's or the machine's; writing to the machine's requires
+//! administrative rights.
+
+use std::{io,
+          mem,
+          ptr};
+
+use widestring::WideCString;
+use winapi::{shared::{minwindef::{DWORD,
+                                  HKEY},
+                      winerror::{ERROR_FILE_NOT_FOUND,
+                                 ERROR_MORE_DATA,
+                                 ERROR_SUCCESS}},
+             um::{winnt::{KEY_READ,
+                          KEY_WRITE,
+                          REG_DWORD,
+                          REG_EXPAND_SZ,
+                          REG_OPTION_NON_VOLATILE,
+                          REG_SZ},
+                  winreg::{RegCloseKey,
+                           RegCreateKeyExW,
+                           RegDeleteKeyW,
+                           RegDeleteValueW,
+                           RegOpenKeyExW,
+                           RegQueryValueExW,
+                           RegSetValueExW,
+                           HKEY_CURRENT_USER,
+                           HKEY_LOCAL_MACHINE}}};
+
+use crate::error::{Error,
+                   Result};
+
+/// The root a key path is looked up under.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Hive {
+    /// `HKEY_CURRENT_USER`, the settings of the user the process runs as.
+    CurrentUser,
+    /// `HKEY_LOCAL_MACHINE`, the settings shared by every user.
+    LocalMachine,
+}
+
+impl Hive {
+    fn handle(self) -> HKEY {
+        match self {
+            Hive::CurrentUser => HKEY_CURRENT_USER,
+            Hive::LocalMachine => HKEY_LOCAL_MACHINE,
+        }
+    }
+}
+
+/// A value stored in the registry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RegValue {
+    /// A `REG_SZ` string.
+    String(String),
+    /// A `REG_EXPAND_SZ` string, whose `%VARIABLE%` references are left for its reader to expand.
+    ExpandString(String),
+    /// A `REG_DWORD` number.
+    Dword(u32),
+}
+
+/// An open registry key, closed when dropped.
+#[derive(Debug)]
+pub struct RegKey(HKEY);
+
+impl RegKey {
+    /// Opens the key at `path` under `hive` for reading, or for reading and writing if
+    /// `writable`. Returns `None` if there is no such key.
+    ///
+    /// # Failures
+    ///
+    /// * The key exists but the caller may not open it as asked
+    pub fn open(hive: Hive, path: &str, writable: bool) -> Result<Option<Self>> {
+        let access = if writable {
+            KEY_READ | KEY_WRITE
+        } else {
+            KEY_READ
+        };
+        let path = wide(path)?;
+        let mut handle: HKEY = ptr::null_mut();
+        let rc = unsafe { RegOpenKeyExW(hive.handle(), path.as_ptr(), 0, access, &mut handle) };
+        match rc as DWORD {
+            ERROR_SUCCESS => Ok(Some(RegKey(handle))),
+            ERROR_FILE_NOT_FOUND => Ok(None),
+            _ => Err(registry_error("RegOpenKeyExW", rc)),
+        }
+    }
+
+    /// Opens the key at `path` under `hive` for reading and writing, creating it and any
+    /// missing keys above it first.
+    ///
+    /// # Failures
+    ///
+    /// * The caller may not create or write to the key
+    pub fn create(hive: Hive, path: &str) -> Result<Self> {
+        let path = wide(path)?;
+        let mut handle: HKEY = ptr::null_mut();
+        let rc = unsafe {
+            RegCreateKeyExW(hive.handle(),
+                            path.as_ptr(),
+                            0,
+                            ptr::null_mut(),
+                            REG_OPTION_NON_VOLATILE,
+                            KEY_READ | KEY_WRITE,
+                            ptr::null_mut(),
+                            &mut handle,
+                            ptr::null_mut())
+        };
+        if rc as DWORD != ERROR_SUCCESS {
+            return Err(registry_error("RegCreateKeyExW", rc));
+        }
+        Ok(RegKey(handle))
+    }
+
+    /// Reads the value `name`, returning `None` if the key has no such value.
+    ///
+    /// # Failures
+    ///
+    /// * The value cannot be read
+    /// * The value is of a type other than those `RegValue` covers
+    pub fn get(&self, name: &str) -> Result<Option<RegValue>> {
+        let name = wide(name)?;
+        let mut kind: DWORD = 0;
+        let mut len: DWORD = 0;
+        let mut data: Vec<u8> = Vec::new();
+        loop {
+            let rc = unsafe {
+                RegQueryValueExW(self.0,
+                                 name.as_ptr(),
+                                 ptr::null_mut(),
+                                 &mut kind,
+                                 if data.is_empty() {
+                                     ptr::null_mut()
+                                 } else {
+                                     data.as_mut_ptr()
+                                 },
+                                 &mut len)
+            };
+            match rc as DWORD {
+                ERROR_FILE_NOT_FOUND => return Ok(None),
+                // The value may have grown between the calls, so ask again with more room
+                ERROR_MORE_DATA => data.resize(len as usize, 0),
+                ERROR_SUCCESS if data.is_empty() && len > 0 => data.resize(len as usize, 0),
+                ERROR_SUCCESS => break,
+                _ => return Err(registry_error("RegQueryValueExW", rc)),
+            }
+        }
+        data.truncate(len as usize);
+        match kind {
+            REG_SZ => Ok(Some(RegValue::String(string_from_bytes(&data)))),
+            REG_EXPAND_SZ => Ok(Some(RegValue::ExpandString(string_from_bytes(&data)))),
+            REG_DWORD if data.len() == mem::size_of::<DWORD>() => {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(&data);
+                Ok(Some(RegValue::Dword(u32::from_le_bytes(bytes))))
+            }
+            _ => {
+                Err(Error::RegistryFailed("RegQueryValueExW",
+                                          io::Error::new(io::ErrorKind::InvalidData,
+                                                         format!("value of unsupported type {}",
+                                                                 kind))))
+            }
+        }
+    }
+
+    /// Reads the string value `name`, whether or not it holds references to expand. Returns
+    /// `None` if the key has no such value.
+    ///
+    /// # Failures
+    ///
+    /// * The value cannot be read, or is not a string
+    pub fn get_string(&self, name: &str) -> Result<Option<String>> {
+        match self.get(name)? {
+            Some(RegValue::String(s)) | Some(RegValue::ExpandString(s)) => Ok(Some(s)),
+            Some(RegValue::Dword(_)) => Err(wrong_type(name, "a string")),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the number value `name`, returning `None` if the key has no such value.
+    ///
+    /// # Failures
+    ///
+    /// * The value cannot be read, or is not a `REG_DWORD`
+    pub fn get_dword(&self, name: &str) -> Result<Option<u32>> {
+        match self.get(name)? {
+            Some(RegValue::Dword(n)) => Ok(Some(n)),
+            Some(_) => Err(wrong_type(name, "a number")),
+            None => Ok(None),
+        }
+    }
+
+    /// Writes `value` as the value `name`, replacing any value of that name whatever its type.
+    ///
+    /// # Failures
+    ///
+    /// * The key was not opened for writing, or the caller may not write to it
+    pub fn set(&self, name: &str, value: &RegValue) -> Result<()> {
+        let (kind, data) = match *value {
+            RegValue::String(ref s) => (REG_SZ, bytes_from_string(s)?),
+            RegValue::ExpandString(ref s) => (REG_EXPAND_SZ, bytes_from_string(s)?),
+            RegValue::Dword(n) => (REG_DWORD, n.to_le_bytes().to_vec()),
+        };
+        let name = wide(name)?;
+        let rc = unsafe {
+            RegSetValueExW(self.0,
+                           name.as_ptr(),
+                           0,
+                           kind,
+                           data.as_ptr(),
+                           data.len() as DWORD)
+        };
+        if rc as DWORD != ERROR_SUCCESS {
+            return Err(registry_error("RegSetValueExW", rc));
+        }
+        Ok(())
+    }
+
+    /// Writes `value` as the `REG_SZ` value `name`.
+    ///
+    /// # Failures
+    ///
+    /// * The key was not opened for writing, or the caller may not write to it
+    pub fn set_string(&self, name: &str, value: &str) -> Result<()> {
+        self.set(name, &RegValue::String(value.to_string()))
+    }
+
+    /// Writes `value` as the `REG_DWORD` value `name`.
+    ///
+    /// # Failures
+    ///
+    /// * The key was not opened for writing, or the caller may not write to it
+    pub fn set_dword(&self, name: &str, value: u32) -> Result<()> {
+        self.set(name, &RegValue::Dword(value))
+    }
+
+    /// Deletes the value `name`, returning `false` if the key had no such value.
+    ///
+    /// # Failures
+    ///
+    /// * The key was not opened for writing, or the caller may not write to it
+    pub fn delete_value(&self, name: &str) -> Result<bool> {
+        let name = wide(name)?;
+        let rc = unsafe { RegDeleteValueW(self.0, name.as_ptr()) };
+        match rc as DWORD {
+            ERROR_SUCCESS => Ok(true),
+            ERROR_FILE_NOT_FOUND => Ok(false),
+            _ => Err(registry_error("RegDeleteValueW", rc)),
+        }
+    }
+}
+
+impl Drop for RegKey {
+    fn drop(&mut self) {
+        unsafe {
+            RegCloseKey(self.0);
+        }
+    }
+}
+
+/// Whether there is a key at `path` under `hive`.
+///
+/// # Failures
+///
+/// * The key exists but the caller may not read it
+pub fn key_exists(hive: Hive, path: &str) -> Result<bool> {
+    Ok(RegKey::open(hive, path, false)?.is_some())
+}
+
+/// Deletes the key at `path` under `hive`, which must not have keys of its own, returning
+/// `false` if there was no such key.
+///
+/// # Failures
+///
+/// * The key has keys below it
+/// * The caller may not delete the key
+pub fn delete_key(hive: Hive, path: &str) -> Result<bool> {
+    let path = wide(path)?;
+    let rc = unsafe { RegDeleteKeyW(hive.handle(), path.as_ptr()) };
+    match rc as DWORD {
+        ERROR_SUCCESS => Ok(true),
+        ERROR_FILE_NOT_FOUND => Ok(false),
+        _ => Err(registry_error("RegDeleteKeyW", rc)),
+    }
+}
+
+/// Decodes a string value, which is UTF-16 and usually, but not always, NUL-terminated.
+fn string_from_bytes(data: &[u8]) -> String {
+    let mut units: Vec<u16> = data.chunks(2)
+                                  .filter(|c| c.len() == 2)
+                                  .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                                  .collect();
+    while units.last() == Some(&0) {
+        units.pop();
+    }
+    String::from_utf16_lossy(&units)
+}
+
+/// Encodes a string value as NUL-terminated UTF-16.
+fn bytes_from_string(s: &str) -> io::Result<Vec<u8>> {
+    Ok(wide(s)?.as_slice_with_nul()
+               .iter()
+               .flat_map(|unit| unit.to_le_bytes().to_vec())
+               .collect())
+}
+
+/// Converts a string for the wide-character APIs.
+///
+/// # Failures
+///
+/// * The string contains a NUL, which would otherwise silently cut it short
+pub(crate) fn wide(s: &str) -> io::Result<WideCString> {
+    // The string is left out of the error, as it may be a password
+    WideCString::from_str(s).map_err(|_| {
+                                io::Error::new(io::ErrorKind::InvalidInput, "string contains a NUL")
+                            })
+}
+
+fn registry_error(call: &'static str, rc: i32) -> Error {
+    Error::RegistryFailed(call, io::Error::from_raw_os_error(rc))
+}
+
+fn wrong_type(name: &str, expected: &str) -> Error {
+    Error::RegistryFailed("RegQueryValueExW",
+                          io::Error::new(io::ErrorKind::InvalidData,
+                                         format!("{} is not {}", name, expected)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn values_round_trip() {
+        let path = format!("Software\\Habitat\\test-{}", rand::random::<u32>());
+        let key = RegKey::create(Hive::CurrentUser, &path).unwrap();
+
+        key.set_string("plain", "C:\\hab\\bin").unwrap();
+        key.set("expand",
+                &RegValue::ExpandString("%USERPROFILE%\\bin".to_string()))
+           .unwrap();
+        key.set_dword("number", 42).unwrap();
+
+        assert!(key_exists(Hive::CurrentUser, &path).unwrap());
+        assert_eq!(Some("C:\\hab\\bin".to_string()),
+                   key.get_string("plain").unwrap());
+        assert_eq!(Some(RegValue::ExpandString("%USERPROFILE%\\bin".to_string())),
+                   key.get("expand").unwrap());
+        assert_eq!(Some(42), key.get_dword("number").unwrap());
+        assert!(key.get_dword("plain").is_err());
+        assert!(key.set_string("nul", "C:\\hab\0bin").is_err());
+        assert_eq!(None, key.get("missing").unwrap());
+        assert!(key.delete_value("number").unwrap());
+        assert!(!key.delete_value("number").unwrap());
+
+        drop(key);
+        assert!(delete_key(Hive::CurrentUser, &path).unwrap());
+        assert!(!key_exists(Hive::CurrentUser, &path).unwrap());
+    }
+}
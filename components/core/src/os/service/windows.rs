@@ -0,0 +1,387 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for registering, querying, and removing Windows Services with the Service Control
+//! Manager (SCM).
+
+use std::{io,
+          mem,
+          path::PathBuf,
+          ptr,
+          time::Duration};
+
+use widestring::WideCString;
+use winapi::{shared::{minwindef::DWORD,
+                      winerror::ERROR_SERVICE_DOES_NOT_EXIST},
+             um::{winnt::{DELETE,
+                          SERVICE_AUTO_START,
+                          SERVICE_DEMAND_START,
+                          SERVICE_DISABLED,
+                          SERVICE_ERROR_NORMAL,
+                          SERVICE_WIN32_OWN_PROCESS},
+                  winsvc::{self,
+                           ChangeServiceConfig2W,
+                           CloseServiceHandle,
+                           CreateServiceW,
+                           DeleteService,
+                           OpenSCManagerW,
+                           OpenServiceW,
+                           QueryServiceStatus,
+                           SC_ACTION,
+                           SC_HANDLE,
+                           SC_MANAGER_CONNECT,
+                           SC_MANAGER_CREATE_SERVICE,
+                           SERVICE_CHANGE_CONFIG,
+                           SERVICE_CONFIG_DESCRIPTION,
+                           SERVICE_CONFIG_FAILURE_ACTIONS,
+                           SERVICE_DESCRIPTIONW,
+                           SERVICE_FAILURE_ACTIONSW,
+                           SERVICE_QUERY_STATUS,
+                           SERVICE_STATUS}}};
+
+use crate::{error::{Error,
+                    Result},
+            os::registry::wide,
+            util};
+
+/// When the SCM starts a service.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StartType {
+    /// Started automatically during system startup.
+    Automatic,
+    /// Started only when requested.
+    Manual,
+    /// Cannot be started.
+    Disabled,
+}
+
+impl StartType {
+    fn as_dword(self) -> DWORD {
+        match self {
+            StartType::Automatic => SERVICE_AUTO_START,
+            StartType::Manual => SERVICE_DEMAND_START,
+            StartType::Disabled => SERVICE_DISABLED,
+        }
+    }
+}
+
+/// An action the SCM takes when a service process exits unexpectedly.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecoveryAction {
+    /// Take no action.
+    None,
+    /// Restart the service after the given delay.
+    Restart(Duration),
+    /// Reboot the computer after the given delay.
+    Reboot(Duration),
+}
+
+impl RecoveryAction {
+    fn as_sc_action(self) -> SC_ACTION {
+        let (action_type, delay) = match self {
+            RecoveryAction::None => (winsvc::SC_ACTION_NONE, Duration::from_secs(0)),
+            RecoveryAction::Restart(delay) => (winsvc::SC_ACTION_RESTART, delay),
+            RecoveryAction::Reboot(delay) => (winsvc::SC_ACTION_REBOOT, delay),
+        };
+        SC_ACTION { Type:  action_type,
+                    Delay: util::duration_as_millis(delay) as DWORD, }
+    }
+}
+
+/// What the SCM does when a service fails.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RecoveryOptions {
+    /// The actions for the first, second, and subsequent failures. The last action is repeated
+    /// for any further failures.
+    pub actions:      Vec<RecoveryAction>,
+    /// How long a service must run without failing before its failure count is reset.
+    pub reset_period: Duration,
+}
+
+/// The definition of a Windows Service wrapping a packaged executable.
+#[derive(Clone, Debug)]
+pub struct ServiceConfig {
+    /// The name used to refer to the service, such as in `sc.exe` and `net start`.
+    pub name:         String,
+    /// The name shown in the Services console.
+    pub display_name: String,
+    pub description:  Option<String>,
+    /// The absolute path to the executable the service runs.
+    pub executable:   PathBuf,
+    pub args:         Vec<String>,
+    pub start_type:   StartType,
+    /// The account the service runs as, in `DOMAIN\user` form. Defaults to `LocalSystem`.
+    pub account:      Option<String>,
+    pub password:     Option<String>,
+    pub recovery:     RecoveryOptions,
+}
+
+/// The current state of a service, as reported by the SCM.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ServiceState {
+    Stopped,
+    StartPending,
+    StopPending,
+    Running,
+    ContinuePending,
+    PausePending,
+    Paused,
+}
+
+impl ServiceState {
+    fn from_dword(state: DWORD) -> Option<Self> {
+        match state {
+            winsvc::SERVICE_STOPPED => Some(ServiceState::Stopped),
+            winsvc::SERVICE_START_PENDING => Some(ServiceState::StartPending),
+            winsvc::SERVICE_STOP_PENDING => Some(ServiceState::StopPending),
+            winsvc::SERVICE_RUNNING => Some(ServiceState::Running),
+            winsvc::SERVICE_CONTINUE_PENDING => Some(ServiceState::ContinuePending),
+            winsvc::SERVICE_PAUSE_PENDING => Some(ServiceState::PausePending),
+            winsvc::SERVICE_PAUSED => Some(ServiceState::Paused),
+            _ => None,
+        }
+    }
+}
+
+/// An open SCM or service handle which is closed when dropped.
+struct ScHandle(SC_HANDLE);
+
+impl ScHandle {
+    fn open_manager(access: DWORD) -> Result<Self> {
+        let handle = unsafe { OpenSCManagerW(ptr::null(), ptr::null(), access) };
+        if handle.is_null() {
+            return Err(scm_error("OpenSCManagerW"));
+        }
+        Ok(ScHandle(handle))
+    }
+
+    /// Opens the named service, returning `None` if no such service exists.
+    fn open_service(&self, name: &str, access: DWORD) -> Result<Option<Self>> {
+        let name = wide(name)?;
+        let handle = unsafe { OpenServiceW(self.0, name.as_ptr(), access) };
+        if handle.is_null() {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(ERROR_SERVICE_DOES_NOT_EXIST as i32) {
+                return Ok(None);
+            }
+            return Err(Error::ServiceControlManagerFailed("OpenServiceW", err));
+        }
+        Ok(Some(ScHandle(handle)))
+    }
+}
+
+impl Drop for ScHandle {
+    fn drop(&mut self) {
+        unsafe {
+            CloseServiceHandle(self.0);
+        }
+    }
+}
+
+/// Registers a new service with the SCM, applying its description and recovery options. If
+/// either cannot be applied, the newly created service is deleted again.
+///
+/// # Failures
+///
+/// * The caller is not permitted to create services
+/// * A service with the same name already exists
+/// * A name, path, argument, or credential contains a NUL
+pub fn register(config: &ServiceConfig) -> Result<()> {
+    let manager = ScHandle::open_manager(SC_MANAGER_CONNECT | SC_MANAGER_CREATE_SERVICE)?;
+    let name = wide(&config.name)?;
+    let display_name = wide(&config.display_name)?;
+    let command_line = wide(&command_line(config))?;
+    let account = match config.account {
+        Some(ref account) => Some(wide(account)?),
+        None => None,
+    };
+    let password = match config.password {
+        Some(ref password) => Some(wide(password)?),
+        None => None,
+    };
+    let description = match config.description {
+        Some(ref description) => Some(wide(description)?),
+        None => None,
+    };
+
+    let handle = unsafe {
+        CreateServiceW(manager.0,
+                       name.as_ptr(),
+                       display_name.as_ptr(),
+                       SERVICE_CHANGE_CONFIG | SERVICE_QUERY_STATUS | DELETE,
+                       SERVICE_WIN32_OWN_PROCESS,
+                       config.start_type.as_dword(),
+                       SERVICE_ERROR_NORMAL,
+                       command_line.as_ptr(),
+                       ptr::null(),
+                       ptr::null_mut(),
+                       ptr::null(),
+                       account.as_ref().map_or(ptr::null(), |a| a.as_ptr()),
+                       password.as_ref().map_or(ptr::null(), |p| p.as_ptr()))
+    };
+    if handle.is_null() {
+        return Err(scm_error("CreateServiceW"));
+    }
+    let service = ScHandle(handle);
+
+    if let Err(err) = configure(&service, description, &config.recovery) {
+        if unsafe { DeleteService(service.0) } == 0 {
+            warn!("Failed to delete service {} after failing to configure it: {}",
+                  config.name,
+                  io::Error::last_os_error());
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Applies a newly created service's description and recovery options.
+fn configure(service: &ScHandle,
+             description: Option<WideCString>,
+             recovery: &RecoveryOptions)
+             -> Result<()> {
+    if let Some(description) = description {
+        let mut info = SERVICE_DESCRIPTIONW { lpDescription: description.into_raw(), };
+        let ok = unsafe {
+            ChangeServiceConfig2W(service.0,
+                                  SERVICE_CONFIG_DESCRIPTION,
+                                  &mut info as *mut _ as *mut _)
+        };
+        // Reclaim the string so it is freed
+        let _ = unsafe { WideCString::from_raw(info.lpDescription) };
+        if ok == 0 {
+            return Err(scm_error("ChangeServiceConfig2W"));
+        }
+    }
+
+    if !recovery.actions.is_empty() {
+        let mut actions: Vec<SC_ACTION> =
+            recovery.actions.iter().map(|a| a.as_sc_action()).collect();
+        let mut info: SERVICE_FAILURE_ACTIONSW = unsafe { mem::zeroed() };
+        info.dwResetPeriod = recovery.reset_period.as_secs() as DWORD;
+        info.cActions = actions.len() as DWORD;
+        info.lpsaActions = actions.as_mut_ptr();
+        let ok = unsafe {
+            ChangeServiceConfig2W(service.0,
+                                  SERVICE_CONFIG_FAILURE_ACTIONS,
+                                  &mut info as *mut _ as *mut _)
+        };
+        if ok == 0 {
+            return Err(scm_error("ChangeServiceConfig2W"));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the current state of the named service, or `None` if it is not registered.
+///
+/// # Failures
+///
+/// * The SCM cannot be contacted or the service status cannot be read
+pub fn query(name: &str) -> Result<Option<ServiceState>> {
+    let manager = ScHandle::open_manager(SC_MANAGER_CONNECT)?;
+    let service = match manager.open_service(name, SERVICE_QUERY_STATUS)? {
+        Some(service) => service,
+        None => return Ok(None),
+    };
+    let mut status: SERVICE_STATUS = unsafe { mem::zeroed() };
+    if unsafe { QueryServiceStatus(service.0, &mut status) } == 0 {
+        return Err(scm_error("QueryServiceStatus"));
+    }
+    Ok(ServiceState::from_dword(status.dwCurrentState))
+}
+
+/// Marks the named service for deletion, returning `false` if it was not registered. The SCM
+/// removes the service once it has stopped and all handles to it are closed.
+///
+/// # Failures
+///
+/// * The caller is not permitted to delete the service
+pub fn remove(name: &str) -> Result<bool> {
+    let manager = ScHandle::open_manager(SC_MANAGER_CONNECT)?;
+    let service = match manager.open_service(name, DELETE)? {
+        Some(service) => service,
+        None => return Ok(false),
+    };
+    if unsafe { DeleteService(service.0) } == 0 {
+        return Err(scm_error("DeleteService"));
+    }
+    Ok(true)
+}
+
+/// Builds the service's command line, quoting the executable and any argument containing
+/// whitespace or quotes so that paths such as `C:\Program Files` are not split by the SCM.
+fn command_line(config: &ServiceConfig) -> String {
+    let mut line = format!("\"{}\"", config.executable.display());
+    for arg in &config.args {
+        line.push(' ');
+        if arg.is_empty() || arg.chars().any(|c| c.is_whitespace() || c == '"') {
+            quote_arg(&mut line, arg);
+        } else {
+            line.push_str(arg);
+        }
+    }
+    line
+}
+
+/// Appends `arg` to `line` in quotes, escaping it as `CommandLineToArgvW` expects: backslashes
+/// are only special before a quote, so those before a quote in `arg` or before the closing quote
+/// are doubled.
+fn quote_arg(line: &mut String, arg: &str) {
+    line.push('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                line.push_str(&"\\".repeat(backslashes + 1));
+                backslashes = 0;
+            }
+            _ => backslashes = 0,
+        }
+        line.push(c);
+    }
+    line.push_str(&"\\".repeat(backslashes));
+    line.push('"');
+}
+
+fn scm_error(call: &'static str) -> Error {
+    Error::ServiceControlManagerFailed(call, io::Error::last_os_error())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn command_line_quotes_args_which_need_it() {
+        let config = ServiceConfig { name:         "hab-sup".to_string(),
+                                     display_name: "Habitat Supervisor".to_string(),
+                                     description:  None,
+                                     executable:   PathBuf::from("C:\\Program Files\\hab.exe"),
+                                     args:         vec!["run".to_string(),
+                                                        "".to_string(),
+                                                        "C:\\my dir\\".to_string(),
+                                                        "say\"hi\"".to_string(),
+                                                        "a\\\"b".to_string()],
+                                     start_type:   StartType::Automatic,
+                                     account:      None,
+                                     password:     None,
+                                     recovery:     RecoveryOptions::default(), };
+
+        assert_eq!("\"C:\\Program Files\\hab.exe\" run \"\" \"C:\\my dir\\\\\" \"say\\\"hi\\\"\" \
+                    \"a\\\\\\\"b\"",
+                   command_line(&config));
+    }
+}
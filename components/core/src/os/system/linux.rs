@@ -12,18 +12,30 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{ffi::CStr,
+use std::{env,
+          ffi::CStr,
           mem};
 
 use libc;
 
 use crate::{error::{Error,
                     Result},
-            os::system::Uname};
+            os::system::{Locale,
+                         Uname}};
 use errno::errno;
 
 pub fn uname() -> Result<Uname> { unsafe { uname_libc() } }
 
+/// Returns the locale that governs character encoding, taken from the first of `LC_ALL`,
+/// `LC_CTYPE` and `LANG` which is set, or the `C` locale when none are.
+pub fn locale() -> Locale {
+    let name = ["LC_ALL", "LC_CTYPE", "LANG"].iter()
+                                             .filter_map(|var| env::var(var).ok())
+                                             .find(|name| !name.is_empty())
+                                             .unwrap_or_else(|| "C".to_string());
+    Locale::from_posix_name(&name)
+}
+
 unsafe fn uname_libc() -> Result<Uname> {
     let mut utsname: libc::utsname = mem::uninitialized();
     let rv = libc::uname(&mut utsname);
@@ -0,0 +1,241 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The active locale, and decoding of text written in its encoding.
+//!
+//! Child processes write their output in whatever encoding their locale calls for, which is not
+//! always UTF-8. On Windows in particular, console programs such as `netsh` or a PowerShell hook
+//! write in the OEM code page (often CP437 or CP850) and other programs in the ANSI code page
+//! (often CP1252). `Locale::decode_output` turns such output into a `String` without garbling it.
+
+use std::str;
+
+/// A character encoding, named after the Windows code page it corresponds to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Ascii,
+    /// ISO-8859-1
+    Latin1,
+    Windows1252,
+    /// The original IBM PC code page, and the OEM code page of US English Windows.
+    Ibm437,
+    /// The OEM code page of most Western European Windows installations.
+    Ibm850,
+    /// Any other Windows code page. Text in these can only be decoded on Windows; elsewhere it is
+    /// decoded as UTF-8.
+    CodePage(u32),
+}
+
+impl Encoding {
+    pub fn from_code_page(code_page: u32) -> Self {
+        match code_page {
+            65001 => Encoding::Utf8,
+            20127 => Encoding::Ascii,
+            28591 => Encoding::Latin1,
+            1252 => Encoding::Windows1252,
+            437 => Encoding::Ibm437,
+            850 => Encoding::Ibm850,
+            n => Encoding::CodePage(n),
+        }
+    }
+
+    pub fn code_page(self) -> u32 {
+        match self {
+            Encoding::Utf8 => 65001,
+            Encoding::Ascii => 20127,
+            Encoding::Latin1 => 28591,
+            Encoding::Windows1252 => 1252,
+            Encoding::Ibm437 => 437,
+            Encoding::Ibm850 => 850,
+            Encoding::CodePage(n) => n,
+        }
+    }
+
+    /// Looks up a charset by the name a POSIX locale gives it, such as `UTF-8` or `ISO-8859-1`,
+    /// ignoring case and punctuation.
+    pub fn from_charset(charset: &str) -> Option<Self> {
+        let name: String = charset.chars()
+                                  .filter(|c| c.is_ascii_alphanumeric() || *c == '.')
+                                  .collect::<String>()
+                                  .to_ascii_lowercase();
+        match name.as_str() {
+            "utf8" => Some(Encoding::Utf8),
+            "ascii" | "usascii" | "ansix3.41968" => Some(Encoding::Ascii),
+            "iso88591" | "latin1" => Some(Encoding::Latin1),
+            "cp1252" | "windows1252" => Some(Encoding::Windows1252),
+            "cp437" | "ibm437" => Some(Encoding::Ibm437),
+            "cp850" | "ibm850" => Some(Encoding::Ibm850),
+            _ => None,
+        }
+    }
+
+    /// Decodes `bytes`, replacing any which cannot be decoded with U+FFFD.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Ascii => {
+                bytes.iter()
+                     .map(|&b| if b < 0x80 { char::from(b) } else { '\u{fffd}' })
+                     .collect()
+            }
+            Encoding::Latin1 => bytes.iter().map(|&b| char::from(b)).collect(),
+            Encoding::Windows1252 => {
+                bytes.iter()
+                     .map(|&b| {
+                         match b {
+                             0x80..=0x9f => WINDOWS_1252[usize::from(b - 0x80)],
+                             _ => char::from(b),
+                         }
+                     })
+                     .collect()
+            }
+            Encoding::Ibm437 => decode_oem(&CP437, bytes),
+            Encoding::Ibm850 => decode_oem(&CP850, bytes),
+            Encoding::CodePage(n) => decode_code_page(n, bytes),
+        }
+    }
+}
+
+/// The active locale, as returned by `os::system::locale`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Locale {
+    /// The locale's name, such as `en_US.UTF-8`, or on Windows `en-US`.
+    pub name:             String,
+    /// The encoding programs write text in: the locale's charset, or on Windows the ANSI code
+    /// page.
+    pub encoding:         Encoding,
+    /// The encoding console programs write text in: on Windows the OEM code page, and elsewhere
+    /// the same as `encoding`.
+    pub console_encoding: Encoding,
+}
+
+impl Locale {
+    /// Reads a locale name of the form `language_TERRITORY.charset@modifier`, as found in
+    /// `LC_ALL`, `LC_CTYPE` or `LANG`. The `C` and `POSIX` locales are ASCII, a locale which names
+    /// no charset is Latin-1 as with glibc, and an unknown charset is taken to be UTF-8.
+    pub fn from_posix_name(name: &str) -> Self {
+        let without_modifier = name.split('@').next().unwrap_or("");
+        let encoding = match without_modifier.find('.') {
+            Some(i) => Encoding::from_charset(&without_modifier[i + 1..]).unwrap_or(Encoding::Utf8),
+            None if name == "C" || name == "POSIX" => Encoding::Ascii,
+            None if cfg!(target_os = "macos") => Encoding::Utf8,
+            None => Encoding::Latin1,
+        };
+        Locale { name: name.to_string(),
+                 encoding,
+                 console_encoding: encoding }
+    }
+
+    /// Decodes the output of a child process. Output which is valid UTF-8 is taken as such, since
+    /// many programs write UTF-8 whatever the locale; anything else is decoded with the console
+    /// encoding.
+    pub fn decode_output(&self, bytes: &[u8]) -> String {
+        match str::from_utf8(bytes) {
+            Ok(s) => s.to_string(),
+            Err(_) => self.console_encoding.decode(bytes),
+        }
+    }
+}
+
+fn decode_oem(table: &[char; 128], bytes: &[u8]) -> String {
+    bytes.iter()
+         .map(|&b| {
+             if b < 0x80 {
+                 char::from(b)
+             } else {
+                 table[usize::from(b - 0x80)]
+             }
+         })
+         .collect()
+}
+
+#[cfg(windows)]
+fn decode_code_page(code_page: u32, bytes: &[u8]) -> String {
+    super::windows::decode_code_page(code_page, bytes)
+        .unwrap_or_else(|| String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(not(windows))]
+fn decode_code_page(_code_page: u32, bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// Windows-1252 from 0x80 to 0x9f; the rest is the same as Latin-1. Bytes which Windows leaves
+/// undefined map to the C1 control characters, as `MultiByteToWideChar` does.
+const WINDOWS_1252: [char; 32] = ['€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹',
+                                  'Œ', '\u{8d}', 'Ž', '\u{8f}', '\u{90}', '‘', '’', '“', '”', '•',
+                                  '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ'];
+
+/// Code page 437 from 0x80 up; below that it is ASCII.
+const CP437: [char; 128] =
+    ['Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ',
+     'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', 'á', 'í', 'ó', 'ú',
+     'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', '░', '▒', '▓', '│', '┤', '╡',
+     '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', '└', '┴', '┬', '├', '─', '┼', '╞', '╟',
+     '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘',
+     '┌', '█', '▄', '▌', '▐', '▀', 'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ',
+     '∞', 'φ', 'ε', '∩', '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²',
+     '■', '\u{a0}'];
+
+/// Code page 850 from 0x80 up; below that it is ASCII.
+const CP850: [char; 128] =
+    ['Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ',
+     'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '×', 'ƒ', 'á', 'í', 'ó', 'ú',
+     'ñ', 'Ñ', 'ª', 'º', '¿', '®', '¬', '½', '¼', '¡', '«', '»', '░', '▒', '▓', '│', '┤', 'Á',
+     'Â', 'À', '©', '╣', '║', '╗', '╝', '¢', '¥', '┐', '└', '┴', '┬', '├', '─', '┼', 'ã', 'Ã',
+     '╚', '╔', '╩', '╦', '╠', '═', '╬', '¤', 'ð', 'Ð', 'Ê', 'Ë', 'È', 'ı', 'Í', 'Î', 'Ï', '┘',
+     '┌', '█', '▄', '¦', 'Ì', '▀', 'Ó', 'ß', 'Ô', 'Ò', 'õ', 'Õ', 'µ', 'þ', 'Þ', 'Ú', 'Û', 'Ù',
+     'ý', 'Ý', '¯', '´', '\u{ad}', '±', '‗', '¾', '¶', '§', '÷', '¸', '°', '¨', '·', '¹', '³',
+     '²', '■', '\u{a0}'];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn posix_names() {
+        let locale = Locale::from_posix_name("de_DE.ISO-8859-1@euro");
+        assert_eq!(Encoding::Latin1, locale.encoding);
+        assert_eq!(Encoding::Latin1, locale.console_encoding);
+        assert_eq!(Encoding::Utf8,
+                   Locale::from_posix_name("en_US.utf8").encoding);
+        assert_eq!(Encoding::Ascii, Locale::from_posix_name("C").encoding);
+        assert_eq!(Encoding::Utf8,
+                   Locale::from_posix_name("ja_JP.eucJP").encoding);
+    }
+
+    #[test]
+    fn decode_code_pages() {
+        // As written by a console program on German Windows, then by a GUI one
+        assert_eq!("Größe: 5 ¤",
+                   Encoding::Ibm850.decode(b"Gr\x94\xe1e: 5 \xcf"));
+        assert_eq!("Größe: 5 €",
+                   Encoding::Windows1252.decode(b"Gr\xf6\xdfe: 5 \x80"));
+        assert_eq!("┌─┐", Encoding::Ibm437.decode(b"\xda\xc4\xbf"));
+        assert_eq!("a\u{fffd}", Encoding::Ascii.decode(b"a\xe9"));
+        assert_eq!(Encoding::Ibm850, Encoding::from_code_page(850));
+        assert_eq!(1252, Encoding::Windows1252.code_page());
+    }
+
+    #[test]
+    fn decode_output_prefers_utf8() {
+        let locale = Locale { name:             "de-DE".to_string(),
+                              encoding:         Encoding::Windows1252,
+                              console_encoding: Encoding::Ibm850, };
+
+        assert_eq!("Größe", locale.decode_output("Größe".as_bytes()));
+        assert_eq!("Größe", locale.decode_output(b"Gr\x94\xe1e"));
+    }
+}
@@ -12,15 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod locale;
+
+pub use self::locale::{Encoding,
+                       Locale};
+
 #[cfg(windows)]
 mod windows;
 #[cfg(windows)]
-pub use self::windows::uname;
+pub use self::windows::{locale,
+                        uname};
 
 #[cfg(not(windows))]
 pub mod linux;
 #[cfg(not(windows))]
-pub use self::linux::uname;
+pub use self::linux::{locale,
+                      uname};
 
 #[derive(Debug)]
 pub struct Uname {
@@ -30,3 +37,7 @@ pub struct Uname {
     pub version:   String,
     pub machine:   String,
 }
+
+/// Decodes the output of a child process according to the active locale. See
+/// `Locale::decode_output`.
+pub fn decode_output(bytes: &[u8]) -> String { locale().decode_output(bytes) }
@@ -12,8 +12,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::ptr;
+
+use winapi::{shared::ntdef::LPCSTR,
+             um::{stringapiset::MultiByteToWideChar,
+                  winnls::{GetACP,
+                           GetOEMCP,
+                           GetUserDefaultLocaleName},
+                  winnt::LOCALE_NAME_MAX_LENGTH}};
+
 use crate::{error::Result,
-            os::system::Uname};
+            os::system::{Encoding,
+                         Locale,
+                         Uname}};
 
 pub fn uname() -> Result<Uname> {
     Ok(Uname { sys_name:  String::from("Windows"),
@@ -22,3 +33,41 @@ pub fn uname() -> Result<Uname> {
                version:   String::from("Microsoft Windows 10 Enterprise Insider Preview"),
                machine:   String::from("x86_64"), })
 }
+
+/// Returns the user's default locale, with the ANSI code page as its encoding and the OEM code
+/// page as its console encoding.
+pub fn locale() -> Locale {
+    let mut name = [0u16; LOCALE_NAME_MAX_LENGTH];
+    let len = unsafe { GetUserDefaultLocaleName(name.as_mut_ptr(), name.len() as i32) };
+    // The length includes the terminating NUL, and is 0 on failure
+    let name = if len > 0 {
+        String::from_utf16_lossy(&name[..len as usize - 1])
+    } else {
+        String::new()
+    };
+    Locale { name,
+             encoding: Encoding::from_code_page(unsafe { GetACP() }),
+             console_encoding: Encoding::from_code_page(unsafe { GetOEMCP() }) }
+}
+
+/// Decodes `bytes` from the code page `code_page`, returning `None` if Windows does not know the
+/// code page.
+pub(super) fn decode_code_page(code_page: u32, bytes: &[u8]) -> Option<String> {
+    if bytes.is_empty() {
+        return Some(String::new());
+    }
+    let src = bytes.as_ptr() as LPCSTR;
+    let src_len = bytes.len() as i32;
+    unsafe {
+        let len = MultiByteToWideChar(code_page, 0, src, src_len, ptr::null_mut(), 0);
+        if len <= 0 {
+            return None;
+        }
+        let mut wide = vec![0u16; len as usize];
+        let len = MultiByteToWideChar(code_page, 0, src, src_len, wide.as_mut_ptr(), len);
+        if len <= 0 {
+            return None;
+        }
+        Some(String::from_utf16_lossy(&wide[..len as usize]))
+    }
+}
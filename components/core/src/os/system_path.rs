@@ -0,0 +1,196 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Changes to the `PATH` Windows gives new processes, as opposed to the `PATH` of this one.
+//!
+//! Windows builds each new process's environment from the registry: the machine's `PATH`
+//! followed by the user's. Setting `PATH` in our own environment is therefore lost as soon as
+//! the shell it was meant for is closed, so a directory which should stay on the `PATH`, such
+//! as the one packages are binlinked into, has to be written to the registry instead. Running
+//! programs, Explorer among them, are then told the environment has changed so that the
+//! processes they start pick it up; shells which are already open keep the `PATH` they have.
+
+use std::{path::Path,
+          ptr};
+
+use widestring::WideCString;
+use winapi::{shared::minwindef::LPARAM,
+             um::winuser::{SendMessageTimeoutW,
+                           HWND_BROADCAST,
+                           SMTO_ABORTIFHUNG,
+                           WM_SETTINGCHANGE}};
+
+use super::registry::{Hive,
+                      RegKey,
+                      RegValue};
+use crate::error::Result;
+
+/// The key holding the environment of the current user.
+const USER_ENVIRONMENT_KEY: &str = "Environment";
+/// The key holding the environment shared by every user.
+const MACHINE_ENVIRONMENT_KEY: &str =
+    "SYSTEM\\CurrentControlSet\\Control\\Session Manager\\Environment";
+const PATH_VALUE: &str = "Path";
+/// How long each running program is given to handle the change notification, in milliseconds.
+const BROADCAST_TIMEOUT_MS: u32 = 5000;
+
+fn environment_key(hive: Hive) -> &'static str {
+    match hive {
+        Hive::CurrentUser => USER_ENVIRONMENT_KEY,
+        Hive::LocalMachine => MACHINE_ENVIRONMENT_KEY,
+    }
+}
+
+/// The directories of the persistent `PATH` of `hive`, in order, with any `%VARIABLE%`
+/// references left unexpanded.
+///
+/// # Failures
+///
+/// * The environment key cannot be read
+pub fn persistent_path(hive: Hive) -> Result<Vec<String>> {
+    let value = match RegKey::open(hive, environment_key(hive), false)? {
+        Some(key) => key.get_string(PATH_VALUE)?.unwrap_or_default(),
+        None => String::new(),
+    };
+    Ok(entries(&value).map(str::to_string).collect())
+}
+
+/// Appends `dir` to the persistent `PATH` of `hive` unless it is already on it, and tells
+/// running programs about the change. Returns whether the `PATH` was changed.
+///
+/// # Failures
+///
+/// * The environment key cannot be read or written, as when changing the machine's `PATH` without
+///   administrative rights
+pub fn add_to_persistent_path(hive: Hive, dir: &Path) -> Result<bool> {
+    update(hive, |value| with_dir(value, &dir.to_string_lossy()))
+}
+
+/// Removes every occurrence of `dir` from the persistent `PATH` of `hive`, and tells running
+/// programs about the change. Returns whether the `PATH` was changed.
+///
+/// # Failures
+///
+/// * The environment key cannot be read or written, as when changing the machine's `PATH` without
+///   administrative rights
+pub fn remove_from_persistent_path(hive: Hive, dir: &Path) -> Result<bool> {
+    update(hive, |value| without_dir(value, &dir.to_string_lossy()))
+}
+
+/// Rewrites the `PATH` of `hive` with `change`, which returns `None` when there is nothing to
+/// do. The value keeps its type, so that a `PATH` holding `%VARIABLE%` references goes on
+/// having them expanded.
+fn update<F>(hive: Hive, change: F) -> Result<bool>
+    where F: FnOnce(&str) -> Option<String>
+{
+    let key = RegKey::create(hive, environment_key(hive))?;
+    let current = key.get(PATH_VALUE)?;
+    let (value, expand) = match current {
+        Some(RegValue::String(ref s)) => (s.as_str(), false),
+        Some(RegValue::ExpandString(ref s)) => (s.as_str(), true),
+        _ => ("", true),
+    };
+    let changed = match change(value) {
+        Some(changed) => changed,
+        None => return Ok(false),
+    };
+    let changed = if expand {
+        RegValue::ExpandString(changed)
+    } else {
+        RegValue::String(changed)
+    };
+    key.set(PATH_VALUE, &changed)?;
+    broadcast_environment_change();
+    Ok(true)
+}
+
+/// Tells every top-level window that the environment has changed. Programs which do not
+/// answer in time are passed over, as this is only a courtesy; the registry already holds the
+/// change.
+fn broadcast_environment_change() {
+    let environment = WideCString::from_str("Environment").unwrap();
+    let rc = unsafe {
+        SendMessageTimeoutW(HWND_BROADCAST,
+                            WM_SETTINGCHANGE,
+                            0,
+                            environment.as_ptr() as LPARAM,
+                            SMTO_ABORTIFHUNG,
+                            BROADCAST_TIMEOUT_MS,
+                            ptr::null_mut())
+    };
+    if rc == 0 {
+        debug!("Broadcasting the environment change failed: {}",
+               std::io::Error::last_os_error());
+    }
+}
+
+/// The non-empty entries of a `PATH` value.
+fn entries(value: &str) -> impl Iterator<Item = &str> {
+    value.split(';').filter(|entry| !entry.trim().is_empty())
+}
+
+/// Whether two `PATH` entries name the same directory, ignoring case and trailing separators
+/// as Windows does.
+fn same_dir(a: &str, b: &str) -> bool {
+    let normalize = |dir: &str| {
+        dir.trim()
+           .trim_end_matches(&['\\', '/'][..])
+           .replace('/', "\\")
+           .to_lowercase()
+    };
+    normalize(a) == normalize(b)
+}
+
+/// `value` with `dir` appended, or `None` if it is already there.
+fn with_dir(value: &str, dir: &str) -> Option<String> {
+    if entries(value).any(|entry| same_dir(entry, dir)) {
+        return None;
+    }
+    let mut dirs: Vec<&str> = entries(value).collect();
+    dirs.push(dir);
+    Some(dirs.join(";"))
+}
+
+/// `value` without any entry naming `dir`, or `None` if there is none.
+fn without_dir(value: &str, dir: &str) -> Option<String> {
+    if !entries(value).any(|entry| same_dir(entry, dir)) {
+        return None;
+    }
+    Some(entries(value).filter(|entry| !same_dir(entry, dir))
+                       .collect::<Vec<_>>()
+                       .join(";"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn adds_directories_once() {
+        assert_eq!(Some("C:\\Windows;C:\\hab\\bin".to_string()),
+                   with_dir("C:\\Windows;", "C:\\hab\\bin"));
+        assert_eq!(Some("C:\\hab\\bin".to_string()),
+                   with_dir("", "C:\\hab\\bin"));
+        assert_eq!(None, with_dir("C:\\Windows;c:\\HAB\\bin\\", "C:\\hab\\bin"));
+        assert_eq!(None, with_dir("%USERPROFILE%\\bin", "%userprofile%/bin"));
+    }
+
+    #[test]
+    fn removes_every_occurrence() {
+        assert_eq!(Some("C:\\Windows;C:\\tools".to_string()),
+                   without_dir("C:\\hab\\bin;C:\\Windows;;c:\\hab\\bin\\;C:\\tools",
+                               "C:\\hab\\bin"));
+        assert_eq!(None, without_dir("C:\\Windows", "C:\\hab\\bin"));
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Origin aliases, which let packages from one origin stand in for those of another, such as a
+//! mirror of `core` published under `mycorp-core`.
+//!
+//! Aliases are read from `hab/etc/origin-aliases.toml` under the filesystem root, and are only
+//! used by resolution which asks for them with `PackageInstall::load_aliased`. Each origin maps
+//! to the origins which may stand in for it, tried in order:
+//!
+//! ```toml
+//! [aliases]
+//! core = ["mycorp-core"]
+//! ```
+
+use std::{collections::BTreeMap,
+          fs,
+          io,
+          path::{Path,
+                 PathBuf}};
+
+use serde_derive::Deserialize;
+use toml;
+
+use crate::{error::{Error,
+                    Result},
+            fs::FS_ROOT_PATH};
+
+/// Where the alias map is kept, relative to the filesystem root.
+pub const ALIASES_PATH: &str = "hab/etc/origin-aliases.toml";
+
+/// Returns the path to the alias map, optionally taking a custom filesystem root.
+pub fn aliases_path(fs_root_path: Option<&Path>) -> PathBuf {
+    fs_root_path.unwrap_or(&*FS_ROOT_PATH).join(ALIASES_PATH)
+}
+
+/// The origins which may stand in for others during resolution.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct OriginAliases {
+    #[serde(default)]
+    aliases: BTreeMap<String, Vec<String>>,
+}
+
+impl OriginAliases {
+    pub fn new() -> Self { Self::default() }
+
+    /// Reads the alias map under `fs_root_path`, which is empty when there is no map.
+    ///
+    /// # Failures
+    ///
+    /// * The map exists but cannot be read or parsed
+    pub fn load(fs_root_path: Option<&Path>) -> Result<Self> {
+        let path = aliases_path(fs_root_path);
+        match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).map_err(Error::ConfigFileSyntax),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Error::ConfigFileIO(path, e)),
+        }
+    }
+
+    /// Lets packages from `alias` stand in for those from `origin`, after any aliases already
+    /// given for it.
+    pub fn alias<T, U>(mut self, origin: T, alias: U) -> Self
+        where T: Into<String>,
+              U: Into<String>
+    {
+        self.aliases
+            .entry(origin.into())
+            .or_default()
+            .push(alias.into());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool { self.aliases.is_empty() }
+
+    /// The origins which may stand in for `origin`, in the order they should be tried.
+    pub fn aliases_for(&self, origin: &str) -> &[String] {
+        self.aliases.get(origin).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn missing_map_is_empty() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+
+        assert!(OriginAliases::load(Some(fs_root.path())).unwrap()
+                                                         .is_empty());
+    }
+
+    #[test]
+    fn load_alias_map() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let path = aliases_path(Some(fs_root.path()));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path,
+                  "[aliases]\ncore = [\"mycorp-core\", \"mirror-core\"]\n").unwrap();
+
+        let aliases = OriginAliases::load(Some(fs_root.path())).unwrap();
+
+        assert_eq!(OriginAliases::new().alias("core", "mycorp-core")
+                                       .alias("core", "mirror-core"),
+                   aliases);
+        assert_eq!(&["mycorp-core".to_string(), "mirror-core".to_string()],
+                   aliases.aliases_for("core"));
+        assert!(aliases.aliases_for("acme").is_empty());
+    }
+}
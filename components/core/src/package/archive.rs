@@ -12,15 +12,29 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{metadata::{MetaFile,
-                       PackageType},
+pub mod builder;
+pub mod sanitize;
+
+use self::sanitize::{Policy,
+                     Violation};
+use super::{metadata::{check_limits,
+                       MetaFile,
+                       PackageType,
+                       ReadLimits},
             Identifiable,
             PackageIdent,
+            PackageInstall,
             PackageTarget};
 use crate::{crypto::{artifact,
                      hash},
             error::{Error,
-                    Result}};
+                    Result},
+            fs::{check_free_space,
+                 pkg_install_path,
+                 pkg_root_path},
+            hooks::package::run_post_install_hook,
+            util::{context::OperationContext,
+                   CancellationToken}};
 use libarchive::{archive::{Entry,
                            ExtractOption,
                            ExtractOptions,
@@ -32,12 +46,20 @@ use libarchive::{archive::{Entry,
 use regex::Regex;
 use std::{collections::HashMap,
           error,
-          path::{Path,
+          fs,
+          io::{self,
+               Read},
+          path::{Component,
+                 Path,
                  PathBuf},
           result,
           str::{self,
                 FromStr},
-          string::ToString};
+          string::ToString,
+          sync::mpsc::Sender};
+
+/// The filesystem block size assumed when estimating the space an unpacked package takes.
+const BLOCK_SIZE: u64 = 4096;
 
 lazy_static::lazy_static! {
     static ref METAFILE_REGXS: HashMap<MetaFile, Regex> = {
@@ -180,6 +202,11 @@ lazy_static::lazy_static! {
         );
         map
     };
+    static ref FILES_REGX: Regex = Regex::new(&format!(
+        r"^/?hab/pkgs/([^/]+)/([^/]+)/([^/]+)/([^/]+)/{}$",
+        MetaFile::Files
+    ))
+    .unwrap();
 }
 
 type Metadata = HashMap<MetaFile, String>;
@@ -377,14 +404,105 @@ impl PackageArchive {
         artifact::verify(&self.path, cache_key_path)
     }
 
+    /// The package's `FILES` metafile exactly as it was archived, signature and all, or `None` if
+    /// it has none. Unlike the other metafiles it is read on its own, since it can be large.
+    ///
+    /// # Failures
+    ///
+    /// * If the archive cannot be read
+    /// * If the metafile is larger than `ReadLimits` allow
+    pub fn files_metafile(&self) -> Result<Option<Vec<u8>>> {
+        let tarball = match self.tarball()? {
+            Some(tarball) => tarball,
+            None => return Ok(None),
+        };
+        let limits = ReadLimits::current();
+        let mut tarball = tar::Archive::new(tarball);
+        for entry in tarball.entries()? {
+            let entry = entry?;
+            if !FILES_REGX.is_match(&String::from_utf8_lossy(&entry.path_bytes())) {
+                continue;
+            }
+            let mut data = Vec::new();
+            entry.take(limits.files_bytes.saturating_add(1))
+                 .read_to_end(&mut data)?;
+            check_limits(MetaFile::Files, &data, limits)?;
+            return Ok(Some(data));
+        }
+        Ok(None)
+    }
+
+    /// Checks every entry of the archive against `policy`, confining them to the package's own
+    /// install path, and returns the violations found. Nothing is written to disk, so this is
+    /// suitable for vetting an uploaded artifact.
+    ///
+    /// # Failures
+    ///
+    /// * If the archive cannot be read
+    /// * If the archive does not contain a fully qualified IDENT metafile
+    pub fn sanitize(&mut self, policy: &Policy) -> Result<Vec<Violation>> {
+        let prefix = self.install_prefix()?;
+        Ok(self.survey(&prefix, policy)?.0)
+    }
+
     /// Given a package name and a path to a file as an `&str`, unpack
     /// the package.
     ///
+    /// The archive is first checked with the default `Policy`, confining entries to the package's
+    /// own install path, and nothing is unpacked if it breaks it. Nothing is unpacked either if
+    /// there is not room for the package's `unpacked_size`. The package's post-install hook is
+    /// not run; see `unpack_and_run_hooks`.
+    ///
     /// # Failures
     ///
+    /// * If the archive does not contain a fully qualified IDENT metafile
+    /// * If the package contains unsafe entries
+    /// * If there is not enough free space to unpack the package
     /// * If the package cannot be unpacked
     pub fn unpack(&self, fs_root_path: Option<&Path>) -> Result<()> {
+        self.unpack_cancellable(fs_root_path, &CancellationToken::new())
+    }
+
+    /// Unpacks the package as `unpack` does, then runs its post-install hook with
+    /// `hooks::package::run_post_install_hook`. The hook runs on the machine itself, so it is
+    /// skipped when unpacking under any filesystem root other than `/`.
+    ///
+    /// # Failures
+    ///
+    /// * If the package cannot be unpacked, as for `unpack`
+    /// * If the package's post-install hook fails, leaving the package unpacked
+    pub fn unpack_and_run_hooks(&self, fs_root_path: Option<&Path>) -> Result<()> {
+        self.unpack(fs_root_path)?;
         let root = fs_root_path.unwrap_or_else(|| Path::new("/"));
+        if root != Path::new("/") {
+            debug!("Not running the post-install hook of {} unpacked under {}",
+                   self.path.display(),
+                   root.display());
+            return Ok(());
+        }
+        let ident = PackageArchive::new(self.path.clone()).qualified_ident()?;
+        let installed_path = pkg_install_path(&ident, Some(root));
+        let pkg_install = PackageInstall::new_from_parts(ident,
+                                                         root.to_path_buf(),
+                                                         pkg_root_path(Some(root)),
+                                                         installed_path);
+        run_post_install_hook(&pkg_install)?;
+        Ok(())
+    }
+
+    /// As `unpack`, but stopping with `Error::Cancelled` if `cancel` is cancelled before the
+    /// package's files start being written. Once they have, unpacking runs to completion rather
+    /// than leave a partial package behind.
+    ///
+    /// # Failures
+    ///
+    /// * If `cancel` is cancelled before unpacking starts
+    /// * If the package cannot be unpacked, as for `unpack`
+    pub fn unpack_cancellable(&self,
+                              fs_root_path: Option<&Path>,
+                              cancel: &CancellationToken)
+                              -> Result<()> {
+        let root = self.prepare_unpack(fs_root_path, cancel)?;
         let tar_reader = artifact::get_archive_reader(&self.path)?;
         let mut builder = reader::Builder::new();
         builder.support_format(ReadFormat::Gnutar)?;
@@ -394,6 +512,8 @@ impl PackageArchive {
         let mut extract_options = ExtractOptions::new();
         extract_options.add(ExtractOption::Time);
         extract_options.add(ExtractOption::Permissions);
+        extract_options.add(ExtractOption::SecureSymlinks);
+        extract_options.add(ExtractOption::SecureNoDotDot);
         writer.set_options(&extract_options)?;
         writer.set_standard_lookup()?;
         writer.write(&mut reader, Some(root.to_string_lossy().as_ref()))?;
@@ -401,6 +521,126 @@ impl PackageArchive {
         Ok(())
     }
 
+    /// Verifies the package's signature, then unpacks it, as a step of the operation `ctx`,
+    /// returning the signer's key name and the archive's checksum. Neither step starts once the
+    /// operation is cancelled or out of time.
+    ///
+    /// # Failures
+    ///
+    /// * If `ctx` is cancelled or its deadline passes before unpacking starts
+    /// * If the package cannot be verified or unpacked
+    pub fn verify_and_unpack<P: AsRef<Path>>(&self,
+                                             cache_key_path: &P,
+                                             fs_root_path: Option<&Path>,
+                                             ctx: &OperationContext)
+                                             -> Result<(String, String)> {
+        ctx.check()?;
+        let verified = self.verify(cache_key_path)?;
+        ctx.check()?;
+        self.unpack_cancellable(fs_root_path, ctx.cancellation())?;
+        Ok(verified)
+    }
+
+    /// Unpacks the package as `unpack` does, sending an `UnpackedEntry` to `events` as soon as
+    /// each entry is laid down, in archive order, so that a caller can follow the unpack and
+    /// record exactly which files the package installed. Events stop being sent, without error,
+    /// if the receiver is dropped, but the package is still unpacked in full.
+    ///
+    /// # Failures
+    ///
+    /// * If the package cannot be unpacked, as for `unpack`
+    /// * If an unpacked entry cannot be inspected
+    pub fn unpack_with_events(&self,
+                              fs_root_path: Option<&Path>,
+                              events: &Sender<UnpackedEntry>)
+                              -> Result<()> {
+        let root = self.prepare_unpack(fs_root_path, &CancellationToken::new())?;
+        if let Some(tarball) = self.tarball()? {
+            extract_with_events(tarball, &self.path, root, events)?;
+        }
+        Ok(())
+    }
+
+    /// An estimate of the disk space the package takes once unpacked: the size of each entry,
+    /// rounded up to a whole filesystem block, with a block for each directory and symlink.
+    ///
+    /// # Failures
+    ///
+    /// * If the archive cannot be read
+    pub fn unpacked_size(&self) -> Result<u64> {
+        let tar_reader = artifact::get_archive_reader(&self.path)?;
+        let mut builder = reader::Builder::new();
+        builder.support_format(ReadFormat::Gnutar)?;
+        builder.support_filter(ReadFilter::Xz)?;
+        let mut reader = builder.open_stream(tar_reader)?;
+        let mut size = 0;
+        while let Some(entry) = reader.next_header() {
+            size += footprint(entry.size().max(0) as u64);
+        }
+        Ok(size)
+    }
+
+    /// Makes the checks every unpack starts with, stopping if `cancel` is cancelled between them,
+    /// and returns the filesystem root to unpack into.
+    fn prepare_unpack<'a>(&self,
+                          fs_root_path: Option<&'a Path>,
+                          cancel: &CancellationToken)
+                          -> Result<&'a Path> {
+        cancel.check()?;
+        let ident = PackageArchive::new(self.path.clone()).qualified_ident()?;
+        let prefix = pkg_install_path(&ident, None::<&Path>);
+        let (violations, unpacked_size) = self.survey(&prefix, &Policy::default())?;
+        if !violations.is_empty() {
+            return Err(Error::UnsafeArchive(self.path.clone(), violations));
+        }
+        cancel.check()?;
+        let root = fs_root_path.unwrap_or_else(|| Path::new("/"));
+        check_free_space(pkg_root_path(Some(root)), unpacked_size)?;
+        cancel.check()?;
+        Ok(root)
+    }
+
+    /// The install path, relative to no filesystem root, which every entry must lie under.
+    fn install_prefix(&mut self) -> Result<PathBuf> {
+        Ok(pkg_install_path(&self.qualified_ident()?, None::<&Path>))
+    }
+
+    fn qualified_ident(&mut self) -> Result<PackageIdent> {
+        let ident = self.ident()?;
+        if !ident.fully_qualified() {
+            return Err(Error::FullyQualifiedPackageIdentRequired(ident.to_string()));
+        }
+        Ok(ident)
+    }
+
+    /// Checks every entry against `policy` and totals the `unpacked_size` in a single pass over
+    /// the archive, returning the violations found and the size.
+    fn survey(&self, prefix: &Path, policy: &Policy) -> Result<(Vec<Violation>, u64)> {
+        let tarball = match self.tarball()? {
+            Some(tarball) => tarball,
+            None => return Ok((vec![], 0)),
+        };
+        let mut size = 0;
+        let violations = sanitize::scan_with(tarball, prefix, policy, |header| {
+            size += footprint(header.entry_size().unwrap_or(0));
+        })?;
+        Ok((violations, size))
+    }
+
+    /// The archive's decompressed tarball as a byte stream, or `None` if there is none.
+    fn tarball(&self) -> Result<Option<BlockReader<reader::StreamReader>>> {
+        let tar_reader = artifact::get_archive_reader(&self.path)?;
+        let mut builder = reader::Builder::new();
+        builder.support_format(ReadFormat::Raw)?;
+        builder.support_filter(ReadFilter::Xz)?;
+        let mut reader = builder.open_stream(tar_reader)?;
+        // The raw format presents the decompressed tarball as a single entry
+        if reader.next_header().is_none() {
+            return Ok(None);
+        }
+        Ok(Some(BlockReader::new(reader)))
+    }
+
     fn read_deps(&mut self, file: MetaFile) -> Result<Vec<PackageIdent>> {
         let mut deps: Vec<PackageIdent> = vec![];
 
@@ -488,6 +728,131 @@ impl PackageArchive {
     }
 }
 
+/// The space an entry of `size` bytes takes on disk: whole blocks, and at least one.
+fn footprint(size: u64) -> u64 { ((size + BLOCK_SIZE - 1) / BLOCK_SIZE).max(1) * BLOCK_SIZE }
+
+/// Writes each entry of the tar stream `tarball` under `root`, sending an `UnpackedEntry` to
+/// `events` once it is written. The stream must already have been checked by `sanitize::scan`,
+/// but since `tar` offers no protection when `root` is the filesystem root, entries which climb
+/// upwards or would be written through a symlink are refused here too, with
+/// `Error::UnsafeArchive` naming `archive`.
+fn extract_with_events<R: Read>(tarball: R,
+                                archive: &Path,
+                                root: &Path,
+                                events: &Sender<UnpackedEntry>)
+                                -> Result<()> {
+    let mut tarball = tar::Archive::new(tarball);
+    tarball.set_preserve_permissions(true);
+    tarball.set_preserve_mtime(true);
+    let mut listening = true;
+    for entry in tarball.entries()? {
+        let mut entry = entry?;
+        let unsafe_entry = |v| Error::UnsafeArchive(archive.to_path_buf(), vec![v]);
+        let path =
+            destination(root, &String::from_utf8_lossy(&entry.path_bytes())).map_err(unsafe_entry)?;
+        if let Some(target) = entry.link_name_bytes() {
+            if entry.header().entry_type().is_hard_link() {
+                destination(root, &String::from_utf8_lossy(&target)).map_err(unsafe_entry)?;
+            }
+        }
+        // An existing symlink is replaced by the entry rather than written through.
+        if fs::symlink_metadata(&path).map(|m| m.file_type().is_symlink())
+                                      .unwrap_or(false)
+        {
+            fs::remove_file(&path)?;
+        }
+        entry.unpack_in(root)?;
+        if !listening {
+            continue;
+        }
+        // The entry's type is taken from what was written rather than from the archive, so that
+        // events describe the filesystem as it now is.
+        let kind = match fs::symlink_metadata(&path)?.file_type() {
+            t if t.is_dir() => UnpackedEntryKind::Directory,
+            t if t.is_symlink() => UnpackedEntryKind::Symlink,
+            t if t.is_file() => UnpackedEntryKind::File,
+            _ => UnpackedEntryKind::Other,
+        };
+        let size = entry.size();
+        listening = events.send(UnpackedEntry { path, size, kind }).is_ok();
+    }
+    Ok(())
+}
+
+/// Where the archive path `path` lies under `root`, refusing, as libarchive's secure extraction
+/// options do, a path which climbs upwards or passes through a symlink already on disk, including
+/// one the archive has just written. Only the part of the path below `root` is checked.
+fn destination(root: &Path, path: &str) -> result::Result<PathBuf, Violation> {
+    let mut dst = root.to_path_buf();
+    for component in Path::new(path).components() {
+        let through_symlink = fs::symlink_metadata(&dst).map(|m| m.file_type().is_symlink())
+                                                        .unwrap_or(false);
+        if through_symlink && dst != root {
+            return Err(Violation::ThroughSymlink(path.to_string()));
+        }
+        match component {
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => return Err(Violation::PathTraversal(path.to_string())),
+            Component::Normal(part) => dst.push(part),
+        }
+    }
+    Ok(dst)
+}
+
+/// An entry laid down by `PackageArchive::unpack_with_events`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnpackedEntry {
+    /// Where the entry was written, including the filesystem root.
+    pub path: PathBuf,
+    /// The size of the entry's content in the archive, which is zero for anything other than a
+    /// regular file.
+    pub size: u64,
+    pub kind: UnpackedEntryKind,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnpackedEntryKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+/// Adapts the blocks of an archive entry into a byte stream.
+struct BlockReader<R: Reader> {
+    reader: R,
+    block:  Vec<u8>,
+    offset: usize,
+}
+
+impl<R: Reader> BlockReader<R> {
+    fn new(reader: R) -> Self {
+        BlockReader { reader,
+                      block: Vec::new(),
+                      offset: 0 }
+    }
+}
+
+impl<R: Reader> Read for BlockReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset == self.block.len() {
+            match self.reader.read_block() {
+                Ok(Some(bytes)) => {
+                    self.block.clear();
+                    self.block.extend_from_slice(bytes);
+                    self.offset = 0;
+                }
+                Ok(None) => return Ok(0),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e.to_string())),
+            }
+        }
+        let len = buf.len().min(self.block.len() - self.offset);
+        buf[..len].copy_from_slice(&self.block[self.offset..self.offset + len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+
 pub trait FromArchive: Sized {
     type Error: error::Error;
 
@@ -539,4 +904,144 @@ mod test {
 
         assert_eq!(target::X86_64_LINUX, target);
     }
+
+    #[test]
+    fn unpacked_size_counts_whole_blocks() {
+        let hart = PackageArchive::new(fixtures().join("happyhumans-possums-8.1.\
+                                                        4-20160427165340-x86_64-linux.hart"));
+
+        let size = hart.unpacked_size().unwrap();
+
+        assert!(size > 0);
+        assert_eq!(0, size % BLOCK_SIZE);
+    }
+
+    #[test]
+    fn survey_totals_the_same_size_as_unpacked_size() {
+        let mut hart = PackageArchive::new(fixtures().join("happyhumans-possums-8.1.\
+                                                            4-20160427165340-x86_64-linux.hart"));
+        let prefix = hart.install_prefix().unwrap();
+
+        let (violations, size) = hart.survey(&prefix, &Policy::default()).unwrap();
+
+        assert!(violations.is_empty());
+        assert_eq!(hart.unpacked_size().unwrap(), size);
+    }
+
+    #[test]
+    fn extract_with_events_reports_each_entry_as_it_is_written() {
+        let fs_root = tempfile::Builder::new().prefix("fs-root")
+                                              .tempdir()
+                                              .unwrap();
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_mode(0o755);
+        header.set_size(0);
+        builder.append_data(&mut header, "hab/pkgs/core/", &[][..])
+               .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(5);
+        builder.append_data(&mut header, "hab/pkgs/core/IDENT", &b"ident"[..])
+               .unwrap();
+        let tarball = builder.into_inner().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        extract_with_events(&tarball[..], Path::new("test.hart"), fs_root.path(), &tx).unwrap();
+        drop(tx);
+        let entries: Vec<UnpackedEntry> = rx.iter().collect();
+
+        let core = fs_root.path().join("hab/pkgs/core");
+        assert_eq!(vec![UnpackedEntry { path: core.clone(),
+                                        size: 0,
+                                        kind: UnpackedEntryKind::Directory, },
+                        UnpackedEntry { path: core.join("IDENT"),
+                                        size: 5,
+                                        kind: UnpackedEntryKind::File, },],
+                   entries);
+        assert_eq!("ident", fs::read_to_string(core.join("IDENT")).unwrap());
+    }
+
+    #[test]
+    fn extract_with_events_refuses_entries_written_through_symlinks() {
+        let fs_root = tempfile::Builder::new().prefix("fs-root")
+                                              .tempdir()
+                                              .unwrap();
+        let outside = tempfile::Builder::new().prefix("outside")
+                                              .tempdir()
+                                              .unwrap();
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_link_name(outside.path()).unwrap();
+        header.set_mode(0o777);
+        header.set_size(0);
+        builder.append_data(&mut header, "hab/esc", &[][..])
+               .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(4);
+        builder.append_data(&mut header, "hab/esc/evil", &b"evil"[..])
+               .unwrap();
+        let tarball = builder.into_inner().unwrap();
+        let (tx, _rx) = std::sync::mpsc::channel();
+
+        match extract_with_events(&tarball[..], Path::new("test.hart"), fs_root.path(), &tx) {
+            Err(Error::UnsafeArchive(_, ref violations)) => {
+                assert_eq!(&vec![Violation::ThroughSymlink("hab/esc/evil".to_string())],
+                           violations)
+            }
+            other => panic!("Expected UnsafeArchive, got {:?}", other),
+        }
+        assert!(!outside.path().join("evil").exists());
+    }
+
+    #[test]
+    fn footprint_rounds_up_to_whole_blocks() {
+        assert_eq!(BLOCK_SIZE, footprint(0));
+        assert_eq!(BLOCK_SIZE, footprint(1));
+        assert_eq!(BLOCK_SIZE, footprint(BLOCK_SIZE));
+        assert_eq!(2 * BLOCK_SIZE, footprint(BLOCK_SIZE + 1));
+    }
+
+    #[test]
+    fn cancelled_unpack_writes_nothing() {
+        let fs_root = tempfile::Builder::new().prefix("fs-root")
+                                              .tempdir()
+                                              .unwrap();
+        let hart = PackageArchive::new(fixtures().join("happyhumans-possums-8.1.\
+                                                        4-20160427165340-x86_64-linux.hart"));
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        match hart.unpack_cancellable(Some(fs_root.path()), &cancel) {
+            Err(Error::Cancelled) => {}
+            other => panic!("Expected Cancelled, got {:?}", other),
+        }
+        assert!(!fs_root.path().join("hab").exists());
+    }
+
+    #[test]
+    fn unpack_with_events_reports_each_entry() {
+        let fs_root = tempfile::Builder::new().prefix("fs-root")
+                                              .tempdir()
+                                              .unwrap();
+        let hart = PackageArchive::new(fixtures().join("happyhumans-possums-8.1.\
+                                                        4-20160427165340-x86_64-linux.hart"));
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        hart.unpack_with_events(Some(fs_root.path()), &tx).unwrap();
+        drop(tx);
+        let entries: Vec<UnpackedEntry> = rx.iter().collect();
+
+        let ident_file = fs_root.path()
+                                .join("hab/pkgs/happyhumans/possums/8.1.4/20160427165340/IDENT");
+        let ident_entry = entries.iter()
+                                 .find(|e| e.path == ident_file)
+                                 .expect("IDENT metafile was reported");
+        assert_eq!(UnpackedEntryKind::File, ident_entry.kind);
+        assert!(ident_entry.size > 0);
+        assert!(entries.iter().all(|e| e.path.starts_with(fs_root.path())));
+    }
 }
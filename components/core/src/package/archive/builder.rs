@@ -0,0 +1,215 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Creation of the tarball which makes up the payload of a package archive.
+//!
+//! In deterministic mode every entry is given the same modification time and is owned by uid
+//! and gid 0, so that independent builders which produce the same tree also produce
+//! byte-identical payloads. Entries are always written in a fixed order, directories before
+//! their contents and siblings sorted by name, whatever order the filesystem lists them in.
+
+use std::{fs::{self,
+               File},
+          io::{self,
+               Write},
+          path::{Path,
+                 PathBuf}};
+
+use tar::{Builder,
+          EntryType,
+          Header,
+          HeaderMode};
+
+use crate::{env,
+            error::Result,
+            package::PackageInstall};
+
+/// The environment variable conventionally used to pin timestamps in reproducible builds. See
+/// https://reproducible-builds.org/specs/source-date-epoch/.
+pub const SOURCE_DATE_EPOCH_ENVVAR: &str = "SOURCE_DATE_EPOCH";
+
+/// Builds the tarball of a tree of installed files.
+///
+/// # Examples
+///
+/// ```no_run
+/// use habitat_core::package::archive::builder::ArchiveBuilder;
+///
+/// let tarball = ArchiveBuilder::new("/")
+///     .path("hab/pkgs/core/redis/4.0.14/20190319155852")
+///     .deterministic(true)
+///     .build(Vec::new())
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct ArchiveBuilder {
+    fs_root:       PathBuf,
+    paths:         Vec<PathBuf>,
+    deterministic: bool,
+    mtime:         Option<u64>,
+}
+
+impl ArchiveBuilder {
+    /// Creates a builder for paths under `fs_root`. Paths in the archive are relative to it.
+    pub fn new<P: Into<PathBuf>>(fs_root: P) -> Self {
+        ArchiveBuilder { fs_root:       fs_root.into(),
+                         paths:         Vec::new(),
+                         deterministic: false,
+                         mtime:         None, }
+    }
+
+    /// Creates a builder for the installed files of a package.
+    pub fn for_package(pkg_install: &PackageInstall) -> Self {
+        let fs_root = pkg_install.fs_root_path();
+        let rel = pkg_install.installed_path()
+                             .strip_prefix(fs_root)
+                             .expect("installed path is under the fs root");
+        Self::new(fs_root).path(rel)
+    }
+
+    /// Adds `path`, relative to the filesystem root, and everything beneath it.
+    pub fn path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.paths.push(path.into());
+        self
+    }
+
+    /// Whether to normalize the modification time and ownership of every entry.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    /// Sets the modification time, in seconds since the Unix epoch, given to every entry in
+    /// deterministic mode. When unset, `SOURCE_DATE_EPOCH` is used if it is set, otherwise 0.
+    pub fn mtime(mut self, mtime: u64) -> Self {
+        self.mtime = Some(mtime);
+        self
+    }
+
+    /// Writes the tarball to `writer`, returning the writer once the archive is finished.
+    ///
+    /// # Failures
+    ///
+    /// * A path cannot be read
+    /// * The tarball cannot be written
+    pub fn build<W: Write>(&self, writer: W) -> Result<W> {
+        let mtime = self.mtime.unwrap_or_else(source_date_epoch);
+        let mut builder = Builder::new(writer);
+        for path in &self.paths {
+            self.append(&mut builder, path, mtime)?;
+        }
+        Ok(builder.into_inner()?)
+    }
+
+    fn append<W: Write>(&self, builder: &mut Builder<W>, rel: &Path, mtime: u64) -> Result<()> {
+        let src = self.fs_root.join(rel);
+        let meta = fs::symlink_metadata(&src)?;
+        let mut header = Header::new_gnu();
+        header.set_metadata_in_mode(&meta, HeaderMode::Complete);
+        if self.deterministic {
+            header.set_mtime(mtime);
+            header.set_uid(0);
+            header.set_gid(0);
+        }
+
+        let file_type = meta.file_type();
+        if file_type.is_symlink() {
+            header.set_entry_type(EntryType::Symlink);
+            builder.append_link(&mut header, rel, fs::read_link(&src)?)?;
+        } else if file_type.is_dir() {
+            header.set_entry_type(EntryType::Directory);
+            builder.append_data(&mut header, rel, io::empty())?;
+            let mut names = fs::read_dir(&src)?.map(|entry| entry.map(|e| e.file_name()))
+                                               .collect::<io::Result<Vec<_>>>()?;
+            names.sort();
+            for name in names {
+                self.append(builder, &rel.join(name), mtime)?;
+            }
+        } else {
+            builder.append_data(&mut header, rel, File::open(&src)?)?;
+        }
+        Ok(())
+    }
+}
+
+fn source_date_epoch() -> u64 {
+    env::var(SOURCE_DATE_EPOCH_ENVVAR).ok()
+                                      .and_then(|epoch| epoch.parse().ok())
+                                      .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use tar::Archive;
+    use tempfile::Builder as TempBuilder;
+
+    const PKG: &str = "hab/pkgs/core/redis/4.0.14/20190319155852";
+
+    /// Lays down a small package tree, creating its files in the given order.
+    fn tree(files: &[&str]) -> tempfile::TempDir {
+        let fs_root = TempBuilder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg = fs_root.path().join(PKG);
+        fs::create_dir_all(pkg.join("bin")).unwrap();
+        for file in files {
+            fs::write(pkg.join(file), file).unwrap();
+        }
+        fs_root
+    }
+
+    #[test]
+    fn deterministic_archives_are_identical() {
+        let first = tree(&["IDENT", "bin/redis-server", "bin/redis-cli"]);
+        std::thread::sleep(Duration::from_millis(1100));
+        let second = tree(&["bin/redis-cli", "bin/redis-server", "IDENT"]);
+
+        let build = |fs_root: &Path| {
+            ArchiveBuilder::new(fs_root).path(PKG)
+                                        .deterministic(true)
+                                        .mtime(1_555_000_000)
+                                        .build(Vec::new())
+                                        .unwrap()
+        };
+
+        assert_eq!(build(first.path()), build(second.path()));
+    }
+
+    #[test]
+    fn entries_are_sorted_and_normalized() {
+        let fs_root = tree(&["bin/redis-server", "IDENT", "bin/redis-cli"]);
+        let tarball = ArchiveBuilder::new(fs_root.path()).path(PKG)
+                                                         .deterministic(true)
+                                                         .mtime(42)
+                                                         .build(Vec::new())
+                                                         .unwrap();
+
+        let mut archive = Archive::new(&tarball[..]);
+        let mut paths = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let entry = entry.unwrap();
+            assert_eq!(42, entry.header().mtime().unwrap());
+            assert_eq!(0, entry.header().uid().unwrap());
+            assert_eq!(0, entry.header().gid().unwrap());
+            paths.push(entry.path().unwrap().into_owned());
+        }
+        let expected: Vec<PathBuf> =
+            ["", "IDENT", "bin", "bin/redis-cli", "bin/redis-server"].iter()
+                                                                     .map(|p| {
+                                                                         Path::new(PKG).join(p)
+                                                                     })
+                                                                     .collect();
+        assert_eq!(expected, paths);
+    }
+}
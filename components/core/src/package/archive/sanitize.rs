@@ -0,0 +1,508 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks on the entries of a package archive, so that a hostile artifact is refused before
+//! anything in it is written to disk.
+//!
+//! Every entry must lie under a prefix, normally the package's own install path, except for the
+//! directories leading down to it. Hardlinks and symlinks must point under the same prefix, and
+//! nothing may be written through a symlink the archive itself created. Which of absolute paths,
+//! device nodes, and setuid or setgid files are acceptable is up to a `Policy`.
+
+use std::{collections::HashMap,
+          ffi::OsStr,
+          fmt,
+          io::Read,
+          path::{Component,
+                 Path,
+                 PathBuf}};
+
+use tar::{Archive,
+          Header};
+
+use crate::error::Result;
+
+const SETUID: u32 = 0o4000;
+const SETGID: u32 = 0o2000;
+
+/// How many symlinks a target may pass through before it is taken to loop, as for `ELOOP` on
+/// Linux.
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+/// What an archive may contain beyond plain files, directories, and symlinks under its prefix.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Policy {
+    /// Whether entries may have absolute paths, which are then taken relative to the filesystem
+    /// root.
+    pub allow_absolute_paths: bool,
+    /// Whether character and block device nodes may be created.
+    pub allow_devices:        bool,
+    /// Whether files may have the setuid or setgid bit set. Packages such as `sudo` need this.
+    pub allow_setuid:         bool,
+}
+
+impl Policy {
+    /// A policy which also refuses setuid and setgid files, for hosts which should never gain
+    /// them from a package.
+    pub fn strict() -> Self {
+        Policy { allow_setuid: false,
+                 ..Self::default() }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy { allow_absolute_paths: false,
+                 allow_devices:        false,
+                 allow_setuid:         true, }
+    }
+}
+
+/// A way in which an archive entry breaks the rules. Each carries the entry's path as it appears
+/// in the archive.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Violation {
+    /// The path has a `..` component.
+    PathTraversal(String),
+    /// The path is absolute and the policy does not allow it.
+    AbsolutePath(String),
+    /// The path is neither under the prefix nor one of the directories leading to it.
+    OutsidePrefix(String),
+    /// A hardlink points somewhere other than under the prefix.
+    HardlinkOutsidePrefix { path: String, target: String },
+    /// A symlink's target, resolved against the directory holding it, is not under the prefix.
+    SymlinkOutsidePrefix { path: String, target: String },
+    /// The path is, or lies beneath, a symlink created earlier in the archive.
+    ThroughSymlink(String),
+    /// The entry is a character or block device and the policy does not allow it.
+    DeviceNode(String),
+    /// The entry has the setuid or setgid bit set and the policy does not allow it.
+    Setuid(String),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Violation::PathTraversal(ref path) => write!(f, "{}: path traverses upwards", path),
+            Violation::AbsolutePath(ref path) => write!(f, "{}: path is absolute", path),
+            Violation::OutsidePrefix(ref path) => {
+                write!(f, "{}: path is outside the package prefix", path)
+            }
+            Violation::HardlinkOutsidePrefix { ref path,
+                                               ref target, } => {
+                write!(f,
+                       "{}: hardlink target {} is outside the package prefix",
+                       path, target)
+            }
+            Violation::SymlinkOutsidePrefix { ref path,
+                                              ref target, } => {
+                write!(f,
+                       "{}: symlink target {} is outside the package prefix",
+                       path, target)
+            }
+            Violation::ThroughSymlink(ref path) => {
+                write!(f, "{}: path passes through a symlink in the archive", path)
+            }
+            Violation::DeviceNode(ref path) => write!(f, "{}: entry is a device node", path),
+            Violation::Setuid(ref path) => write!(f, "{}: entry is setuid or setgid", path),
+        }
+    }
+}
+
+/// Reads the uncompressed tar stream `archive` and returns every violation of `policy` found in
+/// it, in archive order. Entries must lie under `prefix`, such as a package's install path.
+/// Archive paths are relative to the filesystem root, so any root of `prefix` is ignored. A
+/// symlink's target is resolved without touching the disk: a relative target against the
+/// directory holding the symlink, and an absolute one against the filesystem root, following any
+/// symlinks the archive creates along the way. Since a later symlink can change where an earlier
+/// one leads, symlink targets are only resolved once the whole archive has been read, and their
+/// violations come last. An archive with no violations is safe to unpack.
+///
+/// # Failures
+///
+/// * The stream cannot be read, or is not a tar archive
+pub fn scan<R: Read>(archive: R, prefix: &Path, policy: &Policy) -> Result<Vec<Violation>> {
+    scan_with(archive, prefix, policy, |_| {})
+}
+
+/// As `scan`, also passing the header of each entry to `visit` in archive order, so that a caller
+/// can gather whatever else it needs from the archive in the same pass.
+///
+/// # Failures
+///
+/// * The stream cannot be read, or is not a tar archive
+pub fn scan_with<R, F>(archive: R,
+                       prefix: &Path,
+                       policy: &Policy,
+                       mut visit: F)
+                       -> Result<Vec<Violation>>
+    where R: Read,
+          F: FnMut(&Header)
+{
+    let mut archive = Archive::new(archive);
+    let mut violations = Vec::new();
+    let mut symlinks = HashMap::new();
+    let mut symlink_order = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        let header = entry.header();
+        let entry_type = header.entry_type();
+        visit(header);
+
+        if let Some(violation) = check_path(&path, prefix, entry_type.is_dir(), policy) {
+            violations.push(violation);
+        }
+        let location = locate(Path::new(&path), &symlinks);
+        if through_symlink(Path::new(&path), &location, &symlinks) {
+            violations.push(Violation::ThroughSymlink(path.clone()));
+        }
+        if entry_type.is_hard_link() {
+            let target = entry.link_name_bytes()
+                              .map(|t| String::from_utf8_lossy(&t).into_owned())
+                              .unwrap_or_default();
+            let target_location = locate(Path::new(&target), &symlinks);
+            if through_symlink(Path::new(&target), &target_location, &symlinks)
+               || check_path(&target, prefix, false, policy).is_some()
+            {
+                violations.push(Violation::HardlinkOutsidePrefix { path: path.clone(),
+                                                                   target });
+            }
+        }
+        if entry_type.is_symlink() {
+            let target = entry.link_name_bytes()
+                              .map(|t| String::from_utf8_lossy(&t).into_owned())
+                              .unwrap_or_default();
+            symlinks.insert(location.clone(), PathBuf::from(&target));
+            symlink_order.push((path.clone(), location, target));
+        }
+        if !policy.allow_devices
+           && (entry_type.is_character_special() || entry_type.is_block_special())
+        {
+            violations.push(Violation::DeviceNode(path.clone()));
+        }
+        if !policy.allow_setuid && header.mode()? & (SETUID | SETGID) != 0 {
+            violations.push(Violation::Setuid(path));
+        }
+    }
+
+    let prefix = normalize(prefix);
+    for (path, location, target) in symlink_order {
+        let dir = location.parent().map(Path::to_path_buf).unwrap_or_default();
+        match resolve(dir, Path::new(&target), &symlinks, 0) {
+            Some(ref resolved) if resolved.starts_with(&prefix) => {}
+            _ => violations.push(Violation::SymlinkOutsidePrefix { path, target }),
+        }
+    }
+    Ok(violations)
+}
+
+/// Checks where the entry at `path` would be written. Directories may also be ancestors of
+/// `prefix`, since the archive has to create them on the way down.
+fn check_path(path: &str, prefix: &Path, is_dir: bool, policy: &Policy) -> Option<Violation> {
+    let mut absolute = false;
+    let mut parts = Vec::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => absolute = true,
+            Component::CurDir => {}
+            Component::ParentDir => return Some(Violation::PathTraversal(path.to_string())),
+            Component::Normal(part) => parts.push(part),
+        }
+    }
+    if absolute && !policy.allow_absolute_paths {
+        return Some(Violation::AbsolutePath(path.to_string()));
+    }
+
+    let prefix = normal_components(prefix);
+    let under_prefix = parts.len() >= prefix.len() && parts[..prefix.len()] == prefix[..];
+    let leads_to_prefix =
+        is_dir && parts.len() < prefix.len() && prefix[..parts.len()] == parts[..];
+    if under_prefix || leads_to_prefix {
+        None
+    } else {
+        Some(Violation::OutsidePrefix(path.to_string()))
+    }
+}
+
+/// Resolves `target` against the directory `dir`, following any of the archive's `symlinks` it
+/// passes through, or returns `None` if it climbs above the filesystem root or loops.
+fn resolve(dir: PathBuf,
+           target: &Path,
+           symlinks: &HashMap<PathBuf, PathBuf>,
+           depth: usize)
+           -> Option<PathBuf> {
+    if depth > MAX_SYMLINK_DEPTH {
+        return None;
+    }
+    let mut resolved = if target.has_root() {
+        PathBuf::new()
+    } else {
+        dir
+    };
+    for component in target.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => resolved = PathBuf::new(),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+            Component::Normal(part) => {
+                resolved.push(part);
+                if let Some(link) = symlinks.get(&resolved) {
+                    resolved.pop();
+                    resolved = resolve(resolved, link, symlinks, depth + 1)?;
+                }
+            }
+        }
+    }
+    Some(resolved)
+}
+
+/// Where the entry at `path` is written once any of the archive's `symlinks` among its parent
+/// directories are followed, relative to the filesystem root. This is the key under which a
+/// symlink is recorded. A path whose parent cannot be resolved is taken as it stands.
+fn locate(path: &Path, symlinks: &HashMap<PathBuf, PathBuf>) -> PathBuf {
+    let path = normalize(path);
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            resolve(PathBuf::new(), parent, symlinks, 0).map_or_else(|| path.clone(),
+                                                                     |dir| dir.join(name))
+        }
+        _ => path,
+    }
+}
+
+/// Whether the entry at `path`, written to `location`, is itself or lies beneath one of the
+/// archive's `symlinks`.
+fn through_symlink(path: &Path, location: &Path, symlinks: &HashMap<PathBuf, PathBuf>) -> bool {
+    normalize(path).ancestors()
+                   .chain(location.ancestors())
+                   .any(|a| symlinks.contains_key(a))
+}
+
+/// The path relative to the filesystem root.
+fn normalize(path: &Path) -> PathBuf { normal_components(path).into_iter().collect() }
+
+fn normal_components(path: &Path) -> Vec<&OsStr> {
+    path.components()
+        .filter_map(|c| {
+            match c {
+                Component::Normal(part) => Some(part),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tar::{Builder,
+              EntryType};
+
+    const PREFIX: &str = "hab/pkgs/core/redis/4.0.14/20190319155852";
+
+    /// Builds a tar stream from `(path, type, mode, link target)` entries. Paths are written
+    /// straight into the header, since `tar::Builder` would refuse the hostile ones.
+    fn tarball(entries: &[(&str, EntryType, u32, Option<&str>)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for &(path, entry_type, mode, link) in entries {
+            let mut header = Header::new_gnu();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            if let Some(link) = link {
+                header.as_old_mut().linkname[..link.len()].copy_from_slice(link.as_bytes());
+            }
+            header.set_entry_type(entry_type);
+            header.set_mode(mode);
+            header.set_size(0);
+            header.set_cksum();
+            builder.append(&header, &[][..]).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn scan_entries(entries: &[(&str, EntryType, u32, Option<&str>)],
+                    policy: &Policy)
+                    -> Vec<Violation> {
+        scan(&tarball(entries)[..], Path::new(PREFIX), policy).unwrap()
+    }
+
+    #[test]
+    fn well_formed_package_has_no_violations() {
+        let entries =
+            [("hab/", EntryType::Directory, 0o755, None),
+             ("hab/pkgs/core/", EntryType::Directory, 0o755, None),
+             ("./hab/pkgs/core/redis/4.0.14/20190319155852/", EntryType::Directory, 0o755, None),
+             ("hab/pkgs/core/redis/4.0.14/20190319155852/IDENT", EntryType::Regular, 0o644, None),
+             ("hab/pkgs/core/redis/4.0.14/20190319155852/bin/redis-cli",
+              EntryType::Link,
+              0o755,
+              Some("hab/pkgs/core/redis/4.0.14/20190319155852/bin/redis-server")),
+             ("hab/pkgs/core/redis/4.0.14/20190319155852/bin/redis",
+              EntryType::Symlink,
+              0o777,
+              Some("redis-server")),
+             ("hab/pkgs/core/redis/4.0.14/20190319155852/sbin/redis",
+              EntryType::Symlink,
+              0o777,
+              Some("/hab/pkgs/core/redis/4.0.14/20190319155852/bin/../bin/redis-server")),
+             ("hab/pkgs/core/redis/4.0.14/20190319155852/bin/sudo",
+              EntryType::Regular,
+              0o4755,
+              None)];
+
+        assert!(scan_entries(&entries, &Policy::default()).is_empty());
+    }
+
+    #[test]
+    fn hostile_paths_are_reported() {
+        let entries = [("hab/pkgs/core/redis/4.0.14/20190319155852/../../../../../etc/passwd",
+                        EntryType::Regular,
+                        0o644,
+                        None),
+                       ("/etc/cron.d/evil", EntryType::Regular, 0o644, None),
+                       ("hab/pkgs/core/other/IDENT", EntryType::Regular, 0o644, None),
+                       ("hab/pkgs/core", EntryType::Regular, 0o644, None),
+                       ("hab/pkgs/core/redis/4.0.14/20190319155852/shadow",
+                        EntryType::Link,
+                        0o644,
+                        Some("etc/shadow"))];
+
+        let violations = scan_entries(&entries, &Policy::default());
+
+        assert_eq!(vec![Violation::PathTraversal(entries[0].0.to_string()),
+                        Violation::AbsolutePath("/etc/cron.d/evil".to_string()),
+                        Violation::OutsidePrefix("hab/pkgs/core/other/IDENT".to_string()),
+                        Violation::OutsidePrefix("hab/pkgs/core".to_string()),
+                        Violation::HardlinkOutsidePrefix { path:   entries[4].0.to_string(),
+                                                           target: "etc/shadow".to_string(), },],
+                   violations);
+    }
+
+    #[test]
+    fn symlinks_resolving_outside_the_prefix_are_reported() {
+        let entries = [("hab/pkgs/core/redis/4.0.14/20190319155852/passwd",
+                        EntryType::Symlink,
+                        0o777,
+                        Some("/etc/passwd")),
+                       ("hab/pkgs/core/redis/4.0.14/20190319155852/bin/shadow",
+                        EntryType::Symlink,
+                        0o777,
+                        Some("../../../../../../../etc/shadow")),
+                       ("hab/pkgs/core/redis/4.0.14/20190319155852/bin/sibling",
+                        EntryType::Symlink,
+                        0o777,
+                        Some("../../20190101000000/bin/redis-server")),
+                       ("hab/pkgs/core/redis/4.0.14/20190319155852/bin/root",
+                        EntryType::Symlink,
+                        0o777,
+                        Some("../../../../../../../../../../etc/hosts"))];
+
+        let violations = scan_entries(&entries, &Policy::default());
+
+        let expected: Vec<_> =
+            entries.iter()
+                   .map(|&(path, _, _, target)| {
+                       Violation::SymlinkOutsidePrefix { path:   path.to_string(),
+                                                         target: target.unwrap().to_string(), }
+                   })
+                   .collect();
+        assert_eq!(expected, violations);
+    }
+
+    #[test]
+    fn entries_through_archive_symlinks_are_reported() {
+        let p = |rest: &str| format!("{}/{}", PREFIX, rest);
+        let (root, up, escape, evil) =
+            (p(""), p("up"), p("up/up/up/up/up/up/up/up/esc"), p("esc/cron.d/evil"));
+        let (lib, inner, passwd, up_passwd) =
+            (p("lib"), p("bin/inner"), p("bin/passwd"), p("up/passwd"));
+        let entries =
+            [(root.as_str(), EntryType::Directory, 0o755, None),
+             (up.as_str(), EntryType::Symlink, 0o777, Some(".")),
+             (escape.as_str(), EntryType::Symlink, 0o777, Some("../../../../../../../../etc")),
+             (evil.as_str(), EntryType::Regular, 0o644, None),
+             (lib.as_str(), EntryType::Symlink, 0o777, Some("up")),
+             (inner.as_str(), EntryType::Symlink, 0o777, Some("../lib/..")),
+             (passwd.as_str(), EntryType::Link, 0o644, Some(up_passwd.as_str()))];
+
+        let violations = scan_entries(&entries, &Policy::default());
+
+        assert_eq!(vec![Violation::ThroughSymlink(escape.clone()),
+                        Violation::ThroughSymlink(evil.clone()),
+                        Violation::HardlinkOutsidePrefix { path:   passwd.clone(),
+                                                           target: up_passwd.clone(), },
+                        Violation::SymlinkOutsidePrefix { path:   escape.clone(),
+                                                          target:
+                                                              "../../../../../../../../etc".to_string(), },
+                        Violation::SymlinkOutsidePrefix { path:   inner.clone(),
+                                                          target: "../lib/..".to_string(), },],
+                   violations);
+    }
+
+    #[test]
+    fn looping_symlinks_are_reported() {
+        let a = format!("{}/a", PREFIX);
+        let b = format!("{}/b", PREFIX);
+        let entries = [(a.as_str(), EntryType::Symlink, 0o777, Some("b/c")),
+                       (b.as_str(), EntryType::Symlink, 0o777, Some("a"))];
+
+        assert_eq!(vec![Violation::SymlinkOutsidePrefix { path:   a.clone(),
+                                                          target: "b/c".to_string(), },
+                        Violation::SymlinkOutsidePrefix { path:   b.clone(),
+                                                          target: "a".to_string(), },],
+                   scan_entries(&entries, &Policy::default()));
+    }
+
+    #[test]
+    fn scan_with_visits_every_header() {
+        let entries =
+            [("hab/", EntryType::Directory, 0o755, None),
+             ("hab/pkgs/core/redis/4.0.14/20190319155852/IDENT", EntryType::Regular, 0o644, None),
+             ("hab/pkgs/core/other/IDENT", EntryType::Regular, 0o644, None)];
+        let mut visited = Vec::new();
+
+        let violations = scan_with(&tarball(&entries)[..],
+                                   Path::new(PREFIX),
+                                   &Policy::default(),
+                                   |header| visited.push(header.entry_type())).unwrap();
+
+        assert_eq!(vec![EntryType::Directory, EntryType::Regular, EntryType::Regular],
+                   visited);
+        assert_eq!(vec![Violation::OutsidePrefix(entries[2].0.to_string())],
+                   violations);
+    }
+
+    #[test]
+    fn policy_controls_devices_setuid_and_absolute_paths() {
+        let entries =
+            [("/hab/pkgs/core/redis/4.0.14/20190319155852/dev/null", EntryType::Char, 0o666, None),
+             ("hab/pkgs/core/redis/4.0.14/20190319155852/bin/sudo",
+              EntryType::Regular,
+              0o4755,
+              None)];
+        let permissive = Policy { allow_absolute_paths: true,
+                                  allow_devices:        true,
+                                  allow_setuid:         true, };
+
+        assert!(scan_entries(&entries, &permissive).is_empty());
+        assert_eq!(vec![Violation::AbsolutePath(entries[0].0.to_string()),
+                        Violation::DeviceNode(entries[0].0.to_string()),
+                        Violation::Setuid(entries[1].0.to_string()),],
+                   scan_entries(&entries, &Policy::strict()));
+    }
+}
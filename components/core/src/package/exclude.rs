@@ -0,0 +1,153 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Ordering;
+
+use super::{ident::version_sort,
+            Identifiable,
+            PackageIdent};
+
+/// A denylist of installed packages which resolution should pass over, such as a known-bad
+/// release that is still on disk while a fix rolls out.
+///
+/// # Examples
+///
+/// ```
+/// use habitat_core::package::{exclude::Exclusions,
+///                             PackageIdent};
+/// use std::str::FromStr;
+///
+/// let bad_release = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+/// let nginx = PackageIdent::from_str("core/nginx").unwrap();
+/// let exclusions = Exclusions::new().release(bad_release)
+///                                   .versions(nginx, Some("1.15"), Some("1.16"));
+///
+/// let excluded = PackageIdent::from_str("core/nginx/1.15.6/20190115184823").unwrap();
+/// let allowed = PackageIdent::from_str("core/nginx/1.16.0/20190423000000").unwrap();
+/// assert!(exclusions.is_excluded(&excluded));
+/// assert!(!exclusions.is_excluded(&allowed));
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Exclusions(Vec<Exclusion>);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Exclusion {
+    /// Every package which satisfies the ident.
+    Ident(PackageIdent),
+    /// Versions of a package from `min`, inclusive, up to `max`, exclusive. A missing bound is
+    /// unbounded.
+    Versions {
+        ident: PackageIdent,
+        min:   Option<String>,
+        max:   Option<String>,
+    },
+}
+
+impl Exclusions {
+    pub fn new() -> Self { Self::default() }
+
+    /// Excludes every package which satisfies `ident`: a single release when it is fully
+    /// qualified, or every release of a version when it has one.
+    pub fn release(mut self, ident: PackageIdent) -> Self {
+        self.0.push(Exclusion::Ident(ident));
+        self
+    }
+
+    /// Excludes the versions of the package `ident` names from `min`, inclusive, up to `max`,
+    /// exclusive. A bound of `None` leaves that end of the range open. Only the origin and name
+    /// of `ident` are used.
+    pub fn versions(mut self, ident: PackageIdent, min: Option<&str>, max: Option<&str>) -> Self {
+        let ident = PackageIdent::new(ident.origin, ident.name, None, None);
+        self.0.push(Exclusion::Versions { ident,
+                                          min: min.map(str::to_string),
+                                          max: max.map(str::to_string) });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// Whether `ident` is excluded by any rule.
+    pub fn is_excluded(&self, ident: &PackageIdent) -> bool {
+        self.0.iter().any(|exclusion| exclusion.matches(ident))
+    }
+}
+
+impl Exclusion {
+    fn matches(&self, candidate: &PackageIdent) -> bool {
+        match *self {
+            Exclusion::Ident(ref ident) => candidate.satisfies(ident),
+            Exclusion::Versions { ref ident,
+                                  ref min,
+                                  ref max, } => {
+                let version = match candidate.version {
+                    Some(ref version) if candidate.satisfies(ident) => version,
+                    _ => return false,
+                };
+                min.as_ref()
+                   .map_or(true, |min| compare_versions(version, min) != Ordering::Less)
+                && max.as_ref()
+                      .map_or(true, |max| compare_versions(version, max) == Ordering::Less)
+            }
+        }
+    }
+}
+
+/// Compares versions as packages are ordered, falling back to comparing them as strings when
+/// either is not numeric.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    version_sort(a, b).unwrap_or_else(|_| a.cmp(b))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    fn ident(s: &str) -> PackageIdent { PackageIdent::from_str(s).unwrap() }
+
+    #[test]
+    fn empty_exclusions_exclude_nothing() {
+        let exclusions = Exclusions::new();
+
+        assert!(exclusions.is_empty());
+        assert!(!exclusions.is_excluded(&ident("core/redis/4.0.14/20190319155852")));
+    }
+
+    #[test]
+    fn release_exclusions() {
+        let exclusions = Exclusions::new().release(ident("core/redis/4.0.14/20190319155852"))
+                                          .release(ident("core/nginx/1.15.6"));
+
+        assert!(exclusions.is_excluded(&ident("core/redis/4.0.14/20190319155852")));
+        assert!(!exclusions.is_excluded(&ident("core/redis/4.0.14/20190401000000")));
+        assert!(exclusions.is_excluded(&ident("core/nginx/1.15.6/20190115184823")));
+        assert!(exclusions.is_excluded(&ident("core/nginx/1.15.6/20190201000000")));
+        assert!(!exclusions.is_excluded(&ident("core/nginx/1.15.7/20190201000000")));
+        assert!(!exclusions.is_excluded(&ident("acme/redis/4.0.14/20190319155852")));
+    }
+
+    #[test]
+    fn version_range_exclusions() {
+        let exclusions = Exclusions::new().versions(ident("core/redis"), Some("4.0"), Some("4.1"))
+                                          .versions(ident("core/nginx"), None, Some("1.15"));
+
+        assert!(exclusions.is_excluded(&ident("core/redis/4.0/20190319155852")));
+        assert!(exclusions.is_excluded(&ident("core/redis/4.0.14/20190319155852")));
+        assert!(!exclusions.is_excluded(&ident("core/redis/4.1.0/20190319155852")));
+        assert!(!exclusions.is_excluded(&ident("core/redis/3.2.12/20190319155852")));
+        assert!(exclusions.is_excluded(&ident("core/nginx/1.14.2/20190115184823")));
+        assert!(!exclusions.is_excluded(&ident("core/nginx/1.15.0/20190115184823")));
+        assert!(!exclusions.is_excluded(&ident("core/nginx")));
+    }
+}
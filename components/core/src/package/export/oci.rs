@@ -0,0 +1,370 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exporting an installed package and its transitive dependencies as an [OCI image layout].
+//!
+//! The resulting directory can be loaded by any OCI-compliant tool (such as `skopeo`, `podman`,
+//! or a registry client) without requiring a Docker daemon.
+//!
+//! [OCI image layout]: https://github.com/opencontainers/image-spec/blob/master/image-layout.md
+
+use std::{collections::BTreeMap,
+          fs::{self,
+               File},
+          io::{self,
+               Write},
+          path::{Path,
+                 PathBuf}};
+
+use ::crypto::{digest::Digest,
+               sha2::Sha256};
+use serde::Serialize;
+use serde_derive::{Deserialize,
+                   Serialize};
+use serde_json;
+use tar;
+use tempfile::NamedTempFile;
+
+use crate::{error::Result,
+            package::{install::{lossy_environment,
+                                EnvironmentOptions},
+                      PackageInstall,
+                      PackageTarget}};
+
+const OCI_LAYOUT_FILE: &str = "oci-layout";
+const OCI_LAYOUT_VERSION: &str = "1.0.0";
+const INDEX_FILE: &str = "index.json";
+const BLOBS_DIR: &str = "blobs/sha256";
+
+pub const MEDIA_TYPE_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const MEDIA_TYPE_CONFIG: &str = "application/vnd.oci.image.config.v1+json";
+pub const MEDIA_TYPE_LAYER: &str = "application/vnd.oci.image.layer.v1.tar";
+/// The annotation used in the layout's `index.json` to name the image.
+pub const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+/// How the packages of a closure are grouped into image layers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LayerStrategy {
+    /// One layer per package, dependencies first. Images sharing dependencies share those
+    /// layers, which keeps registry storage and pulls small.
+    PerPackage,
+    /// A single layer containing every package in the closure.
+    Single,
+}
+
+impl Default for LayerStrategy {
+    fn default() -> Self { LayerStrategy::PerPackage }
+}
+
+/// Options for `export`.
+#[derive(Clone, Debug, Default)]
+pub struct ExportOptions {
+    pub layers: LayerStrategy,
+    /// The name recorded for the image in the layout's `index.json`. Defaults to the package's
+    /// `<version>-<release>`.
+    pub tag:    Option<String>,
+    /// The default command of the image.
+    pub cmd:    Vec<String>,
+}
+
+/// A reference to a blob in the layout.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Descriptor {
+    #[serde(rename = "mediaType")]
+    pub media_type:  String,
+    pub digest:      String,
+    pub size:        u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+/// The descriptors of an exported image.
+#[derive(Clone, Debug)]
+pub struct OciImage {
+    pub manifest: Descriptor,
+    pub config:   Descriptor,
+    pub layers:   Vec<Descriptor>,
+}
+
+#[derive(Serialize)]
+struct ImageConfig {
+    architecture: &'static str,
+    os:           &'static str,
+    config:       ContainerConfig,
+    rootfs:       RootFs,
+}
+
+#[derive(Serialize)]
+struct ContainerConfig {
+    #[serde(rename = "Env")]
+    env: Vec<String>,
+    #[serde(rename = "Cmd", skip_serializing_if = "Vec::is_empty")]
+    cmd: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct RootFs {
+    #[serde(rename = "type")]
+    kind:     &'static str,
+    diff_ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest<'a> {
+    schema_version: u32,
+    media_type:     &'static str,
+    config:         &'a Descriptor,
+    layers:         &'a [Descriptor],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Index<'a> {
+    schema_version: u32,
+    manifests:      &'a [Descriptor],
+}
+
+/// Writes `pkg_install` and its transitive dependencies as an OCI image layout in `dst`, which
+/// is created if it does not exist.
+///
+/// The image's environment is taken from `PackageInstall::environment_for_command`.
+///
+/// # Failures
+///
+/// * A transitive dependency of the package is not installed
+/// * A package's metafiles cannot be read
+/// * The layout cannot be written to `dst`
+pub fn export(pkg_install: &PackageInstall, dst: &Path, opts: &ExportOptions) -> Result<OciImage> {
+    let blobs = dst.join(BLOBS_DIR);
+    fs::create_dir_all(&blobs)?;
+
+    let mut closure = pkg_install.load_tdeps()?;
+    closure.push(pkg_install.clone());
+    let groups: Vec<&[PackageInstall]> = match opts.layers {
+        LayerStrategy::PerPackage => closure.chunks(1).collect(),
+        LayerStrategy::Single => vec![&closure[..]],
+    };
+    let mut layers = Vec::with_capacity(groups.len());
+    for group in groups {
+        layers.push(write_layer(&blobs, group)?);
+    }
+
+    let (architecture, os) = platform(pkg_install.target()?);
+    let env = pkg_install.environment_for_command(EnvironmentOptions::default())?;
+    let mut env: Vec<String> = lossy_environment(env).into_iter()
+                                                     .map(|(k, v)| format!("{}={}", k, v))
+                                                     .collect();
+    env.sort();
+    let config = ImageConfig { architecture,
+                               os,
+                               config: ContainerConfig { env,
+                                                         cmd: opts.cmd.clone() },
+                               rootfs: RootFs { kind:     "layers",
+                                                diff_ids: layers.iter()
+                                                                .map(|l| l.digest.clone())
+                                                                .collect(), } };
+    let config = write_json_blob(&blobs, MEDIA_TYPE_CONFIG, &config)?;
+
+    let manifest = Manifest { schema_version: 2,
+                              media_type:     MEDIA_TYPE_MANIFEST,
+                              config:         &config,
+                              layers:         &layers, };
+    let mut manifest = write_json_blob(&blobs, MEDIA_TYPE_MANIFEST, &manifest)?;
+    let tag = match opts.tag {
+        Some(ref tag) => tag.clone(),
+        None => {
+            let ident = pkg_install.ident();
+            format!("{}-{}",
+                    ident.version
+                         .as_ref()
+                         .expect("installed ident has a version"),
+                    ident.release
+                         .as_ref()
+                         .expect("installed ident has a release"))
+        }
+    };
+    manifest.annotations
+            .insert(REF_NAME_ANNOTATION.to_string(), tag);
+
+    let index = Index { schema_version: 2,
+                        manifests:      &[manifest.clone()], };
+    serde_json::to_writer(File::create(dst.join(INDEX_FILE))?, &index)?;
+    fs::write(dst.join(OCI_LAYOUT_FILE),
+              format!("{{\"imageLayoutVersion\":\"{}\"}}", OCI_LAYOUT_VERSION))?;
+
+    Ok(OciImage { manifest,
+                  config,
+                  layers })
+}
+
+/// Returns the OCI architecture and operating system names for a package target.
+fn platform(target: PackageTarget) -> (&'static str, &'static str) {
+    let mut parts = target.iter();
+    let architecture = match parts.next() {
+        Some("x86_64") => "amd64",
+        Some("i386") => "386",
+        Some("aarch64") => "arm64",
+        Some("armv7") => "arm",
+        _ => "unknown",
+    };
+    let os = match parts.next() {
+        Some("linux") => "linux",
+        Some("windows") => "windows",
+        Some("darwin") => "darwin",
+        _ => "unknown",
+    };
+    (architecture, os)
+}
+
+/// Writes a layer containing the installed files of each package in `pkgs`.
+fn write_layer(blobs: &Path, pkgs: &[PackageInstall]) -> Result<Descriptor> {
+    let mut builder = tar::Builder::new(BlobWriter::new(blobs)?);
+    builder.follow_symlinks(false);
+    for pkg in pkgs {
+        let fs_root = pkg.fs_root_path();
+        let rel = pkg.installed_path()
+                     .strip_prefix(fs_root)
+                     .expect("installed path is under the fs root");
+        // Include each parent directory so that the layer carries their permissions, rather
+        // than relying on the runtime to create them.
+        let mut parents: Vec<&Path> = rel.ancestors().skip(1).collect();
+        parents.pop();
+        for parent in parents.into_iter().rev() {
+            builder.append_dir(parent, fs_root.join(parent))?;
+        }
+        builder.append_dir_all(rel, pkg.installed_path())?;
+    }
+    Ok(builder.into_inner()?.finish(blobs, MEDIA_TYPE_LAYER)?)
+}
+
+fn write_json_blob<T: Serialize>(blobs: &Path, media_type: &str, value: &T) -> Result<Descriptor> {
+    let mut writer = BlobWriter::new(blobs)?;
+    serde_json::to_writer(&mut writer, value)?;
+    Ok(writer.finish(blobs, media_type)?)
+}
+
+/// Writes a blob into a temporary file while computing its digest, so that it can be moved into
+/// place under its content address once complete.
+struct BlobWriter {
+    file:   NamedTempFile,
+    hasher: Sha256,
+    size:   u64,
+}
+
+impl BlobWriter {
+    fn new(blobs: &Path) -> io::Result<Self> {
+        Ok(BlobWriter { file:   NamedTempFile::new_in(blobs)?,
+                        hasher: Sha256::new(),
+                        size:   0, })
+    }
+
+    fn finish(mut self, blobs: &Path, media_type: &str) -> io::Result<Descriptor> {
+        self.file.flush()?;
+        let hex = self.hasher.result_str();
+        let path: PathBuf = blobs.join(&hex);
+        self.file.persist(&path).map_err(|e| e.error)?;
+        Ok(Descriptor { media_type:  media_type.to_string(),
+                        digest:      format!("sha256:{}", hex),
+                        size:        self.size,
+                        annotations: BTreeMap::new(), })
+    }
+}
+
+impl Write for BlobWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.hasher.input(&buf[..n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.file.flush() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::{metadata::MetaFile,
+                         test_support::testing_package_install};
+    use serde_json::Value;
+    use tempfile::Builder;
+
+    fn read_json(path: &Path) -> Value {
+        serde_json::from_reader(File::open(path).unwrap()).unwrap()
+    }
+
+    fn blob_path(dst: &Path, digest: &str) -> PathBuf {
+        dst.join(BLOBS_DIR)
+           .join(digest.trim_start_matches("sha256:"))
+    }
+
+    #[test]
+    fn export_writes_layer_per_package() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let dep = testing_package_install("acme/dep", fs_root.path());
+        let pkg_install = testing_package_install("acme/app", fs_root.path());
+        fs::write(pkg_install.installed_path()
+                             .join(MetaFile::TDeps.to_string()),
+                  format!("{}\n", dep.ident())).unwrap();
+        let dst = Builder::new().prefix("oci").tempdir().unwrap();
+
+        let image = export(&pkg_install, dst.path(), &ExportOptions::default()).unwrap();
+
+        assert_eq!(2, image.layers.len());
+        assert_eq!(r#"{"imageLayoutVersion":"1.0.0"}"#,
+                   fs::read_to_string(dst.path().join(OCI_LAYOUT_FILE)).unwrap());
+        let index = read_json(&dst.path().join(INDEX_FILE));
+        assert_eq!(Value::from(image.manifest.digest.clone()),
+                   index["manifests"][0]["digest"]);
+        let manifest = read_json(&blob_path(dst.path(), &image.manifest.digest));
+        assert_eq!(Value::from(MEDIA_TYPE_MANIFEST), manifest["mediaType"]);
+        assert_eq!(2, manifest["layers"].as_array().unwrap().len());
+        for layer in &image.layers {
+            let path = blob_path(dst.path(), &layer.digest);
+            assert_eq!(layer.size, fs::metadata(&path).unwrap().len());
+        }
+
+        let mut archive =
+            tar::Archive::new(File::open(blob_path(dst.path(), &image.layers[0].digest)).unwrap());
+        let dep_ident = format!("{}", dep.ident());
+        let dep_path = format!("hab/pkgs/{}/IDENT", dep_ident);
+        assert!(archive.entries()
+                       .unwrap()
+                       .any(|e| e.unwrap().path().unwrap() == Path::new(&dep_path)));
+    }
+
+    #[test]
+    fn export_with_single_layer() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let dep = testing_package_install("acme/dep", fs_root.path());
+        let pkg_install = testing_package_install("acme/app", fs_root.path());
+        fs::write(pkg_install.installed_path()
+                             .join(MetaFile::TDeps.to_string()),
+                  format!("{}\n", dep.ident())).unwrap();
+        let dst = Builder::new().prefix("oci").tempdir().unwrap();
+        let opts = ExportOptions { layers: LayerStrategy::Single,
+                                   tag: Some("latest".to_string()),
+                                   ..Default::default() };
+
+        let image = export(&pkg_install, dst.path(), &opts).unwrap();
+
+        assert_eq!(1, image.layers.len());
+        assert_eq!(Some(&"latest".to_string()),
+                   image.manifest.annotations.get(REF_NAME_ANNOTATION));
+        let config = read_json(&blob_path(dst.path(), &image.config.digest));
+        assert_eq!(Value::from(image.layers[0].digest.clone()),
+                   config["rootfs"]["diff_ids"][0]);
+    }
+}
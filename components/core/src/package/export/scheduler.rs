@@ -0,0 +1,437 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Describing a package's service to container schedulers.
+//!
+//! `ServiceMetadata` gathers what a scheduler needs to know about a service from the package's
+//! metadata, checking it as it goes: the ports it exposes, how its binds are satisfied, and
+//! whether it has a health check. It then renders a Kubernetes `Container` or a Nomad `Task`
+//! (in the JSON form of Nomad's jobs API), both of which run the package's exported image and
+//! pass the service's group and binds to the Supervisor in it.
+//!
+//! A service with a `health-check` hook is probed through the Supervisor's HTTP gateway, which
+//! reports the result of the hook's last run. The probe decides readiness rather than liveness,
+//! since restarting a failing service is the Supervisor's job rather than the scheduler's.
+
+use std::{collections::BTreeMap,
+          time::Duration};
+
+use serde_derive::Serialize;
+
+use crate::{error::{Error,
+                    Result},
+            gateway,
+            package::{PackageIdent,
+                      PackageInstall},
+            service::{HealthCheckInterval,
+                      ServiceBind,
+                      ServiceGroup}};
+
+/// The health check hook's path, relative to the package's installed path.
+pub const HEALTH_CHECK_HOOK: &str = "hooks/health-check";
+/// The name given to the Supervisor's HTTP gateway port.
+pub const GATEWAY_PORT_NAME: &str = "hab-gateway";
+
+/// What a scheduler needs to know about a package's service.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServiceMetadata {
+    pub ident:         PackageIdent,
+    pub service_group: ServiceGroup,
+    /// The ports the package exposes, in the order it lists them.
+    pub ports:         Vec<u16>,
+    /// The service's binds, sorted by name.
+    pub binds:         Vec<ServiceBind>,
+    /// How often to run the health check, if the package has one.
+    pub health_check:  Option<Duration>,
+}
+
+impl ServiceMetadata {
+    /// Describes `pkg_install` run in `group`, with its binds satisfied by `binds`.
+    ///
+    /// # Failures
+    ///
+    /// * The package's metadata cannot be read
+    /// * The package exposes something which is not a port
+    /// * A required bind is not satisfied, or a bind is given which the package does not declare or
+    ///   is given more than once
+    /// * The package has a health check and `interval` is less than a second
+    pub fn new(pkg_install: &PackageInstall,
+               group: &str,
+               binds: &[ServiceBind],
+               interval: HealthCheckInterval)
+               -> Result<Self> {
+        let ident = pkg_install.ident().clone();
+        let invalid = |reason: String| Error::InvalidServiceMetadata(ident.to_string(), reason);
+        let service_group = ServiceGroup::new(None, &ident.name, group, None)?;
+
+        let mut ports = Vec::new();
+        for port in pkg_install.exposes()?.iter().filter(|p| !p.is_empty()) {
+            match port.parse::<u16>() {
+                Ok(port) if port > 0 => ports.push(port),
+                _ => return Err(invalid(format!("exposes '{}', which is not a port", port))),
+            }
+        }
+
+        let required = pkg_install.binds()?;
+        let optional = pkg_install.binds_optional()?;
+        let mut binds = binds.to_vec();
+        binds.sort_by(|a, b| a.name().cmp(b.name()));
+        for pair in binds.windows(2) {
+            if pair[0].name() == pair[1].name() {
+                return Err(invalid(format!("bind '{}' is given more than once", pair[0].name())));
+            }
+        }
+        for bind in &binds {
+            if !required.iter()
+                        .chain(optional.iter())
+                        .any(|b| b.service == bind.name())
+            {
+                return Err(invalid(format!("has no bind named '{}'", bind.name())));
+            }
+        }
+        for bind in &required {
+            if !binds.iter().any(|b| b.name() == bind.service) {
+                return Err(invalid(format!("requires bind '{}', which is not given",
+                                           bind.service)));
+            }
+        }
+
+        let health_check = if pkg_install.installed_path()
+                                         .join(HEALTH_CHECK_HOOK)
+                                         .is_file()
+        {
+            let interval = Duration::from(interval);
+            if interval < Duration::from_secs(1) {
+                return Err(invalid("health check interval is less than a second".to_string()));
+            }
+            Some(interval)
+        } else {
+            None
+        };
+
+        Ok(ServiceMetadata { ident,
+                             service_group,
+                             ports,
+                             binds,
+                             health_check })
+    }
+
+    /// The arguments to pass to the Supervisor running the package in its exported image.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec!["--group".to_string(),
+                            self.service_group.group().to_string()];
+        for bind in &self.binds {
+            args.push("--bind".to_string());
+            args.push(bind.to_string());
+        }
+        args
+    }
+
+    /// The package's name, made fit to name a container or task: lowercase, with anything but
+    /// letters and digits replaced by `-`.
+    pub fn name(&self) -> String {
+        self.ident
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '-'
+                }
+            })
+            .collect()
+    }
+
+    /// The gateway path reporting the service's health.
+    pub fn health_path(&self) -> String {
+        format!("/services/{}/{}/health",
+                self.service_group.service(),
+                self.service_group.group())
+    }
+
+    /// A Kubernetes container running the service from `image`.
+    pub fn container(&self, image: &str) -> Container {
+        let mut ports: Vec<ContainerPort> =
+            self.ports
+                .iter()
+                .map(|&port| ContainerPort::tcp(port_name(port), port))
+                .collect();
+        ports.push(ContainerPort::tcp(GATEWAY_PORT_NAME.to_string(), gateway::DEFAULT_PORT));
+        let readiness_probe = self.health_check.map(|interval| self.probe(interval));
+        Container { name: self.name(),
+                    image: image.to_string(),
+                    args: self.args(),
+                    ports,
+                    readiness_probe }
+    }
+
+    /// A Nomad task running the service from `image` with the Docker driver.
+    pub fn nomad_task(&self, image: &str) -> NomadTask {
+        let mut port_map = BTreeMap::new();
+        for &port in &self.ports {
+            port_map.insert(port_name(port), port);
+        }
+        port_map.insert(GATEWAY_PORT_NAME.to_string(), gateway::DEFAULT_PORT);
+        let dynamic_ports = port_map.keys()
+                                    .map(|label| NomadPort { label: label.clone(), })
+                                    .collect();
+        let checks = self.health_check
+                         .map(|interval| self.check(interval))
+                         .into_iter()
+                         .collect();
+        NomadTask { name:      self.name(),
+                    driver:    "docker",
+                    config:    NomadDockerConfig { image:    image.to_string(),
+                                                   args:     self.args(),
+                                                   port_map: vec![port_map], },
+                    services:  vec![NomadService { name: self.name(),
+                                                   port_label: GATEWAY_PORT_NAME.to_string(),
+                                                   checks }],
+                    resources: NomadResources { networks: vec![NomadNetwork { dynamic_ports }], }, }
+    }
+
+    fn probe(&self, interval: Duration) -> Probe {
+        let http_get = HttpGetAction { path: self.health_path(),
+                                       port: GATEWAY_PORT_NAME.to_string(), };
+        Probe { http_get,
+                period_seconds: interval.as_secs(),
+                timeout_seconds: interval.as_secs() }
+    }
+
+    fn check(&self, interval: Duration) -> NomadCheck {
+        NomadCheck { name:       format!("{}-health", self.name()),
+                     kind:       "http",
+                     path:       self.health_path(),
+                     port_label: GATEWAY_PORT_NAME.to_string(),
+                     interval:   duration_nanos(interval),
+                     timeout:    duration_nanos(interval), }
+    }
+}
+
+/// The name given to an exposed port, which fits Kubernetes' limit of 15 characters.
+fn port_name(port: u16) -> String { format!("port-{}", port) }
+
+fn duration_nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + u64::from(duration.subsec_nanos())
+}
+
+/// A Kubernetes `Container`, with the fields `ServiceMetadata` fills in.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Container {
+    pub name:            String,
+    pub image:           String,
+    pub args:            Vec<String>,
+    pub ports:           Vec<ContainerPort>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub readiness_probe: Option<Probe>,
+}
+
+/// A Kubernetes `ContainerPort`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContainerPort {
+    pub name:           String,
+    pub container_port: u16,
+    pub protocol:       &'static str,
+}
+
+impl ContainerPort {
+    fn tcp(name: String, container_port: u16) -> Self {
+        ContainerPort { name,
+                        container_port,
+                        protocol: "TCP" }
+    }
+}
+
+/// A Kubernetes `Probe` making an HTTP request.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Probe {
+    pub http_get:        HttpGetAction,
+    pub period_seconds:  u64,
+    pub timeout_seconds: u64,
+}
+
+/// A Kubernetes `HTTPGetAction`, whose port is named.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct HttpGetAction {
+    pub path: String,
+    pub port: String,
+}
+
+/// A Nomad `Task`. Durations are in nanoseconds, as Nomad's API expects.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NomadTask {
+    pub name:      String,
+    pub driver:    &'static str,
+    pub config:    NomadDockerConfig,
+    pub services:  Vec<NomadService>,
+    pub resources: NomadResources,
+}
+
+/// The Docker driver's configuration of a Nomad task, whose keys Nomad leaves as they are.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct NomadDockerConfig {
+    pub image:    String,
+    pub args:     Vec<String>,
+    /// Maps port labels to the ports they reach in the container.
+    pub port_map: Vec<BTreeMap<String, u16>>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NomadService {
+    pub name:       String,
+    pub port_label: String,
+    pub checks:     Vec<NomadCheck>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NomadCheck {
+    pub name:       String,
+    #[serde(rename = "Type")]
+    pub kind:       &'static str,
+    pub path:       String,
+    pub port_label: String,
+    pub interval:   u64,
+    pub timeout:    u64,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NomadResources {
+    pub networks: Vec<NomadNetwork>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NomadNetwork {
+    pub dynamic_ports: Vec<NomadPort>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct NomadPort {
+    pub label: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::{metadata::MetaFile,
+                         test_support::testing_package_install};
+    use serde_json::{self,
+                     json};
+    use std::{fs,
+              str::FromStr};
+    use tempfile::Builder;
+
+    fn redis(fs_root: &std::path::Path) -> PackageInstall {
+        let pkg_install = testing_package_install("core/redis_ha/4.0.14/20190319155852", fs_root);
+        let path = pkg_install.installed_path();
+        fs::write(path.join(MetaFile::Exposes.to_string()), "6379\n").unwrap();
+        fs::write(path.join(MetaFile::Binds.to_string()), "leader=port\n").unwrap();
+        fs::write(path.join(MetaFile::BindsOptional.to_string()),
+                  "backup=port\n").unwrap();
+        fs::create_dir_all(path.join("hooks")).unwrap();
+        fs::write(path.join(HEALTH_CHECK_HOOK), "#!/bin/sh\n").unwrap();
+        pkg_install
+    }
+
+    fn bind(s: &str) -> ServiceBind { ServiceBind::from_str(s).unwrap() }
+
+    #[test]
+    fn binds_are_checked() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = redis(fs_root.path());
+        let interval = HealthCheckInterval::default();
+        let new =
+            |binds: &[ServiceBind]| ServiceMetadata::new(&pkg_install, "prod", binds, interval);
+
+        assert!(new(&[]).is_err());
+        assert!(new(&[bind("leader:redis.prod"), bind("cache:memcached.prod")]).is_err());
+        assert!(new(&[bind("leader:redis.prod"), bind("leader:redis.dev")]).is_err());
+        assert!(new(&[bind("leader:redis.prod"), bind("backup:redis.dr")]).is_ok());
+    }
+
+    #[test]
+    fn exposes_must_be_ports() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = redis(fs_root.path());
+        fs::write(pkg_install.installed_path()
+                             .join(MetaFile::Exposes.to_string()),
+                  "6379 http\n").unwrap();
+
+        assert!(ServiceMetadata::new(&pkg_install,
+                                     "prod",
+                                     &[bind("leader:redis.prod")],
+                                     HealthCheckInterval::default()).is_err());
+    }
+
+    #[test]
+    fn kubernetes_container_and_nomad_task() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = redis(fs_root.path());
+        let metadata = ServiceMetadata::new(&pkg_install,
+                                            "prod",
+                                            &[bind("leader:redis.prod")],
+                                            Duration::from_secs(10).into()).unwrap();
+
+        assert_eq!(json!({ "name": "redis-ha",
+                           "image": "core/redis_ha:latest",
+                           "args": ["--group", "prod", "--bind", "leader:redis.prod"],
+                           "ports": [
+                               { "name": "port-6379", "containerPort": 6379, "protocol": "TCP" },
+                               { "name": "hab-gateway", "containerPort": 9631, "protocol": "TCP" }
+                           ],
+                           "readinessProbe": {
+                               "httpGet": { "path": "/services/redis_ha/prod/health",
+                                            "port": "hab-gateway" },
+                               "periodSeconds": 10,
+                               "timeoutSeconds": 10
+                           } }),
+                   serde_json::to_value(metadata.container("core/redis_ha:latest")).unwrap());
+
+        let task = serde_json::to_value(metadata.nomad_task("core/redis_ha:latest")).unwrap();
+        assert_eq!(json!([{ "hab-gateway": 9631, "port-6379": 6379 }]),
+                   task["Config"]["port_map"]);
+        assert_eq!(json!({ "Name": "redis-ha-health",
+                           "Type": "http",
+                           "Path": "/services/redis_ha/prod/health",
+                           "PortLabel": "hab-gateway",
+                           "Interval": 10_000_000_000u64,
+                           "Timeout": 10_000_000_000u64 }),
+                   task["Services"][0]["Checks"][0]);
+        assert_eq!(json!([{ "Label": "hab-gateway" }, { "Label": "port-6379" }]),
+                   task["Resources"]["Networks"][0]["DynamicPorts"]);
+    }
+
+    #[test]
+    fn no_probe_without_a_health_check() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = redis(fs_root.path());
+        fs::remove_file(pkg_install.installed_path().join(HEALTH_CHECK_HOOK)).unwrap();
+        let metadata = ServiceMetadata::new(&pkg_install,
+                                            "prod",
+                                            &[bind("leader:redis.prod")],
+                                            HealthCheckInterval::default()).unwrap();
+
+        assert!(metadata.container("redis").readiness_probe.is_none());
+        assert!(metadata.nomad_task("redis").services[0].checks.is_empty());
+    }
+}
@@ -0,0 +1,352 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exporting an installed package and its transitive dependencies as a plain tarball or
+//! directory tree, for machines where Habitat itself cannot be installed.
+//!
+//! The bundle holds each package of the closure under `hab/pkgs`, as it is installed, alongside
+//! an entrypoint script, `run` (or `run.ps1` for Windows packages), which sets the package's
+//! runtime environment and then runs the bundle's command, or whatever command it is given.
+//! Paths in the environment are rewritten relative to the script's own location, so the bundle
+//! can be unpacked anywhere. Programs built against Habitat's libraries usually name their
+//! interpreter by absolute path though, so the bundle should be unpacked at `/` to run those.
+
+use std::{collections::BTreeMap,
+          fs::{self,
+               File},
+          path::{Path,
+                 PathBuf,
+                 MAIN_SEPARATOR}};
+
+use ::tar::{Builder,
+            Header};
+use time;
+
+use crate::{error::Result,
+            fs::{copy_tree,
+                 pkg_root_path},
+            package::{install::{lossy_environment,
+                                EnvironmentOptions},
+                      PackageIdent,
+                      PackageInstall}};
+
+/// The entrypoint script of a bundle of packages for Unix-like systems.
+pub const ENTRYPOINT: &str = "run";
+/// The entrypoint script of a bundle of Windows packages.
+pub const WINDOWS_ENTRYPOINT: &str = "run.ps1";
+
+/// Options for `export_tarball` and `export_dir`.
+#[derive(Clone, Debug, Default)]
+pub struct BundleOptions {
+    /// The command the entrypoint runs, with any arguments it is given appended. When empty,
+    /// the entrypoint runs the command it is given.
+    pub cmd: Vec<String>,
+}
+
+/// What was exported.
+#[derive(Clone, Debug)]
+pub struct Bundle {
+    /// The packages in the bundle, dependencies first.
+    pub packages:   Vec<PackageIdent>,
+    /// The entrypoint script, relative to the bundle's root.
+    pub entrypoint: PathBuf,
+}
+
+/// Writes `pkg_install`, its transitive dependencies, and an entrypoint script to the tarball
+/// `dst`, replacing any file already there.
+///
+/// # Failures
+///
+/// * A transitive dependency of the package is not installed
+/// * A package's metafiles cannot be read
+/// * The tarball cannot be written
+pub fn export_tarball(pkg_install: &PackageInstall,
+                      dst: &Path,
+                      opts: &BundleOptions)
+                      -> Result<Bundle> {
+    let closure = closure(pkg_install)?;
+    let (entrypoint, script) = entrypoint(pkg_install, opts)?;
+
+    let mut builder = Builder::new(File::create(dst)?);
+    builder.follow_symlinks(false);
+    for pkg in &closure {
+        let fs_root = pkg.fs_root_path();
+        let rel = relative_path(pkg);
+        let mut parents: Vec<&Path> = rel.ancestors().skip(1).collect();
+        parents.pop();
+        for parent in parents.into_iter().rev() {
+            builder.append_dir(parent, fs_root.join(parent))?;
+        }
+        builder.append_dir_all(rel, pkg.installed_path())?;
+    }
+    let mut header = Header::new_gnu();
+    header.set_size(script.len() as u64);
+    header.set_mode(0o755);
+    header.set_mtime(time::now_utc().to_timespec().sec as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, &entrypoint, script.as_bytes())?;
+    builder.into_inner()?.sync_all()?;
+
+    Ok(Bundle { packages: closure.iter().map(|p| p.ident().clone()).collect(),
+                entrypoint })
+}
+
+/// Copies `pkg_install`, its transitive dependencies, and an entrypoint script into the
+/// directory `dst`, which is created if it does not exist.
+///
+/// # Failures
+///
+/// * A transitive dependency of the package is not installed
+/// * A package's metafiles cannot be read
+/// * The files cannot be copied into `dst`
+pub fn export_dir(pkg_install: &PackageInstall,
+                  dst: &Path,
+                  opts: &BundleOptions)
+                  -> Result<Bundle> {
+    let closure = closure(pkg_install)?;
+    let (entrypoint, script) = entrypoint(pkg_install, opts)?;
+
+    for pkg in &closure {
+        copy_tree(pkg.installed_path(), dst.join(relative_path(pkg)))?;
+    }
+    let script_path = dst.join(&entrypoint);
+    fs::write(&script_path, script)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(Bundle { packages: closure.iter().map(|p| p.ident().clone()).collect(),
+                entrypoint })
+}
+
+fn closure(pkg_install: &PackageInstall) -> Result<Vec<PackageInstall>> {
+    let mut closure = pkg_install.load_tdeps()?;
+    closure.push(pkg_install.clone());
+    Ok(closure)
+}
+
+/// The package's installed path relative to its filesystem root.
+fn relative_path(pkg: &PackageInstall) -> &Path {
+    pkg.installed_path()
+       .strip_prefix(pkg.fs_root_path())
+       .expect("installed path is under the fs root")
+}
+
+/// Returns the name and content of the entrypoint script for `pkg_install`.
+fn entrypoint(pkg_install: &PackageInstall, opts: &BundleOptions) -> Result<(PathBuf, String)> {
+    let env = pkg_install.environment_for_command(EnvironmentOptions { append_caller_path:
+                                                                           false,
+                                                                       ..Default::default() })?;
+    let env: BTreeMap<String, String> = lossy_environment(env).into_iter().collect();
+    // Paths into the package root may or may not be joined to the filesystem root
+    let mut pkg_roots: Vec<String> =
+        vec![pkg_root_path(Some(pkg_install.fs_root_path())),
+             pkg_root_path(None::<&Path>)].into_iter()
+                                          .map(|root| {
+                                              format!("{}{}", root.display(), MAIN_SEPARATOR)
+                                          })
+                                          .collect();
+    pkg_roots.dedup();
+    let windows = pkg_install.target()?.iter().nth(1) == Some("windows");
+    if windows {
+        Ok((PathBuf::from(WINDOWS_ENTRYPOINT),
+            powershell_script(pkg_install.ident(), &env, &opts.cmd, &pkg_roots)))
+    } else {
+        Ok((PathBuf::from(ENTRYPOINT), sh_script(pkg_install.ident(), &env, &opts.cmd, &pkg_roots)))
+    }
+}
+
+fn sh_script(ident: &PackageIdent,
+             env: &BTreeMap<String, String>,
+             cmd: &[String],
+             pkg_roots: &[String])
+             -> String {
+    let quote = |value: &str| relocate(value, pkg_roots, "\"${ROOT}/hab/pkgs/\"", sh_quote);
+    let mut script = format!("#!/bin/sh\n# Runs {} with its runtime environment.\nset \
+                              -e\nROOT=\"$(cd \"$(dirname \"$0\")\" && pwd)\"\n",
+                             ident);
+    for (key, value) in env {
+        if key == "PATH" {
+            script.push_str(&format!("export PATH={}\"${{PATH:+:$PATH}}\"\n", quote(value)));
+        } else {
+            script.push_str(&format!("export {}={}\n", key, quote(value)));
+        }
+    }
+    script.push_str("exec");
+    for arg in cmd {
+        script.push(' ');
+        script.push_str(&quote(arg));
+    }
+    script.push_str(" \"$@\"\n");
+    script
+}
+
+fn powershell_script(ident: &PackageIdent,
+                     env: &BTreeMap<String, String>,
+                     cmd: &[String],
+                     pkg_roots: &[String])
+                     -> String {
+    let quote = |value: &str| {
+        format!("\"{}\"",
+                relocate(value, pkg_roots, "$PSScriptRoot\\hab\\pkgs\\", ps_escape))
+    };
+    let mut script = format!("# Runs {} with its runtime environment.\n$ErrorActionPreference = \
+                              \"Stop\"\n",
+                             ident);
+    for (key, value) in env {
+        if key == "PATH" {
+            script.push_str(&format!("$env:PATH = {} + \";$env:PATH\"\n", quote(value)));
+        } else {
+            script.push_str(&format!("${{env:{}}} = {}\n", key, quote(value)));
+        }
+    }
+    let mut args: Vec<String> = cmd.iter().map(|a| quote(a)).collect();
+    if args.is_empty() {
+        script.push_str("$cmd, $rest = $args\n");
+        args = vec!["$cmd".to_string(), "@rest".to_string()];
+    } else {
+        args.push("@args".to_string());
+    }
+    script.push_str(&format!("& {}\nexit $LASTEXITCODE\n", args.join(" ")));
+    script
+}
+
+/// Quotes `value` with `quote`, replacing each occurrence of any of `pkg_roots` with `root`,
+/// which refers to the package root within the bundle and is inserted as it is.
+fn relocate(value: &str, pkg_roots: &[String], root: &str, quote: fn(&str) -> String) -> String {
+    if value.is_empty() {
+        return quote(value);
+    }
+    let mut quoted = String::new();
+    let mut rest = value;
+    loop {
+        let next = pkg_roots.iter()
+                            .filter_map(|r| rest.find(r.as_str()).map(|i| (i, r.len())))
+                            .min();
+        match next {
+            Some((i, len)) => {
+                if i > 0 {
+                    quoted.push_str(&quote(&rest[..i]));
+                }
+                quoted.push_str(root);
+                rest = &rest[i + len..];
+            }
+            None => {
+                if !rest.is_empty() {
+                    quoted.push_str(&quote(rest));
+                }
+                return quoted;
+            }
+        }
+    }
+}
+
+fn sh_quote(value: &str) -> String { format!("'{}'", value.replace('\'', "'\\''")) }
+
+/// Escapes `value` for use within a double-quoted PowerShell string.
+fn ps_escape(value: &str) -> String {
+    value.chars().fold(String::new(), |mut escaped, c| {
+                     if c == '`' || c == '"' || c == '$' {
+                         escaped.push('`');
+                     }
+                     escaped.push(c);
+                     escaped
+                 })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::{metadata::MetaFile,
+                         test_support::testing_package_install};
+    use tempfile::Builder as TempBuilder;
+
+    fn installed_closure(fs_root: &Path) -> PackageInstall {
+        let dep = testing_package_install("acme/dep", fs_root);
+        let pkg_install = testing_package_install("acme/app", fs_root);
+        fs::write(pkg_install.installed_path()
+                             .join(MetaFile::TDeps.to_string()),
+                  format!("{}\n", dep.ident())).unwrap();
+        let bin = Path::new("/").join(relative_path(&pkg_install)).join("bin");
+        fs::write(pkg_install.installed_path()
+                             .join(MetaFile::RuntimePath.to_string()),
+                  bin.to_string_lossy().as_bytes()).unwrap();
+        fs::write(pkg_install.installed_path()
+                             .join(MetaFile::RuntimeEnvironment.to_string()),
+                  "GREETING=it's here\n").unwrap();
+        pkg_install
+    }
+
+    #[test]
+    fn quoting_relocates_paths() {
+        let roots = vec!["/fs/hab/pkgs/".to_string(), "/hab/pkgs/".to_string()];
+        assert_eq!("'/usr/bin:'\"${ROOT}/hab/pkgs/\"'a/b/bin:'\"${ROOT}/hab/pkgs/\"'c/d/bin'",
+                   relocate("/usr/bin:/fs/hab/pkgs/a/b/bin:/hab/pkgs/c/d/bin",
+                            &roots,
+                            "\"${ROOT}/hab/pkgs/\"",
+                            sh_quote));
+        assert_eq!("'it'\\''s'", sh_quote("it's"));
+        assert_eq!("`$HOME `\"quoted`\"", ps_escape("$HOME \"quoted\""));
+        assert_eq!("''", relocate("", &roots, "R", sh_quote));
+    }
+
+    #[test]
+    fn export_tarball_includes_closure_and_entrypoint() {
+        let fs_root = TempBuilder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = installed_closure(fs_root.path());
+        let dst = fs_root.path().join("bundle.tar");
+
+        let bundle = export_tarball(&pkg_install, &dst, &BundleOptions::default()).unwrap();
+
+        assert_eq!(2, bundle.packages.len());
+        assert_eq!(pkg_install.ident(), &bundle.packages[1]);
+        let mut archive = ::tar::Archive::new(File::open(&dst).unwrap());
+        let paths: Vec<PathBuf> = archive.entries()
+                                         .unwrap()
+                                         .map(|e| e.unwrap().path().unwrap().into_owned())
+                                         .collect();
+        assert!(paths.contains(&bundle.entrypoint));
+        assert!(paths.contains(&relative_path(&pkg_install).join("IDENT")));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn exported_dir_runs_with_environment() {
+        let fs_root = TempBuilder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = installed_closure(fs_root.path());
+        let bin = pkg_install.installed_path().join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        fs::write(bin.join("greet"), "#!/bin/sh\necho \"$GREETING\" \"$@\"\n").unwrap();
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(bin.join("greet"), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let dst = TempBuilder::new().prefix("bundle").tempdir().unwrap();
+        let opts = BundleOptions { cmd: vec!["greet".to_string()], };
+
+        let bundle = export_dir(&pkg_install, dst.path(), &opts).unwrap();
+
+        let script = fs::read_to_string(dst.path().join(&bundle.entrypoint)).unwrap();
+        assert!(!script.contains(&fs_root.path().to_string_lossy().into_owned()));
+        // The package's own copy is removed, so only the bundle's can run
+        fs::remove_dir_all(fs_root.path().join("hab")).unwrap();
+        let output = std::process::Command::new(dst.path().join(&bundle.entrypoint)).arg("world")
+                                                                                    .output()
+                                                                                    .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!("it's here world\n", String::from_utf8_lossy(&output.stdout));
+    }
+}
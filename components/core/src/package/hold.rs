@@ -0,0 +1,200 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Holds, which pin an installed package to its current release so that automated updaters
+//! leave it alone.
+//!
+//! A hold is a marker file under the package path, at `.holds/ORIGIN/NAME`, containing the fully
+//! qualified ident the package is held at.
+
+use std::{fs,
+          io,
+          path::{Path,
+                 PathBuf},
+          str::FromStr};
+
+use super::{Identifiable,
+            PackageIdent,
+            PackageInstall};
+use crate::{error::{Error,
+                    Result},
+            fs::{atomic_write,
+                 pkg_root_path}};
+
+/// The name of the directory under the package path where holds are kept.
+pub const HOLDS_DIR: &str = ".holds";
+
+/// Returns the directory holds are kept in.
+pub fn holds_path(fs_root_path: Option<&Path>) -> PathBuf {
+    pkg_root_path(fs_root_path).join(HOLDS_DIR)
+}
+
+/// Holds the installed package which `ident` resolves to, returning the fully qualified ident it
+/// is held at. Any existing hold on the package is replaced.
+///
+/// # Failures
+///
+/// * No installed package satisfies `ident`
+/// * The hold cannot be written
+pub fn hold(ident: &PackageIdent, fs_root_path: Option<&Path>) -> Result<PackageIdent> {
+    let held = PackageInstall::load(ident, fs_root_path)?.ident().clone();
+    let path = hold_path(&held, fs_root_path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    atomic_write(&path, format!("{}\n", held))?;
+    Ok(held)
+}
+
+/// Removes any hold on the package `ident` names, returning whether there was one. Only the
+/// origin and name of `ident` are used.
+pub fn unhold(ident: &PackageIdent, fs_root_path: Option<&Path>) -> Result<bool> {
+    match fs::remove_file(hold_path(ident, fs_root_path)) {
+        Ok(()) => Ok(true),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns the ident the package `ident` names is held at, if it is held. Only the origin and
+/// name of `ident` are used.
+///
+/// # Failures
+///
+/// * The hold cannot be read, or does not contain a fully qualified ident
+pub fn held(ident: &PackageIdent, fs_root_path: Option<&Path>) -> Result<Option<PackageIdent>> {
+    let content = match fs::read_to_string(hold_path(ident, fs_root_path)) {
+        Ok(content) => content,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let held = PackageIdent::from_str(content.trim())?;
+    if !held.fully_qualified() {
+        return Err(Error::FullyQualifiedPackageIdentRequired(held.to_string()));
+    }
+    Ok(Some(held))
+}
+
+/// Returns every package which is held, at the ident it is held at.
+pub fn all_holds(fs_root_path: Option<&Path>) -> Result<Vec<PackageIdent>> {
+    let mut holds = Vec::new();
+    let origins = match fs::read_dir(holds_path(fs_root_path)) {
+        Ok(origins) => origins,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(holds),
+        Err(e) => return Err(e.into()),
+    };
+    for origin in origins {
+        let origin = origin?;
+        if !origin.file_type()?.is_dir() {
+            continue;
+        }
+        for name in fs::read_dir(origin.path())? {
+            let name = name?;
+            let ident = PackageIdent::new(origin.file_name().to_string_lossy(),
+                                          name.file_name().to_string_lossy(),
+                                          None,
+                                          None);
+            if let Some(held) = held(&ident, fs_root_path)? {
+                holds.push(held);
+            }
+        }
+    }
+    holds.sort();
+    Ok(holds)
+}
+
+/// Checks whether moving the package `candidate` names to `candidate` is allowed by its hold,
+/// which it is if the package is not held or `candidate` is the release it is held at.
+///
+/// # Failures
+///
+/// * The package is held at another release
+/// * The hold cannot be read
+pub fn check(candidate: &PackageIdent, fs_root_path: Option<&Path>) -> Result<()> {
+    match held(candidate, fs_root_path)? {
+        Some(ref held) if !held.satisfies(candidate) => {
+            Err(Error::PackageHeld(held.to_string(), candidate.to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn hold_path(ident: &PackageIdent, fs_root_path: Option<&Path>) -> PathBuf {
+    holds_path(fs_root_path).join(&ident.origin)
+                            .join(&ident.name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::{all_packages,
+                         test_support::testing_package_install};
+    use tempfile::Builder;
+
+    fn ident(s: &str) -> PackageIdent { PackageIdent::from_str(s).unwrap() }
+
+    #[test]
+    fn hold_pins_latest_installed_release() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        testing_package_install("core/redis/4.0.14/20190319155852", fs_root.path());
+        testing_package_install("core/redis/5.0.3/20190401000000", fs_root.path());
+        let root = Some(fs_root.path());
+
+        assert_eq!(None, held(&ident("core/redis"), root).unwrap());
+        let pinned = hold(&ident("core/redis"), root).unwrap();
+
+        assert_eq!(ident("core/redis/5.0.3/20190401000000"), pinned);
+        assert_eq!(Some(pinned.clone()),
+                   held(&ident("core/redis"), root).unwrap());
+        assert_eq!(vec![pinned], all_holds(root).unwrap());
+        // Holds are not mistaken for installed packages
+        assert_eq!(2, all_packages(&pkg_root_path(root)).unwrap().len());
+    }
+
+    #[test]
+    fn check_rejects_moving_held_packages() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        testing_package_install("core/redis/4.0.14/20190319155852", fs_root.path());
+        let root = Some(fs_root.path());
+        let upgrade = ident("core/redis/5.0.3/20190401000000");
+
+        check(&upgrade, root).unwrap();
+        hold(&ident("core/redis"), root).unwrap();
+
+        match check(&upgrade, root) {
+            Err(Error::PackageHeld(held, candidate)) => {
+                assert_eq!("core/redis/4.0.14/20190319155852", held);
+                assert_eq!(upgrade.to_string(), candidate);
+            }
+            other => panic!("Expected PackageHeld, got {:?}", other),
+        }
+        check(&ident("core/redis/4.0.14/20190319155852"), root).unwrap();
+        check(&ident("core/nginx/1.15.6/20190115184823"), root).unwrap();
+
+        assert!(unhold(&ident("core/redis"), root).unwrap());
+        assert!(!unhold(&ident("core/redis"), root).unwrap());
+        check(&upgrade, root).unwrap();
+        assert!(all_holds(root).unwrap().is_empty());
+    }
+
+    #[test]
+    fn hold_requires_an_installed_package() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+
+        match hold(&ident("core/redis"), Some(fs_root.path())) {
+            Err(Error::PackageNotFound(_)) => {}
+            other => panic!("Expected PackageNotFound, got {:?}", other),
+        }
+    }
+}
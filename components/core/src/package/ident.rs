@@ -25,9 +25,14 @@ use std::{borrow::Cow,
           result,
           str::FromStr};
 
+/// What precedes the identifier in the path to an installed package.
+const PKG_PATH_MARKER: &str = "hab/pkgs/";
+
 lazy_static::lazy_static! {
     static ref ORIGIN_NAME_RE: Regex =
         Regex::new(r"\A[a-z0-9][a-z0-9_-]*\z").expect("Unable to compile regex");
+    static ref NAME_RE: Regex = Regex::new(r"\A[A-Za-z0-9_-]+\z").expect("Unable to compile regex");
+    static ref RELEASE_RE: Regex = Regex::new(r"\A[0-9]{14}\z").expect("Unable to compile regex");
 }
 
 #[derive(Deserialize, Serialize, Eq, PartialEq, Debug, Clone, Hash)]
@@ -88,6 +93,119 @@ impl PackageIdent {
                        release: release.map(Into::into), }
     }
 
+    /// Parses an identifier as a person might type it, normalizing the common variations
+    /// rather than rejecting them:
+    ///
+    /// * surrounding whitespace, and whitespace around the slashes
+    /// * leading and trailing slashes, as in `core/redis/`
+    /// * the version given after a `:` or `@`, as in `core/redis:4.0.14` or `core/redis@4.0.14`
+    /// * the path to an installed package, as in `/hab/pkgs/core/redis/4.0.14/20190115013919` or
+    ///   `C:\hab\pkgs\core\redis`
+    ///
+    /// What remains must be a valid identifier.
+    ///
+    /// # Failures
+    ///
+    /// * The input cannot be made into an identifier, which fails with
+    ///   `Error::MalformedPackageIdent` saying what is wrong and, where there is an obvious fix,
+    ///   suggesting it
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use habitat_core::package::PackageIdent;
+    ///
+    /// let ident = PackageIdent::parse_lenient(" /hab/pkgs/core/redis/4.0.14/ ").unwrap();
+    /// assert_eq!("core/redis/4.0.14", ident.to_string());
+    ///
+    /// let ident = PackageIdent::parse_lenient("core/redis@4.0.14").unwrap();
+    /// assert_eq!("core/redis/4.0.14", ident.to_string());
+    /// ```
+    pub fn parse_lenient(input: &str) -> Result<Self> {
+        let malformed = |problem: &str, suggestion: Option<String>| {
+            Error::MalformedPackageIdent(IdentDiagnostic { input: input.to_string(),
+                                                           problem: problem.to_string(),
+                                                           suggestion })
+        };
+
+        let mut value = input.trim().replace('\\', "/");
+        if let Some(i) = value.find(PKG_PATH_MARKER) {
+            value = value[i + PKG_PATH_MARKER.len()..].to_string();
+        }
+        let mut parts: Vec<String> = value.trim_matches('/')
+                                          .split('/')
+                                          .map(|p| p.trim().to_string())
+                                          .collect();
+        if parts.len() == 1 && parts[0].is_empty() {
+            return Err(malformed("no identifier was given", None));
+        }
+        if parts.iter().any(String::is_empty) {
+            let fixed: Vec<String> = parts.into_iter().filter(|p| !p.is_empty()).collect();
+            return Err(malformed("it has an empty component", Some(fixed.join("/"))));
+        }
+        if parts.len() >= 2 && parts.len() <= 3 {
+            if let Some(i) = parts[1].find(&[':', '@'][..]) {
+                let version = parts[1][i + 1..].trim().to_string();
+                parts[1] = parts[1][..i].trim().to_string();
+                parts.insert(2, version);
+            }
+        }
+        match parts.len() {
+            1 => {
+                return Err(malformed("it is missing an origin",
+                                     Some(format!("core/{}", parts[0]))));
+            }
+            2..=4 => {}
+            _ => {
+                return Err(malformed("it has more than four components",
+                                     Some(parts[..4].join("/"))));
+            }
+        }
+
+        let suggestion = |parts: &[String]| Some(parts.join("/"));
+        if !is_valid_origin_name(&parts[0]) {
+            let lower = parts[0].to_lowercase();
+            let fix = if is_valid_origin_name(&lower) {
+                let mut fixed = parts.clone();
+                fixed[0] = lower;
+                suggestion(&fixed)
+            } else {
+                None
+            };
+            return Err(malformed("the origin may only contain lowercase letters, \
+                                  digits, '_', and '-'",
+                                 fix));
+        }
+        if !NAME_RE.is_match(&parts[1]) {
+            let mut fixed = parts.clone();
+            fixed[1] = parts[1].chars()
+                               .map(|c| {
+                                   if c.is_ascii_alphanumeric() || c == '_' {
+                                       c
+                                   } else {
+                                       '-'
+                                   }
+                               })
+                               .collect();
+            return Err(malformed("the name may only contain letters, digits, \
+                                  '_', and '-'",
+                                 suggestion(&fixed)));
+        }
+        if parts.len() >= 3 && parts[2].is_empty() {
+            return Err(malformed("the version is empty", suggestion(&parts[..2])));
+        }
+        if parts.len() == 4 && !RELEASE_RE.is_match(&parts[3]) {
+            return Err(malformed("the release must be a timestamp such as \
+                                  20190115013919",
+                                 suggestion(&parts[..3])));
+        }
+
+        let mut parts = parts.into_iter();
+        let origin = parts.next().expect("origin");
+        let name = parts.next().expect("name");
+        Ok(PackageIdent::new(origin, name, parts.next(), parts.next()))
+    }
+
     pub fn archive_name(&self) -> Result<String> {
         self.archive_name_impl(PackageTarget::active_target())
     }
@@ -156,6 +274,16 @@ impl PackageIdent {
         }
     }
 
+    /// Returns when the package was built, in seconds since the Unix epoch, as given by its
+    /// release timestamp, or `None` if the identifier has no release or it is not a timestamp.
+    /// Not available on wasm32, where the `time` crate is not built.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn release_time(&self) -> Option<i64> {
+        let release = self.release.as_ref()?;
+        time::strptime(release, "%Y%m%d%H%M%S").ok()
+                                               .map(|tm| tm.to_timespec().sec)
+    }
+
     fn archive_name_impl(&self, target: PackageTarget) -> Result<String> {
         if self.fully_qualified() {
             Ok(format!("{}-{}-{}-{}-{}.hart",
@@ -333,7 +461,7 @@ pub struct Iter<'a> {
     // The ident to iterate over
     ident: &'a PackageIdent,
     // The position through the ident
-    pos: usize,
+    pos:   usize,
 }
 
 impl<'a> Iterator for Iter<'a> {
@@ -451,6 +579,27 @@ fn split_version(version: &str) -> Result<(Vec<&str>, Option<String>)> {
     Ok((version_parts, extension))
 }
 
+/// Why `PackageIdent::parse_lenient` could not make an identifier of its input.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IdentDiagnostic {
+    pub input:      String,
+    pub problem:    String,
+    /// An identifier the input was probably meant to be.
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for IdentDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,
+               "Invalid package identifier {:?}: {}",
+               self.input, self.problem)?;
+        if let Some(ref suggestion) = self.suggestion {
+            write!(f, ". Did you mean {}?", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
 /// Is the string a valid origin name?
 pub fn is_valid_origin_name(origin: &str) -> bool {
     origin.chars().count() <= 255 && ORIGIN_NAME_RE.is_match(origin)
@@ -463,6 +612,57 @@ mod tests {
     use std::cmp::{Ordering,
                    PartialOrd};
 
+    #[test]
+    fn parse_lenient_normalizes_sloppy_input() {
+        for &(input, expected) in
+            &[("core/redis", "core/redis"),
+              ("  core/redis/ \n", "core/redis"),
+              ("core / redis", "core/redis"),
+              ("core/redis:4.0.14", "core/redis/4.0.14"),
+              ("core/redis@4.0.14/20190115013919", "core/redis/4.0.14/20190115013919"),
+              ("/hab/pkgs/core/redis/4.0.14/20190115013919/", "core/redis/4.0.14/20190115013919"),
+              ("C:\\hab\\pkgs\\core\\redis", "core/redis")]
+        {
+            assert_eq!(expected,
+                       PackageIdent::parse_lenient(input).unwrap().to_string(),
+                       "parsing {:?}",
+                       input);
+        }
+    }
+
+    #[test]
+    fn parse_lenient_suggests_fixes() {
+        for &(input, suggestion) in &[("redis", Some("core/redis")),
+                                      ("Core/redis", Some("core/redis")),
+                                      ("core//redis", Some("core/redis")),
+                                      ("core/my redis", Some("core/my-redis")),
+                                      ("core/redis/4.0.14/latest", Some("core/redis/4.0.14")),
+                                      ("core/redis/4.0.14/20190115013919/bin",
+                                       Some("core/redis/4.0.14/20190115013919")),
+                                      ("   ", None)]
+        {
+            match PackageIdent::parse_lenient(input) {
+                Err(Error::MalformedPackageIdent(diagnostic)) => {
+                    assert_eq!(input, diagnostic.input);
+                    assert_eq!(suggestion.map(str::to_string),
+                               diagnostic.suggestion,
+                               "parsing {:?}",
+                               input);
+                }
+                other => panic!("expected a diagnostic for {:?}, got {:?}", input, other),
+            }
+        }
+    }
+
+    #[test]
+    fn release_time_parses_timestamps() {
+        let ident = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+        assert_eq!(Some(1_553_011_132), ident.release_time());
+        assert_eq!(None,
+                   PackageIdent::from_str("core/redis/4.0.14").unwrap()
+                                                              .release_time());
+    }
+
     #[test]
     fn package_ident_partial_eq() {
         let a = PackageIdent::new("ty".to_string(),
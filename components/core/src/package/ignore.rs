@@ -0,0 +1,169 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Ignore files, which hide parts of a package root from the package tree walkers.
+//!
+//! A package root may contain a `.habignore` file listing one pattern per line. A pattern
+//! without a `/` matches origins, and one with a `/` matches `ORIGIN/NAME`. Within a pattern,
+//! `*` matches any run of characters other than `/` and `?` matches any one such character.
+//! Blank lines and lines starting with `#` are skipped. For example:
+//!
+//! ```text
+//! # Only core packages are relevant on this host
+//! acme*
+//! core/*-musl
+//! ```
+
+use std::{io,
+          path::Path};
+
+use crate::{error::Result,
+            fs::{FileSystem,
+                 RealFileSystem}};
+
+/// The name of the ignore file in a package root.
+pub const IGNORE_FILE: &str = ".habignore";
+
+/// The patterns read from a package root's ignore file.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct IgnoreList(Vec<String>);
+
+impl IgnoreList {
+    /// Reads the ignore file in the package root at `path`, which is empty when there is no
+    /// ignore file.
+    ///
+    /// # Failures
+    ///
+    /// * The ignore file exists but cannot be read
+    pub fn load(path: &Path) -> Result<Self> { Self::load_from(&RealFileSystem, path) }
+
+    /// As `load`, but reading the package root from `filesystem`.
+    pub fn load_from(filesystem: &dyn FileSystem, path: &Path) -> Result<Self> {
+        match filesystem.read_to_string(&path.join(IGNORE_FILE)) {
+            Ok(content) => Ok(Self::parse(&content)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Parses the content of an ignore file.
+    pub fn parse(content: &str) -> Self {
+        IgnoreList(content.lines()
+                          .map(str::trim)
+                          .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                          .map(str::to_string)
+                          .collect())
+    }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// Whether every package in `origin` is ignored.
+    pub fn is_origin_ignored(&self, origin: &str) -> bool {
+        self.0
+            .iter()
+            .any(|pattern| !pattern.contains('/') && glob_match(pattern, origin))
+    }
+
+    /// Whether the package `origin/name` is ignored, either by itself or as part of its origin.
+    pub fn is_ignored(&self, origin: &str, name: &str) -> bool {
+        let origin_and_name = format!("{}/{}", origin, name);
+        self.0.iter().any(|pattern| {
+                         if pattern.contains('/') {
+                             glob_match(pattern, &origin_and_name)
+                         } else {
+                             glob_match(pattern, origin)
+                         }
+                     })
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` matches any run of characters other than `/` and
+/// `?` matches any one such character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the last `*`, should what follows it fail to match
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some(&c) if (c == '?' && text[t] != '/') || c == text[t] => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => {}
+        }
+        match backtrack {
+            // A `*` cannot consume a `/`
+            Some((star, star_t)) if text[star_t] != '/' => {
+                backtrack = Some((star, star_t + 1));
+                p = star + 1;
+                t = star_t + 1;
+            }
+            _ => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use tempfile::Builder;
+
+    #[test]
+    fn glob_matching() {
+        assert!(glob_match("core", "core"));
+        assert!(!glob_match("core", "core2"));
+        assert!(glob_match("acme*", "acme"));
+        assert!(glob_match("acme*", "acme-ci"));
+        assert!(glob_match("*-ci", "acme-ci"));
+        assert!(glob_match("a?me", "acme"));
+        assert!(!glob_match("a?me", "ame"));
+        assert!(glob_match("core/*-musl", "core/gcc-musl"));
+        assert!(!glob_match("core*", "core/gcc"));
+        assert!(!glob_match("*", "core/gcc"));
+        assert!(glob_match("*/*", "core/gcc"));
+        assert!(glob_match("*a*b", "xaxxab"));
+    }
+
+    #[test]
+    fn origin_and_name_patterns() {
+        let ignore = IgnoreList::parse("# Irrelevant here\n\nacme*\n  core/*-musl  \n");
+
+        assert!(ignore.is_origin_ignored("acme-ci"));
+        assert!(!ignore.is_origin_ignored("core"));
+        assert!(ignore.is_ignored("acme", "redis"));
+        assert!(ignore.is_ignored("core", "gcc-musl"));
+        assert!(!ignore.is_ignored("core", "gcc"));
+    }
+
+    #[test]
+    fn missing_ignore_file_is_empty() {
+        let package_root = Builder::new().prefix("pkgs").tempdir().unwrap();
+
+        assert!(IgnoreList::load(package_root.path()).unwrap().is_empty());
+
+        fs::write(package_root.path().join(IGNORE_FILE), "acme\n").unwrap();
+        assert!(IgnoreList::load(package_root.path()).unwrap()
+                                                     .is_origin_ignored("acme"));
+    }
+}
@@ -12,18 +12,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{list::package_list_for_ident,
+use super::{alias::OriginAliases,
+            exclude::Exclusions,
+            integrity::{self,
+                        VerifyLevel},
+            list::{package_list_for_ident,
+                   package_list_for_ident_from},
             metadata::{parse_key_value,
-                       read_metafile,
+                       read_metafile_from,
+                       read_metafile_os_from,
                        Bind,
                        BindMapping,
                        MetaFile,
                        PackageType},
+            overrides::DepOverrides,
+            report::{self,
+                     PackageReport},
+            snapshot::EnvSnapshot,
             Identifiable,
-            PackageIdent};
-use crate::{error::{Error,
+            PackageIdent,
+            PackageTarget};
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use crate::os::process::seccomp;
+use crate::{config::layered::{ConfigSources,
+                              LayeredConfig},
+            error::{Error,
                     Result},
-            fs};
+            fs::{self,
+                 FileSystem},
+            os::ffi::{os_str_bytes,
+                      os_string_from_bytes}};
 use serde_derive::{Deserialize,
                    Serialize};
 use std::{cmp::{Ordering,
@@ -31,31 +49,166 @@ use std::{cmp::{Ordering,
           collections::{HashMap,
                         HashSet},
           env,
+          ffi::{OsStr,
+                OsString},
           fmt,
-          fs::File,
-          io::Read,
           path::{Path,
                  PathBuf},
-          str::FromStr};
+          str::FromStr,
+          sync::Arc};
 use toml::{self,
            Value};
 
-#[cfg(test)]
-use super::PackageTarget;
 #[cfg(test)]
 use std;
 
 pub const DEFAULT_CFG_FILE: &str = "default.toml";
 const PATH_KEY: &str = "PATH";
+/// Prefix for the package-scoped references which may be used in `RUNTIME_ENVIRONMENT` values,
+/// such as `${pkg.path}`.
+const PKG_REF_PREFIX: &str = "pkg.";
+/// How the line of a package's `MANIFEST` listing its licenses begins.
+const MANIFEST_LICENSE: &str = "* __License__:";
+
+/// Options which control how `PackageInstall::environment_for_command` builds its result.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct EnvironmentOptions {
+    /// Expand `$VARNAME`, `${VARNAME}`, and `${pkg.*}` references in `RUNTIME_ENVIRONMENT`
+    /// values.
+    ///
+    /// A reference to another key in the same metafile is replaced with that key's (expanded)
+    /// value, and the `${pkg.path}`, `${pkg.ident}`, `${pkg.origin}`, `${pkg.name}`,
+    /// `${pkg.version}`, and `${pkg.release}` references are replaced with values from this
+    /// installed package. `$$` produces a literal `$`. Any reference which cannot be resolved is
+    /// left as-is. Off by default, as older packages may contain literal `$` characters in their
+    /// values.
+    pub expand_references:  bool,
+    /// Where the package's own `PATH` entries are placed relative to its dependencies' entries.
+    pub path_order:         PathOrder,
+    /// Append the entries of the calling process' `PATH` after the package's entries. Entries
+    /// which are already present are not repeated.
+    pub append_caller_path: bool,
+    /// What to do with metafiles which are malformed.
+    pub metafiles:          MetafileStrictness,
+}
+
+/// How `PackageInstall::environment_for_command` treats malformed metafiles of the package and
+/// its dependencies.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MetafileStrictness {
+    /// Fail on the first malformed metafile.
+    Strict,
+    /// Skip whatever cannot be understood, such as a `RUNTIME_ENVIRONMENT` line without a `=` or
+    /// a dependency whose metafiles are damaged, and carry on with the rest. Each thing skipped
+    /// is recorded as a `MetafileDiagnostic`.
+    Lenient,
+}
+
+impl Default for MetafileStrictness {
+    fn default() -> Self { MetafileStrictness::Strict }
+}
+
+/// Something skipped while building an environment with `MetafileStrictness::Lenient`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MetafileDiagnostic {
+    /// The package whose metafile is malformed.
+    pub ident:    PackageIdent,
+    pub metafile: MetaFile,
+    /// What was wrong, and what was skipped because of it.
+    pub detail:   String,
+}
+
+impl fmt::Display for MetafileDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,
+               "{}: malformed {}: {}",
+               self.ident, self.metafile, self.detail)
+    }
+}
+
+/// Collects `MetafileDiagnostic`s while building an environment, or fails on the first one when
+/// strict.
+struct Diagnostics {
+    strictness: MetafileStrictness,
+    found:      Vec<MetafileDiagnostic>,
+}
+
+impl Diagnostics {
+    fn new(strictness: MetafileStrictness) -> Self {
+        Diagnostics { strictness,
+                      found: Vec::new() }
+    }
+
+    /// Returns `err` when strict, and otherwise records that something in `metafile` of `ident`
+    /// was skipped, as `detail` describes.
+    fn skip(&mut self,
+            ident: &PackageIdent,
+            metafile: MetaFile,
+            detail: String,
+            err: Error)
+            -> Result<()> {
+        match self.strictness {
+            MetafileStrictness::Strict => Err(err),
+            MetafileStrictness::Lenient => {
+                self.found.push(MetafileDiagnostic { ident: ident.clone(),
+                                                     metafile,
+                                                     detail });
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The placement of a package's own `PATH` entries in the `PATH` value built for a command.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PathOrder {
+    /// The package's own entries come before those of its dependencies. This is the order
+    /// recorded in the `RUNTIME_PATH` metafile.
+    OwnFirst,
+    /// The dependencies' entries come before those of the package, allowing a dependency to
+    /// shadow a program of the same name shipped by the package.
+    DepsFirst,
+}
+
+impl Default for PathOrder {
+    fn default() -> Self { PathOrder::OwnFirst }
+}
+
+/// Converts an environment built by `PackageInstall::environment_for_command` to strings, for
+/// consumers which can only take UTF-8, such as generated unit files. Any invalid sequences are
+/// replaced with U+FFFD.
+pub fn lossy_environment(env: HashMap<OsString, OsString>) -> HashMap<String, String> {
+    env.into_iter()
+       .map(|(key, value)| {
+           (key.to_string_lossy().into_owned(), value.to_string_lossy().into_owned())
+       })
+       .collect()
+}
 
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PackageInstall {
     pub ident:          PackageIdent,
     fs_root_path:       PathBuf,
     package_root_path:  PathBuf,
     pub installed_path: PathBuf,
+    /// Where the package and its dependencies are read from.
+    #[serde(skip, default = "fs::real_file_system")]
+    filesystem:         Arc<dyn FileSystem>,
 }
 
+/// Two installs are equal when they are the same package at the same path, whichever
+/// filesystem they are read from.
+impl PartialEq for PackageInstall {
+    fn eq(&self, other: &Self) -> bool {
+        self.ident == other.ident
+        && self.fs_root_path == other.fs_root_path
+        && self.package_root_path == other.package_root_path
+        && self.installed_path == other.installed_path
+    }
+}
+
+impl Eq for PackageInstall {}
+
 // The docs recommend implementing `From` instead, but that feels a
 // bit odd here.
 impl Into<PackageIdent> for PackageInstall {
@@ -72,9 +225,49 @@ impl PackageInstall {
     ///
     /// An optional `fs_root` path may be provided to search for a package that is mounted on a
     /// filesystem not currently rooted at `/`.
+    ///
+    /// When no installed package satisfies `ident`, `suggest::suggest_similar` can find similarly
+    /// named installed packages to offer in its place.
     pub fn load(ident: &PackageIdent, fs_root_path: Option<&Path>) -> Result<PackageInstall> {
-        let package_install = Self::resolve_package_install(ident, fs_root_path)?;
-        Ok(package_install)
+        Self::load_excluding(ident, fs_root_path, &Exclusions::new())
+    }
+
+    /// As `load`, but passing over any installed package which `exclusions` excludes.
+    pub fn load_excluding(ident: &PackageIdent,
+                          fs_root_path: Option<&Path>,
+                          exclusions: &Exclusions)
+                          -> Result<PackageInstall> {
+        Self::resolve_package_install(&fs::real_file_system(), ident, fs_root_path, exclusions)
+    }
+
+    /// As `load`, but reading the package from `filesystem` rather than the real filesystem.
+    /// Its dependencies, when loaded through the returned install, are read from `filesystem`
+    /// too.
+    pub fn load_from(filesystem: Arc<dyn FileSystem>,
+                     ident: &PackageIdent,
+                     fs_root_path: Option<&Path>)
+                     -> Result<PackageInstall> {
+        Self::resolve_package_install(&filesystem, ident, fs_root_path, &Exclusions::new())
+    }
+
+    /// As `load`, but checking the integrity of the package found to `level` before returning
+    /// it. Nothing is written, so this suits read-only and high-assurance systems checking a
+    /// package as its service starts.
+    ///
+    /// # Failures
+    ///
+    /// * No installed package satisfies `ident`, as for `load`
+    /// * The package fails a check, with an `IntegrityCheckFailed` error saying why
+    pub fn load_verified(ident: &PackageIdent,
+                         fs_root_path: Option<&Path>,
+                         level: VerifyLevel)
+                         -> Result<PackageInstall> {
+        let pkg_install = Self::load(ident, fs_root_path)?;
+        integrity::verify(&*pkg_install.filesystem,
+                          &pkg_install,
+                          &pkg_install.fs_root_path,
+                          level)?;
+        Ok(pkg_install)
     }
 
     /// Verifies an installation of a package that is equal or newer to a given ident and returns
@@ -85,29 +278,139 @@ impl PackageInstall {
     pub fn load_at_least(ident: &PackageIdent,
                          fs_root_path: Option<&Path>)
                          -> Result<PackageInstall> {
-        let package_install = Self::resolve_package_install_min(ident, fs_root_path)?;
-        Ok(package_install)
+        Self::load_at_least_excluding(ident, fs_root_path, &Exclusions::new())
+    }
+
+    /// As `load_at_least`, but passing over any installed package which `exclusions` excludes.
+    pub fn load_at_least_excluding(ident: &PackageIdent,
+                                   fs_root_path: Option<&Path>,
+                                   exclusions: &Exclusions)
+                                   -> Result<PackageInstall> {
+        Self::resolve_package_install_min(&fs::real_file_system(), ident, fs_root_path, exclusions)
+    }
+
+    /// As `load`, but finding a package built for `target` rather than for the active target,
+    /// such as one in the package root of another system mounted on this one.
+    ///
+    /// Only this resolution is made for `target`. To load the package's dependencies, or do
+    /// anything else with it, for `target` as well, do it all inside
+    /// `PackageTarget::with_active_target`.
+    pub fn load_for_target(ident: &PackageIdent,
+                           target: PackageTarget,
+                           fs_root_path: Option<&Path>)
+                           -> Result<PackageInstall> {
+        PackageTarget::with_active_target(target, || Self::load(ident, fs_root_path))
+    }
+
+    /// As `load_at_least`, but finding a package built for `target`, as `load_for_target` does.
+    pub fn load_at_least_for_target(ident: &PackageIdent,
+                                    target: PackageTarget,
+                                    fs_root_path: Option<&Path>)
+                                    -> Result<PackageInstall> {
+        PackageTarget::with_active_target(target, || Self::load_at_least(ident, fs_root_path))
+    }
+
+    /// As `load`, but when no installed package satisfies `ident`, each origin which `aliases`
+    /// lets stand in for its origin is tried in turn. The package found may therefore be from
+    /// another origin than `ident`'s, and each such substitution is logged.
+    pub fn load_aliased(ident: &PackageIdent,
+                        fs_root_path: Option<&Path>,
+                        aliases: &OriginAliases)
+                        -> Result<PackageInstall> {
+        let no_exclusions = Exclusions::new();
+        let filesystem = fs::real_file_system();
+        match Self::resolve_package_install(&filesystem, ident, fs_root_path, &no_exclusions) {
+            Err(Error::PackageNotFound(_)) => {}
+            result => return result,
+        }
+        for alias in aliases.aliases_for(&ident.origin) {
+            let aliased = PackageIdent { origin: alias.clone(),
+                                         ..ident.clone() };
+            match Self::resolve_package_install(&filesystem, &aliased, fs_root_path, &no_exclusions)
+            {
+                Ok(pkg_install) => {
+                    info!("Resolved {} to {} through the origin alias of {} for {}",
+                          ident,
+                          pkg_install.ident(),
+                          alias,
+                          ident.origin);
+                    return Ok(pkg_install);
+                }
+                Err(Error::PackageNotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(Error::PackageNotFound(ident.clone()))
     }
 
-    fn resolve_package_install<T>(ident: &PackageIdent,
-                                  fs_root_path: Option<T>)
+    /// Resolves each of `idents` as `load` would, returning a result for each in the same order.
+    ///
+    /// The installed releases of each package are listed once, however many of `idents` name
+    /// it, rather than once per ident.
+    pub fn load_many(idents: &[PackageIdent],
+                     fs_root_path: Option<&Path>)
+                     -> Vec<Result<PackageInstall>> {
+        let filesystem = fs::real_file_system();
+        let fs_root = fs_root_path.map_or(PathBuf::from("/"), Into::into);
+        let package_root_path = fs::pkg_root_path(Some(&fs_root));
+        let mut lists: HashMap<(&str, &str), Result<Vec<PackageIdent>>> = HashMap::new();
+        let mut results = Vec::with_capacity(idents.len());
+        for ident in idents {
+            let all_releases =
+                PackageIdent::new(ident.origin.clone(), ident.name.clone(), None, None);
+            let list =
+                lists.entry((&ident.origin, &ident.name))
+                     .or_insert_with(|| package_list_for_ident(&package_root_path, &all_releases));
+            let result = match *list {
+                Ok(ref pl) => {
+                    Self::resolve_from_list(&filesystem,
+                                            ident,
+                                            pl,
+                                            fs_root.clone(),
+                                            package_root_path.clone())
+                }
+                // Errors cannot be cloned, so repeat the lookup to report this one
+                Err(_) => Self::load(ident, fs_root_path),
+            };
+            results.push(result);
+        }
+        results
+    }
+
+    fn resolve_package_install<T>(filesystem: &Arc<dyn FileSystem>,
+                                  ident: &PackageIdent,
+                                  fs_root_path: Option<T>,
+                                  exclusions: &Exclusions)
                                   -> Result<PackageInstall>
         where T: AsRef<Path>
     {
         let fs_root_path = fs_root_path.map_or(PathBuf::from("/"), |p| p.as_ref().into());
         let package_root_path = fs::pkg_root_path(Some(&fs_root_path));
-        if !package_root_path.exists() {
+        if !filesystem.exists(&package_root_path) {
             return Err(Error::PackageNotFound(ident.clone()));
         }
 
-        let pl = package_list_for_ident(&package_root_path, ident)?;
+        let mut pl = package_list_for_ident_from(&**filesystem, &package_root_path, ident)?;
+        pl.retain(|p| !exclusions.is_excluded(p));
+        Self::resolve_from_list(filesystem, ident, &pl, fs_root_path, package_root_path)
+    }
+
+    /// Picks the installed package `ident` resolves to from `pl`, the packages installed under
+    /// its origin and name.
+    fn resolve_from_list(filesystem: &Arc<dyn FileSystem>,
+                         ident: &PackageIdent,
+                         pl: &[PackageIdent],
+                         fs_root_path: PathBuf,
+                         package_root_path: PathBuf)
+                         -> Result<PackageInstall> {
         if ident.fully_qualified() {
             if pl.iter().any(|ref p| p.satisfies(ident)) {
                 Ok(PackageInstall { installed_path: fs::pkg_install_path(&ident,
                                                                          Some(&fs_root_path)),
                                     fs_root_path,
                                     package_root_path,
-                                    ident: ident.clone() })
+                                    ident: ident.clone(),
+                                    filesystem: filesystem.clone() })
             } else {
                 Err(Error::PackageNotFound(ident.clone()))
             }
@@ -133,7 +436,8 @@ impl PackageInstall {
                                                                          Some(&fs_root_path)),
                                     fs_root_path,
                                     package_root_path,
-                                    ident: id.clone() })
+                                    ident: id.clone(),
+                                    filesystem: filesystem.clone() })
             } else {
                 Err(Error::PackageNotFound(ident.clone()))
             }
@@ -141,8 +445,10 @@ impl PackageInstall {
     }
 
     /// Find an installed package that is at minimum the version of the given ident.
-    fn resolve_package_install_min<T>(ident: &PackageIdent,
-                                      fs_root_path: Option<T>)
+    fn resolve_package_install_min<T>(filesystem: &Arc<dyn FileSystem>,
+                                      ident: &PackageIdent,
+                                      fs_root_path: Option<T>,
+                                      exclusions: &Exclusions)
                                       -> Result<PackageInstall>
         where T: AsRef<Path>
     {
@@ -159,14 +465,15 @@ impl PackageInstall {
         };
         let fs_root_path = fs_root_path.map_or(PathBuf::from("/"), |p| p.as_ref().into());
         let package_root_path = fs::pkg_root_path(Some(&fs_root_path));
-        if !package_root_path.exists() {
+        if !filesystem.exists(&package_root_path) {
             return Err(Error::PackageNotFound(original_ident.clone()));
         }
 
-        let pl = package_list_for_ident(&package_root_path, &original_ident)?;
+        let pl = package_list_for_ident_from(&**filesystem, &package_root_path, &original_ident)?;
         let latest: Option<PackageIdent> =
             pl.iter()
               .filter(|ref p| p.origin == ident.origin && p.name == ident.name)
+              .filter(|p| !exclusions.is_excluded(p))
               .fold(None, |winner, b| {
                   match winner {
                       Some(a) => {
@@ -189,7 +496,8 @@ impl PackageInstall {
                                                                          Some(&fs_root_path)),
                                     fs_root_path,
                                     package_root_path,
-                                    ident: id.clone() })
+                                    ident: id.clone(),
+                                    filesystem: filesystem.clone() })
             }
             None => Err(Error::PackageNotFound(original_ident.clone())),
         }
@@ -203,15 +511,22 @@ impl PackageInstall {
         PackageInstall { ident,
                          fs_root_path,
                          package_root_path,
-                         installed_path }
+                         installed_path,
+                         filesystem: fs::real_file_system() }
     }
 
     /// Determines whether or not this package has a runnable service.
     pub fn is_runnable(&self) -> bool {
         // Currently, a runnable package can be determined by checking if a `run` hook exists in
         // package's hooks directory or directly in the package prefix.
-        self.installed_path.join("hooks").join("run").is_file()
-        || self.installed_path.join("run").is_file()
+        let is_file = |path: PathBuf| {
+            self.filesystem
+                .metadata(&path)
+                .map(|metadata| metadata.is_file())
+                .unwrap_or(false)
+        };
+        is_file(self.installed_path.join("hooks").join("run"))
+        || is_file(self.installed_path.join("run"))
     }
 
     /// Determine what kind of package this is.
@@ -231,13 +546,56 @@ impl PackageInstall {
 
     /// Constructs and returns a `HashMap` of environment variable/value key pairs of all
     /// environment variables needed to properly run a command from the context of this package.
-    pub fn environment_for_command(&self) -> Result<HashMap<String, String>> {
-        let mut env = self.runtime_environment()?;
+    ///
+    /// The `PATH` entry is built from the package's runtime path, ordered and extended according
+    /// to `opts`. Keys and values are kept exactly as the metafiles and the caller's `PATH` give
+    /// them, even if they are not valid UTF-8; use `lossy_environment` where strings are needed.
+    ///
+    /// With `MetafileStrictness::Lenient`, anything skipped in a malformed metafile is logged
+    /// as a warning; use `environment_with_diagnostics` to examine it instead.
+    ///
+    /// # Failures
+    ///
+    /// * A metafile exists but cannot be properly parsed, unless `opts.metafiles` is lenient
+    /// * Reference expansion was requested and the `RUNTIME_ENVIRONMENT` references form a cycle
+    pub fn environment_for_command(&self,
+                                   opts: EnvironmentOptions)
+                                   -> Result<HashMap<OsString, OsString>> {
+        let (env, diagnostics) = self.environment_with_diagnostics(opts)?;
+        for diagnostic in diagnostics {
+            warn!("{}", diagnostic);
+        }
+        Ok(env)
+    }
+
+    /// As `environment_for_command`, but also returning what was skipped in malformed metafiles,
+    /// which is only ever anything with `MetafileStrictness::Lenient`.
+    ///
+    /// # Failures
+    ///
+    /// * As with `environment_for_command`
+    pub fn environment_with_diagnostics(
+        &self,
+        opts: EnvironmentOptions)
+        -> Result<(HashMap<OsString, OsString>, Vec<MetafileDiagnostic>)> {
+        let mut diagnostics = Diagnostics::new(opts.metafiles);
+        let mut env = self.runtime_environment(&mut diagnostics)?;
         // Remove any pre-existing PATH key as this is either from an older package or is
         // present for backwards compatibility with older Habitat releases.
-        env.remove(PATH_KEY);
+        env.remove(OsStr::new(PATH_KEY));
 
-        let mut paths = self.runtime_paths()?;
+        if opts.expand_references {
+            env = self.expand_runtime_environment(env)?;
+        }
+
+        let mut paths = self.runtime_paths_with(&mut diagnostics)?;
+        if opts.path_order == PathOrder::DepsFirst {
+            let pkg_prefix = fs::pkg_install_path(self.ident(), None::<&Path>);
+            let (own, mut deps): (Vec<_>, Vec<_>) =
+                paths.into_iter().partition(|p| p.starts_with(&pkg_prefix));
+            deps.extend(own);
+            paths = deps;
+        }
 
         // Let's join the paths to the FS_ROOT
         // In most cases, this does nothing and should only mutate
@@ -247,13 +605,62 @@ impl PackageInstall {
             *path = fs::fs_rooted_path(&path, &self.fs_root_path);
         }
 
-        let joined = env::join_paths(paths)?.into_string()
-                                            .map_err(Error::InvalidPathString)?;
+        if opts.append_caller_path {
+            if let Some(caller_path) = env::var_os(PATH_KEY) {
+                for path in env::split_paths(&caller_path) {
+                    if !paths.contains(&path) {
+                        paths.push(path);
+                    }
+                }
+            }
+        }
+
+        let joined = env::join_paths(paths)?;
         // Only insert a PATH entry if the resulting path string is non-empty
         if !joined.is_empty() {
-            env.insert(PATH_KEY.to_string(), joined);
+            env.insert(PATH_KEY.into(), joined);
         }
 
+        Ok((env, diagnostics.found))
+    }
+
+    /// Returns the package's runtime environment, as `environment_for_command` builds it with
+    /// the default options, in a canonical form which can be hashed or compared with another
+    /// release's using `snapshot::env_diff`. Values which are not valid UTF-8 are converted
+    /// lossily.
+    ///
+    /// # Failures
+    ///
+    /// * A metafile exists but cannot be properly parsed
+    pub fn env_snapshot(&self) -> Result<EnvSnapshot> {
+        Ok(lossy_environment(self.environment_for_command(EnvironmentOptions::default())?).into())
+    }
+
+    /// As `environment_for_command`, but with the dependencies which `overrides` replaces swapped
+    /// for their replacements. Every occurrence of a replaced dependency's install path in the
+    /// environment, such as its `PATH` entries or the library paths exported by the package,
+    /// is rewritten to the install path of the package its replacement resolves to.
+    ///
+    /// # Failures
+    ///
+    /// * The environment cannot be built, as with `environment_for_command`
+    /// * A replacement is not installed
+    pub fn environment_with_overrides(&self,
+                                      opts: EnvironmentOptions,
+                                      overrides: &DepOverrides)
+                                      -> Result<HashMap<OsString, OsString>> {
+        let mut env = self.environment_for_command(opts)?;
+        for dep in self.tdeps()? {
+            let replacement = match overrides.replacement_for(&dep) {
+                Some(replacement) => self.load_dep(replacement)?,
+                None => continue,
+            };
+            let from = fs::pkg_install_path(&dep, None::<&Path>);
+            let to = fs::pkg_install_path(replacement.ident(), None::<&Path>);
+            for value in env.values_mut() {
+                *value = replace_os(value, from.as_os_str(), to.as_os_str());
+            }
+        }
         Ok(env)
     }
 
@@ -325,13 +732,10 @@ impl PackageInstall {
 
     /// Read and return the decoded contents of the packages default configuration.
     pub fn default_cfg(&self) -> Option<toml::value::Value> {
-        match File::open(self.installed_path.join(DEFAULT_CFG_FILE)) {
-            Ok(mut file) => {
-                let mut raw = String::new();
-                if file.read_to_string(&mut raw).is_err() {
-                    return None;
-                };
-
+        match self.filesystem
+                  .read_to_string(&self.installed_path.join(DEFAULT_CFG_FILE))
+        {
+            Ok(raw) => {
                 match raw.parse::<Value>() {
                     Ok(v) => Some(v),
                     Err(e) => {
@@ -344,6 +748,18 @@ impl PackageInstall {
         }
     }
 
+    /// Returns the configuration of the service `service_name` running this package, with
+    /// `default.toml` overridden by the service's `user.toml` and then by its environment
+    /// variable, along with which of those supplied each value. See `config::layered` for the
+    /// details.
+    ///
+    /// # Failures
+    ///
+    /// * A layer exists but cannot be read, or is not valid TOML
+    pub fn layered_cfg(&self, service_name: &str) -> Result<LayeredConfig> {
+        ConfigSources::new(self, service_name).load()
+    }
+
     /// Return the direct dependencies of the package
     pub fn deps(&self) -> Result<Vec<PackageIdent>> { self.read_deps(MetaFile::Deps) }
 
@@ -397,7 +813,7 @@ impl PackageInstall {
     ///
     /// If no value for `PATH` can be found, return an empty `Vec`.
     pub fn paths(&self) -> Result<Vec<PathBuf>> {
-        match self.read_metafile(MetaFile::Path) {
+        match self.read_metafile_os(MetaFile::Path) {
             Ok(body) => {
                 if body.is_empty() {
                     return Ok(vec![]);
@@ -423,9 +839,11 @@ impl PackageInstall {
                     // workaround attempts to fallback to the `RUNTIME_ENVIRONMENT` metafile and
                     // use the value of the `PATH` key as a stand-in for the `PATH` metafile.
                     let pkg_prefix = fs::pkg_install_path(self.ident(), None::<&Path>);
-                    match self.read_metafile(MetaFile::RuntimeEnvironment) {
+                    match self.read_metafile_os(MetaFile::RuntimeEnvironment) {
                         Ok(ref body) => {
-                            match Self::parse_runtime_environment_metafile(body)?.get(PATH_KEY) {
+                            match Self::parse_runtime_environment_metafile(body)?
+                                .get(OsStr::new(PATH_KEY))
+                            {
                                 Some(env_path) => {
                                     let v = env::split_paths(env_path).filter(|p| {
                                                                           p.starts_with(&pkg_prefix)
@@ -447,20 +865,70 @@ impl PackageInstall {
         }
     }
 
-    /// Attempts to load the extracted package for each direct dependency and returns a
-    /// `Package` struct representation of each in the returned vector.
+    /// Returns the absolute path to `command` if it is found in one of the package's own `PATH`
+    /// entries, or `None` otherwise.
+    ///
+    /// The entries are searched in order and, on Windows, a `command` without an extension is
+    /// also tried with each of the extensions in `PATHEXT`. As with the `PATH` entry of
+    /// `environment_for_command`, the returned path only includes this package's filesystem root
+    /// on Windows.
     ///
     /// # Failures
     ///
-    /// * Any direct dependency could not be located or it's contents could not be read from disk
-    fn load_deps(&self) -> Result<Vec<PackageInstall>> {
-        let ddeps = self.deps()?;
-        let mut deps = Vec::with_capacity(ddeps.len());
-        for dep in ddeps.iter() {
-            let dep_install = Self::load(dep, Some(&*self.fs_root_path))?;
-            deps.push(dep_install);
+    /// * The package's `PATH` metafile exists but cannot be read
+    pub fn find_command<T>(&self, command: T) -> Result<Option<PathBuf>>
+        where T: AsRef<Path>
+    {
+        Ok(self.find_command_in(self.paths()?, command.as_ref(), false))
+    }
+
+    /// Returns the absolute path to `command` if it is found in the package's runtime path, which
+    /// contains the `PATH` entries of the package followed by those of its transitive
+    /// dependencies, or `None` otherwise.
+    ///
+    /// See `find_command` for how each entry is searched, except that on Unix only files with an
+    /// execute bit set are found, as with a shell, so that a file which is not a command in one
+    /// package cannot shadow the command in a later one.
+    ///
+    /// # Failures
+    ///
+    /// * A metafile exists but cannot be properly parsed
+    /// * A dependency of an older package without a `RUNTIME_PATH` metafile cannot be loaded
+    pub fn find_command_with_tdeps<T>(&self, command: T) -> Result<Option<PathBuf>>
+        where T: AsRef<Path>
+    {
+        Ok(self.find_command_in(self.runtime_paths()?, command.as_ref(), true))
+    }
+
+    /// Searches `paths` for `command`, only finding files with an execute bit set on Unix if
+    /// `executable_only`.
+    fn find_command_in(&self,
+                       paths: Vec<PathBuf>,
+                       command: &Path,
+                       executable_only: bool)
+                       -> Option<PathBuf> {
+        for path in paths {
+            // The path entries are absolute, so check for the command relative to the package's
+            // filesystem root
+            let dir = self.fs_root_path
+                          .join(path.strip_prefix("/").unwrap_or(&path));
+            let candidate = dir.join(command);
+            let is_command = if executable_only {
+                fs::is_executable_file(&candidate)
+            } else {
+                candidate.is_file()
+            };
+            let found = if is_command {
+                Some(candidate)
+            } else {
+                fs::find_command_with_pathext(&candidate)
+            };
+            if let Some(found) = found {
+                let command = found.strip_prefix(&dir).unwrap_or(command);
+                return Some(fs::fs_rooted_path(&path.join(command), &self.fs_root_path));
+            }
         }
-        Ok(deps)
+        None
     }
 
     /// Attempts to load the extracted package for each transitive dependency and returns a
@@ -470,16 +938,35 @@ impl PackageInstall {
     ///
     /// * Any transitive dependency could not be located or it's contents could not be read from
     ///   disk
-    fn load_tdeps(&self) -> Result<Vec<PackageInstall>> {
+    pub(crate) fn load_tdeps(&self) -> Result<Vec<PackageInstall>> {
         let tdeps = self.tdeps()?;
         let mut deps = Vec::with_capacity(tdeps.len());
         for dep in tdeps.iter() {
-            let dep_install = Self::load(dep, Some(&*self.fs_root_path))?;
+            let dep_install = self.load_dep(dep)?;
             deps.push(dep_install);
         }
         Ok(deps)
     }
 
+    /// As `load_tdeps`, but loading the replacement of each dependency which `overrides`
+    /// replaces in its place.
+    ///
+    /// # Failures
+    ///
+    /// * Any transitive dependency or replacement could not be located or its contents could not be
+    ///   read from disk
+    pub fn load_tdeps_with_overrides(&self,
+                                     overrides: &DepOverrides)
+                                     -> Result<Vec<PackageInstall>> {
+        let tdeps = self.tdeps()?;
+        let mut deps = Vec::with_capacity(tdeps.len());
+        for dep in tdeps.iter() {
+            let dep = overrides.replacement_for(dep).unwrap_or(dep);
+            deps.push(self.load_dep(dep)?);
+        }
+        Ok(deps)
+    }
+
     /// Returns an ordered `Vec` of path entries which are read from the package's `RUNTIME_PATH`
     /// metafile if it exists, or calcuated using `PATH` metafiles if the package is older.
     /// Otherwise, an empty `Vec` is returned.
@@ -488,7 +975,12 @@ impl PackageInstall {
     ///
     /// * If a metafile exists but cannot be properly parsed
     fn runtime_paths(&self) -> Result<Vec<PathBuf>> {
-        match self.read_metafile(MetaFile::RuntimePath) {
+        self.runtime_paths_with(&mut Diagnostics::new(MetafileStrictness::Strict))
+    }
+
+    /// As `runtime_paths`, skipping malformed metafiles if `diagnostics` allows it.
+    fn runtime_paths_with(&self, diagnostics: &mut Diagnostics) -> Result<Vec<PathBuf>> {
+        match self.read_metafile_os(MetaFile::RuntimePath) {
             Ok(body) => {
                 if body.is_empty() {
                     return Ok(vec![]);
@@ -496,7 +988,9 @@ impl PackageInstall {
 
                 Ok(env::split_paths(&body).collect())
             }
-            Err(Error::MetaFileNotFound(MetaFile::RuntimePath)) => self.legacy_runtime_paths(),
+            Err(Error::MetaFileNotFound(MetaFile::RuntimePath)) => {
+                self.legacy_runtime_paths(diagnostics)
+            }
             Err(e) => Err(e),
         }
     }
@@ -511,23 +1005,43 @@ impl PackageInstall {
     ///
     /// Preserved reference implementation:
     /// https://github.com/habitat-sh/habitat/blob/333b75d6234db0531cf4a5bdcb859f7d4adc2478/components/core/src/package/install.rs#L321-L350
-    fn legacy_runtime_paths(&self) -> Result<Vec<PathBuf>> {
+    ///
+    /// When `diagnostics` allows it, a dependency which cannot be loaded because of a malformed
+    /// metafile, or whose `PATH` metafile is malformed, is left out.
+    fn legacy_runtime_paths(&self, diagnostics: &mut Diagnostics) -> Result<Vec<PathBuf>> {
         let mut paths = Vec::new();
         let mut seen = HashSet::new();
 
-        for p in self.paths()? {
-            if seen.contains(&p) {
-                continue;
+        let mut ordered_pkgs = vec![self.clone()];
+        for file in &[MetaFile::Deps, MetaFile::TDeps] {
+            for dep in self.read_deps_with(*file, diagnostics)? {
+                match self.load_dep(&dep) {
+                    Ok(pkg) => ordered_pkgs.push(pkg),
+                    Err(Error::MetaFileMalformed(metafile)) => {
+                        let detail = format!("leaving out the runtime path of {}", dep);
+                        diagnostics.skip(&dep,
+                                         metafile,
+                                         detail,
+                                         Error::MetaFileMalformed(metafile))?
+                    }
+                    Err(e) => return Err(e),
+                }
             }
-            seen.insert(p.clone());
-            paths.push(p);
         }
-
-        let ordered_pkgs = self.load_deps()?
-                               .into_iter()
-                               .chain(self.load_tdeps()?.into_iter());
         for pkg in ordered_pkgs {
-            for p in pkg.paths()? {
+            let pkg_paths = match pkg.paths() {
+                Ok(pkg_paths) => pkg_paths,
+                Err(Error::MetaFileMalformed(metafile)) => {
+                    let detail = format!("leaving out the runtime path of {}", pkg.ident);
+                    diagnostics.skip(&pkg.ident,
+                                     metafile,
+                                     detail,
+                                     Error::MetaFileMalformed(metafile))?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            for p in pkg_paths {
                 if seen.contains(&p) {
                     continue;
                 }
@@ -539,34 +1053,104 @@ impl PackageInstall {
         Ok(paths)
     }
 
-    fn parse_runtime_environment_metafile(body: &str) -> Result<HashMap<String, String>> {
+    fn parse_runtime_environment_metafile(body: &OsStr) -> Result<HashMap<OsString, OsString>> {
+        let (env, malformed) = Self::parse_runtime_environment_lines(body);
+        if malformed.is_empty() {
+            Ok(env)
+        } else {
+            Err(Error::MetaFileMalformed(MetaFile::RuntimeEnvironment))
+        }
+    }
+
+    /// Parses the `RUNTIME_ENVIRONMENT` metafile, returning the variables of the lines which
+    /// could be parsed along with the numbers of those which could not.
+    fn parse_runtime_environment_lines(body: &OsStr) -> (HashMap<OsString, OsString>, Vec<usize>) {
         let mut env = HashMap::new();
-        for line in body.lines() {
-            let parts: Vec<&str> = line.splitn(2, '=').collect();
-            if parts.len() != 2 {
-                return Err(Error::MetaFileMalformed(MetaFile::RuntimeEnvironment));
+        let mut malformed = Vec::new();
+        let body = os_str_bytes(body);
+        if body.is_empty() {
+            return (env, malformed);
+        }
+        for (n, line) in body.split(|&b| b == b'\n').enumerate() {
+            let line = if line.ends_with(b"\r") {
+                &line[..line.len() - 1]
+            } else {
+                line
+            };
+            match line.iter().position(|&b| b == b'=') {
+                Some(i) => {
+                    env.insert(os_string_from_bytes(line[..i].to_vec()),
+                               os_string_from_bytes(line[i + 1..].to_vec()));
+                }
+                None => malformed.push(n + 1),
             }
-            let key = parts[0].to_string();
-            let value = parts[1].to_string();
-            env.insert(key, value);
         }
-        Ok(env)
+        (env, malformed)
     }
 
     /// Return the parsed contents of the package's `RUNTIME_ENVIRONMENT` metafile as a `HashMap`,
     /// or an empty `HashMap` if not found.
     ///
     /// If no value of `RUNTIME_ENVIRONMENT` is found, return an empty `HashMap`.
-    fn runtime_environment(&self) -> Result<HashMap<String, String>> {
-        match self.read_metafile(MetaFile::RuntimeEnvironment) {
-            Ok(ref body) => Self::parse_runtime_environment_metafile(body),
+    fn runtime_environment(&self,
+                           diagnostics: &mut Diagnostics)
+                           -> Result<HashMap<OsString, OsString>> {
+        match self.read_metafile_os(MetaFile::RuntimeEnvironment) {
+            Ok(ref body) => {
+                let (env, malformed) = Self::parse_runtime_environment_lines(body);
+                for line in malformed {
+                    diagnostics.skip(&self.ident,
+                                     MetaFile::RuntimeEnvironment,
+                                     format!("skipping line {}, which has no '='", line),
+                                     Error::MetaFileMalformed(MetaFile::RuntimeEnvironment))?;
+                }
+                Ok(env)
+            }
             Err(Error::MetaFileNotFound(MetaFile::RuntimeEnvironment)) => Ok(HashMap::new()),
             Err(e) => Err(e),
         }
     }
 
+    /// Returns a copy of `env` with all `$VARNAME`, `${VARNAME}`, and `${pkg.*}` references in
+    /// its values expanded.
+    ///
+    /// # Failures
+    ///
+    /// * Two or more keys reference each other, either directly or indirectly
+    fn expand_runtime_environment(&self,
+                                  env: HashMap<OsString, OsString>)
+                                  -> Result<HashMap<OsString, OsString>> {
+        let pkg_refs = self.pkg_references();
+        let mut expanded = HashMap::with_capacity(env.len());
+        for key in env.keys() {
+            let mut stack = Vec::new();
+            expand_env_key(key, &env, &pkg_refs, &mut expanded, &mut stack)?;
+        }
+        Ok(expanded)
+    }
+
+    /// Returns the values available to `${pkg.*}` references, keyed by the name following the
+    /// `pkg.` prefix.
+    fn pkg_references(&self) -> HashMap<&'static str, OsString> {
+        let mut refs = HashMap::new();
+        refs.insert("path", self.installed_path.clone().into_os_string());
+        refs.insert("ident", self.ident.to_string().into());
+        refs.insert("origin", self.ident.origin.clone().into());
+        refs.insert("name", self.ident.name.clone().into());
+        if let Some(ref version) = self.ident.version {
+            refs.insert("version", version.clone().into());
+        }
+        if let Some(ref release) = self.ident.release {
+            refs.insert("release", release.clone().into());
+        }
+        refs
+    }
+
     pub fn installed_path(&self) -> &Path { &*self.installed_path }
 
+    /// Returns the filesystem root this package was loaded from.
+    pub(crate) fn fs_root_path(&self) -> &Path { &*self.fs_root_path }
+
     /// Returns the user that the package is specified to run as
     /// or None if the package doesn't contain a SVC_USER Metafile
     pub fn svc_user(&self) -> Result<Option<String>> {
@@ -587,6 +1171,67 @@ impl PackageInstall {
         }
     }
 
+    /// Returns the licenses the package was built under, as listed in its `MANIFEST`, or an
+    /// empty list if it has no manifest or the manifest lists none.
+    pub fn licenses(&self) -> Result<Vec<String>> {
+        let manifest = match self.read_metafile(MetaFile::Manifest) {
+            Ok(body) => body,
+            Err(Error::MetaFileNotFound(MetaFile::Manifest)) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(manifest.lines()
+                   .filter_map(|line| {
+                       let line = line.trim();
+                       if line.starts_with(MANIFEST_LICENSE) {
+                           Some(&line[MANIFEST_LICENSE.len()..])
+                       } else {
+                           None
+                       }
+                   })
+                   .flat_map(str::split_whitespace)
+                   .map(str::to_string)
+                   .collect())
+    }
+
+    /// Returns the seccomp profile the package declares in its `SECCOMP_PROFILE` metafile, or
+    /// None if it declares none.
+    ///
+    /// # Failures
+    ///
+    /// * The metafile exists but cannot be parsed as a profile
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    pub fn seccomp_profile(&self) -> Result<Option<seccomp::Profile>> {
+        match self.read_metafile(MetaFile::SeccompProfile) {
+            Ok(body) => body.parse().map(Some),
+            Err(Error::MetaFileNotFound(MetaFile::SeccompProfile)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Gathers everything known about the package into a single serializable report.
+    ///
+    /// # Failures
+    ///
+    /// * A metafile exists but cannot be properly parsed
+    /// * The package's `TARGET` metafile is missing
+    /// * The installed files cannot be read to total their size
+    pub fn to_report(&self) -> Result<PackageReport> {
+        Ok(PackageReport { ident:          self.ident.clone(),
+                           target:         self.target()?,
+                           pkg_type:       self.pkg_type()?.to_string(),
+                           installed_path: self.installed_path.clone(),
+                           deps:           self.deps()?,
+                           tdeps:          self.tdeps()?,
+                           build_deps:     self.build_deps()?,
+                           exports:        self.exports()?.into_iter().collect(),
+                           exposes:        self.exposes()?,
+                           binds:          self.binds()?,
+                           binds_optional: self.binds_optional()?,
+                           svc_user:       self.svc_user()?,
+                           svc_group:      self.svc_group()?,
+                           size:           report::installed_size(&self.installed_path)?, })
+    }
+
     /// Read the contents of a given metafile.
     ///
     /// # Failures
@@ -595,7 +1240,21 @@ impl PackageInstall {
     /// * Contents of the metafile could not be read
     /// * Contents of the metafile are unreadable or malformed
     fn read_metafile(&self, file: MetaFile) -> Result<String> {
-        read_metafile(&self.installed_path, file)
+        read_metafile_from(&*self.filesystem, &self.installed_path, file)
+    }
+
+    /// As `read_metafile`, but without requiring the contents to be UTF-8.
+    fn read_metafile_os(&self, file: MetaFile) -> Result<OsString> {
+        read_metafile_os_from(&*self.filesystem, &self.installed_path, file)
+    }
+
+    /// Loads `dep`, a dependency of this package, from the same filesystem root and filesystem.
+    fn load_dep(&self, dep: &PackageIdent) -> Result<PackageInstall> {
+        let no_exclusions = Exclusions::new();
+        Self::resolve_package_install(&self.filesystem,
+                                      dep,
+                                      Some(&self.fs_root_path),
+                                      &no_exclusions)
     }
 
     /// Reads metafiles containing dependencies represented by package identifiers separated by new
@@ -610,6 +1269,15 @@ impl PackageInstall {
     /// * Contents of the metafile could not be read
     /// * Contents of the metafile are unreadable or malformed
     fn read_deps(&self, file: MetaFile) -> Result<Vec<PackageIdent>> {
+        self.read_deps_with(file, &mut Diagnostics::new(MetafileStrictness::Strict))
+    }
+
+    /// As `read_deps`, skipping malformed identifiers, or the whole metafile if it cannot be
+    /// read, if `diagnostics` allows it.
+    fn read_deps_with(&self,
+                      file: MetaFile,
+                      diagnostics: &mut Diagnostics)
+                      -> Result<Vec<PackageIdent>> {
         let mut deps: Vec<PackageIdent> = vec![];
 
         // For now, all deps files but SERVICES need fully-qualified
@@ -620,11 +1288,20 @@ impl PackageInstall {
             Ok(body) => {
                 if !body.is_empty() {
                     for id in body.lines() {
-                        let package = PackageIdent::from_str(id)?;
+                        let package = match PackageIdent::from_str(id) {
+                            Ok(package) => package,
+                            Err(e) => {
+                                let detail = format!("skipping '{}': {}", id, e);
+                                diagnostics.skip(&self.ident, file, detail, e)?;
+                                continue;
+                            }
+                        };
                         if !package.fully_qualified() && must_be_fully_qualified {
-                            return Err(Error::FullyQualifiedPackageIdentRequired(
-                                package.to_string(),
-                            ));
+                            let detail = format!("skipping '{}', which is not fully qualified", id);
+                            let err =
+                                Error::FullyQualifiedPackageIdentRequired(package.to_string());
+                            diagnostics.skip(&self.ident, file, detail, err)?;
+                            continue;
                         }
                         deps.push(package);
                     }
@@ -632,12 +1309,16 @@ impl PackageInstall {
                 Ok(deps)
             }
             Err(Error::MetaFileNotFound(_)) => Ok(deps),
+            Err(Error::MetaFileMalformed(_)) => {
+                let detail = "skipping every dependency it lists".to_string();
+                diagnostics.skip(&self.ident, file, detail, Error::MetaFileMalformed(file))?;
+                Ok(deps)
+            }
             Err(e) => Err(e),
         }
     }
 
-    #[cfg(test)]
-    fn target(&self) -> Result<PackageTarget> {
+    pub(crate) fn target(&self) -> Result<PackageTarget> {
         match self.read_metafile(MetaFile::Target) {
             Ok(body) => PackageTarget::from_str(&body),
             Err(e) => Err(e),
@@ -649,6 +1330,134 @@ impl fmt::Display for PackageInstall {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", self.ident) }
 }
 
+/// Expands the value of `key` from `raw`, storing the result (and the results of any keys it
+/// references) in `expanded`.
+///
+/// Values are processed byte by byte, so anything other than the ASCII characters making up a
+/// reference is passed through untouched, whether or not it is valid UTF-8.
+///
+/// The `stack` holds the keys currently being expanded and is used to detect reference cycles.
+fn expand_env_key(key: &OsStr,
+                  raw: &HashMap<OsString, OsString>,
+                  pkg_refs: &HashMap<&'static str, OsString>,
+                  expanded: &mut HashMap<OsString, OsString>,
+                  stack: &mut Vec<OsString>)
+                  -> Result<OsString> {
+    if let Some(value) = expanded.get(key) {
+        return Ok(value.clone());
+    }
+    if stack.iter().any(|k| k == key) {
+        let mut cycle: Vec<_> = stack.iter().map(|k| k.to_string_lossy()).collect();
+        cycle.push(key.to_string_lossy());
+        return Err(Error::RuntimeEnvironmentCycle(cycle.join(" -> ")));
+    }
+    let value = os_str_bytes(&raw[key]);
+
+    stack.push(key.to_os_string());
+    let mut result = Vec::with_capacity(value.len());
+    let mut bytes = value.iter().cloned().peekable();
+    while let Some(b) = bytes.next() {
+        if b != b'$' {
+            result.push(b);
+            continue;
+        }
+        let (name, literal) = match bytes.peek() {
+            Some(b'$') => {
+                bytes.next();
+                result.push(b'$');
+                continue;
+            }
+            Some(b'{') => {
+                bytes.next();
+                let mut name = Vec::new();
+                let mut closed = false;
+                for b in bytes.by_ref() {
+                    if b == b'}' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(b);
+                }
+                if !closed {
+                    // An unterminated reference is not a reference at all
+                    result.extend_from_slice(b"${");
+                    result.extend_from_slice(&name);
+                    continue;
+                }
+                let mut literal = b"${".to_vec();
+                literal.extend_from_slice(&name);
+                literal.push(b'}');
+                (name, literal)
+            }
+            _ => {
+                let mut name = Vec::new();
+                while let Some(&b) = bytes.peek() {
+                    if b.is_ascii_alphanumeric() || b == b'_' {
+                        name.push(b);
+                        bytes.next();
+                    } else {
+                        break;
+                    }
+                }
+                if name.is_empty() {
+                    result.push(b'$');
+                    continue;
+                }
+                let mut literal = vec![b'$'];
+                literal.extend_from_slice(&name);
+                (name, literal)
+            }
+        };
+
+        if name.starts_with(PKG_REF_PREFIX.as_bytes()) {
+            let pkg_ref =
+                std::str::from_utf8(&name[PKG_REF_PREFIX.len()..]).ok()
+                                                                  .and_then(|r| pkg_refs.get(r));
+            match pkg_ref {
+                Some(value) => result.extend_from_slice(&os_str_bytes(value)),
+                None => {
+                    debug!("Leaving unknown package reference '{}' in {} unexpanded",
+                           String::from_utf8_lossy(&literal),
+                           key.to_string_lossy());
+                    result.extend_from_slice(&literal);
+                }
+            }
+            continue;
+        }
+        let name = os_string_from_bytes(name);
+        if raw.contains_key(&name) {
+            let value = expand_env_key(&name, raw, pkg_refs, expanded, stack)?;
+            result.extend_from_slice(&os_str_bytes(&value));
+        } else {
+            debug!("Leaving unknown reference '{}' in {} unexpanded",
+                   String::from_utf8_lossy(&literal),
+                   key.to_string_lossy());
+            result.extend_from_slice(&literal);
+        }
+    }
+    stack.pop();
+
+    let result = os_string_from_bytes(result);
+    expanded.insert(key.to_os_string(), result.clone());
+    Ok(result)
+}
+
+/// Replaces every occurrence of `from` in `value` with `to`.
+fn replace_os(value: &OsStr, from: &OsStr, to: &OsStr) -> OsString {
+    let (value, from, to) = (os_str_bytes(value), os_str_bytes(from), os_str_bytes(to));
+    let mut result = Vec::with_capacity(value.len());
+    let mut rest = &value[..];
+    while let Some(i) = rest.windows(from.len())
+                            .position(|window| window == &from[..])
+    {
+        result.extend_from_slice(&rest[..i]);
+        result.extend_from_slice(&to);
+        rest = &rest[i + from.len()..];
+    }
+    result.extend_from_slice(rest);
+    os_string_from_bytes(result)
+}
+
 #[cfg(test)]
 mod test {
     use std::{fs::File,
@@ -658,8 +1467,11 @@ mod test {
     use toml;
 
     use super::*;
-    use crate::package::test_support::{fixture_path,
-                                       testing_package_install};
+    use crate::package::{snapshot::{env_diff,
+                                    EnvChange},
+                         test_support::{fixture_path,
+                                        fixtures,
+                                        testing_package_install}};
 
     /// Write the given contents into the specified metadata file for
     /// the package.
@@ -682,6 +1494,23 @@ mod test {
         );
     }
 
+    /// Creates an empty command named `name` in `dir`, with its execute bits set on Unix if
+    /// `executable`.
+    fn create_command(dir: &Path, name: &str, executable: bool) {
+        std::fs::create_dir_all(dir).unwrap();
+        File::create(dir.join(name)).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = if executable { 0o755 } else { 0o644 };
+            std::fs::set_permissions(dir.join(name), std::fs::Permissions::from_mode(mode))
+                .unwrap();
+        }
+        #[cfg(not(unix))]
+        let _ = executable;
+    }
+
     /// Creates a `RUNTIME_PATH` metafile with path entries in the order of the `Vec` of
     /// `PackageInstall`s. Note that this implementation uses the `PATH` metafile of each
     /// `PackageInstall`, including the target `pkg_install`.
@@ -736,11 +1565,12 @@ mod test {
     #[test]
     fn can_serialize_default_config() {
         let package_ident = PackageIdent::from_str("just/nothing").unwrap();
-        let fixture_path = fixture_path("test_package");
+        let fixture_path = fixture_path(&fixtures(), "test_package");
         let package_install = PackageInstall { ident:             package_ident,
                                                fs_root_path:      PathBuf::from(""),
                                                package_root_path: PathBuf::from(""),
-                                               installed_path:    fixture_path, };
+                                               installed_path:    fixture_path,
+                                               filesystem:        fs::real_file_system(), };
 
         let cfg = package_install.default_cfg().unwrap();
 
@@ -749,6 +1579,36 @@ mod test {
         }
     }
 
+    #[test]
+    fn to_report_gathers_package_details() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let dep = testing_package_install("core/glibc/2.27/20190115002733", fs_root.path());
+        let pkg_install =
+            testing_package_install("core/redis/4.0.14/20190319155852", fs_root.path());
+        set_deps_for(&pkg_install, &[&dep]);
+        set_tdeps_for(&pkg_install, &[&dep]);
+        write_metafile(&pkg_install, MetaFile::Exports, "port=port\n");
+        write_metafile(&pkg_install, MetaFile::Exposes, "6379");
+        write_metafile(&pkg_install, MetaFile::Binds, "backend=port\n");
+        write_metafile(&pkg_install, MetaFile::SvcUser, "hab");
+
+        let report = pkg_install.to_report().unwrap();
+
+        assert_eq!(pkg_install.ident, report.ident);
+        assert_eq!(PackageTarget::active_target(), report.target);
+        assert_eq!(vec![dep.ident.clone()], report.tdeps);
+        assert_eq!(Some("port"), report.exports.get("port").map(String::as_str));
+        assert_eq!(vec!["6379".to_string()], report.exposes);
+        assert_eq!("backend", report.binds[0].service);
+        assert_eq!(Some("hab".to_string()), report.svc_user);
+        assert_eq!(None, report.svc_group);
+        assert!(report.size > 0);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!("Standalone", json["pkg_type"]);
+        assert_eq!("redis", json["ident"]["name"]);
+    }
+
     #[test]
     fn reading_a_valid_bind_map_file_works() {
         let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
@@ -817,6 +1677,45 @@ core/bar=pub:core/publish sub:core/subscribe
         assert_eq!(active_target, loaded.target().unwrap());
     }
 
+    #[test]
+    fn load_for_target_finds_packages_of_other_targets() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let ident_s = "dream-theater/systematic-chaos/1.2.3/20180704142702";
+        let wrong_target = *wrong_package_target();
+        let pkg_install = testing_package_install(ident_s, fs_root.path());
+        write_metafile(&pkg_install, MetaFile::Target, &wrong_target);
+        let ident = PackageIdent::from_str("dream-theater/systematic-chaos").unwrap();
+
+        assert!(PackageInstall::load(&ident, Some(fs_root.path())).is_err());
+        let loaded =
+            PackageInstall::load_for_target(&ident, wrong_target, Some(fs_root.path())).unwrap();
+        assert_eq!(pkg_install, loaded);
+        let loaded = PackageInstall::load_at_least_for_target(&ident,
+                                                              wrong_target,
+                                                              Some(fs_root.path())).unwrap();
+        assert_eq!(pkg_install, loaded);
+        assert!(PackageInstall::load_for_target(&ident,
+                                                PackageTarget::active_target(),
+                                                Some(fs_root.path())).is_err());
+    }
+
+    #[test]
+    fn load_verified_checks_the_package_found() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let ident_s = "dream-theater/systematic-chaos/1.2.3/20180704142702";
+        let pkg_install = testing_package_install(ident_s, fs_root.path());
+        let ident = PackageIdent::from_str("dream-theater/systematic-chaos").unwrap();
+
+        let loaded =
+            PackageInstall::load_verified(&ident, Some(fs_root.path()), VerifyLevel::Metadata);
+        assert_eq!(pkg_install, loaded.unwrap());
+
+        match PackageInstall::load_verified(&ident, Some(fs_root.path()), VerifyLevel::Files) {
+            Err(Error::MetaFileNotFound(MetaFile::Files)) => {}
+            other => panic!("Expected a missing FILES metafile to fail, got {:?}", other),
+        }
+    }
+
     #[test]
     fn load_with_fully_qualified_ident_with_wrong_target_returns_package_not_found_err() {
         let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
@@ -1091,6 +1990,105 @@ core/bar=pub:core/publish sub:core/subscribe
         }
     }
 
+    #[test]
+    fn load_aliased_falls_back_to_alias_origins() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let mirrored =
+            testing_package_install("mycorp-core/redis/4.0.14/20190319155852", fs_root.path());
+        let aliases = OriginAliases::new().alias("core", "mirror-core")
+                                          .alias("core", "mycorp-core");
+        let redis = PackageIdent::from_str("core/redis/4.0.14").unwrap();
+
+        match PackageInstall::load(&redis, Some(fs_root.path())) {
+            Err(Error::PackageNotFound(_)) => {}
+            other => panic!("Expected PackageNotFound, got {:?}", other),
+        }
+        assert_eq!(mirrored,
+                   PackageInstall::load_aliased(&redis, Some(fs_root.path()), &aliases).unwrap());
+
+        // The original origin is preferred when it is installed
+        let original = testing_package_install("core/redis/4.0.14/20190101000000", fs_root.path());
+        assert_eq!(original,
+                   PackageInstall::load_aliased(&redis, Some(fs_root.path()), &aliases).unwrap());
+
+        match PackageInstall::load_aliased(&PackageIdent::from_str("core/nginx").unwrap(),
+                                           Some(fs_root.path()),
+                                           &aliases)
+        {
+            Err(Error::PackageNotFound(ref ident)) => {
+                assert_eq!("core/nginx", ident.to_string())
+            }
+            other => panic!("Expected PackageNotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn load_many_resolves_each_ident_in_order() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let older = testing_package_install("dream-theater/systematic-chaos/1.1.1/20180704142702",
+                                            fs_root.path());
+        let newer = testing_package_install("dream-theater/systematic-chaos/1.2.3/20180704142702",
+                                            fs_root.path());
+        let other =
+            testing_package_install("dream-theater/awake/2.0.0/20180704142702", fs_root.path());
+        let idents: Vec<PackageIdent> =
+            ["dream-theater/systematic-chaos",
+             "dream-theater/awake",
+             "dream-theater/systematic-chaos/1.1.1",
+             "dream-theater/scenes-from-a-memory"].iter()
+                                                  .map(|s| PackageIdent::from_str(s).unwrap())
+                                                  .collect();
+
+        let mut results = PackageInstall::load_many(&idents, Some(fs_root.path())).into_iter();
+
+        assert_eq!(newer, results.next().unwrap().unwrap());
+        assert_eq!(other, results.next().unwrap().unwrap());
+        assert_eq!(older, results.next().unwrap().unwrap());
+        match results.next().unwrap() {
+            Err(Error::PackageNotFound(ref ident)) => assert_eq!(&idents[3], ident),
+            other => panic!("Expected PackageNotFound, got {:?}", other),
+        }
+        assert!(results.next().is_none());
+    }
+
+    #[test]
+    fn load_excluding_passes_over_excluded_releases() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let older = testing_package_install("dream-theater/systematic-chaos/1.1.1/20180704142702",
+                                            fs_root.path());
+        let newer = testing_package_install("dream-theater/systematic-chaos/1.2.3/20180704142702",
+                                            fs_root.path());
+        let ident = PackageIdent::from_str("dream-theater/systematic-chaos").unwrap();
+        let exclusions = Exclusions::new().release(newer.ident().clone());
+
+        let loaded = PackageInstall::load_excluding(&ident, Some(fs_root.path()), &exclusions);
+        assert_eq!(older, loaded.unwrap());
+        let loaded =
+            PackageInstall::load_at_least_excluding(&ident, Some(fs_root.path()), &exclusions);
+        assert_eq!(older, loaded.unwrap());
+
+        match PackageInstall::load_excluding(newer.ident(), Some(fs_root.path()), &exclusions) {
+            Err(Error::PackageNotFound(_)) => {}
+            other => panic!("Expected PackageNotFound, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn load_at_least_excluding_version_range() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        testing_package_install("dream-theater/systematic-chaos/1.1.1/20180704142702",
+                                fs_root.path());
+        testing_package_install("dream-theater/systematic-chaos/1.2.3/20180704142702",
+                                fs_root.path());
+        let ident = PackageIdent::from_str("dream-theater/systematic-chaos/1.1.0").unwrap();
+        let exclusions = Exclusions::new().versions(ident.clone(), Some("1.0"), None);
+
+        match PackageInstall::load_at_least_excluding(&ident, Some(fs_root.path()), &exclusions) {
+            Err(Error::PackageNotFound(_)) => {}
+            other => panic!("Expected PackageNotFound, got {:?}", other),
+        }
+    }
+
     #[test]
     fn paths_metafile_single() {
         let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
@@ -1306,7 +2304,9 @@ core/bar=pub:core/publish sub:core/subscribe
         expected.append(&mut paths_for(&foxtrot));
         expected.append(&mut paths_for(&golf));
 
-        assert_eq!(expected, alpha.legacy_runtime_paths().unwrap());
+        assert_eq!(expected,
+                   alpha.legacy_runtime_paths(&mut Diagnostics::new(MetafileStrictness::Strict))
+                        .unwrap());
     }
 
     #[test]
@@ -1314,8 +2314,9 @@ core/bar=pub:core/publish sub:core/subscribe
         let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
         let pkg_install = testing_package_install("acme/pathy", fs_root.path());
 
-        assert_eq!(HashMap::<String, String>::new(),
-                   pkg_install.environment_for_command().unwrap());
+        assert_eq!(HashMap::<OsString, OsString>::new(),
+                   pkg_install.environment_for_command(EnvironmentOptions::default())
+                              .unwrap());
     }
 
     #[test]
@@ -1332,7 +2333,61 @@ core/bar=pub:core/publish sub:core/subscribe
         expected.insert("FOO".to_string(), "bar".to_string());
         expected.insert("JAVA_HOME".to_string(), "/my/java/home".to_string());
 
-        assert_eq!(expected, pkg_install.environment_for_command().unwrap());
+        assert_eq!(expected,
+                   lossy_environment(pkg_install.environment_for_command(EnvironmentOptions::default())
+                                                .unwrap()));
+    }
+
+    #[test]
+    fn environment_for_command_with_malformed_runtime_environment() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        write_metafile(&pkg_install,
+                       MetaFile::RuntimeEnvironment,
+                       "JAVA_HOME=/my/java/home\nnot a variable\nFOO=bar\n");
+
+        match pkg_install.environment_for_command(EnvironmentOptions::default()) {
+            Err(Error::MetaFileMalformed(MetaFile::RuntimeEnvironment)) => (),
+            other => panic!("expected a malformed metafile error, got {:?}", other),
+        }
+
+        let opts = EnvironmentOptions { metafiles: MetafileStrictness::Lenient,
+                                        ..Default::default() };
+        let (env, diagnostics) = pkg_install.environment_with_diagnostics(opts).unwrap();
+
+        let mut expected = HashMap::new();
+        expected.insert("FOO".to_string(), "bar".to_string());
+        expected.insert("JAVA_HOME".to_string(), "/my/java/home".to_string());
+        assert_eq!(expected, lossy_environment(env));
+        let detail = "skipping line 2, which has no '='".to_string();
+        assert_eq!(vec![MetafileDiagnostic { ident: pkg_install.ident().clone(),
+                                             metafile: MetaFile::RuntimeEnvironment,
+                                             detail }],
+                   diagnostics);
+    }
+
+    #[test]
+    fn environment_for_command_skips_malformed_dependencies_when_lenient() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let dep = testing_package_install("acme/dep", fs_root.path());
+        set_path_for(&dep, &["bin"]);
+        let pkg_install = testing_package_install("acme/legacy", fs_root.path());
+        set_deps_for(&pkg_install, &[&dep]);
+        write_metafile(&pkg_install,
+                       MetaFile::TDeps,
+                       &format!("{}\nacme/broken/1.0.0/20190101000000/extra\n", dep.ident()));
+
+        assert!(pkg_install.environment_for_command(EnvironmentOptions::default())
+                           .is_err());
+
+        let opts = EnvironmentOptions { metafiles: MetafileStrictness::Lenient,
+                                        ..Default::default() };
+        let (env, diagnostics) = pkg_install.environment_with_diagnostics(opts).unwrap();
+
+        assert_eq!(env::join_paths(dep.paths().unwrap()).unwrap(),
+                   env[OsStr::new("PATH")]);
+        assert_eq!(1, diagnostics.len());
+        assert_eq!(MetaFile::TDeps, diagnostics[0].metafile);
     }
 
     #[test]
@@ -1368,6 +2423,295 @@ core/bar=pub:core/publish sub:core/subscribe
                         .into_owned(),
         );
 
-        assert_eq!(expected, pkg_install.environment_for_command().unwrap());
+        assert_eq!(expected,
+                   lossy_environment(pkg_install.environment_for_command(EnvironmentOptions::default())
+                                                .unwrap()));
+    }
+
+    #[test]
+    fn environment_for_command_with_deps_first_path_order() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+
+        let other_pkg_install = testing_package_install("acme/ty-tabor", fs_root.path());
+        set_path_for(&other_pkg_install, &["sbin"]);
+
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        set_path_for(&pkg_install, &["bin", "sbin"]);
+        set_runtime_path_for(&pkg_install, vec![&pkg_install, &other_pkg_install]);
+        let opts = EnvironmentOptions { path_order: PathOrder::DepsFirst,
+                                        ..Default::default() };
+
+        let env = lossy_environment(pkg_install.environment_for_command(opts).unwrap());
+
+        let fs_root_path = fs_root.path();
+        let expected = env::join_paths(vec![
+            fs::fs_rooted_path(
+                &pkg_prefix_for(&other_pkg_install).join("sbin"),
+                fs_root_path,
+            ),
+            fs::fs_rooted_path(&pkg_prefix_for(&pkg_install).join("bin"), fs_root_path),
+            fs::fs_rooted_path(&pkg_prefix_for(&pkg_install).join("sbin"), fs_root_path),
+        ]).unwrap();
+        assert_eq!(expected.to_string_lossy(), env["PATH"]);
+    }
+
+    #[test]
+    fn environment_for_command_appends_caller_path() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        set_path_for(&pkg_install, &["bin"]);
+        set_runtime_path_for(&pkg_install, vec![&pkg_install]);
+        let opts = EnvironmentOptions { append_caller_path: true,
+                                        ..Default::default() };
+
+        let env = lossy_environment(pkg_install.environment_for_command(opts).unwrap());
+
+        let mut expected =
+            vec![fs::fs_rooted_path(&pkg_prefix_for(&pkg_install).join("bin"), fs_root.path())];
+        if let Some(caller_path) = env::var_os("PATH") {
+            for path in env::split_paths(&caller_path) {
+                if !expected.contains(&path) {
+                    expected.push(path);
+                }
+            }
+        }
+        assert_eq!(env::join_paths(expected).unwrap().to_string_lossy(),
+                   env["PATH"]);
+    }
+
+    #[test]
+    fn find_command_in_own_paths() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        set_path_for(&pkg_install, &["sbin", "bin"]);
+        create_command(&pkg_install.installed_path().join("bin"), "pathy", true);
+
+        assert_eq!(Some(fs::fs_rooted_path(&pkg_prefix_for(&pkg_install).join("bin/pathy"),
+                                           fs_root.path())),
+                   pkg_install.find_command("pathy").unwrap());
+        assert_eq!(None, pkg_install.find_command("missing").unwrap());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn find_command_with_tdeps_skips_files_which_are_not_executable() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        set_path_for(&pkg_install, &["sbin", "bin"]);
+        set_runtime_path_for(&pkg_install, vec![&pkg_install]);
+        create_command(&pkg_install.installed_path().join("sbin"), "pathy", false);
+        create_command(&pkg_install.installed_path().join("bin"), "pathy", true);
+        create_command(&pkg_install.installed_path().join("bin"), "notes", false);
+
+        assert_eq!(Some(fs::fs_rooted_path(&pkg_prefix_for(&pkg_install).join("bin/pathy"),
+                                           fs_root.path())),
+                   pkg_install.find_command_with_tdeps("pathy").unwrap());
+        assert_eq!(None, pkg_install.find_command_with_tdeps("notes").unwrap());
+        // Only the runtime path search looks at execute bits
+        assert_eq!(Some(fs::fs_rooted_path(&pkg_prefix_for(&pkg_install).join("sbin/pathy"),
+                                           fs_root.path())),
+                   pkg_install.find_command("pathy").unwrap());
+    }
+
+    #[test]
+    fn find_command_with_tdeps_searches_runtime_path() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+
+        let other_pkg_install = testing_package_install("acme/ty-tabor", fs_root.path());
+        set_path_for(&other_pkg_install, &["bin"]);
+        let other_bin = other_pkg_install.installed_path().join("bin");
+        create_command(&other_bin, "tabor", true);
+
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        set_path_for(&pkg_install, &["bin"]);
+        set_runtime_path_for(&pkg_install, vec![&pkg_install, &other_pkg_install]);
+
+        assert_eq!(None, pkg_install.find_command("tabor").unwrap());
+        assert_eq!(Some(fs::fs_rooted_path(&pkg_prefix_for(&other_pkg_install).join("bin/tabor"),
+                                           fs_root.path())),
+                   pkg_install.find_command_with_tdeps("tabor").unwrap());
+    }
+
+    #[test]
+    fn env_snapshots_of_two_releases_can_be_diffed() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let old = testing_package_install("acme/app/1.0.0/20190101000000", fs_root.path());
+        let new = testing_package_install("acme/app/1.1.0/20190201000000", fs_root.path());
+        for install in &[&old, &new] {
+            set_path_for(install, &["bin"]);
+            set_runtime_path_for(install, vec![install]);
+        }
+        write_metafile(&new,
+                       MetaFile::RuntimeEnvironment,
+                       "LD_LIBRARY_PATH=/opt/lib\n");
+
+        let diff = env_diff(&old.env_snapshot().unwrap(), &new.env_snapshot().unwrap());
+
+        assert_eq!(vec![pkg_prefix_for(&new).join("bin")],
+                   diff[PATH_KEY].entries_added());
+        assert_eq!(EnvChange::Added("/opt/lib".to_string()),
+                   diff["LD_LIBRARY_PATH"]);
+        assert_eq!(old.env_snapshot().unwrap(), old.env_snapshot().unwrap());
+    }
+
+    #[test]
+    fn environment_with_overrides_swaps_replaced_deps() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install =
+            testing_package_install("acme/curl/7.64.0/20190401000000", fs_root.path());
+        let listed = testing_package_install("core/openssl/1.0.2r/20190305210149", fs_root.path());
+        let local = testing_package_install("core/openssl/1.1.1b/20190410000000", fs_root.path());
+        let zlib = testing_package_install("core/zlib/1.2.11/20190115003728", fs_root.path());
+        for install in &[&pkg_install, &listed, &local, &zlib] {
+            set_path_for(install, &["bin"]);
+        }
+        set_tdeps_for(&pkg_install, &[&listed, &zlib]);
+        set_runtime_path_for(&pkg_install, vec![&pkg_install, &listed, &zlib]);
+        write_metafile(&pkg_install,
+                       MetaFile::RuntimeEnvironment,
+                       &format!("LD_LIBRARY_PATH={}\n",
+                                pkg_prefix_for(&listed).join("lib").display()));
+        let overrides =
+            DepOverrides::new().replace(PackageIdent::from_str("core/openssl").unwrap(),
+                                        PackageIdent::from_str("core/openssl/1.1.1b").unwrap());
+
+        let env =
+            lossy_environment(pkg_install.environment_with_overrides(EnvironmentOptions::default(),
+                                                                     &overrides)
+                                         .unwrap());
+
+        let expected_path = env::join_paths(&[pkg_prefix_for(&pkg_install).join("bin"),
+                                              pkg_prefix_for(&local).join("bin"),
+                                              pkg_prefix_for(&zlib).join("bin")]).unwrap();
+        assert_eq!(expected_path.to_string_lossy(), env[PATH_KEY].as_str());
+        assert_eq!(pkg_prefix_for(&local).join("lib").to_string_lossy(),
+                   env["LD_LIBRARY_PATH"].as_str());
+        assert_eq!(vec![local, zlib],
+                   pkg_install.load_tdeps_with_overrides(&overrides).unwrap());
+    }
+
+    #[test]
+    fn environment_for_command_does_not_expand_references_by_default() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        write_metafile(&pkg_install,
+                       MetaFile::RuntimeEnvironment,
+                       "JAVA_HOME=${pkg.path}/java\nFOO=$JAVA_HOME\n");
+
+        let env =
+            lossy_environment(pkg_install.environment_for_command(EnvironmentOptions::default())
+                                         .unwrap());
+
+        assert_eq!("${pkg.path}/java", env["JAVA_HOME"]);
+        assert_eq!("$JAVA_HOME", env["FOO"]);
+    }
+
+    #[test]
+    fn environment_for_command_expands_references() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        write_metafile(&pkg_install,
+                       MetaFile::RuntimeEnvironment,
+                       "JAVA_HOME=${pkg.path}/java\nJRE_HOME=${JAVA_HOME}/jre\nFOO=$JRE_HOME:\
+                        $$HOME:$UNKNOWN:${pkg.nope}\nIDENT=${pkg.ident}\n");
+        let opts = EnvironmentOptions { expand_references: true,
+                                        ..Default::default() };
+
+        let env = lossy_environment(pkg_install.environment_for_command(opts).unwrap());
+
+        let java_home = format!("{}/java", pkg_install.installed_path().display());
+        assert_eq!(java_home, env["JAVA_HOME"]);
+        assert_eq!(format!("{}/jre", java_home), env["JRE_HOME"]);
+        assert_eq!(format!("{}/jre:$HOME:$UNKNOWN:${{pkg.nope}}", java_home),
+                   env["FOO"]);
+        assert_eq!(pkg_install.ident().to_string(), env["IDENT"]);
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn environment_for_command_keeps_values_which_are_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        std::fs::write(pkg_install.installed_path()
+                                  .join(MetaFile::RuntimeEnvironment.to_string()),
+                       b"LATIN1=caf\xe9\nREF=${LATIN1}/x\n").unwrap();
+        let opts = EnvironmentOptions { expand_references: true,
+                                        ..Default::default() };
+
+        let env = pkg_install.environment_for_command(opts).unwrap();
+
+        assert_eq!(OsStr::from_bytes(b"caf\xe9"), env[OsStr::new("LATIN1")]);
+        assert_eq!(OsStr::from_bytes(b"caf\xe9/x"), env[OsStr::new("REF")]);
+        assert_eq!("caf\u{FFFD}", lossy_environment(env)["LATIN1"]);
+    }
+
+    #[test]
+    fn environment_for_command_with_reference_cycle_returns_err() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        write_metafile(&pkg_install,
+                       MetaFile::RuntimeEnvironment,
+                       "ALPHA=$BETA\nBETA=${GAMMA}\nGAMMA=$ALPHA\n");
+        let opts = EnvironmentOptions { expand_references: true,
+                                        ..Default::default() };
+
+        match pkg_install.environment_for_command(opts) {
+            Err(Error::RuntimeEnvironmentCycle(_)) => (),
+            Err(e) => panic!("Wrong error returned, error={:?}", e),
+            Ok(env) => panic!("Should not expand a cycle, env={:?}", env),
+        }
+    }
+
+    /// Lays out an installed package in `filesystem`, with the given metafiles besides its
+    /// `IDENT` and `TARGET`.
+    fn memory_package(filesystem: &fs::MemoryFileSystem,
+                      ident: &str,
+                      metafiles: &[(MetaFile, &str)]) {
+        let ident = PackageIdent::from_str(ident).unwrap();
+        let installed_path = fs::pkg_install_path(&ident, None::<&Path>);
+        let write = |metafile: MetaFile, body: &str| {
+            filesystem.write(&installed_path.join(metafile.to_string()), body.as_bytes())
+                      .unwrap();
+        };
+        write(MetaFile::Ident, &ident.to_string());
+        write(MetaFile::Target, &PackageTarget::active_target());
+        for &(metafile, body) in metafiles {
+            write(metafile, body);
+        }
+    }
+
+    #[test]
+    fn load_from_resolves_in_another_filesystem() {
+        let filesystem = Arc::new(fs::MemoryFileSystem::new());
+        memory_package(&filesystem, "core/redis/4.0.14/20190115013919", &[]);
+        memory_package(&filesystem,
+                       "core/redis/5.0.4/20190419155852",
+                       &[(MetaFile::Deps, "core/glibc/2.27/20190115002733"),
+                         (MetaFile::TDeps, "core/glibc/2.27/20190115002733"),
+                         (MetaFile::Exposes, "6379")]);
+        memory_package(&filesystem, "core/glibc/2.27/20190115002733", &[]);
+        let ident = PackageIdent::from_str("core/redis").unwrap();
+
+        let pkg_install = PackageInstall::load_from(filesystem.clone(), &ident, None).unwrap();
+
+        assert_eq!("core/redis/5.0.4/20190419155852",
+                   pkg_install.ident().to_string());
+        assert_eq!(vec!["6379"], pkg_install.exposes().unwrap());
+        assert_eq!(vec!["core/glibc/2.27/20190115002733"],
+                   pkg_install.deps()
+                              .unwrap()
+                              .iter()
+                              .map(ToString::to_string)
+                              .collect::<Vec<_>>());
+        assert_eq!(1, pkg_install.load_tdeps().unwrap().len());
+        match PackageInstall::load_from(filesystem,
+                                        &PackageIdent::from_str("core/nginx").unwrap(),
+                                        None)
+        {
+            Err(Error::PackageNotFound(_)) => {}
+            other => panic!("Expected PackageNotFound, got {:?}", other),
+        }
     }
 }
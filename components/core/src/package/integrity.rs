@@ -0,0 +1,397 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Read-only integrity checks of installed packages, for `PackageInstall::load_verified`.
+//!
+//! Each `VerifyLevel` includes the checks of the levels before it:
+//!
+//! * `metadata`: the `IDENT` metafile names the package it was loaded as, and the `TARGET` metafile
+//!   names the active target
+//! * `files`: the `FILES` metafile carries a valid signature from a key in the key cache, and every
+//!   file listed in it has the checksum recorded for it
+//! * `signature`: the package's artifact in the artifact cache carries a valid signature from a key
+//!   in the key cache, is of the same package, and holds the same `FILES` metafile
+
+use std::{fmt,
+          path::{Component,
+                 Path,
+                 PathBuf},
+          str::{self,
+                FromStr}};
+
+use crate::{crypto::{artifact,
+                     hash,
+                     HART_FORMAT_VERSION},
+            error::{Error,
+                    Result},
+            fs::{self,
+                 FileSystem},
+            package::{metadata::{read_metafile_bytes_from,
+                                 read_metafile_from,
+                                 MetaFile},
+                      PackageArchive,
+                      PackageIdent,
+                      PackageInstall,
+                      PackageTarget}};
+
+/// How thoroughly an installed package is checked.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum VerifyLevel {
+    /// The package's identity and target are as expected.
+    Metadata,
+    /// As `Metadata`, and the package's signed list of files, and the files themselves, are as
+    /// they were built.
+    Files,
+    /// As `Files`, and the package's cached artifact is signed, of the same package, and holds
+    /// the same files.
+    Signature,
+}
+
+impl VerifyLevel {
+    /// Every level, from the least to the most thorough.
+    pub fn variants() -> &'static [Self] {
+        &[VerifyLevel::Metadata,
+          VerifyLevel::Files,
+          VerifyLevel::Signature]
+    }
+}
+
+impl fmt::Display for VerifyLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match *self {
+            VerifyLevel::Metadata => "metadata",
+            VerifyLevel::Files => "files",
+            VerifyLevel::Signature => "signature",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+impl FromStr for VerifyLevel {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_ref() {
+            "metadata" => Ok(VerifyLevel::Metadata),
+            "files" => Ok(VerifyLevel::Files),
+            "signature" => Ok(VerifyLevel::Signature),
+            _ => Err(Error::BadVerifyLevel(value.to_string())),
+        }
+    }
+}
+
+/// Checks `pkg_install`, as read from `filesystem` under `fs_root_path`, to `level`. Nothing is
+/// written.
+///
+/// # Failures
+///
+/// * A check fails, with an `IntegrityCheckFailed` error saying why
+/// * A metafile, file or artifact cannot be read
+pub(crate) fn verify(filesystem: &dyn FileSystem,
+                     pkg_install: &PackageInstall,
+                     fs_root_path: &Path,
+                     level: VerifyLevel)
+                     -> Result<()> {
+    verify_metadata(filesystem, pkg_install)?;
+    if level >= VerifyLevel::Files {
+        verify_files(filesystem, pkg_install, fs_root_path)?;
+    }
+    if level >= VerifyLevel::Signature {
+        verify_signature(filesystem, pkg_install, fs_root_path)?;
+    }
+    Ok(())
+}
+
+fn failed(pkg_install: &PackageInstall, detail: String) -> Error {
+    Error::IntegrityCheckFailed(pkg_install.ident().to_string(), detail)
+}
+
+fn verify_metadata(filesystem: &dyn FileSystem, pkg_install: &PackageInstall) -> Result<()> {
+    let path = pkg_install.installed_path();
+    let ident = read_metafile_from(filesystem, path, MetaFile::Ident)?;
+    if PackageIdent::from_str(&ident).ok().as_ref() != Some(pkg_install.ident()) {
+        return Err(failed(pkg_install,
+                          format!("its {} metafile names {}",
+                                  MetaFile::Ident,
+                                  ident)));
+    }
+    let target = read_metafile_from(filesystem, path, MetaFile::Target)?;
+    if PackageTarget::from_str(&target).ok() != Some(PackageTarget::active_target()) {
+        return Err(failed(pkg_install,
+                          format!("its {} metafile names {}, not the active \
+                                   target {}",
+                                  MetaFile::Target,
+                                  target,
+                                  PackageTarget::active_target())));
+    }
+    Ok(())
+}
+
+fn verify_files(filesystem: &dyn FileSystem,
+                pkg_install: &PackageInstall,
+                fs_root_path: &Path)
+                -> Result<()> {
+    let data = read_metafile_bytes_from(filesystem, pkg_install.installed_path(), MetaFile::Files)?;
+    if !data.starts_with(HART_FORMAT_VERSION.as_bytes()) {
+        return Err(failed(pkg_install,
+                          format!("its {} metafile is not signed",
+                                  MetaFile::Files)));
+    }
+    if let Err(e) = artifact::verify_reader(&data[..], &fs::cache_key_path(Some(fs_root_path))) {
+        return Err(failed(pkg_install,
+                          format!("its {} metafile's signature cannot be \
+                                   verified: {}",
+                                  MetaFile::Files,
+                                  e)));
+    }
+    let body = str::from_utf8(&data).map_err(|_| Error::MetaFileMalformed(MetaFile::Files))?;
+    for (checksum, file) in files_entries(body)? {
+        let path = file_path(pkg_install.installed_path(), fs_root_path, file);
+        if !path.starts_with(pkg_install.installed_path()) {
+            return Err(failed(pkg_install,
+                              format!("its {} metafile lists {}, which is \
+                                       outside the package",
+                                      MetaFile::Files,
+                                      file)));
+        }
+        let content = match filesystem.read(&path) {
+            Ok(content) => content,
+            Err(e) => return Err(failed(pkg_install, format!("{} cannot be read: {}", file, e))),
+        };
+        if hash::hash_bytes(&content) != checksum {
+            return Err(failed(pkg_install, format!("{} has been modified", file)));
+        }
+    }
+    Ok(())
+}
+
+fn verify_signature(filesystem: &dyn FileSystem,
+                    pkg_install: &PackageInstall,
+                    fs_root_path: &Path)
+                    -> Result<()> {
+    let ident = pkg_install.ident();
+    let path = fs::cache_artifact_path(Some(fs_root_path))
+        .join(ident.archive_name_with_target(PackageTarget::active_target())?);
+    if !path.is_file() {
+        return Err(failed(pkg_install,
+                          format!("there is no cached artifact at {}",
+                                  path.display())));
+    }
+    let mut archive = PackageArchive::new(path);
+    if let Err(e) = archive.verify(&fs::cache_key_path(Some(fs_root_path))) {
+        return Err(failed(pkg_install,
+                          format!("its cached artifact's signature cannot \
+                                   be verified: {}",
+                                  e)));
+    }
+    let archived = archive.ident()?;
+    if archived != *ident {
+        return Err(failed(pkg_install,
+                          format!("its cached artifact is of {}", archived)));
+    }
+    let installed =
+        read_metafile_bytes_from(filesystem, pkg_install.installed_path(), MetaFile::Files)?;
+    if archive.files_metafile()? != Some(installed) {
+        return Err(failed(pkg_install,
+                          format!("its {} metafile differs from its cached \
+                                   artifact's",
+                                  MetaFile::Files)));
+    }
+    Ok(())
+}
+
+/// Parses the body of a `FILES` metafile into checksums and the files they are of. The body
+/// may be signed, in which case it starts with an artifact header, which is passed over.
+fn files_entries(body: &str) -> Result<Vec<(&str, &str)>> {
+    let mut lines = body.lines();
+    if body.starts_with(HART_FORMAT_VERSION) {
+        lines.find(|line| line.is_empty());
+    }
+    lines.filter(|line| !line.trim().is_empty())
+         .map(|line| {
+             let mut parts = line.trim_start().splitn(2, char::is_whitespace);
+             match (parts.next(), parts.next().map(str::trim)) {
+                 (Some(checksum), Some(file)) if !file.is_empty() => Ok((checksum, file)),
+                 _ => Err(Error::MetaFileMalformed(MetaFile::Files)),
+             }
+         })
+         .collect()
+}
+
+/// Where a file listed in a `FILES` metafile is: relative to the package's installed path, or
+/// under the filesystem root when the listing is absolute. Any `.` and `..` components are
+/// resolved, so that a listing cannot escape the package unnoticed.
+fn file_path(installed_path: &Path, fs_root_path: &Path, file: &str) -> PathBuf {
+    let file = Path::new(file);
+    let mut path = if file.has_root() {
+        fs_root_path.to_path_buf()
+    } else {
+        installed_path.to_path_buf()
+    };
+    for component in file.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{crypto::SigKeyPair,
+                package::test_support::testing_package_install};
+    use std::fs as stdfs;
+    use tempfile::{Builder,
+                   TempDir};
+
+    const IDENT: &str = "core/redis/4.0.14/20190319155852";
+
+    fn installed() -> (TempDir, PackageInstall) {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install(IDENT, fs_root.path());
+        let bin = pkg_install.installed_path().join("bin");
+        stdfs::create_dir_all(&bin).unwrap();
+        stdfs::write(bin.join("redis-server"), "#!/bin/sh\n").unwrap();
+        write_files(&pkg_install,
+                    &fs_root,
+                    &format!("{}  ./bin/redis-server\n", hash::hash_bytes(b"#!/bin/sh\n")));
+        (fs_root, pkg_install)
+    }
+
+    /// Writes `listing` as the package's `FILES` metafile, signed by a `core` key kept in the key
+    /// cache under `fs_root`.
+    fn write_files(pkg_install: &PackageInstall, fs_root: &TempDir, listing: &str) {
+        let cache = fs::cache_key_path(Some(fs_root.path()));
+        let pair = SigKeyPair::get_latest_pair_for("core", &cache, None).unwrap_or_else(|_| {
+                       stdfs::create_dir_all(&cache).unwrap();
+                       let pair = SigKeyPair::generate_pair_for_origin("core").unwrap();
+                       pair.to_pair_files(&cache).unwrap();
+                       pair
+                   });
+        let unsigned = fs_root.path().join("FILES.unsigned");
+        stdfs::write(&unsigned, listing).unwrap();
+        artifact::sign(&unsigned,
+                       &pkg_install.installed_path()
+                                   .join(MetaFile::Files.to_string()),
+                       &pair).unwrap();
+    }
+
+    fn verify_real(pkg_install: &PackageInstall,
+                   fs_root: &TempDir,
+                   level: VerifyLevel)
+                   -> Result<()> {
+        verify(&*fs::real_file_system(), pkg_install, fs_root.path(), level)
+    }
+
+    #[test]
+    fn levels_parse_and_display() {
+        for level in VerifyLevel::variants() {
+            assert_eq!(*level, level.to_string().parse().unwrap());
+        }
+        assert_eq!(VerifyLevel::Files, "FILES".parse().unwrap());
+        assert!("all".parse::<VerifyLevel>().is_err());
+    }
+
+    #[test]
+    fn intact_packages_pass_up_to_their_files() {
+        let (fs_root, pkg_install) = installed();
+
+        verify_real(&pkg_install, &fs_root, VerifyLevel::Metadata).unwrap();
+        verify_real(&pkg_install, &fs_root, VerifyLevel::Files).unwrap();
+        match verify_real(&pkg_install, &fs_root, VerifyLevel::Signature) {
+            Err(Error::IntegrityCheckFailed(_, ref detail)) => {
+                assert!(detail.contains("no cached artifact"), "{}", detail)
+            }
+            other => panic!("Expected a missing artifact to fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mismatched_ident_fails_metadata() {
+        let (fs_root, pkg_install) = installed();
+        stdfs::write(pkg_install.installed_path()
+                                .join(MetaFile::Ident.to_string()),
+                     "core/redis/4.0.14/20190101000000").unwrap();
+
+        match verify_real(&pkg_install, &fs_root, VerifyLevel::Metadata) {
+            Err(Error::IntegrityCheckFailed(ref ident, _)) => assert_eq!(IDENT, ident),
+            other => panic!("Expected a mismatched IDENT to fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn modified_and_escaping_files_fail() {
+        let (fs_root, pkg_install) = installed();
+
+        stdfs::write(pkg_install.installed_path().join("bin/redis-server"),
+                     "#!/bin/sh\nrm -rf /\n").unwrap();
+        assert!(verify_real(&pkg_install, &fs_root, VerifyLevel::Metadata).is_ok());
+        assert!(verify_real(&pkg_install, &fs_root, VerifyLevel::Files).is_err());
+
+        write_files(&pkg_install,
+                    &fs_root,
+                    &format!("{}  ../../../../../../etc/passwd\n", hash::hash_bytes(b"")));
+        match verify_real(&pkg_install, &fs_root, VerifyLevel::Files) {
+            Err(Error::IntegrityCheckFailed(_, ref detail)) => {
+                assert!(detail.contains("outside the package"), "{}", detail)
+            }
+            other => panic!("Expected an escaping file to fail, got {:?}", other),
+        }
+
+        write_files(&pkg_install, &fs_root, "not-a-listing\n");
+        assert!(verify_real(&pkg_install, &fs_root, VerifyLevel::Files).is_err());
+    }
+
+    #[test]
+    fn unsigned_and_altered_files_metafiles_fail() {
+        let (fs_root, pkg_install) = installed();
+        let files = pkg_install.installed_path()
+                               .join(MetaFile::Files.to_string());
+        let signed = stdfs::read_to_string(&files).unwrap();
+        let expect_failure = |expected: &str| {
+            match verify_real(&pkg_install, &fs_root, VerifyLevel::Files) {
+                Err(Error::IntegrityCheckFailed(_, ref detail)) => {
+                    assert!(detail.contains(expected), "{}", detail)
+                }
+                other => panic!("Expected {} to fail, got {:?}", expected, other),
+            }
+        };
+
+        stdfs::write(&files,
+                     format!("{}  ./bin/redis-server\n", hash::hash_bytes(b"#!/bin/sh\n"))).unwrap();
+        expect_failure("not signed");
+
+        stdfs::write(&files,
+                     format!("{}{}  ./bin/sh\n", signed, hash::hash_bytes(b""))).unwrap();
+        expect_failure("signature cannot be verified");
+    }
+
+    #[test]
+    fn absolute_listings_are_under_the_fs_root() {
+        let fs_root = Path::new("/tmp/root");
+        let installed_path = fs_root.join("hab/pkgs/core/redis/4.0.14/20190319155852");
+
+        assert_eq!(installed_path.join("bin/redis-server"),
+                   file_path(&installed_path,
+                             fs_root,
+                             "/hab/pkgs/core/redis/4.0.14/20190319155852/bin/redis-server"));
+        assert_eq!(installed_path.join("bin/redis-server"),
+                   file_path(&installed_path, fs_root, "./bin/../bin/redis-server"));
+    }
+}
@@ -12,13 +12,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{metadata::{read_metafile,
+use super::{hold::HOLDS_DIR,
+            ignore::IgnoreList,
+            metadata::{read_metafile_from,
                        MetaFile},
             PackageIdent,
             PackageTarget};
-use crate::error::{Error,
-                   Result};
-use std::{ffi::OsStr,
+use crate::{error::{Error,
+                    Result},
+            fs::{access_error,
+                 Access,
+                 FileSystem,
+                 RealFileSystem},
+            util::CancellationToken};
+use std::{collections::HashMap,
+          ffi::OsStr,
+          fmt,
           fs,
           io,
           path::{Path,
@@ -29,6 +38,33 @@ use tempfile::{Builder,
 
 pub const INSTALL_TMP_PREFIX: &str = ".hab-pkg-install";
 
+/// The package targets a walk of a package root yields packages of.
+#[derive(Clone, Copy, Debug)]
+enum Targets {
+    Only(PackageTarget),
+    All,
+}
+
+impl Targets {
+    fn active() -> Self { Targets::Only(PackageTarget::active_target()) }
+
+    fn includes(self, target: PackageTarget) -> bool {
+        match self {
+            Targets::Only(only) => only == target,
+            Targets::All => true,
+        }
+    }
+}
+
+impl fmt::Display for Targets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Targets::Only(target) => write!(f, "{}", target),
+            Targets::All => write!(f, "any"),
+        }
+    }
+}
+
 /// Return a directory which can be used as a temp dir during package install/
 /// uninstall
 ///
@@ -55,14 +91,173 @@ pub fn temp_package_directory(path: &Path) -> Result<TempDir> {
 }
 
 /// Returns a list of package structs built from the contents of the given directory.
+///
+/// Here and in the other functions which walk a package root, any packages its ignore file
+/// matches are skipped.
 pub fn all_packages(path: &Path) -> Result<Vec<PackageIdent>> {
-    let mut package_list: Vec<PackageIdent> = vec![];
+    all_packages_of(path, Targets::active()).map(idents)
+}
+
+/// As `all_packages`, but returning the packages built for `target` rather than for the active
+/// target, such as those of a Windows package root mounted on a Linux system.
+pub fn all_packages_for_target(path: &Path, target: PackageTarget) -> Result<Vec<PackageIdent>> {
+    all_packages_of(path, Targets::Only(target)).map(idents)
+}
+
+/// As `all_packages`, but returning the packages built for every target, by target.
+pub fn all_packages_by_target(path: &Path) -> Result<HashMap<PackageTarget, Vec<PackageIdent>>> {
+    let mut by_target = HashMap::<_, Vec<_>>::new();
+    for (ident, target) in all_packages_of(path, Targets::All)? {
+        by_target.entry(target).or_default().push(ident);
+    }
+    Ok(by_target)
+}
+
+fn all_packages_of(path: &Path, targets: Targets) -> Result<Vec<(PackageIdent, PackageTarget)>> {
+    let mut package_list = vec![];
     if fs::metadata(path)?.is_dir() {
-        walk_origins(&path, &mut package_list)?;
+        let ignore = IgnoreList::load(path)?;
+        walk_origins(path, &ignore, targets, &mut package_list)?;
     }
     Ok(package_list)
 }
 
+fn idents(packages: Vec<(PackageIdent, PackageTarget)>) -> Vec<PackageIdent> {
+    packages.into_iter().map(|(ident, _)| ident).collect()
+}
+
+/// Returns an iterator over the packages in the given directory, which reads the directory
+/// lazily as it is advanced rather than building a full list up front. The order of the
+/// packages is unspecified.
+pub fn packages_iter(path: &Path) -> Result<Packages> { Packages::new(path, None) }
+
+/// As `packages_iter`, but only yielding packages which satisfy the given ident, so that only
+/// the directories which could contain them are read.
+pub fn packages_iter_for_ident(path: &Path, ident: &PackageIdent) -> Result<Packages> {
+    Packages::new(path, Some(ident.clone()))
+}
+
+/// A lazy iterator over installed packages, created by `packages_iter` or
+/// `packages_iter_for_ident`.
+///
+/// Yields an error, and carries on, for any directory which cannot be read.
+pub struct Packages {
+    targets: Targets,
+    filter:  Option<PackageIdent>,
+    ignore:  IgnoreList,
+    /// The directories being read, from the package root down to a version directory.
+    dirs:    Vec<fs::ReadDir>,
+    /// The origin, name, and version of the directories below the package root in `dirs`.
+    parts:   Vec<String>,
+    cancel:  Option<CancellationToken>,
+}
+
+impl Packages {
+    fn new(path: &Path, filter: Option<PackageIdent>) -> Result<Self> {
+        let mut dirs = Vec::new();
+        let mut ignore = IgnoreList::default();
+        if is_existing_dir(&RealFileSystem, path)? {
+            dirs.push(fs::read_dir(path)?);
+            ignore = IgnoreList::load(path)?;
+        }
+        Ok(Packages { targets: Targets::active(),
+                      filter,
+                      ignore,
+                      dirs,
+                      parts: Vec::new(),
+                      cancel: None })
+    }
+
+    /// Yields the packages built for `target` rather than for the active target.
+    pub fn for_target(mut self, target: PackageTarget) -> Self {
+        self.targets = Targets::Only(target);
+        self
+    }
+
+    /// Stops the walk once `cancel` is cancelled: the next call to `next` yields
+    /// `Error::Cancelled`, and the iterator ends after it.
+    pub fn cancel_on(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Whether a directory named `name`, at `depth` below the package root, could contain
+    /// packages which satisfy the filter.
+    fn is_wanted(&self, depth: usize, name: &str) -> bool {
+        match depth {
+            1 if name == HOLDS_DIR || self.ignore.is_origin_ignored(name) => return false,
+            2 if self.ignore.is_ignored(&self.parts[0], name) => return false,
+            _ => {}
+        }
+        let filter = match self.filter {
+            Some(ref filter) => filter,
+            None => return true,
+        };
+        let wanted = match depth {
+            1 => Some(&filter.origin),
+            2 => Some(&filter.name),
+            3 => filter.version.as_ref(),
+            _ => filter.release.as_ref(),
+        };
+        wanted.map_or(true, |wanted| wanted == name)
+    }
+}
+
+impl Iterator for Packages {
+    type Item = Result<PackageIdent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let cancelled = self.cancel
+                                .as_ref()
+                                .map_or(false, CancellationToken::is_cancelled);
+            if cancelled && !self.dirs.is_empty() {
+                self.dirs.clear();
+                self.parts.clear();
+                return Some(Err(Error::Cancelled));
+            }
+            let depth = self.dirs.len();
+            let entry = match self.dirs.last_mut()?.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(err)) => return Some(Err(err.into())),
+                None => {
+                    self.dirs.pop();
+                    self.parts.pop();
+                    continue;
+                }
+            };
+            let name = filename_from_entry(&entry);
+            if !self.is_wanted(depth, &name) {
+                continue;
+            }
+            let path = entry.path();
+            let dir = path.parent().unwrap_or(&path);
+            match is_walkable_dir(&RealFileSystem, dir, &path) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(err) => return Some(Err(err)),
+            }
+            if depth < 4 {
+                match fs::read_dir(&path) {
+                    Ok(dir) => {
+                        self.dirs.push(dir);
+                        self.parts.push(name);
+                    }
+                    Err(err) => return Some(Err(err.into())),
+                }
+            } else if let Some((ident, _)) = package_ident_from_dir(&RealFileSystem,
+                                                                    &self.parts[0],
+                                                                    &self.parts[1],
+                                                                    &self.parts[2],
+                                                                    self.targets,
+                                                                    &path)
+            {
+                return Some(Ok(ident));
+            }
+        }
+    }
+}
+
 /// Returns a vector of package idents built from the contents of
 /// the given directory, using the given origin to restrict the
 /// search.
@@ -72,16 +267,23 @@ pub fn all_packages(path: &Path) -> Result<Vec<PackageIdent>> {
 ///
 ///    /base/ORIGIN/NAME/VERSION/RELEASE/
 pub fn package_list_for_origin(base_pkg_path: &Path, origin: &str) -> Result<Vec<PackageIdent>> {
-    let mut package_list: Vec<PackageIdent> = vec![];
+    let mut package_list = vec![];
     let mut package_path = PathBuf::from(base_pkg_path);
     package_path.push(&origin);
 
-    if !is_existing_dir(&package_path)? {
-        return Ok(package_list);
+    let ignore = IgnoreList::load(base_pkg_path)?;
+    if ignore.is_origin_ignored(origin)
+       || !is_walkable_dir(&RealFileSystem, base_pkg_path, &package_path)?
+    {
+        return Ok(Vec::new());
     };
 
-    walk_names(&origin, &package_path, &mut package_list)?;
-    Ok(package_list)
+    walk_names(&origin,
+               &package_path,
+               &ignore,
+               Targets::active(),
+               &mut package_list)?;
+    Ok(idents(package_list))
 }
 
 /// Returns a vector of package structs built from the contents of
@@ -95,46 +297,84 @@ pub fn package_list_for_origin(base_pkg_path: &Path, origin: &str) -> Result<Vec
 pub fn package_list_for_ident(base_pkg_path: &Path,
                               ident: &PackageIdent)
                               -> Result<Vec<PackageIdent>> {
-    let mut package_list: Vec<PackageIdent> = vec![];
-    let mut package_path = PathBuf::from(base_pkg_path);
-    package_path.push(&ident.origin);
-    package_path.push(&ident.name);
+    package_list_for_ident_from(&RealFileSystem, base_pkg_path, ident)
+}
+
+/// As `package_list_for_ident`, but returning the packages built for `target` rather than for
+/// the active target.
+pub fn package_list_for_ident_and_target(base_pkg_path: &Path,
+                                         ident: &PackageIdent,
+                                         target: PackageTarget)
+                                         -> Result<Vec<PackageIdent>> {
+    package_list_of(&RealFileSystem, base_pkg_path, ident, Targets::Only(target)).map(idents)
+}
+
+/// As `package_list_for_ident`, but reading the package root from `filesystem`.
+pub fn package_list_for_ident_from(filesystem: &dyn FileSystem,
+                                   base_pkg_path: &Path,
+                                   ident: &PackageIdent)
+                                   -> Result<Vec<PackageIdent>> {
+    package_list_of(filesystem, base_pkg_path, ident, Targets::active()).map(idents)
+}
 
-    if !is_existing_dir(&package_path)? {
+fn package_list_of(filesystem: &dyn FileSystem,
+                   base_pkg_path: &Path,
+                   ident: &PackageIdent,
+                   targets: Targets)
+                   -> Result<Vec<(PackageIdent, PackageTarget)>> {
+    let mut package_list = vec![];
+    let origin_path = base_pkg_path.join(&ident.origin);
+    let name_path = origin_path.join(&ident.name);
+
+    if IgnoreList::load_from(filesystem, base_pkg_path)?.is_ignored(&ident.origin, &ident.name)
+       || !is_walkable_dir(filesystem, base_pkg_path, &origin_path)?
+       || !is_walkable_dir(filesystem, &origin_path, &name_path)?
+    {
         return Ok(package_list);
     }
 
     match (&ident.version, &ident.release) {
         // origin/name
-        (None, _) => walk_versions(&ident.origin, &ident.name, &package_path, &mut package_list)?,
+        (None, _) => {
+            walk_versions(filesystem,
+                          &ident.origin,
+                          &ident.name,
+                          &name_path,
+                          targets,
+                          &mut package_list)?
+        }
         // origin/name/version
         (Some(version), None) => {
-            package_path.push(version);
-            if !is_existing_dir(&package_path)? {
+            let version_path = name_path.join(version);
+            if !is_walkable_dir(filesystem, &name_path, &version_path)? {
                 return Ok(package_list);
             }
-            walk_releases(&ident.origin,
+            walk_releases(filesystem,
+                          &ident.origin,
                           &ident.name,
                           &version,
-                          &package_path,
+                          &version_path,
+                          targets,
                           &mut package_list)?
         }
         // origin/name/version/release
         (Some(version), Some(release)) => {
-            package_path.push(version);
-            package_path.push(release);
-            if !is_existing_dir(&package_path)? {
+            let version_path = name_path.join(version);
+            let release_path = version_path.join(release);
+            if !is_walkable_dir(filesystem, &name_path, &version_path)?
+               || !is_walkable_dir(filesystem, &version_path, &release_path)?
+            {
                 return Ok(package_list);
             }
 
-            let active_target = PackageTarget::active_target();
-            if let Some(new_ident) = package_ident_from_dir(&ident.origin,
-                                                            &ident.name,
-                                                            &version,
-                                                            active_target,
-                                                            &package_path)
+            if let Some(found) = package_ident_from_dir(filesystem,
+                                                        &ident.origin,
+                                                        &ident.name,
+                                                        &version,
+                                                        targets,
+                                                        &release_path)
             {
-                package_list.push(new_ident.clone())
+                package_list.push(found)
             }
         }
     }
@@ -144,13 +384,20 @@ pub fn package_list_for_ident(base_pkg_path: &Path,
 /// Helper function for all_packages. Walks the directory at the given
 /// Path for origin directories and builds on the given package list
 /// by recursing into name, version, and release directories.
-fn walk_origins(path: &Path, packages: &mut Vec<PackageIdent>) -> Result<()> {
-    for entry in fs::read_dir(path)? {
+fn walk_origins(path: &Path,
+                ignore: &IgnoreList,
+                targets: Targets,
+                packages: &mut Vec<(PackageIdent, PackageTarget)>)
+                -> Result<()> {
+    for entry in fs::read_dir(path).map_err(walk_error(path))? {
         let origin_dir = entry?;
         let origin_path = origin_dir.path();
-        if fs::metadata(&origin_path)?.is_dir() {
-            let origin = filename_from_entry(&origin_dir);
-            walk_names(&origin, &origin_path, packages)?;
+        let origin = filename_from_entry(&origin_dir);
+        if origin == HOLDS_DIR || ignore.is_origin_ignored(&origin) {
+            continue;
+        }
+        if is_walkable_dir(&RealFileSystem, path, &origin_path)? {
+            walk_names(&origin, &origin_path, ignore, targets, packages)?;
         }
     }
     Ok(())
@@ -159,13 +406,26 @@ fn walk_origins(path: &Path, packages: &mut Vec<PackageIdent>) -> Result<()> {
 /// Helper function for walk_origins. Walks the direcotry at the given
 /// Path for name directories and recurses into them to find version
 /// and release directories.
-fn walk_names(origin: &str, dir: &Path, packages: &mut Vec<PackageIdent>) -> Result<()> {
-    for entry in fs::read_dir(dir)? {
+fn walk_names(origin: &str,
+              dir: &Path,
+              ignore: &IgnoreList,
+              targets: Targets,
+              packages: &mut Vec<(PackageIdent, PackageTarget)>)
+              -> Result<()> {
+    for entry in fs::read_dir(dir).map_err(walk_error(dir))? {
         let name_dir = entry?;
         let name_path = name_dir.path();
-        if fs::metadata(&name_path)?.is_dir() {
-            let name = filename_from_entry(&name_dir);
-            walk_versions(&origin, &name, &name_path, packages)?;
+        let name = filename_from_entry(&name_dir);
+        if ignore.is_ignored(origin, &name) {
+            continue;
+        }
+        if is_walkable_dir(&RealFileSystem, dir, &name_path)? {
+            walk_versions(&RealFileSystem,
+                          &origin,
+                          &name,
+                          &name_path,
+                          targets,
+                          packages)?;
         }
     }
     Ok(())
@@ -173,17 +433,23 @@ fn walk_names(origin: &str, dir: &Path, packages: &mut Vec<PackageIdent>) -> Res
 
 /// Helper function for walk_names. Walks the directory at the given
 /// Path and recurses into them to find release directories.
-fn walk_versions(origin: &str,
+fn walk_versions(filesystem: &dyn FileSystem,
+                 origin: &str,
                  name: &str,
                  dir: &Path,
-                 packages: &mut Vec<PackageIdent>)
+                 targets: Targets,
+                 packages: &mut Vec<(PackageIdent, PackageTarget)>)
                  -> Result<()> {
-    for entry in fs::read_dir(dir)? {
-        let version_dir = entry?;
-        let version_path = version_dir.path();
-        if fs::metadata(&version_path)?.is_dir() {
-            let version = filename_from_entry(&version_dir);
-            walk_releases(origin, name, &version, &version_path, packages)?;
+    for version_path in filesystem.list(dir).map_err(walk_error(dir))? {
+        if is_walkable_dir(filesystem, dir, &version_path)? {
+            let version = filename_from_path(&version_path);
+            walk_releases(filesystem,
+                          origin,
+                          name,
+                          &version,
+                          &version_path,
+                          targets,
+                          packages)?;
         }
     }
     Ok(())
@@ -194,41 +460,41 @@ fn walk_versions(origin: &str,
 /// valid package directory. Any resulting packages are pushed onto
 /// the given packages vector, assuming the given origin, name, and
 /// version.
-fn walk_releases(origin: &str,
+fn walk_releases(filesystem: &dyn FileSystem,
+                 origin: &str,
                  name: &str,
                  version: &str,
                  dir: &Path,
-                 packages: &mut Vec<PackageIdent>)
+                 targets: Targets,
+                 packages: &mut Vec<(PackageIdent, PackageTarget)>)
                  -> Result<()> {
-    let active_target = PackageTarget::active_target();
-    for entry in fs::read_dir(dir)? {
-        let release_dir = entry?;
-        let release_path = release_dir.path();
-        if fs::metadata(&release_path)?.is_dir() {
-            if let Some(ident) =
-                package_ident_from_dir(origin, name, version, active_target, &release_path)
+    for release_path in filesystem.list(dir).map_err(walk_error(dir))? {
+        if is_walkable_dir(filesystem, dir, &release_path)? {
+            if let Some(found) =
+                package_ident_from_dir(filesystem, origin, name, version, targets, &release_path)
             {
-                packages.push(ident)
+                packages.push(found)
             }
         }
     }
     Ok(())
 }
 
-/// package_ident_from_dir returns a PackageIdent if the given
-/// path contains a valid package for the given active_target.
+/// package_ident_from_dir returns a PackageIdent and its target if the given
+/// path contains a valid package for one of the given targets.
 ///
 /// Returns None when
 ///    - The directory is a temporary install directroy
 ///    - An error occurs reading the package metadata
 ///    - An error occurs reading the package target
-///    - The package target doesn't match the given active target
-fn package_ident_from_dir(origin: &str,
+///    - The package target isn't one of the given targets
+fn package_ident_from_dir(filesystem: &dyn FileSystem,
+                          origin: &str,
                           name: &str,
                           version: &str,
-                          active_target: PackageTarget,
+                          targets: Targets,
                           dir: &Path)
-                          -> Option<PackageIdent> {
+                          -> Option<(PackageIdent, PackageTarget)> {
     let release = if let Some(rel) = dir.file_name().and_then(OsStr::to_str) {
         rel
     } else {
@@ -242,7 +508,7 @@ fn package_ident_from_dir(origin: &str,
         return None;
     }
 
-    let metafile_content = read_metafile(dir, MetaFile::Target);
+    let metafile_content = read_metafile_from(filesystem, dir, MetaFile::Target);
     // If there is an error reading the target metafile, then skip the candidate
     if let Err(e) = metafile_content {
         debug!("PackageInstall::package_ident_from_dir(): rejected PackageInstall candidate due \
@@ -267,19 +533,20 @@ fn package_ident_from_dir(origin: &str,
     // Any errors have been cleared, so unwrap is safe
     let install_target = install_target.unwrap();
 
-    // Ensure that the installed package's target matches the active `PackageTarget`,
-    // otherwise skip the candidate
-    if active_target == install_target {
-        Some(PackageIdent::new(origin.to_string(),
-                               name.to_string(),
-                               Some(version.to_string()),
-                               Some(release.to_owned())))
+    // Ensure that the installed package's target is one of those wanted, otherwise skip the
+    // candidate
+    if targets.includes(install_target) {
+        Some((PackageIdent::new(origin.to_string(),
+                                name.to_string(),
+                                Some(version.to_string()),
+                                Some(release.to_owned())),
+              install_target))
     } else {
         debug!("PackageInstall::package_ident_from_dir(): rejected PackageInstall candidate, \
-                found={}, installed_target={}, active_target={}",
+                found={}, installed_target={}, wanted_target={}",
                dir.display(),
                install_target,
-               active_target,);
+               targets,);
         None
     }
 }
@@ -288,13 +555,115 @@ fn filename_from_entry(entry: &fs::DirEntry) -> String {
     entry.file_name().to_string_lossy().into_owned().to_string()
 }
 
-fn is_existing_dir(path: &Path) -> Result<bool> {
-    match fs::metadata(&path) {
+fn filename_from_path(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// The most symlinks an entry of a package root may chain through and still be walked into.
+pub const MAX_LINK_DEPTH: usize = 8;
+
+/// Makes an `Error` of a failure to read `path` while walking a package root, explaining it when
+/// the operating system refused access.
+fn walk_error(path: &Path) -> impl Fn(io::Error) -> Error + '_ {
+    move |err| access_error(err, path, Access::Package, "looking for installed packages")
+}
+
+/// Whether `path`, an entry of the directory `dir` in a package root, is a directory to walk
+/// into. Entries which have gone since `dir` was read are not.
+///
+/// Symlinks are followed, so that the package root or any origin, name, version or release
+/// directory in it can live on another volume or image layer, but not blindly. Identifiers are
+/// read from directory names, so a link must lead to a directory of its own name, and it must not
+/// lead back to `dir` or above it, where the walk would go round in a cycle. Links which fail
+/// these checks are skipped, as are links which dangle or chain through more than
+/// `MAX_LINK_DEPTH` links; none of them fail the walk.
+fn is_walkable_dir(filesystem: &dyn FileSystem, dir: &Path, path: &Path) -> Result<bool> {
+    match filesystem.is_symlink(path) {
+        Ok(false) => {}
+        Ok(true) => return is_walkable_link(filesystem, dir, path),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(walk_error(path)(err)),
+    }
+    match filesystem.metadata(path) {
+        Ok(metadata) => Ok(metadata.is_dir()),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(walk_error(path)(err)),
+    }
+}
+
+fn is_walkable_link(filesystem: &dyn FileSystem, dir: &Path, path: &Path) -> Result<bool> {
+    match link_depth(filesystem, path) {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            debug!("Skipping {}, a symlink which chains through more than {} links",
+                   path.display(),
+                   MAX_LINK_DEPTH);
+            return Ok(false);
+        }
+        Err(err) => return unfollowable_link(path, err),
+    }
+    let target = match filesystem.canonicalize(path) {
+        Ok(target) => target,
+        Err(err) => return unfollowable_link(path, err),
+    };
+    if target.file_name() != path.file_name() {
+        debug!("Skipping {}, a symlink to {}, which is named differently",
+               path.display(),
+               target.display());
+        return Ok(false);
+    }
+    if filesystem.canonicalize(dir)
+                 .map_err(walk_error(dir))?
+                 .starts_with(&target)
+    {
+        debug!("Skipping {}, a symlink to {}, which contains it",
+               path.display(),
+               target.display());
+        return Ok(false);
+    }
+    Ok(filesystem.metadata(&target)
+                 .map_err(walk_error(&target))?
+                 .is_dir())
+}
+
+/// Follows the symlink `path` one link at a time, returning how many links it chains through, or
+/// `None` once that is more than `MAX_LINK_DEPTH`.
+fn link_depth(filesystem: &dyn FileSystem, path: &Path) -> io::Result<Option<usize>> {
+    let mut link = path.to_path_buf();
+    for depth in 0..=MAX_LINK_DEPTH {
+        if !filesystem.is_symlink(&link)? {
+            return Ok(Some(depth));
+        }
+        let target = filesystem.read_link(&link)?;
+        link = match link.parent() {
+            Some(parent) => parent.join(target),
+            None => target,
+        };
+    }
+    Ok(None)
+}
+
+/// Skips the symlink `path`, which cannot be followed because of `err`, unless the operating
+/// system refused access to it.
+fn unfollowable_link(path: &Path, err: io::Error) -> Result<bool> {
+    if err.kind() == io::ErrorKind::PermissionDenied {
+        return Err(walk_error(path)(err));
+    }
+    debug!("Skipping {}, a symlink which cannot be followed: {}",
+           path.display(),
+           err);
+    Ok(false)
+}
+
+fn is_existing_dir(filesystem: &dyn FileSystem, path: &Path) -> Result<bool> {
+    match filesystem.metadata(&path) {
         Err(err) => {
             if err.kind() == io::ErrorKind::NotFound {
                 return Ok(false);
             }
-            Err(Error::from(err))
+            Err(walk_error(path)(err))
         }
         Ok(metadata) => Ok(metadata.is_dir()),
     }
@@ -303,7 +672,8 @@ fn is_existing_dir(path: &Path) -> Result<bool> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::package::test_support::testing_package_install;
+    use crate::package::{ignore::IGNORE_FILE,
+                         test_support::testing_package_install};
 
     use crate::fs;
     use std::fs::File;
@@ -411,4 +781,230 @@ mod test {
 
         assert_eq!(0, packages.len());
     }
+
+    #[test]
+    fn packages_iter_yields_every_package() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let package_root = fs::pkg_root_path(Some(fs_root.path()));
+        let expected = vec![testing_package_install("core/redis/1.0.0", fs_root.path()),
+                            testing_package_install("test/foobar", fs_root.path()),
+                            testing_package_install("core/redis/1.1.0", fs_root.path()),];
+
+        let mut packages = packages_iter(&package_root).unwrap()
+                                                       .collect::<Result<Vec<_>>>()
+                                                       .unwrap();
+        packages.sort();
+        let mut all = all_packages(&package_root).unwrap();
+        all.sort();
+
+        assert_eq!(all, packages);
+        for p in &expected {
+            assert!(packages.contains(&p.ident));
+        }
+    }
+
+    #[test]
+    fn packages_iter_for_ident_filters() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let package_root = fs::pkg_root_path(Some(fs_root.path()));
+        let redis = testing_package_install("core/redis/1.0.0", fs_root.path());
+        testing_package_install("test/foobar", fs_root.path());
+        testing_package_install("core/redis/1.1.0", fs_root.path());
+        let ident = PackageIdent::from_str("core/redis").unwrap();
+
+        assert_eq!(2,
+                   packages_iter_for_ident(&package_root, &ident).unwrap()
+                                                                 .count());
+        let ident = PackageIdent::from_str("core/redis/1.0.0").unwrap();
+        let mut packages = packages_iter_for_ident(&package_root, &ident).unwrap();
+        assert_eq!(redis.ident, packages.next().unwrap().unwrap());
+        assert!(packages.next().is_none());
+    }
+
+    #[test]
+    fn packages_iter_of_missing_dir_is_empty() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+
+        assert_eq!(0,
+                   packages_iter(&fs_root.path().join("missing")).unwrap()
+                                                                 .count());
+    }
+
+    #[test]
+    fn packages_iter_stops_when_cancelled() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let package_root = fs::pkg_root_path(Some(fs_root.path()));
+        testing_package_install("core/redis/1.0.0", fs_root.path());
+        testing_package_install("core/redis/1.1.0", fs_root.path());
+        let cancel = CancellationToken::new();
+        let mut packages = packages_iter(&package_root).unwrap()
+                                                       .cancel_on(cancel.child());
+
+        assert!(packages.next().unwrap().is_ok());
+        cancel.cancel();
+        match packages.next() {
+            Some(Err(Error::Cancelled)) => {}
+            other => panic!("Expected Cancelled, got {:?}", other),
+        }
+        assert!(packages.next().is_none());
+    }
+
+    #[test]
+    fn walkers_skip_ignored_packages() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let package_root = fs::pkg_root_path(Some(fs_root.path()));
+        let redis = testing_package_install("core/redis/1.0.0", fs_root.path());
+        testing_package_install("core/gcc-musl/8.2.0", fs_root.path());
+        testing_package_install("acme/foobar", fs_root.path());
+        std::fs::write(package_root.join(IGNORE_FILE), "acme\ncore/*-musl\n").unwrap();
+
+        assert_eq!(vec![redis.ident.clone()],
+                   all_packages(&package_root).unwrap());
+        assert_eq!(vec![redis.ident.clone()],
+                   packages_iter(&package_root).unwrap()
+                                               .collect::<Result<Vec<_>>>()
+                                               .unwrap());
+        assert_eq!(vec![redis.ident.clone()],
+                   package_list_for_origin(&package_root, "core").unwrap());
+        assert!(package_list_for_origin(&package_root, "acme").unwrap()
+                                                              .is_empty());
+        let ident = PackageIdent::from_str("core/gcc-musl").unwrap();
+        assert!(package_list_for_ident(&package_root, &ident).unwrap()
+                                                             .is_empty());
+    }
+
+    #[test]
+    fn packages_of_other_targets_can_be_listed() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let package_root = fs::pkg_root_path(Some(fs_root.path()));
+        let active = PackageTarget::active_target();
+        let other = *PackageTarget::supported_targets().find(|&&target| target != active)
+                                                       .unwrap();
+        let redis = testing_package_install("core/redis/1.0.0", fs_root.path());
+        let windows_redis = testing_package_install("core/redis/1.1.0", fs_root.path());
+        std::fs::write(windows_redis.installed_path()
+                                    .join(MetaFile::Target.to_string()),
+                       other.to_string()).unwrap();
+        let ident = PackageIdent::from_str("core/redis").unwrap();
+
+        assert_eq!(vec![redis.ident.clone()],
+                   all_packages(&package_root).unwrap());
+        assert_eq!(vec![windows_redis.ident.clone()],
+                   all_packages_for_target(&package_root, other).unwrap());
+        assert_eq!(vec![windows_redis.ident.clone()],
+                   package_list_for_ident_and_target(&package_root, &ident, other).unwrap());
+        assert_eq!(vec![windows_redis.ident.clone()],
+                   packages_iter(&package_root).unwrap()
+                                               .for_target(other)
+                                               .collect::<Result<Vec<_>>>()
+                                               .unwrap());
+
+        let by_target = all_packages_by_target(&package_root).unwrap();
+        assert_eq!(2, by_target.len());
+        assert_eq!(vec![redis.ident], by_target[&active]);
+        assert_eq!(vec![windows_redis.ident], by_target[&other]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlinked_package_roots_and_origins_are_walked() {
+        use crate::package::PackageInstall;
+        use std::os::unix::fs::symlink;
+
+        let store = Builder::new().prefix("store").tempdir().unwrap();
+        let redis = testing_package_install("core/redis/4.0.14/20190115013919", store.path());
+        let layer = Builder::new().prefix("layer").tempdir().unwrap();
+        let app = testing_package_install("acme/app/1.0.0/20190201000000", layer.path());
+        let store_root = fs::pkg_root_path(Some(store.path()));
+        symlink(fs::pkg_root_path(Some(layer.path())).join("acme"),
+                store_root.join("acme")).unwrap();
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let package_root = fs::pkg_root_path(Some(fs_root.path()));
+        std::fs::create_dir(package_root.parent().unwrap()).unwrap();
+        symlink(&store_root, &package_root).unwrap();
+
+        let expected = vec![app.ident.clone(), redis.ident.clone()];
+        let mut packages = all_packages(&package_root).unwrap();
+        packages.sort();
+        assert_eq!(expected, packages);
+        let mut packages = packages_iter(&package_root).unwrap()
+                                                       .collect::<Result<Vec<_>>>()
+                                                       .unwrap();
+        packages.sort();
+        assert_eq!(expected, packages);
+        assert_eq!(vec![app.ident.clone()],
+                   package_list_for_origin(&package_root, "acme").unwrap());
+        assert_eq!(vec![app.ident.clone()],
+                   package_list_for_ident(&package_root, &app.ident).unwrap());
+
+        let loaded = PackageInstall::load(&app.ident, Some(fs_root.path())).unwrap();
+        assert_eq!(package_root.join("acme/app/1.0.0/20190201000000"),
+                   loaded.installed_path());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlinks_which_dangle_loop_or_rename_are_skipped() {
+        use std::os::unix::fs::symlink;
+
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let package_root = fs::pkg_root_path(Some(fs_root.path()));
+        let redis = testing_package_install("core/redis/4.0.14/20190115013919", fs_root.path());
+        symlink(fs_root.path().join("gone"), package_root.join("gone")).unwrap();
+        symlink("loop-b", package_root.join("loop-a")).unwrap();
+        symlink("loop-a", package_root.join("loop-b")).unwrap();
+        symlink("core", package_root.join("core2")).unwrap();
+        symlink("../redis", package_root.join("core/redis/redis")).unwrap();
+        symlink("..", package_root.join("core/redis/4.0.14/4.0.14")).unwrap();
+
+        assert_eq!(vec![redis.ident.clone()],
+                   all_packages(&package_root).unwrap());
+        assert_eq!(vec![redis.ident.clone()],
+                   packages_iter(&package_root).unwrap()
+                                               .collect::<Result<Vec<_>>>()
+                                               .unwrap());
+        assert!(package_list_for_origin(&package_root, "core2").unwrap()
+                                                               .is_empty());
+        let renamed = PackageIdent::from_str("core2/redis").unwrap();
+        assert!(package_list_for_ident(&package_root, &renamed).unwrap()
+                                                               .is_empty());
+        let looped = PackageIdent::from_str("core/redis/redis").unwrap();
+        assert!(package_list_for_ident(&package_root, &looped).unwrap()
+                                                              .is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn chains_of_symlinks_are_walked_up_to_the_depth_bound() {
+        use std::os::unix::fs::symlink;
+
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let package_root = fs::pkg_root_path(Some(fs_root.path()));
+        std::fs::create_dir_all(&package_root).unwrap();
+        let store = Builder::new().prefix("store").tempdir().unwrap();
+        // Links an origin into the package root through a chain of `depth` links, each named
+        // for the origin: <root>/<origin> -> 1/<origin> -> ... -> the origin's real directory
+        let chain = |origin: &str, depth: usize| {
+            let hops = store.path().join(origin);
+            let installed =
+                testing_package_install(&format!("{}/app", origin), &hops.join(depth.to_string()));
+            let mut target = fs::pkg_root_path(Some(&hops.join(depth.to_string()))).join(origin);
+            for hop in (1..depth).rev() {
+                let link = hops.join(hop.to_string()).join(origin);
+                std::fs::create_dir_all(link.parent().unwrap()).unwrap();
+                symlink(&target, &link).unwrap();
+                target = link;
+            }
+            symlink(&target, package_root.join(origin)).unwrap();
+            installed.ident
+        };
+        let shallow = chain("shallow", MAX_LINK_DEPTH);
+        chain("deep", MAX_LINK_DEPTH + 1);
+
+        assert_eq!(vec![shallow.clone()], all_packages(&package_root).unwrap());
+        assert_eq!(vec![shallow],
+                   package_list_for_origin(&package_root, "shallow").unwrap());
+        assert!(package_list_for_origin(&package_root, "deep").unwrap()
+                                                              .is_empty());
+    }
 }
@@ -12,23 +12,37 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{error::{Error,
+use crate::{env as henv,
+            error::{Error,
                     Result},
             package::PackageIdent};
+#[cfg(feature = "fs")]
+use crate::{fs::{Access,
+                 FileSystem,
+                 PermissionDenied,
+                 RealFileSystem},
+            os::ffi::os_string_from_bytes,
+            util::timeout};
 use serde_derive::Serialize;
 use std::{self,
           collections::HashMap,
           env,
           fmt,
-          fs::File,
-          io::Read,
-          iter::{FromIterator,
-                 IntoIterator},
-          path::{Path,
-                 PathBuf},
+          iter::IntoIterator,
+          path::PathBuf,
           str::FromStr,
           string::ToString,
           vec::IntoIter};
+#[cfg(feature = "fs")]
+use std::{ffi::OsString,
+          io::{self,
+               Read},
+          path::Path,
+          time::Duration};
+
+lazy_static::lazy_static! {
+    static ref READ_LIMITS: ReadLimits = <ReadLimits as henv::Config>::configured_value();
+}
 
 #[cfg(not(windows))]
 const ENV_PATH_SEPARATOR: char = ':';
@@ -37,11 +51,15 @@ const ENV_PATH_SEPARATOR: char = ':';
 const ENV_PATH_SEPARATOR: char = ';';
 
 pub fn parse_key_value(s: &str) -> Result<HashMap<String, String>> {
-    Ok(HashMap::from_iter(s.lines()
-                           .map(|l| l.splitn(2, '=').collect::<Vec<_>>())
-                           .map(|kv| {
-                               (kv[0].to_string(), kv[1].to_string())
-                           })))
+    s.lines()
+     .map(|l| {
+         let mut kv = l.splitn(2, '=');
+         match (kv.next(), kv.next()) {
+             (Some(k), Some(v)) => Ok((k.to_string(), v.to_string())),
+             _ => Err(Error::KeyValueMalformed(l.to_string())),
+         }
+     })
+     .collect()
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -78,7 +96,7 @@ impl fmt::Display for Bind {
 #[derive(Debug, PartialEq)]
 pub struct BindMapping {
     /// The name of the bind of a given service.
-    pub bind_name: String,
+    pub bind_name:          String,
     /// The identifier of the service within the composite package
     /// that should satisfy the named bind.
     pub satisfying_service: PackageIdent,
@@ -162,6 +180,7 @@ pub enum MetaFile {
     EnvironmentSep,
     Exports,
     Exposes,
+    Files,
     Ident,
     LdFlags,
     LdRunPath,
@@ -170,6 +189,7 @@ pub enum MetaFile {
     ResolvedServices, // Composite-only
     RuntimeEnvironment,
     RuntimePath,
+    SeccompProfile,
     Services, // Composite-only
     SvcGroup,
     SvcUser,
@@ -193,6 +213,7 @@ impl fmt::Display for MetaFile {
             MetaFile::EnvironmentSep => "ENVIRONMENT_SEP",
             MetaFile::Exports => "EXPORTS",
             MetaFile::Exposes => "EXPOSES",
+            MetaFile::Files => "FILES",
             MetaFile::Ident => "IDENT",
             MetaFile::LdFlags => "LDFLAGS",
             MetaFile::LdRunPath => "LD_RUN_PATH",
@@ -201,6 +222,7 @@ impl fmt::Display for MetaFile {
             MetaFile::ResolvedServices => "RESOLVED_SERVICES",
             MetaFile::RuntimeEnvironment => "RUNTIME_ENVIRONMENT",
             MetaFile::RuntimePath => "RUNTIME_PATH",
+            MetaFile::SeccompProfile => "SECCOMP_PROFILE",
             MetaFile::Services => "SERVICES",
             MetaFile::SvcGroup => "SVC_GROUP",
             MetaFile::SvcUser => "SVC_USER",
@@ -212,34 +234,224 @@ impl fmt::Display for MetaFile {
     }
 }
 
+/// Limits on how much is read from a metafile, or from a line of an artifact's header, so that a
+/// corrupt file fails to be read with an error rather than exhausting the process' memory.
+///
+/// The limits are set for the whole process through the `HAB_READ_LIMITS` environment variable,
+/// as a comma-separated list of settings such as
+/// `metafile_bytes=16777216,metafile_lines=200000,header_line_bytes=8192`. Any setting left out
+/// keeps its default. The `FILES` metafile, which lists every file in a package and so grows with
+/// it, has limits of its own, `files_bytes` and `files_lines`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ReadLimits {
+    /// The size of the largest metafile which will be read, in bytes.
+    pub metafile_bytes:    u64,
+    /// The most lines a metafile may have.
+    pub metafile_lines:    usize,
+    /// The length of the longest line of an artifact's header, in bytes.
+    pub header_line_bytes: u64,
+    /// The size of the largest `FILES` metafile which will be read, in bytes.
+    pub files_bytes:       u64,
+    /// The most lines a `FILES` metafile may have.
+    pub files_lines:       usize,
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        ReadLimits { metafile_bytes:    8 * 1024 * 1024,
+                     metafile_lines:    100_000,
+                     header_line_bytes: 4096,
+                     files_bytes:       256 * 1024 * 1024,
+                     files_lines:       2_000_000, }
+    }
+}
+
+impl henv::Config for ReadLimits {
+    const ENVVAR: &'static str = "HAB_READ_LIMITS";
+}
+
+impl FromStr for ReadLimits {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut limits = ReadLimits::default();
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let mut parts = setting.splitn(2, '=');
+            let key = parts.next().unwrap_or_default().trim();
+            let invalid = || Error::InvalidReadLimits(s.to_string());
+            let value = parts.next().ok_or_else(invalid)?.trim();
+            match key {
+                "metafile_bytes" => limits.metafile_bytes = value.parse().map_err(|_| invalid())?,
+                "metafile_lines" => limits.metafile_lines = value.parse().map_err(|_| invalid())?,
+                "header_line_bytes" => {
+                    limits.header_line_bytes = value.parse().map_err(|_| invalid())?
+                }
+                "files_bytes" => limits.files_bytes = value.parse().map_err(|_| invalid())?,
+                "files_lines" => limits.files_lines = value.parse().map_err(|_| invalid())?,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(limits)
+    }
+}
+
+impl ReadLimits {
+    /// The limits for this process, from `HAB_READ_LIMITS`.
+    pub fn current() -> Self { *READ_LIMITS }
+
+    /// The most bytes and lines which may be read from the metafile `file`.
+    pub fn for_metafile(&self, file: MetaFile) -> (u64, usize) {
+        match file {
+            MetaFile::Files => (self.files_bytes, self.files_lines),
+            _ => (self.metafile_bytes, self.metafile_lines),
+        }
+    }
+}
+
 /// Read a metadata file from within a package directory if it exists
 ///
 /// Returns the contents of the file
+///
+/// # Failures
+///
+/// * The file is larger or has more lines than `ReadLimits` allow
+#[cfg(feature = "fs")]
 pub fn read_metafile<P: AsRef<Path>>(installed_path: P, file: MetaFile) -> Result<String> {
-    match existing_metafile(installed_path, file) {
+    read_metafile_from(&RealFileSystem, installed_path, file)
+}
+
+/// As `read_metafile`, but reading the package from `filesystem`.
+#[cfg(feature = "fs")]
+pub fn read_metafile_from<P: AsRef<Path>>(filesystem: &dyn FileSystem,
+                                          installed_path: P,
+                                          file: MetaFile)
+                                          -> Result<String> {
+    match existing_metafile(filesystem, installed_path, file) {
         Some(filepath) => {
-            match File::open(&filepath) {
-                Ok(mut f) => {
-                    let mut data = String::new();
-                    if f.read_to_string(&mut data).is_err() {
-                        return Err(Error::MetaFileMalformed(file));
-                    }
-                    Ok(data.trim().to_string())
-                }
-                Err(e) => Err(Error::MetaFileIO(e)),
+            let data = read_limited(filesystem, &filepath, file, ReadLimits::current())?;
+            match String::from_utf8(data) {
+                Ok(data) => Ok(data.trim().to_string()),
+                Err(_) => Err(Error::MetaFileMalformed(file)),
             }
         }
         None => Err(Error::MetaFileNotFound(file)),
     }
 }
 
+/// As `read_metafile_from`, but returning the contents exactly as they are, for metafiles such
+/// as a signed `FILES` whose every byte matters.
+#[cfg(feature = "fs")]
+pub(crate) fn read_metafile_bytes_from<P: AsRef<Path>>(filesystem: &dyn FileSystem,
+                                                       installed_path: P,
+                                                       file: MetaFile)
+                                                       -> Result<Vec<u8>> {
+    match existing_metafile(filesystem, installed_path, file) {
+        Some(filepath) => read_limited(filesystem, &filepath, file, ReadLimits::current()),
+        None => Err(Error::MetaFileNotFound(file)),
+    }
+}
+
+/// As `read_metafile`, but giving up with `Error::OperationTimedOut` if the read takes longer
+/// than `timeout`, as it can when the package is on an unresponsive network filesystem.
+#[cfg(feature = "fs")]
+pub fn read_metafile_with_timeout<P: AsRef<Path>>(installed_path: P,
+                                                  file: MetaFile,
+                                                  timeout: Duration)
+                                                  -> Result<String> {
+    let installed_path = installed_path.as_ref().to_path_buf();
+    let operation = format!("read {}", installed_path.join(file.to_string()).display());
+    timeout::with_timeout(timeout, move || read_metafile(installed_path, file))
+        .map_err(|e| e.into_error(operation))?
+}
+
+/// As `read_metafile`, but without requiring the contents to be UTF-8, for metafiles which hold
+/// paths and environment values.
+#[cfg(feature = "fs")]
+pub fn read_metafile_os<P: AsRef<Path>>(installed_path: P, file: MetaFile) -> Result<OsString> {
+    read_metafile_os_from(&RealFileSystem, installed_path, file)
+}
+
+/// As `read_metafile_os`, but reading the package from `filesystem`.
+#[cfg(feature = "fs")]
+pub fn read_metafile_os_from<P: AsRef<Path>>(filesystem: &dyn FileSystem,
+                                             installed_path: P,
+                                             file: MetaFile)
+                                             -> Result<OsString> {
+    match existing_metafile(filesystem, installed_path, file) {
+        Some(filepath) => {
+            let data = read_limited(filesystem, &filepath, file, ReadLimits::current())?;
+            let start = data.iter()
+                            .position(|b| !b.is_ascii_whitespace())
+                            .unwrap_or_else(|| data.len());
+            let end = data.iter()
+                          .rposition(|b| !b.is_ascii_whitespace())
+                          .map_or(start, |i| i + 1);
+            Ok(os_string_from_bytes(data[start..end].to_vec()))
+        }
+        None => Err(Error::MetaFileNotFound(file)),
+    }
+}
+
+/// Reads the metafile `file` at `path`, reading no more of it than `limits` allow.
+#[cfg(feature = "fs")]
+fn read_limited(filesystem: &dyn FileSystem,
+                path: &Path,
+                file: MetaFile,
+                limits: ReadLimits)
+                -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let (max_bytes, _) = limits.for_metafile(file);
+    filesystem.open(path)
+              .and_then(|f| f.take(max_bytes.saturating_add(1)).read_to_end(&mut data))
+              .map_err(|e| {
+                  if e.kind() == io::ErrorKind::PermissionDenied {
+                      let needed_by = format!("reading the {} metafile", file);
+                      Error::PermissionDenied(PermissionDenied::new(path,
+                                                                    needed_by,
+                                                                    Access::Package))
+                  } else {
+                      Error::MetaFileIO(e)
+                  }
+              })?;
+    check_limits(file, &data, limits)?;
+    Ok(data)
+}
+
+/// Fails with `Error::MetaFileTooLarge` if `data`, the contents of the metafile `file`, is larger
+/// or has more lines than `limits` allow.
+#[cfg(feature = "fs")]
+pub(crate) fn check_limits(file: MetaFile, data: &[u8], limits: ReadLimits) -> Result<()> {
+    let (max_bytes, max_lines) = limits.for_metafile(file);
+    if data.len() as u64 > max_bytes {
+        return Err(Error::MetaFileTooLarge(file,
+                                           format!("larger than {} bytes",
+                                                   max_bytes)));
+    }
+    let mut lines = data.iter().filter(|&&b| b == b'\n').count();
+    if data.last().map_or(false, |&b| b != b'\n') {
+        lines += 1;
+    }
+    if lines > max_lines {
+        return Err(Error::MetaFileTooLarge(file,
+                                           format!("longer than {} lines",
+                                                   max_lines)));
+    }
+    Ok(())
+}
+
 /// Returns the path to a specified MetaFile in an installed path if it exists.
 ///
 /// Useful for fallback logic for dealing with older Habitat packages.
-fn existing_metafile<P: AsRef<Path>>(installed_path: P, file: MetaFile) -> Option<PathBuf> {
+#[cfg(feature = "fs")]
+fn existing_metafile<P: AsRef<Path>>(filesystem: &dyn FileSystem,
+                                     installed_path: P,
+                                     file: MetaFile)
+                                     -> Option<PathBuf> {
     let filepath = installed_path.as_ref().join(file.to_string());
-    match std::fs::metadata(&filepath) {
+    match filesystem.metadata(&filepath) {
         Ok(_) => Some(filepath),
+        // Reading it will fail the same way, and say why.
+        Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => Some(filepath),
         Err(_) => None,
     }
 }
@@ -274,7 +486,14 @@ impl FromStr for PackageType {
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::io::Write;
+    use rand::{rngs::StdRng,
+               Rng,
+               SeedableRng};
+    #[cfg(feature = "fs")]
+    use std::{fs::{self,
+                   File},
+              io::Write};
+    #[cfg(feature = "fs")]
     use tempfile::Builder;
 
     static ENVIRONMENT: &str = r#"PATH=/hab/pkgs/python/setuptools/35.0.1/20170424072606/bin
@@ -290,6 +509,7 @@ port=front-end.port
 
     /// Write the given contents into the specified metadata file for
     /// the package.
+    #[cfg(feature = "fs")]
     fn write_metafile(install_dir: &Path, metafile: MetaFile, content: &str) {
         let path = install_dir.join(metafile.to_string());
         let mut f = File::create(path).expect("Could not create metafile");
@@ -394,6 +614,7 @@ port=front-end.port
         assert!(output.is_err());
     }
 
+    #[cfg(feature = "fs")]
     #[test]
     fn can_read_metafile() {
         let pkg_root = Builder::new().prefix("pkg-root").tempdir().unwrap();
@@ -407,6 +628,23 @@ port=front-end.port
         assert_eq!(expected, bind_map);
     }
 
+    #[cfg(feature = "fs")]
+    #[test]
+    fn can_read_metafile_with_timeout() {
+        let pkg_root = Builder::new().prefix("pkg-root").tempdir().unwrap();
+        let install_dir = pkg_root.path();
+
+        let expected = "core/foo=db:core/database";
+        write_metafile(install_dir, MetaFile::Binds, expected);
+
+        let bind_map = read_metafile_with_timeout(install_dir,
+                                                  MetaFile::Binds,
+                                                  Duration::from_secs(5)).unwrap();
+
+        assert_eq!(expected, bind_map);
+    }
+
+    #[cfg(feature = "fs")]
     #[test]
     fn reading_a_non_existing_metafile_is_an_error() {
         let pkg_root = Builder::new().prefix("pkg-root").tempdir().unwrap();
@@ -416,4 +654,80 @@ port=front-end.port
         assert!(bind_map.is_err());
     }
 
+    #[test]
+    fn read_limits_from_str() {
+        assert_eq!(ReadLimits::default(), "".parse().unwrap());
+        assert_eq!(ReadLimits { metafile_bytes: 1024,
+                                header_line_bytes: 256,
+                                ..ReadLimits::default() },
+                   "metafile_bytes=1024, header_line_bytes=256".parse()
+                                                               .unwrap());
+        assert_eq!(ReadLimits { files_lines: 10,
+                                ..ReadLimits::default() },
+                   "files_lines=10".parse().unwrap());
+        assert!("metafile_bytes=lots".parse::<ReadLimits>().is_err());
+        assert!("metafile_size=1024".parse::<ReadLimits>().is_err());
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn metafiles_beyond_the_limits_are_refused() {
+        let install_dir = Builder::new().prefix("pkg").tempdir().unwrap();
+        let path = install_dir.path().join(MetaFile::Deps.to_string());
+        let limits = ReadLimits { metafile_bytes: 16,
+                                  metafile_lines: 2,
+                                  ..ReadLimits::default() };
+
+        fs::write(&path, "a\nb\n").unwrap();
+        assert_eq!(b"a\nb\n".to_vec(),
+                   read_limited(&RealFileSystem, &path, MetaFile::Deps, limits).unwrap());
+
+        fs::write(&path, "a\nb\nc").unwrap();
+        match read_limited(&RealFileSystem, &path, MetaFile::Deps, limits) {
+            Err(Error::MetaFileTooLarge(MetaFile::Deps, _)) => (),
+            other => panic!("expected the metafile to be too long, got {:?}", other),
+        }
+
+        fs::write(&path, vec![b'x'; 17]).unwrap();
+        match read_limited(&RealFileSystem, &path, MetaFile::Deps, limits) {
+            Err(Error::MetaFileTooLarge(MetaFile::Deps, _)) => (),
+            other => panic!("expected the metafile to be too large, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn files_metafile_has_limits_of_its_own() {
+        let install_dir = Builder::new().prefix("pkg").tempdir().unwrap();
+        let path = install_dir.path().join(MetaFile::Files.to_string());
+        let limits = ReadLimits { metafile_lines: 1,
+                                  files_lines: 3,
+                                  ..ReadLimits::default() };
+
+        fs::write(&path, "a\nb\nc\n").unwrap();
+        assert!(read_limited(&RealFileSystem, &path, MetaFile::Files, limits).is_ok());
+
+        fs::write(&path, "a\nb\nc\nd\n").unwrap();
+        match read_limited(&RealFileSystem, &path, MetaFile::Files, limits) {
+            Err(Error::MetaFileTooLarge(MetaFile::Files, _)) => (),
+            other => panic!("expected FILES to be too long, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parsers_survive_random_input() {
+        let alphabet = b"=:/ \n\r\tab01.-_\xff";
+        let mut rng = StdRng::seed_from_u64(0x68_6162_6974_6174);
+        for _ in 0..2000 {
+            let len = rng.gen::<usize>() % 64;
+            let bytes: Vec<u8> = (0..len).map(|_| alphabet[rng.gen::<usize>() % alphabet.len()])
+                                         .collect();
+            let input = String::from_utf8_lossy(&bytes);
+
+            let _ = parse_key_value(&input);
+            let _ = Bind::from_str(&input);
+            let _ = BindMapping::from_str(&input);
+            let _ = PackageIdent::from_str(&input);
+        }
+    }
 }
@@ -0,0 +1,396 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Origin mirrors, which send fetches of chosen packages to an internal rebuild of them, so that
+//! upstream plans which depend on `core/openssl` can be built against `mycorp-core/openssl`
+//! without being changed.
+//!
+//! Mirrors are read from `hab/etc/mirrors.toml` under the filesystem root. Each one names the
+//! upstream origin it stands in for, optionally only for some of its packages, the origin the
+//! rebuilds are published under, and optionally the Builder and channel to fetch them from:
+//!
+//! ```toml
+//! [[mirror]]
+//! origin = "core"
+//! packages = ["openssl", "glibc"]
+//! mirror_origin = "mycorp-core"
+//! url = "https://bldr.mycorp.example"
+//! channel = "stable"
+//! ```
+//!
+//! An artifact fetched from a mirror must be signed by the mirror's own key, which is the key of
+//! its `mirror_origin` unless a `key` is given. A `key` with a revision, such as
+//! `mycorp-core-20190101000000`, accepts only that key. Any other artifact must be signed by a key
+//! of its own origin.
+
+use std::{fs,
+          io,
+          path::{Path,
+                 PathBuf}};
+
+use serde_derive::Deserialize;
+use toml;
+
+use crate::{crypto::keys::parse_name_with_rev,
+            error::{Error,
+                    Result},
+            fs::FS_ROOT_PATH,
+            package::{Identifiable,
+                      PackageArchive,
+                      PackageIdent,
+                      PackageTarget},
+            ChannelIdent};
+
+/// Where the mirror configuration is kept, relative to the filesystem root.
+pub const MIRRORS_PATH: &str = "hab/etc/mirrors.toml";
+
+/// Returns the path to the mirror configuration, optionally taking a custom filesystem root.
+pub fn mirrors_path(fs_root_path: Option<&Path>) -> PathBuf {
+    fs_root_path.unwrap_or(&*FS_ROOT_PATH).join(MIRRORS_PATH)
+}
+
+/// A mirror of some or all of an origin's packages.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct Mirror {
+    /// The upstream origin the mirror stands in for.
+    pub origin:        String,
+    /// The upstream packages the mirror stands in for, or every package of the origin if empty.
+    #[serde(default)]
+    pub packages:      Vec<String>,
+    /// The origin the mirror's packages are published under.
+    pub mirror_origin: String,
+    /// The Builder to fetch the mirror's packages from, if not the one fetches are made from.
+    #[serde(default)]
+    pub url:           Option<String>,
+    /// The channel to fetch the mirror's packages from, if not the channel asked for.
+    #[serde(default)]
+    pub channel:       Option<ChannelIdent>,
+    /// The key the mirror's packages must be signed by, if not the key of `mirror_origin`.
+    #[serde(default)]
+    pub key:           Option<String>,
+}
+
+impl Mirror {
+    /// A mirror of every package of `origin`, published under `mirror_origin`.
+    pub fn new<T, U>(origin: T, mirror_origin: U) -> Self
+        where T: Into<String>,
+              U: Into<String>
+    {
+        Mirror { origin:        origin.into(),
+                 packages:      Vec::new(),
+                 mirror_origin: mirror_origin.into(),
+                 url:           None,
+                 channel:       None,
+                 key:           None, }
+    }
+
+    /// Limits the mirror to the upstream package `name`, along with any others already given.
+    pub fn package<T: Into<String>>(mut self, name: T) -> Self {
+        self.packages.push(name.into());
+        self
+    }
+
+    pub fn url<T: Into<String>>(mut self, url: T) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    pub fn channel(mut self, channel: ChannelIdent) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    pub fn key<T: Into<String>>(mut self, key: T) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    fn covers(&self, ident: &PackageIdent) -> bool {
+        ident.origin == self.origin
+        && (self.packages.is_empty() || self.packages.contains(&ident.name))
+    }
+}
+
+/// The mirrors configured for a system, consulted in the order they were given.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct MirrorConfig {
+    #[serde(default, rename = "mirror")]
+    mirrors: Vec<Mirror>,
+}
+
+impl MirrorConfig {
+    pub fn new() -> Self { Self::default() }
+
+    /// Reads the mirror configuration under `fs_root_path`, which is empty when there is none.
+    ///
+    /// # Failures
+    ///
+    /// * The configuration exists but cannot be read or parsed
+    pub fn load(fs_root_path: Option<&Path>) -> Result<Self> {
+        let path = mirrors_path(fs_root_path);
+        match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).map_err(Error::ConfigFileSyntax),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(Error::ConfigFileIO(path, e)),
+        }
+    }
+
+    /// Adds `mirror`, which is consulted after any mirrors already given.
+    pub fn mirror(mut self, mirror: Mirror) -> Self {
+        self.mirrors.push(mirror);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool { self.mirrors.is_empty() }
+
+    /// Where to fetch `ident` from when it was asked for from `channel`.
+    ///
+    /// A package no mirror covers is fetched as asked. A covered package is fetched from the
+    /// first mirror covering it, under the mirror's origin. Since a rebuild has its own release,
+    /// the release of a fully qualified identifier is dropped, keeping its version.
+    pub fn source_for(&self, ident: &PackageIdent, channel: &ChannelIdent) -> FetchSource {
+        match self.mirrors.iter().find(|mirror| mirror.covers(ident)) {
+            Some(mirror) => {
+                let mut mirrored = ident.clone();
+                mirrored.origin = mirror.mirror_origin.clone();
+                mirrored.release = None;
+                FetchSource { ident:    mirrored,
+                              channel:  mirror.channel.clone().unwrap_or_else(|| channel.clone()),
+                              url:      mirror.url.clone(),
+                              signer:   Some(mirror.key.clone().unwrap_or_else(|| {
+                                                                   mirror.mirror_origin.clone()
+                                                               })),
+                              upstream: ident.clone(), }
+            }
+            None => {
+                FetchSource { ident:    ident.clone(),
+                              channel:  channel.clone(),
+                              url:      None,
+                              signer:   None,
+                              upstream: ident.clone(), }
+            }
+        }
+    }
+}
+
+/// Where to fetch a package from, and whose signature its artifact must carry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FetchSource {
+    /// The package to fetch.
+    pub ident:    PackageIdent,
+    pub channel:  ChannelIdent,
+    /// The Builder to fetch from, if not the one fetches are made from.
+    pub url:      Option<String>,
+    /// The key the artifact must be signed by, for a mirrored package.
+    pub signer:   Option<String>,
+    /// The package as it was asked for.
+    pub upstream: PackageIdent,
+}
+
+impl FetchSource {
+    /// Whether the package is fetched from a mirror rather than as asked.
+    pub fn is_mirrored(&self) -> bool { self.signer.is_some() }
+
+    /// Verifies the signature of the fetched `archive`, as `PackageArchive::verify` does, and that
+    /// it was signed by its mirror's key or, if not mirrored, by a key of the package's origin.
+    /// The archive must then hold the package asked for, built for `target`.
+    ///
+    /// # Failures
+    ///
+    /// * The signature cannot be verified
+    /// * The archive came from a mirror but was signed by some other key
+    /// * The archive was not mirrored and was signed by a key of some other origin
+    /// * The archive's metadata cannot be read
+    /// * The archive holds some other package, or was built for some other target
+    pub fn verify<P: AsRef<Path>>(&self,
+                                  archive: &PackageArchive,
+                                  target: PackageTarget,
+                                  cache_key_path: &P)
+                                  -> Result<(String, String)> {
+        let verified = archive.verify(cache_key_path)?;
+        match self.signer {
+            Some(ref expected) => {
+                if !signed_by(expected, &verified.0)? {
+                    return Err(Error::MirrorSignerMismatch(self.upstream.to_string(),
+                                                           expected.clone(),
+                                                           verified.0));
+                }
+            }
+            None => {
+                if !signed_by(&self.ident.origin, &verified.0)? {
+                    return Err(Error::ArtifactSignerMismatch(self.ident.to_string(),
+                                                             self.ident.origin.clone(),
+                                                             verified.0));
+                }
+            }
+        }
+
+        let mut contents = PackageArchive::new(archive.path.clone());
+        let (ident, found_target) = (contents.ident()?, contents.target()?);
+        if !ident.satisfies(&self.ident) || found_target != target {
+            return Err(Error::ArtifactMismatch(format!("{} ({})",
+                                                       self.ident, target),
+                                               format!("{} ({})",
+                                                       ident, found_target)));
+        }
+        Ok(verified)
+    }
+}
+
+/// Whether the key `signer`, a name with revision, is the `expected` key, which may be given
+/// with or without a revision.
+fn signed_by(expected: &str, signer: &str) -> Result<bool> {
+    if expected == signer {
+        return Ok(true);
+    }
+    if parse_name_with_rev(expected).is_ok() {
+        return Ok(false);
+    }
+    let (name, _) = parse_name_with_rev(signer)?;
+    Ok(name == expected)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::test_support::fixtures;
+    use std::str::FromStr;
+    use tempfile::{Builder,
+                   TempDir};
+
+    fn ident(s: &str) -> PackageIdent { PackageIdent::from_str(s).unwrap() }
+
+    #[test]
+    fn load_mirror_config() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        assert!(MirrorConfig::load(Some(fs_root.path())).unwrap().is_empty());
+
+        let path = mirrors_path(Some(fs_root.path()));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path,
+                  "[[mirror]]\norigin = \"core\"\npackages = [\"openssl\"]\nmirror_origin = \
+                   \"mycorp-core\"\nurl = \"https://bldr.mycorp.example\"\nchannel = \
+                   \"rebuilt\"\n\n[[mirror]]\norigin = \"core\"\nmirror_origin = \
+                   \"mycorp-extras\"\nkey = \"mycorp-core\"\n").unwrap();
+
+        let openssl = Mirror::new("core", "mycorp-core").package("openssl")
+                                                        .url("https://bldr.mycorp.example")
+                                                        .channel("rebuilt".into());
+        let extras = Mirror::new("core", "mycorp-extras").key("mycorp-core");
+        assert_eq!(MirrorConfig::new().mirror(openssl).mirror(extras),
+                   MirrorConfig::load(Some(fs_root.path())).unwrap());
+    }
+
+    #[test]
+    fn sources_follow_the_first_covering_mirror() {
+        let openssl = Mirror::new("core", "mycorp-core").package("openssl")
+                                                        .channel("rebuilt".into());
+        let extras = Mirror::new("core", "mycorp-extras").url("https://bldr.mycorp.example");
+        let config = MirrorConfig::new().mirror(openssl).mirror(extras);
+        let stable = ChannelIdent::stable();
+
+        let openssl = config.source_for(&ident("core/openssl/1.0.2r/20190305210149"), &stable);
+        assert_eq!(ident("mycorp-core/openssl/1.0.2r"), openssl.ident);
+        assert_eq!(ChannelIdent::from("rebuilt"), openssl.channel);
+        assert_eq!(Some("mycorp-core".to_string()), openssl.signer);
+
+        let glibc = config.source_for(&ident("core/glibc"), &stable);
+        assert_eq!(ident("mycorp-extras/glibc"), glibc.ident);
+        assert_eq!(stable, glibc.channel);
+        assert_eq!(Some("https://bldr.mycorp.example".to_string()), glibc.url);
+
+        let app = config.source_for(&ident("acme/app/1.0.0/20190101000000"), &stable);
+        assert!(!app.is_mirrored());
+        assert_eq!(app.upstream, app.ident);
+    }
+
+    /// A key cache holding the public key which signed the `happyhumans/possums` fixture.
+    fn happyhumans_cache() -> TempDir {
+        let cache = Builder::new().prefix("key_cache").tempdir().unwrap();
+        fs::copy(fixtures().join("happyhumans-20160424223347.pub"),
+                 cache.path().join("happyhumans-20160424223347.pub")).unwrap();
+        cache
+    }
+
+    fn possums() -> PackageArchive {
+        PackageArchive::new(fixtures().join("happyhumans-possums-8.1.\
+                                             4-20160427165340-x86_64-linux.hart"))
+    }
+
+    fn linux() -> PackageTarget { PackageTarget::from_str("x86_64-linux").unwrap() }
+
+    #[test]
+    fn mirrored_artifacts_must_carry_the_mirror_signature() {
+        let cache = happyhumans_cache();
+        let archive = possums();
+        let upstream = ident("core/possums");
+
+        for key in &["happyhumans", "happyhumans-20160424223347"] {
+            let config = MirrorConfig::new().mirror(Mirror::new("core", "happyhumans").key(*key));
+            let source = config.source_for(&upstream, &ChannelIdent::stable());
+            let verified = source.verify(&archive, linux(), &cache.path());
+            assert!(verified.is_ok(), "{}: {:?}", key, verified);
+        }
+        for key in &["core", "happyhumans-20190101000000"] {
+            let config = MirrorConfig::new().mirror(Mirror::new("core", "happyhumans").key(*key));
+            match config.source_for(&upstream, &ChannelIdent::stable())
+                        .verify(&archive, linux(), &cache.path())
+            {
+                Err(Error::MirrorSignerMismatch(ref ident, ..)) => {
+                    assert_eq!(upstream.to_string(), *ident)
+                }
+                other => panic!("Expected a signer mismatch for {}, got {:?}", key, other),
+            }
+        }
+    }
+
+    #[test]
+    fn unmirrored_artifacts_must_carry_their_origin_signature() {
+        let cache = happyhumans_cache();
+        let config = MirrorConfig::new();
+        let source = |s: &str| config.source_for(&ident(s), &ChannelIdent::stable());
+
+        assert!(source("happyhumans/possums").verify(&possums(), linux(), &cache.path())
+                                             .is_ok());
+        match source("core/possums").verify(&possums(), linux(), &cache.path()) {
+            Err(Error::ArtifactSignerMismatch(ref ident, ref origin, _)) => {
+                assert_eq!(("core/possums", "core"), (ident.as_str(), origin.as_str()))
+            }
+            other => panic!("Expected ArtifactSignerMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn artifacts_must_be_the_package_and_target_asked_for() {
+        let cache = happyhumans_cache();
+        let config = MirrorConfig::new();
+        let windows = PackageTarget::from_str("x86_64-windows").unwrap();
+
+        for &(asked, target) in &[("happyhumans/possums/8.1.5", linux()),
+                                  ("happyhumans/opossums", linux()),
+                                  ("happyhumans/possums", windows)]
+        {
+            match config.source_for(&ident(asked), &ChannelIdent::stable())
+                        .verify(&possums(), target, &cache.path())
+            {
+                Err(Error::ArtifactMismatch(ref expected, ref found)) => {
+                    assert_eq!(format!("{} ({})", asked, target), *expected);
+                    assert_eq!("happyhumans/possums/8.1.4/20160427165340 (x86_64-linux)",
+                               found);
+                }
+                other => panic!("Expected ArtifactMismatch for {}, got {:?}", asked, other),
+            }
+        }
+    }
+}
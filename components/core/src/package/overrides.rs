@@ -0,0 +1,59 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Identifiable,
+            PackageIdent};
+
+/// Substitutions to make in a package's dependency closure, such as a locally built release of
+/// `core/openssl` in place of the one the package was built against. Overrides are meant for
+/// debugging and canary testing; the package itself is left untouched.
+///
+/// # Examples
+///
+/// ```
+/// use habitat_core::package::{overrides::DepOverrides,
+///                             PackageIdent};
+/// use std::str::FromStr;
+///
+/// let overrides =
+///     DepOverrides::new().replace(PackageIdent::from_str("core/openssl").unwrap(),
+///                                 PackageIdent::from_str("core/openssl/1.1.1").unwrap());
+///
+/// let listed = PackageIdent::from_str("core/openssl/1.0.2r/20190305210149").unwrap();
+/// assert_eq!("core/openssl/1.1.1",
+///            overrides.replacement_for(&listed).unwrap().to_string());
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DepOverrides(Vec<(PackageIdent, PackageIdent)>);
+
+impl DepOverrides {
+    pub fn new() -> Self { Self::default() }
+
+    /// Replaces any dependency which satisfies `dep` with the installed package `replacement`
+    /// resolves to. Earlier overrides take precedence over later ones.
+    pub fn replace(mut self, dep: PackageIdent, replacement: PackageIdent) -> Self {
+        self.0.push((dep, replacement));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// Returns what to resolve in place of the dependency `ident`, if it is overridden.
+    pub fn replacement_for(&self, ident: &PackageIdent) -> Option<&PackageIdent> {
+        self.0
+            .iter()
+            .find(|(dep, _)| ident.satisfies(dep))
+            .map(|(_, replacement)| replacement)
+    }
+}
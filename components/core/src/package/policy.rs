@@ -0,0 +1,229 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Admission policies, which decide whether a package may be installed or loaded.
+//!
+//! Callers describe the package in an `AdmissionRequest` and hand it to `admit`, which consults
+//! an `AdmissionPolicy` and turns a denial into an error. `AllowAll` admits everything, and
+//! `AllowList` admits only packages signed by given origins and taken from given channels.
+
+use super::{archive::PackageArchive,
+            PackageIdent,
+            PackageInstall,
+            PackageTarget};
+use crate::{crypto::{artifact,
+                     attestation::Statement,
+                     keys::parse_name_with_rev},
+            error::{Error,
+                    Result},
+            ChannelIdent};
+
+/// What is known about a package at the point it is about to be installed or loaded.
+#[derive(Clone, Copy, Debug)]
+pub struct AdmissionRequest<'a> {
+    pub ident:       &'a PackageIdent,
+    pub target:      PackageTarget,
+    /// The name with revision of the origin key the package's archive was signed with, if known.
+    /// Installed packages do not record their signer.
+    pub signer:      Option<&'a str>,
+    /// The channel the package was taken from, if any.
+    pub channel:     Option<&'a ChannelIdent>,
+    /// A verified provenance statement for the package's archive, if one was supplied.
+    pub attestation: Option<&'a Statement>,
+}
+
+/// The outcome of consulting a policy.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Verdict {
+    Allow,
+    /// Admit the package, but report the reason given.
+    Warn(String),
+    /// Refuse the package for the reason given.
+    Deny(String),
+}
+
+/// Decides whether packages may be installed or loaded.
+pub trait AdmissionPolicy {
+    fn check(&self, request: &AdmissionRequest<'_>) -> Verdict;
+}
+
+/// The default policy, which admits every package.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AllowAll;
+
+impl AdmissionPolicy for AllowAll {
+    fn check(&self, _request: &AdmissionRequest<'_>) -> Verdict { Verdict::Allow }
+}
+
+/// Admits only packages signed with a key of one of the listed origins and taken from one of
+/// the listed channels. An empty list places no restriction, and a package whose signer or
+/// channel is unknown fails any restriction on it.
+#[derive(Clone, Debug, Default)]
+pub struct AllowList {
+    origins:  Vec<String>,
+    channels: Vec<ChannelIdent>,
+}
+
+impl AllowList {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn origin<T: Into<String>>(mut self, origin: T) -> Self {
+        self.origins.push(origin.into());
+        self
+    }
+
+    pub fn channel(mut self, channel: ChannelIdent) -> Self {
+        self.channels.push(channel);
+        self
+    }
+}
+
+impl AdmissionPolicy for AllowList {
+    fn check(&self, request: &AdmissionRequest<'_>) -> Verdict {
+        if !self.origins.is_empty() {
+            let origin = request.signer
+                                .and_then(|signer| parse_name_with_rev(signer).ok())
+                                .map(|(origin, _)| origin);
+            match origin {
+                Some(ref origin) if self.origins.contains(origin) => {}
+                Some(origin) => {
+                    return Verdict::Deny(format!("signed by untrusted origin {}", origin))
+                }
+                None => return Verdict::Deny("signer is unknown".to_string()),
+            }
+        }
+        if !self.channels.is_empty() {
+            match request.channel {
+                Some(channel) if self.channels.contains(channel) => {}
+                Some(channel) => {
+                    return Verdict::Deny(format!("taken from untrusted channel {}", channel))
+                }
+                None => return Verdict::Deny("channel is unknown".to_string()),
+            }
+        }
+        Verdict::Allow
+    }
+}
+
+/// Consults `policy` about `request`, logging any warning.
+///
+/// # Failures
+///
+/// * The policy denies the package
+pub fn admit(policy: &dyn AdmissionPolicy, request: &AdmissionRequest<'_>) -> Result<()> {
+    match policy.check(request) {
+        Verdict::Allow => Ok(()),
+        Verdict::Warn(reason) => {
+            warn!("Admitting {} despite policy: {}", request.ident, reason);
+            Ok(())
+        }
+        Verdict::Deny(reason) => Err(Error::AdmissionDenied(request.ident.to_string(), reason)),
+    }
+}
+
+/// Consults `policy` before the package in `archive` is installed.
+///
+/// # Failures
+///
+/// * The archive's ident, target, or signer cannot be read
+/// * The policy denies the package
+pub fn admit_archive(policy: &dyn AdmissionPolicy,
+                     archive: &mut PackageArchive,
+                     channel: Option<&ChannelIdent>,
+                     attestation: Option<&Statement>)
+                     -> Result<()> {
+    let ident = archive.ident()?;
+    let signer = artifact::artifact_signer(&archive.path)?;
+    let request = AdmissionRequest { ident: &ident,
+                                     target: archive.target()?,
+                                     signer: Some(&signer),
+                                     channel,
+                                     attestation };
+    admit(policy, &request)
+}
+
+/// Consults `policy` before the installed package `pkg_install` is loaded.
+///
+/// # Failures
+///
+/// * The package's target cannot be read
+/// * The policy denies the package
+pub fn admit_install(policy: &dyn AdmissionPolicy, pkg_install: &PackageInstall) -> Result<()> {
+    let request = AdmissionRequest { ident:       pkg_install.ident(),
+                                     target:      pkg_install.target()?,
+                                     signer:      None,
+                                     channel:     None,
+                                     attestation: None, };
+    admit(policy, &request)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::target;
+    use std::str::FromStr;
+
+    fn request<'a>(ident: &'a PackageIdent,
+                   signer: Option<&'a str>,
+                   channel: Option<&'a ChannelIdent>)
+                   -> AdmissionRequest<'a> {
+        AdmissionRequest { ident,
+                           target: target::X86_64_LINUX,
+                           signer,
+                           channel,
+                           attestation: None }
+    }
+
+    #[test]
+    fn allow_all_admits_everything() {
+        let ident = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+
+        admit(&AllowAll, &request(&ident, None, None)).unwrap();
+    }
+
+    #[test]
+    fn allow_list_restricts_origins_and_channels() {
+        let ident = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+        let stable = ChannelIdent::stable();
+        let unstable = ChannelIdent::unstable();
+        let policy = AllowList::new().origin("core").channel(stable.clone());
+
+        assert_eq!(Verdict::Allow,
+                   policy.check(&request(&ident, Some("core-20160810182414"), Some(&stable))));
+        assert_eq!(Verdict::Deny("signed by untrusted origin acme".to_string()),
+                   policy.check(&request(&ident, Some("acme-20160810182414"), Some(&stable))));
+        assert_eq!(Verdict::Deny("signer is unknown".to_string()),
+                   policy.check(&request(&ident, None, Some(&stable))));
+        assert_eq!(Verdict::Deny("taken from untrusted channel unstable".to_string()),
+                   policy.check(&request(&ident, Some("core-20160810182414"), Some(&unstable))));
+
+        match admit(&policy, &request(&ident, None, None)) {
+            Err(Error::AdmissionDenied(denied, _)) => assert_eq!(ident.to_string(), denied),
+            other => panic!("Expected AdmissionDenied, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn warnings_admit() {
+        struct WarnAll;
+        impl AdmissionPolicy for WarnAll {
+            fn check(&self, _request: &AdmissionRequest<'_>) -> Verdict {
+                Verdict::Warn("unvetted".to_string())
+            }
+        }
+        let ident = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+
+        admit(&WarnAll, &request(&ident, None, None)).unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{collections::BTreeMap,
+          fs,
+          io,
+          path::{Path,
+                 PathBuf}};
+
+use serde_derive::Serialize;
+
+use super::{metadata::Bind,
+            PackageIdent,
+            PackageInstall,
+            PackageTarget};
+use crate::error::Result;
+
+/// Everything known about an installed package, gathered by `PackageInstall::to_report` into
+/// one value that can be serialized as a whole, such as for `hab pkg info --json`. Maps are
+/// ordered, so the same package always serializes the same way.
+#[derive(Clone, Debug, Serialize)]
+pub struct PackageReport {
+    pub ident:          PackageIdent,
+    pub target:         PackageTarget,
+    /// `Standalone` or `Composite`.
+    pub pkg_type:       String,
+    pub installed_path: PathBuf,
+    pub deps:           Vec<PackageIdent>,
+    pub tdeps:          Vec<PackageIdent>,
+    pub build_deps:     Vec<PackageIdent>,
+    pub exports:        BTreeMap<String, String>,
+    pub exposes:        Vec<String>,
+    pub binds:          Vec<Bind>,
+    pub binds_optional: Vec<Bind>,
+    pub svc_user:       Option<String>,
+    pub svc_group:      Option<String>,
+    /// The total size of the package's installed files, in bytes.
+    pub size:           u64,
+}
+
+/// What governance tooling needs to know about one package in a closure.
+#[derive(Clone, Debug, Serialize)]
+pub struct ClosureEntry {
+    pub ident:    PackageIdent,
+    /// The total size of the package's installed files, in bytes.
+    pub size:     u64,
+    /// The licenses listed in the package's `MANIFEST`.
+    pub licenses: Vec<String>,
+    pub exposes:  Vec<String>,
+    pub svc_user: Option<String>,
+}
+
+impl ClosureEntry {
+    fn new(pkg_install: &PackageInstall) -> Result<Self> {
+        Ok(ClosureEntry { ident:    pkg_install.ident().clone(),
+                          size:     installed_size(pkg_install.installed_path())?,
+                          licenses: pkg_install.licenses()?,
+                          exposes:  pkg_install.exposes()?,
+                          svc_user: pkg_install.svc_user()?, })
+    }
+}
+
+/// A package and its transitive dependencies, described by `closure_report`.
+#[derive(Clone, Debug, Serialize)]
+pub struct ClosureReport {
+    /// The package itself, followed by its transitive dependencies in the order its `TDEPS`
+    /// lists them.
+    pub packages:   Vec<ClosureEntry>,
+    /// The packages in the closure under each license. Packages whose manifest lists no license
+    /// are under `unknown`.
+    pub licenses:   BTreeMap<String, Vec<PackageIdent>>,
+    /// The total size of the closure's installed files, in bytes.
+    pub total_size: u64,
+}
+
+/// The license under which packages listing none are reported.
+pub const UNKNOWN_LICENSE: &str = "unknown";
+
+/// Gathers the size, licenses, exposed ports and service user of `pkg_install` and each of its
+/// transitive dependencies into one report.
+///
+/// # Failures
+///
+/// * A dependency is not installed
+/// * A metafile exists but cannot be properly parsed
+/// * The installed files cannot be read to total their size
+pub fn closure_report(pkg_install: &PackageInstall) -> Result<ClosureReport> {
+    let mut packages = vec![ClosureEntry::new(pkg_install)?];
+    for dep in pkg_install.load_tdeps()? {
+        packages.push(ClosureEntry::new(&dep)?);
+    }
+    let mut licenses = BTreeMap::<String, Vec<PackageIdent>>::new();
+    for entry in &packages {
+        if entry.licenses.is_empty() {
+            licenses.entry(UNKNOWN_LICENSE.to_string())
+                    .or_default()
+                    .push(entry.ident.clone());
+        }
+        for license in &entry.licenses {
+            licenses.entry(license.clone())
+                    .or_default()
+                    .push(entry.ident.clone());
+        }
+    }
+    let total_size = packages.iter().map(|entry| entry.size).sum();
+    Ok(ClosureReport { packages,
+                       licenses,
+                       total_size })
+}
+
+/// Returns the total size of the files under `path`, not following symlinks.
+pub(crate) fn installed_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        size += if metadata.is_dir() {
+            installed_size(&entry.path())?
+        } else {
+            metadata.len()
+        };
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::{metadata::MetaFile,
+                         test_support::testing_package_install};
+    use tempfile::Builder;
+
+    #[test]
+    fn closure_report_aggregates_dependencies() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let glibc = testing_package_install("core/glibc/2.27/20190115002733", fs_root.path());
+        let openssl = testing_package_install("core/openssl/1.0.2r/20190305210149", fs_root.path());
+        let redis = testing_package_install("core/redis/4.0.14/20190319155852", fs_root.path());
+        let write = |pkg: &PackageInstall, file: MetaFile, content: &str| {
+            fs::write(pkg.installed_path().join(file.to_string()), content).unwrap();
+        };
+        write(&redis,
+              MetaFile::TDeps,
+              &format!("{}\n{}\n", openssl.ident(), glibc.ident()));
+        write(&redis,
+              MetaFile::Manifest,
+              "# core / redis\n\n* __License__: BSD-3-Clause \n* __Version__: 4.0.14\n");
+        write(&redis, MetaFile::Exposes, "6379");
+        write(&redis, MetaFile::SvcUser, "hab");
+        write(&openssl,
+              MetaFile::Manifest,
+              "* __License__: OpenSSL Apache-2.0\n");
+        fs::write(glibc.installed_path().join("libc.so"), vec![0; 1024]).unwrap();
+
+        let report = closure_report(&redis).unwrap();
+
+        assert_eq!(vec![redis.ident(), openssl.ident(), glibc.ident()],
+                   report.packages.iter().map(|e| &e.ident).collect::<Vec<_>>());
+        assert_eq!(vec!["6379".to_string()], report.packages[0].exposes);
+        assert_eq!(Some("hab".to_string()), report.packages[0].svc_user);
+        assert_eq!(vec!["OpenSSL".to_string(), "Apache-2.0".to_string()],
+                   report.packages[1].licenses);
+        assert_eq!(vec!["Apache-2.0", "BSD-3-Clause", "OpenSSL", UNKNOWN_LICENSE],
+                   report.licenses.keys().collect::<Vec<_>>());
+        assert_eq!(vec![glibc.ident().clone()],
+                   report.licenses[UNKNOWN_LICENSE]);
+        assert_eq!(report.packages.iter().map(|e| e.size).sum::<u64>(),
+                   report.total_size);
+        assert!(report.total_size >= 1024);
+    }
+}
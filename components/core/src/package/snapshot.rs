@@ -0,0 +1,170 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Snapshots of a package's runtime environment, and the differences between them.
+//!
+//! Upgrade tooling can take a snapshot of the running release and of its candidate replacement
+//! with `PackageInstall::env_snapshot` and compare them with `env_diff`, to flag changes such as
+//! new `PATH` entries before switching over.
+
+use std::{collections::{BTreeMap,
+                        HashMap},
+          env,
+          fmt,
+          path::PathBuf};
+
+use serde_derive::{Deserialize,
+                   Serialize};
+
+use crate::crypto::hash;
+
+/// A runtime environment in canonical form: keys in sorted order, so that equal environments
+/// always serialize and hash the same way.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct EnvSnapshot(BTreeMap<String, String>);
+
+impl EnvSnapshot {
+    pub fn get(&self, key: &str) -> Option<&str> { self.0.get(key).map(String::as_str) }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// The BLAKE2b hash of the snapshot's canonical form, as hex.
+    pub fn hash(&self) -> String { hash::hash_string(&self.to_string()) }
+}
+
+impl From<HashMap<String, String>> for EnvSnapshot {
+    fn from(env: HashMap<String, String>) -> Self { EnvSnapshot(env.into_iter().collect()) }
+}
+
+/// The canonical form: one `KEY=VALUE` line per variable, sorted by key.
+impl fmt::Display for EnvSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (key, value) in &self.0 {
+            writeln!(f, "{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// How one variable differs between two snapshots.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum EnvChange {
+    Added(String),
+    Removed(String),
+    Changed { old: String, new: String },
+}
+
+impl EnvChange {
+    /// For a variable holding a list of paths, such as `PATH` or `LD_LIBRARY_PATH`, the entries
+    /// which are in the new value but not the old.
+    pub fn entries_added(&self) -> Vec<PathBuf> {
+        match *self {
+            EnvChange::Added(ref new) => env::split_paths(new).collect(),
+            EnvChange::Removed(_) => Vec::new(),
+            EnvChange::Changed { ref old, ref new } => entries_missing(new, old),
+        }
+    }
+
+    /// For a variable holding a list of paths, the entries which are in the old value but not
+    /// the new.
+    pub fn entries_removed(&self) -> Vec<PathBuf> {
+        match *self {
+            EnvChange::Added(_) => Vec::new(),
+            EnvChange::Removed(ref old) => env::split_paths(old).collect(),
+            EnvChange::Changed { ref old, ref new } => entries_missing(old, new),
+        }
+    }
+}
+
+/// The entries of the path list `from` which are not in `other`.
+fn entries_missing(from: &str, other: &str) -> Vec<PathBuf> {
+    let other: Vec<PathBuf> = env::split_paths(other).collect();
+    env::split_paths(from).filter(|p| !other.contains(p))
+                          .collect()
+}
+
+/// Every variable which differs between two snapshots, keyed by name.
+pub type EnvDiff = BTreeMap<String, EnvChange>;
+
+/// Compares the snapshot `a` of one release with the snapshot `b` of another, returning how each
+/// variable changed going from `a` to `b`. Variables which are the same in both are left out.
+pub fn env_diff(a: &EnvSnapshot, b: &EnvSnapshot) -> EnvDiff {
+    let mut diff = EnvDiff::new();
+    for (key, old) in &a.0 {
+        match b.0.get(key) {
+            Some(new) if new == old => {}
+            Some(new) => {
+                diff.insert(key.clone(),
+                            EnvChange::Changed { old: old.clone(),
+                                                 new: new.clone(), });
+            }
+            None => {
+                diff.insert(key.clone(), EnvChange::Removed(old.clone()));
+            }
+        }
+    }
+    for (key, new) in &b.0 {
+        if !a.0.contains_key(key) {
+            diff.insert(key.clone(), EnvChange::Added(new.clone()));
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn snapshot(vars: &[(&str, &str)]) -> EnvSnapshot {
+        vars.iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>()
+            .into()
+    }
+
+    #[test]
+    fn snapshots_are_canonical() {
+        let a = snapshot(&[("PATH", "/bin"), ("HOME", "/root"), ("LANG", "C")]);
+        let b = snapshot(&[("LANG", "C"), ("PATH", "/bin"), ("HOME", "/root")]);
+
+        assert_eq!(a, b);
+        assert_eq!("HOME=/root\nLANG=C\nPATH=/bin\n", a.to_string());
+        assert_eq!(a.hash(), b.hash());
+    }
+
+    #[test]
+    fn diff_reports_added_removed_and_changed() {
+        let path = |entries: &[&str]| env::join_paths(entries).unwrap().into_string().unwrap();
+        let old_path = path(&["/hab/pkgs/acme/app/1.0.0/20190101000000/bin", "/usr/bin"]);
+        let new_path = path(&["/hab/pkgs/acme/app/1.1.0/20190201000000/bin", "/usr/bin"]);
+        let a = snapshot(&[("PATH", &old_path), ("LANG", "C"), ("DEBUG", "1")]);
+        let b = snapshot(&[("PATH", &new_path),
+                           ("LANG", "C"),
+                           ("SSL_CERT_FILE", "/cacert.pem")]);
+
+        let diff = env_diff(&a, &b);
+
+        assert_eq!(3, diff.len());
+        assert_eq!(EnvChange::Removed("1".to_string()), diff["DEBUG"]);
+        assert_eq!(EnvChange::Added("/cacert.pem".to_string()),
+                   diff["SSL_CERT_FILE"]);
+        assert_eq!(vec![PathBuf::from("/hab/pkgs/acme/app/1.1.0/20190201000000/bin")],
+                   diff["PATH"].entries_added());
+        assert_eq!(vec![PathBuf::from("/hab/pkgs/acme/app/1.0.0/20190101000000/bin")],
+                   diff["PATH"].entries_removed());
+        assert!(env_diff(&a, &a).is_empty());
+    }
+}
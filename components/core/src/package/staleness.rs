@@ -0,0 +1,270 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Finding installed packages which are older than a patch-compliance policy allows.
+//!
+//! A package's age is taken from its release timestamp, which is when it was built. A
+//! `StalenessPolicy` gives the oldest a release may be, for each origin and for every other
+//! origin, and `evaluate` reports which of a set of packages are past it:
+//!
+//! ```
+//! use habitat_core::package::{staleness::{self,
+//!                                         Staleness,
+//!                                         StalenessPolicy},
+//!                             PackageIdent};
+//! use std::str::FromStr;
+//!
+//! let policy = StalenessPolicy::from_str("core=30d,*=13w").unwrap();
+//! let redis = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+//!
+//! let report = staleness::evaluate(&[redis], &policy);
+//! assert_eq!(Staleness::Stale, report.packages[0].staleness);
+//! ```
+
+use std::{collections::BTreeMap,
+          fmt,
+          str::FromStr};
+
+use serde_derive::Serialize;
+use time;
+
+use crate::{error::{Error,
+                    Result},
+            package::PackageIdent,
+            util};
+
+const DAY: u64 = 24 * 60 * 60;
+const WEEK: u64 = 7 * DAY;
+/// The key of the age which applies to origins the policy does not name.
+const ANY_ORIGIN: &str = "*";
+
+/// The oldest a release may be, in seconds, for each origin.
+///
+/// Written as a comma-separated list of `ORIGIN=AGE`, with `*` standing for any origin not
+/// otherwise named, where an age is a number of days followed by `d`, a number of weeks followed
+/// by `w`, or `none` for no limit. The default policy sets no limits.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StalenessPolicy {
+    origins: BTreeMap<String, Option<u64>>,
+    default: Option<u64>,
+}
+
+impl StalenessPolicy {
+    /// A policy limiting the age of releases from every origin to `max_age` seconds.
+    pub fn new(max_age: Option<u64>) -> Self {
+        StalenessPolicy { origins: BTreeMap::new(),
+                          default: max_age, }
+    }
+
+    /// Limits the age of releases from `origin` to `max_age` seconds, or lifts the limit for it
+    /// with `None`, whatever the limit for other origins.
+    pub fn with_origin(mut self, origin: &str, max_age: Option<u64>) -> Self {
+        self.origins.insert(origin.to_string(), max_age);
+        self
+    }
+
+    /// The oldest a release from `origin` may be, in seconds, or `None` if it may be any age.
+    pub fn max_age(&self, origin: &str) -> Option<u64> {
+        self.origins.get(origin).cloned().unwrap_or(self.default)
+    }
+}
+
+impl FromStr for StalenessPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut policy = StalenessPolicy::default();
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let mut parts = setting.splitn(2, '=');
+            let origin = parts.next().unwrap_or_default().trim();
+            let invalid = || Error::InvalidStalenessPolicy(s.to_string());
+            let age = parts.next().ok_or_else(invalid)?.trim();
+            let age = if age == "none" {
+                None
+            } else {
+                Some(parse_age(age).ok_or_else(invalid)?)
+            };
+            match origin {
+                "" => return Err(invalid()),
+                ANY_ORIGIN => policy.default = age,
+                origin => policy = policy.with_origin(origin, age),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+impl fmt::Display for StalenessPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let settings: Vec<String> =
+            self.origins
+                .iter()
+                .map(|(origin, age)| (origin.as_str(), *age))
+                .chain(Some((ANY_ORIGIN, self.default)))
+                .map(|(origin, age)| format!("{}={}", origin, format_age(age)))
+                .collect();
+        write!(f, "{}", settings.join(","))
+    }
+}
+
+/// Parses an age such as `30d` or `13w` into seconds.
+fn parse_age(s: &str) -> Option<u64> {
+    if !(s.ends_with('d') || s.ends_with('w')) {
+        return None;
+    }
+    util::parse_duration(s).map(|age| age.as_secs())
+}
+
+fn format_age(age: Option<u64>) -> String {
+    match age {
+        Some(age) if age % WEEK == 0 && age > 0 => format!("{}w", age / WEEK),
+        Some(age) => format!("{}d", age / DAY),
+        None => "none".to_string(),
+    }
+}
+
+/// How a package stands against a `StalenessPolicy`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Staleness {
+    /// The release is no older than its origin allows.
+    Fresh,
+    /// The release is older than its origin allows.
+    Stale,
+    /// The policy sets no limit for the package's origin.
+    Exempt,
+    /// The package's age cannot be told, as its release is missing or not a timestamp.
+    Unknown,
+}
+
+/// One package's age and standing.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct StalenessEntry {
+    pub ident:     PackageIdent,
+    /// How long ago the release was built, in seconds, when that can be told.
+    pub age:       Option<i64>,
+    /// The oldest the policy allows a release from the package's origin to be, in seconds.
+    pub max_age:   Option<u64>,
+    pub staleness: Staleness,
+}
+
+/// The standing of a set of packages against a `StalenessPolicy`, in the order they were
+/// given.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct StalenessReport {
+    /// When the ages were measured from, in seconds since the Unix epoch.
+    pub evaluated_at: i64,
+    pub packages:     Vec<StalenessEntry>,
+}
+
+impl StalenessReport {
+    /// The packages which are older than the policy allows.
+    pub fn stale(&self) -> impl Iterator<Item = &StalenessEntry> {
+        self.packages
+            .iter()
+            .filter(|entry| entry.staleness == Staleness::Stale)
+    }
+
+    /// How many packages of each origin have each standing, for summarizing a fleet.
+    pub fn counts_by_origin(&self) -> BTreeMap<&str, BTreeMap<Staleness, usize>> {
+        let mut counts = BTreeMap::<&str, BTreeMap<Staleness, usize>>::new();
+        for entry in &self.packages {
+            *counts.entry(&entry.ident.origin)
+                   .or_default()
+                   .entry(entry.staleness)
+                   .or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+/// Judges each of `idents` against `policy`, as of now.
+pub fn evaluate<'a, I>(idents: I, policy: &StalenessPolicy) -> StalenessReport
+    where I: IntoIterator<Item = &'a PackageIdent>
+{
+    evaluate_at(idents, policy, time::now_utc().to_timespec().sec)
+}
+
+/// As `evaluate`, with ages measured from `now`, in seconds since the Unix epoch.
+pub fn evaluate_at<'a, I>(idents: I, policy: &StalenessPolicy, now: i64) -> StalenessReport
+    where I: IntoIterator<Item = &'a PackageIdent>
+{
+    let packages =
+        idents.into_iter()
+              .map(|ident| {
+                  let age = ident.release_time().map(|built| now - built);
+                  let max_age = policy.max_age(&ident.origin);
+                  let staleness = match (age, max_age) {
+                      (None, _) => Staleness::Unknown,
+                      (_, None) => Staleness::Exempt,
+                      (Some(age), Some(max_age)) if age > max_age as i64 => Staleness::Stale,
+                      _ => Staleness::Fresh,
+                  };
+                  StalenessEntry { ident: ident.clone(),
+                                   age,
+                                   max_age,
+                                   staleness }
+              })
+              .collect();
+    StalenessReport { evaluated_at: now,
+                      packages }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ident(s: &str) -> PackageIdent { PackageIdent::from_str(s).unwrap() }
+
+    #[test]
+    fn policies_parse_and_display() {
+        let policy = StalenessPolicy::from_str(" core=30d, acme=none,*=13w ").unwrap();
+
+        assert_eq!(Some(30 * DAY), policy.max_age("core"));
+        assert_eq!(None, policy.max_age("acme"));
+        assert_eq!(Some(13 * WEEK), policy.max_age("other"));
+        assert_eq!("acme=none,core=30d,*=13w", policy.to_string());
+        assert_eq!(policy, policy.to_string().parse().unwrap());
+        assert_eq!(StalenessPolicy::default(), "".parse().unwrap());
+        for invalid in &["core", "core=30", "core=30m", "=30d", "core=-1d", "core=d"] {
+            assert!(StalenessPolicy::from_str(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn packages_are_judged_by_origin() {
+        let policy = StalenessPolicy::new(Some(90 * DAY)).with_origin("core", Some(30 * DAY))
+                                                         .with_origin("acme", None);
+        let packages = vec![ident("core/redis/4.0.14/20190319155852"),
+                            ident("core/nginx/1.15.6/20190501000000"),
+                            ident("acme/app/1.0.0/20180101000000"),
+                            ident("other/tool/1.0.0/20190301000000"),
+                            ident("core/unreleased")];
+        let now = packages[1].release_time().unwrap() + DAY as i64;
+
+        let report = evaluate_at(&packages, &policy, now);
+
+        let standings: Vec<_> = report.packages.iter().map(|e| e.staleness).collect();
+        assert_eq!(vec![Staleness::Stale,
+                        Staleness::Fresh,
+                        Staleness::Exempt,
+                        Staleness::Fresh,
+                        Staleness::Unknown],
+                   standings);
+        assert_eq!(Some(DAY as i64), report.packages[1].age);
+        assert_eq!(vec![&packages[0]],
+                   report.stale().map(|e| &e.ident).collect::<Vec<_>>());
+        assert_eq!(1, report.counts_by_origin()["core"][&Staleness::Stale]);
+    }
+}
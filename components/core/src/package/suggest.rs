@@ -0,0 +1,145 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! "Did you mean" suggestions for a package which cannot be found, drawn from the packages which
+//! are installed. Finding them walks the package root, so nothing does it up front: a CLI which
+//! gets a `PackageNotFound` error calls `suggest_similar` to offer them rather than a bare
+//! not-found message.
+
+use std::{collections::BTreeSet,
+          path::Path};
+
+use super::{list,
+            PackageIdent};
+use crate::fs;
+
+/// The most suggestions offered for a single missing package.
+pub const MAX_SUGGESTIONS: usize = 3;
+
+/// Returns up to `MAX_SUGGESTIONS` installed packages, by origin and name, which are close to
+/// `ident`, closest first. A package root which is missing or cannot be read yields no
+/// suggestions.
+pub fn suggest_similar(ident: &PackageIdent, fs_root_path: Option<&Path>) -> Vec<PackageIdent> {
+    match list::all_packages(&fs::pkg_root_path(fs_root_path)) {
+        Ok(installed) => closest(ident, &installed),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Returns up to `MAX_SUGGESTIONS` of the distinct origins and names among `candidates` which are
+/// within a few edits of `ident`'s, closest first.
+///
+/// Only the origin and name are compared, so when some other release of `ident`'s package is
+/// among `candidates`, the package itself is the first suggestion.
+pub fn closest(ident: &PackageIdent, candidates: &[PackageIdent]) -> Vec<PackageIdent> {
+    let wanted = format!("{}/{}", ident.origin, ident.name);
+    let threshold = max_distance(&wanted);
+    let names: BTreeSet<(&str, &str)> = candidates.iter()
+                                                  .map(|c| (c.origin.as_str(), c.name.as_str()))
+                                                  .collect();
+    let mut scored: Vec<(usize, PackageIdent)> =
+        names.into_iter()
+             .filter_map(|(origin, name)| {
+                 let distance = edit_distance(&wanted, &format!("{}/{}", origin, name));
+                 if distance <= threshold {
+                     Some((distance, PackageIdent::new(origin, name, None, None)))
+                 } else {
+                     None
+                 }
+             })
+             .collect();
+    // The set was already ordered by origin and name, so a stable sort keeps ties in that order
+    scored.sort_by_key(|&(distance, _)| distance);
+    scored.into_iter()
+          .take(MAX_SUGGESTIONS)
+          .map(|(_, ident)| ident)
+          .collect()
+}
+
+/// How many edits away a package may be and still be suggested for `wanted`: about a third of
+/// its length, so that short names don't match everything.
+fn max_distance(wanted: &str) -> usize { (wanted.chars().count() / 3).max(2) }
+
+/// The Levenshtein distance between `a` and `b`: the fewest single character insertions,
+/// deletions or substitutions which turn one into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    fn idents(idents: &[&str]) -> Vec<PackageIdent> {
+        idents.iter()
+              .map(|i| PackageIdent::from_str(i).unwrap())
+              .collect()
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(0, edit_distance("core/redis", "core/redis"));
+        assert_eq!(1, edit_distance("core/rediss", "core/redis"));
+        assert_eq!(2, edit_distance("core/rdeis", "core/redis"));
+        assert_eq!(3, edit_distance("kitten", "sitting"));
+        assert_eq!(4, edit_distance("", "core"));
+    }
+
+    #[test]
+    fn closest_installed_packages() {
+        let installed = idents(&["core/redis/4.0.14/20190319155852",
+                                 "core/redis/5.0.3/20190319155852",
+                                 "core/nginx/1.15.6/20190115184343",
+                                 "acme/redis/1.0.0/20190101000000",
+                                 "core/glibc/2.27/20190115002733"]);
+
+        let suggested = closest(&PackageIdent::from_str("core/rediss").unwrap(), &installed);
+        assert_eq!(idents(&["core/redis"]), suggested);
+
+        let suggested = closest(&PackageIdent::from_str("core/redis/9.9.9").unwrap(),
+                                &installed);
+        assert_eq!(idents(&["core/redis", "acme/redis"]), suggested);
+
+        assert!(closest(&PackageIdent::from_str("core/postgresql").unwrap(),
+                        &installed).is_empty());
+    }
+
+    #[test]
+    fn similar_packages_from_fs_root() {
+        let fs_root = tempfile::Builder::new().prefix("fs-root")
+                                              .tempdir()
+                                              .unwrap();
+        assert!(suggest_similar(&PackageIdent::from_str("core/redis").unwrap(),
+                                Some(fs_root.path())).is_empty());
+
+        super::super::test_support::testing_package_install("core/redis/5.0.3/20190319155852",
+                                                            fs_root.path());
+
+        assert_eq!(idents(&["core/redis"]),
+                   suggest_similar(&PackageIdent::from_str("core/reddis").unwrap(),
+                                   Some(fs_root.path())));
+    }
+}
@@ -51,6 +51,24 @@
 //! when calling the [`load`][install_load] or [`load_at_least`][install_load_at_least] functions
 //! on [`PackageInstall`].
 //!
+//! # Overriding the Active Target
+//!
+//! The active target is the [`system_target`][system_target] unless a thread overrides it, which
+//! lets tooling that works on behalf of several targets at once, such as a Builder worker
+//! evaluating both Linux targets, use the resolution functions from a thread per target without
+//! their choices of target crossing over. An override applies only to the thread which made it,
+//! and lasts until the [`ActiveTargetOverride`] it returns is dropped:
+//!
+//! ```
+//! use habitat_core::package::target::{self,
+//!                                     PackageTarget};
+//!
+//! PackageTarget::with_active_target(target::X86_64_LINUX_KERNEL2, || {
+//!     assert_eq!(target::X86_64_LINUX_KERNEL2, PackageTarget::active_target());
+//! });
+//! assert_eq!(PackageTarget::system_target(), PackageTarget::active_target());
+//! ```
+//!
 //! # A Special Note Concerning Variants
 //!
 //! The optional variant does **not** correspond to a `<vendor>` or `<abi>` as taken from a
@@ -67,6 +85,8 @@
 //! [archive_target]: ../archive/struct.PackageArchive.html#method.target
 //! [install_load]: ../install/struct.PackageInstall.html#method.load
 //! [install_load_at_least]: ../install/struct.PackageInstall.html#method.load_at_least
+//! [system_target]: struct.PackageTarget.html#method.system_target
+//! [`ActiveTargetOverride`]: struct.ActiveTargetOverride.html
 //! [`PackageArchive`]: ../archive/struct.PackageArchive.html
 //! [`PackageInstall`]: ../install/struct.PackageInstall.html
 //! [`PackageTarget`]: struct.PackageTarget.html
@@ -77,7 +97,9 @@
 //! [musl]: https://www.musl-libc.org/
 //! [rust_triple]: https://github.com/rust-lang/rust/tree/master/src/librustc_back/target
 
-use std::{fmt,
+use std::{cell::Cell,
+          fmt,
+          marker::PhantomData,
           ops::Deref,
           result,
           str::FromStr};
@@ -319,6 +341,30 @@ lazy_static::lazy_static! {
     static ref ACTIVE_PACKAGE_TARGET: PackageTarget = active_package_target();
 }
 
+thread_local! {
+    /// The target which the current thread has put in place of the active target, if any.
+    static ACTIVE_TARGET_OVERRIDE: Cell<Option<PackageTarget>> = Cell::new(None);
+}
+
+/// An override of the current thread's active target, made by
+/// `PackageTarget::override_active_target`, which lasts until it is dropped.
+///
+/// It cannot be sent to another thread, as it must be dropped on the thread whose active target
+/// it overrides.
+#[derive(Debug)]
+#[must_use = "the active target reverts as soon as the override is dropped"]
+pub struct ActiveTargetOverride {
+    previous: Option<PackageTarget>,
+    not_send: PhantomData<*const ()>,
+}
+
+impl Drop for ActiveTargetOverride {
+    fn drop(&mut self) {
+        let previous = self.previous;
+        ACTIVE_TARGET_OVERRIDE.with(|active| active.set(previous));
+    }
+}
+
 /// Represents a specific system architecture.
 ///
 /// More details about the overall approach can be found in the [module documentation](index.html).
@@ -369,8 +415,8 @@ impl PackageTarget {
                pos:    0, }
     }
 
-    /// Returns the `PackageTarget` that is determined at compile time for the currently running
-    /// system architecture.
+    /// Returns the `PackageTarget` which packages are resolved and named for: the target the
+    /// current thread has overridden it with, if any, or otherwise the `system_target`.
     ///
     /// This can be used to compare a [`PackageArchive`] or [`PackageInstall`]'s type with the
     /// currently supported version when this code is compiled.
@@ -386,7 +432,46 @@ impl PackageTarget {
     /// let active = PackageTarget::active_target();
     /// println!("The active target for this system is '{}'", active);
     /// ```
-    pub fn active_target() -> Self { *ACTIVE_PACKAGE_TARGET }
+    pub fn active_target() -> Self {
+        ACTIVE_TARGET_OVERRIDE.with(Cell::get)
+                              .unwrap_or(*ACTIVE_PACKAGE_TARGET)
+    }
+
+    /// Returns the `PackageTarget` that is determined at compile time for the currently running
+    /// system architecture, whatever the active target has been overridden with.
+    pub fn system_target() -> Self { *ACTIVE_PACKAGE_TARGET }
+
+    /// Makes `target` the active target of the current thread until the returned override is
+    /// dropped, when the active target reverts to what it was before. Other threads, including
+    /// those spawned while the override is in place, are not affected.
+    ///
+    /// Overrides may be nested, and should be dropped in the reverse of the order they were made.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use habitat_core::package::target::{self,
+    ///                                     PackageTarget};
+    ///
+    /// let active = PackageTarget::override_active_target(target::X86_64_WINDOWS);
+    /// assert_eq!(target::X86_64_WINDOWS, PackageTarget::active_target());
+    /// drop(active);
+    /// assert_eq!(PackageTarget::system_target(), PackageTarget::active_target());
+    /// ```
+    pub fn override_active_target(target: PackageTarget) -> ActiveTargetOverride {
+        let previous = ACTIVE_TARGET_OVERRIDE.with(|active| active.replace(Some(target)));
+        ActiveTargetOverride { previous,
+                               not_send: PhantomData }
+    }
+
+    /// Calls `f` with `target` as the active target of the current thread, reverting to the
+    /// previous active target when `f` returns or panics.
+    pub fn with_active_target<F, T>(target: PackageTarget, f: F) -> T
+        where F: FnOnce() -> T
+    {
+        let _active = Self::override_active_target(target);
+        f()
+    }
 
     /// Produces an iterator over all supported `PackageTarget`s.
     ///
@@ -628,4 +713,48 @@ mod test {
         assert_eq!(Some("kernel2"), iter.next());
         assert_eq!(None, iter.next());
     }
+
+    #[test]
+    fn active_target_overrides_nest_and_revert() {
+        let system = PackageTarget::system_target();
+        {
+            let _outer = PackageTarget::override_active_target(X86_64_WINDOWS);
+            PackageTarget::with_active_target(X86_64_DARWIN, || {
+                assert_eq!(X86_64_DARWIN, PackageTarget::active_target());
+            });
+            assert_eq!(X86_64_WINDOWS, PackageTarget::active_target());
+            assert_eq!(system, PackageTarget::system_target());
+        }
+        assert_eq!(system, PackageTarget::active_target());
+
+        let panicked = std::panic::catch_unwind(|| {
+            PackageTarget::with_active_target(X86_64_WINDOWS, || panic!("resolution failed"))
+        });
+        assert!(panicked.is_err());
+        assert_eq!(system, PackageTarget::active_target());
+    }
+
+    #[test]
+    fn active_target_overrides_are_per_thread() {
+        let targets = vec![X86_64_LINUX, X86_64_LINUX_KERNEL2];
+        let handles: Vec<_> = targets.iter()
+                                     .map(|&target| {
+                                         std::thread::spawn(move || {
+                                             PackageTarget::with_active_target(target, || {
+                                                 (0..100).all(|_| {
+                                                             std::thread::yield_now();
+                                                             PackageTarget::active_target()
+                                                             == target
+                                                         })
+                                             })
+                                         })
+                                     })
+                                     .collect();
+
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+        assert_eq!(PackageTarget::system_target(),
+                   PackageTarget::active_target());
+    }
 }
@@ -0,0 +1,370 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fixtures and generators for tests of code which works with packages, in this crate and in
+//! crates depending on it with the `testing` feature.
+//!
+//! Besides `testing_package_install`, which lays out a minimal installed package, the generators
+//! here produce random values for property-style tests: valid idents, targets and metafile
+//! bodies, which code must accept, and adversarial ones, which it must reject or cope with
+//! without panicking. Each takes the `Rng` to draw from, so a test can use a seeded generator
+//! to reproduce a failure.
+
+use std::{fs::{create_dir_all,
+               File},
+          io::Write,
+          path::{Path,
+                 PathBuf},
+          str::FromStr};
+
+use rand::Rng;
+use time;
+
+use super::{metadata::MetaFile,
+            *};
+use crate::fs;
+
+/// Returns the path of the fixture `name` under `fixtures`, the directory a crate's test fixtures
+/// are kept in.
+pub fn fixture_path(fixtures: &Path, name: &str) -> PathBuf { fixtures.join(name) }
+
+/// The directory this crate's own test fixtures are kept in.
+#[cfg(test)]
+pub fn fixtures() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests")
+                                             .join("fixtures")
+}
+
+/// Creates a minimal installed package under an fs_root and return a corresponding loaded
+/// `PackageInstall` suitable for testing against. The `IDENT` and `TARGET` metafiles are
+/// created and for the target system the tests are running on. Further subdirectories, files,
+/// and metafile can be created under this path.
+pub fn testing_package_install(ident: &str, fs_root: &Path) -> PackageInstall {
+    fn write_file(path: &Path, content: &str) {
+        let mut f = File::create(path).unwrap();
+        f.write_all(content.as_bytes()).unwrap()
+    }
+
+    let mut pkg_ident = PackageIdent::from_str(ident).unwrap();
+    if !pkg_ident.fully_qualified() {
+        if pkg_ident.version.is_none() {
+            pkg_ident.version = Some(String::from("1.0.0"));
+        }
+        if pkg_ident.release.is_none() {
+            pkg_ident.release = Some(time::now_utc().strftime("%Y%m%d%H%M%S")
+                                                    .unwrap()
+                                                    .to_string());
+        }
+    }
+    let pkg_install_path = fs::pkg_install_path(&pkg_ident, Some(fs_root));
+
+    create_dir_all(&pkg_install_path).unwrap();
+    write_file(&pkg_install_path.join(MetaFile::Ident.to_string()),
+               &pkg_ident.to_string());
+    write_file(&pkg_install_path.join(MetaFile::Target.to_string()),
+               &PackageTarget::active_target());
+
+    PackageInstall::load(&pkg_ident, Some(fs_root)).unwrap_or_else(|_| {
+                                                       panic!("PackageInstall should load for {}",
+                                                              &pkg_ident)
+                                                   })
+}
+
+/// Characters which have caused trouble in idents and metafiles: separators, whitespace,
+/// control characters, path components and characters outside ASCII.
+const NASTY: &[&str] = &["/", "\\", " ", "\t", "\n", "\r", "\0", "=", ":", ";", ".", "..", "-",
+                         "_", "@", "$", "${", "%", "é", "\u{202e}", "\u{feff}", "🦀"];
+
+/// Ident-shaped strings which have turned up in the wild or in bug reports.
+const ADVERSARIAL_IDENTS: &[&str] = &["",
+                                      "/",
+                                      "//",
+                                      "core",
+                                      "core/",
+                                      "/redis",
+                                      "core//redis",
+                                      "core/redis/",
+                                      "core/redis//20190115013919",
+                                      "core/redis/4.0.14/20190115013919/extra",
+                                      "core/redis/4.0.14/2019",
+                                      "core/redis/4.0.14/notarelease",
+                                      " core/redis ",
+                                      "Core/Redis",
+                                      "core/re dis",
+                                      "../../etc/passwd",
+                                      "core\\redis",
+                                      "core/redis/../../..",
+                                      "core/redis/4.0.14/20190115013919\n"];
+
+fn pick<R: Rng, T: Copy>(rng: &mut R, items: &[T]) -> T { items[rng.gen::<usize>() % items.len()] }
+
+/// A string of `min` to `max` characters drawn from `chars`.
+fn word<R: Rng>(rng: &mut R, chars: &[u8], min: usize, max: usize) -> String {
+    let len = min + rng.gen::<usize>() % (max - min + 1);
+    (0..len).map(|_| pick(rng, chars) as char).collect()
+}
+
+/// A valid origin name.
+pub fn origin<R: Rng>(rng: &mut R) -> String {
+    let first = word(rng, b"abcdefghijklmnopqrstuvwxyz0123456789", 1, 1);
+    first + &word(rng, b"abcdefghijklmnopqrstuvwxyz0123456789_-", 0, 15)
+}
+
+/// A valid package name.
+pub fn name<R: Rng>(rng: &mut R) -> String {
+    word(rng,
+         b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_-",
+         1,
+         20)
+}
+
+/// A version of one to four numeric parts, sometimes with a suffix such as `-rc1`.
+pub fn version<R: Rng>(rng: &mut R) -> String {
+    let parts = 1 + rng.gen::<usize>() % 4;
+    let mut version = (0..parts).map(|_| (rng.gen::<u16>() % 100).to_string())
+                                .collect::<Vec<_>>()
+                                .join(".");
+    if rng.gen_bool(0.1) {
+        version.push_str(pick(rng, &["-rc1", "-beta", "-dev", "+build5"]));
+    }
+    version
+}
+
+/// A release timestamp, as the fourteen digits `%Y%m%d%H%M%S` gives.
+pub fn release<R: Rng>(rng: &mut R) -> String {
+    format!("{:04}{:02}{:02}{:02}{:02}{:02}",
+            2016 + rng.gen::<u32>() % 15,
+            1 + rng.gen::<u32>() % 12,
+            1 + rng.gen::<u32>() % 28,
+            rng.gen::<u32>() % 24,
+            rng.gen::<u32>() % 60,
+            rng.gen::<u32>() % 60)
+}
+
+/// A valid, fully qualified ident.
+pub fn ident<R: Rng>(rng: &mut R) -> PackageIdent {
+    PackageIdent::new(origin(rng),
+                      name(rng),
+                      Some(version(rng)),
+                      Some(release(rng)))
+}
+
+/// A valid ident which may leave out its release, or both its version and release.
+pub fn fuzzy_ident<R: Rng>(rng: &mut R) -> PackageIdent {
+    let mut ident = ident(rng);
+    match rng.gen::<u8>() % 3 {
+        0 => ident.release = None,
+        1 => {
+            ident.version = None;
+            ident.release = None;
+        }
+        _ => {}
+    }
+    ident
+}
+
+/// An ident-shaped string which is damaged in some way: one known to cause trouble, or a valid
+/// ident with characters inserted, removed or replaced. `PackageIdent::from_str` accepts some of
+/// them, so code taking idents from outside must cope with all of them without panicking.
+pub fn adversarial_ident<R: Rng>(rng: &mut R) -> String {
+    if rng.gen_bool(0.3) {
+        return pick(rng, ADVERSARIAL_IDENTS).to_string();
+    }
+    let mut chars: Vec<String> = ident(rng).to_string()
+                                           .chars()
+                                           .map(|c| c.to_string())
+                                           .collect();
+    for _ in 0..1 + rng.gen::<usize>() % 3 {
+        let i = rng.gen::<usize>() % (chars.len() + 1);
+        match rng.gen::<u8>() % 3 {
+            0 => chars.insert(i, pick(rng, NASTY).to_string()),
+            1 if i < chars.len() => {
+                chars.remove(i);
+            }
+            _ if i < chars.len() => chars[i] = pick(rng, NASTY).to_string(),
+            _ => chars.push(pick(rng, NASTY).to_string()),
+        }
+    }
+    if rng.gen_bool(0.05) {
+        chars.insert(0, "a".repeat(300));
+    }
+    chars.concat()
+}
+
+/// One of the targets this crate supports.
+pub fn target<R: Rng>(rng: &mut R) -> PackageTarget {
+    let targets: Vec<_> = PackageTarget::supported_targets().collect();
+    *pick(rng, &targets)
+}
+
+/// A valid body for `metafile`, as a build would write it. Metafiles without a generator here,
+/// such as `MANIFEST` or `default.toml`, get an empty body.
+pub fn metafile_body<R: Rng>(rng: &mut R, metafile: MetaFile) -> String {
+    let count = rng.gen::<usize>() % 5;
+    let lines = |rng: &mut R, line: &dyn Fn(&mut R) -> String| {
+        (0..count).map(|_| line(rng)).collect::<Vec<_>>().join("\n")
+    };
+    let var = |rng: &mut R| word(rng, b"ABCDEFGHIJKLMNOPQRSTUVWXYZ_", 1, 12);
+    let path = |rng: &mut R| {
+        fs::pkg_install_path(&ident(rng), None::<&Path>).join("bin")
+                                                        .display()
+                                                        .to_string()
+    };
+    match metafile {
+        MetaFile::Deps | MetaFile::TDeps | MetaFile::BuildDeps | MetaFile::BuildTDeps => {
+            lines(rng, &|rng| ident(rng).to_string())
+        }
+        MetaFile::Services => lines(rng, &|rng| fuzzy_ident(rng).to_string()),
+        MetaFile::Binds | MetaFile::BindsOptional => {
+            lines(rng, &|rng| {
+                format!("{}={}", name(rng), var(rng).to_lowercase())
+            })
+        }
+        MetaFile::Environment | MetaFile::RuntimeEnvironment | MetaFile::Exports => {
+            lines(rng, &|rng| format!("{}={}", var(rng), path(rng)))
+        }
+        MetaFile::EnvironmentSep => lines(rng, &|rng| format!("{}=:", var(rng))),
+        MetaFile::Exposes => {
+            (0..count).map(|_| (1 + rng.gen::<u16>() % 65535).to_string())
+                      .collect::<Vec<_>>()
+                      .join(" ")
+        }
+        MetaFile::Path | MetaFile::RuntimePath => {
+            (0..count).map(|_| path(rng)).collect::<Vec<_>>().join(":")
+        }
+        MetaFile::Ident => ident(rng).to_string(),
+        MetaFile::Target => target(rng).to_string(),
+        MetaFile::SvcUser | MetaFile::SvcGroup => "hab".to_string(),
+        MetaFile::Type => pick(rng, &["Standalone", "Composite"]).to_string(),
+        _ => String::new(),
+    }
+}
+
+/// A metafile body which is damaged in some way: lines without a `=`, invalid UTF-8, NUL bytes,
+/// stray carriage returns, very long lines, duplicated keys and blank lines, mixed in with valid
+/// lines of `metafile`.
+pub fn adversarial_metafile_body<R: Rng>(rng: &mut R, metafile: MetaFile) -> Vec<u8> {
+    let mut body = Vec::new();
+    for _ in 0..1 + rng.gen::<usize>() % 8 {
+        let line: Vec<u8> = match rng.gen::<u8>() % 8 {
+            0 => metafile_body(rng, metafile).into_bytes(),
+            1 => b"no equals sign here".to_vec(),
+            2 => vec![0xff, 0xfe, b'=', 0xc3],
+            3 => b"KEY=\0value".to_vec(),
+            4 => b"KEY=value\r\r".to_vec(),
+            5 => vec![b'x'; 64 * 1024],
+            6 => b"KEY=one\nKEY=two".to_vec(),
+            _ => adversarial_ident(rng).into_bytes(),
+        };
+        body.extend(line);
+        body.extend(pick(rng, &[&b"\n"[..], b"\r\n", b"\n\n", b""]).iter());
+    }
+    body
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{rngs::StdRng,
+               SeedableRng};
+    use tempfile::Builder;
+
+    /// A fixed seed, so that a failure can be reproduced.
+    fn rng() -> StdRng { StdRng::seed_from_u64(0x68_6162_6974_6174) }
+
+    #[test]
+    fn generated_idents_are_valid() {
+        let mut rng = rng();
+        for _ in 0..500 {
+            let ident = ident(&mut rng);
+
+            assert!(ident.fully_qualified());
+            assert!(ident::is_valid_origin_name(&ident.origin), "{}", ident);
+            assert_eq!(ident, PackageIdent::from_str(&ident.to_string()).unwrap());
+            assert!(ident.archive_name().is_ok());
+        }
+    }
+
+    #[test]
+    fn adversarial_idents_do_not_panic() {
+        let mut rng = rng();
+        for _ in 0..2000 {
+            let input = adversarial_ident(&mut rng);
+
+            if let Ok(ident) = PackageIdent::from_str(&input) {
+                let _ = ident.to_string();
+                let _ = ident.archive_name();
+            }
+            let _ = PackageIdent::parse_lenient(&input);
+        }
+    }
+
+    #[test]
+    fn generated_metafiles_are_read() {
+        let mut rng = rng();
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/generated", fs_root.path());
+        let write = |metafile: MetaFile, body: &[u8]| {
+            std::fs::write(pkg_install.installed_path().join(metafile.to_string()),
+                           body).unwrap();
+        };
+
+        for _ in 0..50 {
+            for &metafile in &[MetaFile::Binds,
+                               MetaFile::Exports,
+                               MetaFile::RuntimeEnvironment]
+            {
+                write(metafile, metafile_body(&mut rng, metafile).as_bytes());
+            }
+            let exposes = metafile_body(&mut rng, MetaFile::Exposes);
+            write(MetaFile::Exposes, exposes.as_bytes());
+
+            assert!(pkg_install.binds().is_ok());
+            assert!(pkg_install.exports().is_ok());
+            if !exposes.is_empty() {
+                assert_eq!(exposes.split(' ').collect::<Vec<_>>(),
+                           pkg_install.exposes().unwrap());
+            }
+            assert!(pkg_install.environment_for_command(Default::default())
+                               .is_ok());
+        }
+    }
+
+    #[test]
+    fn adversarial_metafiles_do_not_panic() {
+        let mut rng = rng();
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/damaged", fs_root.path());
+        let metafiles = [MetaFile::Binds,
+                         MetaFile::Deps,
+                         MetaFile::TDeps,
+                         MetaFile::Exports,
+                         MetaFile::Exposes,
+                         MetaFile::RuntimeEnvironment,
+                         MetaFile::RuntimePath];
+
+        for _ in 0..50 {
+            for &metafile in &metafiles {
+                std::fs::write(pkg_install.installed_path().join(metafile.to_string()),
+                               adversarial_metafile_body(&mut rng, metafile)).unwrap();
+            }
+
+            let _ = pkg_install.binds();
+            let _ = pkg_install.deps();
+            let _ = pkg_install.exports();
+            let _ = pkg_install.exposes();
+            let _ = pkg_install.environment_for_command(Default::default());
+        }
+    }
+}
@@ -0,0 +1,215 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Removal of installed packages.
+//!
+//! Before a package is removed, its `pre-uninstall` hook is run, and the removal is refused if
+//! any running process was started from one of the package's executables, since removing it
+//! from under them breaks whatever they load from it next. Both checks can be overridden with
+//! `force`, for cleanup which must go ahead regardless.
+
+use std::{fs,
+          path::PathBuf};
+
+use super::{list::temp_package_directory,
+            PackageIdent,
+            PackageInstall};
+use crate::{error::{Error,
+                    Result},
+            hooks::package::{clear_post_install_status,
+                             run_pre_uninstall_hook,
+                             HookRun},
+            os::process::{self,
+                          Pid}};
+
+/// The processes, with their executables, which were started from `pkg_install`'s files.
+pub fn processes_using(pkg_install: &PackageInstall) -> Vec<(Pid, PathBuf)> {
+    process::executables().into_iter()
+                          .filter(|(_, exe)| exe.starts_with(pkg_install.installed_path()))
+                          .collect()
+}
+
+/// Removes an installed package.
+///
+/// # Examples
+///
+/// ```no_run
+/// use habitat_core::package::{uninstall::Uninstaller,
+///                             PackageIdent,
+///                             PackageInstall};
+/// use std::str::FromStr;
+///
+/// let ident = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+/// let pkg_install = PackageInstall::load(&ident, None).unwrap();
+/// let report = Uninstaller::new(&pkg_install).uninstall().unwrap();
+/// println!("Removed {}", report.ident);
+/// ```
+pub struct Uninstaller<'a> {
+    pkg_install: &'a PackageInstall,
+    force:       bool,
+}
+
+/// What an uninstall did.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UninstallReport {
+    pub ident:             PackageIdent,
+    /// How the pre-uninstall hook ran, which is only `Failed` for a forced uninstall.
+    pub pre_uninstall:     HookRun,
+    /// Processes which were running from the package when it was forcibly removed.
+    pub running_processes: Vec<Pid>,
+}
+
+impl<'a> Uninstaller<'a> {
+    pub fn new(pkg_install: &'a PackageInstall) -> Self {
+        Uninstaller { pkg_install,
+                      force: false }
+    }
+
+    /// Removes the package even if processes are running from it or its pre-uninstall hook
+    /// fails, logging a warning for each.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Runs the package's pre-uninstall hook, then removes its installed path. The directory is
+    /// first moved aside, so that a removal which fails part way never leaves a partial package
+    /// in place. The record of its post-install hook having run is forgotten along with it.
+    ///
+    /// # Failures
+    ///
+    /// * Processes are running from the package, unless forced
+    /// * The pre-uninstall hook fails, unless forced
+    /// * The package's directory cannot be removed
+    pub fn uninstall(self) -> Result<UninstallReport> {
+        let ident = self.pkg_install.ident().clone();
+        let running: Vec<Pid> = processes_using(self.pkg_install).into_iter()
+                                                                 .map(|(pid, _)| pid)
+                                                                 .collect();
+        if !running.is_empty() {
+            if !self.force {
+                return Err(Error::PackageInUse(ident, running));
+            }
+            warn!("Removing {}, which processes {:?} are running from",
+                  ident, running);
+        }
+
+        let pre_uninstall = match run_pre_uninstall_hook(self.pkg_install) {
+            Ok(run) => run,
+            Err(Error::PackageHookFailed(_, hook, result)) => {
+                let err = Error::PackageHookFailed(ident.clone(), hook, result.clone());
+                if !self.force {
+                    return Err(err);
+                }
+                warn!("Removing {} anyway: {}", ident, err);
+                HookRun::Failed(*result)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let installed_path = self.pkg_install.installed_path();
+        let temp_dir = temp_package_directory(installed_path)?;
+        fs::rename(installed_path, temp_dir.path().join("pkg"))?;
+        temp_dir.close()?;
+        clear_post_install_status(self.pkg_install)?;
+        debug!("Removed {}", installed_path.display());
+
+        Ok(UninstallReport { ident,
+                             pre_uninstall,
+                             running_processes: running })
+    }
+}
+
+#[cfg(all(test, not(windows)))]
+mod test {
+    use super::*;
+    use crate::{hooks::package::{test::write_hook,
+                                 PRE_UNINSTALL_HOOK},
+                package::test_support::testing_package_install};
+    use std::process::Command;
+    use tempfile::Builder;
+
+    #[test]
+    fn uninstall_runs_pre_uninstall_hook() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("core/ruby", fs_root.path());
+        let marker = fs_root.path().join("uninstalled");
+        write_hook(&pkg_install,
+                   PRE_UNINSTALL_HOOK,
+                   &format!("#!/bin/sh\ntouch {}\n", marker.display()));
+
+        let report = Uninstaller::new(&pkg_install).uninstall().unwrap();
+
+        assert_eq!(pkg_install.ident(), &report.ident);
+        match report.pre_uninstall {
+            HookRun::Ran(ref result) => assert!(result.success()),
+            ref other => panic!("Expected the hook to run, got {:?}", other),
+        }
+        assert!(marker.exists());
+        assert!(!pkg_install.installed_path().exists());
+        // Nothing is left behind from moving the package aside
+        assert_eq!(0,
+                   fs::read_dir(pkg_install.installed_path().parent().unwrap()).unwrap()
+                                                                               .count());
+    }
+
+    #[test]
+    fn failing_pre_uninstall_hook_stops_uninstall_unless_forced() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("core/ruby", fs_root.path());
+        write_hook(&pkg_install, PRE_UNINSTALL_HOOK, "#!/bin/sh\nexit 1\n");
+
+        match Uninstaller::new(&pkg_install).uninstall() {
+            Err(Error::PackageHookFailed(..)) => {}
+            other => panic!("Expected PackageHookFailed, got {:?}", other),
+        }
+        assert!(pkg_install.installed_path().exists());
+
+        let report = Uninstaller::new(&pkg_install).force(true)
+                                                   .uninstall()
+                                                   .unwrap();
+        match report.pre_uninstall {
+            HookRun::Failed(ref result) => assert_eq!(Some(1), result.exit_code),
+            ref other => panic!("Expected the hook to fail, got {:?}", other),
+        }
+        assert!(!pkg_install.installed_path().exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn uninstall_refuses_packages_with_running_processes() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("core/coreutils", fs_root.path());
+        let bin = pkg_install.installed_path().join("bin");
+        fs::create_dir_all(&bin).unwrap();
+        fs::copy("/bin/sleep", bin.join("sleep")).unwrap();
+        let mut child = Command::new(bin.join("sleep")).arg("30").spawn().unwrap();
+        let pid = child.id() as Pid;
+
+        assert!(processes_using(&pkg_install).iter().any(|&(p, _)| p == pid));
+        match Uninstaller::new(&pkg_install).uninstall() {
+            Err(Error::PackageInUse(_, ref pids)) => assert!(pids.contains(&pid)),
+            other => panic!("Expected PackageInUse, got {:?}", other),
+        }
+        assert!(pkg_install.installed_path().exists());
+
+        let report = Uninstaller::new(&pkg_install).force(true)
+                                                   .uninstall()
+                                                   .unwrap();
+        assert!(report.running_processes.contains(&pid));
+        assert!(!pkg_install.installed_path().exists());
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Health probes: checks that a service is reachable over TCP, or answers HTTP requests as
+//! expected.
+//!
+//! The same probes back the Supervisor's built-in health checks and health check hooks written
+//! in Rust, so both are configured the same way and agree on what counts as healthy. A probe
+//! which succeeds is `HealthStatus::Ok`, and one which fails for any reason is
+//! `HealthStatus::Critical`.
+//!
+//! HTTPS probes are only available with the `tls` feature.
+
+use std::{io::{BufRead,
+               BufReader,
+               Read,
+               Write},
+          net::{TcpStream,
+                ToSocketAddrs},
+          ops::RangeInclusive,
+          time::Duration};
+
+use url::Url;
+
+#[cfg(feature = "tls")]
+use crate::tls::TlsOptions;
+use crate::{error::{Error,
+                    Result},
+            events::HealthStatus};
+
+/// How long a probe waits by default to connect, and then for each read and write.
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Checks the health of a service.
+pub trait Probe {
+    /// Probes the service once.
+    ///
+    /// # Failures
+    ///
+    /// * The service is unreachable or does not respond as expected
+    fn probe(&self) -> Result<()>;
+
+    /// Probes the service once, logging the reason for any failure.
+    fn check(&self) -> HealthStatus {
+        match self.probe() {
+            Ok(()) => HealthStatus::Ok,
+            Err(e) => {
+                debug!("{}", e);
+                HealthStatus::Critical
+            }
+        }
+    }
+}
+
+/// Succeeds when a TCP connection to `address` can be established.
+#[derive(Clone, Debug)]
+pub struct TcpProbe {
+    /// The address to connect to, as `<HOST>:<PORT>`.
+    pub address: String,
+    pub timeout: Duration,
+}
+
+impl TcpProbe {
+    pub fn new<T: Into<String>>(address: T) -> Self {
+        TcpProbe { address: address.into(),
+                   timeout: DEFAULT_PROBE_TIMEOUT, }
+    }
+}
+
+impl Probe for TcpProbe {
+    fn probe(&self) -> Result<()> { connect(&self.address, self.timeout).map(|_| ()) }
+}
+
+/// Succeeds when a `GET` request for `url` is answered with one of the expected statuses.
+#[derive(Clone, Debug)]
+pub struct HttpProbe {
+    /// The URL to request, with an `http` or `https` scheme.
+    pub url:             String,
+    pub timeout:         Duration,
+    /// The response statuses which count as healthy.
+    pub expected_status: Vec<RangeInclusive<u16>>,
+    /// TLS settings for `https` URLs.
+    #[cfg(feature = "tls")]
+    pub tls:             TlsOptions,
+}
+
+impl HttpProbe {
+    /// A probe of `url` which expects any successful (2xx) status.
+    pub fn new<T: Into<String>>(url: T) -> Self {
+        HttpProbe { url: url.into(),
+                    timeout: DEFAULT_PROBE_TIMEOUT,
+                    expected_status: vec![200..=299],
+                    #[cfg(feature = "tls")]
+                    tls: TlsOptions::default(), }
+    }
+
+    fn is_expected(&self, status: u16) -> bool {
+        self.expected_status
+            .iter()
+            .any(|range| *range.start() <= status && status <= *range.end())
+    }
+
+    /// Sends the request and returns the response's status.
+    fn status(&self) -> Result<u16> {
+        let url = Url::parse(&self.url).map_err(|e| {
+                                           Error::ProbeFailed(format!("invalid URL {}: {}",
+                                                                      self.url, e))
+                                       })?;
+        let host = url.host_str()
+                      .ok_or_else(|| Error::ProbeFailed(format!("no host in {}", self.url)))?;
+        let port = url.port_or_known_default()
+                      .ok_or_else(|| Error::ProbeFailed(format!("no port for {}", self.url)))?;
+        let tcp = connect(&format!("{}:{}", host, port), self.timeout)?;
+
+        let mut target = url.path().to_string();
+        if let Some(query) = url.query() {
+            target.push('?');
+            target.push_str(query);
+        }
+        let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: \
+                               habitat-probe\r\nConnection: close\r\n\r\n",
+                              target, host);
+        match url.scheme() {
+            "http" => send(tcp, &request),
+            "https" => self.send_tls(host, tcp, &request),
+            scheme => {
+                Err(Error::ProbeFailed(format!("unsupported scheme {} in {}", scheme, self.url)))
+            }
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    fn send_tls(&self, host: &str, tcp: TcpStream, request: &str) -> Result<u16> {
+        match self.tls.connector()?.connect(host, tcp) {
+            Ok(stream) => send(stream, request),
+            Err(e) => Err(Error::TlsError(format!("handshake with {} failed: {}", host, e))),
+        }
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn send_tls(&self, _host: &str, _tcp: TcpStream, _request: &str) -> Result<u16> {
+        Err(Error::ProbeFailed(format!("cannot probe {} without TLS \
+                                        support",
+                                       self.url)))
+    }
+}
+
+impl Probe for HttpProbe {
+    fn probe(&self) -> Result<()> {
+        let status = self.status()?;
+        if self.is_expected(status) {
+            Ok(())
+        } else {
+            Err(Error::ProbeFailed(format!("{} answered with unexpected \
+                                            status {}",
+                                           self.url, status)))
+        }
+    }
+}
+
+/// Connects to the first address `address` resolves to which accepts within `timeout`.
+fn connect(address: &str, timeout: Duration) -> Result<TcpStream> {
+    let unreachable =
+        |reason: String| Error::ProbeFailed(format!("cannot connect to {}: {}", address, reason));
+    let addrs = address.to_socket_addrs()
+                       .map_err(|e| unreachable(e.to_string()))?;
+    let mut last_err = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(tcp) => {
+                tcp.set_read_timeout(Some(timeout))?;
+                tcp.set_write_timeout(Some(timeout))?;
+                return Ok(tcp);
+            }
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(unreachable(last_err.map_or_else(|| {
+                                             "no addresses".to_string()
+                                         },
+                                         |e| e.to_string())))
+}
+
+/// Writes `request` and reads the status from the response's status line.
+fn send<S: Read + Write>(mut stream: S, request: &str) -> Result<u16> {
+    stream.write_all(request.as_bytes())?;
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    let mut parts = status_line.split_whitespace();
+    match (parts.next(), parts.next().and_then(|s| s.parse().ok())) {
+        (Some(version), Some(status)) if version.starts_with("HTTP/") => Ok(status),
+        _ => {
+            Err(Error::ProbeFailed(format!("malformed HTTP status line \
+                                            {:?}",
+                                           status_line.trim_end())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{net::TcpListener,
+              thread};
+
+    /// Answers a single HTTP request with `status`, returning the request it received.
+    fn serve_once(status: &'static str) -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" || line.is_empty() {
+                    break;
+                }
+                request.push_str(&line);
+            }
+            write!(reader.get_mut(),
+                   "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n",
+                   status).unwrap();
+            request
+        });
+        (address, handle)
+    }
+
+    #[test]
+    fn tcp_probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        assert_eq!(HealthStatus::Ok, TcpProbe::new(address.as_str()).check());
+        drop(listener);
+        assert_eq!(HealthStatus::Critical,
+                   TcpProbe::new(address.as_str()).check());
+    }
+
+    #[test]
+    fn http_probe_expects_success() {
+        let (address, server) = serve_once("204 No Content");
+        let url = format!("http://{}/health?verbose=1", address);
+
+        HttpProbe::new(url).probe().unwrap();
+        let request = server.join().unwrap();
+        assert!(request.starts_with("GET /health?verbose=1 HTTP/1.1\r\n"));
+        assert!(request.contains("Host: 127.0.0.1\r\n"));
+    }
+
+    #[test]
+    fn http_probe_checks_expected_status() {
+        let (address, server) = serve_once("503 Service Unavailable");
+        let url = format!("http://{}/", address);
+
+        match HttpProbe::new(url.as_str()).probe() {
+            Err(Error::ProbeFailed(msg)) => assert!(msg.contains("unexpected status 503")),
+            other => panic!("Expected ProbeFailed, got {:?}", other),
+        }
+        server.join().unwrap();
+
+        let (address, server) = serve_once("503 Service Unavailable");
+        let mut probe = HttpProbe::new(format!("http://{}/", address));
+        probe.expected_status = vec![200..=299, 503..=503];
+        assert_eq!(HealthStatus::Ok, probe.check());
+        server.join().unwrap();
+    }
+}
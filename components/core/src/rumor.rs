@@ -0,0 +1,410 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! On-disk persistence of rumors and membership.
+//!
+//! A `Store` is an append-only log of records, each of which sets or removes the value of a
+//! key. The current state is rebuilt by replaying the log when the store is opened. Every record
+//! carries a CRC-32 of its contents, so a record left half written by an unclean shutdown is
+//! detected, and the log is truncated back to the last complete record rather than refusing to
+//! load. Superseded records are dropped by `Store::compact`.
+//!
+//! The file starts with an 8 byte header, `HABRUMOR`, followed by a version byte. Each record is
+//! then laid out as, with integers little endian:
+//!
+//! ```text
+//! body length: u32 | body CRC-32: u32 | op: u8 | key length: u16 | key | value
+//! ```
+//!
+//! where the op is `0` to set the key to the value or `1` to remove it, in which case the value
+//! is empty.
+
+use std::{collections::{btree_map,
+                        BTreeMap},
+          fs::{self,
+               File,
+               OpenOptions},
+          io::{Read,
+               Seek,
+               SeekFrom,
+               Write},
+          path::{Path,
+                 PathBuf}};
+
+use flate2::Crc;
+
+use crate::error::{Error,
+                   Result};
+
+const MAGIC: &[u8] = b"HABRUMOR";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 9;
+const RECORD_HEADER_LEN: usize = 8;
+/// Records claiming to be larger than this are treated as corrupt.
+const MAX_BODY_LEN: usize = 64 * 1024 * 1024;
+
+const OP_SET: u8 = 0;
+const OP_REMOVE: u8 = 1;
+
+/// An append-only, crash-tolerant key-value store for rumors.
+#[derive(Debug)]
+pub struct Store {
+    path:    PathBuf,
+    file:    File,
+    entries: BTreeMap<String, Vec<u8>>,
+    /// The number of records in the log which no longer contribute to `entries`.
+    stale:   usize,
+}
+
+impl Store {
+    /// Opens the store at `path`, creating it if it does not exist.
+    ///
+    /// If the log ends with an incomplete or corrupt record, it is truncated to the last valid
+    /// record and a warning is logged.
+    ///
+    /// # Failures
+    ///
+    /// * The file cannot be read or written
+    /// * The file exists and is not a rumor store
+    pub fn open<P>(path: P) -> Result<Self>
+        where P: Into<PathBuf>
+    {
+        let path = path.into();
+        let mut file = OpenOptions::new().read(true)
+                                         .write(true)
+                                         .create(true)
+                                         .truncate(false)
+                                         .open(&path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        if data.is_empty() {
+            file.write_all(MAGIC)?;
+            file.write_all(&[VERSION])?;
+            file.sync_data()?;
+            return Ok(Store { path,
+                              file,
+                              entries: BTreeMap::new(),
+                              stale: 0 });
+        }
+        if data.len() < HEADER_LEN || &data[..MAGIC.len()] != MAGIC {
+            return Err(Error::InvalidRumorStore(path, "missing header".to_string()));
+        }
+        if data[MAGIC.len()] != VERSION {
+            let msg = format!("unsupported version {}", data[MAGIC.len()]);
+            return Err(Error::InvalidRumorStore(path, msg));
+        }
+
+        let mut entries = BTreeMap::new();
+        let mut stale = 0;
+        let mut offset = HEADER_LEN;
+        while offset < data.len() {
+            match decode(&data[offset..]) {
+                Some((len, record)) => {
+                    let superseded = match record {
+                        Record::Set(key, value) => entries.insert(key, value.to_vec()).is_some(),
+                        Record::Remove(key) => {
+                            // The remove record itself is stale as well as what it removed
+                            stale += 1;
+                            entries.remove(&key).is_some()
+                        }
+                    };
+                    if superseded {
+                        stale += 1;
+                    }
+                    offset += len;
+                }
+                None => {
+                    warn!("Truncating corrupt rumor store {} to its last valid record, at {} \
+                           bytes",
+                          path.display(),
+                          offset);
+                    file.set_len(offset as u64)?;
+                    file.sync_data()?;
+                    break;
+                }
+            }
+        }
+        file.seek(SeekFrom::End(0))?;
+        Ok(Store { path,
+                   file,
+                   entries,
+                   stale })
+    }
+
+    /// The path of the store's file.
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// Returns the value of `key`, if it is set.
+    pub fn get(&self, key: &str) -> Option<&[u8]> { self.entries.get(key).map(Vec::as_slice) }
+
+    /// Iterates over the keys and values in the store, in key order.
+    pub fn iter(&self) -> Iter<'_> { Iter(self.entries.iter()) }
+
+    /// The number of keys in the store.
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    /// Whether the store has no keys.
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+
+    /// The number of records in the log which `compact` would drop.
+    pub fn stale_records(&self) -> usize { self.stale }
+
+    /// Sets `key` to `value`.
+    ///
+    /// # Failures
+    ///
+    /// * The record cannot be appended to the file
+    pub fn insert(&mut self, key: &str, value: &[u8]) -> Result<()> {
+        let record = encode(&self.path, OP_SET, key, value)?;
+        self.file.write_all(&record)?;
+        let replaced = self.entries.insert(key.to_string(), value.to_vec());
+        if replaced.is_some() {
+            self.stale += 1;
+        }
+        Ok(())
+    }
+
+    /// Removes `key`, returning whether it was set.
+    ///
+    /// # Failures
+    ///
+    /// * The record cannot be appended to the file
+    pub fn remove(&mut self, key: &str) -> Result<bool> {
+        if !self.entries.contains_key(key) {
+            return Ok(false);
+        }
+        let record = encode(&self.path, OP_REMOVE, key, &[])?;
+        self.file.write_all(&record)?;
+        self.entries.remove(key);
+        self.stale += 2;
+        Ok(true)
+    }
+
+    /// Waits for every record appended so far to reach the disk.
+    pub fn sync(&self) -> Result<()> { Ok(self.file.sync_data()?) }
+
+    /// Rewrites the log with a single record for each key, dropping stale records.
+    ///
+    /// The new log is written alongside the old one and renamed over it once complete, so the
+    /// store is never left without a complete log.
+    ///
+    /// # Failures
+    ///
+    /// * The new log cannot be written or renamed into place
+    pub fn compact(&mut self) -> Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".compact");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut data = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        for (key, value) in &self.entries {
+            data.extend_from_slice(&encode(&self.path, OP_SET, key, value)?);
+        }
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&data)?;
+        tmp.sync_all()?;
+        drop(tmp);
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.stale = 0;
+        Ok(())
+    }
+}
+
+/// An iterator over the keys and values of a `Store`.
+pub struct Iter<'a>(btree_map::Iter<'a, String, Vec<u8>>);
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(k, v)| (k.as_str(), v.as_slice()))
+    }
+}
+
+enum Record<'a> {
+    Set(String, &'a [u8]),
+    Remove(String),
+}
+
+fn encode(path: &Path, op: u8, key: &str, value: &[u8]) -> Result<Vec<u8>> {
+    if key.len() > u16::max_value() as usize {
+        let msg = format!("key of {} bytes is too long", key.len());
+        return Err(Error::InvalidRumorStore(path.to_path_buf(), msg));
+    }
+    let mut body = Vec::with_capacity(3 + key.len() + value.len());
+    body.push(op);
+    body.extend_from_slice(&(key.len() as u16).to_le_bytes());
+    body.extend_from_slice(key.as_bytes());
+    body.extend_from_slice(value);
+    if body.len() > MAX_BODY_LEN {
+        let msg = format!("value of {} bytes is too large", value.len());
+        return Err(Error::InvalidRumorStore(path.to_path_buf(), msg));
+    }
+
+    let mut crc = Crc::new();
+    crc.update(&body);
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + body.len());
+    record.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc.sum().to_le_bytes());
+    record.extend_from_slice(&body);
+    Ok(record)
+}
+
+/// Decodes the record at the start of `data`, returning its length and contents, or `None` if
+/// it is incomplete or corrupt.
+fn decode(data: &[u8]) -> Option<(usize, Record<'_>)> {
+    if data.len() < RECORD_HEADER_LEN {
+        return None;
+    }
+    let len = read_u32(&data[0..4]) as usize;
+    let expected_crc = read_u32(&data[4..8]);
+    if len < 3 || len > MAX_BODY_LEN || data.len() - RECORD_HEADER_LEN < len {
+        return None;
+    }
+    let body = &data[RECORD_HEADER_LEN..RECORD_HEADER_LEN + len];
+    let mut crc = Crc::new();
+    crc.update(body);
+    if crc.sum() != expected_crc {
+        return None;
+    }
+
+    let key_len = usize::from(u16::from_le_bytes([body[1], body[2]]));
+    if body.len() < 3 + key_len {
+        return None;
+    }
+    let key = String::from_utf8(body[3..3 + key_len].to_vec()).ok()?;
+    let value = &body[3 + key_len..];
+    let record = match body[0] {
+        OP_SET => Record::Set(key, value),
+        OP_REMOVE if value.is_empty() => Record::Remove(key),
+        _ => return None,
+    };
+    Some((RECORD_HEADER_LEN + len, record))
+}
+
+fn read_u32(bytes: &[u8]) -> u32 { u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::{Builder,
+                   TempDir};
+
+    fn store_path() -> (TempDir, PathBuf) {
+        let dir = Builder::new().prefix("rumor").tempdir().unwrap();
+        let path = dir.path().join("rumors.dat");
+        (dir, path)
+    }
+
+    #[test]
+    fn persists_across_reopen() {
+        let (_dir, path) = store_path();
+        let mut store = Store::open(&path).unwrap();
+        store.insert("member/a", b"alive").unwrap();
+        store.insert("member/b", b"alive").unwrap();
+        store.insert("member/a", b"suspect").unwrap();
+        assert!(store.remove("member/b").unwrap());
+        assert!(!store.remove("member/c").unwrap());
+        drop(store);
+
+        let store = Store::open(&path).unwrap();
+
+        assert_eq!(vec![("member/a", &b"suspect"[..])],
+                   store.iter().collect::<Vec<_>>());
+        assert_eq!(3, store.stale_records());
+    }
+
+    #[test]
+    fn truncates_incomplete_record() {
+        let (_dir, path) = store_path();
+        let mut store = Store::open(&path).unwrap();
+        store.insert("a", b"1").unwrap();
+        drop(store);
+        let valid_len = fs::metadata(&path).unwrap().len();
+        let record = encode(&path, OP_SET, "b", b"2").unwrap();
+        OpenOptions::new().append(true)
+                          .open(&path)
+                          .unwrap()
+                          .write_all(&record[..record.len() - 1])
+                          .unwrap();
+
+        let mut store = Store::open(&path).unwrap();
+
+        assert_eq!(Some(&b"1"[..]), store.get("a"));
+        assert_eq!(None, store.get("b"));
+        assert_eq!(valid_len, fs::metadata(&path).unwrap().len());
+
+        // The store is usable after recovery
+        store.insert("c", b"3").unwrap();
+        drop(store);
+        assert_eq!(Some(&b"3"[..]), Store::open(&path).unwrap().get("c"));
+    }
+
+    #[test]
+    fn stops_at_checksum_mismatch() {
+        let (_dir, path) = store_path();
+        let mut store = Store::open(&path).unwrap();
+        store.insert("a", b"1").unwrap();
+        store.insert("b", b"2").unwrap();
+        store.insert("c", b"3").unwrap();
+        drop(store);
+        let mut data = fs::read(&path).unwrap();
+        let second_value = HEADER_LEN + 2 * (RECORD_HEADER_LEN + 5) - 1;
+        data[second_value] ^= 0xff;
+        fs::write(&path, &data).unwrap();
+
+        let store = Store::open(&path).unwrap();
+
+        assert_eq!(vec![("a", &b"1"[..])], store.iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn compact_drops_stale_records() {
+        let (_dir, path) = store_path();
+        let mut store = Store::open(&path).unwrap();
+        for i in 0..100 {
+            store.insert("a", i.to_string().as_bytes()).unwrap();
+        }
+        store.insert("b", b"x").unwrap();
+        store.remove("b").unwrap();
+        let before = fs::metadata(&path).unwrap().len();
+
+        store.compact().unwrap();
+
+        assert_eq!(0, store.stale_records());
+        assert!(fs::metadata(&path).unwrap().len() < before);
+        store.insert("c", b"y").unwrap();
+        drop(store);
+        let store = Store::open(&path).unwrap();
+        assert_eq!(vec![("a", &b"99"[..]), ("c", &b"y"[..])],
+                   store.iter().collect::<Vec<_>>());
+        assert_eq!(0, store.stale_records());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let (_dir, path) = store_path();
+        fs::write(&path, "not a rumor store").unwrap();
+
+        match Store::open(&path) {
+            Err(Error::InvalidRumorStore(..)) => {}
+            other => panic!("Expected InvalidRumorStore, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! How quickly services which keep exiting are restarted.
+
+use std::{fmt,
+          str::FromStr,
+          time::Duration};
+
+use super::state::Backoff;
+use crate::{env,
+            error::{Error,
+                    Result},
+            util::{duration_as_millis,
+                   parse_duration}};
+
+/// An exponential backoff between restarts of a failing service, which gives up once the
+/// service has failed too many times in a row.
+///
+/// The delay before the first restart is `initial_delay`, and each further consecutive failure
+/// multiplies it by `multiplier`, up to `max_delay`. Each delay is then randomly varied by up to
+/// `jitter` of itself in either direction, so that services which failed together, such as when
+/// a shared dependency went away, do not all restart at the same instant.
+///
+/// Set from `HAB_RESTART_POLICY` as a comma-separated list of settings, any of which may be
+/// omitted, such as `initial=1s,multiplier=2,max=5m,jitter=0.1,budget=10`. Durations are given
+/// in `ms`, `s`, `m`, or `h`, or as a bare number of seconds. A `budget` of `none` never gives
+/// up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RestartPolicy {
+    pub initial_delay:  Duration,
+    pub multiplier:     f64,
+    pub max_delay:      Duration,
+    /// The fraction, between 0 and 1, by which each delay is randomly varied.
+    pub jitter:         f64,
+    /// The number of consecutive failures after which the service is marked failed instead of
+    /// being restarted, or `None` to always restart it.
+    pub failure_budget: Option<u32>,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy { initial_delay:  Duration::from_secs(1),
+                        multiplier:     2.0,
+                        max_delay:      Duration::from_secs(5 * 60),
+                        jitter:         0.1,
+                        failure_budget: Some(10), }
+    }
+}
+
+impl RestartPolicy {
+    /// The delay before restarting a service which has failed `failures` times in a row, before
+    /// jitter is applied.
+    pub fn base_delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(i32::max_value() as u32) as i32;
+        let secs = duration_as_secs_f64(self.initial_delay) * self.multiplier.powi(exponent);
+        let max = duration_as_secs_f64(self.max_delay);
+        if !secs.is_finite() || secs >= max {
+            self.max_delay
+        } else {
+            duration_from_secs_f64(secs)
+        }
+    }
+
+    fn validate(self) -> Result<Self> {
+        let problem = if !(self.multiplier >= 1.0) || !self.multiplier.is_finite() {
+            format!("multiplier must be at least 1, got {}", self.multiplier)
+        } else if !(self.jitter >= 0.0 && self.jitter <= 1.0) {
+            format!("jitter must be between 0 and 1, got {}", self.jitter)
+        } else if self.max_delay < self.initial_delay {
+            "max must not be less than initial".to_string()
+        } else {
+            return Ok(self);
+        };
+        Err(Error::InvalidRestartPolicy(problem))
+    }
+}
+
+impl Backoff for RestartPolicy {
+    fn delay(&self, failures: u32) -> Option<Duration> {
+        if let Some(budget) = self.failure_budget {
+            if failures > budget {
+                return None;
+            }
+        }
+        let base = duration_as_secs_f64(self.base_delay(failures));
+        // Scale by a random factor in [1 - jitter, 1 + jitter)
+        let factor = 1.0 + self.jitter * (2.0 * rand::random::<f64>() - 1.0);
+        Some(duration_from_secs_f64(base * factor))
+    }
+}
+
+impl env::Config for RestartPolicy {
+    const ENVVAR: &'static str = "HAB_RESTART_POLICY";
+}
+
+impl FromStr for RestartPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut policy = RestartPolicy::default();
+        for setting in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let mut parts = setting.splitn(2, '=');
+            let key = parts.next().unwrap_or_default().trim();
+            let invalid = || Error::InvalidRestartPolicy(s.to_string());
+            let value = parts.next().ok_or_else(invalid)?.trim();
+            match key {
+                "initial" => policy.initial_delay = parse_duration(value).ok_or_else(invalid)?,
+                "multiplier" => policy.multiplier = value.parse().map_err(|_| invalid())?,
+                "max" => policy.max_delay = parse_duration(value).ok_or_else(invalid)?,
+                "jitter" => policy.jitter = value.parse().map_err(|_| invalid())?,
+                "budget" if value == "none" => policy.failure_budget = None,
+                "budget" => policy.failure_budget = Some(value.parse().map_err(|_| invalid())?),
+                _ => return Err(invalid()),
+            }
+        }
+        policy.validate()
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,
+               "initial={}ms,multiplier={},max={}ms,jitter={},budget=",
+               duration_as_millis(self.initial_delay),
+               self.multiplier,
+               duration_as_millis(self.max_delay),
+               self.jitter)?;
+        match self.failure_budget {
+            Some(budget) => write!(f, "{}", budget),
+            None => write!(f, "none"),
+        }
+    }
+}
+
+fn duration_as_secs_f64(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1_000_000_000.0
+}
+
+fn duration_from_secs_f64(secs: f64) -> Duration {
+    let secs = secs.max(0.0);
+    Duration::new(secs.trunc() as u64, (secs.fract() * 1_000_000_000.0) as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::service::state::{ServiceState,
+                                Transition};
+
+    fn without_jitter() -> RestartPolicy {
+        RestartPolicy { jitter: 0.0,
+                        ..Default::default() }
+    }
+
+    #[test]
+    fn delays_grow_exponentially_to_max() {
+        let policy = RestartPolicy { max_delay: Duration::from_secs(10),
+                                     ..without_jitter() };
+
+        assert_eq!(Some(Duration::from_secs(1)), policy.delay(1));
+        assert_eq!(Some(Duration::from_secs(2)), policy.delay(2));
+        assert_eq!(Some(Duration::from_secs(8)), policy.delay(4));
+        assert_eq!(Some(Duration::from_secs(10)), policy.delay(5));
+        assert_eq!(Duration::from_secs(10), policy.base_delay(u32::max_value()));
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds() {
+        let policy = RestartPolicy { jitter: 0.5,
+                                     ..Default::default() };
+
+        for _ in 0..100 {
+            let delay = policy.delay(3).unwrap();
+            assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(6),
+                    "{:?} out of bounds",
+                    delay);
+        }
+    }
+
+    #[test]
+    fn budget_trips_to_failed() {
+        let policy = RestartPolicy { failure_budget: Some(2),
+                                     ..without_jitter() };
+        let mut state = ServiceState::Down.apply(Transition::Start, &policy)
+                                          .unwrap();
+        for _ in 0..2 {
+            state = state.apply(Transition::Exited, &policy).unwrap();
+            assert!(match state {
+                        ServiceState::Restarting { .. } => true,
+                        _ => false,
+                    });
+            state = state.apply(Transition::Retry, &policy).unwrap();
+        }
+
+        state = state.apply(Transition::Exited, &policy).unwrap();
+
+        assert_eq!(ServiceState::Failed { failures: 3 }, state);
+    }
+
+    #[test]
+    fn from_str() {
+        let policy: RestartPolicy =
+            "initial=500ms, multiplier=3, max=2m, jitter=0, budget=none".parse()
+                                                                        .unwrap();
+
+        assert_eq!(RestartPolicy { initial_delay:  Duration::from_millis(500),
+                                   multiplier:     3.0,
+                                   max_delay:      Duration::from_secs(120),
+                                   jitter:         0.0,
+                                   failure_budget: None, },
+                   policy);
+        assert_eq!(policy, policy.to_string().parse().unwrap());
+        assert_eq!(RestartPolicy { failure_budget: Some(3),
+                                   ..Default::default() },
+                   "budget=3".parse().unwrap());
+    }
+
+    #[test]
+    fn from_str_invalid() {
+        for s in &["initial",
+                   "delay=1s",
+                   "initial=1d",
+                   "multiplier=0.5",
+                   "jitter=2",
+                   "initial=10m,max=1m",
+                   "budget=-1"]
+        {
+            match s.parse::<RestartPolicy>() {
+                Err(Error::InvalidRestartPolicy(_)) => {}
+                other => panic!("Expected InvalidRestartPolicy for {}, got {:?}", s, other),
+            }
+        }
+    }
+}
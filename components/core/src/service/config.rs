@@ -0,0 +1,546 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Configuration applied to a running service.
+
+use std::{fmt,
+          fs,
+          io,
+          path::{Path,
+                 PathBuf},
+          str::FromStr};
+
+use base64;
+use serde_derive::{Deserialize,
+                   Serialize};
+use sodiumoxide::crypto::sign;
+
+use super::ServiceGroup;
+use crate::{crypto::{hash,
+                     SigKeyPair,
+                     SymKey},
+            error::{Error,
+                    Result},
+            fs::{atomic_write,
+                 svc_path}};
+
+/// The format version of a serialized `ConfigEnvelope`.
+pub const CONFIG_ENVELOPE_FORMAT_VERSION: &str = "CFG-ENV-1";
+
+/// The name of the directory under a service's svc path where its configuration history is
+/// kept.
+pub const HISTORY_DIR: &str = "config-history";
+/// The number of versions kept by default.
+pub const DEFAULT_RETAIN: usize = 10;
+
+/// One version of a service's applied configuration.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ConfigVersion {
+    /// The incarnation the configuration was applied with. Each applied version has a higher
+    /// incarnation than the last.
+    pub incarnation: u64,
+    /// The BLAKE2b hash of `content`, as hex.
+    pub hash:        String,
+    /// Who applied the configuration, such as the name of the user key it was signed with, if
+    /// known.
+    pub author:      Option<String>,
+    /// When the configuration was recorded, in RFC 3339 format.
+    pub applied_at:  String,
+    /// The configuration itself, as TOML.
+    pub content:     String,
+}
+
+/// The configuration versions applied to a service, kept as one file per version.
+///
+/// Only the most recent versions are kept; older ones are removed as new ones are recorded.
+#[derive(Clone, Debug)]
+pub struct History {
+    dir:    PathBuf,
+    retain: usize,
+}
+
+impl History {
+    /// Keeps history in `dir`, which is created when the first version is recorded.
+    pub fn new<P>(dir: P) -> Self
+        where P: Into<PathBuf>
+    {
+        History { dir:    dir.into(),
+                  retain: DEFAULT_RETAIN, }
+    }
+
+    /// Keeps the history of the service named `service_name` under its svc path.
+    pub fn for_service<T>(service_name: T) -> Self
+        where T: AsRef<Path>
+    {
+        History::new(svc_path(service_name).join(HISTORY_DIR))
+    }
+
+    /// Sets the number of versions to keep, which is at least one.
+    pub fn retain(mut self, retain: usize) -> Self {
+        self.retain = retain.max(1);
+        self
+    }
+
+    /// The directory the history is kept in.
+    pub fn dir(&self) -> &Path { &self.dir }
+
+    /// Records `content` as applied with `incarnation`, then removes versions beyond the
+    /// number retained. Recording the latest version again is allowed, and returns it
+    /// unchanged.
+    ///
+    /// # Failures
+    ///
+    /// * `incarnation` is lower than the latest version's, or equal to it with different content
+    /// * The version cannot be written
+    pub fn record(&self,
+                  incarnation: u64,
+                  content: &str,
+                  author: Option<&str>)
+                  -> Result<ConfigVersion> {
+        let hash = hash::hash_string(content);
+        if let Some(latest) = self.latest()? {
+            if latest.incarnation == incarnation && latest.content == content {
+                return Ok(latest);
+            }
+            if latest.incarnation >= incarnation {
+                return Err(Error::StaleConfigIncarnation(incarnation, latest.incarnation));
+            }
+        }
+
+        let version = ConfigVersion { incarnation,
+                                      hash,
+                                      author: author.map(str::to_string),
+                                      applied_at: time::now_utc().rfc3339().to_string(),
+                                      content: content.to_string() };
+        fs::create_dir_all(&self.dir)?;
+        atomic_write(&self.version_path(incarnation),
+                     serde_json::to_vec_pretty(&version)?)?;
+        self.prune()?;
+        Ok(version)
+    }
+
+    /// Every version kept, oldest first.
+    ///
+    /// # Failures
+    ///
+    /// * The history cannot be read
+    pub fn versions(&self) -> Result<Vec<ConfigVersion>> {
+        self.incarnations()?
+            .into_iter()
+            .map(|incarnation| self.read(incarnation))
+            .collect()
+    }
+
+    /// The most recently applied version, if any.
+    pub fn latest(&self) -> Result<Option<ConfigVersion>> {
+        match self.incarnations()?.last() {
+            Some(&incarnation) => self.read(incarnation).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The version applied with `incarnation`, if it is still kept.
+    pub fn get(&self, incarnation: u64) -> Result<Option<ConfigVersion>> {
+        if self.version_path(incarnation).is_file() {
+            self.read(incarnation).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// The version applied before the latest one, which is what rolling back the latest change
+    /// would restore, if it is still kept.
+    pub fn previous(&self) -> Result<Option<ConfigVersion>> {
+        let incarnations = self.incarnations()?;
+        match incarnations.len() {
+            0 | 1 => Ok(None),
+            n => self.read(incarnations[n - 2]).map(Some),
+        }
+    }
+
+    fn version_path(&self, incarnation: u64) -> PathBuf {
+        // Zero padded so that versions sort by name
+        self.dir.join(format!("{:020}.json", incarnation))
+    }
+
+    fn read(&self, incarnation: u64) -> Result<ConfigVersion> {
+        let content = fs::read(self.version_path(incarnation))?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    /// The incarnations of the versions kept, in ascending order.
+    fn incarnations(&self) -> Result<Vec<u64>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut incarnations = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            if let Some(incarnation) = path.file_stem()
+                                           .and_then(|s| s.to_str())
+                                           .and_then(|s| s.parse().ok())
+            {
+                incarnations.push(incarnation);
+            }
+        }
+        incarnations.sort();
+        Ok(incarnations)
+    }
+
+    fn prune(&self) -> Result<()> {
+        let incarnations = self.incarnations()?;
+        let excess = incarnations.len().saturating_sub(self.retain);
+        for &incarnation in &incarnations[..excess] {
+            debug!("Pruning configuration version {} from {}",
+                   incarnation,
+                   self.dir.display());
+            fs::remove_file(self.version_path(incarnation))?;
+        }
+        Ok(())
+    }
+}
+
+/// A configuration payload applied to a service group, signed by the user applying it and
+/// optionally encrypted with the ring key, so that those receiving it can authenticate it rather
+/// than trusting whatever arrives over gossip.
+///
+/// Serialized, an envelope is a header of one field per line, a blank line, then the base64
+/// encoded payload:
+///
+/// ```text
+/// CFG-ENV-1
+/// <service group>
+/// <incarnation>
+/// <signing key name with revision>
+/// <ring key name with revision, or empty if not encrypted>
+/// <base64 nonce, or empty if not encrypted>
+/// <base64 signature>
+///
+/// <base64 payload>
+/// ```
+///
+/// The signature covers every other field, so none of them can be altered or the payload
+/// replayed against another service group or incarnation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConfigEnvelope {
+    service_group: ServiceGroup,
+    incarnation:   u64,
+    signer:        String,
+    ring_key:      Option<String>,
+    nonce:         Vec<u8>,
+    signature:     Vec<u8>,
+    payload:       Vec<u8>,
+}
+
+impl ConfigEnvelope {
+    /// Creates an envelope for `config`, signed with `signer`'s secret key and, when
+    /// `ring_key` is given, encrypted with it.
+    ///
+    /// # Failures
+    ///
+    /// * `signer` or `ring_key` has no secret key
+    pub fn create(service_group: ServiceGroup,
+                  incarnation: u64,
+                  config: &[u8],
+                  signer: &SigKeyPair,
+                  ring_key: Option<&SymKey>)
+                  -> Result<Self> {
+        let (ring_key, nonce, payload) = match ring_key {
+            Some(key) => {
+                let (nonce, ciphertext) = key.encrypt(config)?;
+                (Some(key.name_with_rev()), nonce, ciphertext)
+            }
+            None => (None, Vec::new(), config.to_vec()),
+        };
+        let mut envelope = ConfigEnvelope { service_group,
+                                            incarnation,
+                                            signer: signer.name_with_rev(),
+                                            ring_key,
+                                            nonce,
+                                            signature: Vec::new(),
+                                            payload };
+        envelope.signature = sign::sign(&envelope.signed_bytes(), signer.secret()?);
+        Ok(envelope)
+    }
+
+    pub fn service_group(&self) -> &ServiceGroup { &self.service_group }
+
+    pub fn incarnation(&self) -> u64 { self.incarnation }
+
+    /// The name with revision of the key the envelope was signed with.
+    pub fn signer(&self) -> &str { &self.signer }
+
+    /// The name with revision of the ring key the payload is encrypted with, if any.
+    pub fn ring_key(&self) -> Option<&str> { self.ring_key.as_ref().map(String::as_str) }
+
+    pub fn is_encrypted(&self) -> bool { self.ring_key.is_some() }
+
+    /// Verifies the envelope with keys from `cache_key_path` and returns its configuration,
+    /// decrypted if need be.
+    ///
+    /// # Failures
+    ///
+    /// * The signing key, or the ring key of an encrypted envelope, is not in the cache
+    /// * The envelope fails verification or decryption
+    pub fn verify<P>(&self, cache_key_path: P) -> Result<Vec<u8>>
+        where P: AsRef<Path>
+    {
+        let signer = SigKeyPair::get_pair_for(&self.signer, cache_key_path.as_ref())?;
+        let ring_key = match self.ring_key {
+            Some(ref name_with_rev) => {
+                Some(SymKey::get_pair_for(name_with_rev, cache_key_path.as_ref())?)
+            }
+            None => None,
+        };
+        self.verify_with(&signer, ring_key.as_ref())
+    }
+
+    /// Verifies the envelope with the given keys and returns its configuration, decrypted if
+    /// need be.
+    ///
+    /// # Failures
+    ///
+    /// * `signer` is not the key the envelope names, or has no public key
+    /// * The signature does not match the envelope's content
+    /// * The envelope is encrypted and `ring_key` is missing, is not the key the envelope names, or
+    ///   cannot decrypt the payload
+    pub fn verify_with(&self, signer: &SigKeyPair, ring_key: Option<&SymKey>) -> Result<Vec<u8>> {
+        if signer.name_with_rev() != self.signer {
+            return Err(Error::CryptoError(format!("Configuration was signed by \
+                                                   {}, not {}",
+                                                  self.signer,
+                                                  signer.name_with_rev())));
+        }
+        match sign::verify(&self.signature, signer.public()?) {
+            Ok(ref signed) if *signed == self.signed_bytes() => {}
+            _ => {
+                return Err(Error::CryptoError(format!("Configuration for {} failed \
+                                                       verification",
+                                                      self.service_group)));
+            }
+        }
+        match (self.ring_key.as_ref(), ring_key) {
+            (None, _) => Ok(self.payload.clone()),
+            (Some(name_with_rev), Some(key)) if *name_with_rev == key.name_with_rev() => {
+                key.decrypt(&self.nonce, &self.payload)
+            }
+            (Some(name_with_rev), _) => {
+                Err(Error::CryptoError(format!("Configuration is encrypted \
+                                                with ring key {}",
+                                               name_with_rev)))
+            }
+        }
+    }
+
+    /// Everything the signature covers: the serialized envelope without its signature.
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!("{}\n{}\n{}\n{}\n{}\n{}\n\n",
+                                CONFIG_ENVELOPE_FORMAT_VERSION,
+                                self.service_group,
+                                self.incarnation,
+                                self.signer,
+                                self.ring_key.as_ref().map_or("", String::as_str),
+                                base64::encode(&self.nonce)).into_bytes();
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+}
+
+impl fmt::Display for ConfigEnvelope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,
+               "{}\n{}\n{}\n{}\n{}\n{}\n{}\n\n{}",
+               CONFIG_ENVELOPE_FORMAT_VERSION,
+               self.service_group,
+               self.incarnation,
+               self.signer,
+               self.ring_key.as_ref().map_or("", String::as_str),
+               base64::encode(&self.nonce),
+               base64::encode(&self.signature),
+               base64::encode(&self.payload))
+    }
+}
+
+impl FromStr for ConfigEnvelope {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let invalid = |what: &str| Error::CryptoError(format!("Corrupt configuration, {}", what));
+        let decode = |field: &str, what: &str| {
+            base64::decode(field).map_err(|e| invalid(&format!("can't decode {}: {}", what, e)))
+        };
+        let mut lines = value.splitn(9, '\n');
+        let mut next = |what: &str| {
+            lines.next()
+                 .ok_or_else(|| invalid(&format!("can't read {}", what)))
+        };
+
+        let version = next("format version")?;
+        if version != CONFIG_ENVELOPE_FORMAT_VERSION {
+            return Err(Error::CryptoError(format!("Unsupported configuration \
+                                                   format version: {}",
+                                                  version)));
+        }
+        let service_group = ServiceGroup::from_str(next("service group")?)?;
+        let incarnation = next("incarnation")?.parse()
+                                              .map_err(|_| invalid("can't parse incarnation"))?;
+        let signer = next("signing key name")?.to_string();
+        let ring_key = match next("ring key name")? {
+            "" => None,
+            name_with_rev => Some(name_with_rev.to_string()),
+        };
+        let nonce = decode(next("nonce")?, "nonce")?;
+        let signature = decode(next("signature")?, "signature")?;
+        if !next("end of header")?.is_empty() {
+            return Err(invalid("can't find end of header"));
+        }
+        let payload = decode(next("payload")?.trim_end(), "payload")?;
+        Ok(ConfigEnvelope { service_group,
+                            incarnation,
+                            signer,
+                            ring_key,
+                            nonce,
+                            signature,
+                            payload })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn records_versions_in_order() {
+        let dir = Builder::new().prefix("history").tempdir().unwrap();
+        let history = History::new(dir.path().join("config-history"));
+        assert_eq!(None, history.latest().unwrap());
+
+        history.record(10, "port = 80", Some("ops")).unwrap();
+        let latest = history.record(25, "port = 81", None).unwrap();
+
+        let versions = history.versions().unwrap();
+        assert_eq!(vec![10, 25],
+                   versions.iter().map(|v| v.incarnation).collect::<Vec<_>>());
+        assert_eq!(Some("ops".to_string()), versions[0].author);
+        assert_eq!(hash::hash_string("port = 81"), latest.hash);
+        assert_eq!(Some(latest), history.latest().unwrap());
+        assert_eq!("port = 80", history.previous().unwrap().unwrap().content);
+        assert_eq!("port = 80", history.get(10).unwrap().unwrap().content);
+        assert_eq!(None, history.get(11).unwrap());
+    }
+
+    #[test]
+    fn rejects_stale_incarnations() {
+        let dir = Builder::new().prefix("history").tempdir().unwrap();
+        let history = History::new(dir.path());
+        let first = history.record(5, "port = 80", None).unwrap();
+
+        assert_eq!(first, history.record(5, "port = 80", None).unwrap());
+        for incarnation in &[4, 5] {
+            match history.record(*incarnation, "port = 81", None) {
+                Err(Error::StaleConfigIncarnation(i, 5)) if i == *incarnation => {}
+                other => panic!("Expected StaleConfigIncarnation, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn prunes_oldest_versions() {
+        let dir = Builder::new().prefix("history").tempdir().unwrap();
+        let history = History::new(dir.path()).retain(3);
+
+        for incarnation in 1..=5 {
+            history.record(incarnation, &format!("n = {}", incarnation), None)
+                   .unwrap();
+        }
+
+        assert_eq!(vec![3, 4, 5],
+                   history.versions()
+                          .unwrap()
+                          .iter()
+                          .map(|v| v.incarnation)
+                          .collect::<Vec<_>>());
+    }
+
+    fn service_group() -> ServiceGroup { ServiceGroup::from_str("redis.default").unwrap() }
+
+    #[test]
+    fn envelope_round_trips_and_verifies() {
+        let cache = Builder::new().prefix("key_cache").tempdir().unwrap();
+        let signer = SigKeyPair::generate_pair_for_origin("jdoe").unwrap();
+        signer.to_pair_files(cache.path()).unwrap();
+
+        let envelope =
+            ConfigEnvelope::create(service_group(), 42, b"port = 80", &signer, None).unwrap();
+        let parsed = ConfigEnvelope::from_str(&envelope.to_string()).unwrap();
+
+        assert_eq!(envelope, parsed);
+        assert!(!parsed.is_encrypted());
+        assert_eq!(42, parsed.incarnation());
+        assert_eq!(signer.name_with_rev(), parsed.signer());
+        assert_eq!(b"port = 80".to_vec(), parsed.verify(cache.path()).unwrap());
+    }
+
+    #[test]
+    fn envelope_rejects_tampering() {
+        let signer = SigKeyPair::generate_pair_for_origin("jdoe").unwrap();
+        let envelope =
+            ConfigEnvelope::create(service_group(), 42, b"port = 80", &signer, None).unwrap();
+
+        let replayed = envelope.to_string().replacen("\n42\n", "\n43\n", 1);
+        let replayed = ConfigEnvelope::from_str(&replayed).unwrap();
+        match replayed.verify_with(&signer, None) {
+            Err(Error::CryptoError(_)) => {}
+            other => panic!("Expected CryptoError, got {:?}", other),
+        }
+
+        let other = SigKeyPair::generate_pair_for_origin("mallory").unwrap();
+        assert!(envelope.verify_with(&other, None).is_err());
+    }
+
+    #[test]
+    fn envelope_rejects_other_format_versions() {
+        let signer = SigKeyPair::generate_pair_for_origin("jdoe").unwrap();
+        let envelope =
+            ConfigEnvelope::create(service_group(), 1, b"port = 80", &signer, None).unwrap();
+        let content = envelope.to_string()
+                              .replacen(CONFIG_ENVELOPE_FORMAT_VERSION, "CFG-ENV-0", 1);
+
+        assert!(ConfigEnvelope::from_str(&content).is_err());
+        assert!(ConfigEnvelope::from_str("CFG-ENV-1\nredis.default\n").is_err());
+    }
+
+    #[test]
+    fn encrypted_envelope_requires_ring_key() {
+        let signer = SigKeyPair::generate_pair_for_origin("jdoe").unwrap();
+        let ring_key = SymKey::generate_pair_for_ring("beyonce").unwrap();
+        let envelope = ConfigEnvelope::create(service_group(),
+                                              1,
+                                              b"password = \"hunter2\"",
+                                              &signer,
+                                              Some(&ring_key)).unwrap();
+
+        assert!(envelope.is_encrypted());
+        assert_eq!(Some(ring_key.name_with_rev().as_str()), envelope.ring_key());
+        assert!(envelope.verify_with(&signer, None).is_err());
+        assert_eq!(b"password = \"hunter2\"".to_vec(),
+                   envelope.verify_with(&signer, Some(&ring_key)).unwrap());
+    }
+}
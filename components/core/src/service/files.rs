@@ -0,0 +1,399 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Files uploaded to a service group, as with `hab file upload`, and their placement in each
+//! member's `/hab/svc/<name>/files` directory.
+//!
+//! A `ServiceFile` carries one file for one service group, at an incarnation which must rise with
+//! each upload of the same file name, along with the BLAKE2b checksum of its content. Its
+//! content is usually encrypted by the uploading user for the service group's box key. A
+//! `FileStore` applies files to a service's files directory, refusing stale incarnations and
+//! oversized or misnamed files, and keeps a manifest of what it placed so that each file's
+//! incarnation and checksum can be checked later.
+
+use std::{collections::BTreeMap,
+          fs,
+          io,
+          path::{Path,
+                 PathBuf}};
+
+use serde_derive::{Deserialize,
+                   Serialize};
+use serde_json;
+use time;
+
+use super::ServiceGroup;
+use crate::{crypto::{hash,
+                     keys::box_key_pair::WrappedSealedBox,
+                     BoxKeyPair},
+            error::{Error,
+                    Result},
+            fs::{atomic_write,
+                 svc_files_path,
+                 svc_path}};
+
+/// The largest file which may be applied by default. Files travel over gossip, which is not
+/// meant for large payloads.
+pub const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// The name of the manifest kept under a service's svc path, recording the files applied to it.
+pub const FILES_MANIFEST: &str = "files.json";
+
+/// One file for a service group, as uploaded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServiceFile {
+    service_group: ServiceGroup,
+    filename:      String,
+    incarnation:   u64,
+    encrypted:     bool,
+    body:          Vec<u8>,
+    checksum:      String,
+}
+
+impl ServiceFile {
+    /// A file sent in the clear.
+    ///
+    /// # Failures
+    ///
+    /// * `filename` is not a plain file name
+    /// * `content` is larger than `MAX_FILE_SIZE`
+    pub fn new<S>(service_group: ServiceGroup,
+                  filename: S,
+                  incarnation: u64,
+                  content: &[u8])
+                  -> Result<Self>
+        where S: Into<String>
+    {
+        let filename = filename.into();
+        validate(&filename, content.len() as u64, MAX_FILE_SIZE)?;
+        Ok(ServiceFile { service_group,
+                         filename,
+                         incarnation,
+                         encrypted: false,
+                         body: content.to_vec(),
+                         checksum: hash::hash_bytes(content) })
+    }
+
+    /// A file encrypted by `user` so that only `service`, the service group's box key, can read
+    /// it.
+    ///
+    /// # Failures
+    ///
+    /// * `filename` is not a plain file name
+    /// * `content` is larger than `MAX_FILE_SIZE`
+    /// * `user` has no secret key, or `service` no public key
+    pub fn encrypted<S>(service_group: ServiceGroup,
+                        filename: S,
+                        incarnation: u64,
+                        content: &[u8],
+                        user: &BoxKeyPair,
+                        service: &BoxKeyPair)
+                        -> Result<Self>
+        where S: Into<String>
+    {
+        let mut file = Self::new(service_group, filename, incarnation, content)?;
+        file.body = user.encrypt(content, Some(service))?.into_bytes();
+        file.encrypted = true;
+        Ok(file)
+    }
+
+    /// Reassembles a file received with the given fields, such as from a gossip rumor.
+    pub fn from_parts(service_group: ServiceGroup,
+                      filename: String,
+                      incarnation: u64,
+                      encrypted: bool,
+                      body: Vec<u8>,
+                      checksum: String)
+                      -> Self {
+        ServiceFile { service_group,
+                      filename,
+                      incarnation,
+                      encrypted,
+                      body,
+                      checksum }
+    }
+
+    pub fn service_group(&self) -> &ServiceGroup { &self.service_group }
+
+    pub fn filename(&self) -> &str { &self.filename }
+
+    pub fn incarnation(&self) -> u64 { self.incarnation }
+
+    pub fn is_encrypted(&self) -> bool { self.encrypted }
+
+    /// The file as sent, which is ciphertext for an encrypted file.
+    pub fn body(&self) -> &[u8] { &self.body }
+
+    /// The BLAKE2b hash of the file's content, as hex.
+    pub fn checksum(&self) -> &str { &self.checksum }
+
+    /// Returns the file's content, decrypted with keys from `cache_key_path` if need be, once
+    /// it has been checked against the checksum.
+    ///
+    /// # Failures
+    ///
+    /// * The file is encrypted, and the keys to decrypt it are not in the cache
+    /// * The content does not match the checksum
+    pub fn content<P>(&self, cache_key_path: P) -> Result<Vec<u8>>
+        where P: AsRef<Path>
+    {
+        let content = if self.encrypted {
+            let payload =
+                WrappedSealedBox::from_bytes(&self.body).map_err(|_| {
+                                                            self.invalid("encrypted body is not \
+                                                                          valid UTF-8")
+                                                        })?;
+            BoxKeyPair::decrypt_with_path(&payload, cache_key_path)?
+        } else {
+            self.body.clone()
+        };
+        if hash::hash_bytes(&content) != self.checksum {
+            return Err(self.invalid("content does not match its checksum"));
+        }
+        Ok(content)
+    }
+
+    fn invalid(&self, reason: &str) -> Error {
+        Error::InvalidServiceFile(self.filename.clone(), reason.to_string())
+    }
+}
+
+/// What a `FileStore` records about a file it applied.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FileRecord {
+    pub incarnation: u64,
+    /// The BLAKE2b hash of the file's content, as hex.
+    pub checksum:    String,
+    pub size:        u64,
+    /// When the file was applied, in RFC 3339 format.
+    pub applied_at:  String,
+}
+
+/// The files applied to one service.
+#[derive(Clone, Debug)]
+pub struct FileStore {
+    dir:      PathBuf,
+    manifest: PathBuf,
+    max_size: u64,
+}
+
+impl FileStore {
+    /// Places files in `dir`, recording them in the manifest at `manifest`.
+    pub fn new<P, Q>(dir: P, manifest: Q) -> Self
+        where P: Into<PathBuf>,
+              Q: Into<PathBuf>
+    {
+        FileStore { dir:      dir.into(),
+                    manifest: manifest.into(),
+                    max_size: MAX_FILE_SIZE, }
+    }
+
+    /// Places files in the files directory of the service named `service_name`.
+    pub fn for_service<T>(service_name: T) -> Self
+        where T: AsRef<Path>
+    {
+        Self::new(svc_files_path(&service_name),
+                  svc_path(&service_name).join(FILES_MANIFEST))
+    }
+
+    /// Sets the largest file which may be applied.
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    pub fn dir(&self) -> &Path { &self.dir }
+
+    /// Writes `file`'s content into the files directory, decrypting it with keys from
+    /// `cache_key_path` if need be, and records it in the manifest. Applying the file already in
+    /// place again is allowed, and returns its record unchanged.
+    ///
+    /// # Failures
+    ///
+    /// * The file's name is not a plain file name, or it is larger than allowed
+    /// * A file of the same name has already been applied with the same or a higher incarnation
+    /// * The file cannot be decrypted, or does not match its checksum
+    /// * The file or the manifest cannot be written
+    pub fn apply<P>(&self, file: &ServiceFile, cache_key_path: P) -> Result<FileRecord>
+        where P: AsRef<Path>
+    {
+        validate(file.filename(), file.body().len() as u64, self.max_size)?;
+        let mut records = self.records()?;
+        if let Some(current) = records.get(file.filename()) {
+            if current.incarnation == file.incarnation() && current.checksum == file.checksum() {
+                return Ok(current.clone());
+            }
+            if current.incarnation >= file.incarnation() {
+                return Err(Error::StaleFileIncarnation(file.filename().to_string(),
+                                                       file.incarnation(),
+                                                       current.incarnation));
+            }
+        }
+
+        let content = file.content(cache_key_path)?;
+        validate(file.filename(), content.len() as u64, self.max_size)?;
+        fs::create_dir_all(&self.dir)?;
+        atomic_write(&self.dir.join(file.filename()), &content)?;
+        let record = FileRecord { incarnation: file.incarnation(),
+                                  checksum:    file.checksum().to_string(),
+                                  size:        content.len() as u64,
+                                  applied_at:  time::now_utc().rfc3339().to_string(), };
+        records.insert(file.filename().to_string(), record.clone());
+        if let Some(parent) = self.manifest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        atomic_write(&self.manifest, serde_json::to_vec_pretty(&records)?)?;
+        debug!("Applied {} at incarnation {} to {}",
+               file.filename(),
+               file.incarnation(),
+               self.dir.display());
+        Ok(record)
+    }
+
+    /// The files applied, by name.
+    ///
+    /// # Failures
+    ///
+    /// * The manifest cannot be read or parsed
+    pub fn records(&self) -> Result<BTreeMap<String, FileRecord>> {
+        match fs::read(&self.manifest) {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether the file `filename` is in place with the content it was applied with. A file which
+    /// was never applied is not intact.
+    ///
+    /// # Failures
+    ///
+    /// * The manifest or the file cannot be read
+    pub fn is_intact(&self, filename: &str) -> Result<bool> {
+        match self.records()?.get(filename) {
+            Some(record) => {
+                match hash::hash_file(self.dir.join(filename)) {
+                    Ok(checksum) => Ok(checksum == record.checksum),
+                    Err(Error::IO(ref e)) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+                    Err(e) => Err(e),
+                }
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Checks that `filename` names a file directly within the files directory, and that `size` is
+/// no more than `max_size`.
+fn validate(filename: &str, size: u64, max_size: u64) -> Result<()> {
+    let invalid = |reason: String| Err(Error::InvalidServiceFile(filename.to_string(), reason));
+    if filename.is_empty()
+       || filename == "."
+       || filename == ".."
+       || filename.contains(&['/', '\\', '\0'][..])
+    {
+        return invalid("not a plain file name".to_string());
+    }
+    if size > max_size {
+        return invalid(format!("{} bytes is larger than the limit of {} bytes",
+                               size, max_size));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+    use tempfile::Builder;
+
+    fn store(root: &Path) -> FileStore {
+        FileStore::new(root.join("svc/redis/files"),
+                       root.join("svc/redis").join(FILES_MANIFEST))
+    }
+
+    fn file(incarnation: u64, content: &str) -> ServiceFile {
+        ServiceFile::new(ServiceGroup::from_str("redis.default").unwrap(),
+                         "users.acl",
+                         incarnation,
+                         content.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn apply_places_files_by_incarnation() {
+        let root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let store = store(root.path());
+
+        let record = store.apply(&file(1, "user default on"), root.path())
+                          .unwrap();
+        assert_eq!(1, record.incarnation);
+        assert_eq!(hash::hash_string("user default on"), record.checksum);
+        assert_eq!("user default on",
+                   fs::read_to_string(store.dir().join("users.acl")).unwrap());
+        assert!(store.is_intact("users.acl").unwrap());
+
+        // Applying the same file again changes nothing
+        assert_eq!(record,
+                   store.apply(&file(1, "user default on"), root.path())
+                        .unwrap());
+        match store.apply(&file(1, "user default off"), root.path()) {
+            Err(Error::StaleFileIncarnation(ref name, 1, 1)) => assert_eq!("users.acl", name),
+            other => panic!("Expected StaleFileIncarnation, got {:?}", other),
+        }
+
+        store.apply(&file(2, "user default off"), root.path())
+             .unwrap();
+        assert_eq!(2, store.records().unwrap()["users.acl"].incarnation);
+        assert_eq!("user default off",
+                   fs::read_to_string(store.dir().join("users.acl")).unwrap());
+
+        fs::write(store.dir().join("users.acl"), "tampered").unwrap();
+        assert!(!store.is_intact("users.acl").unwrap());
+        assert!(!store.is_intact("missing").unwrap());
+    }
+
+    #[test]
+    fn invalid_files_are_refused() {
+        let root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let group = ServiceGroup::from_str("redis.default").unwrap();
+
+        for name in &["", "..", "../redis.conf", "config/redis.conf"] {
+            match ServiceFile::new(group.clone(), *name, 1, b"") {
+                Err(Error::InvalidServiceFile(..)) => {}
+                other => {
+                    panic!("Expected InvalidServiceFile for {:?}, got {:?}",
+                           name, other)
+                }
+            }
+        }
+
+        let small = store(root.path()).max_size(4);
+        match small.apply(&file(1, "too big"), root.path()) {
+            Err(Error::InvalidServiceFile(..)) => {}
+            other => panic!("Expected InvalidServiceFile, got {:?}", other),
+        }
+
+        let corrupt = ServiceFile::from_parts(group,
+                                              "users.acl".to_string(),
+                                              1,
+                                              false,
+                                              b"user default on".to_vec(),
+                                              hash::hash_string("user default off"));
+        match store(root.path()).apply(&corrupt, root.path()) {
+            Err(Error::InvalidServiceFile(..)) => {}
+            other => panic!("Expected InvalidServiceFile, got {:?}", other),
+        }
+        assert!(!root.path().join("svc/redis/files/users.acl").exists());
+    }
+}
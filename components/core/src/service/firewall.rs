@@ -0,0 +1,348 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generation of host firewall rules which open a package's exposed ports.
+//!
+//! `rules_for` describes one rule per port in the package's `EXPOSES` metafile, as the command
+//! which adds it for the platform's usual firewall. The rules can be reviewed and applied by
+//! hand, or applied with `apply`, which removes any it added again if one of them fails.
+
+use std::{fmt,
+          process::Command};
+
+use crate::{error::{Error,
+                    Result},
+            os::system,
+            package::{metadata::MetaFile,
+                      PackageInstall}};
+
+/// The firewall a rule is written for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Backend {
+    Iptables,
+    Nftables,
+    /// `netsh advfirewall`, on Windows.
+    Netsh,
+    /// The `NetSecurity` PowerShell module, on Windows.
+    PowerShell,
+}
+
+impl Default for Backend {
+    #[cfg(windows)]
+    fn default() -> Self { Backend::Netsh }
+
+    #[cfg(not(windows))]
+    fn default() -> Self { Backend::Iptables }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match *self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+/// A rule allowing inbound traffic to one port.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rule {
+    pub backend:  Backend,
+    pub port:     u16,
+    pub protocol: Protocol,
+    /// Identifies the rule as belonging to the package, such as `hab-core-redis-6379-tcp`.
+    pub name:     String,
+}
+
+impl Rule {
+    /// The program and arguments which add the rule.
+    pub fn apply_args(&self) -> Vec<String> {
+        let port = self.port.to_string();
+        let protocol = self.protocol.to_string();
+        match self.backend {
+            Backend::Iptables => {
+                args(&["iptables",
+                       "-I",
+                       "INPUT",
+                       "-p",
+                       &protocol,
+                       "--dport",
+                       &port,
+                       "-m",
+                       "comment",
+                       "--comment",
+                       &self.name,
+                       "-j",
+                       "ACCEPT"])
+            }
+            // `--echo --handle` prints the new rule's handle, which is needed to delete it
+            Backend::Nftables => {
+                args(&["nft", "--echo", "--handle", "add", "rule", "inet", "filter", "input",
+                       &protocol, "dport", &port, "accept", "comment", &self.name])
+            }
+            Backend::Netsh => {
+                args(&["netsh",
+                       "advfirewall",
+                       "firewall",
+                       "add",
+                       "rule",
+                       &format!("name={}", self.name),
+                       "dir=in",
+                       "action=allow",
+                       &format!("protocol={}", protocol.to_uppercase()),
+                       &format!("localport={}", port)])
+            }
+            Backend::PowerShell => {
+                let cmdlet = format!("New-NetFirewallRule -Name {0} -DisplayName {0} -Direction \
+                                      Inbound -Action Allow -Protocol {1} -LocalPort {2}",
+                                     self.name,
+                                     protocol.to_uppercase(),
+                                     port);
+                args(&["powershell", "-NoProfile", "-Command", &cmdlet])
+            }
+        }
+    }
+
+    /// The program and arguments which remove the rule again, given the output of the command
+    /// which added it. `None` if the output does not identify the rule.
+    fn rollback_args(&self, apply_output: &str) -> Option<Vec<String>> {
+        let rollback = match self.backend {
+            Backend::Iptables => {
+                let mut rollback = self.apply_args();
+                rollback[1] = "-D".to_string();
+                rollback
+            }
+            Backend::Nftables => {
+                const HANDLE: &str = "# handle ";
+                let start = apply_output.rfind(HANDLE)? + HANDLE.len();
+                let handle = apply_output[start..].trim();
+                args(&["nft", "delete", "rule", "inet", "filter", "input", "handle", handle])
+            }
+            Backend::Netsh => {
+                args(&["netsh",
+                       "advfirewall",
+                       "firewall",
+                       "delete",
+                       "rule",
+                       &format!("name={}", self.name)])
+            }
+            Backend::PowerShell => {
+                args(&["powershell",
+                       "-NoProfile",
+                       "-Command",
+                       &format!("Remove-NetFirewallRule -Name {}", self.name)])
+            }
+        };
+        Some(rollback)
+    }
+}
+
+/// The command which adds the rule, as it would be typed into a shell.
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", command_line(&self.apply_args()))
+    }
+}
+
+/// Returns a rule for each of the package's exposed ports, for the platform's default firewall.
+///
+/// # Failures
+///
+/// * The package's `EXPOSES` metafile cannot be read or holds an invalid port
+pub fn rules_for(pkg_install: &PackageInstall) -> Result<Vec<Rule>> {
+    rules_for_backend(pkg_install, Backend::default())
+}
+
+/// Returns a rule for each of the package's exposed ports, for the given firewall. Ports are
+/// taken to be TCP unless written as `<PORT>/udp`.
+///
+/// # Failures
+///
+/// * The package's `EXPOSES` metafile cannot be read or holds an invalid port
+pub fn rules_for_backend(pkg_install: &PackageInstall, backend: Backend) -> Result<Vec<Rule>> {
+    let ident = pkg_install.ident();
+    let mut rules = Vec::new();
+    for exposed in pkg_install.exposes()? {
+        let exposed = exposed.trim();
+        if exposed.is_empty() {
+            continue;
+        }
+        let mut parts = exposed.splitn(2, '/');
+        let port = parts.next()
+                        .and_then(|port| port.parse().ok())
+                        .ok_or(Error::MetaFileMalformed(MetaFile::Exposes))?;
+        let protocol = match parts.next().map(str::to_lowercase) {
+            None => Protocol::Tcp,
+            Some(ref protocol) if protocol == "tcp" => Protocol::Tcp,
+            Some(ref protocol) if protocol == "udp" => Protocol::Udp,
+            Some(_) => return Err(Error::MetaFileMalformed(MetaFile::Exposes)),
+        };
+        rules.push(Rule { backend,
+                          port,
+                          protocol,
+                          name: format!("hab-{}-{}-{}-{}",
+                                        ident.origin, ident.name, port, protocol) });
+    }
+    Ok(rules)
+}
+
+/// Rules which have been added to the firewall, and can be removed again.
+#[derive(Debug, Default)]
+pub struct Applied {
+    rollback: Vec<Vec<String>>,
+}
+
+impl Applied {
+    /// Removes the rules, most recently added first.
+    ///
+    /// # Failures
+    ///
+    /// * A command removing a rule fails; the rules after it are still removed
+    pub fn rollback(self) -> Result<()> {
+        let mut result = Ok(());
+        for args in self.rollback.into_iter().rev() {
+            if let Err(e) = run(&args) {
+                warn!("{}", e);
+                result = Err(e);
+            }
+        }
+        result
+    }
+}
+
+/// Adds each of `rules` to the firewall in turn. If one cannot be added, the rules already added
+/// are removed again.
+///
+/// # Failures
+///
+/// * A command adding a rule fails
+pub fn apply(rules: &[Rule]) -> Result<Applied> {
+    let mut applied = Applied::default();
+    for rule in rules {
+        let output = match run(&rule.apply_args()) {
+            Ok(output) => output,
+            Err(e) => {
+                if let Err(rollback_err) = applied.rollback() {
+                    warn!("Could not roll back firewall rules: {}", rollback_err);
+                }
+                return Err(e);
+            }
+        };
+        match rule.rollback_args(&output) {
+            Some(args) => applied.rollback.push(args),
+            None => {
+                warn!("Cannot identify firewall rule {} to roll it back",
+                      rule.name)
+            }
+        }
+    }
+    Ok(applied)
+}
+
+/// Runs a command, returning its standard output.
+fn run(args: &[String]) -> Result<String> {
+    let failed = |reason: String| Error::FirewallCommandFailed(command_line(args), reason);
+    let output = Command::new(&args[0]).args(&args[1..])
+                                       .output()
+                                       .map_err(|e| failed(e.to_string()))?;
+    if output.status.success() {
+        Ok(system::decode_output(&output.stdout))
+    } else {
+        Err(failed(system::decode_output(&output.stderr).trim().to_string()))
+    }
+}
+
+fn args(args: &[&str]) -> Vec<String> { args.iter().map(|arg| arg.to_string()).collect() }
+
+/// Joins arguments into a command line, quoting any which contain spaces.
+fn command_line(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            if arg.contains(' ') {
+                format!("\"{}\"", arg)
+            } else {
+                arg.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::test_support::testing_package_install;
+    use std::fs;
+    use tempfile::Builder;
+
+    fn pkg_exposing(exposes: &str, fs_root: &std::path::Path) -> PackageInstall {
+        let pkg_install = testing_package_install("core/redis/4.0.14/20190319155852", fs_root);
+        fs::write(pkg_install.installed_path()
+                             .join(MetaFile::Exposes.to_string()),
+                  exposes).unwrap();
+        pkg_install
+    }
+
+    #[test]
+    fn rules_for_exposed_ports() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = pkg_exposing("6379 16379/udp\n", fs_root.path());
+
+        let rules = rules_for_backend(&pkg_install, Backend::Iptables).unwrap();
+
+        assert_eq!(2, rules.len());
+        assert_eq!("iptables -I INPUT -p tcp --dport 6379 -m comment --comment \
+                    hab-core-redis-6379-tcp -j ACCEPT",
+                   rules[0].to_string());
+        assert_eq!(Protocol::Udp, rules[1].protocol);
+
+        let rules = rules_for_backend(&pkg_install, Backend::PowerShell).unwrap();
+        assert_eq!("powershell -NoProfile -Command \"New-NetFirewallRule -Name \
+                    hab-core-redis-6379-tcp -DisplayName hab-core-redis-6379-tcp -Direction \
+                    Inbound -Action Allow -Protocol TCP -LocalPort 6379\"",
+                   rules[0].to_string());
+
+        let pkg_install = pkg_exposing("redis", fs_root.path());
+        assert!(rules_for(&pkg_install).is_err());
+    }
+
+    #[test]
+    fn rollback_commands() {
+        let rule = |backend| {
+            Rule { backend,
+                   port: 6379,
+                   protocol: Protocol::Tcp,
+                   name: "hab-core-redis-6379-tcp".to_string() }
+        };
+
+        assert_eq!("iptables -D INPUT -p tcp --dport 6379 -m comment --comment \
+                    hab-core-redis-6379-tcp -j ACCEPT",
+                   command_line(&rule(Backend::Iptables).rollback_args("").unwrap()));
+        assert_eq!("nft delete rule inet filter input handle 12",
+                   command_line(&rule(Backend::Nftables).rollback_args("add rule inet filter \
+                                                                        input tcp dport 6379 \
+                                                                        accept # handle 12\n")
+                                                        .unwrap()));
+        assert_eq!(None, rule(Backend::Nftables).rollback_args(""));
+        assert_eq!("netsh advfirewall firewall delete rule name=hab-core-redis-6379-tcp",
+                   command_line(&rule(Backend::Netsh).rollback_args("Ok.").unwrap()));
+    }
+}
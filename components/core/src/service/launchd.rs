@@ -0,0 +1,214 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generation of launchd property lists for running installed packages on macOS.
+
+use std::{fmt::Write,
+          path::PathBuf};
+
+use crate::{error::Result,
+            package::{install::{lossy_environment,
+                                EnvironmentOptions},
+                      PackageInstall},
+            service::program::Program};
+
+const PLIST_DOCTYPE: &str = "<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+                             \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">";
+
+/// Options for `plist_for`.
+#[derive(Clone, Debug)]
+pub struct PlistOptions {
+    /// What the job's `ProgramArguments` run.
+    pub program:      Program,
+    /// The job's label. Defaults to the value of `label_for`.
+    pub label:        Option<String>,
+    /// Start the job as soon as it is loaded.
+    pub run_at_load:  bool,
+    /// Restart the job whenever it exits.
+    pub keep_alive:   bool,
+    /// Files which receive the job's standard output and standard error streams.
+    pub stdout_path:  Option<PathBuf>,
+    pub stderr_path:  Option<PathBuf>,
+    /// The name of the service the job runs, which names its directory under `/hab/svc`.
+    /// Defaults to the package name.
+    pub service_name: Option<String>,
+    /// Where the service's hooks are rendered. Defaults to the service's hooks directory under
+    /// the package's filesystem root.
+    pub hooks_dir:    Option<PathBuf>,
+}
+
+impl Default for PlistOptions {
+    fn default() -> Self {
+        PlistOptions { program:      Program::RunHook,
+                       label:        None,
+                       run_at_load:  true,
+                       keep_alive:   true,
+                       stdout_path:  None,
+                       stderr_path:  None,
+                       service_name: None,
+                       hooks_dir:    None, }
+    }
+}
+
+/// Returns the default launchd label for a package, such as `sh.habitat.core.redis`.
+pub fn label_for(pkg_install: &PackageInstall) -> String {
+    let ident = pkg_install.ident();
+    format!("sh.habitat.{}.{}", ident.origin, ident.name)
+}
+
+/// Generates a launchd property list which runs `pkg_install`.
+///
+/// The job runs as the package's `SVC_USER` and `SVC_GROUP`, if set, with the package's
+/// runtime environment. With `Program::RunHook`, the package's run hook is rendered into the
+/// service's hooks directory first, and the job runs the rendered hook.
+///
+/// # Failures
+///
+/// * A metafile exists but cannot be properly parsed
+/// * The run hook is needed but cannot be rendered
+pub fn plist_for(pkg_install: &PackageInstall, opts: &PlistOptions) -> Result<String> {
+    let args = opts.program.arguments(pkg_install,
+                                       opts.service_name.as_ref(),
+                                       opts.hooks_dir.as_ref())?;
+    let label = match opts.label {
+        Some(ref label) => label.clone(),
+        None => label_for(pkg_install),
+    };
+    let env = pkg_install.environment_for_command(EnvironmentOptions::default())?;
+    let mut env: Vec<_> = lossy_environment(env).into_iter().collect();
+    env.sort();
+
+    // Writing to a `String` cannot fail
+    let mut plist = String::new();
+    writeln!(plist, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(plist, "{}", PLIST_DOCTYPE).unwrap();
+    writeln!(plist, r#"<plist version="1.0">"#).unwrap();
+    writeln!(plist, "<dict>").unwrap();
+    write_string(&mut plist, "Label", &label);
+    writeln!(plist, "  <key>ProgramArguments</key>").unwrap();
+    writeln!(plist, "  <array>").unwrap();
+    for arg in &args {
+        writeln!(plist, "    <string>{}</string>", escape(arg)).unwrap();
+    }
+    writeln!(plist, "  </array>").unwrap();
+    if let Some(user) = pkg_install.svc_user()? {
+        write_string(&mut plist, "UserName", user.trim());
+    }
+    if let Some(group) = pkg_install.svc_group()? {
+        write_string(&mut plist, "GroupName", group.trim());
+    }
+    if !env.is_empty() {
+        writeln!(plist, "  <key>EnvironmentVariables</key>").unwrap();
+        writeln!(plist, "  <dict>").unwrap();
+        for (key, value) in env {
+            writeln!(plist, "    <key>{}</key>", escape(&key)).unwrap();
+            writeln!(plist, "    <string>{}</string>", escape(&value)).unwrap();
+        }
+        writeln!(plist, "  </dict>").unwrap();
+    }
+    write_bool(&mut plist, "RunAtLoad", opts.run_at_load);
+    write_bool(&mut plist, "KeepAlive", opts.keep_alive);
+    if let Some(ref path) = opts.stdout_path {
+        write_string(&mut plist, "StandardOutPath", &path.to_string_lossy());
+    }
+    if let Some(ref path) = opts.stderr_path {
+        write_string(&mut plist, "StandardErrorPath", &path.to_string_lossy());
+    }
+    writeln!(plist, "</dict>").unwrap();
+    writeln!(plist, "</plist>").unwrap();
+    Ok(plist)
+}
+
+fn write_string(plist: &mut String, key: &str, value: &str) {
+    writeln!(plist, "  <key>{}</key>", key).unwrap();
+    writeln!(plist, "  <string>{}</string>", escape(value)).unwrap();
+}
+
+fn write_bool(plist: &mut String, key: &str, value: bool) {
+    writeln!(plist, "  <key>{}</key>", key).unwrap();
+    writeln!(plist, "  <{}/>", value).unwrap();
+}
+
+/// Escapes the characters which are significant in XML character data.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::{metadata::MetaFile,
+                         test_support::testing_package_install};
+    use std::fs;
+    use tempfile::Builder;
+
+    #[test]
+    fn plist_for_run_hook() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        let path = pkg_install.installed_path();
+        fs::write(path.join(MetaFile::SvcUser.to_string()), "hab\n").unwrap();
+        fs::write(path.join(MetaFile::RuntimeEnvironment.to_string()),
+                  "GREETING=fish & chips\n").unwrap();
+        fs::create_dir_all(path.join("hooks")).unwrap();
+        fs::write(path.join("hooks").join("run"), "exec {{pkg.name}}\n").unwrap();
+        let hooks_dir = fs_root.path().join("hab/svc/pathy/hooks");
+        let opts = PlistOptions { hooks_dir: Some(hooks_dir.clone()),
+                                  ..Default::default() };
+
+        let plist = plist_for(&pkg_install, &opts).unwrap();
+
+        assert!(plist.contains("  <string>sh.habitat.acme.pathy</string>\n"));
+        assert!(plist.contains(&format!("    <string>{}</string>\n",
+                                        hooks_dir.join("run").display())));
+        assert_eq!("exec pathy\n",
+                   fs::read_to_string(hooks_dir.join("run")).unwrap());
+        assert!(plist.contains("  <key>UserName</key>\n  <string>hab</string>\n"));
+        assert!(!plist.contains("GroupName"));
+        assert!(plist.contains("    <key>GREETING</key>\n    <string>fish &amp; chips</string>\n"));
+        assert!(plist.contains("  <key>KeepAlive</key>\n  <true/>\n"));
+    }
+
+    #[test]
+    fn plist_for_supervisor_with_label() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        let opts = PlistOptions { program: Program::Supervisor(PathBuf::from("/usr/local/bin/\
+                                                                              hab")),
+                                  label: Some("com.example.pathy".to_string()),
+                                  keep_alive: false,
+                                  ..Default::default() };
+
+        let plist = plist_for(&pkg_install, &opts).unwrap();
+
+        assert!(plist.contains("  <string>com.example.pathy</string>\n"));
+        assert!(plist.contains(&format!("    <string>/usr/local/bin/hab</string>\n    \
+                                         <string>sup</string>\n    <string>run</string>\n    \
+                                         <string>{}</string>\n",
+                                        pkg_install.ident())));
+        assert!(!plist.contains("EnvironmentVariables"));
+        assert!(plist.contains("  <key>KeepAlive</key>\n  <false/>\n"));
+    }
+}
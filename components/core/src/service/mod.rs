@@ -12,6 +12,18 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod backoff;
+pub mod config;
+pub mod files;
+pub mod firewall;
+pub mod launchd;
+pub mod program;
+pub mod spec;
+pub mod specs;
+pub mod state;
+pub mod systemd;
+pub mod update;
+
 use crate::error::{Error,
                    Result};
 use regex::Regex;
@@ -42,13 +54,20 @@ pub enum BindingMode {
     /// Binds may be satisfied at runtime, and are not required to be
     /// satisfied before a service starts. Modern distributed services
     /// should be constructed in this way.
+    #[serde(alias = "relaxed")]
     Relaxed,
     /// Binds *must* be satisfied before a service can start. Legacy
     /// applications that cannot cope with the absence of a service
     /// dependency at startup should bind with this mode.
+    #[serde(alias = "strict")]
     Strict,
 }
 
+impl BindingMode {
+    /// Every binding mode, for listing the values a CLI accepts.
+    pub fn variants() -> &'static [Self] { &[BindingMode::Relaxed, BindingMode::Strict] }
+}
+
 impl Default for BindingMode {
     /// Strict is the default _for now_, since that's the de facto
     /// behavior that has been in place for until this point.
@@ -83,6 +102,71 @@ impl FromStr for BindingMode {
     }
 }
 
+/// How the members of a service group relate to one another.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Topology {
+    /// Every member runs independently.
+    Standalone,
+    /// One member is elected leader and the rest are followers.
+    Leader,
+}
+
+impl Topology {
+    /// Every topology, for listing the values a CLI accepts.
+    pub fn variants() -> &'static [Self] { &[Topology::Standalone, Topology::Leader] }
+
+    /// The fewest members a service group with this topology can run with. Electing a leader
+    /// needs a majority which survives the loss of a member, so needs at least three.
+    pub fn minimum_members(self) -> usize {
+        match self {
+            Topology::Standalone => 1,
+            Topology::Leader => 3,
+        }
+    }
+
+    /// Checks that a service group of `count` members can run with this topology.
+    ///
+    /// # Failures
+    ///
+    /// * `count` is less than `minimum_members`
+    pub fn validate_members(self, count: usize) -> Result<()> {
+        let required = self.minimum_members();
+        if count < required {
+            return Err(Error::InsufficientMembers(self.to_string(),
+                                                  required,
+                                                  count));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Topology {
+    fn default() -> Topology { Topology::Standalone }
+}
+
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match *self {
+            Topology::Standalone => "standalone",
+            Topology::Leader => "leader",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+impl FromStr for Topology {
+    type Err = Error;
+
+    fn from_str(value: &str) -> result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_ref() {
+            "standalone" => Ok(Topology::Standalone),
+            "leader" => Ok(Topology::Leader),
+            _ => Err(Error::BadTopology(value.to_string())),
+        }
+    }
+}
+
 /// A binding from a service name to a service group that provides that service
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct ServiceBind {
@@ -773,4 +857,65 @@ mod test {
         assert_eq!("(5s)".to_owned(),
                    format!("{}", HealthCheckInterval::from_str("5").unwrap()));
     }
+
+    #[test]
+    fn topology_round_trips() {
+        for topology in &[Topology::Standalone, Topology::Leader] {
+            assert_eq!(*topology, topology.to_string().parse().unwrap());
+            let json = serde_json::to_string(topology).unwrap();
+            assert_eq!(format!("\"{}\"", topology), json);
+            assert_eq!(*topology, serde_json::from_str(&json).unwrap());
+        }
+        assert_eq!(Topology::Leader, Topology::from_str("LEADER").unwrap());
+        match Topology::from_str("follower") {
+            Err(Error::BadTopology(ref s)) if s == "follower" => {}
+            other => panic!("Expected BadTopology, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn topology_validate_members() {
+        assert!(Topology::Standalone.validate_members(1).is_ok());
+        assert!(Topology::Leader.validate_members(3).is_ok());
+        match Topology::Leader.validate_members(2) {
+            Err(Error::InsufficientMembers(_, 3, 2)) => {}
+            other => panic!("Expected InsufficientMembers, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn binding_mode_deserializes_either_spelling() {
+        for (json, mode) in &[("\"Strict\"", BindingMode::Strict),
+                              ("\"strict\"", BindingMode::Strict),
+                              ("\"Relaxed\"", BindingMode::Relaxed),
+                              ("\"relaxed\"", BindingMode::Relaxed)]
+        {
+            assert_eq!(*mode, serde_json::from_str::<BindingMode>(json).unwrap());
+        }
+    }
+
+    #[test]
+    fn topology_variants_lists_every_variant() {
+        // No wildcard arm: a new variant must be given a position here before this compiles.
+        let position = |value: &Topology| {
+            match *value {
+                Topology::Standalone => 0,
+                Topology::Leader => 1,
+            }
+        };
+        let positions: Vec<_> = Topology::variants().iter().map(position).collect();
+        assert_eq!(vec![0, 1], positions);
+    }
+
+    #[test]
+    fn binding_mode_variants_lists_every_variant() {
+        let position = |value: &BindingMode| {
+            match *value {
+                BindingMode::Relaxed => 0,
+                BindingMode::Strict => 1,
+            }
+        };
+        let positions: Vec<_> = BindingMode::variants().iter().map(position).collect();
+        assert_eq!(vec![0, 1], positions);
+    }
 }
@@ -0,0 +1,72 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! What the service definitions generated for an installed package, such as systemd units and
+//! launchd jobs, run.
+
+use std::path::PathBuf;
+
+use crate::{error::Result,
+            fs::svc_path_in,
+            hooks::render::render_hook,
+            package::PackageInstall};
+
+/// What a generated service definition runs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Program {
+    /// Run the service's `run` hook, rendered from the package's `hooks/run` template into the
+    /// service's hooks directory, as the Supervisor would.
+    RunHook,
+    /// Run the package under a Supervisor started by the given `hab` binary, with
+    /// `hab sup run <ident>`.
+    Supervisor(PathBuf),
+    /// Run an arbitrary program; the first element is the program and the rest its arguments.
+    Arguments(Vec<String>),
+}
+
+impl Program {
+    /// Returns the program and arguments which run `pkg_install`.
+    ///
+    /// With `Program::RunHook`, the run hook is rendered first, for the service `service_name`,
+    /// which defaults to the package name, into `hooks_dir`, which defaults to the service's
+    /// hooks directory under the package's filesystem root.
+    ///
+    /// # Failures
+    ///
+    /// * The run hook is needed but cannot be rendered
+    pub(crate) fn arguments(&self,
+                            pkg_install: &PackageInstall,
+                            service_name: Option<&String>,
+                            hooks_dir: Option<&PathBuf>)
+                            -> Result<Vec<String>> {
+        match *self {
+            Program::RunHook => {
+                let service_name = service_name.unwrap_or(&pkg_install.ident().name);
+                let hooks_dir = match hooks_dir {
+                    Some(dir) => dir.clone(),
+                    None => svc_path_in(pkg_install.fs_root_path(), service_name).join("hooks"),
+                };
+                let run = render_hook(pkg_install, service_name, "run", &hooks_dir)?;
+                Ok(vec![run.to_string_lossy().into_owned()])
+            }
+            Program::Supervisor(ref hab) => {
+                Ok(vec![hab.to_string_lossy().into_owned(),
+                        "sup".to_string(),
+                        "run".to_string(),
+                        pkg_install.ident().to_string()])
+            }
+            Program::Arguments(ref args) => Ok(args.clone()),
+        }
+    }
+}
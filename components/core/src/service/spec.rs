@@ -0,0 +1,370 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Service spec files: what the Supervisor is to run, one service per file, kept as
+//! `<name>.spec` in its specs directory.
+//!
+//! A spec looks like this, with every field but `ident` optional:
+//!
+//! ```toml
+//! ident = "core/redis"
+//! group = "default"
+//! bldr_url = "https://bldr.habitat.sh"
+//! channel = "stable"
+//! topology = "standalone"
+//! update_strategy = "none"
+//! update_condition = "latest"
+//! binds = ["backend:redis.default"]
+//! binding_mode = "strict"
+//! desired_state = "up"
+//! health_check_interval = 30
+//! ```
+//!
+//! A Windows service run as another user also carries that user's password, encrypted, as
+//! `svc_encrypted_password`.
+//!
+//! Older specs gave the health check interval as a table of `secs` and `nanos`, and could carry
+//! `start_style`, `composite` and `application_environment`, which no longer mean anything.
+//! Reading a spec brings it up to date, so writing it back out upgrades the file.
+
+use std::{collections::HashSet,
+          fmt,
+          fs,
+          path::{Path,
+                 PathBuf},
+          result,
+          str::FromStr,
+          time::Duration};
+
+use serde_derive::{Deserialize,
+                   Serialize};
+use toml::{self,
+           value::Table,
+           Value};
+
+use super::{update::{UpdateCondition,
+                     UpdateStrategy},
+            BindingMode,
+            HealthCheckInterval,
+            ServiceBind,
+            ServiceGroup,
+            Topology};
+use crate::{error::{Error,
+                    Result},
+            events::ident_string,
+            fs::atomic_write,
+            package::PackageIdent,
+            url::DEFAULT_BLDR_URL,
+            ChannelIdent};
+
+/// The extension of a spec file.
+pub const SPEC_FILE_EXT: &str = "spec";
+
+/// Keys of older specs which are no longer used.
+const OBSOLETE_KEYS: &[&str] = &["start_style", "composite", "application_environment"];
+
+/// Whether a service should be running.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DesiredState {
+    Up,
+    Down,
+}
+
+impl Default for DesiredState {
+    fn default() -> Self { DesiredState::Up }
+}
+
+impl fmt::Display for DesiredState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match *self {
+            DesiredState::Up => "up",
+            DesiredState::Down => "down",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+/// One service for the Supervisor to run.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceSpec {
+    #[serde(with = "ident_string")]
+    pub ident:                  PackageIdent,
+    #[serde(default = "default_group")]
+    pub group:                  String,
+    #[serde(default = "default_bldr_url")]
+    pub bldr_url:               String,
+    #[serde(default)]
+    pub channel:                ChannelIdent,
+    #[serde(default)]
+    pub topology:               Topology,
+    #[serde(default)]
+    pub update_strategy:        UpdateStrategy,
+    #[serde(default)]
+    pub update_condition:       UpdateCondition,
+    #[serde(default)]
+    pub binds:                  Vec<ServiceBind>,
+    #[serde(default)]
+    pub binding_mode:           BindingMode,
+    #[serde(default)]
+    pub desired_state:          DesiredState,
+    /// Written as a whole number of seconds.
+    #[serde(default, with = "interval_secs")]
+    pub health_check_interval:  HealthCheckInterval,
+    /// A directory to take the service's configuration from in place of its package's, for
+    /// developing a package's configuration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_from:            Option<PathBuf>,
+    /// The password of the user a Windows service runs as, encrypted for the Supervisor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub svc_encrypted_password: Option<String>,
+}
+
+fn default_group() -> String { "default".to_string() }
+
+fn default_bldr_url() -> String { DEFAULT_BLDR_URL.to_string() }
+
+impl ServiceSpec {
+    /// A spec for running `ident` with every other field at its default.
+    pub fn new(ident: PackageIdent) -> Self {
+        ServiceSpec { ident,
+                      group: default_group(),
+                      bldr_url: default_bldr_url(),
+                      channel: ChannelIdent::default(),
+                      topology: Topology::default(),
+                      update_strategy: UpdateStrategy::default(),
+                      update_condition: UpdateCondition::default(),
+                      binds: Vec::new(),
+                      binding_mode: BindingMode::default(),
+                      desired_state: DesiredState::default(),
+                      health_check_interval: HealthCheckInterval::default(),
+                      config_from: None,
+                      svc_encrypted_password: None }
+    }
+
+    /// Reads the spec file at `path`, upgrading it from an older format if need be.
+    ///
+    /// # Failures
+    ///
+    /// * The file cannot be read
+    /// * The spec is not valid
+    /// * The file is not named for the service in it
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let raw = fs::read_to_string(path).map_err(|e| Error::ConfigFileIO(path.to_path_buf(), e))?;
+        let spec = raw.parse::<Self>()?;
+        let expected = spec.file_name();
+        if path.file_name().and_then(|n| n.to_str()) != Some(expected.as_str()) {
+            return Err(invalid(format!("{} should be named {}",
+                                       path.display(),
+                                       expected)));
+        }
+        Ok(spec)
+    }
+
+    /// Writes the spec, in the current format, to its file in `spec_dir`, returning the file's
+    /// path.
+    ///
+    /// # Failures
+    ///
+    /// * The spec is not valid
+    /// * The file cannot be written
+    pub fn to_file<P: AsRef<Path>>(&self, spec_dir: P) -> Result<PathBuf> {
+        let path = spec_dir.as_ref().join(self.file_name());
+        atomic_write(&path, self.to_toml_string()?)?;
+        Ok(path)
+    }
+
+    /// The spec in the current format, as TOML.
+    ///
+    /// # Failures
+    ///
+    /// * The spec is not valid
+    pub fn to_toml_string(&self) -> Result<String> {
+        self.validate()?;
+        toml::to_string(self).map_err(|e| invalid(e.to_string()))
+    }
+
+    /// The name of the spec's file: the package name, with the `spec` extension.
+    pub fn file_name(&self) -> String { format!("{}.{}", self.ident.name, SPEC_FILE_EXT) }
+
+    /// The service group the service runs in.
+    ///
+    /// # Failures
+    ///
+    /// * The group is not a valid service group name
+    pub fn service_group(&self) -> Result<ServiceGroup> {
+        ServiceGroup::new(None, &self.ident.name, &self.group, None)
+    }
+
+    /// Checks that the spec could be run.
+    ///
+    /// # Failures
+    ///
+    /// * The group is not a valid service group name
+    /// * The Builder URL is empty
+    /// * Two binds have the same name
+    pub fn validate(&self) -> Result<()> {
+        self.service_group()
+            .map_err(|_| invalid(format!("invalid group '{}'", self.group)))?;
+        if self.bldr_url.is_empty() {
+            return Err(invalid("empty bldr_url".to_string()));
+        }
+        let mut names = HashSet::new();
+        for bind in &self.binds {
+            if !names.insert(bind.name()) {
+                return Err(invalid(format!("bind '{}' is given more than once", bind.name())));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ServiceSpec {
+    type Err = Error;
+
+    /// Parses a spec, upgrading it from an older format if need be.
+    fn from_str(raw: &str) -> result::Result<Self, Self::Err> {
+        let mut table: Table = toml::from_str(raw).map_err(Error::ConfigFileSyntax)?;
+        upgrade(&mut table)?;
+        let spec: Self = Value::Table(table).try_into()
+                                            .map_err(|e| invalid(e.to_string()))?;
+        spec.validate()?;
+        Ok(spec)
+    }
+}
+
+/// Brings a spec in an older format up to date, leaving a current one as it is.
+fn upgrade(table: &mut Table) -> Result<()> {
+    for key in OBSOLETE_KEYS {
+        table.remove(*key);
+    }
+    if let Some(Value::Table(interval)) = table.get("health_check_interval").cloned() {
+        let secs = match interval.get("secs") {
+            Some(Value::Integer(secs)) if *secs >= 0 => *secs,
+            _ => return Err(invalid("invalid health_check_interval".to_string())),
+        };
+        table.insert("health_check_interval".to_string(), Value::Integer(secs));
+    }
+    Ok(())
+}
+
+fn invalid(reason: String) -> Error { Error::InvalidServiceSpec(reason) }
+
+/// Writes a `HealthCheckInterval` as a whole number of seconds.
+mod interval_secs {
+    use super::*;
+    use serde::{Deserialize,
+                Deserializer,
+                Serializer};
+
+    pub fn serialize<S>(interval: &HealthCheckInterval, s: S) -> result::Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        s.serialize_u64(interval.as_ref().as_secs())
+    }
+
+    pub fn deserialize<'de, D>(d: D) -> result::Result<HealthCheckInterval, D::Error>
+        where D: Deserializer<'de>
+    {
+        u64::deserialize(d).map(|secs| Duration::from_secs(secs).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::Builder;
+
+    #[test]
+    fn minimal_spec_takes_defaults() {
+        let spec: ServiceSpec = "ident = \"core/redis\"".parse().unwrap();
+
+        assert_eq!(ServiceSpec::new(PackageIdent::from_str("core/redis").unwrap()),
+                   spec);
+        assert_eq!("redis.spec", spec.file_name());
+        assert_eq!("redis.default", spec.service_group().unwrap().as_ref());
+    }
+
+    #[test]
+    fn spec_round_trips_through_a_file() {
+        let dir = Builder::new().prefix("specs").tempdir().unwrap();
+        let mut spec = ServiceSpec::new(PackageIdent::from_str("core/redis/4.0.14").unwrap());
+        spec.group = "prod".to_string();
+        spec.topology = Topology::Leader;
+        spec.update_strategy = UpdateStrategy::Rolling;
+        spec.binds = vec!["backend:redis.default".parse().unwrap()];
+        spec.desired_state = DesiredState::Down;
+        spec.health_check_interval = Duration::from_secs(10).into();
+
+        let path = spec.to_file(dir.path()).unwrap();
+
+        assert_eq!(dir.path().join("redis.spec"), path);
+        let raw = fs::read_to_string(&path).unwrap();
+        assert!(!raw.contains("svc_encrypted_password"));
+        assert!(raw.contains("health_check_interval = 10\n"));
+        assert_eq!(spec, ServiceSpec::from_file(&path).unwrap());
+
+        spec.svc_encrypted_password = Some("c2VjcmV0".to_string());
+        let path = spec.to_file(dir.path()).unwrap();
+        assert_eq!(spec, ServiceSpec::from_file(&path).unwrap());
+
+        let misnamed = dir.path().join("cache.spec");
+        fs::copy(&path, &misnamed).unwrap();
+        match ServiceSpec::from_file(&misnamed) {
+            Err(Error::InvalidServiceSpec(_)) => {}
+            other => panic!("Expected InvalidServiceSpec, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn older_specs_are_upgraded() {
+        let raw = r#"
+ident = "core/redis"
+group = "default"
+start_style = "persistent"
+topology = "standalone"
+update_strategy = "at-once"
+binds = []
+desired_state = "up"
+
+[health_check_interval]
+secs = 45
+nanos = 0
+"#;
+        let spec: ServiceSpec = raw.parse().unwrap();
+
+        assert_eq!(UpdateStrategy::AtOnce, spec.update_strategy);
+        assert_eq!(spec.health_check_interval, Duration::from_secs(45));
+        assert!(spec.to_toml_string()
+                    .unwrap()
+                    .contains("health_check_interval = 45\n"));
+    }
+
+    #[test]
+    fn invalid_specs_are_refused() {
+        for raw in &["ident = \"core/redis\"\nversion = 2",
+                     "ident = \"core/redis\"\ngroup = \"a.b\"",
+                     "ident = \"core/redis\"\nbinds = [\"db:pg.default\", \"db:mysql.default\"]",
+                     "ident = \"core/redis\"\nhealth_check_interval = { secs = -1 }",
+                     "group = \"default\""]
+        {
+            match raw.parse::<ServiceSpec>() {
+                Err(Error::InvalidServiceSpec(_)) => {}
+                other => panic!("Expected InvalidServiceSpec for {:?}, got {:?}", raw, other),
+            }
+        }
+    }
+}
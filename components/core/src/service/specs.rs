@@ -0,0 +1,277 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A directory of service spec files as the desired state of a Supervisor, and the decisions
+//! needed to bring the services actually running in line with it.
+//!
+//! A frontend reads the directory with `read_spec_dir`, or keeps up with it through a
+//! `SpecWatcher`, and hands the desired specs along with the specs of the services it is running
+//! to `reconcile`, which says what to load, reload and unload.
+
+use std::{collections::BTreeMap,
+          fs,
+          path::{Path,
+                 PathBuf},
+          time::Duration};
+
+use super::spec::{DesiredState,
+                  ServiceSpec,
+                  SPEC_FILE_EXT};
+use crate::{error::{Error,
+                    Result},
+            fs::watch::{DebouncedWatcher,
+                        WatchOptions}};
+
+/// Specs keyed by service name.
+pub type Specs = BTreeMap<String, ServiceSpec>;
+
+/// What a spec directory holds.
+#[derive(Debug, Default)]
+pub struct SpecDir {
+    /// The valid specs.
+    pub specs:   Specs,
+    /// The spec files which could not be read, with why.
+    pub invalid: Vec<(PathBuf, Error)>,
+}
+
+/// Reads every `*.spec` file in `dir`. A file which cannot be read or is not a valid spec is
+/// set aside in `invalid` rather than failing the whole directory, so one bad file does not stop
+/// the other services from being managed.
+///
+/// # Failures
+///
+/// * The directory cannot be read
+pub fn read_spec_dir(dir: &Path) -> Result<SpecDir> {
+    let mut spec_dir = SpecDir::default();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(SPEC_FILE_EXT) || !path.is_file() {
+            continue;
+        }
+        match ServiceSpec::from_file(&path) {
+            Ok(spec) => {
+                spec_dir.specs.insert(spec.ident.name.clone(), spec);
+            }
+            Err(e) => {
+                debug!("Skipping spec file {}: {}", path.display(), e);
+                spec_dir.invalid.push((path, e));
+            }
+        }
+    }
+    Ok(spec_dir)
+}
+
+/// How a spec changed between two reads of a spec directory.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SpecChange {
+    Added(ServiceSpec),
+    /// The old spec and the new, boxed to keep the variant as small as the others.
+    Changed {
+        old: Box<ServiceSpec>,
+        new: Box<ServiceSpec>,
+    },
+    Removed(ServiceSpec),
+}
+
+/// The changes going from the specs `old` to `new`, in order of service name.
+pub fn spec_changes(old: &Specs, new: &Specs) -> Vec<SpecChange> {
+    let mut changes = Vec::new();
+    for (name, spec) in old {
+        match new.get(name) {
+            Some(new_spec) if new_spec != spec => {
+                changes.push(SpecChange::Changed { old: Box::new(spec.clone()),
+                                                   new: Box::new(new_spec.clone()), })
+            }
+            Some(_) => {}
+            None => changes.push(SpecChange::Removed(spec.clone())),
+        }
+    }
+    for (name, spec) in new {
+        if !old.contains_key(name) {
+            changes.push(SpecChange::Added(spec.clone()));
+        }
+    }
+    changes
+}
+
+/// Keeps up with the specs in a directory as files are added, changed and removed, using a
+/// `DebouncedWatcher` so that a burst of writes is read once it settles.
+#[derive(Debug)]
+pub struct SpecWatcher {
+    dir:     PathBuf,
+    watcher: DebouncedWatcher,
+    current: SpecDir,
+}
+
+impl SpecWatcher {
+    /// Reads the specs in `dir` and starts watching it.
+    ///
+    /// # Failures
+    ///
+    /// * The directory does not exist or cannot be read
+    /// * The directory cannot be watched
+    pub fn new<P: Into<PathBuf>>(dir: P, opts: WatchOptions) -> Result<Self> {
+        let dir = dir.into();
+        let watcher = DebouncedWatcher::new(vec![dir.clone()], opts)?;
+        let current = read_spec_dir(&dir)?;
+        Ok(SpecWatcher { dir,
+                         watcher,
+                         current })
+    }
+
+    /// The specs as of the last read.
+    pub fn specs(&self) -> &Specs { &self.current.specs }
+
+    /// The spec files which could not be read as of the last read.
+    pub fn invalid(&self) -> &[(PathBuf, Error)] { &self.current.invalid }
+
+    /// Waits up to `timeout` for the directory to change, returning how the specs changed. The
+    /// changes are empty if the directory did not change in time, or changed in a way which left
+    /// every valid spec as it was.
+    ///
+    /// # Failures
+    ///
+    /// * The directory cannot be read
+    pub fn wait(&mut self, timeout: Duration) -> Result<Vec<SpecChange>> {
+        if self.watcher.events().recv_timeout(timeout).is_err() {
+            return Ok(Vec::new());
+        }
+        // Changes which settled in the meantime are covered by reading the directory now
+        while self.watcher.events().try_recv().is_ok() {}
+        let next = read_spec_dir(&self.dir)?;
+        let changes = spec_changes(&self.current.specs, &next.specs);
+        self.current = next;
+        Ok(changes)
+    }
+}
+
+/// What to do to bring the running services in line with the desired specs.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Reconciliation {
+    /// Services to start, which are desired up and not running.
+    pub load:   Vec<ServiceSpec>,
+    /// Services to restart with a new spec, which are running with a different one.
+    pub reload: Vec<ServiceSpec>,
+    /// Services to stop, by name, which are running and either have no spec or are desired
+    /// down.
+    pub unload: Vec<String>,
+}
+
+impl Reconciliation {
+    /// Whether the running services already match the desired specs.
+    pub fn is_empty(&self) -> bool {
+        self.load.is_empty() && self.reload.is_empty() && self.unload.is_empty()
+    }
+}
+
+/// Compares the `desired` specs with those of the services `running`, each keyed by service
+/// name, and returns what needs doing, with each list in order of service name.
+pub fn reconcile(desired: &Specs, running: &Specs) -> Reconciliation {
+    let mut reconciliation = Reconciliation::default();
+    for (name, spec) in desired {
+        if spec.desired_state == DesiredState::Down {
+            continue;
+        }
+        match running.get(name) {
+            None => reconciliation.load.push(spec.clone()),
+            Some(current) if current != spec => reconciliation.reload.push(spec.clone()),
+            Some(_) => {}
+        }
+    }
+    for name in running.keys() {
+        let wanted = desired.get(name)
+                            .map_or(false, |s| s.desired_state == DesiredState::Up);
+        if !wanted {
+            reconciliation.unload.push(name.clone());
+        }
+    }
+    reconciliation
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::PackageIdent;
+    use std::str::FromStr;
+    use tempfile::Builder;
+
+    fn spec(ident: &str) -> ServiceSpec { ServiceSpec::new(PackageIdent::from_str(ident).unwrap()) }
+
+    fn specs(list: &[ServiceSpec]) -> Specs {
+        list.iter()
+            .map(|s| (s.ident.name.clone(), s.clone()))
+            .collect()
+    }
+
+    fn options() -> WatchOptions {
+        WatchOptions { debounce:      Duration::from_millis(100),
+                       poll_interval: Duration::from_millis(50),
+                       force_polling: false, }
+    }
+
+    #[test]
+    fn read_spec_dir_sets_aside_invalid_files() {
+        let dir = Builder::new().prefix("specs").tempdir().unwrap();
+        spec("core/redis").to_file(dir.path()).unwrap();
+        fs::write(dir.path().join("broken.spec"), "ident = ").unwrap();
+        fs::write(dir.path().join("README"), "not a spec").unwrap();
+
+        let spec_dir = read_spec_dir(dir.path()).unwrap();
+
+        assert_eq!(specs(&[spec("core/redis")]), spec_dir.specs);
+        assert_eq!(1, spec_dir.invalid.len());
+        assert_eq!(dir.path().join("broken.spec"), spec_dir.invalid[0].0);
+    }
+
+    #[test]
+    fn watcher_reports_spec_changes() {
+        let dir = Builder::new().prefix("specs").tempdir().unwrap();
+        spec("core/redis").to_file(dir.path()).unwrap();
+        let mut watcher = SpecWatcher::new(dir.path(), options()).unwrap();
+        assert_eq!(&specs(&[spec("core/redis")]), watcher.specs());
+
+        let mut redis = spec("core/redis");
+        redis.group = "prod".to_string();
+        redis.to_file(dir.path()).unwrap();
+        assert_eq!(vec![SpecChange::Changed { old: Box::new(spec("core/redis")),
+                                              new: Box::new(redis.clone()), }],
+                   watcher.wait(Duration::from_secs(5)).unwrap());
+
+        fs::remove_file(dir.path().join("redis.spec")).unwrap();
+        assert_eq!(vec![SpecChange::Removed(redis)],
+                   watcher.wait(Duration::from_secs(5)).unwrap());
+        assert!(watcher.specs().is_empty());
+    }
+
+    #[test]
+    fn reconcile_loads_reloads_and_unloads() {
+        let mut nginx = spec("core/nginx");
+        nginx.group = "prod".to_string();
+        let mut postgres = spec("core/postgresql");
+        postgres.desired_state = DesiredState::Down;
+        let desired = specs(&[spec("core/redis"), nginx.clone(), postgres]);
+        let running = specs(&[spec("core/nginx"),
+                              spec("core/postgresql"),
+                              spec("core/memcached")]);
+
+        let reconciliation = reconcile(&desired, &running);
+
+        assert_eq!(vec![spec("core/redis")], reconciliation.load);
+        assert_eq!(vec![nginx.clone()], reconciliation.reload);
+        assert_eq!(vec!["memcached".to_string(), "postgresql".to_string()],
+                   reconciliation.unload);
+        let reconciled = specs(&[spec("core/redis"), nginx]);
+        assert!(reconcile(&desired, &reconciled).is_empty());
+    }
+}
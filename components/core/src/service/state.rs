@@ -0,0 +1,285 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The lifecycle of a supervised service.
+//!
+//! A service moves between `ServiceState`s in response to `Transition`s: requests to start or
+//! stop it, and observations of its process starting and exiting. Only the transitions shown
+//! below are valid; anything else is an error, which keeps the Supervisor, the Launcher, and
+//! anything displaying service status in agreement about what each state means.
+//!
+//! ```text
+//!           Start             Started
+//!   Down ──────────> Starting ──────────> Up
+//!                     ^    │               │
+//!               Retry │    │ Exited        │ Exited
+//!                     │    v               │
+//!                   Restarting <───────────┘
+//! ```
+//!
+//! A `Backoff` decides how long to wait before each restart. Once it gives up, an exit leads to
+//! `Failed` instead of `Restarting`, and the service stays failed until it is started again. A
+//! service whose process may be running can be asked to `Stop`, and passes through `Stopping`
+//! until the process has exited.
+
+use std::{fmt,
+          time::Duration};
+
+use serde_derive::{Deserialize,
+                   Serialize};
+
+use crate::error::{Error,
+                   Result};
+
+/// Decides how long to wait before restarting a service which has exited.
+pub trait Backoff {
+    /// Returns the delay before restarting a service which has now failed `failures` times in a
+    /// row, or `None` to stop restarting it and mark it failed.
+    fn delay(&self, failures: u32) -> Option<Duration>;
+}
+
+/// A fixed delay between restarts, which never gives up.
+impl Backoff for Duration {
+    fn delay(&self, _failures: u32) -> Option<Duration> { Some(*self) }
+}
+
+/// The lifecycle state of a supervised service.
+///
+/// `failures` counts the consecutive times the service has exited without being asked to; it
+/// is reset once the service has been up long enough to be considered stable.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ServiceState {
+    /// Not running, and not expected to be.
+    Down,
+    /// The service's process is being started.
+    Starting { failures: u32 },
+    /// The service's process is running.
+    Up { pid: u32, failures: u32 },
+    /// The service has been asked to stop and its process has not yet exited.
+    Stopping,
+    /// The service exited and will be started again once `delay` has passed.
+    Restarting { failures: u32, delay: Duration },
+    /// The service exited too many times and will not be restarted until it is started again.
+    Failed { failures: u32 },
+}
+
+impl Default for ServiceState {
+    fn default() -> Self { ServiceState::Down }
+}
+
+impl fmt::Display for ServiceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            ServiceState::Down => "down",
+            ServiceState::Starting { .. } => "starting",
+            ServiceState::Up { .. } => "up",
+            ServiceState::Stopping => "stopping",
+            ServiceState::Restarting { .. } => "restarting",
+            ServiceState::Failed { .. } => "failed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Something which moves a service between states.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Transition {
+    /// Start a service which is down or has failed.
+    Start,
+    /// The service's process was started with the given process ID.
+    Started(u32),
+    /// The service has been up long enough that earlier failures no longer count against it.
+    Stable,
+    /// The service's process exited, or could not be started.
+    Exited,
+    /// The restart delay has passed.
+    Retry,
+    /// Stop the service.
+    Stop,
+}
+
+impl fmt::Display for Transition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            Transition::Start => "start",
+            Transition::Started(_) => "started",
+            Transition::Stable => "stable",
+            Transition::Exited => "exited",
+            Transition::Retry => "retry",
+            Transition::Stop => "stop",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl ServiceState {
+    /// Returns the state reached by applying `transition`, consulting `backoff` when the
+    /// service exits unexpectedly.
+    ///
+    /// # Failures
+    ///
+    /// * `transition` is not valid from this state
+    pub fn apply(self, transition: Transition, backoff: &dyn Backoff) -> Result<Self> {
+        let next = match (self, transition) {
+            (ServiceState::Down, Transition::Start)
+            | (ServiceState::Failed { .. }, Transition::Start) => {
+                ServiceState::Starting { failures: 0 }
+            }
+            (ServiceState::Starting { failures }, Transition::Started(pid)) => {
+                ServiceState::Up { pid, failures }
+            }
+            (ServiceState::Up { pid, .. }, Transition::Stable) => {
+                ServiceState::Up { pid, failures: 0 }
+            }
+            (ServiceState::Starting { failures }, Transition::Exited)
+            | (ServiceState::Up { failures, .. }, Transition::Exited) => {
+                let failures = failures.saturating_add(1);
+                match backoff.delay(failures) {
+                    Some(delay) => ServiceState::Restarting { failures, delay },
+                    None => ServiceState::Failed { failures },
+                }
+            }
+            (ServiceState::Restarting { failures, .. }, Transition::Retry) => {
+                ServiceState::Starting { failures }
+            }
+            (ServiceState::Starting { .. }, Transition::Stop)
+            | (ServiceState::Up { .. }, Transition::Stop) => ServiceState::Stopping,
+            (ServiceState::Stopping, Transition::Exited)
+            | (ServiceState::Restarting { .. }, Transition::Stop)
+            | (ServiceState::Failed { .. }, Transition::Stop) => ServiceState::Down,
+            (state, transition) => {
+                return Err(Error::InvalidStateTransition(state.to_string(),
+                                                         transition.to_string()));
+            }
+        };
+        Ok(next)
+    }
+
+    /// Whether the service's process may be running in this state.
+    pub fn is_running(self) -> bool {
+        match self {
+            ServiceState::Starting { .. } | ServiceState::Up { .. } | ServiceState::Stopping => {
+                true
+            }
+            ServiceState::Down | ServiceState::Restarting { .. } | ServiceState::Failed { .. } => {
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Restarts after a second, giving up on the third consecutive failure.
+    struct GiveUpAfterTwo;
+
+    impl Backoff for GiveUpAfterTwo {
+        fn delay(&self, failures: u32) -> Option<Duration> {
+            if failures < 3 {
+                Some(Duration::from_secs(1))
+            } else {
+                None
+            }
+        }
+    }
+
+    fn apply_all(transitions: &[Transition]) -> Result<ServiceState> {
+        transitions.iter()
+                   .try_fold(ServiceState::default(), |state, &t| {
+                       state.apply(t, &GiveUpAfterTwo)
+                   })
+    }
+
+    #[test]
+    fn start_and_stop() {
+        let state = apply_all(&[Transition::Start, Transition::Started(42)]).unwrap();
+        assert_eq!(ServiceState::Up { pid:      42,
+                                      failures: 0, },
+                   state);
+        assert!(state.is_running());
+
+        let state = state.apply(Transition::Stop, &GiveUpAfterTwo).unwrap();
+        assert_eq!(ServiceState::Stopping, state);
+        let state = state.apply(Transition::Exited, &GiveUpAfterTwo).unwrap();
+        assert_eq!(ServiceState::Down, state);
+    }
+
+    #[test]
+    fn exits_restart_until_backoff_gives_up() {
+        let state = apply_all(&[Transition::Start,
+                                Transition::Started(1),
+                                Transition::Exited,
+                                Transition::Retry,
+                                Transition::Started(2),
+                                Transition::Exited]).unwrap();
+        assert_eq!(ServiceState::Restarting { failures: 2,
+                                              delay:    Duration::from_secs(1), },
+                   state);
+
+        let state = apply_all(&[Transition::Start,
+                                Transition::Exited,
+                                Transition::Retry,
+                                Transition::Exited,
+                                Transition::Retry,
+                                Transition::Exited]).unwrap();
+        assert_eq!(ServiceState::Failed { failures: 3 }, state);
+        assert!(!state.is_running());
+
+        let state = state.apply(Transition::Start, &GiveUpAfterTwo).unwrap();
+        assert_eq!(ServiceState::Starting { failures: 0 }, state);
+    }
+
+    #[test]
+    fn stable_resets_failures() {
+        let state = apply_all(&[Transition::Start,
+                                Transition::Exited,
+                                Transition::Retry,
+                                Transition::Started(7),
+                                Transition::Stable]).unwrap();
+        assert_eq!(ServiceState::Up { pid:      7,
+                                      failures: 0, },
+                   state);
+    }
+
+    #[test]
+    fn invalid_transitions() {
+        for transitions in &[vec![Transition::Stop],
+                             vec![Transition::Started(1)],
+                             vec![Transition::Start, Transition::Start],
+                             vec![Transition::Start, Transition::Retry]]
+        {
+            match apply_all(transitions) {
+                Err(Error::InvalidStateTransition(..)) => {}
+                other => {
+                    panic!("Expected InvalidStateTransition for {:?}, got {:?}",
+                           transitions, other)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn serializes_with_state_tag() {
+        let state = ServiceState::Up { pid:      42,
+                                       failures: 1, };
+
+        let json = serde_json::to_string(&state).unwrap();
+
+        assert_eq!(r#"{"state":"up","pid":42,"failures":1}"#, json);
+        assert_eq!(state, serde_json::from_str(&json).unwrap());
+    }
+}
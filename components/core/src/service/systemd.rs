@@ -0,0 +1,225 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Generation of systemd service units for installed packages.
+
+use std::{fmt::Write,
+          fs,
+          path::{Path,
+                 PathBuf}};
+
+use crate::{error::Result,
+            fs::atomic_write,
+            package::{install::{lossy_environment,
+                                EnvironmentOptions},
+                      PackageInstall},
+            service::program::Program};
+
+/// The directory in which locally administered unit files are installed.
+pub const SYSTEMD_UNIT_DIR: &str = "/etc/systemd/system";
+
+/// Options for `unit_for`.
+#[derive(Clone, Debug)]
+pub struct UnitOptions {
+    /// What the unit's `ExecStart=` runs.
+    pub exec_start:   Program,
+    /// The unit's description. Defaults to the package identifier.
+    pub description:  Option<String>,
+    /// The value of the unit's `Restart=` setting.
+    pub restart:      String,
+    /// The target which wants the unit when it is enabled.
+    pub wanted_by:    String,
+    /// The name of the service the unit runs, which names its directory under `/hab/svc`.
+    /// Defaults to the package name.
+    pub service_name: Option<String>,
+    /// Where the service's hooks are rendered. Defaults to the service's hooks directory under
+    /// the package's filesystem root.
+    pub hooks_dir:    Option<PathBuf>,
+}
+
+impl Default for UnitOptions {
+    fn default() -> Self {
+        UnitOptions { exec_start:   Program::RunHook,
+                      description:  None,
+                      restart:      "on-failure".to_string(),
+                      wanted_by:    "multi-user.target".to_string(),
+                      service_name: None,
+                      hooks_dir:    None, }
+    }
+}
+
+/// Returns the name of the unit for a package, such as `hab-core-redis.service`.
+pub fn unit_name(pkg_install: &PackageInstall) -> String {
+    let ident = pkg_install.ident();
+    format!("hab-{}-{}.service", ident.origin, ident.name)
+}
+
+/// Generates the contents of a systemd service unit which runs `pkg_install`.
+///
+/// The service runs as the package's `SVC_USER` and `SVC_GROUP`, if set, with the package's
+/// runtime environment. With `Program::RunHook`, the package's run hook is rendered into the
+/// service's hooks directory first, and the unit runs the rendered hook.
+///
+/// # Failures
+///
+/// * A metafile exists but cannot be properly parsed
+/// * The run hook is needed but cannot be rendered
+pub fn unit_for(pkg_install: &PackageInstall, opts: &UnitOptions) -> Result<String> {
+    let args = opts.exec_start.arguments(pkg_install,
+                                          opts.service_name.as_ref(),
+                                          opts.hooks_dir.as_ref())?;
+    let exec_start: Vec<_> = args.iter().map(|arg| escape_arg(arg)).collect();
+    let description = match opts.description {
+        Some(ref description) => description.clone(),
+        None => pkg_install.ident().to_string(),
+    };
+
+    let env = pkg_install.environment_for_command(EnvironmentOptions::default())?;
+    let mut env: Vec<_> = lossy_environment(env).into_iter().collect();
+    env.sort();
+
+    // Writing to a `String` cannot fail
+    let mut unit = String::new();
+    writeln!(unit, "[Unit]").unwrap();
+    writeln!(unit, "Description={}", description).unwrap();
+    writeln!(unit, "After=network.target").unwrap();
+    writeln!(unit).unwrap();
+    writeln!(unit, "[Service]").unwrap();
+    writeln!(unit, "ExecStart={}", exec_start.join(" ")).unwrap();
+    writeln!(unit, "Restart={}", opts.restart).unwrap();
+    if let Some(user) = pkg_install.svc_user()? {
+        writeln!(unit, "User={}", user.trim()).unwrap();
+    }
+    if let Some(group) = pkg_install.svc_group()? {
+        writeln!(unit, "Group={}", group.trim()).unwrap();
+    }
+    for (key, value) in env {
+        writeln!(unit,
+                 "Environment={}",
+                 escape_arg(&format!("{}={}", key, value))).unwrap();
+    }
+    writeln!(unit).unwrap();
+    writeln!(unit, "[Install]").unwrap();
+    writeln!(unit, "WantedBy={}", opts.wanted_by).unwrap();
+    Ok(unit)
+}
+
+/// Writes `unit` as the unit named `name` into `unit_dir`, or `SYSTEMD_UNIT_DIR` if `None`,
+/// returning the path of the unit file. The unit must still be enabled with `systemctl`.
+///
+/// # Failures
+///
+/// * The unit directory cannot be created or the unit cannot be written
+pub fn install_unit(name: &str, unit: &str, unit_dir: Option<&Path>) -> Result<PathBuf> {
+    let unit_dir = unit_dir.unwrap_or_else(|| Path::new(SYSTEMD_UNIT_DIR));
+    fs::create_dir_all(unit_dir)?;
+    let path = unit_dir.join(name);
+    atomic_write(&path, unit)?;
+    Ok(path)
+}
+
+/// Quotes a value for use in a unit file if necessary. Specifiers (`%`) are always escaped, as
+/// systemd would otherwise expand them, and so are line breaks, which would end the setting.
+fn escape_arg(value: &str) -> String {
+    let escaped = value.replace('%', "%%");
+    if escaped.chars()
+              .any(|c| c.is_whitespace() || c == '"' || c == '\\' || c == '\'')
+    {
+        format!("\"{}\"",
+                escaped.replace('\\', "\\\\")
+                       .replace('"', "\\\"")
+                       .replace('\n', "\\n")
+                       .replace('\r', "\\r"))
+    } else {
+        escaped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::package::{metadata::MetaFile,
+                         test_support::testing_package_install};
+    use tempfile::Builder;
+
+    #[test]
+    fn unit_for_run_hook() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        let path = pkg_install.installed_path();
+        fs::write(path.join(MetaFile::SvcUser.to_string()), "hab\n").unwrap();
+        fs::write(path.join(MetaFile::RuntimeEnvironment.to_string()),
+                  "GREETING=hello world\nPCT=100%\n").unwrap();
+        fs::create_dir_all(path.join("hooks")).unwrap();
+        fs::write(path.join("hooks").join("run"), "exec {{pkg.name}}\n").unwrap();
+        let hooks_dir = fs_root.path().join("hab/svc/pathy/hooks");
+
+        let unit = unit_for(&pkg_install, &UnitOptions::default()).unwrap();
+
+        assert!(unit.contains(&format!("Description={}\n", pkg_install.ident())));
+        assert!(unit.contains(&format!("ExecStart={}\n", hooks_dir.join("run").display())));
+        assert_eq!("exec pathy\n",
+                   fs::read_to_string(hooks_dir.join("run")).unwrap());
+        assert!(unit.contains("User=hab\n"));
+        assert!(!unit.contains("Group="));
+        assert!(unit.contains("Environment=\"GREETING=hello world\"\n"));
+        assert!(unit.contains("Environment=PCT=100%%\n"));
+        assert!(unit.contains("WantedBy=multi-user.target\n"));
+    }
+
+    #[test]
+    fn unit_for_supervisor() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        let opts = UnitOptions { exec_start: Program::Supervisor(PathBuf::from("/bin/hab")),
+                                 ..Default::default() };
+
+        let unit = unit_for(&pkg_install, &opts).unwrap();
+
+        assert!(unit.contains(&format!("ExecStart=/bin/hab sup run {}\n", pkg_install.ident())));
+    }
+
+    #[test]
+    fn unit_for_arguments() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let pkg_install = testing_package_install("acme/pathy", fs_root.path());
+        let args = vec!["/bin/echo".to_string(), "two\nlines".to_string()];
+        let opts = UnitOptions { exec_start: Program::Arguments(args),
+                                 ..Default::default() };
+
+        let unit = unit_for(&pkg_install, &opts).unwrap();
+
+        assert!(unit.contains("ExecStart=/bin/echo \"two\\nlines\"\n"));
+    }
+
+    #[test]
+    fn escape_arg_quotes_and_escapes() {
+        assert_eq!("plain", escape_arg("plain"));
+        assert_eq!("100%%", escape_arg("100%"));
+        assert_eq!(r#""say \"hi\"""#, escape_arg(r#"say "hi""#));
+        assert_eq!(r#""a\\b""#, escape_arg(r"a\b"));
+        assert_eq!(r#""K=one\ntwo\r""#, escape_arg("K=one\ntwo\r"));
+    }
+
+    #[test]
+    fn install_unit_writes_file() {
+        let unit_dir = Builder::new().prefix("units").tempdir().unwrap();
+
+        let path =
+            install_unit("hab-acme-pathy.service", "[Unit]\n", Some(unit_dir.path())).unwrap();
+
+        assert_eq!(unit_dir.path().join("hab-acme-pathy.service"), path);
+        assert_eq!("[Unit]\n", fs::read_to_string(path).unwrap());
+    }
+}
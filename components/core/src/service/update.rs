@@ -0,0 +1,288 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! How a service group picks up new releases of its package.
+
+use std::{collections::HashSet,
+          fmt,
+          result,
+          str::FromStr};
+
+use serde_derive::{Deserialize,
+                   Serialize};
+
+use crate::error::{Error,
+                   Result};
+
+/// How the members of a service group are updated when a new release is available.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdateStrategy {
+    /// Never update automatically.
+    None,
+    /// Every member updates as soon as it sees the new release.
+    AtOnce,
+    /// Members update one at a time, in the order given by a `Rollout`.
+    Rolling,
+}
+
+impl UpdateStrategy {
+    /// Every update strategy, for listing the values a CLI accepts.
+    pub fn variants() -> &'static [Self] {
+        &[UpdateStrategy::None,
+          UpdateStrategy::AtOnce,
+          UpdateStrategy::Rolling]
+    }
+}
+
+impl Default for UpdateStrategy {
+    fn default() -> Self { UpdateStrategy::None }
+}
+
+impl fmt::Display for UpdateStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match *self {
+            UpdateStrategy::None => "none",
+            UpdateStrategy::AtOnce => "at-once",
+            UpdateStrategy::Rolling => "rolling",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+impl FromStr for UpdateStrategy {
+    type Err = Error;
+
+    fn from_str(value: &str) -> result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_ref() {
+            "none" => Ok(UpdateStrategy::None),
+            "at-once" => Ok(UpdateStrategy::AtOnce),
+            "rolling" => Ok(UpdateStrategy::Rolling),
+            _ => Err(Error::BadUpdateStrategy(value.to_string())),
+        }
+    }
+}
+
+/// Which release a service group updates to.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdateCondition {
+    /// The latest release in the channel, which only ever moves forward.
+    Latest,
+    /// Whatever release the channel holds, including an older one if the channel was rolled
+    /// back or demoted.
+    TrackChannel,
+}
+
+impl Default for UpdateCondition {
+    fn default() -> Self { UpdateCondition::Latest }
+}
+
+impl fmt::Display for UpdateCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match *self {
+            UpdateCondition::Latest => "latest",
+            UpdateCondition::TrackChannel => "track-channel",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+impl FromStr for UpdateCondition {
+    type Err = Error;
+
+    fn from_str(value: &str) -> result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_ref() {
+            "latest" => Ok(UpdateCondition::Latest),
+            "track-channel" => Ok(UpdateCondition::TrackChannel),
+            _ => Err(Error::BadUpdateCondition(value.to_string())),
+        }
+    }
+}
+
+impl UpdateCondition {
+    /// Every update condition, for listing the values a CLI accepts.
+    pub fn variants() -> &'static [Self] {
+        &[UpdateCondition::Latest, UpdateCondition::TrackChannel]
+    }
+
+    /// Checks that this condition makes sense with `strategy`. Tracking a channel only has an
+    /// effect when the service is updated automatically.
+    ///
+    /// # Failures
+    ///
+    /// * The condition is `TrackChannel` and the strategy is `None`
+    pub fn validate(self, strategy: UpdateStrategy) -> Result<()> {
+        match (self, strategy) {
+            (UpdateCondition::TrackChannel, UpdateStrategy::None) => {
+                Err(Error::IncompatibleUpdateCondition(self.to_string(), strategy.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The order in which the members of a service group update.
+///
+/// Members are identified by their member IDs. Every member computing a rollout from the same
+/// set of IDs arrives at the same order, so no coordination beyond agreeing on membership is
+/// needed to take turns.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rollout {
+    batches: Vec<Vec<String>>,
+}
+
+impl Rollout {
+    /// Plans the rollout of an update to `members` using `strategy`. Duplicate IDs are ignored.
+    pub fn plan<I, S>(strategy: UpdateStrategy, members: I) -> Self
+        where I: IntoIterator<Item = S>,
+              S: Into<String>
+    {
+        let mut members: Vec<String> = members.into_iter().map(Into::into).collect();
+        members.sort();
+        members.dedup();
+        let batches = match strategy {
+            UpdateStrategy::None => Vec::new(),
+            UpdateStrategy::AtOnce if members.is_empty() => Vec::new(),
+            UpdateStrategy::AtOnce => vec![members],
+            UpdateStrategy::Rolling => members.into_iter().map(|m| vec![m]).collect(),
+        };
+        Rollout { batches }
+    }
+
+    /// The groups of members which update together, in order.
+    pub fn batches(&self) -> &[Vec<String>] { &self.batches }
+
+    /// Returns the first batch containing a member which has not yet updated, or `None` once
+    /// every member has.
+    pub fn next_batch(&self, updated: &HashSet<String>) -> Option<&[String]> {
+        self.batches
+            .iter()
+            .find(|batch| batch.iter().any(|m| !updated.contains(m)))
+            .map(Vec::as_slice)
+    }
+
+    /// Whether it is `member`'s turn to update, given the members which have already updated.
+    pub fn is_turn(&self, member: &str, updated: &HashSet<String>) -> bool {
+        !updated.contains(member)
+        && self.next_batch(updated)
+               .map_or(false, |batch| batch.iter().any(|m| m == member))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn update_strategy_round_trips() {
+        for strategy in &[UpdateStrategy::None,
+                          UpdateStrategy::AtOnce,
+                          UpdateStrategy::Rolling]
+        {
+            assert_eq!(*strategy, strategy.to_string().parse().unwrap());
+            let json = serde_json::to_string(strategy).unwrap();
+            assert_eq!(format!("\"{}\"", strategy), json);
+            assert_eq!(*strategy, serde_json::from_str(&json).unwrap());
+        }
+        assert_eq!(UpdateStrategy::AtOnce, "AT-ONCE".parse().unwrap());
+        match "sometimes".parse::<UpdateStrategy>() {
+            Err(Error::BadUpdateStrategy(ref s)) if s == "sometimes" => {}
+            other => panic!("Expected BadUpdateStrategy, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn update_condition_round_trips() {
+        for condition in &[UpdateCondition::Latest, UpdateCondition::TrackChannel] {
+            assert_eq!(*condition, condition.to_string().parse().unwrap());
+            let json = serde_json::to_string(condition).unwrap();
+            assert_eq!(format!("\"{}\"", condition), json);
+        }
+        assert!("oldest".parse::<UpdateCondition>().is_err());
+    }
+
+    #[test]
+    fn update_condition_validate() {
+        assert!(UpdateCondition::Latest.validate(UpdateStrategy::None)
+                                       .is_ok());
+        assert!(UpdateCondition::TrackChannel.validate(UpdateStrategy::Rolling)
+                                             .is_ok());
+        assert!(UpdateCondition::TrackChannel.validate(UpdateStrategy::None)
+                                             .is_err());
+    }
+
+    #[test]
+    fn rolling_rollout_is_deterministic() {
+        let a = Rollout::plan(UpdateStrategy::Rolling, vec!["c", "a", "b", "a"]);
+        let b = Rollout::plan(UpdateStrategy::Rolling, vec!["b", "c", "a"]);
+
+        assert_eq!(a, b);
+        assert_eq!(&[vec!["a".to_string()],
+                     vec!["b".to_string()],
+                     vec!["c".to_string()]],
+                   a.batches());
+    }
+
+    #[test]
+    fn rolling_rollout_takes_turns() {
+        let rollout = Rollout::plan(UpdateStrategy::Rolling, vec!["a", "b"]);
+        let mut updated = HashSet::new();
+
+        assert!(rollout.is_turn("a", &updated));
+        assert!(!rollout.is_turn("b", &updated));
+        updated.insert("a".to_string());
+        assert!(rollout.is_turn("b", &updated));
+        updated.insert("b".to_string());
+        assert_eq!(None, rollout.next_batch(&updated));
+    }
+
+    #[test]
+    fn at_once_and_none_rollouts() {
+        let updated = HashSet::new();
+
+        let at_once = Rollout::plan(UpdateStrategy::AtOnce, vec!["b", "a"]);
+        assert!(at_once.is_turn("a", &updated) && at_once.is_turn("b", &updated));
+
+        let none = Rollout::plan(UpdateStrategy::None, vec!["a"]);
+        assert!(none.batches().is_empty());
+        assert!(!none.is_turn("a", &updated));
+    }
+
+    #[test]
+    fn update_strategy_variants_lists_every_variant() {
+        // Exhaustive, so that adding a strategy breaks the build until it is positioned here.
+        let position = |value: &UpdateStrategy| {
+            match *value {
+                UpdateStrategy::None => 0,
+                UpdateStrategy::AtOnce => 1,
+                UpdateStrategy::Rolling => 2,
+            }
+        };
+        let positions: Vec<_> = UpdateStrategy::variants().iter().map(position).collect();
+        assert_eq!(vec![0, 1, 2], positions);
+    }
+
+    #[test]
+    fn update_condition_variants_lists_every_variant() {
+        let position = |value: &UpdateCondition| {
+            match *value {
+                UpdateCondition::Latest => 0,
+                UpdateCondition::TrackChannel => 1,
+            }
+        };
+        let positions: Vec<_> = UpdateCondition::variants().iter().map(position).collect();
+        assert_eq!(vec![0, 1], positions);
+    }
+}
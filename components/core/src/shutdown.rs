@@ -0,0 +1,399 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Orderly shutdown of a process's children and background threads.
+//!
+//! A `Coordinator` is told about everything a program needs to stop before it exits: child
+//! processes it spawned, and background tasks it started with `Coordinator::spawn_task`. Once
+//! a shutdown signal arrives, or a `ShutdownHandle` asks for it, everything is stopped in the
+//! reverse of the order it was registered, so that anything started on top of something else is
+//! stopped before it. Each item is given its own time to stop gracefully: a child is sent
+//! `SIGTERM` (on Windows, where there is no equivalent, it is terminated straight away), and a
+//! task has its `StopToken` triggered. Children which outlast their timeout are killed; tasks,
+//! which cannot be, are left behind. What happened to each is returned as a `ShutdownReport`.
+//!
+//! ```no_run
+//! use habitat_core::shutdown::Coordinator;
+//! use std::{process::Command,
+//!           thread,
+//!           time::Duration};
+//!
+//! habitat_core::os::signals::init();
+//! let mut coordinator = Coordinator::new();
+//! let child = Command::new("redis-server").spawn().unwrap();
+//! coordinator.register_child("redis", child, Duration::from_secs(8));
+//! coordinator.spawn_task("gossip", Duration::from_secs(2), |stop| {
+//!                while !stop.is_stopping() {
+//!                    thread::sleep(Duration::from_millis(100));
+//!                }
+//!            })
+//!            .unwrap();
+//!
+//! let reason = coordinator.wait();
+//! let report = coordinator.shutdown(reason);
+//! println!("{}", report);
+//! ```
+
+use std::{fmt,
+          io,
+          process::Child,
+          sync::{atomic::{AtomicBool,
+                          Ordering},
+                 Arc},
+          thread::{self,
+                   JoinHandle},
+          time::{Duration,
+                 Instant}};
+
+use serde_derive::Serialize;
+
+#[cfg(unix)]
+use crate::os::process::{self,
+                         Pid,
+                         Signal};
+use crate::os::signals;
+
+/// How often the state of whatever is being waited on is checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Asks a `Coordinator` to shut down from elsewhere in the program.
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle(Arc<AtomicBool>);
+
+impl ShutdownHandle {
+    pub fn trigger(&self) { self.0.store(true, Ordering::SeqCst) }
+
+    pub fn is_triggered(&self) -> bool { self.0.load(Ordering::SeqCst) }
+}
+
+/// Tells a task started with `Coordinator::spawn_task` when to stop.
+#[derive(Clone, Debug)]
+pub struct StopToken(Arc<AtomicBool>);
+
+impl StopToken {
+    pub fn is_stopping(&self) -> bool { self.0.load(Ordering::SeqCst) }
+}
+
+/// Why a shutdown happened.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum ShutdownReason {
+    /// The process received a shutdown signal, such as `SIGTERM` or Ctrl-C.
+    Signal,
+    /// A `ShutdownHandle` asked for it.
+    Requested,
+}
+
+/// What happened to one item during a shutdown.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum Outcome {
+    /// The child exited by itself, with this exit code if it has one.
+    Exited(Option<i32>),
+    /// The task finished once asked to stop.
+    Stopped,
+    /// The child did not exit in time and was killed.
+    Killed,
+    /// The task did not finish in time, and was left running.
+    TimedOut,
+    /// The task panicked.
+    Panicked,
+    /// The item could not be stopped.
+    Failed(String),
+}
+
+impl Outcome {
+    /// Whether the item stopped by itself, as asked.
+    pub fn is_clean(&self) -> bool {
+        match *self {
+            Outcome::Exited(_) | Outcome::Stopped => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Outcome::Exited(Some(code)) => write!(f, "exited with code {}", code),
+            Outcome::Exited(None) => write!(f, "exited"),
+            Outcome::Stopped => write!(f, "stopped"),
+            Outcome::Killed => write!(f, "killed after timing out"),
+            Outcome::TimedOut => write!(f, "timed out"),
+            Outcome::Panicked => write!(f, "panicked"),
+            Outcome::Failed(ref e) => write!(f, "failed to stop: {}", e),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum ItemKind {
+    Child,
+    Task,
+}
+
+/// What happened to one item, in the order items were stopped.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ItemReport {
+    pub name:       String,
+    pub kind:       ItemKind,
+    pub outcome:    Outcome,
+    /// How long the item took to stop, or to be given up on.
+    pub elapsed_ms: u64,
+}
+
+/// The record of a shutdown.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ShutdownReport {
+    pub reason:     ShutdownReason,
+    pub items:      Vec<ItemReport>,
+    pub elapsed_ms: u64,
+}
+
+impl ShutdownReport {
+    /// Whether everything stopped by itself.
+    pub fn is_clean(&self) -> bool { self.items.iter().all(|i| i.outcome.is_clean()) }
+}
+
+impl fmt::Display for ShutdownReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Shutdown ({:?}) took {}ms", self.reason, self.elapsed_ms)?;
+        for item in &self.items {
+            writeln!(f,
+                     "  {} ({:?}): {} after {}ms",
+                     item.name, item.kind, item.outcome, item.elapsed_ms)?;
+        }
+        Ok(())
+    }
+}
+
+enum Item {
+    Child(Child),
+    Task {
+        stop:   Arc<AtomicBool>,
+        done:   Arc<AtomicBool>,
+        handle: JoinHandle<()>,
+    },
+}
+
+struct Entry {
+    name:    String,
+    timeout: Duration,
+    item:    Item,
+}
+
+/// Sets a flag when dropped, whether a task returns or panics.
+struct DoneGuard(Arc<AtomicBool>);
+
+impl Drop for DoneGuard {
+    fn drop(&mut self) { self.0.store(true, Ordering::SeqCst) }
+}
+
+/// Collects what has to be stopped before a program exits, and stops it.
+pub struct Coordinator {
+    entries:   Vec<Entry>,
+    requested: Arc<AtomicBool>,
+}
+
+impl Default for Coordinator {
+    fn default() -> Self { Self::new() }
+}
+
+impl Coordinator {
+    pub fn new() -> Self {
+        Coordinator { entries:   Vec::new(),
+                      requested: Arc::new(AtomicBool::new(false)), }
+    }
+
+    /// A handle which makes `wait` return, for shutting down other than by signal.
+    pub fn handle(&self) -> ShutdownHandle { ShutdownHandle(Arc::clone(&self.requested)) }
+
+    /// Registers a child process, to be given `timeout` to exit once asked to.
+    pub fn register_child<N: Into<String>>(&mut self, name: N, child: Child, timeout: Duration) {
+        self.entries.push(Entry { name: name.into(),
+                                  timeout,
+                                  item: Item::Child(child) });
+    }
+
+    /// Runs `task` on a thread of its own, passing it the `StopToken` it should watch. The task
+    /// is given `timeout` to return once the token is triggered.
+    ///
+    /// # Failures
+    ///
+    /// * The thread cannot be spawned
+    pub fn spawn_task<N, F>(&mut self, name: N, timeout: Duration, task: F) -> io::Result<()>
+        where N: Into<String>,
+              F: FnOnce(StopToken) + Send + 'static
+    {
+        let name = name.into();
+        let stop = Arc::new(AtomicBool::new(false));
+        let done = Arc::new(AtomicBool::new(false));
+        let token = StopToken(Arc::clone(&stop));
+        let guard = DoneGuard(Arc::clone(&done));
+        let handle = thread::Builder::new().name(name.clone()).spawn(move || {
+                                                                   let _guard = guard;
+                                                                   task(token)
+                                                               })?;
+        self.entries.push(Entry { name,
+                                  timeout,
+                                  item: Item::Task { stop, done, handle } });
+        Ok(())
+    }
+
+    /// Blocks until the process receives a shutdown signal or a `ShutdownHandle` is triggered.
+    /// Signal handling must already be set up with `os::signals::init`.
+    pub fn wait(&self) -> ShutdownReason {
+        loop {
+            if signals::check_for_shutdown() {
+                return ShutdownReason::Signal;
+            }
+            if self.requested.load(Ordering::SeqCst) {
+                return ShutdownReason::Requested;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Stops everything registered, in the reverse of the order it was registered.
+    pub fn shutdown(self, reason: ShutdownReason) -> ShutdownReport {
+        let started = Instant::now();
+        let mut items = Vec::with_capacity(self.entries.len());
+        for entry in self.entries.into_iter().rev() {
+            let item_started = Instant::now();
+            let (kind, outcome) = match entry.item {
+                Item::Child(child) => (ItemKind::Child, stop_child(child, entry.timeout)),
+                Item::Task { stop, done, handle } => {
+                    (ItemKind::Task, stop_task(&stop, &done, handle, entry.timeout))
+                }
+            };
+            debug!("Shutdown of {}: {}", entry.name, outcome);
+            items.push(ItemReport { name: entry.name,
+                                    kind,
+                                    outcome,
+                                    elapsed_ms: millis(item_started.elapsed()) });
+        }
+        ShutdownReport { reason,
+                         items,
+                         elapsed_ms: millis(started.elapsed()) }
+    }
+}
+
+fn stop_child(mut child: Child, timeout: Duration) -> Outcome {
+    match child.try_wait() {
+        Ok(Some(status)) => return Outcome::Exited(status.code()),
+        Ok(None) => {}
+        Err(e) => return Outcome::Failed(e.to_string()),
+    }
+    #[cfg(unix)]
+    {
+        if let Err(e) = process::signal(child.id() as Pid, Signal::TERM) {
+            return Outcome::Failed(e.to_string());
+        }
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            match child.try_wait() {
+                Ok(Some(status)) => return Outcome::Exited(status.code()),
+                Ok(None) => thread::sleep(POLL_INTERVAL),
+                Err(e) => return Outcome::Failed(e.to_string()),
+            }
+        }
+    }
+    #[cfg(windows)]
+    let _ = timeout;
+    match child.kill().and_then(|_| child.wait()) {
+        Ok(_) => Outcome::Killed,
+        Err(e) => Outcome::Failed(e.to_string()),
+    }
+}
+
+fn stop_task(stop: &AtomicBool,
+             done: &AtomicBool,
+             handle: JoinHandle<()>,
+             timeout: Duration)
+             -> Outcome {
+    stop.store(true, Ordering::SeqCst);
+    let deadline = Instant::now() + timeout;
+    while !done.load(Ordering::SeqCst) {
+        if Instant::now() >= deadline {
+            return Outcome::TimedOut;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    match handle.join() {
+        Ok(()) => Outcome::Stopped,
+        Err(_) => Outcome::Panicked,
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stops_everything_in_reverse_order() {
+        let mut coordinator = Coordinator::new();
+        coordinator.spawn_task("first", Duration::from_secs(5), |stop| {
+                       while !stop.is_stopping() {
+                           thread::sleep(Duration::from_millis(10));
+                       }
+                   })
+                   .unwrap();
+        coordinator.spawn_task("stuck", Duration::from_millis(100), |_| {
+                       thread::sleep(Duration::from_secs(1))
+                   })
+                   .unwrap();
+        coordinator.spawn_task("panics", Duration::from_secs(5), |_| panic!("boom"))
+                   .unwrap();
+        let handle = coordinator.handle();
+        handle.trigger();
+
+        let reason = coordinator.wait();
+        let report = coordinator.shutdown(reason);
+
+        assert_eq!(ShutdownReason::Requested, report.reason);
+        let outcomes: Vec<(&str, &Outcome)> = report.items
+                                                    .iter()
+                                                    .map(|i| (i.name.as_str(), &i.outcome))
+                                                    .collect();
+        assert_eq!(vec![("panics", &Outcome::Panicked),
+                        ("stuck", &Outcome::TimedOut),
+                        ("first", &Outcome::Stopped)],
+                   outcomes);
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn children_are_terminated_then_killed() {
+        use std::process::Command;
+
+        let mut coordinator = Coordinator::new();
+        let trapping = Command::new("sh").arg("-c")
+                                         .arg("trap '' TERM; sleep 10")
+                                         .spawn()
+                                         .unwrap();
+        coordinator.register_child("trapping", trapping, Duration::from_millis(200));
+        let sleeping = Command::new("sleep").arg("10").spawn().unwrap();
+        coordinator.register_child("sleeping", sleeping, Duration::from_secs(5));
+
+        let report = coordinator.shutdown(ShutdownReason::Requested);
+
+        assert_eq!(ItemKind::Child, report.items[0].kind);
+        assert_eq!("sleeping", report.items[0].name);
+        assert_eq!(Outcome::Exited(None), report.items[0].outcome);
+        assert_eq!(Outcome::Killed, report.items[1].outcome);
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Datagram transport for SWIM gossip.
+//!
+//! A `Transport` moves whole messages between peers and knows nothing about their contents.
+//! When a ring key is in use every datagram is encrypted with it, and datagrams which are not
+//! encrypted with it are rejected. The framing is handled by `seal` and `open`, which are pure
+//! functions over bytes so they can be tested and fuzzed without a network.
+//!
+//! Each datagram is a single flag byte followed by the payload. The flag is `0` for a plaintext
+//! payload, or `1` for an encrypted one, in which case the payload is the nonce followed by the
+//! ciphertext.
+
+pub mod udp;
+
+use std::net::SocketAddr;
+
+use sodiumoxide::crypto::secretbox;
+
+pub use self::udp::UdpTransport;
+use crate::{crypto::SymKey,
+            error::{Error,
+                    Result}};
+
+/// The largest payload of a UDP datagram over IPv4.
+pub const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+const PLAINTEXT: u8 = 0;
+const ENCRYPTED: u8 = 1;
+
+/// Sends and receives gossip messages to and from peers.
+pub trait Transport: Send + Sync {
+    /// Sends `payload` to the peer at `addr`.
+    fn send(&self, payload: &[u8], addr: SocketAddr) -> Result<()>;
+
+    /// Waits for the next message, returning its payload and the address of the peer which
+    /// sent it.
+    fn recv(&self) -> Result<(Vec<u8>, SocketAddr)>;
+
+    /// The address peers send to in order to reach this transport.
+    fn local_addr(&self) -> Result<SocketAddr>;
+}
+
+/// Frames `payload` as a datagram, encrypting it with `ring_key` if one is given.
+///
+/// # Failures
+///
+/// * `ring_key` has no secret key
+pub fn seal(payload: &[u8], ring_key: Option<&SymKey>) -> Result<Vec<u8>> {
+    match ring_key {
+        Some(key) => {
+            let (nonce, ciphertext) = key.encrypt(payload)?;
+            let mut datagram = Vec::with_capacity(1 + nonce.len() + ciphertext.len());
+            datagram.push(ENCRYPTED);
+            datagram.extend_from_slice(&nonce);
+            datagram.extend_from_slice(&ciphertext);
+            Ok(datagram)
+        }
+        None => {
+            let mut datagram = Vec::with_capacity(1 + payload.len());
+            datagram.push(PLAINTEXT);
+            datagram.extend_from_slice(payload);
+            Ok(datagram)
+        }
+    }
+}
+
+/// Returns the payload of a datagram framed by `seal`, decrypting it with `ring_key` if one is
+/// given. Any input, however malformed, results in an error rather than a panic.
+///
+/// # Failures
+///
+/// * The datagram is empty or its flag is unknown
+/// * A ring key is given and the datagram is not encrypted, or the other way around
+/// * The datagram cannot be decrypted with the ring key
+pub fn open(datagram: &[u8], ring_key: Option<&SymKey>) -> Result<Vec<u8>> {
+    let (flag, rest) = match datagram.split_first() {
+        Some((&flag, rest)) => (flag, rest),
+        None => return Err(Error::InvalidGossipMessage("empty datagram".to_string())),
+    };
+    match (flag, ring_key) {
+        (PLAINTEXT, None) => Ok(rest.to_vec()),
+        (ENCRYPTED, Some(key)) => {
+            if rest.len() < secretbox::NONCEBYTES {
+                return Err(Error::InvalidGossipMessage("truncated nonce".to_string()));
+            }
+            let (nonce, ciphertext) = rest.split_at(secretbox::NONCEBYTES);
+            key.decrypt(nonce, ciphertext)
+        }
+        (PLAINTEXT, Some(_)) => {
+            Err(Error::InvalidGossipMessage("unencrypted message \
+                                             received, but a ring key is \
+                                             in use"
+                                                    .to_string()))
+        }
+        (ENCRYPTED, None) => {
+            Err(Error::InvalidGossipMessage("encrypted message received, but no ring key is in \
+                                             use".to_string()))
+        }
+        (flag, _) => Err(Error::InvalidGossipMessage(format!("unknown flag {}", flag))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn plaintext_round_trip() {
+        let datagram = seal(b"ping", None).unwrap();
+
+        assert_eq!(b"\0ping".to_vec(), datagram);
+        assert_eq!(b"ping".to_vec(), open(&datagram, None).unwrap());
+    }
+
+    #[test]
+    fn encrypted_round_trip() {
+        let key = SymKey::generate_pair_for_ring("ring").unwrap();
+
+        let datagram = seal(b"ping", Some(&key)).unwrap();
+
+        assert!(!datagram.windows(4).any(|w| w == b"ping"));
+        assert_eq!(b"ping".to_vec(), open(&datagram, Some(&key)).unwrap());
+    }
+
+    #[test]
+    fn rejects_mismatched_encryption() {
+        let key = SymKey::generate_pair_for_ring("ring").unwrap();
+        let other = SymKey::generate_pair_for_ring("other").unwrap();
+        let plain = seal(b"ping", None).unwrap();
+        let encrypted = seal(b"ping", Some(&key)).unwrap();
+
+        assert!(open(&plain, Some(&key)).is_err());
+        assert!(open(&encrypted, None).is_err());
+        assert!(open(&encrypted, Some(&other)).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_datagrams() {
+        let key = SymKey::generate_pair_for_ring("ring").unwrap();
+        let encrypted = seal(b"ping", Some(&key)).unwrap();
+
+        assert!(open(&[], None).is_err());
+        assert!(open(&[7, 1, 2, 3], None).is_err());
+        for len in 0..encrypted.len() {
+            assert!(open(&encrypted[..len], Some(&key)).is_err(),
+                    "length {}",
+                    len);
+        }
+    }
+}
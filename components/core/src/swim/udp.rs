@@ -0,0 +1,111 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{net::{SocketAddr,
+                ToSocketAddrs,
+                UdpSocket},
+          time::Duration};
+
+use super::{open,
+            seal,
+            Transport,
+            MAX_DATAGRAM_SIZE};
+use crate::{crypto::SymKey,
+            error::{Error,
+                    Result}};
+
+/// A `Transport` over a UDP socket.
+#[derive(Debug)]
+pub struct UdpTransport {
+    socket:   UdpSocket,
+    ring_key: Option<SymKey>,
+}
+
+impl UdpTransport {
+    /// Binds a socket to `addr`, encrypting messages with `ring_key` if one is given.
+    ///
+    /// # Failures
+    ///
+    /// * The socket cannot be bound
+    pub fn bind<A>(addr: A, ring_key: Option<SymKey>) -> Result<Self>
+        where A: ToSocketAddrs
+    {
+        Ok(UdpTransport { socket: UdpSocket::bind(addr)?,
+                          ring_key })
+    }
+
+    /// Sets how long `recv` waits for a message before failing, or `None` to wait forever.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.socket.set_read_timeout(timeout)?)
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&self, payload: &[u8], addr: SocketAddr) -> Result<()> {
+        let datagram = seal(payload, self.ring_key.as_ref())?;
+        if datagram.len() > MAX_DATAGRAM_SIZE {
+            return Err(Error::InvalidGossipMessage(format!("{} byte message is \
+                                                            too large to send",
+                                                           payload.len())));
+        }
+        self.socket.send_to(&datagram, addr)?;
+        Ok(())
+    }
+
+    fn recv(&self) -> Result<(Vec<u8>, SocketAddr)> {
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        let (len, addr) = self.socket.recv_from(&mut buf)?;
+        Ok((open(&buf[..len], self.ring_key.as_ref())?, addr))
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> { Ok(self.socket.local_addr()?) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pair(key: Option<SymKey>) -> (UdpTransport, UdpTransport) {
+        let a = UdpTransport::bind("127.0.0.1:0", key.clone()).unwrap();
+        let b = UdpTransport::bind("127.0.0.1:0", key).unwrap();
+        b.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        (a, b)
+    }
+
+    #[test]
+    fn sends_between_peers() {
+        let key = SymKey::generate_pair_for_ring("ring").unwrap();
+        let (a, b) = pair(Some(key));
+
+        a.send(b"ping", b.local_addr().unwrap()).unwrap();
+        let (payload, from) = b.recv().unwrap();
+
+        assert_eq!(b"ping".to_vec(), payload);
+        assert_eq!(a.local_addr().unwrap(), from);
+    }
+
+    #[test]
+    fn rejects_peer_without_ring_key() {
+        let key = SymKey::generate_pair_for_ring("ring").unwrap();
+        let a = UdpTransport::bind("127.0.0.1:0", None).unwrap();
+        let (_, b) = pair(Some(key));
+
+        a.send(b"ping", b.local_addr().unwrap()).unwrap();
+
+        match b.recv() {
+            Err(Error::InvalidGossipMessage(_)) => {}
+            other => panic!("Expected InvalidGossipMessage, got {:?}", other),
+        }
+    }
+}
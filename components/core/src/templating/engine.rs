@@ -0,0 +1,174 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{fs,
+          path::Path};
+
+use handlebars::{self,
+                 Handlebars};
+use serde::Serialize;
+
+use super::helpers;
+use crate::{error::Result,
+            fs::atomic_write};
+
+/// Renders templates with Habitat's helpers registered.
+///
+/// Output is never HTML-escaped, since templates render configuration files rather than web
+/// pages.
+pub struct TemplateRenderer(Handlebars);
+
+impl TemplateRenderer {
+    pub fn new() -> Self {
+        let mut handlebars = Handlebars::new();
+        handlebars.register_escape_fn(handlebars::no_escape);
+        helpers::register(&mut handlebars);
+        TemplateRenderer(handlebars)
+    }
+
+    /// Sets whether rendering fails when a template refers to a value which is missing from the
+    /// context, rather than rendering it as empty. Off by default, as in the Supervisor.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.0.set_strict_mode(strict);
+        self
+    }
+
+    /// Registers `template` under `name`.
+    ///
+    /// # Failures
+    ///
+    /// * The template is not valid Handlebars
+    pub fn register_template_string(&mut self, name: &str, template: &str) -> Result<()> {
+        Ok(self.0.register_template_string(name, template)?)
+    }
+
+    /// Registers the template in the file at `path` under `name`.
+    ///
+    /// # Failures
+    ///
+    /// * The file cannot be read
+    /// * The template is not valid Handlebars
+    pub fn register_template_file<P>(&mut self, name: &str, path: P) -> Result<()>
+        where P: AsRef<Path>
+    {
+        Ok(self.0.register_template_file(name, path)?)
+    }
+
+    /// Whether a template is registered under `name`.
+    pub fn has_template(&self, name: &str) -> bool { self.0.has_template(name) }
+
+    /// Renders the template registered under `name` with `ctx`.
+    ///
+    /// # Failures
+    ///
+    /// * No template is registered under `name`
+    /// * A helper fails, or in strict mode a value is missing from `ctx`
+    pub fn render<T>(&self, name: &str, ctx: &T) -> Result<String>
+        where T: Serialize
+    {
+        Ok(self.0.render(name, ctx)?)
+    }
+
+    /// Renders the template registered under `name` with `ctx` into the file at `dest`,
+    /// returning whether the file changed.
+    ///
+    /// The file is only written when the rendered content differs from what it already
+    /// contains, so that anything watching it, or its modification time, sees no change when
+    /// there was none. When it is written, it is replaced atomically.
+    ///
+    /// # Failures
+    ///
+    /// * The template cannot be rendered
+    /// * The file cannot be written
+    pub fn render_to_file<T, P>(&self, name: &str, ctx: &T, dest: P) -> Result<bool>
+        where T: Serialize,
+              P: AsRef<Path>
+    {
+        let dest = dest.as_ref();
+        let content = self.render(name, ctx)?;
+        if let Ok(existing) = fs::read(dest) {
+            if existing == content.as_bytes() {
+                return Ok(false);
+            }
+        }
+        atomic_write(dest, content)?;
+        Ok(true)
+    }
+}
+
+impl Default for TemplateRenderer {
+    fn default() -> Self { TemplateRenderer::new() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::Error;
+    use serde_json::json;
+    use tempfile::Builder;
+
+    fn render(template: &str, ctx: &serde_json::Value) -> Result<String> {
+        let mut renderer = TemplateRenderer::new();
+        renderer.register_template_string("t", template).unwrap();
+        renderer.render("t", ctx)
+    }
+
+    #[test]
+    fn renders_without_escaping() {
+        let ctx = json!({ "cfg": { "motd": "<b>\"hi\" & bye</b>" } });
+
+        assert_eq!("<b>\"hi\" & bye</b>", render("{{cfg.motd}}", &ctx).unwrap());
+    }
+
+    #[test]
+    fn strict_mode_fails_on_missing_values() {
+        let ctx = json!({ "cfg": {} });
+        let mut renderer = TemplateRenderer::new().strict(true);
+        renderer.register_template_string("t", "port={{cfg.port}}")
+                .unwrap();
+
+        assert_eq!("port=", render("port={{cfg.port}}", &ctx).unwrap());
+        match renderer.render("t", &ctx) {
+            Err(Error::TemplateRenderError(_)) => {}
+            other => panic!("Expected TemplateRenderError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn render_to_file_only_writes_changes() {
+        let dir = Builder::new().prefix("templating").tempdir().unwrap();
+        let dest = dir.path().join("app.conf");
+        let mut renderer = TemplateRenderer::new();
+        renderer.register_template_string("app.conf", "port={{cfg.port}}")
+                .unwrap();
+
+        assert!(renderer.render_to_file("app.conf", &json!({ "cfg": { "port": 80 } }), &dest)
+                        .unwrap());
+        assert!(!renderer.render_to_file("app.conf", &json!({ "cfg": { "port": 80 } }), &dest)
+                         .unwrap());
+        assert!(renderer.render_to_file("app.conf", &json!({ "cfg": { "port": 81 } }), &dest)
+                        .unwrap());
+        assert_eq!("port=81", fs::read_to_string(&dest).unwrap());
+    }
+
+    #[test]
+    fn invalid_template_fails_to_register() {
+        let mut renderer = TemplateRenderer::new();
+
+        match renderer.register_template_string("t", "{{#if}}") {
+            Err(Error::TemplateError(_)) => {}
+            other => panic!("Expected TemplateError, got {:?}", other),
+        }
+    }
+}
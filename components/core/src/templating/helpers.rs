@@ -0,0 +1,403 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The helpers available to every template, matching those registered by the Supervisor.
+//!
+//! The rules the helpers follow to look things up in the render context are also available as
+//! plain functions over the context's JSON, so that anything rendering templates with another
+//! engine resolves package paths, members, and exports exactly as Handlebars templates do.
+
+use std::{collections::BTreeMap,
+          path::PathBuf,
+          str::FromStr};
+
+use handlebars::{to_json,
+                 Context,
+                 Handlebars,
+                 Helper,
+                 HelperResult,
+                 JsonRender,
+                 Output,
+                 RenderContext,
+                 RenderError,
+                 Renderable};
+use serde_json::Value as Json;
+
+use crate::{fs::{pkg_install_path,
+                 FS_ROOT_PATH},
+            package::{Identifiable,
+                      PackageIdent}};
+
+/// Registers every helper with `handlebars`.
+pub fn register(handlebars: &mut Handlebars) {
+    handlebars.register_helper("eachAlive", Box::new(each_alive_helper));
+    handlebars.register_helper("pkgPathFor", Box::new(pkg_path_for_helper));
+    handlebars.register_helper("strConcat", Box::new(str_concat));
+    handlebars.register_helper("strJoin", Box::new(str_join));
+    handlebars.register_helper("strReplace", Box::new(str_replace));
+    handlebars.register_helper("toJson", Box::new(to_json_helper));
+    handlebars.register_helper("toLowercase", Box::new(to_lowercase));
+    handlebars.register_helper("toToml", Box::new(to_toml));
+    handlebars.register_helper("toUppercase", Box::new(to_uppercase));
+}
+
+fn param<'a>(h: &'a Helper<'_, '_>, index: usize) -> Result<&'a Json, RenderError> {
+    h.param(index)
+     .map(|p| p.value())
+     .ok_or_else(|| RenderError::new(format!("Missing parameter for \"{}\"", h.name())))
+}
+
+fn str_param<'a>(h: &'a Helper<'_, '_>, index: usize) -> Result<&'a str, RenderError> {
+    param(h, index)?.as_str().ok_or_else(|| {
+                                 RenderError::new(format!("Expected a string parameter for \"{}\"",
+                                                          h.name()))
+                             })
+}
+
+/// Returns the install path of the dependency of the package being rendered with `ctx` which
+/// satisfies `ident`, if there is one. The dependencies are read from `pkg.deps`.
+pub fn pkg_path_for(ident: &PackageIdent, ctx: &Json) -> Option<PathBuf> {
+    pkg_deps(ctx).iter()
+                 .find(|dep| dep.fully_qualified() && dep.satisfies(ident))
+                 .map(|dep| pkg_install_path(dep, Some(&*FS_ROOT_PATH)))
+}
+
+/// Returns the dependencies of the package being rendered with `ctx`, in the order given by
+/// `pkg.deps`, or none if they are missing or malformed.
+pub fn pkg_deps(ctx: &Json) -> Vec<PackageIdent> {
+    serde_json::from_value(ctx["pkg"]["deps"].clone()).unwrap_or_default()
+}
+
+/// Whether a service group member is alive.
+pub fn is_alive(member: &Json) -> bool { member.get("alive").and_then(Json::as_bool) == Some(true) }
+
+/// Returns the members in a list of service group members which are alive, in order. Anything
+/// other than a list has no members.
+pub fn alive_members(members: &Json) -> Vec<&Json> {
+    members.as_array()
+           .map(|list| list.iter().filter(|m| is_alive(m)).collect())
+           .unwrap_or_default()
+}
+
+/// Returns the value which the service bound to as `bind` in `ctx` exports as `key`.
+///
+/// The value is taken from the alive leader of the bound service group if it has one, or its
+/// first alive member otherwise, since every member of a group exports the same configuration.
+pub fn bind_export<'a>(ctx: &'a Json, bind: &str, key: &str) -> Option<&'a Json> {
+    let members = alive_members(&ctx["bind"][bind]["members"]);
+    let member = members.iter()
+                        .find(|m| m.get("leader").and_then(Json::as_bool) == Some(true))
+                        .or_else(|| members.first())?;
+    member.get("cfg")?.get(key)
+}
+
+/// Renders its block once for each member of a list of service group members which is alive,
+/// like `each` otherwise.
+///
+/// ```text
+/// {{#eachAlive bind.database.members as |member|}}{{member.sys.ip}}{{/eachAlive}}
+/// ```
+fn each_alive_helper<'reg, 'rc>(h: &Helper<'reg, 'rc>,
+                                r: &'reg Handlebars,
+                                ctx: &'rc Context,
+                                rc: &mut RenderContext<'reg>,
+                                out: &mut dyn Output)
+                                -> HelperResult {
+    let value = h.param(0)
+                 .ok_or_else(|| RenderError::new("Param not found for helper \"eachAlive\""))?;
+    let template = match h.template() {
+        Some(t) => t,
+        None => return Ok(()),
+    };
+
+    rc.promote_local_vars();
+    let local_path_root = value.path_root()
+                               .map(|p| format!("{}/{}", rc.get_path(), p));
+    let rendered = match value.value() {
+        Json::Array(ref list) if list.iter().any(is_alive) => {
+            let alive: Vec<(usize, &Json)> = list.iter()
+                                                 .enumerate()
+                                                 .filter(|(_, m)| is_alive(m))
+                                                 .collect();
+            let len = alive.len();
+            for (n, (i, member)) in alive.into_iter().enumerate() {
+                let mut local_rc = rc.derive();
+                if let Some(ref p) = local_path_root {
+                    local_rc.push_local_path_root(p.clone());
+                }
+                local_rc.set_local_var("@first".to_string(), to_json(&(n == 0)));
+                local_rc.set_local_var("@last".to_string(), to_json(&(n == len - 1)));
+                local_rc.set_local_var("@index".to_string(), to_json(&n));
+                // Paths refer to the member's position in the full list
+                if let Some(inner_path) = value.path() {
+                    let new_path = format!("{}/{}/[{}]", local_rc.get_path(), inner_path, i);
+                    local_rc.set_path(new_path);
+                }
+                if let Some(block_param) = h.block_param() {
+                    let mut map = BTreeMap::new();
+                    map.insert(block_param.to_string(), to_json(member));
+                    local_rc.push_block_context(&map)?;
+                }
+
+                template.render(r, ctx, &mut local_rc, out)?;
+
+                if h.block_param().is_some() {
+                    local_rc.pop_block_context();
+                }
+                if local_path_root.is_some() {
+                    local_rc.pop_local_path_root();
+                }
+            }
+            Ok(())
+        }
+        _ => {
+            match h.inverse() {
+                Some(else_template) => else_template.render(r, ctx, rc, out),
+                None => Ok(()),
+            }
+        }
+    };
+    rc.demote_local_vars();
+    rendered
+}
+
+/// Renders the install path of the dependency of the package being rendered which satisfies
+/// the given identifier, or nothing if there is none.
+///
+/// ```text
+/// {{pkgPathFor "core/openssl"}}
+/// ```
+fn pkg_path_for_helper(h: &Helper<'_, '_>,
+                       _: &Handlebars,
+                       ctx: &Context,
+                       _: &mut RenderContext<'_>,
+                       out: &mut dyn Output)
+                       -> HelperResult {
+    let ident = match PackageIdent::from_str(str_param(h, 0)?) {
+        Ok(ident) => ident,
+        Err(_) => return Err(RenderError::new("Invalid package identifier for \"pkgPathFor\"")),
+    };
+    if let Some(path) = pkg_path_for(&ident, ctx.data()) {
+        out.write(&path.to_string_lossy())?;
+    }
+    Ok(())
+}
+
+/// Renders each of its parameters, one after the other.
+fn str_concat(h: &Helper<'_, '_>,
+              _: &Handlebars,
+              _: &Context,
+              _: &mut RenderContext<'_>,
+              out: &mut dyn Output)
+              -> HelperResult {
+    let concatenated: String = h.params().iter().map(|p| p.value().render()).collect();
+    out.write(&concatenated)?;
+    Ok(())
+}
+
+/// Renders the items of a list separated by a string.
+///
+/// ```text
+/// {{strJoin cfg.hosts ","}}
+/// ```
+fn str_join(h: &Helper<'_, '_>,
+            _: &Handlebars,
+            _: &Context,
+            _: &mut RenderContext<'_>,
+            out: &mut dyn Output)
+            -> HelperResult {
+    let list = param(h, 0)?.as_array()
+                           .ok_or_else(|| RenderError::new("Expected a list for \"strJoin\""))?;
+    let separator = str_param(h, 1)?;
+    let items: Vec<String> = list.iter().map(JsonRender::render).collect();
+    out.write(&items.join(separator))?;
+    Ok(())
+}
+
+/// Renders a string with every occurrence of one substring replaced by another.
+///
+/// ```text
+/// {{strReplace cfg.name "-" "_"}}
+/// ```
+fn str_replace(h: &Helper<'_, '_>,
+               _: &Handlebars,
+               _: &Context,
+               _: &mut RenderContext<'_>,
+               out: &mut dyn Output)
+               -> HelperResult {
+    let value = str_param(h, 0)?;
+    let from = str_param(h, 1)?;
+    let to = str_param(h, 2)?;
+    out.write(&value.replace(from, to))?;
+    Ok(())
+}
+
+/// Renders a value as pretty-printed JSON.
+fn to_json_helper(h: &Helper<'_, '_>,
+                  _: &Handlebars,
+                  _: &Context,
+                  _: &mut RenderContext<'_>,
+                  out: &mut dyn Output)
+                  -> HelperResult {
+    let json =
+        serde_json::to_string_pretty(param(h, 0)?).map_err(|e| {
+                                                      RenderError::new(format!("Unable to render \
+                                                                                value as JSON: {}",
+                                                                               e))
+                                                  })?;
+    out.write(&json)?;
+    Ok(())
+}
+
+/// Renders a table as TOML.
+fn to_toml(h: &Helper<'_, '_>,
+           _: &Handlebars,
+           _: &Context,
+           _: &mut RenderContext<'_>,
+           out: &mut dyn Output)
+           -> HelperResult {
+    let toml = toml::to_string(param(h, 0)?).map_err(|e| {
+                                                RenderError::new(format!("Unable to render value \
+                                                                          as TOML: {}",
+                                                                         e))
+                                            })?;
+    out.write(&toml)?;
+    Ok(())
+}
+
+fn to_lowercase(h: &Helper<'_, '_>,
+                _: &Handlebars,
+                _: &Context,
+                _: &mut RenderContext<'_>,
+                out: &mut dyn Output)
+                -> HelperResult {
+    out.write(&str_param(h, 0)?.to_lowercase())?;
+    Ok(())
+}
+
+fn to_uppercase(h: &Helper<'_, '_>,
+                _: &Handlebars,
+                _: &Context,
+                _: &mut RenderContext<'_>,
+                out: &mut dyn Output)
+                -> HelperResult {
+    out.write(&str_param(h, 0)?.to_uppercase())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::templating::TemplateRenderer;
+    use serde_json::{json,
+                     Value};
+
+    fn render(template: &str, ctx: &Value) -> String {
+        let mut renderer = TemplateRenderer::new();
+        renderer.register_template_string("t", template).unwrap();
+        renderer.render("t", ctx).unwrap()
+    }
+
+    #[test]
+    fn each_alive_skips_dead_members() {
+        let ctx = json!({ "bind": { "db": { "members": [
+            { "alive": true, "sys": { "ip": "10.0.0.1" } },
+            { "alive": false, "sys": { "ip": "10.0.0.2" } },
+            { "alive": true, "sys": { "ip": "10.0.0.3" } }
+        ] } } });
+
+        assert_eq!("10.0.0.1,10.0.0.3",
+                   render("{{#eachAlive bind.db.members as |m|}}{{m.sys.ip}}{{#unless \
+                           @last}},{{/unless}}{{/eachAlive}}",
+                          &ctx));
+        assert_eq!("0:10.0.0.1 1:10.0.0.3 ",
+                   render("{{#eachAlive bind.db.members}}{{@index}}:{{sys.ip}} {{/eachAlive}}",
+                          &ctx));
+    }
+
+    #[test]
+    fn each_alive_renders_else_without_alive_members() {
+        let ctx = json!({ "members": [{ "alive": false }] });
+
+        assert_eq!("none",
+                   render("{{#eachAlive members}}x{{else}}none{{/eachAlive}}", &ctx));
+    }
+
+    #[test]
+    fn pkg_path_for_finds_dependency() {
+        let ctx = json!({ "pkg": { "deps": [
+            { "origin": "core", "name": "glibc", "version": "2.27", "release": "20190115002733" },
+            { "origin": "core", "name": "openssl", "version": "1.0.2", "release": "20190115014206" }
+        ] } });
+
+        let path = render("{{pkgPathFor \"core/openssl\"}}", &ctx);
+        assert!(path.ends_with("core/openssl/1.0.2/20190115014206")
+                || path.ends_with("core\\openssl\\1.0.2\\20190115014206"),
+                "{}",
+                path);
+        assert_eq!("", render("{{pkgPathFor \"core/zlib\"}}", &ctx));
+    }
+
+    #[test]
+    fn string_helpers() {
+        let ctx = json!({ "cfg": { "name": "my-app", "hosts": ["a", "b"] } });
+
+        assert_eq!("MY-APP", render("{{toUppercase cfg.name}}", &ctx));
+        assert_eq!("my_app",
+                   render("{{strReplace cfg.name \"-\" \"_\"}}", &ctx));
+        assert_eq!("a,b", render("{{strJoin cfg.hosts \",\"}}", &ctx));
+        assert_eq!("my-app.conf",
+                   render("{{strConcat cfg.name \".conf\"}}", &ctx));
+    }
+
+    #[test]
+    fn serialization_helpers() {
+        let ctx = json!({ "cfg": { "port": 80 } });
+
+        assert_eq!("port = 80\n", render("{{toToml cfg}}", &ctx));
+        assert_eq!("{\n  \"port\": 80\n}", render("{{toJson cfg}}", &ctx));
+    }
+
+    #[test]
+    fn pkg_path_for_library() {
+        let ctx = json!({ "pkg": { "deps": [
+            { "origin": "core", "name": "openssl", "version": "1.0.2", "release": "20190115014206" },
+            { "origin": "core", "name": "openssl", "version": "1.1.1", "release": "20190305213939" }
+        ] } });
+
+        let path = pkg_path_for(&PackageIdent::from_str("core/openssl/1.1.1").unwrap(), &ctx);
+
+        assert_eq!(Some(pkg_install_path(&pkg_deps(&ctx)[1], Some(&*FS_ROOT_PATH))),
+                   path);
+        assert_eq!(None,
+                   pkg_path_for(&PackageIdent::from_str("core/openssl").unwrap(), &json!({})));
+    }
+
+    #[test]
+    fn bind_export_prefers_leader() {
+        let mut ctx = json!({ "bind": { "db": { "members": [
+            { "alive": false, "leader": false, "cfg": { "port": 1 } },
+            { "alive": true, "leader": false, "cfg": { "port": 2 } },
+            { "alive": true, "leader": true, "cfg": { "port": 3 } }
+        ] } } });
+
+        assert_eq!(2, alive_members(&ctx["bind"]["db"]["members"]).len());
+        assert_eq!(Some(&json!(3)), bind_export(&ctx, "db", "port"));
+
+        ctx["bind"]["db"]["members"][2]["leader"] = json!(false);
+        assert_eq!(Some(&json!(2)), bind_export(&ctx, "db", "port"));
+        assert_eq!(None, bind_export(&ctx, "db", "host"));
+        assert_eq!(None, bind_export(&ctx, "cache", "port"));
+    }
+}
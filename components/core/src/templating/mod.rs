@@ -0,0 +1,25 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rendering of service configuration and hook templates.
+//!
+//! Templates are Handlebars, extended with the helpers available to every Habitat package, so
+//! anything rendering a package's templates outside the Supervisor produces the same output as
+//! the Supervisor would.
+
+pub mod engine;
+pub mod helpers;
+pub mod preview;
+
+pub use self::engine::TemplateRenderer;
@@ -0,0 +1,316 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Previewing what rendering would change, without writing anything.
+
+use std::{fs,
+          io,
+          path::{Path,
+                 PathBuf}};
+
+use serde::Serialize;
+
+use super::TemplateRenderer;
+use crate::error::Result;
+
+/// The number of unchanged lines shown around each change in a diff.
+const CONTEXT_LINES: usize = 3;
+
+/// The result of rendering a template, alongside what is currently at its destination.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Preview {
+    pub path:     PathBuf,
+    /// The destination's current content, or `None` if it does not exist.
+    pub current:  Option<String>,
+    pub rendered: String,
+}
+
+impl Preview {
+    /// Whether rendering would change the destination.
+    pub fn is_changed(&self) -> bool { self.current.as_ref() != Some(&self.rendered) }
+
+    /// A unified diff from the destination's current content to the rendered content, which is
+    /// empty if there is no change. A destination which does not exist is shown as
+    /// `/dev/null`.
+    pub fn diff(&self) -> String {
+        let path = self.path.display().to_string();
+        match self.current {
+            Some(ref current) => unified_diff(current, &self.rendered, &path, &path),
+            None => unified_diff("", &self.rendered, "/dev/null", &path),
+        }
+    }
+}
+
+impl TemplateRenderer {
+    /// Renders the template registered under `name` with `ctx` and compares the result with
+    /// the file at `dest`, which is left untouched.
+    ///
+    /// # Failures
+    ///
+    /// * The template cannot be rendered
+    /// * The destination exists and cannot be read
+    pub fn preview<T, P>(&self, name: &str, ctx: &T, dest: P) -> Result<Preview>
+        where T: Serialize,
+              P: AsRef<Path>
+    {
+        let path = dest.as_ref().to_path_buf();
+        let rendered = self.render(name, ctx)?;
+        let current = match fs::read(&path) {
+            Ok(bytes) => Some(String::from_utf8_lossy(&bytes).into_owned()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Preview { path,
+                     current,
+                     rendered })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Edit {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Produces a unified diff from `old` to `new`, labelling them `old_label` and `new_label`, or
+/// an empty string if they are the same.
+pub fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let edits = diff_lines(&old_lines, &new_lines);
+    if edits.iter().all(|&(edit, _)| edit == Edit::Equal) {
+        return String::new();
+    }
+
+    // The number of old and new lines before each edit
+    let mut old_pos = Vec::with_capacity(edits.len() + 1);
+    let mut new_pos = Vec::with_capacity(edits.len() + 1);
+    let (mut o, mut n) = (0, 0);
+    for &(edit, _) in &edits {
+        old_pos.push(o);
+        new_pos.push(n);
+        match edit {
+            Edit::Equal => {
+                o += 1;
+                n += 1;
+            }
+            Edit::Delete => o += 1,
+            Edit::Insert => n += 1,
+        }
+    }
+    old_pos.push(o);
+    new_pos.push(n);
+
+    let mut out = format!("--- {}\n+++ {}\n", old_label, new_label);
+    for (start, stop) in hunks(&edits) {
+        out.push_str(&format!("@@ -{} +{} @@\n",
+                              hunk_range(old_pos[start], old_pos[stop]),
+                              hunk_range(new_pos[start], new_pos[stop])));
+        for &(edit, line) in &edits[start..stop] {
+            out.push(match edit {
+                         Edit::Equal => ' ',
+                         Edit::Delete => '-',
+                         Edit::Insert => '+',
+                     });
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+/// Splits `s` into lines, each keeping its line ending so that a missing final newline counts
+/// as a difference.
+fn split_lines(s: &str) -> Vec<&str> {
+    let mut lines = Vec::new();
+    let mut rest = s;
+    while !rest.is_empty() {
+        let end = rest.find('\n').map_or(rest.len(), |i| i + 1);
+        lines.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    lines
+}
+
+/// Finds a shortest edit from `old` to `new` from their longest common subsequence of lines.
+/// Common leading and trailing lines are set aside first, which keeps the quadratic part small
+/// for the localized changes typical of configuration files.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(Edit, &'a str)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter()
+                              .rev()
+                              .zip(new[prefix..].iter().rev())
+                              .take_while(|(a, b)| a == b)
+                              .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut edits: Vec<(Edit, &str)> = old[..prefix].iter().map(|l| (Edit::Equal, *l)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            edits.push((Edit::Equal, a[i]));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            edits.push((Edit::Delete, a[i]));
+            i += 1;
+        } else {
+            edits.push((Edit::Insert, b[j]));
+            j += 1;
+        }
+    }
+    edits.extend(old[old.len() - suffix..].iter().map(|l| (Edit::Equal, *l)));
+    edits
+}
+
+/// Groups the changes in `edits` into hunks, returned as ranges of `edits` which include the
+/// surrounding context. Changes separated by no more than twice the context share a hunk.
+fn hunks(edits: &[(Edit, &str)]) -> Vec<(usize, usize)> {
+    let mut hunks = Vec::new();
+    let mut i = 0;
+    while i < edits.len() {
+        if edits[i].0 == Edit::Equal {
+            i += 1;
+            continue;
+        }
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let mut last_change = i;
+        let mut j = i;
+        while j < edits.len() && j - last_change <= 2 * CONTEXT_LINES + 1 {
+            if edits[j].0 != Edit::Equal {
+                last_change = j;
+            }
+            j += 1;
+        }
+        let stop = (last_change + CONTEXT_LINES + 1).min(edits.len());
+        hunks.push((start, stop));
+        i = stop;
+    }
+    hunks
+}
+
+/// Formats one side of a hunk header given the number of lines before the hunk and after it.
+fn hunk_range(before: usize, after: usize) -> String {
+    match after - before {
+        // An empty range is numbered by the line it follows
+        0 => format!("{},0", before),
+        1 => format!("{}", before + 1),
+        count => format!("{},{}", before + 1, count),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn identical_content_has_no_diff() {
+        assert_eq!("", unified_diff("a\nb\n", "a\nb\n", "old", "new"));
+    }
+
+    #[test]
+    fn single_change_with_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n";
+
+        assert_eq!("--- a\n+++ b\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n",
+                   unified_diff(old, new, "a", "b"));
+    }
+
+    #[test]
+    fn distant_changes_get_separate_hunks() {
+        let old: String = (1..=20).map(|i| format!("{}\n", i)).collect();
+        let new: String = (1..=20).map(|i| {
+                                      match i {
+                                          2 => "two\n".to_string(),
+                                          19 => "nineteen\n".to_string(),
+                                          _ => format!("{}\n", i),
+                                      }
+                                  })
+                                  .collect();
+
+        let diff = unified_diff(&old, &new, "a", "b");
+
+        assert_eq!(vec!["@@ -1,5 +1,5 @@", "@@ -16,5 +16,5 @@"],
+                   diff.lines()
+                       .filter(|l| l.starts_with("@@"))
+                       .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn changes_separated_by_twice_the_context_share_a_hunk() {
+        let old = "a\n1\n2\n3\n4\n5\n6\nb\n";
+        let new = "A\n1\n2\n3\n4\n5\n6\nB\n";
+
+        let diff = unified_diff(old, new, "x", "y");
+
+        assert_eq!(vec!["@@ -1,8 +1,8 @@"],
+                   diff.lines()
+                       .filter(|l| l.starts_with("@@"))
+                       .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn created_file_and_missing_newline() {
+        assert_eq!("--- /dev/null\n+++ b\n@@ -0,0 +1,2 @@\n+a\n+b\n\\ No newline at end of file\n",
+                   unified_diff("", "a\nb", "/dev/null", "b"));
+        assert_eq!("--- a\n+++ b\n@@ -1 +1 @@\n-a\n\\ No newline at end of file\n+a\n",
+                   unified_diff("a", "a\n", "a", "b"));
+    }
+
+    #[test]
+    fn insertions_and_deletions() {
+        let diff = unified_diff("a\nb\nc\n", "a\nc\nd\n", "x", "y");
+
+        assert_eq!("--- x\n+++ y\n@@ -1,3 +1,3 @@\n a\n-b\n c\n+d\n", diff);
+    }
+
+    #[test]
+    fn renderer_preview_does_not_write() {
+        let dir = tempfile::Builder::new().prefix("preview")
+                                          .tempdir()
+                                          .unwrap();
+        let dest = dir.path().join("app.conf");
+        let mut renderer = TemplateRenderer::new();
+        renderer.register_template_string("app.conf", "port={{cfg.port}}\n")
+                .unwrap();
+        let ctx = serde_json::json!({ "cfg": { "port": 80 } });
+
+        let preview = renderer.preview("app.conf", &ctx, &dest).unwrap();
+        assert!(preview.is_changed());
+        assert!(preview.diff().starts_with("--- /dev/null\n"));
+        assert!(!dest.exists());
+
+        fs::write(&dest, "port=80\n").unwrap();
+        let preview = renderer.preview("app.conf", &ctx, &dest).unwrap();
+        assert!(!preview.is_changed());
+        assert_eq!("", preview.diff());
+    }
+}
@@ -0,0 +1,56 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TLS settings shared by everything in this crate which makes TLS connections.
+
+use std::{fs,
+          path::PathBuf};
+
+use native_tls::{Certificate,
+                 Identity,
+                 TlsConnector};
+
+use crate::error::{Error,
+                   Result};
+
+/// TLS settings for connecting to a server.
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    /// A PEM encoded certificate to trust in addition to the system's trusted certificates.
+    pub ca_cert:  Option<PathBuf>,
+    /// A PKCS #12 archive holding a client certificate and key, and its password, for servers
+    /// which verify clients.
+    pub identity: Option<(PathBuf, String)>,
+}
+
+impl TlsOptions {
+    /// Builds a connector which applies these settings.
+    ///
+    /// # Failures
+    ///
+    /// * The CA certificate or client identity cannot be read or parsed
+    pub fn connector(&self) -> Result<TlsConnector> {
+        let tls_err = |e: native_tls::Error| Error::TlsError(e.to_string());
+        let mut builder = TlsConnector::builder();
+        if let Some(ref path) = self.ca_cert {
+            let cert = Certificate::from_pem(&fs::read(path)?).map_err(tls_err)?;
+            builder.add_root_certificate(cert);
+        }
+        if let Some((ref path, ref password)) = self.identity {
+            let identity = Identity::from_pkcs12(&fs::read(path)?, password).map_err(tls_err)?;
+            builder.identity(identity);
+        }
+        builder.build().map_err(tls_err)
+    }
+}
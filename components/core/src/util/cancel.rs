@@ -0,0 +1,199 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cooperative cancellation for long running operations, such as unpacking a package or walking
+//! the package root, so that a UI can stop one promptly without killing the process.
+//!
+//! Tokens form a tree: cancelling a token cancels every token made from it with `child`, but
+//! not the other way round. `any_of` makes a token which is cancelled when any of several are,
+//! for an operation which should stop for more than one reason.
+
+use std::{fmt,
+          sync::{Arc,
+                 Condvar,
+                 Mutex,
+                 Weak},
+          time::{Duration,
+                 Instant}};
+
+use crate::error::{Error,
+                   Result};
+
+struct State {
+    cancelled: bool,
+    /// Tokens to cancel along with this one.
+    children:  Vec<Weak<Inner>>,
+}
+
+struct Inner {
+    state:   Mutex<State>,
+    condvar: Condvar,
+}
+
+/// A handle through which an operation can be asked to stop. Clones share the same state, so
+/// cancelling any of them cancels them all.
+#[derive(Clone)]
+pub struct CancellationToken(Arc<Inner>);
+
+impl CancellationToken {
+    pub fn new() -> Self { Self::with_state(false) }
+
+    fn with_state(cancelled: bool) -> Self {
+        CancellationToken(Arc::new(Inner { state:   Mutex::new(State { cancelled,
+                                                                       children: Vec::new() }),
+                                           condvar: Condvar::new(), }))
+    }
+
+    /// Returns a token which is cancelled when this one is, but which can also be cancelled on
+    /// its own without affecting this one.
+    pub fn child(&self) -> Self { Self::any_of(&[self]) }
+
+    /// Returns a token which is cancelled as soon as any of `tokens` is.
+    pub fn any_of(tokens: &[&CancellationToken]) -> Self {
+        let token = Self::new();
+        for parent in tokens {
+            let mut state = parent.lock();
+            if state.cancelled {
+                drop(state);
+                token.cancel();
+                break;
+            }
+            state.children.retain(|child| child.upgrade().is_some());
+            state.children.push(Arc::downgrade(&token.0));
+        }
+        token
+    }
+
+    /// Cancels this token and all of its children, waking anything waiting on them.
+    pub fn cancel(&self) {
+        let children = {
+            let mut state = self.lock();
+            if state.cancelled {
+                return;
+            }
+            state.cancelled = true;
+            self.0.condvar.notify_all();
+            std::mem::replace(&mut state.children, Vec::new())
+        };
+        for child in children.iter().filter_map(Weak::upgrade) {
+            CancellationToken(child).cancel();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool { self.lock().cancelled }
+
+    /// Returns `Error::Cancelled` if this token has been cancelled, for an operation to call
+    /// between steps.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Blocks until this token is cancelled.
+    pub fn wait(&self) {
+        let mut state = self.lock();
+        while !state.cancelled {
+            state = self.0
+                        .condvar
+                        .wait(state)
+                        .expect("Cancellation token lock poisoned");
+        }
+    }
+
+    /// Blocks until this token is cancelled or `timeout` passes, returning whether it was
+    /// cancelled. Useful in place of `thread::sleep` between retries.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        while !state.cancelled {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self.0
+                        .condvar
+                        .wait_timeout(state, deadline - now)
+                        .expect("Cancellation token lock poisoned")
+                        .0;
+        }
+        state.cancelled
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.0
+            .state
+            .lock()
+            .expect("Cancellation token lock poisoned")
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self { Self::new() }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+         .field("cancelled", &self.is_cancelled())
+         .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn cancelling_a_parent_cancels_its_children() {
+        let parent = CancellationToken::new();
+        let child = parent.child();
+        let grandchild = child.child();
+        let sibling = parent.child();
+
+        child.cancel();
+        assert!(child.is_cancelled());
+        assert!(grandchild.is_cancelled());
+        assert!(!parent.is_cancelled());
+        assert!(!sibling.is_cancelled());
+
+        parent.clone().cancel();
+        assert!(sibling.is_cancelled());
+        assert!(parent.child().is_cancelled());
+        match parent.check() {
+            Err(Error::Cancelled) => {}
+            other => panic!("Expected Cancelled, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn any_of_is_cancelled_by_either_token() {
+        let user = CancellationToken::new();
+        let shutdown = CancellationToken::new();
+        let either = CancellationToken::any_of(&[&user, &shutdown]);
+        assert!(!either.wait_timeout(Duration::from_millis(10)));
+
+        let waiter = {
+            let either = either.clone();
+            thread::spawn(move || either.wait())
+        };
+        shutdown.cancel();
+        waiter.join().unwrap();
+        assert!(either.wait_timeout(Duration::from_secs(5)));
+        assert!(!user.is_cancelled());
+    }
+}
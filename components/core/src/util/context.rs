@@ -0,0 +1,308 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A deadline and retry budget shared by the steps of a composed operation, such as resolving,
+//! downloading, verifying and unpacking a package for `hab pkg install --timeout 120s`, so that
+//! the limit applies to the whole operation rather than to each step in turn.
+//!
+//! The deadline is checked between steps and bounds the waits between retries; a step which is
+//! already running is not interrupted, unless it watches the context's `CancellationToken`.
+
+use std::{cmp,
+          fmt,
+          result,
+          sync::{atomic::{AtomicUsize,
+                          Ordering},
+                 Arc},
+          time::{Duration,
+                 Instant}};
+
+use crate::{error::{Error,
+                    Result},
+            exit_code::ExitCode,
+            util::CancellationToken};
+
+/// The limits an operation runs under. Clones share the same retry budget and cancellation, so
+/// a context can be handed to each step, or to other threads, and still be enforced as one.
+/// Setting `retries` gives a context a budget of its own, which only the clones made from it
+/// afterwards share.
+#[derive(Clone, Debug)]
+pub struct OperationContext {
+    operation: String,
+    timeout:   Option<Duration>,
+    deadline:  Option<Instant>,
+    retries:   Arc<AtomicUsize>,
+    cancel:    CancellationToken,
+}
+
+impl OperationContext {
+    /// A context with no deadline and no retries, for the operation `operation`, which names it
+    /// in any timeout error.
+    pub fn new<S: Into<String>>(operation: S) -> Self {
+        OperationContext { operation: operation.into(),
+                           timeout:   None,
+                           deadline:  None,
+                           retries:   Arc::new(AtomicUsize::new(0)),
+                           cancel:    CancellationToken::new(), }
+    }
+
+    /// Gives the operation `timeout` to finish, starting now.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self.deadline = Some(Instant::now() + timeout);
+        self
+    }
+
+    /// Lets failed steps be retried `retries` times in all, across every step of the operation.
+    /// The budget is new, so setting it on a clone leaves the original's budget alone.
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = Arc::new(AtomicUsize::new(retries));
+        self
+    }
+
+    /// Stops the operation when `cancel` is cancelled, as well as when this context's own token
+    /// is.
+    pub fn cancel_on(mut self, cancel: &CancellationToken) -> Self {
+        self.cancel = CancellationToken::any_of(&[&self.cancel, cancel]);
+        self
+    }
+
+    pub fn operation(&self) -> &str { &self.operation }
+
+    /// The token which long running steps should watch to stop early.
+    pub fn cancellation(&self) -> &CancellationToken { &self.cancel }
+
+    /// The time left before the deadline, if there is one.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| {
+                         let now = Instant::now();
+                         if deadline > now {
+                             deadline - now
+                         } else {
+                             Duration::from_secs(0)
+                         }
+                     })
+    }
+
+    /// The retries which have not yet been used.
+    pub fn retries_left(&self) -> usize { self.retries.load(Ordering::SeqCst) }
+
+    /// Checks that the operation may carry on, for use between steps.
+    ///
+    /// # Failures
+    ///
+    /// * The operation was cancelled
+    /// * The deadline has passed, in which case the error names the whole operation
+    pub fn check(&self) -> Result<()> {
+        self.cancel.check()?;
+        match (self.remaining(), self.timeout) {
+            (Some(remaining), Some(timeout)) if remaining == Duration::from_secs(0) => {
+                Err(Error::OperationTimedOut(self.operation.clone(), timeout))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs the step `f` if the operation may carry on, retrying it after `backoff` for as long
+    /// as it fails with a transient error, and retries and time remain. Network, timeout and
+    /// operating system failures are transient; other failures are returned straight away.
+    ///
+    /// # Failures
+    ///
+    /// * The operation was cancelled, or ran out of time, before `f` succeeded
+    /// * `f` failed, and could not be retried
+    pub fn retry<T, F>(&self, backoff: Duration, f: F) -> Result<T>
+        where F: FnMut(&Self) -> Result<T>
+    {
+        self.retry_if(backoff, is_transient, f)
+    }
+
+    /// As `retry`, for steps which fail with some other error type, retrying them for as long as
+    /// `transient` says their error is worth retrying.
+    ///
+    /// # Failures
+    ///
+    /// * The operation was cancelled, or ran out of time, before `f` succeeded
+    /// * `f` failed, and could not be retried
+    pub fn retry_if<T, E, P, F>(&self,
+                                backoff: Duration,
+                                transient: P,
+                                mut f: F)
+                                -> result::Result<T, E>
+        where E: From<Error> + fmt::Display,
+              P: Fn(&E) -> bool,
+              F: FnMut(&Self) -> result::Result<T, E>
+    {
+        loop {
+            self.check()?;
+            let err = match f(self) {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            if !transient(&err) || !self.take_retry() {
+                return Err(err);
+            }
+            let wait = self.remaining()
+                           .map_or(backoff, |remaining| cmp::min(backoff, remaining));
+            debug!("Retrying a step of {} in {:?} after: {}",
+                   self.operation, wait, err);
+            self.cancel.wait_timeout(wait);
+        }
+    }
+
+    /// Uses up one retry from the shared budget, returning whether there was one to use.
+    fn take_retry(&self) -> bool {
+        let mut left = self.retries.load(Ordering::SeqCst);
+        while left > 0 {
+            match self.retries
+                      .compare_exchange(left, left - 1, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => return true,
+                Err(current) => left = current,
+            }
+        }
+        false
+    }
+}
+
+fn is_transient(err: &Error) -> bool {
+    match ExitCode::from(err) {
+        ExitCode::Unavailable | ExitCode::TimedOut | ExitCode::System => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+
+    fn unavailable() -> Error { Error::IO(io::Error::from(io::ErrorKind::ConnectionRefused)) }
+
+    #[test]
+    fn retries_are_shared_between_steps() {
+        let ctx = OperationContext::new("install core/redis").retries(3);
+        let mut attempts = 0;
+
+        let result = ctx.retry(Duration::from_millis(1), |_| {
+                            attempts += 1;
+                            if attempts < 3 {
+                                Err(unavailable())
+                            } else {
+                                Ok(attempts)
+                            }
+                        });
+        assert_eq!(3, result.unwrap());
+        assert_eq!(1, ctx.retries_left());
+
+        // The step's clone draws on the same budget
+        let step = ctx.clone();
+        let mut attempts = 0;
+        assert!(step.retry(Duration::from_millis(1), |_| -> Result<()> {
+                        attempts += 1;
+                        Err(unavailable())
+                    })
+                    .is_err());
+        assert_eq!(2, attempts);
+        assert_eq!(0, ctx.retries_left());
+
+        // Failures which would fail again are not retried
+        let ctx = OperationContext::new("install core/redis").retries(3);
+        let mut attempts = 0;
+        assert!(ctx.retry(Duration::from_millis(1), |_| -> Result<()> {
+                       attempts += 1;
+                       Err(Error::InvalidPackageIdent("core".to_string()))
+                   })
+                   .is_err());
+        assert_eq!(1, attempts);
+    }
+
+    #[test]
+    fn setting_retries_gives_a_budget_of_its_own() {
+        let ctx = OperationContext::new("install core/redis").retries(1);
+        let step = ctx.clone().retries(5);
+        assert_eq!(1, ctx.retries_left());
+        assert_eq!(5, step.retries_left());
+
+        let mut attempts = 0;
+        assert!(step.retry(Duration::from_millis(1), |_| -> Result<()> {
+                        attempts += 1;
+                        Err(unavailable())
+                    })
+                    .is_err());
+        assert_eq!(6, attempts);
+        assert_eq!(1, ctx.retries_left());
+    }
+
+    #[test]
+    fn retry_if_decides_which_errors_are_transient() {
+        let ctx = OperationContext::new("fetch core/redis").retries(3);
+        let mut attempts = 0;
+
+        let result = ctx.retry_if(Duration::from_millis(1),
+                                  |err| {
+                                      match *err {
+                                          Error::InvalidPackageIdent(_) => true,
+                                          _ => false,
+                                      }
+                                  },
+                                  |_| -> Result<()> {
+                                      attempts += 1;
+                                      match attempts {
+                                          1 => Err(Error::InvalidPackageIdent("core".to_string())),
+                                          _ => Err(unavailable()),
+                                      }
+                                  });
+
+        assert!(result.is_err());
+        assert_eq!(2, attempts);
+        assert_eq!(2, ctx.retries_left());
+    }
+
+    #[test]
+    fn deadline_covers_the_whole_operation() {
+        let ctx = OperationContext::new("install core/redis").timeout(Duration::from_millis(50))
+                                                             .retries(1000);
+        ctx.check().unwrap();
+
+        let err = ctx.retry(Duration::from_millis(10), |_| -> Result<()> {
+                         Err(unavailable())
+                     })
+                     .unwrap_err();
+
+        match err {
+            Error::OperationTimedOut(ref operation, timeout) => {
+                assert_eq!("install core/redis", operation);
+                assert_eq!(Duration::from_millis(50), timeout);
+            }
+            ref other => panic!("Expected OperationTimedOut, got {:?}", other),
+        }
+        assert!(ctx.retries_left() > 0);
+    }
+
+    #[test]
+    fn cancellation_stops_the_operation() {
+        let cancel = CancellationToken::new();
+        let ctx = OperationContext::new("install core/redis").cancel_on(&cancel);
+        ctx.check().unwrap();
+
+        cancel.cancel();
+
+        assert!(ctx.cancellation().is_cancelled());
+        match ctx.check() {
+            Err(Error::Cancelled) => {}
+            other => panic!("Expected Cancelled, got {:?}", other),
+        }
+    }
+}
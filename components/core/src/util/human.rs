@@ -0,0 +1,172 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Durations, sizes and ages written for people to read rather than for programs to parse,
+//! so that every CLI built on core shows them the same way.
+//!
+//! ```
+//! use habitat_core::util::human;
+//! use std::time::Duration;
+//!
+//! assert_eq!("3m 12s", human::duration(Duration::from_secs(192)));
+//! assert_eq!("1.4 GiB", human::size(1_503_238_554));
+//! assert_eq!("12 days ago", human::ago(12 * 24 * 60 * 60));
+//! ```
+
+use std::time::Duration;
+
+use time;
+
+use crate::package::PackageIdent;
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+/// Months and years are taken to be of a fixed length, which is near enough for an age.
+const MONTH: u64 = 30 * DAY;
+const YEAR: u64 = 365 * DAY;
+
+const SIZE_UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+/// Writes `d` in its two largest units, such as `3m 12s` or `2d 5h`, leaving out a second unit
+/// which would be zero. Durations under a second are written in milliseconds.
+pub fn duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs == 0 {
+        return format!("{}ms", d.subsec_millis());
+    }
+    let units = [(DAY, "d"), (HOUR, "h"), (MINUTE, "m"), (1, "s")];
+    let largest = units.iter()
+                       .position(|&(length, _)| secs >= length)
+                       .unwrap_or(units.len() - 1);
+    let (length, unit) = units[largest];
+    let mut written = format!("{}{}", secs / length, unit);
+    if let Some(&(next_length, next_unit)) = units.get(largest + 1) {
+        let rest = secs % length / next_length;
+        if rest > 0 {
+            written.push_str(&format!(" {}{}", rest, next_unit));
+        }
+    }
+    written
+}
+
+/// Writes a size in bytes in the largest binary unit it makes at least one of, to one decimal
+/// place, such as `1.4 GiB`. Sizes under a kibibyte are written in bytes.
+pub fn size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < SIZE_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, SIZE_UNITS[unit])
+}
+
+/// Writes how long ago something happened, given the seconds since it did, in its largest
+/// whole unit, such as `12 days ago`. Anything under a minute is `just now`, and a negative
+/// age, from a clock which is behind, is written as being in the future.
+pub fn ago(seconds: i64) -> String {
+    let (magnitude, future) = if seconds < 0 {
+        (seconds.wrapping_neg() as u64, true)
+    } else {
+        (seconds as u64, false)
+    };
+    if magnitude < MINUTE {
+        return "just now".to_string();
+    }
+    let units = [(YEAR, "year"),
+                 (MONTH, "month"),
+                 (DAY, "day"),
+                 (HOUR, "hour"),
+                 (MINUTE, "minute")];
+    let &(length, unit) = units.iter()
+                               .find(|&&(length, _)| magnitude >= length)
+                               .unwrap_or(&units[units.len() - 1]);
+    let count = magnitude / length;
+    let plural = if count == 1 { "" } else { "s" };
+    if future {
+        format!("in {} {}{}", count, unit, plural)
+    } else {
+        format!("{} {}{} ago", count, unit, plural)
+    }
+}
+
+/// Writes how long ago `ident` was built, judged by its release timestamp, such as
+/// `built 12 days ago`. Returns `None` for an ident without a release, or with a release which
+/// is not a timestamp.
+pub fn release_age(ident: &PackageIdent) -> Option<String> {
+    release_age_at(ident, time::now_utc().to_timespec().sec)
+}
+
+/// As `release_age`, with the age measured from `now`, in seconds since the Unix epoch.
+pub fn release_age_at(ident: &PackageIdent, now: i64) -> Option<String> {
+    ident.release_time()
+         .map(|built| format!("built {}", ago(now - built)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn durations() {
+        assert_eq!("0ms", duration(Duration::from_secs(0)));
+        assert_eq!("250ms", duration(Duration::from_millis(250)));
+        assert_eq!("59s", duration(Duration::from_secs(59)));
+        assert_eq!("1m", duration(Duration::from_secs(60)));
+        assert_eq!("3m 12s", duration(Duration::from_secs(192)));
+        assert_eq!("2h 5m",
+                   duration(Duration::from_secs(2 * 3600 + 5 * 60 + 7)));
+        assert_eq!("1d", duration(Duration::from_secs(DAY + 59)));
+        assert_eq!("400d 3h",
+                   duration(Duration::from_secs(400 * DAY + 3 * HOUR)));
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!("0 B", size(0));
+        assert_eq!("1023 B", size(1023));
+        assert_eq!("1.0 KiB", size(1024));
+        assert_eq!("1.5 MiB", size(1024 * 1024 * 3 / 2));
+        assert_eq!("1.4 GiB", size(1_503_238_554));
+        assert_eq!("16.0 EiB", size(u64::max_value()));
+    }
+
+    #[test]
+    fn ages() {
+        assert_eq!("just now", ago(59));
+        assert_eq!("just now", ago(-30));
+        assert_eq!("1 minute ago", ago(60));
+        assert_eq!("5 hours ago", ago(5 * 3600 + 1));
+        assert_eq!("1 day ago", ago(DAY as i64));
+        assert_eq!("2 months ago", ago(65 * DAY as i64));
+        assert_eq!("3 years ago", ago(3 * YEAR as i64 + 10));
+        assert_eq!("in 2 hours", ago(-2 * 3600));
+    }
+
+    #[test]
+    fn release_ages() {
+        let ident = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+        let built = ident.release_time().unwrap();
+
+        assert_eq!(Some("built 12 days ago".to_string()),
+                   release_age_at(&ident, built + 12 * DAY as i64 + 5));
+        assert_eq!(None,
+                   release_age_at(&PackageIdent::from_str("core/redis").unwrap(), built));
+    }
+}
@@ -12,18 +12,31 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#[cfg(not(windows))]
+#[cfg(feature = "fs")]
+pub mod cancel;
+#[cfg(feature = "fs")]
+pub mod context;
+#[cfg(feature = "fs")]
+pub mod human;
+#[cfg(all(not(windows), feature = "fs"))]
 pub mod posix_perm;
+#[cfg(feature = "fs")]
 pub mod sys;
-#[cfg(windows)]
+#[cfg(feature = "fs")]
+pub mod timeout;
+#[cfg(all(windows, feature = "fs"))]
 pub mod win_perm;
 
+#[cfg(feature = "fs")]
+pub use self::cancel::CancellationToken;
+
 use std::{error,
           fmt,
           marker::PhantomData,
           mem,
           result,
-          str::FromStr};
+          str::FromStr,
+          time::Duration};
 
 use serde;
 
@@ -68,6 +81,29 @@ pub fn serialize_using_to_string<T, S>(t: &T, s: S) -> result::Result<S::Ok, S::
     s.serialize_str(&t.to_string())
 }
 
+/// Parses a duration such as `500ms`, `10s`, `5m`, `1h`, `30d`, `13w`, or `30`, which is taken
+/// as seconds.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let n: u64 = digits.parse().ok()?;
+    let secs = match unit {
+        "ms" => return Some(Duration::from_millis(n)),
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    n.checked_mul(secs).map(Duration::from_secs)
+}
+
+/// Returns the whole milliseconds in `d`.
+pub fn duration_as_millis(d: Duration) -> u64 { d.as_secs() * 1000 + u64::from(d.subsec_millis()) }
+
 /// Provide a way to convert numeric types safely to i64
 pub trait ToI64 {
     fn to_i64(self) -> i64;
@@ -110,6 +146,31 @@ impl ToI64 for u64 {
 mod tests {
     use super::*;
 
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(Some(Duration::from_millis(500)), parse_duration("500ms"));
+        assert_eq!(Some(Duration::from_secs(30)), parse_duration("30"));
+        assert_eq!(Some(Duration::from_secs(10)), parse_duration("10s"));
+        assert_eq!(Some(Duration::from_secs(300)), parse_duration("5m"));
+        assert_eq!(Some(Duration::from_secs(3600)), parse_duration("1h"));
+        assert_eq!(Some(Duration::from_secs(2 * 86_400)), parse_duration("2d"));
+        assert_eq!(Some(Duration::from_secs(604_800)), parse_duration("1w"));
+    }
+
+    #[test]
+    fn parse_duration_rejects_invalid() {
+        for s in &["",
+                   "s",
+                   "-1s",
+                   "1.5s",
+                   "10y",
+                   "1 s",
+                   "18446744073709551615w"]
+        {
+            assert_eq!(None, parse_duration(s), "{:?}", s);
+        }
+    }
+
     #[test]
     fn conversion_of_usize_to_i64() {
         let just_right: usize = 42;
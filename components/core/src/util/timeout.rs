@@ -0,0 +1,217 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bounds on how long a blocking operation may take. Reads from a filesystem such as NFS can
+//! hang indefinitely when the server goes away; these helpers give up after a deadline instead,
+//! reporting `Error::OperationTimedOut` with the name of the operation.
+//!
+//! Rust has no way to interrupt a thread stuck in a system call, so an operation which times out
+//! is left running on a detached thread, and its result discarded when it eventually finishes.
+//! An operation which panics before the deadline panics the caller in turn, as if it had been
+//! called directly.
+
+use std::{error,
+          fmt,
+          fs,
+          io,
+          net::{SocketAddr,
+                TcpStream},
+          panic,
+          path::{Path,
+                 PathBuf},
+          sync::mpsc::{self,
+                       RecvTimeoutError},
+          thread,
+          time::Duration};
+
+use crate::error::{Error,
+                   Result};
+
+/// Returned by `with_timeout` when its operation does not produce a result.
+#[derive(Debug)]
+pub enum TimeoutError {
+    /// The operation took longer than it was given.
+    Elapsed(Duration),
+    /// No thread could be started to run the operation.
+    Spawn(io::Error),
+}
+
+impl TimeoutError {
+    /// Converts this into an `Error::OperationTimedOut` for the named operation, or the
+    /// `Error::IO` a thread could not be spawned with.
+    pub fn into_error<S: Into<String>>(self, operation: S) -> Error {
+        match self {
+            TimeoutError::Elapsed(timeout) => Error::OperationTimedOut(operation.into(), timeout),
+            TimeoutError::Spawn(e) => Error::IO(e),
+        }
+    }
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            TimeoutError::Elapsed(timeout) => {
+                write!(f, "Operation timed out after {:?}", timeout)
+            }
+            TimeoutError::Spawn(ref e) => write!(f, "Unable to spawn thread: {}", e),
+        }
+    }
+}
+
+impl error::Error for TimeoutError {}
+
+/// Runs `f` on another thread, returning its result if it finishes within `timeout`. If `f`
+/// panics, the panic is resumed on the calling thread.
+///
+/// # Failures
+///
+/// * `f` does not finish in time, in which case it is left to run to completion in the background
+/// * A thread cannot be spawned to run `f`
+pub fn with_timeout<T, F>(timeout: Duration, f: F) -> std::result::Result<T, TimeoutError>
+    where T: Send + 'static,
+          F: FnOnce() -> T + Send + 'static
+{
+    let (tx, rx) = mpsc::sync_channel(1);
+    let handle = thread::Builder::new().name("timeout".to_string())
+                                       .spawn(move || {
+                                           // The receiver is gone if we've already timed out
+                                           let _ = tx.send(f());
+                                       })
+                                       .map_err(TimeoutError::Spawn)?;
+    match rx.recv_timeout(timeout) {
+        Ok(value) => Ok(value),
+        Err(RecvTimeoutError::Timeout) => Err(TimeoutError::Elapsed(timeout)),
+        // The sender was dropped without sending, so `f` panicked
+        Err(RecvTimeoutError::Disconnected) => {
+            match handle.join() {
+                Err(payload) => panic::resume_unwind(payload),
+                Ok(()) => unreachable!("the timeout thread finished without sending a result"),
+            }
+        }
+    }
+}
+
+/// As `with_timeout`, for a fallible operation named `operation` in any error.
+fn run<T, F>(operation: String, timeout: Duration, f: F) -> Result<T>
+    where T: Send + 'static,
+          F: FnOnce() -> Result<T> + Send + 'static
+{
+    with_timeout(timeout, f).map_err(|e| e.into_error(operation))?
+}
+
+/// As `std::fs::read`, giving up after `timeout`.
+pub fn read<P: AsRef<Path>>(path: P, timeout: Duration) -> Result<Vec<u8>> {
+    let path = path.as_ref().to_path_buf();
+    run(format!("read {}", path.display()), timeout, move || {
+        Ok(fs::read(&path)?)
+    })
+}
+
+/// As `std::fs::read_to_string`, giving up after `timeout`.
+pub fn read_to_string<P: AsRef<Path>>(path: P, timeout: Duration) -> Result<String> {
+    let path = path.as_ref().to_path_buf();
+    run(format!("read {}", path.display()), timeout, move || {
+        Ok(fs::read_to_string(&path)?)
+    })
+}
+
+/// As `std::fs::metadata`, giving up after `timeout`.
+pub fn metadata<P: AsRef<Path>>(path: P, timeout: Duration) -> Result<fs::Metadata> {
+    let path = path.as_ref().to_path_buf();
+    run(format!("stat {}", path.display()), timeout, move || {
+        Ok(fs::metadata(&path)?)
+    })
+}
+
+/// Lists the paths of the entries in the directory `path`, giving up after `timeout`.
+pub fn read_dir<P: AsRef<Path>>(path: P, timeout: Duration) -> Result<Vec<PathBuf>> {
+    let path = path.as_ref().to_path_buf();
+    run(format!("list {}", path.display()), timeout, move || {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&path)? {
+            paths.push(entry?.path());
+        }
+        Ok(paths)
+    })
+}
+
+/// As `TcpStream::connect_timeout`, reporting a connection which times out as
+/// `Error::OperationTimedOut`.
+pub fn connect(addr: &SocketAddr, timeout: Duration) -> Result<TcpStream> {
+    TcpStream::connect_timeout(addr, timeout).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::TimedOut {
+            Error::OperationTimedOut(format!("connect to {}", addr), timeout)
+        } else {
+            Error::IO(e)
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::TcpListener;
+    use tempfile::Builder;
+
+    #[test]
+    fn operations_within_the_timeout_return_their_result() {
+        assert_eq!(42, with_timeout(Duration::from_secs(5), || 42).unwrap());
+
+        let dir = Builder::new().prefix("timeout").tempdir().unwrap();
+        let path = dir.path().join("IDENT");
+        fs::write(&path, "core/redis/5.0.3/20190319155852\n").unwrap();
+        assert_eq!("core/redis/5.0.3/20190319155852\n",
+                   read_to_string(&path, Duration::from_secs(5)).unwrap());
+        assert!(metadata(&path, Duration::from_secs(5)).unwrap().is_file());
+        assert_eq!(vec![path.clone()],
+                   read_dir(dir.path(), Duration::from_secs(5)).unwrap());
+
+        match read(dir.path().join("MISSING"), Duration::from_secs(5)) {
+            Err(Error::IO(ref e)) => assert_eq!(std::io::ErrorKind::NotFound, e.kind()),
+            other => panic!("Expected IO error, got {:?}", other),
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        connect(&listener.local_addr().unwrap(), Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn slow_operations_time_out() {
+        let timeout = Duration::from_millis(50);
+        match with_timeout(timeout, || thread::sleep(Duration::from_secs(2))) {
+            Err(TimeoutError::Elapsed(t)) => assert_eq!(timeout, t),
+            other => panic!("Expected Elapsed, got {:?}", other),
+        }
+
+        let err = run("read /hab/pkgs/core/redis".to_string(), timeout, || {
+                      thread::sleep(Duration::from_secs(2));
+                      Ok(())
+                  }).unwrap_err();
+        match err {
+            Error::OperationTimedOut(ref operation, t) => {
+                assert_eq!("read /hab/pkgs/core/redis", operation);
+                assert_eq!(timeout, t);
+            }
+            ref other => panic!("Expected OperationTimedOut, got {:?}", other),
+        }
+        assert_eq!("Timed out after 50ms waiting to read /hab/pkgs/core/redis",
+                   err.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "metafile exploded")]
+    fn panics_are_resumed_rather_than_timing_out() {
+        let _ = with_timeout::<(), _>(Duration::from_secs(5), || panic!("metafile exploded"));
+    }
+}
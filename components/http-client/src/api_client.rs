@@ -93,6 +93,9 @@ impl ApiClient {
                        user_agent_header: user_agent(product, version)? })
     }
 
+    /// Returns the base URL for the client.
+    pub fn endpoint(&self) -> &Url { &self.endpoint }
+
     /// Builds an HTTP GET request for a given path.
     pub fn get(&self, path: &str) -> RequestBuilder { self.get_with_custom_url(path, |_| {}) }
 
@@ -28,14 +28,20 @@ pub type Result<T> = result::Result<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
+    /// Occurs when a downloaded artifact does not match the checksum it was advertised with.
+    ArtifactChecksumMismatch(String, String, String),
     HabitatCore(hab_core::Error),
     HyperError(hyper::error::Error),
     /// Occurs when an improper http or https proxy value is given.
     InvalidProxyValue(String),
     IO(io::Error),
     Json(serde_json::Error),
+    /// Occurs when an HTTP response body is larger than the most that will be read of it.
+    ResponseTooLarge(String, u64),
     SslError(ssl::Error),
     SslErrorStack(openssl::error::ErrorStack),
+    /// Occurs when an HTTP response has a status other than the one expected.
+    UnexpectedStatus(String, hyper::status::StatusCode),
     /// When an error occurs attempting to parse a string into a URL.
     UrlParseError(url::ParseError),
 }
@@ -43,13 +49,24 @@ pub enum Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let msg = match *self {
+            Error::ArtifactChecksumMismatch(ref name, ref expected, ref actual) => {
+                format!("Checksum mismatch for {}: expected {}, got {}",
+                        name, expected, actual)
+            }
             Error::HabitatCore(ref e) => format!("{}", e),
             Error::HyperError(ref err) => format!("{}", err),
             Error::IO(ref e) => format!("{}", e),
             Error::Json(ref e) => format!("{}", e),
             Error::InvalidProxyValue(ref e) => format!("Invalid proxy value: {:?}", e),
+            Error::ResponseTooLarge(ref url, max) => {
+                format!("Response from {} is larger than the {} bytes allowed",
+                        url, max)
+            }
             Error::SslError(ref e) => format!("{}", e),
             Error::SslErrorStack(ref e) => format!("{}", e),
+            Error::UnexpectedStatus(ref url, ref status) => {
+                format!("Unexpected response status from {}: {}", url, status)
+            }
             Error::UrlParseError(ref e) => format!("{}", e),
         };
         write!(f, "{}", msg)
@@ -59,13 +76,16 @@ impl fmt::Display for Error {
 impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
+            Error::ArtifactChecksumMismatch(..) => "Downloaded artifact checksum mismatch",
             Error::HabitatCore(ref err) => err.description(),
             Error::HyperError(ref err) => err.description(),
             Error::IO(ref err) => err.description(),
             Error::Json(ref err) => err.description(),
             Error::InvalidProxyValue(_) => "Invalid proxy value",
+            Error::ResponseTooLarge(..) => "HTTP response is larger than allowed",
             Error::SslError(ref err) => err.description(),
             Error::SslErrorStack(ref err) => err.description(),
+            Error::UnexpectedStatus(..) => "Unexpected HTTP response status",
             Error::UrlParseError(ref err) => err.description(),
         }
     }
@@ -23,6 +23,7 @@ extern crate log;
 mod api_client;
 mod error;
 mod net;
+pub mod peer;
 pub mod proxy;
 pub mod util;
 
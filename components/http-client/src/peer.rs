@@ -0,0 +1,659 @@
+// Copyright (c) 2019 Chef Software Inc. and/or applicable contributors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fetching artifacts from peers on the local network.
+//!
+//! A peer advertises the contents of its artifact cache by serving an `ArtifactIndex` as
+//! `index.json` next to the artifacts themselves, so that `<peer-url>/index.json` lists what is
+//! available and `<peer-url>/<artifact-file-name>` serves each artifact. A `PeerFetcher` asks each
+//! of its peers in turn for an artifact before a caller falls back to Builder.
+//!
+//! A fetch can be cancelled with a `CancellationToken`, which is checked before each peer is asked
+//! and between each block of a download, so that a partial download is discarded promptly. It can
+//! also run as a step of an `OperationContext`, whose deadline and retry budget then cover a
+//! whole install: resolving the artifact in a peer's index, downloading, verifying and unpacking
+//! it.
+//!
+//! Installs follow the origin mirrors configured under the filesystem root (see
+//! `habitat_core::package::mirror`): a package a mirror covers is fetched under the mirror's
+//! origin, as the newest release of its version a peer has, and must carry the mirror's
+//! signature. Peers serve whatever is in their caches, so a mirror's Builder and channel do not
+//! apply to them.
+//!
+//! Downloads are checked against the checksum in the peer's index, which guards against
+//! truncated or corrupted transfers. It does not establish trust in the peer; the artifact's
+//! signature must still be verified before it is installed.
+
+use std::{env,
+          fs,
+          io::{Read,
+               Write},
+          path::{Path,
+                 PathBuf},
+          str::FromStr,
+          time::Duration};
+
+use habitat_core::{self as hab_core,
+                   crypto::hash,
+                   objectstore::{ArtifactIndex,
+                                 ArtifactKey,
+                                 ARTIFACT_INDEX_NAME},
+                   package::{mirror::MirrorConfig,
+                             PackageArchive,
+                             PackageIdent,
+                             PackageTarget},
+                   util::{context::OperationContext,
+                          CancellationToken},
+                   ChannelIdent};
+use hyper::status::StatusCode;
+use serde_json;
+use tempfile;
+use url::Url;
+
+use crate::{error::{Error,
+                    Result},
+            ApiClient};
+
+/// Environment variable holding a comma-separated list of peer URLs.
+pub const ARTIFACT_PEERS_ENVVAR: &str = "HAB_ARTIFACT_PEERS";
+
+/// The largest index, in bytes, read from a peer. An index lists a few hundred bytes per
+/// artifact, so anything larger is a misbehaving peer rather than a large cache.
+pub const MAX_INDEX_SIZE: u64 = 16 * 1024 * 1024;
+
+/// How long to wait before asking a peer again after it failed in a way which may pass.
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Fetches artifacts from a fixed list of peers.
+pub struct PeerFetcher {
+    peers:   Vec<ApiClient>,
+    mirrors: MirrorConfig,
+}
+
+impl PeerFetcher {
+    /// Creates a fetcher which queries the given peers, in order, following the mirrors
+    /// configured under `fs_root_path`.
+    ///
+    /// # Errors
+    ///
+    /// * If an HTTP client cannot be created for any of the peers
+    /// * If the mirror configuration exists but cannot be read
+    pub fn new(peers: Vec<Url>,
+               product: &str,
+               version: &str,
+               fs_root_path: Option<&Path>)
+               -> Result<Self> {
+        let peers = peers.into_iter()
+                         .map(|peer| ApiClient::new(peer, product, version, fs_root_path))
+                         .collect::<Result<_>>()?;
+        let mirrors = MirrorConfig::load(fs_root_path)?;
+        Ok(PeerFetcher { peers, mirrors })
+    }
+
+    /// Follows `mirrors` rather than the configured mirrors.
+    pub fn mirrors(mut self, mirrors: MirrorConfig) -> Self {
+        self.mirrors = mirrors;
+        self
+    }
+
+    /// Creates a fetcher for the peers listed in the `HAB_ARTIFACT_PEERS` environment variable.
+    /// If the variable is unset or empty, the fetcher has no peers and never finds an artifact.
+    ///
+    /// # Errors
+    ///
+    /// * If any of the listed peers is not a valid URL
+    /// * If an HTTP client cannot be created for any of the peers
+    pub fn from_env(product: &str, version: &str, fs_root_path: Option<&Path>) -> Result<Self> {
+        let peers = match env::var(ARTIFACT_PEERS_ENVVAR) {
+            Ok(val) => parse_peers(&val)?,
+            Err(_) => Vec::new(),
+        };
+        Self::new(peers, product, version, fs_root_path)
+    }
+
+    /// Returns the index advertised by the peer at position `peer`.
+    fn index(&self, peer: usize) -> Result<ArtifactIndex> {
+        let res = self.peers[peer].get(ARTIFACT_INDEX_NAME).send()?;
+        if res.status != StatusCode::Ok {
+            return Err(Error::UnexpectedStatus(res.url.to_string(), res.status));
+        }
+        let url = res.url.to_string();
+        let mut body = Vec::new();
+        res.take(MAX_INDEX_SIZE + 1).read_to_end(&mut body)?;
+        if body.len() as u64 > MAX_INDEX_SIZE {
+            return Err(Error::ResponseTooLarge(url, MAX_INDEX_SIZE));
+        }
+        serde_json::from_slice(&body).map_err(Error::Json)
+    }
+
+    /// Downloads the artifact for `key` from the first peer which advertises it and writes it
+    /// into `dst_dir`, returning the path of the downloaded artifact. Returns `None` if no peer
+    /// could provide a verified copy, in which case the caller should fetch the artifact from
+    /// Builder.
+    ///
+    /// Failures to reach or download from an individual peer are logged and the next peer is
+    /// tried.
+    ///
+    /// # Errors
+    ///
+    /// * If `dst_dir` cannot be created
+    pub fn fetch(&self, key: &ArtifactKey, dst_dir: &Path) -> Result<Option<PathBuf>> {
+        self.fetch_cancellable(key, dst_dir, &CancellationToken::new())
+    }
+
+    /// As `fetch`, but stopping with `Error::Cancelled` as soon as `cancel` is cancelled, rather
+    /// than trying the next peer, and leaving nothing behind in `dst_dir`.
+    ///
+    /// # Errors
+    ///
+    /// * If `cancel` is cancelled before the artifact is fetched
+    /// * If `dst_dir` cannot be created
+    pub fn fetch_cancellable(&self,
+                             key: &ArtifactKey,
+                             dst_dir: &Path,
+                             cancel: &CancellationToken)
+                             -> Result<Option<PathBuf>> {
+        let ctx = OperationContext::new(format!("fetch {}", key)).cancel_on(cancel);
+        self.fetch_in(key, dst_dir, &ctx)
+    }
+
+    /// As `fetch`, as a step of the operation `ctx`. A peer which cannot be reached, or answers
+    /// with a server error, is asked again for as long as `ctx` has retries and time left, before
+    /// the next peer is tried. The fetch stops as soon as `ctx` is cancelled or out of time.
+    ///
+    /// # Errors
+    ///
+    /// * If `ctx` is cancelled or its deadline passes before the artifact is fetched
+    /// * If `dst_dir` cannot be created
+    pub fn fetch_in(&self,
+                    key: &ArtifactKey,
+                    dst_dir: &Path,
+                    ctx: &OperationContext)
+                    -> Result<Option<PathBuf>> {
+        self.fetch_newest(key.ident(), key.target(), dst_dir, ctx)
+    }
+
+    /// Fetches the artifact for `key` into `dst_dir` as `fetch_in` does, following any mirror
+    /// configured for it, then verifies it against the keys in `cache_key_path` as
+    /// `FetchSource::verify` does. Returns `None` if no peer could provide the artifact.
+    ///
+    /// # Errors
+    ///
+    /// * If `ctx` is cancelled or its deadline passes before the artifact is verified
+    /// * If `dst_dir` cannot be created
+    /// * If the artifact cannot be verified, is not signed by its mirror's or origin's key, or is
+    ///   not the package and target asked for
+    pub fn fetch_verified(&self,
+                          key: &ArtifactKey,
+                          dst_dir: &Path,
+                          cache_key_path: &Path,
+                          ctx: &OperationContext)
+                          -> Result<Option<PackageArchive>> {
+        // Peers serve what is in their caches, whichever channel it came from
+        let source = self.mirrors
+                         .source_for(key.ident(), &ChannelIdent::default());
+        if source.is_mirrored() {
+            debug!("Fetching {} from its mirror as {}",
+                   key.ident(),
+                   source.ident);
+        }
+        let archive = match self.fetch_newest(&source.ident, key.target(), dst_dir, ctx)? {
+            Some(path) => PackageArchive::new(path),
+            None => return Ok(None),
+        };
+        ctx.check()?;
+        source.verify(&archive, key.target(), &cache_key_path)?;
+        Ok(Some(archive))
+    }
+
+    /// Fetches and verifies the artifact for `key` as `fetch_verified` does, then unpacks it under
+    /// `fs_root_path`, all as steps of the operation `ctx`, so that its deadline and retries cover
+    /// the whole install. Returns `None` if no peer could provide the artifact. The package's
+    /// post-install hook is left to the caller.
+    ///
+    /// # Errors
+    ///
+    /// * If `ctx` is cancelled or its deadline passes before unpacking starts
+    /// * If `dst_dir` cannot be created
+    /// * If the artifact cannot be verified or unpacked
+    pub fn install(&self,
+                   key: &ArtifactKey,
+                   dst_dir: &Path,
+                   cache_key_path: &Path,
+                   fs_root_path: Option<&Path>,
+                   ctx: &OperationContext)
+                   -> Result<Option<PackageArchive>> {
+        let archive = match self.fetch_verified(key, dst_dir, cache_key_path, ctx)? {
+            Some(archive) => archive,
+            None => return Ok(None),
+        };
+        ctx.check()?;
+        archive.unpack_cancellable(fs_root_path, ctx.cancellation())?;
+        Ok(Some(archive))
+    }
+
+    /// Fetches the newest artifact satisfying `ident` and built for `target` from the first peer
+    /// whose index has one, as `fetch_in` does.
+    fn fetch_newest(&self,
+                    ident: &PackageIdent,
+                    target: PackageTarget,
+                    dst_dir: &Path,
+                    ctx: &OperationContext)
+                    -> Result<Option<PathBuf>> {
+        fs::create_dir_all(dst_dir)?;
+        for peer in 0..self.peers.len() {
+            ctx.check()?;
+            let fetched = ctx.retry_if(RETRY_BACKOFF, is_transient, |ctx| {
+                                 self.fetch_from(peer, ident, target, dst_dir, ctx.cancellation())
+                             });
+            match fetched {
+                Ok(Some(path)) => return Ok(Some(path)),
+                Err(Error::HabitatCore(e @ hab_core::Error::Cancelled))
+                | Err(Error::HabitatCore(e @ hab_core::Error::OperationTimedOut(..))) => {
+                    return Err(Error::HabitatCore(e));
+                }
+                Ok(None) => {
+                    debug!("Peer {} does not advertise {} ({})",
+                           self.peers[peer].endpoint(),
+                           ident,
+                           target)
+                }
+                Err(e) => {
+                    warn!("Failed to fetch {} ({}) from peer {}: {}",
+                          ident,
+                          target,
+                          self.peers[peer].endpoint(),
+                          e)
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn fetch_from(&self,
+                  peer: usize,
+                  ident: &PackageIdent,
+                  target: PackageTarget,
+                  dst_dir: &Path,
+                  cancel: &CancellationToken)
+                  -> Result<Option<PathBuf>> {
+        let index = self.index(peer)?;
+        let (key, entry) = match index.latest(ident, target) {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        // The object name is rebuilt from the resolved key rather than taken from the peer's
+        // index, since it becomes part of a URL and of paths on disk.
+        let name = key.object_name();
+
+        let mut res = self.peers[peer].get(&name).send()?;
+        if res.status != StatusCode::Ok {
+            return Err(Error::UnexpectedStatus(res.url.to_string(), res.status));
+        }
+        // Download into a temporary file in the destination directory so that a partial or
+        // corrupt download is never visible under the artifact's name.
+        let mut tmp = tempfile::Builder::new().prefix(&name)
+                                              .tempfile_in(dst_dir)?;
+        copy_cancellable(&mut res, &mut tmp, cancel)?;
+
+        let checksum = hash::hash_file(tmp.path())?;
+        if checksum != entry.checksum {
+            return Err(Error::ArtifactChecksumMismatch(name,
+                                                       entry.checksum.clone(),
+                                                       checksum));
+        }
+        let dst = dst_dir.join(&name);
+        tmp.persist(&dst).map_err(|e| Error::IO(e.error))?;
+        Ok(Some(dst))
+    }
+}
+
+/// Whether asking the peer again might succeed where `err` failed.
+fn is_transient(err: &Error) -> bool {
+    match *err {
+        Error::HyperError(_) | Error::IO(_) => true,
+        Error::UnexpectedStatus(_, status) => status.is_server_error(),
+        _ => false,
+    }
+}
+
+/// Copies `src` into `dst` as `io::copy` does, checking `cancel` before each block.
+fn copy_cancellable<R, W>(src: &mut R, dst: &mut W, cancel: &CancellationToken) -> Result<u64>
+    where R: Read,
+          W: Write
+{
+    let mut buf = [0; 8 * 1024];
+    let mut copied = 0;
+    loop {
+        cancel.check()?;
+        let len = src.read(&mut buf)?;
+        if len == 0 {
+            return Ok(copied);
+        }
+        dst.write_all(&buf[..len])?;
+        copied += len as u64;
+    }
+}
+
+/// Parses a comma-separated list of peer URLs, ignoring empty entries.
+fn parse_peers(val: &str) -> Result<Vec<Url>> {
+    val.split(',')
+       .map(str::trim)
+       .filter(|peer| !peer.is_empty())
+       .map(|peer| Url::from_str(peer).map_err(Error::UrlParseError))
+       .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use habitat_core::{objectstore::ArtifactIndexEntry,
+                       package::mirror::{mirrors_path,
+                                         Mirror}};
+    use std::{io::{BufRead,
+                   BufReader,
+                   Write},
+              net::TcpListener,
+              thread};
+    use tempfile::Builder;
+
+    /// Serves each of `files` by name under `/cache/` over plain HTTP, answering anything else
+    /// with a 404, and returns the URL of the cache.
+    fn serve(files: Vec<(String, Vec<u8>)>) -> Url { serve_after_failures(0, files) }
+
+    /// As `serve`, but answering the first `failures` requests with a 503.
+    fn serve_after_failures(failures: usize, files: Vec<(String, Vec<u8>)>) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!("http://{}/cache", listener.local_addr().unwrap())).unwrap();
+        thread::spawn(move || {
+            for (request_number, stream) in listener.incoming().enumerate() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => return,
+                };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                let path = request.split_whitespace().nth(1).unwrap_or("");
+                let name = path.trim_start_matches("/cache/");
+                let (status, body) = match files.iter().find(|(n, _)| n == name) {
+                    _ if request_number < failures => ("503 Service Unavailable", &b""[..]),
+                    Some((_, body)) => ("200 OK", &body[..]),
+                    None => ("404 Not Found", &b""[..]),
+                };
+                let _ = write!(stream,
+                               "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                               status,
+                               body.len());
+                let _ = stream.write_all(body);
+            }
+        });
+        url
+    }
+
+    fn key() -> ArtifactKey {
+        let ident = PackageIdent::from_str("core/redis/3.0.7/20160614001713").unwrap();
+        ArtifactKey::new(ident, PackageTarget::active_target()).unwrap()
+    }
+
+    fn index_for(key: &ArtifactKey, artifact: &[u8], checksum: String) -> Vec<u8> {
+        let index =
+            ArtifactIndex { artifacts: vec![ArtifactIndexEntry { name: key.object_name(),
+                                                                 size: artifact.len() as u64,
+                                                                 checksum }], };
+        serde_json::to_vec(&index).unwrap()
+    }
+
+    fn fetcher(peers: Vec<Url>, fs_root: &Path) -> PeerFetcher {
+        PeerFetcher::new(peers, "hab-test", "0.0.0", Some(fs_root)).unwrap()
+    }
+
+    #[test]
+    fn fetch_from_first_peer_advertising_artifact() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let key = key();
+        let artifact = b"a hart, or near enough".to_vec();
+        let empty = serde_json::to_vec(&ArtifactIndex::default()).unwrap();
+        let without = serve(vec![(ARTIFACT_INDEX_NAME.to_string(), empty)]);
+        let with = serve(vec![(ARTIFACT_INDEX_NAME.to_string(),
+                               index_for(&key, &artifact, hash::hash_bytes(&artifact))),
+                              (key.object_name(), artifact.clone())]);
+        let dst = fs_root.path().join("artifacts");
+
+        let path = fetcher(vec![without, with], fs_root.path()).fetch(&key, &dst)
+                                                               .unwrap()
+                                                               .expect("artifact fetched");
+
+        assert_eq!(dst.join(key.object_name()), path);
+        assert_eq!(artifact, fs::read(&path).unwrap());
+    }
+
+    #[test]
+    fn fetch_rejects_artifact_not_matching_checksum() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let key = key();
+        let artifact = b"a hart, or near enough".to_vec();
+        let peer = serve(vec![(ARTIFACT_INDEX_NAME.to_string(),
+                               index_for(&key, &artifact, hash::hash_bytes(b"another hart"))),
+                              (key.object_name(), artifact)]);
+        let dst = fs_root.path().join("artifacts");
+        let fetcher = fetcher(vec![peer], fs_root.path());
+
+        assert!(fetcher.fetch(&key, &dst).unwrap().is_none());
+        match fetcher.fetch_from(0,
+                                 key.ident(),
+                                 key.target(),
+                                 &dst,
+                                 &CancellationToken::new())
+        {
+            Err(Error::ArtifactChecksumMismatch(name, ..)) => assert_eq!(key.object_name(), name),
+            other => panic!("Expected ArtifactChecksumMismatch, got {:?}", other),
+        }
+        assert_eq!(0, fs::read_dir(&dst).unwrap().count());
+    }
+
+    #[test]
+    fn cancelled_fetch_stops_without_trying_other_peers() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let key = key();
+        let artifact = b"a hart, or near enough".to_vec();
+        let peer = serve(vec![(ARTIFACT_INDEX_NAME.to_string(),
+                               index_for(&key, &artifact, hash::hash_bytes(&artifact))),
+                              (key.object_name(), artifact.clone())]);
+        let dst = fs_root.path().join("artifacts");
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        match fetcher(vec![peer], fs_root.path()).fetch_cancellable(&key, &dst, &cancel) {
+            Err(Error::HabitatCore(hab_core::Error::Cancelled)) => {}
+            other => panic!("Expected Cancelled, got {:?}", other),
+        }
+        assert_eq!(0, fs::read_dir(&dst).unwrap().count());
+
+        let mut copied = Vec::new();
+        assert!(copy_cancellable(&mut &artifact[..], &mut copied, &cancel).is_err());
+        assert!(copied.is_empty());
+        assert_eq!(artifact.len() as u64,
+                   copy_cancellable(&mut &artifact[..], &mut copied, &CancellationToken::new())
+                       .unwrap());
+        assert_eq!(artifact, copied);
+    }
+
+    #[test]
+    fn fetch_in_asks_a_failing_peer_again_within_the_retry_budget() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let key = key();
+        let artifact = b"a hart, or near enough".to_vec();
+        let files = vec![(ARTIFACT_INDEX_NAME.to_string(),
+                          index_for(&key, &artifact, hash::hash_bytes(&artifact))),
+                         (key.object_name(), artifact.clone())];
+        let dst = fs_root.path().join("artifacts");
+
+        let ctx = OperationContext::new("install core/redis").retries(1);
+        let retrying = fetcher(vec![serve_after_failures(1, files.clone())], fs_root.path());
+        assert!(retrying.fetch_in(&key, &dst, &ctx).unwrap().is_some());
+        assert_eq!(0, ctx.retries_left());
+
+        let ctx = OperationContext::new("install core/redis");
+        let once = fetcher(vec![serve_after_failures(1, files)], fs_root.path());
+        assert!(once.fetch_in(&key, &dst, &ctx).unwrap().is_none());
+    }
+
+    #[test]
+    fn fetch_in_stops_once_the_deadline_passes() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let key = key();
+        let artifact = b"a hart, or near enough".to_vec();
+        let peer = serve(vec![(ARTIFACT_INDEX_NAME.to_string(),
+                               index_for(&key, &artifact, hash::hash_bytes(&artifact))),
+                              (key.object_name(), artifact)]);
+        let ctx = OperationContext::new("install core/redis").timeout(Duration::from_secs(0));
+        let dst = fs_root.path().join("artifacts");
+        let fetcher = fetcher(vec![peer], fs_root.path());
+
+        match fetcher.install(&key, &dst, fs_root.path(), Some(fs_root.path()), &ctx) {
+            Err(Error::HabitatCore(hab_core::Error::OperationTimedOut(ref operation, _))) => {
+                assert_eq!("install core/redis", operation)
+            }
+            other => panic!("Expected OperationTimedOut, got {:?}", other),
+        }
+        assert_eq!(0, fs::read_dir(&dst).unwrap().count());
+    }
+
+    #[test]
+    fn install_fetches_verifies_and_unpacks_under_one_context() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("../core/tests/fixtures");
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let ident = PackageIdent::from_str("happyhumans/possums/8.1.4/20160427165340").unwrap();
+        let key =
+            ArtifactKey::new(ident, PackageTarget::from_str("x86_64-linux").unwrap()).unwrap();
+        let artifact = fs::read(fixtures.join(key.object_name())).unwrap();
+        let peer = serve(vec![(ARTIFACT_INDEX_NAME.to_string(),
+                               index_for(&key, &artifact, hash::hash_bytes(&artifact))),
+                              (key.object_name(), artifact)]);
+        let ctx =
+            OperationContext::new("install happyhumans/possums").timeout(Duration::from_secs(60));
+
+        let archive = fetcher(vec![peer], fs_root.path()).install(&key,
+                                                                  &fs_root.path()
+                                                                          .join("artifacts"),
+                                                                  &fixtures,
+                                                                  Some(fs_root.path()),
+                                                                  &ctx)
+                                                         .unwrap()
+                                                         .expect("artifact installed");
+
+        assert_eq!(fs_root.path().join("artifacts").join(key.object_name()),
+                   archive.path);
+        assert!(fs_root.path()
+                       .join("hab/pkgs/happyhumans/possums/8.1.4/20160427165340/IDENT")
+                       .is_file());
+    }
+
+    #[test]
+    fn fetch_verified_follows_mirrors_and_checks_the_artifact() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("../core/tests/fixtures");
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let target = PackageTarget::from_str("x86_64-linux").unwrap();
+        let key = |ident: &str| ArtifactKey::new(PackageIdent::from_str(ident).unwrap(), target);
+        let mirrored = key("happyhumans/possums/8.1.4/20160427165340").unwrap();
+        let upstream = key("core/possums/8.1.4/20160427165340").unwrap();
+        let artifact = fs::read(fixtures.join(mirrored.object_name())).unwrap();
+        let serve_as = |key: &ArtifactKey| {
+            serve(vec![(ARTIFACT_INDEX_NAME.to_string(),
+                        index_for(key, &artifact, hash::hash_bytes(&artifact))),
+                       (key.object_name(), artifact.clone())])
+        };
+        let mirror_peer = serve_as(&mirrored);
+        let mirrors = mirrors_path(Some(fs_root.path()));
+        fs::create_dir_all(mirrors.parent().unwrap()).unwrap();
+        fs::write(&mirrors,
+                  "[[mirror]]\norigin = \"core\"\nmirror_origin = \"happyhumans\"\n").unwrap();
+        let ctx = OperationContext::new("install core/possums");
+        let dst = fs_root.path().join("artifacts");
+
+        let archive =
+            fetcher(vec![mirror_peer.clone()], fs_root.path()).fetch_verified(&upstream, &dst,
+                                                                              &fixtures, &ctx)
+                                                              .unwrap()
+                                                              .expect("artifact fetched through \
+                                                                       the mirror");
+        assert_eq!(dst.join(mirrored.object_name()), archive.path);
+
+        let unmirrored =
+            fetcher(vec![mirror_peer.clone()], fs_root.path()).mirrors(MirrorConfig::new());
+        assert!(unmirrored.fetch_verified(&upstream, &dst, &fixtures, &ctx)
+                          .unwrap()
+                          .is_none());
+
+        let other_key =
+            fetcher(vec![mirror_peer], fs_root.path()).mirrors(MirrorConfig::new().mirror(
+                Mirror::new("core", "happyhumans").key("core"),
+            ));
+        match other_key.fetch_verified(&upstream, &dst, &fixtures, &ctx) {
+            Err(Error::HabitatCore(hab_core::Error::MirrorSignerMismatch(ident, ..))) => {
+                assert_eq!(upstream.ident().to_string(), ident)
+            }
+            other => panic!("Expected MirrorSignerMismatch, got {:?}", other),
+        }
+
+        let impostor = key("happyhumans/opossums/8.1.4/20160427165340").unwrap();
+        match fetcher(vec![serve_as(&impostor)], fs_root.path()).fetch_verified(&impostor, &dst,
+                                                                                &fixtures, &ctx)
+        {
+            Err(Error::HabitatCore(hab_core::Error::ArtifactMismatch(expected, _))) => {
+                assert_eq!(format!("{} ({})", impostor.ident(), target), expected)
+            }
+            other => panic!("Expected ArtifactMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn index_larger_than_limit_is_refused() {
+        let fs_root = Builder::new().prefix("fs-root").tempdir().unwrap();
+        let peer =
+            serve(vec![(ARTIFACT_INDEX_NAME.to_string(), vec![b' '; MAX_INDEX_SIZE as usize + 1])]);
+
+        match fetcher(vec![peer], fs_root.path()).index(0) {
+            Err(Error::ResponseTooLarge(_, max)) => assert_eq!(MAX_INDEX_SIZE, max),
+            other => panic!("Expected ResponseTooLarge, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_peers_with_multiple_peers() {
+        let peers = parse_peers("http://10.0.0.1:9632/cache, http://10.0.0.2:9632/cache,").unwrap();
+
+        assert_eq!(vec![Url::parse("http://10.0.0.1:9632/cache").unwrap(),
+                        Url::parse("http://10.0.0.2:9632/cache").unwrap()],
+                   peers);
+    }
+
+    #[test]
+    fn parse_peers_with_empty_value() {
+        assert!(parse_peers("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_peers_with_invalid_url() {
+        match parse_peers("http://10.0.0.1:9632,not a url") {
+            Err(Error::UrlParseError(_)) => (),
+            other => panic!("Expected UrlParseError, got {:?}", other),
+        }
+    }
+}